@@ -0,0 +1,291 @@
+#[cfg(unix)]
+use std::env;
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use crate::stats::AgentState;
+use crate::{statsd, syslog, Config};
+
+/// The first descriptor systemd passes (`SD_LISTEN_FDS_START`).
+#[cfg(unix)]
+const FIRST_FD: RawFd = 3;
+
+/// Name of the socket unit `install-service` writes along with the service.
+pub const SOCKET_UNIT: &str = "rust-signoz-agent.socket";
+
+/// Only ever made from a descriptor systemd passed.
+#[cfg_attr(windows, allow(dead_code))]
+enum Socket {
+    Udp(UdpSocket),
+    Tcp(TcpListener),
+}
+
+/// A socket passed by systemd, which keeps it open while the agent restarts or is
+/// upgraded, so senders are not refused and datagrams wait in its buffer.
+struct Inherited {
+    socket: Socket,
+    address: SocketAddr,
+    /// `FileDescriptorName=` of the socket unit, or the descriptor number.
+    name: String,
+    used: AtomicBool,
+}
+
+impl Inherited {
+    fn describe(&self) -> String {
+        let scheme = match self.socket {
+            Socket::Udp(_) => "udp",
+            Socket::Tcp(_) => "tcp",
+        };
+        format!("{} {}://{}", self.name, scheme, self.address)
+    }
+
+    fn matches(&self, address: &str) -> bool {
+        address.to_socket_addrs().is_ok_and(|mut resolved| {
+            resolved.any(|a| {
+                a == self.address
+                    // `ListenDatagram=514` binds [::]:514, for both "0.0.0.0:514" and "[::]:514".
+                    || (a.port() == self.address.port()
+                        && a.ip().is_unspecified()
+                        && self.address.ip().is_unspecified())
+            })
+        })
+    }
+
+    fn take(&self) {
+        if !self.used.swap(true, Ordering::Relaxed) {
+            println!("Listening on {}, passed by systemd", self.describe());
+        }
+    }
+}
+
+static INHERITED: OnceLock<Vec<Inherited>> = OnceLock::new();
+
+/// Takes over the sockets systemd passed to this process (`LISTEN_PID`, `LISTEN_FDS`),
+/// before any fork changes its pid. The listeners of the inputs bound to their addresses
+/// use them rather than binding their own.
+#[cfg(unix)]
+pub fn inherit() {
+    INHERITED.get_or_init(|| {
+        let ours = env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            == Some(std::process::id());
+        let count = match env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|n| n.parse::<RawFd>().ok())
+        {
+            Some(count) if ours => count,
+            _ => return Vec::new(),
+        };
+        let names: Vec<String> = env::var("LISTEN_FDNAMES")
+            .map(|names| names.split(':').map(str::to_string).collect())
+            .unwrap_or_default();
+        // Not passed on to the commands the agent runs.
+        for key in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            env::remove_var(key);
+        }
+        (0..count)
+            .filter_map(|i| {
+                let fd = FIRST_FD + i;
+                let name = names
+                    .get(i as usize)
+                    .filter(|n| !n.is_empty() && *n != "unknown")
+                    .cloned()
+                    .unwrap_or_else(|| format!("fd {}", fd));
+                match inherit_fd(fd) {
+                    Ok((socket, address)) => Some(Inherited {
+                        socket,
+                        address,
+                        name,
+                        used: AtomicBool::new(false),
+                    }),
+                    Err(e) => {
+                        eprintln!("Ignoring socket {} passed by systemd: {}", name, e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    });
+}
+
+/// Windows has no socket activation: every listener binds its own socket.
+#[cfg(windows)]
+pub fn inherit() {
+    INHERITED.get_or_init(Vec::new);
+}
+
+#[cfg(unix)]
+fn inherit_fd(fd: RawFd) -> io::Result<(Socket, SocketAddr)> {
+    let mut kind: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `kind` and `len` are valid for the writes of getsockopt, and the descriptor
+    // is owned from here on, as systemd hands it over.
+    unsafe {
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        if libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            &mut kind as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        ) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        match kind {
+            libc::SOCK_DGRAM => {
+                let socket = UdpSocket::from_raw_fd(fd);
+                let address = socket.local_addr()?;
+                Ok((Socket::Udp(socket), address))
+            }
+            libc::SOCK_STREAM => {
+                let listener = TcpListener::from_raw_fd(fd);
+                let address = listener.local_addr()?;
+                Ok((Socket::Tcp(listener), address))
+            }
+            _ => Err(io::Error::other("not a datagram or stream socket")),
+        }
+    }
+}
+
+fn inherited() -> &'static [Inherited] {
+    INHERITED.get().map_or(&[], Vec::as_slice)
+}
+
+/// The datagram socket passed by systemd for `address`, as a socket of its own; each
+/// call, one for each time the listener is bound, gets a new one.
+pub fn udp(address: &str) -> Option<io::Result<UdpSocket>> {
+    inherited()
+        .iter()
+        .find_map(|inherited| match inherited.socket {
+            Socket::Udp(ref socket) if inherited.matches(address) => {
+                inherited.take();
+                Some(socket.try_clone())
+            }
+            _ => None,
+        })
+}
+
+/// The stream socket passed by systemd for `address`, like `udp`.
+pub fn tcp(address: &str) -> Option<io::Result<TcpListener>> {
+    inherited()
+        .iter()
+        .find_map(|inherited| match inherited.socket {
+            Socket::Tcp(ref listener) if inherited.matches(address) => {
+                inherited.take();
+                Some(listener.try_clone())
+            }
+            _ => None,
+        })
+}
+
+/// Reports the sockets passed by systemd that no input listens on, e.g. after an input
+/// was removed from the config but not from the socket unit.
+pub fn report_unused(state: &AgentState) {
+    for inherited in inherited() {
+        if !inherited.used.load(Ordering::Relaxed) {
+            state.record_error(format!(
+                "Socket {} passed by systemd matches no input",
+                inherited.describe()
+            ));
+        }
+    }
+}
+
+/// `ListenDatagram=` and `ListenStream=` lines for the addresses the syslog and StatsD
+/// inputs of `config` listen on.
+pub fn listen_lines(config: &Config) -> String {
+    let mut lines = String::new();
+    for spec in &config.inputs {
+        let options = toml::Value::Table(spec.options.clone());
+        match spec.kind.as_str() {
+            "syslog" => {
+                let Ok(syslog) = options.try_into::<syslog::SyslogInput>() else {
+                    continue;
+                };
+                if let Some(udp) = syslog.udp {
+                    lines.push_str(&format!("ListenDatagram={}\n", udp));
+                }
+                if let Some(tcp) = syslog.tcp {
+                    lines.push_str(&format!("ListenStream={}\n", tcp));
+                }
+            }
+            "statsd" => {
+                if let Ok(statsd) = options.try_into::<statsd::StatsdConfig>() {
+                    lines.push_str(&format!("ListenDatagram={}\n", statsd.bind));
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(ref statsd) = config.statsd {
+        lines.push_str(&format!("ListenDatagram={}\n", statsd.bind));
+    }
+    lines
+}
+
+/// The socket unit holding the listeners of `config` for the service, if it has any.
+pub fn socket_unit(config: &Config) -> Option<String> {
+    let listen = listen_lines(config);
+    if listen.is_empty() {
+        return None;
+    }
+    Some(format!(
+        r#"[Unit]
+Description=Rust SigNoz Agent listeners
+
+[Socket]
+{}
+[Install]
+WantedBy=sockets.target
+"#,
+        listen
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inherited_sockets_match_their_configured_address() {
+        let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let inherited = Inherited {
+            address: socket.local_addr().unwrap(),
+            socket: Socket::Udp(socket),
+            name: "syslog".to_string(),
+            used: AtomicBool::new(false),
+        };
+        assert!(inherited.matches(&format!("0.0.0.0:{}", port)));
+        assert!(inherited.matches(&format!("[::]:{}", port)));
+        assert!(!inherited.matches(&format!("127.0.0.1:{}", port)));
+        assert!(!inherited.matches(&format!("0.0.0.0:{}", port.wrapping_add(1))));
+        assert!(!inherited.matches("not an address"));
+
+        let config: Config = toml::from_str(
+            r#"
+            endpoint = "http://localhost:4318/v1/logs"
+            log_files = []
+
+            [[inputs]]
+            type = "syslog"
+            udp = "0.0.0.0:514"
+            tcp = "0.0.0.0:601"
+
+            [[inputs]]
+            type = "statsd"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            listen_lines(&config),
+            "ListenDatagram=0.0.0.0:514\nListenStream=0.0.0.0:601\nListenDatagram=127.0.0.1:8125\n"
+        );
+    }
+}
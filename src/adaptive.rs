@@ -0,0 +1,242 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The file in `state_dir` the learned rates are kept in, by exporter name.
+pub const STATE_FILE: &str = "rate_limits.json";
+
+/// 429s closer together than this are taken as one: the requests in flight when the
+/// collector started throttling all get one.
+const SAME_THROTTLING: Duration = Duration::from_secs(1);
+
+/// `[adaptive_rate]`: an exporter whose collector answers HTTP 429 (or gRPC
+/// `RESOURCE_EXHAUSTED`) cuts the rate it sends records at by `decrease_factor`, and
+/// raises it by `increase` every `recovery_interval_secs` without one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AdaptiveRateConfig {
+    /// Records per second the rate is never cut below.
+    #[serde(default = "default_min_rate")]
+    pub min_rate: f64,
+    #[serde(default = "default_decrease_factor")]
+    pub decrease_factor: f64,
+    /// Records per second the rate is raised by.
+    #[serde(default = "default_increase")]
+    pub increase: f64,
+    #[serde(default = "default_recovery_interval_secs")]
+    pub recovery_interval_secs: u64,
+}
+
+fn default_min_rate() -> f64 {
+    10.0
+}
+
+fn default_decrease_factor() -> f64 {
+    0.5
+}
+
+fn default_increase() -> f64 {
+    50.0
+}
+
+fn default_recovery_interval_secs() -> u64 {
+    10
+}
+
+impl AdaptiveRateConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !(self.decrease_factor > 0.0 && self.decrease_factor < 1.0) {
+            return Err(anyhow::anyhow!(
+                "adaptive_rate decrease_factor must be between 0 and 1"
+            ));
+        }
+        if self.min_rate <= 0.0 || self.increase <= 0.0 || self.recovery_interval_secs == 0 {
+            return Err(anyhow::anyhow!(
+                "adaptive_rate min_rate, increase and recovery_interval_secs must be greater than 0"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// What an exporter learned, saved so that a restarted agent does not start out
+/// throttled again.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Learned {
+    /// Records per second sent at.
+    pub rate: f64,
+    /// The rate the collector last throttled at.
+    pub ceiling: f64,
+}
+
+/// The send rate of one exporter.
+pub struct AdaptiveRate {
+    config: AdaptiveRateConfig,
+    name: String,
+    path: PathBuf,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    /// Unlimited until the collector first throttles.
+    learned: Option<Learned>,
+    changed: Instant,
+    /// When the records reserved so far have all been sent at the rate.
+    next_free: Instant,
+    /// Records delivered since the start of the current second.
+    window: (Instant, u64),
+    /// Records per second delivered in the second before.
+    throughput: f64,
+}
+
+impl AdaptiveRate {
+    /// The rate of exporter `name`, starting where it was left in `state_dir`.
+    pub fn new(config: &AdaptiveRateConfig, name: &str, state_dir: &str) -> AdaptiveRate {
+        let path = PathBuf::from(state_dir).join(STATE_FILE);
+        let learned = load(&path).remove(name);
+        if let Some(learned) = learned {
+            println!(
+                "Exporter {}: sending at most {:.0} records/s, as learned from 429s before",
+                name, learned.rate
+            );
+        }
+        let now = Instant::now();
+        AdaptiveRate {
+            config: config.clone(),
+            name: name.to_string(),
+            path,
+            inner: Mutex::new(Inner {
+                learned,
+                changed: now,
+                next_free: now,
+                window: (now, 0),
+                throughput: 0.0,
+            }),
+        }
+    }
+
+    /// Takes the time `records` records are sent in at the current rate, and returns how
+    /// long to wait before sending them.
+    pub fn reserve(&self, records: usize, now: Instant) -> Duration {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(learned) = inner.learned else {
+            return Duration::ZERO;
+        };
+        let start = inner.next_free.max(now);
+        inner.next_free = start + Duration::from_secs_f64(records as f64 / learned.rate);
+        start - now
+    }
+
+    /// Notes that `records` records were accepted, raising the rate once it has held
+    /// for `recovery_interval_secs`.
+    pub fn delivered(&self, records: usize, now: Instant) {
+        let mut inner = self.inner.lock().unwrap();
+        let elapsed = now - inner.window.0;
+        if elapsed >= Duration::from_secs(1) {
+            inner.throughput = inner.window.1 as f64 / elapsed.as_secs_f64();
+            inner.window = (now, 0);
+        }
+        inner.window.1 += records as u64;
+        let due = now - inner.changed >= Duration::from_secs(self.config.recovery_interval_secs);
+        let Some(learned) = inner.learned.as_mut().filter(|_| due) else {
+            return;
+        };
+        learned.rate += self.config.increase;
+        let learned = *learned;
+        inner.changed = now;
+        self.save(learned);
+    }
+
+    /// Cuts the rate after a 429: the rate sent at, or what was sent in the last second
+    /// while unlimited, by `decrease_factor`.
+    pub fn throttled(&self, now: Instant) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.learned.is_some() && now - inner.changed < SAME_THROTTLING {
+            return;
+        }
+        let current = match inner.learned {
+            Some(learned) => learned.rate,
+            None => {
+                let elapsed = (now - inner.window.0).as_secs_f64().max(1.0);
+                inner.throughput.max(inner.window.1 as f64 / elapsed)
+            }
+        };
+        let rate = (current * self.config.decrease_factor).max(self.config.min_rate);
+        let learned = Learned {
+            rate,
+            ceiling: current,
+        };
+        inner.learned = Some(learned);
+        inner.changed = now;
+        println!(
+            "Exporter {}: the collector is throttling at {:.0} records/s, sending at most {:.0}",
+            self.name, current, rate
+        );
+        self.save(learned);
+    }
+
+    fn save(&self, learned: Learned) {
+        // Exporters share the file.
+        static SAVING: Mutex<()> = Mutex::new(());
+        let _saving = SAVING.lock().unwrap();
+        let mut rates = load(&self.path);
+        rates.insert(self.name.clone(), learned);
+        let tmp = self.path.with_extension("json.tmp");
+        let written = serde_json::to_vec_pretty(&rates)
+            .map_err(std::io::Error::from)
+            .and_then(|json| fs::write(&tmp, json))
+            .and_then(|()| fs::rename(&tmp, &self.path));
+        if let Err(e) = written {
+            eprintln!("Failed to save {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+fn load(path: &Path) -> BTreeMap<String, Learned> {
+    fs::read(path)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn rate_is_halved_on_429_and_recovers_slowly() {
+        let dir = TempDir::new("adaptive-rate");
+        let config: AdaptiveRateConfig = toml::from_str("increase = 100").unwrap();
+        config.validate().unwrap();
+        let rate = AdaptiveRate::new(&config, "signoz", dir.to_str().unwrap());
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        // Unlimited, 2000 records are sent in a second, then the collector pushes back.
+        assert_eq!(rate.reserve(1000, at(0)), Duration::ZERO);
+        rate.delivered(1000, at(0));
+        rate.delivered(1000, at(500));
+        rate.delivered(0, at(1000));
+        rate.throttled(at(1200));
+        rate.throttled(at(1300));
+        let learned = rate.inner.lock().unwrap().learned.unwrap();
+        assert!((learned.ceiling - 2000.0).abs() < 1.0);
+        assert!((learned.rate - 1000.0).abs() < 1.0);
+        assert_eq!(rate.reserve(500, at(1200)), Duration::ZERO);
+        let wait = rate.reserve(500, at(1200));
+        assert!(wait >= Duration::from_millis(499) && wait <= Duration::from_millis(501));
+
+        // Ten quiet seconds later it goes up by `increase`, and a restart picks it up.
+        rate.delivered(10, at(5000));
+        rate.delivered(10, at(11200));
+        let restarted = AdaptiveRate::new(&config, "signoz", dir.to_str().unwrap());
+        let learned = restarted.inner.lock().unwrap().learned.unwrap();
+        assert!((learned.rate - 1100.0).abs() < 1.0);
+
+        let factor: AdaptiveRateConfig = toml::from_str("decrease_factor = 1.5").unwrap();
+        assert!(factor.validate().is_err());
+    }
+}
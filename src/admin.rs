@@ -0,0 +1,299 @@
+use anyhow::Result;
+use std::time::Duration;
+
+use crate::stats::StatusSnapshot;
+#[cfg(unix)]
+use {
+    crate::checkpoint::Positions,
+    crate::stats::AgentState,
+    anyhow::Context,
+    signal_hook::consts::{SIGUSR1, SIGUSR2},
+    signal_hook::iterator::Signals,
+    std::fs,
+    std::io::{BufRead, BufReader, Write},
+    std::os::unix::net::{UnixListener, UnixStream},
+    std::path::Path,
+    std::sync::atomic::Ordering,
+    std::sync::Arc,
+    std::thread,
+    std::time::Instant,
+};
+
+/// Default `admin_socket`, in the state directory.
+pub const SOCKET_FILE: &str = "agent.sock";
+
+/// Binds the admin socket and answers control commands from `rust-signoz-agent <command>`.
+///
+/// The protocol is a single request line per connection answered with a single response
+/// line: `flush <timeout_secs>` gets `ok <remaining>` or `timeout <remaining>`, `pause`
+/// and `resume` get `ok`, and `status` gets the JSON-encoded [`StatusSnapshot`].
+#[cfg(unix)]
+pub fn serve(path: &str, state: Arc<AgentState>) -> Result<thread::JoinHandle<()>> {
+    if Path::new(path).exists() {
+        fs::remove_file(path).with_context(|| format!("Failed to remove stale socket {}", path))?;
+    }
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind admin socket {}", path))?;
+
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let state = state.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = handle_connection(stream, &state) {
+                            eprintln!("Admin socket error: {e}");
+                        }
+                    });
+                }
+                Err(e) => eprintln!("Admin socket accept failed: {e}"),
+            }
+        }
+    }))
+}
+
+#[cfg(unix)]
+fn handle_connection(stream: UnixStream, state: &AgentState) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+
+    let mut parts = request.split_whitespace();
+    let response = match parts.next() {
+        Some("flush") => {
+            let timeout = parts
+                .next()
+                .and_then(|t| t.parse::<u64>().ok())
+                .unwrap_or(30);
+            flush(state, Duration::from_secs(timeout))
+        }
+        Some(command @ ("pause" | "resume")) => {
+            let paused = command == "pause";
+            if state.exports_paused.swap(paused, Ordering::SeqCst) != paused {
+                println!(
+                    "Exporting {} via admin socket",
+                    if paused { "paused" } else { "resumed" }
+                );
+            }
+            "ok".to_string()
+        }
+        Some("status") => serde_json::to_string(&state.snapshot())?,
+        Some(other) => format!("error unknown command: {}", other),
+        None => "error empty command".to_string(),
+    };
+
+    let mut stream = stream;
+    writeln!(stream, "{}", response)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn flush(state: &AgentState, timeout: Duration) -> String {
+    println!("Flush requested via admin socket");
+    state.flushing.store(true, Ordering::SeqCst);
+
+    // Records an exporter put in its disk buffer are not delivered yet either.
+    let undelivered = || state.pending() + state.buffered();
+    let deadline = Instant::now() + timeout;
+    let mut remaining = undelivered();
+    while remaining > 0 && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(100));
+        remaining = undelivered();
+    }
+
+    state.flushing.store(false, Ordering::SeqCst);
+    if remaining == 0 {
+        "ok 0".to_string()
+    } else {
+        format!("timeout {}", remaining)
+    }
+}
+
+/// Lets the agent be looked into with `kill` alone: SIGUSR1 prints the status, as `status`
+/// answers it, and the read position of each tailed file to the agent log; SIGUSR2 turns
+/// debug logging on or off.
+#[cfg(unix)]
+pub fn watch_signals(state: Arc<AgentState>, positions: Arc<Positions>) -> Result<()> {
+    let mut signals = Signals::new([SIGUSR1, SIGUSR2])?;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if signal == SIGUSR2 {
+                let on = !state.debug_logging.fetch_xor(true, Ordering::SeqCst);
+                println!("Debug logging {}", if on { "on" } else { "off" });
+                continue;
+            }
+            let status = serde_json::json!({
+                "status": state.snapshot(),
+                "offsets": positions.offsets(),
+            });
+            match serde_json::to_string_pretty(&status) {
+                Ok(status) => println!("Status on SIGUSR1:\n{}", status),
+                Err(e) => eprintln!("Failed to encode the status: {e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Asks a running agent to drain its queue and disk buffers, returning the number of
+/// records still pending.
+#[cfg(unix)]
+pub fn request_flush(path: &str, timeout: Duration) -> Result<usize> {
+    let mut stream = UnixStream::connect(path)
+        .with_context(|| format!("Failed to connect to admin socket {}", path))?;
+    stream.set_read_timeout(Some(timeout + Duration::from_secs(5)))?;
+    writeln!(stream, "flush {}", timeout.as_secs())?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+
+    let mut parts = response.split_whitespace();
+    match (
+        parts.next(),
+        parts.next().and_then(|n| n.parse::<usize>().ok()),
+    ) {
+        (Some("ok"), Some(remaining)) | (Some("timeout"), Some(remaining)) => Ok(remaining),
+        _ => Err(anyhow::anyhow!(
+            "Unexpected response from agent: {}",
+            response.trim()
+        )),
+    }
+}
+
+/// Pauses or resumes exporting in a running agent.
+#[cfg(unix)]
+pub fn request_pause(path: &str, paused: bool) -> Result<()> {
+    let mut stream = UnixStream::connect(path)
+        .with_context(|| format!("Failed to connect to admin socket {}", path))?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    writeln!(stream, "{}", if paused { "pause" } else { "resume" })?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    match response.trim() {
+        "ok" => Ok(()),
+        other => Err(anyhow::anyhow!("Unexpected response from agent: {}", other)),
+    }
+}
+
+/// Fetches the current counters of a running agent.
+#[cfg(unix)]
+pub fn request_status(path: &str) -> Result<StatusSnapshot> {
+    let mut stream = UnixStream::connect(path)
+        .with_context(|| format!("Failed to connect to admin socket {}", path))?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    writeln!(stream, "status")?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    serde_json::from_str(&response)
+        .with_context(|| format!("Unexpected response from agent: {}", response.trim()))
+}
+
+/// The agent on Windows has no admin socket: std has no Unix sockets there.
+#[cfg(windows)]
+fn unsupported(path: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Failed to connect to admin socket {}: admin sockets are not available on Windows",
+        path
+    )
+}
+
+#[cfg(windows)]
+pub fn request_flush(path: &str, _timeout: Duration) -> Result<usize> {
+    Err(unsupported(path))
+}
+
+#[cfg(windows)]
+pub fn request_pause(path: &str, _paused: bool) -> Result<()> {
+    Err(unsupported(path))
+}
+
+#[cfg(windows)]
+pub fn request_status(path: &str) -> Result<StatusSnapshot> {
+    Err(unsupported(path))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::clock::SimulatedClock;
+    use crate::delivery::Listeners;
+    use crate::exporter::{DeliveryPolicy, ExporterHandle, Protocol, Receipt, MAX_RETRIES};
+    use crate::testutil::TempDir;
+    use crate::{Config, Record};
+    use chrono::Utc;
+    use std::io::Read;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn a_flush_waits_for_the_disk_buffer_to_be_sent() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/logs", listener.local_addr().unwrap());
+        // Every attempt of the first request fails, so it is buffered; the rest succeed.
+        let posts = Arc::new(AtomicUsize::new(0));
+        {
+            let posts = posts.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let mut reader = BufReader::new(stream);
+                    let (mut line, mut length) = (String::new(), 0);
+                    reader.read_line(&mut line).unwrap();
+                    while reader.read_line(&mut line).unwrap() > 0 && !line.ends_with("\r\n\r\n") {
+                        let header = line.lines().last().unwrap_or_default().to_lowercase();
+                        if let Some(value) = header.strip_prefix("content-length:") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                    reader.read_exact(&mut vec![0; length]).unwrap();
+                    let status = match posts.fetch_add(1, Ordering::SeqCst) < MAX_RETRIES {
+                        true => "503 Service Unavailable",
+                        false => "200 OK",
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    );
+                    reader.get_mut().write_all(response.as_bytes()).unwrap();
+                }
+            });
+        }
+        let dir = TempDir::new("flush-disk-buffer");
+        let config: Config = format!(
+            "endpoint = \"{}\"\nbatch_size = 1\ncompression = \"none\"\nstate_dir = {:?}\n\n[disk_buffer]\n",
+            endpoint,
+            dir.to_str().unwrap()
+        )
+        .parse()
+        .unwrap();
+        let config = Arc::new(config);
+        let state = Arc::new(AgentState::default());
+        let exporter = ExporterHandle::spawn(
+            "signoz",
+            &endpoint,
+            Protocol::Http,
+            config.credentials(),
+            config.clone(),
+            state.clone(),
+            Arc::new(SimulatedClock::new(Utc::now())),
+        );
+        state.exporters.lock().unwrap().push(exporter.stats.clone());
+        let record = Arc::new(Record::new("buffered"));
+        let receipt = Receipt::new(
+            1,
+            DeliveryPolicy::Any,
+            state.clone(),
+            record.clone(),
+            Listeners::default(),
+        );
+        exporter.submit(&Arc::from("app"), record, Arc::new(receipt));
+        while state.buffered() == 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(state.pending(), 0);
+
+        // Sooner than the disk buffer would be retried without the flush.
+        assert_eq!(flush(&state, Duration::from_secs(3)), "ok 0");
+        assert_eq!(state.buffered(), 0);
+        assert_eq!(posts.load(Ordering::SeqCst), MAX_RETRIES + 1);
+    }
+}
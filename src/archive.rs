@@ -0,0 +1,102 @@
+use flate2::read::MultiGzDecoder;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::input::StopFlag;
+use crate::stats::AgentState;
+
+/// The rotated copies of `path` next to it, oldest first: `app.log.1`, `app.log.2.gz` or
+/// `app.log-20240305.gz`, as logrotate names them.
+pub fn rotated(path: &str) -> Vec<PathBuf> {
+    let path = Path::new(path);
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name().and_then(|n| n.to_str())) else {
+        return Vec::new();
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut rotated: Vec<(SystemTime, PathBuf)> = entries
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .and_then(|n| n.strip_prefix(name))
+                .is_some_and(is_rotation_suffix)
+        })
+        .filter_map(|entry| {
+            let meta = entry.metadata().ok().filter(|m| m.is_file())?;
+            Some((meta.modified().ok()?, entry.path()))
+        })
+        .collect();
+    rotated.sort();
+    rotated.into_iter().map(|(_, path)| path).collect()
+}
+
+/// `.1`, `.2.gz`, `-20240305` or `-20240305.gz`.
+fn is_rotation_suffix(suffix: &str) -> bool {
+    let suffix = suffix.strip_suffix(".gz").unwrap_or(suffix);
+    suffix
+        .strip_prefix(['.', '-'])
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Reads the lines of `path`, decompressed if it ends in `.gz`, delivering them in batches
+/// of about `buffer_bytes`. False if `stop` ended it early.
+pub fn read<F>(
+    path: &Path,
+    buffer_bytes: usize,
+    state: &AgentState,
+    stop: &StopFlag,
+    mut deliver: F,
+) -> bool
+where
+    F: FnMut(Vec<Vec<u8>>),
+{
+    let file = match fs::File::open(path) {
+        Ok(f) => f,
+        Err(e) => {
+            state.record_error(format!("Failed to open {}: {e}", path.display()));
+            return true;
+        }
+    };
+    let file: Box<dyn Read> = if path.extension().is_some_and(|e| e == "gz") {
+        Box::new(MultiGzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    let mut reader = BufReader::with_capacity(buffer_bytes, file);
+    let mut buf = Vec::new();
+    let (mut batch, mut batch_bytes) = (Vec::new(), 0);
+    while !stop.is_stopped() {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                if !String::from_utf8_lossy(&buf).trim().is_empty() {
+                    batch.push(crate::trim_line_end(&buf).to_vec());
+                    batch_bytes += n;
+                }
+                if batch_bytes >= buffer_bytes {
+                    deliver(std::mem::take(&mut batch));
+                    batch_bytes = 0;
+                }
+            }
+            Err(e) => {
+                state.record_error(format!("Error reading {}: {e}", path.display()));
+                break;
+            }
+        }
+    }
+    if !batch.is_empty() {
+        deliver(batch);
+    }
+    !stop.is_stopped()
+}
@@ -0,0 +1,334 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::delivery::{Listener, Outcome};
+use crate::exporter::http_client;
+use crate::stats::AgentState;
+use crate::{Config, Record};
+
+/// How long the query API gets to answer.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Periodically asks the SigNoz query API how many records of this agent it holds over a
+/// trailing window, and warns when fewer arrived than were delivered.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditConfig {
+    /// The SigNoz query service, e.g. `http://signoz:8080`.
+    pub query_url: String,
+    /// Sent as `SIGNOZ-API-KEY`.
+    pub api_key: Option<String>,
+    #[serde(default = "default_interval_minutes")]
+    pub interval_minutes: u64,
+    /// How many minutes of records each check counts.
+    #[serde(default = "default_window_minutes")]
+    pub window_minutes: u64,
+    /// How many minutes the window ends before the check, for records still on their way
+    /// through the collector.
+    #[serde(default = "default_delay_minutes")]
+    pub delay_minutes: u64,
+    /// The share of the delivered records that may be missing without a warning.
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+    /// The `agent.instance.id` resource attribute the records carry, by default one
+    /// generated once and kept in the state directory.
+    pub instance_id: Option<String>,
+}
+
+fn default_interval_minutes() -> u64 {
+    15
+}
+
+fn default_window_minutes() -> u64 {
+    60
+}
+
+fn default_delay_minutes() -> u64 {
+    5
+}
+
+fn default_tolerance() -> f64 {
+    0.01
+}
+
+impl AuditConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !self.query_url.starts_with("http://") && !self.query_url.starts_with("https://") {
+            return Err(anyhow::anyhow!("query_url must be an http(s) URL"));
+        }
+        if self.interval_minutes == 0 || self.window_minutes == 0 {
+            return Err(anyhow::anyhow!(
+                "interval_minutes and window_minutes must be at least 1"
+            ));
+        }
+        if !(0.0..1.0).contains(&self.tolerance) {
+            return Err(anyhow::anyhow!("tolerance must be at least 0 and below 1"));
+        }
+        Ok(())
+    }
+}
+
+/// The configured `instance_id`, or the one kept in `state_dir`, created on first use.
+pub fn instance_id(audit: &AuditConfig, state_dir: &str) -> anyhow::Result<String> {
+    if let Some(ref id) = audit.instance_id {
+        return Ok(id.clone());
+    }
+    let path = crate::statedir::file_in(state_dir, "instance-id");
+    if let Ok(id) = fs::read_to_string(&path) {
+        if !id.trim().is_empty() {
+            return Ok(id.trim().to_string());
+        }
+    }
+    let id = uuid::Uuid::new_v4().to_string();
+    fs::write(&path, format!("{}\n", id)).with_context(|| format!("Failed to write {}", path))?;
+    Ok(id)
+}
+
+fn minute_of(time: DateTime<Utc>) -> i64 {
+    time.timestamp().div_euclid(60)
+}
+
+fn time_of(minute: i64) -> String {
+    DateTime::<Utc>::from_timestamp(minute * 60, 0)
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+/// Records delivered since the agent started, per minute of their timestamp, the time
+/// SigNoz counts them by.
+pub struct Sent {
+    since: i64,
+    minutes: Mutex<BTreeMap<i64, u64>>,
+}
+
+impl Sent {
+    pub fn new(now: DateTime<Utc>) -> Sent {
+        Sent {
+            since: minute_of(now),
+            minutes: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// The window of `window_minutes` ending `delay_minutes` before `now`, in whole
+    /// minutes, as Unix minutes. It starts no earlier than the agent, whose earlier
+    /// records are not counted here.
+    fn window(&self, audit: &AuditConfig, now: DateTime<Utc>) -> (i64, i64) {
+        let end = minute_of(now) - audit.delay_minutes as i64;
+        let start = (end - audit.window_minutes as i64).max(self.since + 1);
+        (start, end)
+    }
+
+    /// Records of the minutes from `start` up to `end`; earlier minutes are forgotten.
+    fn take(&self, start: i64, end: i64) -> u64 {
+        let mut minutes = self.minutes.lock().unwrap();
+        *minutes = minutes.split_off(&start);
+        minutes.range(start..end).map(|(_, n)| n).sum()
+    }
+}
+
+impl Listener for Sent {
+    fn outcome(&self, record: &Record, outcome: &Outcome) {
+        if matches!(outcome, Outcome::Delivered) {
+            let minute = minute_of(record.timestamp.unwrap_or(record.observed));
+            *self.minutes.lock().unwrap().entry(minute).or_default() += 1;
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Finding {
+    Matched,
+    /// SigNoz holds fewer records than were delivered, beyond `tolerance`.
+    Missing {
+        sent: u64,
+        found: u64,
+    },
+    /// SigNoz holds more, e.g. batches stored twice after a retry.
+    Duplicated {
+        sent: u64,
+        found: u64,
+    },
+}
+
+pub fn compare(sent: u64, found: u64, tolerance: f64) -> Finding {
+    if found > sent {
+        Finding::Duplicated { sent, found }
+    } else if (sent - found) as f64 > sent as f64 * tolerance {
+        Finding::Missing { sent, found }
+    } else {
+        Finding::Matched
+    }
+}
+
+/// Checks every `interval_minutes` the records SigNoz holds for `instance_id` against
+/// those `sent` delivered, recording an error for every shortfall.
+pub fn run(
+    audit: AuditConfig,
+    instance_id: String,
+    sent: Arc<Sent>,
+    config: Arc<Config>,
+    state: Arc<AgentState>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let client = match http_client(&config, &Default::default(), QUERY_TIMEOUT) {
+            Ok(client) => client,
+            Err(e) => {
+                state.record_error(format!("Audit disabled, no HTTP client: {}", e));
+                return;
+            }
+        };
+        loop {
+            thread::sleep(Duration::from_secs(audit.interval_minutes * 60));
+            let (start, end) = sent.window(&audit, Utc::now());
+            if start >= end {
+                continue;
+            }
+            let delivered = sent.take(start, end);
+            let found = match count(&client, &audit, &instance_id, start, end) {
+                Ok(found) => found,
+                Err(e) => {
+                    state.record_error(format!(
+                        "Audit query to {} failed: {:#}",
+                        audit.query_url, e
+                    ));
+                    continue;
+                }
+            };
+            let window = format!("{} to {} UTC", time_of(start), time_of(end));
+            match compare(delivered, found, audit.tolerance) {
+                Finding::Matched => println!(
+                    "Audit: SigNoz holds {} of the {} records delivered from {}",
+                    found, delivered, window
+                ),
+                Finding::Missing { sent, found } => state.record_error(format!(
+                    "Audit: SigNoz holds {} of the {} records delivered from {}; {} are missing",
+                    found,
+                    sent,
+                    window,
+                    sent - found
+                )),
+                Finding::Duplicated { sent, found } => println!(
+                    "Audit: SigNoz holds {} records for the {} delivered from {}, some of them twice",
+                    found, sent, window
+                ),
+            }
+        }
+    })
+}
+
+/// The records SigNoz holds with `agent.instance.id` set to `instance_id` and a timestamp
+/// from minute `start` up to minute `end`.
+fn count(
+    client: &reqwest::blocking::Client,
+    audit: &AuditConfig,
+    instance_id: &str,
+    start: i64,
+    end: i64,
+) -> anyhow::Result<u64> {
+    let query = json!({
+        "start": start * 60_000,
+        "end": end * 60_000 - 1,
+        "step": 60,
+        "compositeQuery": {
+            "queryType": "builder",
+            "panelType": "value",
+            "builderQueries": {
+                "A": {
+                    "queryName": "A",
+                    "expression": "A",
+                    "dataSource": "logs",
+                    "aggregateOperator": "count",
+                    "reduceTo": "sum",
+                    "stepInterval": 60,
+                    "disabled": false,
+                    "filters": {
+                        "op": "AND",
+                        "items": [{
+                            "key": {"key": "agent.instance.id", "type": "resource", "dataType": "string"},
+                            "op": "=",
+                            "value": instance_id,
+                        }],
+                    },
+                },
+            },
+        },
+    });
+    let mut request = client
+        .post(format!(
+            "{}/api/v3/query_range",
+            audit.query_url.trim_end_matches('/')
+        ))
+        .json(&query);
+    if let Some(ref key) = audit.api_key {
+        request = request.header("SIGNOZ-API-KEY", key);
+    }
+    let response = request.send()?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("it answered {}", response.status()));
+    }
+    let body: Value = response.json()?;
+    Ok(body["data"]["result"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|result| result["series"].as_array().into_iter().flatten())
+        .flat_map(|series| series["values"].as_array().into_iter().flatten())
+        .filter_map(|point| match &point["value"] {
+            Value::String(value) => value.parse::<f64>().ok(),
+            value => value.as_f64(),
+        })
+        .sum::<f64>() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn delivered_records_are_counted_by_minute_and_compared() {
+        let audit: AuditConfig = toml::from_str("query_url = \"http://signoz:8080\"").unwrap();
+        let started = Utc.with_ymd_and_hms(2024, 3, 5, 10, 0, 30).unwrap();
+        let sent = Sent::new(started);
+        let record = |minute: u32| Record {
+            timestamp: Some(Utc.with_ymd_and_hms(2024, 3, 5, 10, minute, 10).unwrap()),
+            ..Record::new("line")
+        };
+        for minute in [1, 1, 2, 30, 56] {
+            sent.outcome(&record(minute), &Outcome::Delivered);
+        }
+        sent.outcome(
+            &record(2),
+            &Outcome::Undelivered(vec!["signoz".to_string()]),
+        );
+
+        // At 11:00 the window covers 10:01 up to 10:55, after the agent started at 10:00.
+        let (start, end) = sent.window(&audit, started + chrono::Duration::minutes(60));
+        assert_eq!(end - start, 54);
+        assert_eq!(sent.take(start, end), 4);
+        assert_eq!(sent.take(end, end + 5), 1);
+        assert_eq!(sent.take(start, end), 0);
+
+        assert_eq!(compare(1000, 995, audit.tolerance), Finding::Matched);
+        assert_eq!(
+            compare(1000, 980, audit.tolerance),
+            Finding::Missing {
+                sent: 1000,
+                found: 980
+            }
+        );
+        assert_eq!(
+            compare(10, 12, audit.tolerance),
+            Finding::Duplicated {
+                sent: 10,
+                found: 12
+            }
+        );
+    }
+}
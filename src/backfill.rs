@@ -0,0 +1,248 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use crate::input::{Input, InputContext, StopFlag};
+use crate::stats::{AgentState, InputStats};
+
+/// Batches of lines buffered per chunk in ordered mode before its reader blocks.
+const ORDERED_CHUNK_BUFFER: usize = 64;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Delivery {
+    /// Records are delivered in file order; later chunks are read ahead but held back.
+    #[default]
+    Ordered,
+    /// Each chunk delivers as soon as its lines are read.
+    Unordered,
+}
+
+/// A one-off read of an existing file from the start, split into chunks at line
+/// boundaries that are read in parallel.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BackfillInput {
+    pub path: String,
+    /// Number of chunks read concurrently; defaults to the number of CPUs.
+    pub workers: Option<usize>,
+    #[serde(default)]
+    pub delivery: Delivery,
+    #[serde(skip)]
+    pub stop: StopFlag,
+}
+
+impl Input for BackfillInput {
+    fn describe(&self) -> String {
+        format!("backfill:{}", self.path)
+    }
+
+    fn start(&mut self, ctx: &InputContext) -> anyhow::Result<()> {
+        let stats = ctx.register(self);
+        let handler = ctx.replay_line_handler(&self.path, stats.clone());
+        let buffer_bytes = ctx
+            .config
+            .read_buffer_bytes
+            .unwrap_or(crate::DEFAULT_READ_BUFFER_BYTES);
+        let replay_rate = crate::replay::rate(&ctx.config);
+        let (state, stop) = (ctx.state.clone(), self.stop.clone());
+        run_backfill_input(
+            self.clone(),
+            buffer_bytes,
+            ctx.state.clone(),
+            stats,
+            move |lines| {
+                // A backfill is all backlog; lines cut off by a stop are simply not sent.
+                if let Some(rate) = replay_rate {
+                    if !crate::replay::wait(&state, rate, lines.len(), &stop) {
+                        return;
+                    }
+                }
+                handler(lines, None)
+            },
+        );
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.stop.stop();
+    }
+}
+
+pub fn run_backfill_input<F>(
+    input: BackfillInput,
+    buffer_bytes: usize,
+    state: Arc<AgentState>,
+    stats: Arc<InputStats>,
+    handler: F,
+) -> thread::JoinHandle<()>
+where
+    F: Fn(Vec<Vec<u8>>) + Send + Sync + 'static,
+{
+    thread::spawn(move || {
+        let started = Instant::now();
+        let workers = input
+            .workers
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+            .max(1);
+        let chunks = match split_at_lines(&input.path, workers) {
+            Ok(chunks) => chunks,
+            Err(e) => {
+                state.record_error(format!("Failed to open backfill {}: {e}", input.path));
+                return;
+            }
+        };
+        let total = chunks.last().map_or(0, |&(_, end)| end);
+        stats.lag_bytes.store(total, Ordering::Relaxed);
+        println!(
+            "Backfilling {} ({} bytes) in {} chunks",
+            input.path,
+            total,
+            chunks.len()
+        );
+
+        let handler = Arc::new(handler);
+        match input.delivery {
+            Delivery::Unordered => {
+                let readers: Vec<_> = chunks
+                    .into_iter()
+                    .map(|chunk| {
+                        let (path, state, stats, stop, handler) = (
+                            input.path.clone(),
+                            state.clone(),
+                            stats.clone(),
+                            input.stop.clone(),
+                            handler.clone(),
+                        );
+                        thread::spawn(move || {
+                            read_chunk(&path, chunk, buffer_bytes, &state, &stats, &stop, |lines| {
+                                handler(lines)
+                            })
+                        })
+                    })
+                    .collect();
+                for reader in readers {
+                    reader.join().ok();
+                }
+            }
+            Delivery::Ordered => {
+                let receivers: Vec<_> = chunks
+                    .into_iter()
+                    .map(|chunk| {
+                        let (tx, rx) = mpsc::sync_channel(ORDERED_CHUNK_BUFFER);
+                        let (path, state, stats, stop) = (
+                            input.path.clone(),
+                            state.clone(),
+                            stats.clone(),
+                            input.stop.clone(),
+                        );
+                        thread::spawn(move || {
+                            read_chunk(&path, chunk, buffer_bytes, &state, &stats, &stop, |lines| {
+                                tx.send(lines).ok();
+                            })
+                        });
+                        rx
+                    })
+                    .collect();
+                // Draining the chunks one after another keeps file order while the
+                // readers of later chunks fill their buffers in the meantime.
+                for rx in receivers {
+                    for lines in rx {
+                        handler(lines);
+                    }
+                }
+            }
+        }
+
+        stats.lag_bytes.store(0, Ordering::Relaxed);
+        println!(
+            "Backfill of {} finished in {:.1}s",
+            input.path,
+            started.elapsed().as_secs_f64()
+        );
+    })
+}
+
+/// Splits the file into up to `parts` byte ranges, each moved forward to the start of
+/// the next line so that no line straddles two chunks.
+fn split_at_lines(path: &str, parts: usize) -> std::io::Result<Vec<(u64, u64)>> {
+    let mut file = fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut boundaries = vec![0];
+    for i in 1..parts as u64 {
+        let target = len * i / parts as u64;
+        if target <= *boundaries.last().unwrap() {
+            continue;
+        }
+        file.seek(SeekFrom::Start(target - 1))?;
+        let mut reader = BufReader::new(&mut file);
+        let mut skipped = Vec::new();
+        let n = reader.read_until(b'\n', &mut skipped)? as u64;
+        let boundary = target - 1 + n;
+        if boundary >= len {
+            break;
+        }
+        if boundary > *boundaries.last().unwrap() {
+            boundaries.push(boundary);
+        }
+    }
+    boundaries.push(len);
+    Ok(boundaries.windows(2).map(|w| (w[0], w[1])).collect())
+}
+
+/// Reads the lines of one chunk, delivering them in batches of about `buffer_bytes`.
+fn read_chunk<F>(
+    path: &str,
+    (start, end): (u64, u64),
+    buffer_bytes: usize,
+    state: &AgentState,
+    stats: &InputStats,
+    stop: &StopFlag,
+    mut deliver: F,
+) where
+    F: FnMut(Vec<Vec<u8>>),
+{
+    let mut file = match fs::File::open(path) {
+        Ok(f) => f,
+        Err(e) => {
+            state.record_error(format!("Failed to open backfill {}: {e}", path));
+            return;
+        }
+    };
+    if let Err(e) = file.seek(SeekFrom::Start(start)) {
+        state.record_error(format!("Failed to seek backfill {}: {e}", path));
+        return;
+    }
+
+    let mut reader = BufReader::with_capacity(buffer_bytes, file.take(end - start));
+    let mut buf = Vec::new();
+    let (mut batch, mut batch_bytes) = (Vec::new(), 0);
+    while !stop.is_stopped() {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                stats.lag_bytes.fetch_sub(n as u64, Ordering::Relaxed);
+                if !String::from_utf8_lossy(&buf).trim().is_empty() {
+                    batch.push(crate::trim_line_end(&buf).to_vec());
+                    batch_bytes += n;
+                }
+                if batch_bytes >= buffer_bytes {
+                    deliver(std::mem::take(&mut batch));
+                    batch_bytes = 0;
+                }
+            }
+            Err(e) => {
+                state.record_error(format!("Error reading backfill {}: {e}", path));
+                break;
+            }
+        }
+    }
+    if !batch.is_empty() {
+        deliver(batch);
+    }
+}
@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use crate::input::StopFlag;
+
+/// How often a file waiting for its turn checks whether its input was stopped.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+/// No turn is given before the inputs started at startup had this long to get in line, so
+/// that the first files started are not the first read.
+const SETTLE: Duration = Duration::from_secs(1);
+
+/// `[catch_up]`: the files read from their start on a first run, found in a directory of
+/// historical logs, take turns, so the ones written to last reach SigNoz first.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CatchUpConfig {
+    /// Files read through their backlog at once; the others wait for one to catch up.
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+    #[serde(default)]
+    pub order: CatchUpOrder,
+}
+
+fn default_max_files() -> usize {
+    4
+}
+
+/// Which files go first, by their modification time.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CatchUpOrder {
+    #[default]
+    NewestFirst,
+    OldestFirst,
+}
+
+/// The files in line, each by its place: its modification time in the configured order,
+/// then its path.
+#[derive(Default)]
+struct Line {
+    reading: usize,
+    waiting: BTreeSet<(i128, String)>,
+}
+
+/// Hands out the turns to read a backlog, `max_files` at a time.
+pub struct CatchUp {
+    max_files: usize,
+    order: CatchUpOrder,
+    opened: Instant,
+    line: Mutex<Line>,
+    turn_over: Condvar,
+}
+
+/// A file's turn, over once it is dropped: when the file reached its end for the first
+/// time, or its reader stopped.
+#[derive(Debug)]
+pub struct Turn {
+    catch_up: Arc<CatchUp>,
+    path: String,
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        self.catch_up.line.lock().unwrap().reading -= 1;
+        self.catch_up.turn_over.notify_all();
+        println!("Caught up with {}", self.path);
+    }
+}
+
+impl std::fmt::Debug for CatchUp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CatchUp")
+            .field("max_files", &self.max_files)
+            .finish()
+    }
+}
+
+impl CatchUp {
+    pub fn new(config: &CatchUpConfig) -> Arc<CatchUp> {
+        Arc::new(CatchUp {
+            max_files: config.max_files.max(1),
+            order: config.order,
+            opened: Instant::now(),
+            line: Mutex::default(),
+            turn_over: Condvar::new(),
+        })
+    }
+
+    /// Waits for the turn of `path`; `None` if `stop` came first.
+    pub fn wait(self: &Arc<Self>, path: &str, stop: &StopFlag) -> Option<Turn> {
+        let modified = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_nanos() as i128);
+        let place = (
+            match self.order {
+                CatchUpOrder::NewestFirst => -modified,
+                CatchUpOrder::OldestFirst => modified,
+            },
+            path.to_string(),
+        );
+        let mut line = self.line.lock().unwrap();
+        line.waiting.insert(place.clone());
+        loop {
+            if stop.is_stopped() {
+                line.waiting.remove(&place);
+                self.turn_over.notify_all();
+                return None;
+            }
+            if self.opened.elapsed() >= SETTLE
+                && line.reading < self.max_files
+                && line.waiting.first() == Some(&place)
+            {
+                line.waiting.remove(&place);
+                line.reading += 1;
+                if !line.waiting.is_empty() {
+                    println!(
+                        "Catching up with {}, {} files waiting",
+                        path,
+                        line.waiting.len()
+                    );
+                }
+                return Some(Turn {
+                    catch_up: self.clone(),
+                    path: path.to_string(),
+                });
+            }
+            line = self
+                .turn_over
+                .wait_timeout(line, STOP_CHECK_INTERVAL)
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::thread;
+    use std::time::SystemTime;
+
+    #[test]
+    fn newest_files_catch_up_first() {
+        let dir = TempDir::new("catchup-test");
+        let now = SystemTime::now();
+        let files: Vec<String> = (0..3)
+            .map(|age| {
+                let path = dir.join(format!("app-{}.log", age));
+                let file = fs::File::create(&path).unwrap();
+                file.set_modified(now - Duration::from_secs(3600 * age))
+                    .unwrap();
+                path.display().to_string()
+            })
+            .collect();
+        let catch_up = CatchUp::new(&CatchUpConfig {
+            max_files: 1,
+            order: CatchUpOrder::NewestFirst,
+        });
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        // Got in line oldest first, read newest first.
+        let readers: Vec<_> = files
+            .iter()
+            .rev()
+            .map(|path| {
+                let (catch_up, order, path) = (catch_up.clone(), order.clone(), path.clone());
+                let reader = thread::spawn(move || {
+                    let turn = catch_up.wait(&path, &StopFlag::default()).unwrap();
+                    order.lock().unwrap().push(path);
+                    thread::sleep(Duration::from_millis(20));
+                    drop(turn);
+                });
+                thread::sleep(Duration::from_millis(20));
+                reader
+            })
+            .collect();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), files);
+
+        let stop = StopFlag::default();
+        stop.stop();
+        assert!(catch_up.wait(&files[0], &stop).is_none());
+    }
+}
@@ -0,0 +1,129 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::exporter::ExporterStats;
+use crate::stats::AgentState;
+
+/// How often exporter certificates are checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(6 * 3600);
+/// Default `cert_warning_days`.
+pub const DEFAULT_WARNING_DAYS: u32 = 14;
+
+/// Checks the TLS certificate of every https exporter now and every `CHECK_INTERVAL`,
+/// storing its expiry in the exporter's stats and recording a warning once fewer than
+/// `warning_days` remain. Collectors asking for a client certificate get `identity`.
+pub fn run(
+    exporters: Vec<Arc<ExporterStats>>,
+    warning_days: u32,
+    identity: Option<reqwest::Identity>,
+    state: Arc<AgentState>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        for exporter in &exporters {
+            if !exporter.endpoint.starts_with("https://") {
+                continue;
+            }
+            match peer_expiry(&exporter.endpoint, identity.as_ref()) {
+                Ok(expires) => {
+                    exporter
+                        .cert_expires_unix
+                        .store(expires.timestamp(), Ordering::SeqCst);
+                    let days = (expires - Utc::now()).num_days();
+                    if days < warning_days as i64 {
+                        state.record_error(format!(
+                            "Warning: TLS certificate of exporter {} ({}) expires in {} days, on {}",
+                            exporter.name,
+                            exporter.endpoint,
+                            days,
+                            expires.to_rfc3339()
+                        ));
+                    }
+                }
+                Err(e) => state.record_error(format!(
+                    "Could not check the TLS certificate of exporter {}: {}",
+                    exporter.name, e
+                )),
+            }
+        }
+        thread::sleep(CHECK_INTERVAL);
+    })
+}
+
+/// Expiry of the leaf certificate `endpoint` presents. The certificate is not verified
+/// here; the exporter's own requests do that.
+fn peer_expiry(
+    endpoint: &str,
+    identity: Option<&reqwest::Identity>,
+) -> anyhow::Result<DateTime<Utc>> {
+    let mut client = reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .tls_info(true)
+        .danger_accept_invalid_certs(true);
+    if let Some(identity) = identity {
+        client = client.identity(identity.clone());
+    }
+    let client = client.build()?;
+    let response = client.get(endpoint).send()?;
+    let der = response
+        .extensions()
+        .get::<reqwest::tls::TlsInfo>()
+        .and_then(|info| info.peer_certificate())
+        .ok_or_else(|| anyhow::anyhow!("no peer certificate"))?;
+    not_after(der).ok_or_else(|| anyhow::anyhow!("unreadable certificate validity"))
+}
+
+/// Splits a DER TLV into its tag, contents and the bytes after it.
+fn read_tlv(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = data.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || rest.len() < n {
+            return None;
+        }
+        let len = rest[..n]
+            .iter()
+            .fold(0usize, |acc, &b| acc << 8 | b as usize);
+        (len, &rest[n..])
+    };
+    (rest.len() >= len).then(|| (tag, &rest[..len], &rest[len..]))
+}
+
+/// The `notAfter` time of an X.509 certificate.
+fn not_after(der: &[u8]) -> Option<DateTime<Utc>> {
+    let (0x30, certificate, _) = read_tlv(der)? else {
+        return None;
+    };
+    let (0x30, mut tbs, _) = read_tlv(certificate)? else {
+        return None;
+    };
+    // Optional explicit version, then serial number, signature algorithm and issuer.
+    if tbs.first() == Some(&0xa0) {
+        tbs = read_tlv(tbs)?.2;
+    }
+    for _ in 0..3 {
+        tbs = read_tlv(tbs)?.2;
+    }
+    let (0x30, validity, _) = read_tlv(tbs)? else {
+        return None;
+    };
+    let (tag, time, _) = read_tlv(read_tlv(validity)?.2)?;
+    let time = std::str::from_utf8(time).ok()?;
+    let full = match tag {
+        // UTCTime: two-digit years from 50 are 19xx (RFC 5280).
+        0x17 if time.len() >= 2 => {
+            let century = if &time[..2] >= "50" { "19" } else { "20" };
+            format!("{}{}", century, time)
+        }
+        0x18 => time.to_string(),
+        _ => return None,
+    };
+    NaiveDateTime::parse_from_str(&full, "%Y%m%d%H%M%SZ")
+        .ok()
+        .map(|t| t.and_utc())
+}
@@ -0,0 +1,558 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::failure::Failure;
+use crate::fileid::FileId;
+use crate::paths;
+use crate::stats::{AgentState, InputStats};
+
+/// Default `checkpoint_interval_secs`.
+pub const DEFAULT_INTERVAL_SECS: u64 = 5;
+const POSITIONS_FILE: &str = "positions.json";
+
+/// How far a tailed file was read, and which file that was.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+struct Position {
+    dev: u64,
+    ino: u64,
+    offset: u64,
+    /// The input's record sequence, so `sequence_attribute` keeps increasing.
+    #[serde(default)]
+    sequence: u64,
+}
+
+/// Read positions of the tailed files, by `paths::key` of their path, kept in `positions.json` in the state
+/// directory so a restarted agent picks up the lines written while it was down.
+///
+/// A position is only taken once the records of the lines before it are done with:
+/// delivered, buffered on disk or dropped. Until then it waits in `pending`, so records
+/// still queued in memory when the agent is killed are read again after a restart.
+pub struct Positions {
+    path: PathBuf,
+    /// The state directory, opened once at load. The privsep reader saves as root into
+    /// a directory `privsep_user` owns, so names are looked up in it through this rather
+    /// than through a path that user could move.
+    dir: StateDir,
+    files: Mutex<BTreeMap<String, Position>>,
+    /// Positions read up to but not taken yet, oldest first, by `paths::key`.
+    pending: Mutex<BTreeMap<String, VecDeque<Pending>>>,
+    next_id: AtomicU64,
+    /// What was last written, to skip rewriting an unchanged file.
+    saved: Mutex<BTreeMap<String, Position>>,
+}
+
+struct Pending {
+    id: u64,
+    position: Position,
+    done: bool,
+}
+
+/// Handed on with the lines read up to a position and held by the records made of them;
+/// once the last of them is dropped, the position is taken, after those read before it.
+/// The records of the `privsep_user` agent process hold one that tells the reader.
+pub struct Checkpoint(Mutex<Option<Box<dyn FnOnce() + Send>>>);
+
+impl Checkpoint {
+    /// A checkpoint calling `done` once it is dropped.
+    pub fn on_done(done: impl FnOnce() + Send + 'static) -> Arc<Checkpoint> {
+        Arc::new(Checkpoint(Mutex::new(Some(Box::new(done)))))
+    }
+}
+
+impl Drop for Checkpoint {
+    fn drop(&mut self) {
+        if let Some(done) = self.0.get_mut().unwrap().take() {
+            done();
+        }
+    }
+}
+
+impl fmt::Debug for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Checkpoint")
+    }
+}
+
+/// The checkpoint of the lines a multiline joiner or stitcher holds back across batches:
+/// that of the batch the first of them came in, so the position does not move past them
+/// while they wait.
+#[derive(Default)]
+pub struct Held(Mutex<Option<Arc<Checkpoint>>>);
+
+impl Held {
+    /// The checkpoint for what a batch that came with `current` completed, given whether
+    /// lines were held back before (`holding`) and after it (`held`) and whether it
+    /// completed any (`completed`).
+    pub fn hand_on(
+        &self,
+        current: Option<Arc<Checkpoint>>,
+        (holding, completed, held): (bool, bool, bool),
+    ) -> Option<Arc<Checkpoint>> {
+        let mut kept = self.0.lock().unwrap();
+        let handed_on = if holding {
+            kept.clone()
+        } else {
+            current.clone()
+        };
+        if !held {
+            *kept = None;
+        } else if completed || !holding {
+            // What is held back now started in this batch.
+            *kept = current;
+        }
+        handed_on
+    }
+
+    /// The checkpoint for the lines held back, handed on without waiting for more.
+    pub fn take(&self) -> Option<Arc<Checkpoint>> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+impl Positions {
+    /// Loads the positions saved in `state_dir`; none when the agent never ran there. A
+    /// file that cannot be parsed fails with `Failure::StateCorrupt` rather than being
+    /// overwritten, which would send every file again or skip its backlog.
+    pub fn load(state_dir: &str) -> Result<Arc<Positions>> {
+        let path = Path::new(state_dir).join(POSITIONS_FILE);
+        let dir = open_dir(state_dir).with_context(|| format!("Failed to open {}", state_dir))?;
+        let files: BTreeMap<String, Position> = match read_unlinked(&path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| {
+                    anyhow::anyhow!(
+                        "{} is not a valid positions file ({}); delete it to start over from the end of each file",
+                        path.display(),
+                        e
+                    )
+                })
+                .context(Failure::StateCorrupt)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()))
+            }
+        };
+        Ok(Arc::new(Positions {
+            path,
+            dir,
+            saved: Mutex::new(files.clone()),
+            files: Mutex::new(files),
+            pending: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(0),
+        }))
+    }
+
+    /// Which file to read first for `path`, given the just opened `file` at it, and from
+    /// where: the saved offset if it is the same file, its start if another file took the
+    /// path while the agent was down, and its end for a path never read before. With
+    /// `from_start`, a path never read before is read from its start instead, or from
+    /// where the file was read to under another name it was renamed from. With
+    /// `finish_rotated`, a file rotated away meanwhile is read from the saved offset
+    /// instead, when it can still be found next to `path`; the tailer moves on to the
+    /// file at the path once it is done with it.
+    pub fn resume(
+        &self,
+        path: &str,
+        file: fs::File,
+        stats: &InputStats,
+        finish_rotated: bool,
+        from_start: bool,
+    ) -> (fs::File, u64) {
+        let Ok(meta) = file.metadata() else {
+            return (file, 0);
+        };
+        let files = self.files.lock().unwrap().clone();
+        let Some(&saved) = files.get(paths::key(path).as_ref()) else {
+            if !from_start {
+                return (file, meta.len());
+            }
+            let renamed = files
+                .values()
+                .find(|p| p.dev == meta.dev() && p.ino == meta.ino() && p.offset <= meta.len());
+            return (file, renamed.map_or(0, |p| p.offset));
+        };
+        stats.resume_sequence(saved.sequence);
+        if saved.dev != meta.dev() || saved.ino != meta.ino() {
+            if let Some((rotated, rotated_path)) =
+                finish_rotated.then(|| find_rotated(path, &saved)).flatten()
+            {
+                println!(
+                    "{} was rotated to {} while the agent was down, finishing it from byte {} first",
+                    path,
+                    rotated_path.display(),
+                    saved.offset
+                );
+                return (rotated, saved.offset);
+            }
+            println!(
+                "{} was replaced while the agent was down, reading it from the start",
+                path
+            );
+            return (file, 0);
+        }
+        if saved.offset > meta.len() {
+            println!(
+                "{} was truncated while the agent was down, reading it from the start",
+                path
+            );
+            return (file, 0);
+        }
+        if saved.offset < meta.len() {
+            println!(
+                "Resuming {} at byte {} of {}",
+                path,
+                saved.offset,
+                meta.len()
+            );
+        }
+        (file, saved.offset)
+    }
+
+    /// Notes that `path`, the file described by `meta`, has been read up to `offset`,
+    /// with no lines handed on since the last checkpoint; it is taken once those before
+    /// it are.
+    pub fn record(&self, path: &str, meta: &fs::Metadata, offset: u64, stats: &InputStats) {
+        let key = paths::key(path).into_owned();
+        let id = self.push(&key, meta, offset, stats);
+        self.done(&key, id);
+    }
+
+    /// The checkpoint for the lines of `path` read up to `offset`, to hand on with them.
+    pub fn checkpoint(
+        self: &Arc<Self>,
+        path: &str,
+        meta: &fs::Metadata,
+        offset: u64,
+        stats: &InputStats,
+    ) -> Arc<Checkpoint> {
+        let key = paths::key(path).into_owned();
+        let id = self.push(&key, meta, offset, stats);
+        let positions = self.clone();
+        Checkpoint::on_done(move || positions.done(&key, id))
+    }
+
+    /// Saves the sequence the lines handed on with the last checkpoint of `path` took
+    /// the input to with it.
+    pub fn sequenced(&self, path: &str, stats: &InputStats) {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(last) = pending
+            .get_mut(paths::key(path).as_ref())
+            .and_then(VecDeque::back_mut)
+        {
+            last.position.sequence = stats.sequence();
+        }
+    }
+
+    fn push(&self, key: &str, meta: &fs::Metadata, offset: u64, stats: &InputStats) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let position = Position {
+            dev: meta.dev(),
+            ino: meta.ino(),
+            offset,
+            sequence: stats.sequence(),
+        };
+        let mut pending = self.pending.lock().unwrap();
+        pending
+            .entry(key.to_string())
+            .or_default()
+            .push_back(Pending {
+                id,
+                position,
+                done: false,
+            });
+        id
+    }
+
+    /// Marks checkpoint `id` of `key` done and takes the positions done in order.
+    fn done(&self, key: &str, id: u64) {
+        let mut pending = self.pending.lock().unwrap();
+        let Some(queue) = pending.get_mut(key) else {
+            // Forgotten meanwhile.
+            return;
+        };
+        if let Some(checkpoint) = queue.iter_mut().find(|p| p.id == id) {
+            checkpoint.done = true;
+        }
+        let mut taken = None;
+        while queue.front().is_some_and(|p| p.done) {
+            taken = queue.pop_front().map(|p| p.position);
+        }
+        if queue.is_empty() {
+            pending.remove(key);
+        }
+        if let Some(position) = taken {
+            self.files.lock().unwrap().insert(key.to_string(), position);
+        }
+    }
+
+    /// How far each file was read, by path.
+    #[cfg(unix)]
+    pub fn offsets(&self) -> BTreeMap<String, u64> {
+        let files = self.files.lock().unwrap();
+        files
+            .iter()
+            .map(|(path, p)| (path.clone(), p.offset))
+            .collect()
+    }
+
+    /// Whether `path` was read before.
+    pub fn contains(&self, path: &str) -> bool {
+        self.files
+            .lock()
+            .unwrap()
+            .contains_key(paths::key(path).as_ref())
+    }
+
+    /// Drops the position of `path`, a file no longer followed.
+    pub fn forget(&self, path: &str) {
+        let key = paths::key(path);
+        self.pending.lock().unwrap().remove(key.as_ref());
+        self.files.lock().unwrap().remove(key.as_ref());
+    }
+
+    /// Writes the positions if they changed since the last save, through a temporary
+    /// file so a crash mid-write leaves the previous positions in place.
+    pub fn save(&self) -> Result<()> {
+        let files = self.files.lock().unwrap().clone();
+        let mut saved = self.saved.lock().unwrap();
+        if *saved == files {
+            return Ok(());
+        }
+        replace_in(
+            &self.dir,
+            POSITIONS_FILE,
+            &serde_json::to_vec_pretty(&files)?,
+        )
+        .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+        *saved = files;
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+type StateDir = fs::File;
+#[cfg(windows)]
+type StateDir = PathBuf;
+
+#[cfg(unix)]
+fn open_dir(state_dir: &str) -> std::io::Result<StateDir> {
+    fs::File::open(state_dir)
+}
+
+#[cfg(windows)]
+fn open_dir(state_dir: &str) -> std::io::Result<StateDir> {
+    fs::metadata(state_dir).map(|_| PathBuf::from(state_dir))
+}
+
+/// The contents of `path`, unless it is a symlink.
+#[cfg(unix)]
+fn read_unlinked(path: &Path) -> std::io::Result<String> {
+    use std::io::Read;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut text = String::new();
+    fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)?
+        .read_to_string(&mut text)?;
+    Ok(text)
+}
+
+#[cfg(windows)]
+fn read_unlinked(path: &Path) -> std::io::Result<String> {
+    fs::read_to_string(path)
+}
+
+/// Replaces `name` in `dir` with a file holding `contents`, written as `name.tmp` first.
+/// Whoever owns `dir` may have left a symlink at either name: the temporary file is
+/// created anew and never through a link, and the rename replaces the entry `name`
+/// itself rather than what it points at.
+#[cfg(unix)]
+fn replace_in(dir: &StateDir, name: &str, contents: &[u8]) -> std::io::Result<()> {
+    use std::ffi::CString;
+    use std::io::Write;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    let dir = dir.as_raw_fd();
+    let target = CString::new(name)?;
+    let tmp = CString::new(format!("{}.tmp", name))?;
+    let check = |result: libc::c_int| match result {
+        -1 => Err(std::io::Error::last_os_error()),
+        result => Ok(result),
+    };
+    let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+    // SAFETY: the names are NUL-terminated and outlive the calls, and the descriptor
+    // openat returns is owned by `file` alone. A leftover of a crashed save, or whatever
+    // was planted there, is unlinked first; unlinkat removes a symlink, not its target.
+    let mut file = unsafe {
+        libc::unlinkat(dir, tmp.as_ptr(), 0);
+        fs::File::from_raw_fd(check(libc::openat(dir, tmp.as_ptr(), flags, 0o644))?)
+    };
+    file.write_all(contents)?;
+    drop(file);
+    // SAFETY: as above.
+    check(unsafe { libc::renameat(dir, tmp.as_ptr(), dir, target.as_ptr()) })?;
+    Ok(())
+}
+
+/// Windows has no privsep reader, so the paths are used as they are.
+#[cfg(windows)]
+fn replace_in(dir: &StateDir, name: &str, contents: &[u8]) -> std::io::Result<()> {
+    let tmp = dir.join(format!("{}.tmp", name));
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, dir.join(name))
+}
+
+/// The file `saved` describes, if it was renamed within the directory of `path` under a
+/// name starting with that of `path` (as logrotate does: "app.log.1", "app.log-20240101")
+/// and still holds the saved offset.
+fn find_rotated(path: &str, saved: &Position) -> Option<(fs::File, PathBuf)> {
+    let path = Path::new(path);
+    let name = path.file_name()?.to_str()?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let candidate = entry.file_name();
+        if candidate
+            .to_str()
+            .is_none_or(|c| c == name || !c.starts_with(name))
+        {
+            continue;
+        }
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        if meta.dev() == saved.dev && meta.ino() == saved.ino && meta.len() >= saved.offset {
+            let rotated = entry.path();
+            return Some((fs::File::open(&rotated).ok()?, rotated));
+        }
+    }
+    None
+}
+
+/// Saves `positions` every `interval` until the process exits.
+pub fn run(positions: Arc<Positions>, interval: Duration, state: Arc<AgentState>) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if let Err(e) = positions.save() {
+            state.record_error(format!("Failed to save read positions: {:#}", e));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::StopFlag;
+    use crate::testutil::TempDir;
+    use std::sync::mpsc;
+
+    #[test]
+    fn positions_are_taken_once_the_lines_before_them_are_done_with() {
+        let dir = TempDir::new("checkpoint");
+        let log = dir.join("app.log");
+        fs::write(&log, "one\ntwo\nthree\n").unwrap();
+        let (path, meta) = (log.to_str().unwrap(), fs::metadata(&log).unwrap());
+        let state = AgentState::default();
+        let stats = state.register_input(path);
+        let positions = Positions::load(dir.to_str().unwrap()).unwrap();
+
+        let first = positions.checkpoint(path, &meta, 4, &stats);
+        let second = positions.checkpoint(path, &meta, 8, &stats);
+        positions.record(path, &meta, 14, &stats);
+        drop(second);
+        assert_eq!(positions.offsets().get(path), None);
+        drop(first);
+        assert_eq!(positions.offsets()[path], 14);
+
+        positions.save().unwrap();
+        let held = positions.checkpoint(path, &meta, 4, &stats);
+        let loaded = Positions::load(dir.to_str().unwrap()).unwrap();
+        assert_eq!(loaded.offsets()[path], 14);
+        drop(held);
+
+        // The same file resumes where it was read to, a truncated one at its start.
+        let resumed = |positions: &Positions| {
+            let file = fs::File::open(&log).unwrap();
+            positions.resume(path, file, &stats, true, false).1
+        };
+        assert_eq!(resumed(&loaded), 14);
+        fs::write(&log, "one\n").unwrap();
+        assert_eq!(resumed(&loaded), 0);
+        loaded.forget(path);
+        assert_eq!(resumed(&loaded), 4);
+        assert!(!loaded.contains(path));
+    }
+
+    #[test]
+    fn saving_never_writes_through_a_planted_symlink() {
+        let dir = TempDir::new("planted");
+        let (victim, log) = (dir.join("shadow"), dir.join("app.log"));
+        fs::write(&victim, "root:x:0:0\n").unwrap();
+        fs::write(&log, "one\n").unwrap();
+        let (path, meta) = (log.to_str().unwrap(), fs::metadata(&log).unwrap());
+        let stats = AgentState::default().register_input(path);
+        let positions = Positions::load(dir.to_str().unwrap()).unwrap();
+
+        std::os::unix::fs::symlink(&victim, dir.join("positions.json.tmp")).unwrap();
+        positions.record(path, &meta, 4, &stats);
+        positions.save().unwrap();
+        std::os::unix::fs::symlink(&victim, dir.join("positions.json.tmp")).unwrap();
+        fs::remove_file(dir.join(POSITIONS_FILE)).unwrap();
+        std::os::unix::fs::symlink(&victim, dir.join(POSITIONS_FILE)).unwrap();
+        assert!(Positions::load(dir.to_str().unwrap()).is_err());
+        positions.forget(path);
+        positions.save().unwrap();
+
+        assert_eq!(fs::read_to_string(&victim).unwrap(), "root:x:0:0\n");
+        let saved = fs::symlink_metadata(dir.join(POSITIONS_FILE)).unwrap();
+        assert!(saved.file_type().is_file());
+        assert!(!dir.join("positions.json.tmp").exists());
+    }
+
+    #[test]
+    fn a_restart_reads_again_what_was_not_delivered() {
+        let dir = TempDir::new("crash");
+        let log = dir.join("app.log");
+        fs::write(&log, "").unwrap();
+        let path = log.to_str().unwrap().to_string();
+        let state = Arc::new(AgentState::default());
+        let stats = state.register_input(&path);
+        let (tx, rx) = mpsc::channel();
+        let (reading, stop) = (stats.clone(), StopFlag::default());
+        let positions = Positions::load(dir.to_str().unwrap()).unwrap();
+        crate::tail_file(
+            path.clone(),
+            crate::TailOptions::test(),
+            positions.clone(),
+            state,
+            stats,
+            stop.clone(),
+            move |lines, _| tx.send((lines, reading.checkpoint())).unwrap(),
+        );
+        thread::sleep(Duration::from_millis(200));
+        fs::write(&log, "one\ntwo\n").unwrap();
+        // The records of the lines, still queued when the agent is killed.
+        let (lines, undelivered) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(lines, [b"one".to_vec(), b"two".to_vec()]);
+        positions.save().unwrap();
+        let restarted = Positions::load(dir.to_str().unwrap()).unwrap();
+        assert_eq!(restarted.offsets()[&path], 0);
+
+        drop(undelivered);
+        positions.save().unwrap();
+        let restarted = Positions::load(dir.to_str().unwrap()).unwrap();
+        assert_eq!(restarted.offsets()[&path], 8);
+        stop.stop();
+    }
+}
@@ -0,0 +1,233 @@
+use clap::{Args, Parser, Subcommand};
+
+use crate::Config;
+
+/// Ships log files to SigNoz over OTLP. Without a subcommand, runs the agent.
+#[derive(Parser, Debug)]
+#[command(name = "rust-signoz-agent", version)]
+pub struct Cli {
+    /// config.toml to read instead of the one found in the default locations.
+    #[arg(long, global = true, env = "RUST_SIGNOZ_AGENT_CONFIG")]
+    pub config: Option<String>,
+    /// Fetch config.toml from this URL, in which `{hostname}` stands for the host's name,
+    /// at startup and every `--config-url-interval`; the `--config` file caches it.
+    #[arg(long, global = true, env = "RUST_SIGNOZ_AGENT_CONFIG_URL")]
+    pub config_url: Option<String>,
+    /// Seconds between fetches of `--config-url`.
+    #[arg(long, global = true, default_value_t = 300)]
+    pub config_url_interval: u64,
+    #[command(flatten)]
+    pub overrides: Overrides,
+    #[command(flatten)]
+    pub run: RunArgs,
+    /// Same as the `install-service` subcommand, kept for existing scripts.
+    #[arg(long, hide = true)]
+    pub install_service: bool,
+    #[arg(long, hide = true, requires = "install_service")]
+    pub output: Option<String>,
+    /// Development: write the golden files of the OTLP requests, to `testdata/golden` or
+    /// this directory, and exit, after a wire format change that was meant.
+    #[arg(long, hide = true, value_name = "DIR", num_args = 0..=1, default_missing_value = crate::golden::DIR)]
+    pub emit_golden: Option<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Values that take precedence over those of config.toml, also on a reload.
+#[derive(Args, Debug, Clone, Default)]
+pub struct Overrides {
+    /// The endpoint of the default exporter.
+    #[arg(long, global = true, env = "RUST_SIGNOZ_AGENT_ENDPOINT")]
+    pub endpoint: Option<String>,
+    /// The service name of records without one of their own.
+    #[arg(long, global = true, env = "RUST_SIGNOZ_AGENT_SERVICE_NAME")]
+    pub service_name: Option<String>,
+    /// Read files never read before from their start, after their rotated copies, unless
+    /// `start_positions` says otherwise for them.
+    #[arg(long, global = true)]
+    pub from_beginning: bool,
+    /// Also ship the lines piped to the agent, and shut down once the pipe is closed.
+    #[arg(long, global = true)]
+    pub stdin: bool,
+    /// Replay stdin, backfills and `ship` at the pace of their event times: "realtime", or
+    /// a multiplier such as "10x".
+    #[arg(long, global = true)]
+    pub replay_speed: Option<String>,
+    /// Print only the lines read of this severity and above, e.g. "warn"; all are shipped.
+    #[arg(long, global = true)]
+    pub min_severity: Option<String>,
+    /// Print only the lines of this input name or pattern; may be given more than once.
+    #[arg(long = "only-input", global = true)]
+    pub only_inputs: Vec<String>,
+    /// Print only the lines matching this regex.
+    #[arg(long, global = true)]
+    pub grep: Option<String>,
+}
+
+impl Overrides {
+    /// Applies the standard OTel environment variables, then the flags and their
+    /// variables over them.
+    pub fn apply(&self, config: &mut Config) {
+        crate::otel_env::apply(config);
+        if let Some(ref endpoint) = self.endpoint {
+            config.endpoint = endpoint.clone();
+        }
+        if let Some(ref service_name) = self.service_name {
+            config.service_name = Some(service_name.clone());
+        }
+        if self.from_beginning {
+            config
+                .start_positions
+                .entry("*".to_string())
+                .or_insert(crate::input::StartPosition::Beginning);
+        }
+        if self.replay_speed.is_some() {
+            config.replay_speed = self.replay_speed.clone();
+        }
+        let console = &mut config.console;
+        if self.min_severity.is_some() {
+            console.min_severity = self.min_severity.clone();
+        }
+        if !self.only_inputs.is_empty() {
+            console.inputs = self.only_inputs.clone();
+        }
+        if self.grep.is_some() {
+            console.pattern = self.grep.clone();
+        }
+        if self.stdin && !config.inputs.iter().any(|spec| spec.kind == "stdin") {
+            config.inputs.push(crate::input::InputSpec {
+                kind: "stdin".to_string(),
+                options: toml::Table::new(),
+            });
+        }
+    }
+}
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct RunArgs {
+    /// Run detached on hosts without systemd (requires an existing config.toml).
+    #[arg(long)]
+    pub daemon: bool,
+    /// Check every OTLP payload against the spec before sending it.
+    #[arg(long)]
+    pub self_check: bool,
+    /// Refuse to start unless the endpoint accepts a probe request.
+    #[arg(long)]
+    pub fail_fast: bool,
+    /// Take the state directory over from an agent that seems to hold it.
+    #[arg(long)]
+    pub force: bool,
+    /// Read every configured file from its saved position (or its start) to its end,
+    /// deliver what was read, save the state and exit.
+    #[arg(long)]
+    pub oneshot: bool,
+    /// Set on the command line `install-service` registers on Windows, which the Service
+    /// Control Manager starts the agent with.
+    #[arg(long, hide = true)]
+    pub windows_service: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the agent (the default).
+    Run(RunArgs),
+    /// Check config.toml the way startup does, without starting anything.
+    Validate,
+    /// Send a test record to every exporter and report what each endpoint answered.
+    TestConnection,
+    /// Write a systemd unit for the agent; on Windows, register it as a service.
+    InstallService {
+        /// Where to write the unit instead of the default location; on Windows, a script
+        /// with the `sc.exe` commands that register the service.
+        #[arg(long)]
+        output: Option<String>,
+    },
+    /// Follow the configured inputs in the foreground.
+    Tail {
+        /// Print the OTLP request of each record instead of sending it.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Read one file through the pipeline and exit once it is sent.
+    Ship {
+        file: String,
+        /// Wait until every record is delivered, and fail unless all were.
+        #[arg(long)]
+        wait: bool,
+    },
+    /// Ship the events of an exported Windows event log (.evtx) file and exit, e.g. for
+    /// an incident-response import.
+    BackfillEvtx {
+        file: String,
+        /// Wait until every event is delivered, and fail unless all were.
+        #[arg(long)]
+        wait: bool,
+    },
+    /// Drain the queue of a running agent.
+    Flush {
+        /// Seconds to wait before failing with records still queued.
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+    },
+    /// Stop exporting on a running agent; collection goes on.
+    Pause,
+    /// Resume exporting on a running agent.
+    Resume,
+    /// Live dashboard of a running agent.
+    Top {
+        /// Seconds between refreshes.
+        #[arg(long, default_value_t = 1)]
+        interval: u64,
+    },
+    /// Check that the service user can read every input.
+    Doctor,
+    /// Print a deployment manifest embedding config.toml.
+    GenerateManifest {
+        /// docker, daemonset or ansible.
+        target: String,
+        #[arg(long, default_value = crate::manifest::DEFAULT_IMAGE)]
+        image: String,
+        #[arg(long, default_value = "default")]
+        namespace: String,
+    },
+    /// Write what a .deb or .rpm package of this binary and config.toml is built from: the
+    /// systemd unit, the config marked as a conffile, and scripts creating the service user
+    /// and state directory.
+    Package {
+        /// deb or rpm.
+        #[arg(long)]
+        format: String,
+        /// Directory to write to.
+        #[arg(long, default_value = "dist")]
+        output: String,
+        /// Maintainer of the package, as `Name <email>`.
+        #[arg(long, default_value = crate::package::DEFAULT_MAINTAINER)]
+        maintainer: String,
+    },
+    /// Replace the binary with the newest release.
+    SelfUpdate {
+        /// stable or prerelease.
+        #[arg(long, default_value = "stable")]
+        channel: String,
+        /// Restart the systemd service once the binary is replaced.
+        #[arg(long)]
+        restart: bool,
+        #[arg(long, default_value = crate::update::RELEASES_URL)]
+        releases_url: String,
+    },
+    /// Work on config.toml.
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Compare buffered and mmap line reading throughput on a file.
+    BenchReader { file: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Rewrite config.toml to list every input as an `[[inputs]]` entry.
+    Migrate {
+        /// Print the result instead of writing it.
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
@@ -0,0 +1,236 @@
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clock::Clock;
+use crate::exporter::{run_worker, BatchSink, ClickhouseConfig, ExporterStats, Submitted};
+use crate::queue::Lanes;
+use crate::stats::AgentState;
+use crate::{build_otlp_payload, severity_of, AttributeValue, Config, KeyValue, LogBody};
+use crate::{LogRecord, Record};
+
+/// Width of the `ts_bucket_start` buckets of the v2 schema.
+const TS_BUCKET_SECS: u64 = 1800;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Worker loop of the ClickHouse exporter: collects up to `batch_size` records, or
+/// whatever arrived within `flush_interval_ms` of the first, and inserts them at once.
+pub fn run(
+    clickhouse: ClickhouseConfig,
+    rx: Arc<Lanes<Submitted>>,
+    config: Arc<Config>,
+    state: Arc<AgentState>,
+    stats: Arc<ExporterStats>,
+    clock: Arc<dyn Clock>,
+) {
+    let client =
+        crate::exporter::http_client(&config, &Default::default(), Duration::from_secs(30))
+            .unwrap_or_else(|_| reqwest::blocking::Client::new());
+    let flush_interval = Duration::from_millis(clickhouse.flush_interval_ms.max(1));
+    println!(
+        "Exporter clickhouse writes schema v{} tables of {} at {}",
+        clickhouse.schema_version, clickhouse.database, clickhouse.url
+    );
+
+    let batching = (clickhouse.batch_size, flush_interval);
+    let inserter = Inserter {
+        client,
+        clickhouse,
+        config,
+    };
+    run_worker(rx, inserter, batching, &state, &stats, clock.as_ref());
+}
+
+struct Inserter {
+    client: reqwest::blocking::Client,
+    clickhouse: ClickhouseConfig,
+    config: Arc<Config>,
+}
+
+impl BatchSink for Inserter {
+    fn write(&mut self, records: &[&Record]) -> Result<(), String> {
+        insert_batch(&self.client, &self.clickhouse, &self.config, records).map_err(|e| {
+            format!(
+                "ClickHouse insert of {} records failed: {}",
+                records.len(),
+                e
+            )
+        })
+    }
+}
+
+fn insert_batch(
+    client: &reqwest::blocking::Client,
+    clickhouse: &ClickhouseConfig,
+    config: &Config,
+    records: &[&Record],
+) -> Result<(), String> {
+    let mut rows = Vec::with_capacity(records.len());
+    let mut resources = BTreeMap::new();
+    for entry in records {
+        let payload = build_otlp_payload(entry, severity_of(entry, config), config);
+        let resource_log = &payload.resource_logs[0];
+        let record = &resource_log.scope_logs[0].log_records[0];
+        let observed = record.observed_time_unix_nano.parse().unwrap_or(0);
+        let resource = &resource_log.resource.attributes;
+        rows.push(match clickhouse.schema_version {
+            1 => row_v1(record, resource, observed),
+            _ => {
+                let (row, fingerprint, bucket) = row_v2(record, resource, observed);
+                resources.insert((fingerprint, bucket), string_map(resource));
+                row
+            }
+        });
+    }
+
+    let (logs_table, resource_table) = match clickhouse.schema_version {
+        1 => ("distributed_logs", None),
+        _ => ("distributed_logs_v2", Some("distributed_logs_v2_resource")),
+    };
+    if let Some(table) = resource_table {
+        let rows: Vec<Value> = resources
+            .into_iter()
+            .map(|((fingerprint, bucket), labels)| {
+                json!({
+                    "labels": Value::Object(labels).to_string(),
+                    "fingerprint": fingerprint,
+                    "seen_at_ts_bucket_start": bucket,
+                })
+            })
+            .collect();
+        insert(client, clickhouse, table, &rows)?;
+    }
+    insert(client, clickhouse, logs_table, &rows)
+}
+
+fn insert(
+    client: &reqwest::blocking::Client,
+    clickhouse: &ClickhouseConfig,
+    table: &str,
+    rows: &[Value],
+) -> Result<(), String> {
+    let mut body = String::new();
+    for row in rows {
+        body.push_str(&row.to_string());
+        body.push('\n');
+    }
+    let query = format!(
+        "INSERT INTO {}.{} FORMAT JSONEachRow",
+        clickhouse.database, table
+    );
+    let mut request = client
+        .post(&clickhouse.url)
+        .query(&[("query", query.as_str())])
+        .body(body);
+    if let Some(ref user) = clickhouse.user {
+        request = request.header("X-ClickHouse-User", user);
+    }
+    if let Some(ref password) = clickhouse.password {
+        request = request.header("X-ClickHouse-Key", password);
+    }
+    let response = request.send().map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let message = response.text().unwrap_or_default();
+        return Err(format!("HTTP {} on {}: {}", status, table, message.trim()));
+    }
+    Ok(())
+}
+
+fn row_v1(record: &LogRecord, resource: &[KeyValue], observed: u64) -> Value {
+    let (resource_keys, resource_values): (Vec<_>, Vec<_>) = resource.iter().map(pair).unzip();
+    let (keys, values): (Vec<_>, Vec<_>) = record.attributes.iter().map(pair).unzip();
+    let mut row = common_columns(record, observed);
+    row.insert("resources_string_key".into(), json!(resource_keys));
+    row.insert("resources_string_value".into(), json!(resource_values));
+    row.insert("attributes_string_key".into(), json!(keys));
+    row.insert("attributes_string_value".into(), json!(values));
+    Value::Object(row)
+}
+
+/// A v2 row plus the resource fingerprint and time bucket it is listed under in the
+/// resource table.
+fn row_v2(record: &LogRecord, resource: &[KeyValue], observed: u64) -> (Value, String, u64) {
+    let timestamp = timestamp_nanos(record);
+    let bucket = timestamp / 1_000_000_000 / TS_BUCKET_SECS * TS_BUCKET_SECS;
+    let fingerprint = fingerprint(resource);
+    let mut row = common_columns(record, observed);
+    row.insert("ts_bucket_start".into(), json!(bucket));
+    row.insert("resource_fingerprint".into(), json!(fingerprint));
+    row.insert(
+        "attributes_string".into(),
+        Value::Object(string_map(&record.attributes)),
+    );
+    row.insert(
+        "resources_string".into(),
+        Value::Object(string_map(resource)),
+    );
+    row.insert("scope_name".into(), json!("rust-signoz-agent"));
+    (Value::Object(row), fingerprint, bucket)
+}
+
+fn common_columns(record: &LogRecord, observed: u64) -> Map<String, Value> {
+    let timestamp = timestamp_nanos(record);
+    let body = match record.body {
+        LogBody::String { ref value } | LogBody::Bytes { ref value } => value.clone(),
+    };
+    let mut row = Map::new();
+    row.insert("timestamp".into(), json!(timestamp));
+    row.insert("observed_timestamp".into(), json!(observed));
+    row.insert(
+        "id".into(),
+        json!(format!(
+            "{:016x}{:08x}",
+            timestamp,
+            NEXT_ID.fetch_add(1, Ordering::Relaxed) as u32
+        )),
+    );
+    row.insert(
+        "trace_id".into(),
+        json!(record.trace_id.clone().unwrap_or_default()),
+    );
+    row.insert(
+        "span_id".into(),
+        json!(record.span_id.clone().unwrap_or_default()),
+    );
+    row.insert("severity_text".into(), json!(record.severity_text));
+    row.insert("severity_number".into(), json!(record.severity_number));
+    row.insert("body".into(), json!(body));
+    row
+}
+
+fn timestamp_nanos(record: &LogRecord) -> u64 {
+    record.time_unix_nano.parse().unwrap_or(0)
+}
+
+fn pair(kv: &KeyValue) -> (&str, &str) {
+    match kv.value {
+        AttributeValue::StringValue { ref value } => (kv.key.as_str(), value.as_str()),
+    }
+}
+
+fn string_map(attributes: &[KeyValue]) -> Map<String, Value> {
+    attributes
+        .iter()
+        .map(pair)
+        .map(|(k, v)| (k.to_string(), json!(v)))
+        .collect()
+}
+
+/// Stable identifier of a resource attribute set: `hash=` and the FNV-1a hash of its
+/// sorted `key=value` pairs.
+fn fingerprint(resource: &[KeyValue]) -> String {
+    let mut pairs: Vec<_> = resource.iter().map(pair).collect();
+    pairs.sort();
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for (key, value) in pairs {
+        for byte in key.bytes().chain([b'=']).chain(value.bytes()).chain([b';']) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("hash={}", hash)
+}
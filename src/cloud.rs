@@ -0,0 +1,381 @@
+use reqwest::blocking::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Where the AWS and Azure metadata services answer; GCP's answers there as well as at
+/// metadata.google.internal, which saves a DNS lookup on hosts that are not instances.
+const METADATA_HOST: &str = "http://169.254.169.254";
+
+/// How long an IMDSv2 session token is asked for; it is used until shortly before.
+const AWS_TOKEN_TTL: Duration = Duration::from_secs(6 * 3600);
+
+/// How long the region of `{region}` in endpoint templates is waited for without
+/// `[cloud_metadata]`.
+const REGION_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// `[cloud_metadata]`: the resource attributes of every record get the `cloud.*` and
+/// `host.*` attributes of the AWS, GCP or Azure instance the agent runs on, as its
+/// metadata service gives them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CloudMetadataConfig {
+    /// How long the attributes are used before they are asked for again.
+    #[serde(default = "default_ttl_secs")]
+    pub ttl_secs: u64,
+    /// How long the metadata services get to answer, all of them together; a host none
+    /// answers on within it is taken not to be an instance.
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_ttl_secs() -> u64 {
+    3600
+}
+
+fn default_timeout_ms() -> u64 {
+    300
+}
+
+impl CloudMetadataConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.ttl_secs == 0 {
+            return Err(anyhow::anyhow!(
+                "cloud_metadata ttl_secs must be greater than 0"
+            ));
+        }
+        if !(1..=5000).contains(&self.timeout_ms) {
+            return Err(anyhow::anyhow!(
+                "cloud_metadata timeout_ms must be between 1 and 5000"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Resource attributes, by OTel semantic convention name.
+type Attributes = Vec<(String, String)>;
+
+/// The attributes last fetched; `None` until the first fetch.
+static CURRENT: RwLock<Option<Arc<Attributes>>> = RwLock::new(None);
+
+/// The cached IMDSv2 session token and when it expires.
+static AWS_TOKEN: Mutex<Option<(String, Instant)>> = Mutex::new(None);
+
+/// The attributes of the instance as last fetched; empty on other hosts. Never waits on
+/// the metadata services.
+pub fn attributes() -> Arc<Attributes> {
+    CURRENT.read().unwrap().clone().unwrap_or_default()
+}
+
+/// Fetches the attributes once, then again every `ttl_secs` in the background. Keeps
+/// what was fetched before when the services stop answering.
+pub fn watch(cloud: &CloudMetadataConfig) {
+    let timeout = Duration::from_millis(cloud.timeout_ms);
+    let ttl = Duration::from_secs(cloud.ttl_secs);
+    match refresh(timeout) {
+        Some(ref attributes) => println!("Cloud metadata: {}", describe(attributes)),
+        None => println!(
+            "No cloud metadata service answered within {}ms; records get no cloud attributes",
+            cloud.timeout_ms
+        ),
+    }
+    thread::spawn(move || loop {
+        thread::sleep(ttl);
+        refresh(timeout);
+    });
+}
+
+/// The region of the instance, for `{region}` in endpoint templates.
+pub fn region() -> Option<String> {
+    let fetched = CURRENT.read().unwrap().is_some();
+    if !fetched {
+        refresh(REGION_TIMEOUT);
+    }
+    attributes()
+        .iter()
+        .find(|(key, _)| key == "cloud.region")
+        .map(|(_, region)| region.clone())
+}
+
+/// Asks the metadata services, and caches what the first to answer gives.
+fn refresh(timeout: Duration) -> Option<Arc<Attributes>> {
+    let fetched = fetch(timeout).map(Arc::new);
+    let mut current = CURRENT.write().unwrap();
+    match fetched {
+        Some(attributes) => {
+            *current = Some(attributes.clone());
+            Some(attributes)
+        }
+        None => {
+            // Keep the attributes; only a host that never answered goes without.
+            current.get_or_insert_with(Arc::default);
+            None
+        }
+    }
+}
+
+/// Asks all the services at once, giving up on those that have not answered when
+/// `timeout` is over, so that a host that is not an instance waits no longer than that.
+fn fetch(timeout: Duration) -> Option<Attributes> {
+    let client = Client::builder()
+        .timeout(timeout)
+        .connect_timeout(timeout)
+        .no_proxy()
+        .build()
+        .ok()?;
+    let deadline = Instant::now() + timeout;
+    let (tx, rx) = mpsc::channel();
+    let providers: [fn(&Client) -> Option<Attributes>; 3] = [aws, gcp, azure];
+    for provider in providers {
+        let (client, tx) = (client.clone(), tx.clone());
+        thread::spawn(move || tx.send(provider(&client)).ok());
+    }
+    drop(tx);
+    while let Ok(answer) = rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        if answer.is_some() {
+            return answer;
+        }
+    }
+    None
+}
+
+fn text(request: RequestBuilder) -> Option<String> {
+    let response = request.send().ok()?.error_for_status().ok()?;
+    Some(response.text().ok()?.trim().to_string()).filter(|t| !t.is_empty())
+}
+
+fn json(request: RequestBuilder) -> Option<Value> {
+    serde_json::from_str(&text(request)?).ok()
+}
+
+/// IMDSv2: a session token is fetched with a PUT, which a container more hops away from
+/// the instance than its metadata options allow never sees answered, and sent with every
+/// request until it expires.
+fn aws(client: &Client) -> Option<Attributes> {
+    let document = |token: &str| {
+        client
+            .get(format!(
+                "{}/latest/dynamic/instance-identity/document",
+                METADATA_HOST
+            ))
+            .header("X-aws-ec2-metadata-token", token)
+            .send()
+            .ok()
+    };
+    let token = aws_token(client)?;
+    let mut response = document(&token)?;
+    if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+        // Expired early, as when the instance was stopped and started again.
+        *AWS_TOKEN.lock().unwrap() = None;
+        response = document(&aws_token(client)?)?;
+    }
+    let document: Value = response.error_for_status().ok()?.json().ok()?;
+    aws_attributes(&document)
+}
+
+fn aws_token(client: &Client) -> Option<String> {
+    let mut cached = AWS_TOKEN.lock().unwrap();
+    if let Some((ref token, expires)) = *cached {
+        if Instant::now() + Duration::from_secs(60) < expires {
+            return Some(token.clone());
+        }
+    }
+    let token = text(
+        client
+            .put(format!("{}/latest/api/token", METADATA_HOST))
+            .header(
+                "X-aws-ec2-metadata-token-ttl-seconds",
+                AWS_TOKEN_TTL.as_secs().to_string(),
+            ),
+    )?;
+    *cached = Some((token.clone(), Instant::now() + AWS_TOKEN_TTL));
+    Some(token)
+}
+
+fn aws_attributes(document: &Value) -> Option<Attributes> {
+    let mut attributes = vec![
+        ("cloud.provider".to_string(), "aws".to_string()),
+        ("cloud.platform".to_string(), "aws_ec2".to_string()),
+        (
+            "cloud.region".to_string(),
+            document["region"].as_str()?.to_string(),
+        ),
+    ];
+    for (key, field) in [
+        ("cloud.availability_zone", "availabilityZone"),
+        ("cloud.account.id", "accountId"),
+        ("host.id", "instanceId"),
+        ("host.type", "instanceType"),
+        ("host.image.id", "imageId"),
+    ] {
+        push(&mut attributes, key, document[field].as_str());
+    }
+    Some(attributes)
+}
+
+/// The `Metadata-Flavor: Google` header is required of requests, and checked on the
+/// answer: other services at the same address serve something else on the same path.
+fn gcp(client: &Client) -> Option<Attributes> {
+    let get = |path: &str| {
+        let response = client
+            .get(format!("{}/computeMetadata/v1/{}", METADATA_HOST, path))
+            .header("Metadata-Flavor", "Google")
+            .send()
+            .ok()?
+            .error_for_status()
+            .ok()?;
+        let flavor = response.headers().get("Metadata-Flavor")?;
+        (flavor == "Google").then_some(response.text().ok()?)
+    };
+    let instance: Value = serde_json::from_str(&get("instance/?recursive=true")?).ok()?;
+    let project = get("project/project-id");
+    gcp_attributes(&instance, project.as_deref())
+}
+
+fn gcp_attributes(instance: &Value, project: Option<&str>) -> Option<Attributes> {
+    // "projects/<number>/zones/us-central1-a" names the zone, not the region.
+    let zone = instance["zone"].as_str()?.rsplit('/').next()?;
+    let region = zone.rsplit_once('-')?.0;
+    let mut attributes = vec![
+        ("cloud.provider".to_string(), "gcp".to_string()),
+        (
+            "cloud.platform".to_string(),
+            "gcp_compute_engine".to_string(),
+        ),
+        ("cloud.region".to_string(), region.to_string()),
+        ("cloud.availability_zone".to_string(), zone.to_string()),
+    ];
+    push(&mut attributes, "cloud.account.id", project);
+    // A number too large for some JSON readers, so either way round.
+    let id = match instance["id"] {
+        Value::Number(ref id) => Some(id.to_string()),
+        ref id => id.as_str().map(str::to_string),
+    };
+    push(&mut attributes, "host.id", id.as_deref());
+    let machine_type = instance["machineType"].as_str();
+    push(
+        &mut attributes,
+        "host.type",
+        machine_type.and_then(|t| t.rsplit('/').next()),
+    );
+    Some(attributes)
+}
+
+fn azure(client: &Client) -> Option<Attributes> {
+    let compute = json(
+        client
+            .get(format!(
+                "{}/metadata/instance/compute?api-version=2021-02-01",
+                METADATA_HOST
+            ))
+            .header("Metadata", "true"),
+    )?;
+    azure_attributes(&compute)
+}
+
+fn azure_attributes(compute: &Value) -> Option<Attributes> {
+    let mut attributes = vec![
+        ("cloud.provider".to_string(), "azure".to_string()),
+        ("cloud.platform".to_string(), "azure_vm".to_string()),
+        (
+            "cloud.region".to_string(),
+            compute["location"].as_str()?.to_string(),
+        ),
+    ];
+    for (key, field) in [
+        ("cloud.availability_zone", "zone"),
+        ("cloud.account.id", "subscriptionId"),
+        ("host.id", "vmId"),
+        ("host.type", "vmSize"),
+    ] {
+        push(&mut attributes, key, compute[field].as_str());
+    }
+    Some(attributes)
+}
+
+fn push(attributes: &mut Attributes, key: &str, value: Option<&str>) {
+    if let Some(value) = value.filter(|v| !v.is_empty()) {
+        attributes.push((key.to_string(), value.to_string()));
+    }
+}
+
+fn describe(attributes: &Attributes) -> String {
+    attributes
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn instance_documents_become_resource_attributes() {
+        let value = |attributes: &[(String, String)], key: &str| {
+            attributes
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.clone())
+        };
+
+        let aws = aws_attributes(&json!({
+            "accountId": "123456789012",
+            "availabilityZone": "eu-west-1b",
+            "imageId": "ami-0abcdef",
+            "instanceId": "i-0123456789",
+            "instanceType": "m6i.large",
+            "region": "eu-west-1",
+        }))
+        .unwrap();
+        assert_eq!(value(&aws, "cloud.provider").unwrap(), "aws");
+        assert_eq!(value(&aws, "cloud.region").unwrap(), "eu-west-1");
+        assert_eq!(
+            value(&aws, "cloud.availability_zone").unwrap(),
+            "eu-west-1b"
+        );
+        assert_eq!(value(&aws, "host.id").unwrap(), "i-0123456789");
+
+        let gcp = gcp_attributes(
+            &json!({
+                "id": 4520031799277581759u64,
+                "machineType": "projects/123/machineTypes/e2-medium",
+                "zone": "projects/123/zones/us-central1-a",
+            }),
+            Some("acme-prod"),
+        )
+        .unwrap();
+        assert_eq!(value(&gcp, "cloud.region").unwrap(), "us-central1");
+        assert_eq!(
+            value(&gcp, "cloud.availability_zone").unwrap(),
+            "us-central1-a"
+        );
+        assert_eq!(value(&gcp, "cloud.account.id").unwrap(), "acme-prod");
+        assert_eq!(value(&gcp, "host.id").unwrap(), "4520031799277581759");
+        assert_eq!(value(&gcp, "host.type").unwrap(), "e2-medium");
+
+        let azure = azure_attributes(&json!({
+            "location": "westeurope",
+            "vmId": "02aab8a4-74ef-476e-8182-f6d2ba4166a6",
+            "vmSize": "Standard_D2s_v3",
+            "zone": "",
+        }))
+        .unwrap();
+        assert_eq!(value(&azure, "cloud.region").unwrap(), "westeurope");
+        assert_eq!(value(&azure, "cloud.availability_zone"), None);
+
+        // What another service answers on the same path is no instance document.
+        assert!(aws_attributes(&json!({"error": "not found"})).is_none());
+
+        let config: CloudMetadataConfig = toml::from_str("").unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.timeout_ms, 300);
+        let slow: CloudMetadataConfig = toml::from_str("timeout_ms = 30000").unwrap();
+        assert!(slow.validate().is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::input::{Input, InputContext, StopFlag};
+use crate::stats::Stage;
+use crate::trace_context::TraceContext;
+use crate::{enqueue, severity_from_name, timestamp_extractor, Attributes, Body, Record, Severity};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    #[default]
+    Always,
+    OnFailure,
+    Never,
+}
+
+/// A command whose stdout/stderr are shipped as log records.
+///
+/// With `interval_secs` set the command is run to completion on that schedule (health
+/// scripts); without it the command is kept running and restarted per `restart`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CommandInput {
+    pub name: String,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub interval_secs: Option<u64>,
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    pub restart_delay_secs: Option<u64>,
+    pub stderr_severity: Option<String>,
+    #[serde(skip)]
+    pub stop: StopFlag,
+}
+
+impl Input for CommandInput {
+    fn describe(&self) -> String {
+        format!("command:{}", self.name)
+    }
+
+    fn start(&mut self, ctx: &InputContext) -> anyhow::Result<()> {
+        let name = self.name.clone();
+        let stderr_severity = self
+            .stderr_severity
+            .as_deref()
+            .and_then(severity_from_name)
+            .unwrap_or(Severity::WARN);
+        let state = ctx.state.clone();
+        let stats = ctx.register(self);
+        let tx = ctx.queue.sender(&stats.name);
+        let sequence_attribute = ctx.config.sequence_attribute.clone();
+        let timestamps = timestamp_extractor(&ctx.config, &stats.name);
+        let otlp_path: Option<Arc<str>> = ctx
+            .config
+            .otlp_paths
+            .get(&stats.name)
+            .map(|p| p.as_str().into());
+        // The job the agent runs in, whose trace the command's output is part of.
+        let trace = TraceContext::from_env().map(Arc::new);
+
+        println!("Running command input {}: {}", name, self.command);
+        run_command_input(self.clone(), move |event| {
+            let started = Instant::now();
+            let mut attributes = Attributes::from(vec![("command.name".to_string(), name.clone())]);
+            let (line, default_severity) = match event {
+                CommandEvent::Line(stream, line) => {
+                    stats.record_line();
+                    println!("[{}:{}] {}", name, stream.as_str(), line);
+                    attributes.push("log.iostream", stream.as_str());
+                    let severity = match stream {
+                        Stream::Stdout => None,
+                        Stream::Stderr => Some(stderr_severity),
+                    };
+                    (line, severity)
+                }
+                CommandEvent::Exited(code) => {
+                    let status = code.map_or("signal".to_string(), |c| c.to_string());
+                    attributes.push("process.exit.code", status.clone());
+                    let severity = if code == Some(0) {
+                        Severity::INFO
+                    } else {
+                        Severity::ERROR
+                    };
+                    (
+                        format!("Command {} exited with status {}", name, status),
+                        Some(severity),
+                    )
+                }
+            };
+
+            if let Some(ref key) = sequence_attribute {
+                attributes.push(key.clone(), stats.next_sequence().to_string());
+            }
+            let timestamp = timestamps
+                .as_ref()
+                .and_then(|t| t.extract(&line, &attributes));
+            state.record_stage(Stage::Parse, 1, started.elapsed());
+            enqueue(
+                &tx,
+                &state,
+                Record {
+                    body: Body::Text(line),
+                    attributes,
+                    default_severity,
+                    severity: None,
+                    service_name: None,
+                    timestamp,
+                    observed: Utc::now(),
+                    otlp_path: otlp_path.clone(),
+                    trace: trace.clone(),
+                    resource: None,
+                    checkpoint: None,
+                },
+            );
+        });
+        Ok(())
+    }
+
+    /// Prevents further runs and restarts; a process that is running is left to exit.
+    fn stop(&mut self) {
+        self.stop.stop();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        }
+    }
+}
+
+pub enum CommandEvent {
+    Line(Stream, String),
+    /// The process terminated; `None` when it was killed by a signal.
+    Exited(Option<i32>),
+}
+
+pub fn run_command_input<F>(input: CommandInput, handler: F) -> thread::JoinHandle<()>
+where
+    F: Fn(CommandEvent) + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    thread::spawn(move || {
+        let restart_delay = Duration::from_secs(input.restart_delay_secs.unwrap_or(5));
+        while !input.stop.is_stopped() {
+            let success = match run_once(&input, &handler) {
+                Ok(code) => {
+                    handler(CommandEvent::Exited(code));
+                    code == Some(0)
+                }
+                Err(e) => {
+                    eprintln!("Failed to run command {}: {e}", input.name);
+                    false
+                }
+            };
+
+            if let Some(interval) = input.interval_secs {
+                thread::sleep(Duration::from_secs(interval));
+                continue;
+            }
+
+            match input.restart {
+                RestartPolicy::Never => break,
+                RestartPolicy::OnFailure if success => break,
+                _ => {
+                    println!(
+                        "Command {} exited, restarting in {}s",
+                        input.name,
+                        restart_delay.as_secs()
+                    );
+                    thread::sleep(restart_delay);
+                }
+            }
+        }
+    })
+}
+
+fn run_once<F>(input: &CommandInput, handler: &Arc<F>) -> std::io::Result<Option<i32>>
+where
+    F: Fn(CommandEvent) + Send + Sync + 'static,
+{
+    let mut child = Command::new(&input.command)
+        .args(&input.args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdout = child
+        .stdout
+        .take()
+        .map(|s| forward_lines(s, Stream::Stdout, handler.clone()));
+    let stderr = child
+        .stderr
+        .take()
+        .map(|s| forward_lines(s, Stream::Stderr, handler.clone()));
+
+    let status = child.wait()?;
+    for reader in [stdout, stderr].into_iter().flatten() {
+        reader.join().ok();
+    }
+    Ok(status.code())
+}
+
+fn forward_lines<R, F>(source: R, stream: Stream, handler: Arc<F>) -> thread::JoinHandle<()>
+where
+    R: Read + Send + 'static,
+    F: Fn(CommandEvent) + Send + Sync + 'static,
+{
+    thread::spawn(move || {
+        for line in BufReader::new(source).lines() {
+            match line {
+                Ok(line) if !line.trim().is_empty() => {
+                    handler(CommandEvent::Line(stream, line.trim_end().to_string()))
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Error reading command {}: {e}", stream.as_str());
+                    break;
+                }
+            }
+        }
+    })
+}
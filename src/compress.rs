@@ -0,0 +1,95 @@
+use flate2::write::GzEncoder;
+use std::io::Write;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Default `compression_level`, flate2's own.
+pub const DEFAULT_LEVEL: u32 = 6;
+
+/// Gzips `body` at `level`, 0 (stored) to 9 (smallest).
+pub fn gzip(body: &[u8], level: u32) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::new(level));
+    encoder.write_all(body)?;
+    encoder.finish()
+}
+
+type Job = (Vec<u8>, mpsc::Sender<std::io::Result<Vec<u8>>>);
+
+/// Threads of one exporter that gzip request bodies, so the next batch is compressed
+/// while the current one is sent. At most `workers` bodies are compressed at a time; the
+/// others wait their turn.
+pub struct Pool {
+    level: u32,
+    jobs: Mutex<mpsc::Sender<Job>>,
+}
+
+impl Pool {
+    pub fn new(name: &str, workers: usize, level: u32) -> Pool {
+        let (tx, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+        for i in 0..workers.max(1) {
+            let rx = rx.clone();
+            thread::Builder::new()
+                .name(format!("{}-gzip-{}", name, i))
+                .spawn(move || loop {
+                    // The pool is gone once its sender is dropped.
+                    let Ok((body, done)) = rx.lock().unwrap().recv() else {
+                        break;
+                    };
+                    done.send(gzip(&body, level)).ok();
+                })
+                .ok();
+        }
+        Pool {
+            level,
+            jobs: Mutex::new(tx),
+        }
+    }
+
+    /// Starts compressing `body` on the pool.
+    pub fn start(&self, body: Vec<u8>) -> Compressing {
+        let (done, rx) = mpsc::channel();
+        if let Err(mpsc::SendError((body, done))) = self.jobs.lock().unwrap().send((body, done)) {
+            // Without workers the body is compressed by whoever waits for it.
+            done.send(gzip(&body, self.level)).ok();
+        }
+        Compressing(rx)
+    }
+}
+
+/// A body being compressed on a `Pool`.
+pub struct Compressing(mpsc::Receiver<std::io::Result<Vec<u8>>>);
+
+impl Compressing {
+    pub fn wait(self) -> Result<Vec<u8>, String> {
+        match self.0.recv() {
+            Ok(result) => result.map_err(|e| format!("Failed to compress payload: {e}")),
+            Err(_) => Err("Failed to compress payload: its worker stopped".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn pooled_bodies_come_back_compressed_in_any_order() {
+        let pool = Pool::new("test", 2, 9);
+        let bodies: Vec<Vec<u8>> = (0..5)
+            .map(|i| format!("{{\"line\":{}}}", i).repeat(1000).into_bytes())
+            .collect();
+        let pending: Vec<Compressing> = bodies.iter().map(|b| pool.start(b.clone())).collect();
+        for (body, pending) in bodies.iter().zip(pending).rev() {
+            let compressed = pending.wait().unwrap();
+            assert!(compressed.len() < body.len() / 10);
+            let mut plain = Vec::new();
+            flate2::read::GzDecoder::new(&compressed[..])
+                .read_to_end(&mut plain)
+                .unwrap();
+            assert_eq!(&plain, body);
+        }
+    }
+}
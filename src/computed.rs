@@ -0,0 +1,544 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+use crate::fieldpath::FieldPath;
+use crate::processor::Processor;
+use crate::Record;
+
+/// `[computed_attributes]`: attributes set from an expression over the others, e.g.
+/// `is_5xx = "status >= 500"`. Each expression sees the attributes as the record had them
+/// before any was computed; one that comes out empty sets nothing.
+pub struct Computed {
+    attributes: Vec<(String, Expr)>,
+}
+
+impl Computed {
+    pub fn new(config: &BTreeMap<String, String>) -> Result<Computed> {
+        let attributes = config
+            .iter()
+            .map(|(name, text)| {
+                let expr = parse(text)
+                    .map_err(|e| anyhow::anyhow!("Invalid computed_attributes {}: {}", name, e))?;
+                Ok((name.clone(), expr))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Computed { attributes })
+    }
+}
+
+impl Processor for Computed {
+    fn process(&mut self, mut record: Record) -> Option<Record> {
+        let computed: Vec<(String, String)> = self
+            .attributes
+            .iter()
+            .filter_map(|(name, expr)| {
+                let value = expr.eval(&record.attributes).into_text()?;
+                Some((name.clone(), value))
+            })
+            .collect();
+        for (name, value) in computed {
+            match record.attributes.iter_mut().find(|(key, _)| *key == name) {
+                Some((_, existing)) => *existing = value,
+                None => record.attributes.push(name, value),
+            }
+        }
+        Some(record)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    /// A missing attribute, or what cannot be computed from the values at hand.
+    Null,
+    Bool(bool),
+    Number(f64),
+    Text(String),
+}
+
+impl Value {
+    fn number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Text(text) => text.trim().parse().ok(),
+            Value::Bool(_) | Value::Null => None,
+        }
+    }
+
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Bool(b) => *b,
+            Value::Null => false,
+            Value::Number(n) => *n != 0.0,
+            Value::Text(text) => !text.is_empty(),
+        }
+    }
+
+    fn into_text(self) -> Option<String> {
+        match self {
+            Value::Null => None,
+            Value::Bool(b) => Some(b.to_string()),
+            Value::Number(n) => Some(number_text(n)),
+            Value::Text(text) => Some(text),
+        }
+    }
+}
+
+/// 3 rather than 3.0.
+fn number_text(n: f64) -> String {
+    if n.fract() == 0.0 && n.abs() < 1e15 {
+        format!("{}", n as i64)
+    } else {
+        n.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Literal(Value),
+    Field(FieldPath),
+    List(Vec<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Function {
+    Bucket,
+    If,
+    Coalesce,
+    Lower,
+    Upper,
+    Len,
+    Contains,
+}
+
+impl Function {
+    /// The function of `name`, with the numbers of arguments it takes.
+    fn named(name: &str) -> Option<(Function, usize, usize)> {
+        Some(match name {
+            "bucket" => (Function::Bucket, 2, 2),
+            "if" => (Function::If, 3, 3),
+            "coalesce" => (Function::Coalesce, 1, usize::MAX),
+            "lower" => (Function::Lower, 1, 1),
+            "upper" => (Function::Upper, 1, 1),
+            "len" => (Function::Len, 1, 1),
+            "contains" => (Function::Contains, 2, 2),
+            _ => return None,
+        })
+    }
+}
+
+impl Expr {
+    fn eval(&self, attributes: &[(String, String)]) -> Value {
+        match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Field(path) => path
+                .lookup(attributes)
+                .map_or(Value::Null, |v| Value::Text(v.into_owned())),
+            // Only ever an argument of bucket, which reads it itself.
+            Expr::List(_) => Value::Null,
+            Expr::Not(expr) => Value::Bool(!expr.eval(attributes).truthy()),
+            Expr::Neg(expr) => expr
+                .eval(attributes)
+                .number()
+                .map_or(Value::Null, |n| Value::Number(-n)),
+            Expr::Binary(Op::Or, a, b) => {
+                Value::Bool(a.eval(attributes).truthy() || b.eval(attributes).truthy())
+            }
+            Expr::Binary(Op::And, a, b) => {
+                Value::Bool(a.eval(attributes).truthy() && b.eval(attributes).truthy())
+            }
+            Expr::Binary(op, a, b) => binary(*op, a.eval(attributes), b.eval(attributes)),
+            Expr::Call(function, args) => call(*function, args, attributes),
+        }
+    }
+}
+
+fn binary(op: Op, a: Value, b: Value) -> Value {
+    if a == Value::Null || b == Value::Null {
+        return match op {
+            Op::Eq => Value::Bool(a == b),
+            Op::Ne => Value::Bool(a != b),
+            _ => Value::Null,
+        };
+    }
+    // Numbers compare as numbers, even when an attribute holds them as text.
+    if let (Some(x), Some(y)) = (a.number(), b.number()) {
+        return match op {
+            Op::Eq => Value::Bool(x == y),
+            Op::Ne => Value::Bool(x != y),
+            Op::Lt => Value::Bool(x < y),
+            Op::Le => Value::Bool(x <= y),
+            Op::Gt => Value::Bool(x > y),
+            Op::Ge => Value::Bool(x >= y),
+            Op::Add => Value::Number(x + y),
+            Op::Sub => Value::Number(x - y),
+            Op::Mul => Value::Number(x * y),
+            Op::Div if y != 0.0 => Value::Number(x / y),
+            Op::Div | Op::And | Op::Or => Value::Null,
+        };
+    }
+    let (Some(x), Some(y)) = (a.into_text(), b.into_text()) else {
+        return Value::Null;
+    };
+    match op {
+        Op::Eq => Value::Bool(x == y),
+        Op::Ne => Value::Bool(x != y),
+        Op::Lt => Value::Bool(x < y),
+        Op::Le => Value::Bool(x <= y),
+        Op::Gt => Value::Bool(x > y),
+        Op::Ge => Value::Bool(x >= y),
+        Op::Add => Value::Text(x + &y),
+        _ => Value::Null,
+    }
+}
+
+fn call(function: Function, args: &[Expr], attributes: &[(String, String)]) -> Value {
+    let arg = |i: usize| args[i].eval(attributes);
+    match function {
+        // `bucket(ms, [100, 500])` is "<100", "100-500" or ">=500".
+        Function::Bucket => {
+            let (Some(value), Expr::List(bounds)) = (arg(0).number(), &args[1]) else {
+                return Value::Null;
+            };
+            let bounds: Vec<f64> = bounds
+                .iter()
+                .filter_map(|b| b.eval(attributes).number())
+                .collect();
+            let Some(i) = bounds.iter().position(|&bound| value < bound) else {
+                return bounds.last().map_or(Value::Null, |b| {
+                    Value::Text(format!(">={}", number_text(*b)))
+                });
+            };
+            Value::Text(match i {
+                0 => format!("<{}", number_text(bounds[0])),
+                i => format!("{}-{}", number_text(bounds[i - 1]), number_text(bounds[i])),
+            })
+        }
+        Function::If => match arg(0).truthy() {
+            true => arg(1),
+            false => arg(2),
+        },
+        Function::Coalesce => args
+            .iter()
+            .map(|a| a.eval(attributes))
+            .find(|v| *v != Value::Null)
+            .unwrap_or(Value::Null),
+        Function::Lower => arg(0)
+            .into_text()
+            .map_or(Value::Null, |t| Value::Text(t.to_lowercase())),
+        Function::Upper => arg(0)
+            .into_text()
+            .map_or(Value::Null, |t| Value::Text(t.to_uppercase())),
+        Function::Len => arg(0)
+            .into_text()
+            .map_or(Value::Null, |t| Value::Number(t.chars().count() as f64)),
+        Function::Contains => match (arg(0).into_text(), arg(1).into_text()) {
+            (Some(text), Some(part)) => Value::Bool(text.contains(&part)),
+            _ => Value::Null,
+        },
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Text(String),
+    Name(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: &[&str] = &[
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "+", "-", "*", "/", "(", ")", "[", "]", ",",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else if c == '"' || c == '\'' {
+            let end = rest[1..]
+                .find(c)
+                .ok_or_else(|| anyhow::anyhow!("a string is not closed"))?;
+            tokens.push(Token::Text(rest[1..end + 1].to_string()));
+            rest = &rest[end + 2..];
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            let number = rest[..end]
+                .parse()
+                .map_err(|_| anyhow::anyhow!("{} is not a number", &rest[..end]))?;
+            tokens.push(Token::Number(number));
+            rest = &rest[end..];
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || "_.[]".contains(c)))
+                .unwrap_or(rest.len());
+            // A [ right after a name is an index of the path, but not `[` of a list.
+            let name = rest[..end].trim_end_matches('[');
+            tokens.push(Token::Name(name.to_string()));
+            rest = &rest[name.len()..];
+        } else {
+            return Err(anyhow::anyhow!("unexpected {:?}", c));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Parses an expression: comparisons (`==`, `!=`, `<`, `<=`, `>`, `>=`), `&&`, `||`,
+/// `!`, arithmetic, strings, numbers, `true`, `false`, attribute paths and the functions
+/// of `Function::named`.
+fn parse(text: &str) -> Result<Expr> {
+    let tokens = tokenize(text)?;
+    let mut parser = Parser { tokens, at: 0 };
+    let expr = parser.or()?;
+    match parser.tokens.get(parser.at) {
+        None => Ok(expr),
+        Some(token) => Err(anyhow::anyhow!("unexpected {:?}", token)),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    at: usize,
+}
+
+impl Parser {
+    fn eat(&mut self, symbol: &str) -> bool {
+        let eaten = matches!(self.tokens.get(self.at), Some(Token::Symbol(s)) if *s == symbol);
+        if eaten {
+            self.at += 1;
+        }
+        eaten
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<()> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("expected {}", symbol))
+        }
+    }
+
+    /// One level of left-associative binary operators, over operands of `next`.
+    fn level(&mut self, ops: &[(&str, Op)], next: fn(&mut Parser) -> Result<Expr>) -> Result<Expr> {
+        let mut expr = next(self)?;
+        'operators: loop {
+            for (symbol, op) in ops {
+                if self.eat(symbol) {
+                    expr = Expr::Binary(*op, Box::new(expr), Box::new(next(self)?));
+                    continue 'operators;
+                }
+            }
+            return Ok(expr);
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        self.level(&[("||", Op::Or)], Parser::and)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        self.level(&[("&&", Op::And)], Parser::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        self.level(
+            &[
+                ("==", Op::Eq),
+                ("!=", Op::Ne),
+                ("<=", Op::Le),
+                (">=", Op::Ge),
+                ("<", Op::Lt),
+                (">", Op::Gt),
+            ],
+            Parser::sum,
+        )
+    }
+
+    fn sum(&mut self) -> Result<Expr> {
+        self.level(&[("+", Op::Add), ("-", Op::Sub)], Parser::product)
+    }
+
+    fn product(&mut self) -> Result<Expr> {
+        self.level(&[("*", Op::Mul), ("/", Op::Div)], Parser::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("-") {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let token = self
+            .tokens
+            .get(self.at)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("ends too early"))?;
+        self.at += 1;
+        match token {
+            Token::Number(n) => Ok(Expr::Literal(Value::Number(n))),
+            Token::Text(text) => Ok(Expr::Literal(Value::Text(text))),
+            Token::Symbol("(") => {
+                let expr = self.or()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Token::Symbol("[") => Ok(Expr::List(self.list("]")?)),
+            Token::Symbol(symbol) => Err(anyhow::anyhow!("unexpected {}", symbol)),
+            Token::Name(name) if name == "true" => Ok(Expr::Literal(Value::Bool(true))),
+            Token::Name(name) if name == "false" => Ok(Expr::Literal(Value::Bool(false))),
+            Token::Name(name) if self.eat("(") => {
+                let (function, min, max) = Function::named(&name)
+                    .ok_or_else(|| anyhow::anyhow!("unknown function {}", name))?;
+                let args = self.list(")")?;
+                if args.len() < min || args.len() > max {
+                    return Err(anyhow::anyhow!("{} takes {} arguments", name, min));
+                }
+                if function == Function::Bucket && !matches!(args[1], Expr::List(_)) {
+                    return Err(anyhow::anyhow!(
+                        "bucket takes a list of bounds, e.g. [100, 500]"
+                    ));
+                }
+                Ok(Expr::Call(function, args))
+            }
+            Token::Name(name) => Ok(Expr::Field(FieldPath::parse(&name)?)),
+        }
+    }
+
+    /// Comma-separated expressions up to `end`.
+    fn list(&mut self, end: &str) -> Result<Vec<Expr>> {
+        let mut items = Vec::new();
+        if self.eat(end) {
+            return Ok(items);
+        }
+        loop {
+            items.push(self.or()?);
+            if self.eat(end) {
+                return Ok(items);
+            }
+            self.expect(",")?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attributes_are_computed_from_expressions() {
+        let config = BTreeMap::from([
+            (
+                "latency_bucket".to_string(),
+                "bucket(duration_ms, [100, 500, 1000])".to_string(),
+            ),
+            ("is_5xx".to_string(), "http.status >= 500".to_string()),
+            (
+                "kind".to_string(),
+                "if(contains(lower(path), '/api/') && method != 'GET', 'write', 'read')"
+                    .to_string(),
+            ),
+            (
+                "total_ms".to_string(),
+                "coalesce(queue_ms, 0) + duration_ms * 2".to_string(),
+            ),
+        ]);
+        let mut computed = Computed::new(&config).unwrap();
+        let record = |attributes: &[(&str, &str)]| Record {
+            attributes: attributes
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            ..Record::new("request")
+        };
+        let mut attributes_of = |attributes: &[(&str, &str)]| {
+            let record = computed.process(record(attributes)).unwrap();
+            let get = |key: &str| {
+                record
+                    .attributes
+                    .iter()
+                    .find(|(k, _)| k == key)
+                    .map(|(_, v)| v.clone())
+            };
+            (
+                get("latency_bucket"),
+                get("is_5xx"),
+                get("kind"),
+                get("total_ms"),
+            )
+        };
+
+        assert_eq!(
+            attributes_of(&[
+                ("duration_ms", "742"),
+                ("http.status", "503"),
+                ("path", "/API/orders"),
+                ("method", "POST"),
+            ]),
+            (
+                Some("500-1000".into()),
+                Some("true".into()),
+                Some("write".into()),
+                Some("1484".into())
+            )
+        );
+        assert_eq!(
+            attributes_of(&[
+                ("duration_ms", "12.5"),
+                ("queue_ms", "3"),
+                ("http.status", "200")
+            ]),
+            (
+                Some("<100".into()),
+                Some("false".into()),
+                Some("read".into()),
+                Some("28".into())
+            )
+        );
+        // Nothing is set from missing attributes but what does not need them.
+        assert_eq!(
+            attributes_of(&[("duration_ms", "slow")]),
+            (None, None, Some("read".into()), None)
+        );
+
+        for invalid in [
+            "status >=",
+            "nope(1)",
+            "bucket(ms, 100)",
+            "len(a, b)",
+            "'open",
+        ] {
+            let config = BTreeMap::from([("x".to_string(), invalid.to_string())]);
+            assert!(Computed::new(&config).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,1412 @@
+use anyhow::{Context, Result};
+#[cfg(feature = "wizard")]
+use dialoguer::Input;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::failure::Failure;
+use crate::parser::severity_from_name;
+use crate::unit::ServiceUnit;
+use crate::{
+    adaptive, admin, audit, backfill, catchup, cloud, command, computed, console, control, daemon, exception,
+    exporter, failure, faults, fieldpath, filter, handshake, header, hostmetrics, http_status, incident,
+    input, kubernetes, lifecycle, listener, local_archive, log_format, metadata, multiline,
+    quarantine, queue, redact, replay, resolve_host_name, resync, rewrite, rollup, rules,
+    sampling, schedule, severity, shutdown, snapshot, spool, statedir, statsd, stitch, telemetry, template,
+    throttle, timestamp, tls, volume,
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Config {
+    #[serde(default)]
+    pub(crate) log_files: Vec<String>,
+    /// Tailed files with their own service name, resource attributes and parsing
+    /// options; folded into `log_files` and the per-input sections when loaded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) sources: Vec<input::Source>,
+    /// May hold `{name}` variables, such as `https://ingest.{region}.signoz.cloud`,
+    /// filled in from `endpoint_vars`, the environment or the cloud metadata service.
+    pub(crate) endpoint: String,
+    /// Values of the variables in `endpoint`, `[[exporters]]` endpoints and
+    /// `metrics_endpoint`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) endpoint_vars: BTreeMap<String, String>,
+    pub(crate) rate_limit: Option<u32>,
+    /// Share of `rate_limit` that lines already in a file when tailing starts, and
+    /// backfills, are read at, so live lines keep most of it while a backlog is caught up.
+    pub(crate) replay_rate_fraction: Option<f64>,
+    /// Pace of stdin, backfills and `ship` by the event times of their records:
+    /// "realtime" or a multiplier such as "10x".
+    pub(crate) replay_speed: Option<String>,
+    pub(crate) service_name: Option<String>,
+    pub(crate) host_name: Option<String>,
+    /// Set to false to never query the system host name (e.g. scratch containers).
+    pub(crate) detect_host_name: Option<bool>,
+    /// Host name reported when none is configured and detection fails or is disabled.
+    pub(crate) host_name_fallback: Option<String>,
+    /// Add the `cloud.*` and `host.*` attributes the instance's metadata service gives.
+    pub(crate) cloud_metadata: Option<cloud::CloudMetadataConfig>,
+    /// Resource attributes of every record, such as `deployment.environment`, over the
+    /// agent's own; `OTEL_RESOURCE_ATTRIBUTES` adds to them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) resource_attributes: BTreeMap<String, String>,
+    /// `User=` of the unit written by `--install-service`; the current user by default.
+    pub(crate) service_user: Option<String>,
+    /// Ordering, delay and restarts of the unit written by `install-service`.
+    #[serde(default, skip_serializing_if = "ServiceUnit::is_default")]
+    pub(crate) service_unit: ServiceUnit,
+    /// Which lines read are printed to the standard output, and how.
+    #[serde(default, skip_serializing_if = "console::ConsoleConfig::is_default")]
+    pub(crate) console: console::ConsoleConfig,
+    pub(crate) admin_socket: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) commands: Vec<command::CommandInput>,
+    pub(crate) pid_file: Option<String>,
+    /// Directory for the agent's own files (the lock against a second agent, offsets);
+    /// `statedir::default_dir()` by default.
+    pub(crate) state_dir: Option<String>,
+    /// Bucket the state directory is fetched from before a `--oneshot` run and stored in
+    /// after it, for hosts that do not outlive one.
+    pub(crate) state_s3: Option<exporter::S3Config>,
+    pub(crate) agent_log_file: Option<String>,
+    /// Print one of every this many records delivered (1 by default); 0 prints none.
+    pub(crate) success_log_every: Option<u64>,
+    /// Refuse to start unless everything the agent writes is inside `state_dir`, for
+    /// containers with a read-only root filesystem and one writable state volume.
+    #[serde(default)]
+    pub(crate) read_only_root: bool,
+    /// Drop root after startup: the tailed files are read by a small process that stays
+    /// root, everything else runs as this user.
+    pub(crate) privsep_user: Option<String>,
+    /// Drop root for good once the inputs have opened their files and bound their ports.
+    pub(crate) run_as_user: Option<String>,
+    /// The group to run as with `run_as_user`, instead of its primary group.
+    pub(crate) run_as_group: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) path_attributes: Vec<PathAttributes>,
+    /// Records whose event time is older than this (e.g. "24h") are dropped.
+    pub(crate) max_record_age: Option<String>,
+    /// Scope attribute carrying an ID for each export request, which the agent logs with
+    /// the request's outcome, to find a batch in the collector's logs (e.g.
+    /// "agent.batch.id").
+    pub(crate) batch_id_attribute: Option<String>,
+    /// Attribute carrying a per-input sequence number, for stable ordering of records
+    /// that share a timestamp (e.g. "log.record.sequence").
+    pub(crate) sequence_attribute: Option<String>,
+    /// Attribute carrying a hash of the host, inode and offset of a tailed line, the same
+    /// in every shipper reading it, for deduplicating records downstream (e.g.
+    /// "log.origin.id").
+    pub(crate) origin_id_attribute: Option<String>,
+    /// Files left to another shipper, by path or pattern, e.g. during a migration.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) other_shipper_files: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) snapshots: Vec<snapshot::SnapshotInput>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) backfills: Vec<backfill::BackfillInput>,
+    /// Inputs of any registered `type`, in addition to the dedicated sections above.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) inputs: Vec<input::InputSpec>,
+    #[serde(default, skip_serializing_if = "AttributeHints::is_empty")]
+    pub(crate) attribute_hints: AttributeHints,
+    /// Attribute values longer than this many bytes are cut and end in `TRUNCATION_MARKER`.
+    pub(crate) max_attribute_value_length: Option<usize>,
+    /// Attributes beyond this count are dropped and reported in `droppedAttributesCount`.
+    pub(crate) max_attributes_per_record: Option<usize>,
+    /// Name the steps that altered a record, such as redaction, in its `log.mutations`
+    /// attribute.
+    #[serde(default)]
+    pub(crate) mark_mutations: bool,
+    /// Files whose lines are shipped byte-for-byte as base64 `bytesValue` bodies rather
+    /// than as lossily decoded UTF-8 text.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) bytes_body_files: Vec<String>,
+    /// Inputs whose records are shipped as their metadata only, without their lines,
+    /// keyed by input name like `timestamps`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) metadata_only: BTreeMap<String, metadata::MetadataOnly>,
+    /// Event time extraction per input name (as shown by `top`, e.g. "/var/log/app.log" or
+    /// "command:disk-health"); the "*" entry applies to inputs without their own.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) timestamps: BTreeMap<String, timestamp::TimestampConfig>,
+    /// Event time corrections per input name, looked up like `timestamps`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) clock_skew: BTreeMap<String, timestamp::ClockSkewConfig>,
+    /// Flags records of every input whose event time is far from the time read.
+    pub(crate) timestamp_check: Option<timestamp::TimestampCheckConfig>,
+    /// Lines split by a syslog daemon put back together per input name, looked up like
+    /// `timestamps`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) stitch: BTreeMap<String, stitch::StitchConfig>,
+    /// Lines of files appended to by several processes at once split back into records
+    /// per input name, looked up like `timestamps`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) resync: BTreeMap<String, resync::ResyncConfig>,
+    /// Header lines and byte order marks skipped at the start of each tailed file per
+    /// input name, looked up like `timestamps`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) file_headers: BTreeMap<String, header::HeaderConfig>,
+    /// How the sockets of network inputs are watched and bound again, keyed by input name
+    /// or pattern, such as "syslog:*".
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) listener_health: BTreeMap<String, listener::ListenerHealth>,
+    /// When an input whose lines fail to parse is quarantined, keyed by input name or
+    /// pattern like `log_formats`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) parse_quarantine: BTreeMap<String, quarantine::QuarantineConfig>,
+    /// Files read from their start on a first run take turns, newest first by default.
+    pub(crate) catch_up: Option<catchup::CatchUpConfig>,
+    /// Container log files enriched with their pod's metadata per input name, looked up
+    /// like `timestamps`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) kubernetes: BTreeMap<String, kubernetes::KubernetesConfig>,
+    /// Lines joined into multi-line records per input name, looked up like `timestamps`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) multiline: BTreeMap<String, multiline::MultilineConfig>,
+    /// How lines are read per input name (keyed like `timestamps`): "plain", "json" or a
+    /// `{ layout = "..." }` pattern.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) log_formats: BTreeMap<String, log_format::LogFormat>,
+    /// Where tailed files never read before start per input name, looked up like
+    /// `timestamps`; at their end by default.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) start_positions: BTreeMap<String, input::StartPosition>,
+    /// Records shipped or dropped by regex per input name, looked up like `timestamps`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) filters: BTreeMap<String, filter::FilterConfig>,
+    pub(crate) sampling: Option<sampling::SamplingConfig>,
+    /// The least severity shipped by time of day, per input name, looked up like
+    /// `timestamps`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) severity_schedules: BTreeMap<String, schedule::SeveritySchedule>,
+    /// Patterns whose lines pass at most so many per interval, the rest counted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) throttle_rules: Vec<throttle::ThrottleRule>,
+    /// Emails, card numbers, tokens and own patterns masked before records are shipped.
+    pub(crate) redaction: Option<redact::RedactionConfig>,
+    /// Size and time bounds for user-supplied regexes (path attributes, keep patterns).
+    #[serde(default, skip_serializing_if = "rules::RegexLimits::is_default")]
+    pub(crate) regex_limits: rules::RegexLimits,
+    /// Log files tailed through the mmap reader instead of buffered reads.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) mmap_files: Vec<String>,
+    /// Read-ahead buffer of the file readers, which is also the most a batch of lines
+    /// handed on at once adds up to; `DEFAULT_READ_BUFFER_BYTES` by default.
+    pub(crate) read_buffer_bytes: Option<usize>,
+    /// Tailed files held open at once; past it, the files written to least recently are
+    /// closed until they grow.
+    pub(crate) max_open_files: Option<usize>,
+    /// Seconds a tailed file may go without growing, per input name or pattern like
+    /// `timestamps`, before it is closed and its thread ended until it grows.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) idle_close_secs: BTreeMap<String, u64>,
+    /// What tailed files rewritten in place (truncated and written again) are read as:
+    /// "reread", "diff" (only lines past the unchanged prefix) or "skip".
+    #[serde(default)]
+    pub(crate) rewrite_policy: rewrite::RewritePolicy,
+    /// Seconds a deleted or replaced log file is still read through its open handle
+    /// before it is released; `tombstone::DEFAULT_GRACE_SECS` by default.
+    pub(crate) deleted_file_grace_secs: Option<u64>,
+    /// Seconds between rescans of the glob patterns in `log_files` for new files;
+    /// `input::DEFAULT_RESCAN_SECS` by default.
+    pub(crate) glob_rescan_secs: Option<u64>,
+    /// Seconds between saves of the tailed files' read positions to `state_dir`;
+    /// `checkpoint::DEFAULT_INTERVAL_SECS` by default.
+    pub(crate) checkpoint_interval_secs: Option<u64>,
+    /// Seconds a shutdown waits for queued records and partial batches to be delivered;
+    /// `SHUTDOWN_DRAIN_TIMEOUT` by default.
+    pub(crate) shutdown_timeout_secs: Option<u64>,
+    /// Seconds each shutdown step waits at most, by step name (see `shutdown::STEPS`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) shutdown_step_timeouts: BTreeMap<String, u64>,
+    /// Set to false to switch to the new file at a rotated path as soon as the rotation
+    /// is noticed, instead of finishing the rotated file first (true by default).
+    pub(crate) finish_rotated_files: Option<bool>,
+    /// Attributes set from an expression over the others, by name, e.g. `is_5xx =
+    /// "http.status >= 500"`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) computed_attributes: BTreeMap<String, String>,
+    /// ANSI escape sequences and control characters stripped or made visible.
+    pub(crate) control_characters: Option<control::ControlCharsConfig>,
+    /// `exception.*` attributes from the stack traces in records.
+    pub(crate) exceptions: Option<exception::ExceptionsConfig>,
+    /// Severity of records by ranges of a numeric attribute, applied in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) severity_maps: Vec<severity::SeverityMap>,
+    /// Level words detected in lines besides the built-in English ones, e.g. `FEHLER =
+    /// "ERROR"`; matched case-insensitively as whole words.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) severity_keywords: BTreeMap<String, String>,
+    /// Where the level of a line is and what it means, per input name, looked up like
+    /// `timestamps`; the first rule that finds a level wins over level word detection.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) severity_rules: BTreeMap<String, Vec<severity::SeverityRule>>,
+    /// Most records per OTLP request; `exporter::DEFAULT_BATCH_SIZE` by default.
+    pub(crate) batch_size: Option<usize>,
+    /// Longest a record waits for its batch to fill up before the batch is sent;
+    /// `exporter::DEFAULT_FLUSH_INTERVAL_MS` by default.
+    pub(crate) flush_interval_ms: Option<u64>,
+    /// Requests each exporter has in flight at most, 1 by default. Several let batches
+    /// go out while earlier ones wait on a slow collector, at the cost of their order;
+    /// an exporter with a disk buffer sends one at a time.
+    pub(crate) max_in_flight_requests: Option<usize>,
+    /// URL path appended to endpoints given without one (such as "host:4318").
+    pub(crate) otlp_path: Option<String>,
+    /// Whether `otlp_path` is appended to endpoints without a path ("auto", the default)
+    /// or they are used as given ("as_is"), for a collector taking logs at its root.
+    #[serde(default)]
+    pub(crate) endpoint_path: EndpointPath,
+    /// Per input name, its share of the dispatcher when inputs compete (default 1): a
+    /// weight of 3 hands on three times the records per turn.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) queue_weights: BTreeMap<String, u32>,
+    /// Most records queued between the inputs and the dispatcher, and again for each
+    /// exporter (default 100000).
+    pub(crate) queue_capacity: Option<usize>,
+    /// What happens to records queued while `queue_capacity` are.
+    #[serde(default)]
+    pub(crate) queue_overflow: queue::Overflow,
+    /// Per input name, a URL path replacing that of the endpoints for the input's records
+    /// (e.g. a gateway exposing "/otlp/v1/logs").
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) otlp_paths: BTreeMap<String, String>,
+    #[serde(default)]
+    pub(crate) compression: exporter::Compression,
+    /// Gzip level of OTLP/HTTP requests, 0 to 9; `compress::DEFAULT_LEVEL` by default.
+    pub(crate) compression_level: Option<u32>,
+    /// Threads per exporter compressing OTLP/HTTP requests, so batches are compressed
+    /// while earlier ones are sent; unset compresses each on the thread sending it.
+    pub(crate) compression_workers: Option<usize>,
+    #[serde(default)]
+    pub(crate) encoding: exporter::Encoding,
+    /// Transport to `endpoint`; by default gRPC on port 4317 and HTTP otherwise.
+    pub(crate) protocol: Option<exporter::Protocol>,
+    /// Credentials for `endpoint` and `metrics_endpoint`; `[[exporters]]` take their own.
+    pub(crate) auth: Option<exporter::Auth>,
+    /// SigNoz Cloud ingestion key, sent as `signoz-ingestion-key` like `auth`.
+    pub(crate) ingestion_key: Option<exporter::HeaderSource>,
+    /// More headers for the requests `auth` applies to.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) headers: BTreeMap<String, exporter::HeaderSource>,
+    /// Check every encoded OTLP payload against the spec before sending it and report
+    /// violations; set by the `--self-check` debug flag.
+    #[serde(skip)]
+    pub(crate) self_check: bool,
+    /// Set by `--oneshot`: files are read from their start when never read before, and
+    /// the agent exits once all of them were read to their end.
+    #[serde(skip)]
+    pub(crate) oneshot: bool,
+    /// User-Agent of all outgoing requests; `exporter::default_user_agent()` by default.
+    pub(crate) user_agent: Option<String>,
+    /// Further destinations receiving a copy of every record, or of their environments',
+    /// each with its own queue and retries.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) exporters: Vec<exporter::ExporterConfig>,
+    #[serde(default)]
+    pub(crate) delivery_policy: exporter::DeliveryPolicy,
+    /// Environments whose records `endpoint` takes; every record when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) environments: Vec<String>,
+    /// Attribute naming a record's environment; `routes::DEFAULT_ATTRIBUTE` by default.
+    pub(crate) environment_attribute: Option<String>,
+    /// Keep requests that failed every retry on disk and send them once the endpoint is
+    /// back, instead of discarding them.
+    pub(crate) disk_buffer: Option<spool::DiskBufferConfig>,
+    /// Delays, failures and 429s injected into the exporters' requests, for outage drills.
+    pub(crate) fault_injection: Option<faults::FaultInjection>,
+    /// Slow an exporter down while its collector answers 429, and speed it up again.
+    pub(crate) adaptive_rate: Option<adaptive::AdaptiveRateConfig>,
+    /// Also write every record to hourly files on the host.
+    pub(crate) local_archive: Option<local_archive::ArchiveConfig>,
+    /// Warn once an exporter's TLS certificate expires within this many days
+    /// (`cert::DEFAULT_WARNING_DAYS` by default); 0 turns the check off.
+    pub(crate) cert_warning_days: Option<u32>,
+    /// Also write every record directly into SigNoz's ClickHouse tables.
+    pub(crate) clickhouse: Option<exporter::ClickhouseConfig>,
+    /// Also write every record to Parquet files, locally or on S3.
+    pub(crate) parquet: Option<exporter::ParquetConfig>,
+    /// OTLP/HTTP metrics URL; defaults to `endpoint` with `/v1/logs` replaced by `/v1/metrics`.
+    pub(crate) metrics_endpoint: Option<String>,
+    pub(crate) statsd: Option<statsd::StatsdConfig>,
+    /// Records of agent starts, stops, upgrades and config reloads, and of host name and
+    /// address changes.
+    pub(crate) lifecycle_events: Option<lifecycle::LifecycleConfig>,
+    pub(crate) self_telemetry: Option<telemetry::SelfTelemetryConfig>,
+    /// Warnings about inputs whose lines per minute suddenly jump or drop, per input name
+    /// (looked up like `timestamps`, `"*"` for every input).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) volume_alerts: BTreeMap<String, volume::VolumeAlertConfig>,
+    /// A record per input and interval counting its records by severity, before sampling.
+    pub(crate) severity_rollup: Option<rollup::RollupConfig>,
+    /// Patterns whose lines are saved with their context to the state directory.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) incidents: Vec<incident::IncidentConfig>,
+    /// `/healthz` and Prometheus `/metrics` over HTTP.
+    pub(crate) http_status: Option<http_status::HttpStatusConfig>,
+    /// CPU, memory, filesystem and network metrics of the host, sent to `metrics_endpoint`.
+    pub(crate) metrics: Option<hostmetrics::HostMetricsConfig>,
+    /// Startup checks that the exporters point at a collector taking OTLP logs.
+    pub(crate) handshake: Option<handshake::HandshakeConfig>,
+    /// CA bundle, client certificate and verification of HTTPS and gRPC connections.
+    pub(crate) tls: Option<tls::TlsConfig>,
+    /// Periodic checks that SigNoz holds the records the agent delivered.
+    pub(crate) audit: Option<audit::AuditConfig>,
+    /// Shipped as the `agent.instance.id` resource attribute, set at startup with `audit`.
+    #[serde(skip)]
+    pub(crate) instance_id: Option<String>,
+}
+
+/// Controls where attributes land in the OTLP record so SigNoz indexes them as intended.
+/// Each names an attribute key, or an element of an attribute holding a JSON array by a
+/// path such as `spans[0].trace_id`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AttributeHints {
+    /// Attribute keys emitted as resource attributes instead of record attributes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resource: Vec<String>,
+    /// Attribute whose value (32 hex chars) becomes the record's top-level traceId.
+    pub trace_id: Option<String>,
+    /// Attribute whose value (16 hex chars) becomes the record's top-level spanId.
+    pub span_id: Option<String>,
+    /// Attribute additionally emitted as the SigNoz-recognized `component` attribute.
+    pub component: Option<String>,
+}
+
+impl AttributeHints {
+    pub fn is_empty(&self) -> bool {
+        self.resource.is_empty()
+            && self.trace_id.is_none()
+            && self.span_id.is_none()
+            && self.component.is_none()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let hints = self.resource.iter().chain(&self.trace_id);
+        for hint in hints.chain(&self.span_id).chain(&self.component) {
+            fieldpath::FieldPath::parse(hint)
+                .with_context(|| format!("attribute_hints entry {} is invalid", hint))?;
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// `state_dir`, or the default for the current user.
+    pub(crate) fn state_directory(&self) -> String {
+        self.state_dir.clone().unwrap_or_else(statedir::default_dir)
+    }
+
+    pub(crate) fn shutdown_timeout(&self) -> Duration {
+        self.shutdown_timeout_secs
+            .map_or(SHUTDOWN_DRAIN_TIMEOUT, Duration::from_secs)
+    }
+
+    /// Directory of the disk buffers, if they are on.
+    pub(crate) fn disk_buffer_dir(&self) -> Option<String> {
+        let buffer = self.disk_buffer.as_ref()?;
+        Some(
+            buffer
+                .dir
+                .clone()
+                .unwrap_or_else(|| statedir::file_in(&self.state_directory(), "buffer")),
+        )
+    }
+
+    /// Credentials of `endpoint` and `metrics_endpoint`.
+    pub(crate) fn credentials(&self) -> exporter::Credentials {
+        exporter::Credentials {
+            auth: self.auth.clone(),
+            ingestion_key: self.ingestion_key.clone(),
+            headers: self.headers.clone(),
+        }
+    }
+
+    pub(crate) fn socket_path(&self) -> String {
+        self.admin_socket
+            .clone()
+            .unwrap_or_else(|| statedir::file_in(&self.state_directory(), admin::SOCKET_FILE))
+    }
+
+    pub(crate) fn pid_file_path(&self) -> String {
+        self.pid_file
+            .clone()
+            .unwrap_or_else(|| statedir::file_in(&self.state_directory(), daemon::PID_FILE))
+    }
+
+    pub(crate) fn agent_log_path(&self) -> String {
+        self.agent_log_file
+            .clone()
+            .unwrap_or_else(|| statedir::file_in(&self.state_directory(), daemon::LOG_FILE))
+    }
+}
+
+/// Reads the contents of a `config.toml`, e.g. for `Agent::from_config`. The config is
+/// validated when the agent starts.
+impl std::str::FromStr for Config {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Config> {
+        Ok(toml::from_str(text)?)
+    }
+}
+
+/// What is done to an endpoint URL without a path.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EndpointPath {
+    /// `otlp_path` is appended.
+    #[default]
+    Auto,
+    /// Used as given.
+    AsIs,
+}
+
+/// Derives record attributes from the named groups of a regex matched against the file path.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PathAttributes {
+    pub pattern: String,
+    /// Named group whose value also becomes the record's `service.name`.
+    pub service_name_group: Option<String>,
+}
+
+pub fn load_or_create_config<P: AsRef<Path>>(config_path: P) -> Result<Config> {
+    if config_path.as_ref().exists() {
+        let contents = fs::read_to_string(&config_path)?;
+        let config: Config = toml::from_str(&contents)?;
+        Ok(config)
+    } else {
+        create_config(config_path.as_ref())
+    }
+}
+
+/// Asks for the essentials on the terminal and writes them to `config_path`.
+#[cfg(feature = "wizard")]
+pub fn create_config(config_path: &Path) -> Result<Config> {
+    println!(
+        "No config found at {}. Let's create one.",
+        config_path.display()
+    );
+    let log_files = Input::<String>::new()
+        .with_prompt("Enter comma-separated log file paths")
+        .interact_text()?
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    let endpoint = Input::<String>::new()
+        .with_prompt("Enter SigNoz OTLP HTTP endpoint")
+        .default("http://localhost:4318/v1/logs".into())
+        .interact_text()?;
+
+    let rate_limit = Input::<u32>::new()
+        .with_prompt("Enter rate limit (logs per second, 0 for unlimited)")
+        .default(100)
+        .interact_text()?;
+
+    let rate_limit = if rate_limit > 0 {
+        Some(rate_limit)
+    } else {
+        None
+    };
+
+    let service_name = Input::<String>::new()
+        .with_prompt("Enter service name (for SigNoz)")
+        .default("rust-signoz-agent".into())
+        .interact_text()?;
+
+    let host_name = Input::<String>::new()
+        .with_prompt("Enter host name (leave blank to auto-detect)")
+        .default("".into())
+        .interact_text()?;
+
+    let service_name = if service_name.trim().is_empty() {
+        None
+    } else {
+        Some(service_name)
+    };
+    let host_name = if host_name.trim().is_empty() {
+        None
+    } else {
+        Some(host_name)
+    };
+
+    let config = Config {
+        log_files,
+        sources: Vec::new(),
+        endpoint,
+        endpoint_vars: BTreeMap::new(),
+        rate_limit,
+        replay_rate_fraction: None,
+        replay_speed: None,
+        service_name,
+        host_name,
+        detect_host_name: None,
+        host_name_fallback: None,
+        cloud_metadata: None,
+        resource_attributes: BTreeMap::new(),
+        service_user: None,
+        service_unit: ServiceUnit::default(),
+        console: console::ConsoleConfig::default(),
+        admin_socket: None,
+        commands: Vec::new(),
+        pid_file: None,
+        state_dir: None,
+        state_s3: None,
+        agent_log_file: None,
+        success_log_every: None,
+        read_only_root: false,
+        privsep_user: None,
+        run_as_user: None,
+        run_as_group: None,
+        path_attributes: Vec::new(),
+        max_record_age: None,
+        sequence_attribute: None,
+        origin_id_attribute: None,
+        other_shipper_files: Vec::new(),
+        batch_id_attribute: None,
+        snapshots: Vec::new(),
+        backfills: Vec::new(),
+        inputs: Vec::new(),
+        attribute_hints: AttributeHints::default(),
+        max_attribute_value_length: None,
+        max_attributes_per_record: None,
+        mark_mutations: false,
+        bytes_body_files: Vec::new(),
+        metadata_only: BTreeMap::new(),
+        timestamps: BTreeMap::new(),
+        clock_skew: BTreeMap::new(),
+        timestamp_check: None,
+        stitch: BTreeMap::new(),
+        resync: BTreeMap::new(),
+        file_headers: BTreeMap::new(),
+        listener_health: BTreeMap::new(),
+        parse_quarantine: BTreeMap::new(),
+        catch_up: None,
+        kubernetes: BTreeMap::new(),
+        multiline: BTreeMap::new(),
+        log_formats: BTreeMap::new(),
+        start_positions: BTreeMap::new(),
+        filters: BTreeMap::new(),
+        sampling: None,
+        severity_schedules: BTreeMap::new(),
+        throttle_rules: Vec::new(),
+        redaction: None,
+        regex_limits: rules::RegexLimits::default(),
+        mmap_files: Vec::new(),
+        read_buffer_bytes: None,
+        max_open_files: None,
+        idle_close_secs: BTreeMap::new(),
+        rewrite_policy: rewrite::RewritePolicy::default(),
+        deleted_file_grace_secs: None,
+        checkpoint_interval_secs: None,
+        shutdown_timeout_secs: None,
+        shutdown_step_timeouts: BTreeMap::new(),
+        glob_rescan_secs: None,
+        finish_rotated_files: None,
+        computed_attributes: BTreeMap::new(),
+        control_characters: None,
+        exceptions: None,
+        severity_maps: Vec::new(),
+        severity_keywords: BTreeMap::new(),
+        severity_rules: BTreeMap::new(),
+        batch_size: None,
+        flush_interval_ms: None,
+        max_in_flight_requests: None,
+        otlp_path: None,
+        endpoint_path: EndpointPath::default(),
+        queue_weights: BTreeMap::new(),
+        queue_capacity: None,
+        queue_overflow: queue::Overflow::default(),
+        otlp_paths: BTreeMap::new(),
+        compression: exporter::Compression::default(),
+        compression_level: None,
+        compression_workers: None,
+        encoding: exporter::Encoding::default(),
+        protocol: None,
+        auth: None,
+        ingestion_key: None,
+        headers: BTreeMap::new(),
+        self_check: false,
+        oneshot: false,
+        user_agent: None,
+        exporters: Vec::new(),
+        delivery_policy: exporter::DeliveryPolicy::default(),
+        environments: Vec::new(),
+        environment_attribute: None,
+        disk_buffer: None,
+        fault_injection: None,
+        adaptive_rate: None,
+        local_archive: None,
+        cert_warning_days: None,
+        clickhouse: None,
+        parquet: None,
+        metrics_endpoint: None,
+        statsd: None,
+        lifecycle_events: None,
+        self_telemetry: None,
+        volume_alerts: BTreeMap::new(),
+        severity_rollup: None,
+        incidents: Vec::new(),
+        http_status: None,
+        metrics: None,
+        handshake: None,
+        tls: None,
+        audit: None,
+        instance_id: None,
+    };
+
+    let toml_str = toml::to_string_pretty(&config)?;
+    if let Some(dir) = config_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(config_path, toml_str)?;
+    println!("Saved config to {:?}", config_path);
+    Ok(config)
+}
+
+#[cfg(not(feature = "wizard"))]
+pub fn create_config(config_path: &Path) -> Result<Config> {
+    Err(anyhow::anyhow!(
+        "No config found at {}; this build has no setup wizard, so write one first",
+        config_path.display()
+    ))
+}
+
+/// A config as read from the file, completed (endpoints, host name) and validated; the
+/// one path both startup and reloads take.
+pub fn prepare_config(mut config: Config) -> Result<Config> {
+    resolve_endpoints(&mut config).map_err(|e| failure::classify(e, Failure::ConfigInvalid))?;
+    resolve_sources(&mut config).map_err(|e| failure::classify(e, Failure::ConfigInvalid))?;
+    config.host_name = Some(resolve_host_name(&config));
+    validate_config(&config).map_err(|e| failure::classify(e, Failure::ConfigInvalid))?;
+    Ok(config)
+}
+
+/// Adds the `[[sources]]` entries to `log_files`, `mmap_files` and the per-input sections
+/// they stand for. A section with its own entry for a source's path is a conflict.
+pub fn resolve_sources(config: &mut Config) -> Result<()> {
+    fn insert<T>(
+        (key, section): (&str, &str),
+        map: &mut BTreeMap<String, T>,
+        path: &str,
+        value: Option<T>,
+    ) -> Result<()> {
+        let Some(value) = value else {
+            return Ok(());
+        };
+        if map.contains_key(path) {
+            return Err(anyhow::anyhow!(
+                "Source {} sets {}, which [{}] also sets for it",
+                path,
+                key,
+                section
+            ));
+        }
+        map.insert(path.to_string(), value);
+        Ok(())
+    }
+
+    for source in config.sources.clone() {
+        let path = source.path.as_str();
+        if !config.log_files.iter().any(|f| f == path) {
+            config.log_files.push(source.path.clone());
+        }
+        if source.mmap && !config.mmap_files.iter().any(|f| f == path) {
+            config.mmap_files.push(source.path.clone());
+        }
+        insert(
+            ("log_format", "log_formats"),
+            &mut config.log_formats,
+            path,
+            source.log_format,
+        )?;
+        insert(
+            ("multiline", "multiline"),
+            &mut config.multiline,
+            path,
+            source.multiline,
+        )?;
+        insert(
+            ("timestamp", "timestamps"),
+            &mut config.timestamps,
+            path,
+            source.timestamp,
+        )?;
+        insert(
+            ("start_position", "start_positions"),
+            &mut config.start_positions,
+            path,
+            source.start_position,
+        )?;
+        insert(
+            ("severity_rules", "severity_rules"),
+            &mut config.severity_rules,
+            path,
+            source.severity_rules,
+        )?;
+    }
+    Ok(())
+}
+
+pub fn validate_config(config: &Config) -> Result<()> {
+    for log_file in &config.log_files {
+        if input::is_pattern(log_file) {
+            // Files matching it may only appear later.
+            glob::Pattern::new(log_file)
+                .with_context(|| format!("Invalid log_files pattern {}", log_file))?;
+            continue;
+        }
+        let path = Path::new(log_file);
+        if !path.exists() {
+            return Err(anyhow::anyhow!("Log file does not exist: {}", log_file));
+        }
+        if let Err(e) = fs::metadata(path) {
+            return Err(anyhow::anyhow!(
+                "Cannot access log file {}: {}",
+                log_file,
+                e
+            ));
+        }
+        if let Err(e) = fs::File::open(path) {
+            let err = anyhow::anyhow!("Cannot read log file {}: {}", log_file, e);
+            if e.kind() == std::io::ErrorKind::PermissionDenied {
+                return Err(err.context(Failure::PermissionDenied));
+            }
+            return Err(err);
+        }
+    }
+
+    for input in &config.snapshots {
+        if let Err(e) = fs::File::open(&input.path) {
+            return Err(anyhow::anyhow!(
+                "Cannot read snapshot file {}: {}",
+                input.path,
+                e
+            ));
+        }
+    }
+
+    for input in &config.backfills {
+        if let Err(e) = fs::File::open(&input.path) {
+            let err = anyhow::anyhow!("Cannot read backfill file {}: {}", input.path, e);
+            if e.kind() == std::io::ErrorKind::PermissionDenied {
+                return Err(err.context(Failure::PermissionDenied));
+            }
+            return Err(err);
+        }
+        if input.workers == Some(0) {
+            return Err(anyhow::anyhow!("Backfill {} has workers = 0", input.path));
+        }
+    }
+
+    for file in &config.bytes_body_files {
+        if !input::file_paths(config)
+            .iter()
+            .any(|path| input::key_matches(path, file))
+        {
+            return Err(anyhow::anyhow!(
+                "bytes_body_files entry {} is not a configured log file or backfill",
+                file
+            ));
+        }
+    }
+
+    for (input, ts) in &config.timestamps {
+        timestamp::TimestampExtractor::new(ts)
+            .with_context(|| format!("Invalid timestamp config for {}", input))?;
+    }
+    for (input, skew) in &config.clock_skew {
+        timestamp::SkewCorrection::new(skew)
+            .with_context(|| format!("Invalid clock_skew config for {}", input))?;
+    }
+    if let Some(ref check) = config.timestamp_check {
+        timestamp::TimestampCheck::new(check, Default::default())
+            .context("Invalid timestamp_check config")?;
+    }
+
+    for (input, format) in &config.log_formats {
+        log_format::Parser::new(format, &config.regex_limits)
+            .with_context(|| format!("Invalid log_formats entry for {}", input))?;
+    }
+
+    for (input, filter) in &config.filters {
+        filter::LineFilter::new(input, filter, &config.regex_limits)
+            .with_context(|| format!("Invalid filters entry for {}", input))?;
+    }
+    if let Some(ref redaction) = config.redaction {
+        redact::Redactor::new(redaction, &config.regex_limits).context("Invalid redaction")?;
+    }
+    if let Some(ref faults) = config.fault_injection {
+        faults.validate()?;
+    }
+    if let Some(ref adaptive) = config.adaptive_rate {
+        adaptive.validate()?;
+    }
+    if let Some(ref archive) = config.local_archive {
+        archive.validate()?;
+    }
+    if let Some(ref buffer) = config.disk_buffer {
+        spool::Retention::new(&buffer.retention)?;
+        if let Some(ref encryption) = buffer.encryption {
+            encryption.validate()?;
+        }
+    }
+    if let Some(ref rollup) = config.severity_rollup {
+        rollup.validate()?;
+    }
+    for (input, alerts) in &config.volume_alerts {
+        alerts
+            .validate()
+            .with_context(|| format!("Invalid volume_alerts for {}", input))?;
+    }
+    if let Some(ref tls) = config.tls {
+        tls.validate().context("Invalid tls")?;
+    }
+    for (i, incident) in config.incidents.iter().enumerate() {
+        incident.validate(&config.regex_limits)?;
+        if config.incidents[..i]
+            .iter()
+            .any(|o| o.name == incident.name)
+        {
+            return Err(anyhow::anyhow!(
+                "Incident {} is defined twice",
+                incident.name
+            ));
+        }
+    }
+    if let Some(ref audit) = config.audit {
+        audit.validate().context("Invalid audit")?;
+    }
+    if let Some(ref cloud) = config.cloud_metadata {
+        cloud.validate()?;
+    }
+    if config.resource_attributes.contains_key("service.name") {
+        return Err(anyhow::anyhow!(
+            "resource_attributes cannot set service.name; use service_name"
+        ));
+    }
+    for (input, header) in &config.file_headers {
+        header::HeaderSkipper::new(input, header, &config.regex_limits)
+            .with_context(|| format!("Invalid file_headers entry for {}", input))?;
+    }
+    if config.run_as_user.is_some() && config.privsep_user.is_some() {
+        return Err(anyhow::anyhow!(
+            "run_as_user and privsep_user cannot be used together"
+        ));
+    }
+    if config.run_as_group.is_some() && config.run_as_user.is_none() {
+        return Err(anyhow::anyhow!("run_as_group needs run_as_user"));
+    }
+    if cfg!(windows) && (config.run_as_user.is_some() || config.privsep_user.is_some()) {
+        return Err(anyhow::anyhow!(
+            "run_as_user and privsep_user are not available on Windows; set the service's account instead"
+        ));
+    }
+    if config.catch_up.as_ref().is_some_and(|c| c.max_files == 0) {
+        return Err(anyhow::anyhow!("catch_up.max_files must be greater than 0"));
+    }
+    for (input, quarantine) in &config.parse_quarantine {
+        quarantine.validate(input)?;
+    }
+    for (input, health) in &config.listener_health {
+        health.validate(input)?;
+    }
+    for (input, resync) in &config.resync {
+        resync::Resyncer::new(input, resync, &config.regex_limits)
+            .with_context(|| format!("Invalid resync config for {}", input))?;
+    }
+    for (input, stitch) in &config.stitch {
+        stitch::Stitcher::new(input, stitch, &config.regex_limits)
+            .with_context(|| format!("Invalid stitch config for {}", input))?;
+    }
+    for (input, ml) in &config.multiline {
+        multiline::Joiner::new(input, ml, &config.regex_limits)
+            .with_context(|| format!("Invalid multiline config for {}", input))?;
+    }
+
+    input::configured_inputs(config)?;
+
+    if let Some(ref sampling) = config.sampling {
+        sampling::Sampler::new(sampling, &config.regex_limits)?;
+    }
+    for (input, schedule) in &config.severity_schedules {
+        schedule::Schedule::new(schedule)
+            .with_context(|| format!("Invalid severity_schedules entry for {}", input))?;
+    }
+    throttle::Throttle::new(
+        &config.throttle_rules,
+        &config.regex_limits,
+        chrono::Utc::now(),
+    )?;
+
+    if let Some(fraction) = config.replay_rate_fraction {
+        if !(fraction > 0.0 && fraction <= 1.0) {
+            return Err(anyhow::anyhow!(
+                "replay_rate_fraction must be greater than 0 and at most 1"
+            ));
+        }
+        if config.rate_limit.is_none() {
+            return Err(anyhow::anyhow!(
+                "replay_rate_fraction is a share of rate_limit, which is not set"
+            ));
+        }
+    }
+    if let Some(ref speed) = config.replay_speed {
+        replay::speed(speed)?;
+    }
+
+    for file in &config.mmap_files {
+        if !config.log_files.contains(file) {
+            return Err(anyhow::anyhow!(
+                "mmap_files entry {} is not a configured log file",
+                file
+            ));
+        }
+    }
+
+    computed::Computed::new(&config.computed_attributes)?;
+    for map in &config.severity_maps {
+        map.validate()?;
+    }
+    config.attribute_hints.validate()?;
+    for (keyword, sev) in &config.severity_keywords {
+        if severity_from_name(sev).is_none() {
+            return Err(anyhow::anyhow!(
+                "severity_keywords entry {} has unknown severity: {}",
+                keyword,
+                sev
+            ));
+        }
+    }
+    if let Some(step) = config
+        .shutdown_step_timeouts
+        .keys()
+        .find(|step| !shutdown::STEPS.contains(&step.as_str()))
+    {
+        return Err(anyhow::anyhow!(
+            "shutdown_step_timeouts has unknown step {}; known steps: {}",
+            step,
+            shutdown::STEPS.join(", ")
+        ));
+    }
+    for (input, rules) in &config.severity_rules {
+        severity::SeverityRules::new(input, rules, &config.regex_limits)?;
+    }
+
+    if let Some((input, _)) = config.queue_weights.iter().find(|(_, &w)| w == 0) {
+        return Err(anyhow::anyhow!(
+            "queue_weights entry {} must be greater than 0",
+            input
+        ));
+    }
+    if config.queue_capacity == Some(0) {
+        return Err(anyhow::anyhow!("queue_capacity must be greater than 0"));
+    }
+
+    if config.batch_size == Some(0) {
+        return Err(anyhow::anyhow!("batch_size must be greater than 0"));
+    }
+    if config.max_in_flight_requests == Some(0) {
+        return Err(anyhow::anyhow!(
+            "max_in_flight_requests must be greater than 0"
+        ));
+    }
+    if config.compression_level.is_some_and(|level| level > 9) {
+        return Err(anyhow::anyhow!("compression_level must be between 0 and 9"));
+    }
+    if config.compression_workers == Some(0) {
+        return Err(anyhow::anyhow!(
+            "compression_workers must be greater than 0"
+        ));
+    }
+
+    if config.read_buffer_bytes == Some(0) {
+        return Err(anyhow::anyhow!("read_buffer_bytes must be greater than 0"));
+    }
+    if config.max_open_files == Some(0) {
+        return Err(anyhow::anyhow!("max_open_files must be greater than 0"));
+    }
+    if let Some((input, _)) = config.idle_close_secs.iter().find(|(_, &secs)| secs == 0) {
+        return Err(anyhow::anyhow!(
+            "idle_close_secs for {} must be greater than 0",
+            input
+        ));
+    }
+
+    config.service_unit.validate()?;
+    config.console.validate(&config.regex_limits)?;
+    if config
+        .inputs
+        .iter()
+        .filter(|spec| spec.kind == "stdin")
+        .count()
+        > 1
+    {
+        return Err(anyhow::anyhow!("Only one stdin input can read stdin"));
+    }
+    for input in &config.commands {
+        if input.command.trim().is_empty() {
+            return Err(anyhow::anyhow!(
+                "Command input {} has an empty command",
+                input.name
+            ));
+        }
+        if let Some(ref sev) = input.stderr_severity {
+            if severity_from_name(sev).is_none() {
+                return Err(anyhow::anyhow!(
+                    "Command input {} has unknown stderr_severity: {}",
+                    input.name,
+                    sev
+                ));
+            }
+        }
+    }
+
+    if config.regex_limits.size_limit == 0 {
+        return Err(anyhow::anyhow!(
+            "regex_limits.size_limit must be greater than 0"
+        ));
+    }
+    for (i, rule) in config.path_attributes.iter().enumerate() {
+        let regex = rules::compile(
+            &format!("path_attributes[{}]", i),
+            &rule.pattern,
+            &config.regex_limits,
+        )?;
+        if let Some(ref group) = rule.service_name_group {
+            if !regex.capture_names().flatten().any(|n| n == group) {
+                return Err(anyhow::anyhow!(
+                    "path_attributes pattern {} has no group named {}",
+                    rule.pattern,
+                    group
+                ));
+            }
+        }
+    }
+
+    if let Some(ref age) = config.max_record_age {
+        parse_time_span(age).context("Invalid max_record_age")?;
+    }
+
+    for path in config.otlp_path.iter().chain(config.otlp_paths.values()) {
+        validate_otlp_path(path)?;
+    }
+
+    if !config.endpoint.starts_with("http://") && !config.endpoint.starts_with("https://") {
+        return Err(anyhow::anyhow!(
+            "Endpoint URL must start with http:// or https://"
+        ));
+    }
+
+    let url_regex = Regex::new(r"^https?://[^\s/$.?#].[^\s]*$").unwrap();
+    if !url_regex.is_match(&config.endpoint) {
+        return Err(anyhow::anyhow!(
+            "Invalid endpoint URL format: {}",
+            config.endpoint
+        ));
+    }
+    config
+        .credentials()
+        .header_map()
+        .context("Invalid auth or headers")?;
+    if let Some(ref user_agent) = config.user_agent {
+        reqwest::header::HeaderValue::from_str(user_agent)
+            .map_err(|_| anyhow::anyhow!("Invalid user_agent: {:?}", user_agent))?;
+    }
+    let mut protocols = vec![(
+        "signoz",
+        &config.endpoint,
+        exporter::Protocol::for_endpoint(config.protocol, &config.endpoint),
+    )];
+    let mut exporter_names = vec!["signoz"];
+    if config.local_archive.is_some() {
+        exporter_names.push(local_archive::NAME);
+    }
+    for exporter in &config.exporters {
+        exporter
+            .credentials()
+            .header_map()
+            .with_context(|| format!("Invalid auth or headers of exporter {}", exporter.name))?;
+        if exporter_names.contains(&exporter.name.as_str()) {
+            return Err(anyhow::anyhow!(
+                "Duplicate exporter name: {}",
+                exporter.name
+            ));
+        }
+        exporter_names.push(&exporter.name);
+        if !url_regex.is_match(&exporter.endpoint) {
+            return Err(anyhow::anyhow!(
+                "Invalid endpoint URL format for exporter {}: {}",
+                exporter.name,
+                exporter.endpoint
+            ));
+        }
+        protocols.push((
+            &exporter.name,
+            &exporter.endpoint,
+            exporter::Protocol::for_endpoint(exporter.protocol, &exporter.endpoint),
+        ));
+    }
+    let environments = std::iter::once(("signoz", &config.environments)).chain(
+        config
+            .exporters
+            .iter()
+            .map(|e| (e.name.as_str(), &e.environments)),
+    );
+    for (name, environments) in environments {
+        if environments.iter().any(String::is_empty) {
+            return Err(anyhow::anyhow!(
+                "Exporter {} has an empty name in environments",
+                name
+            ));
+        }
+    }
+    if config.environment_attribute.as_deref() == Some("") {
+        return Err(anyhow::anyhow!("environment_attribute must not be empty"));
+    }
+    if config.encoding == exporter::Encoding::Protobuf && cfg!(not(feature = "protobuf")) {
+        return Err(anyhow::anyhow!(
+            "encoding = \"protobuf\" needs a build with the protobuf feature (cargo build --features protobuf)"
+        ));
+    }
+    for (name, endpoint, protocol) in protocols {
+        if protocol == exporter::Protocol::Grpc && cfg!(not(feature = "grpc")) {
+            return Err(anyhow::anyhow!(
+                "Exporter {} sends to {} over gRPC, which needs a build with the grpc feature (cargo build --features grpc); set protocol = \"http\" if it serves OTLP/HTTP",
+                name,
+                endpoint
+            ));
+        }
+    }
+
+    if let Some(ref clickhouse) = config.clickhouse {
+        if cfg!(not(feature = "clickhouse")) {
+            return Err(anyhow::anyhow!(
+                "[clickhouse] needs a build with the clickhouse feature (cargo build --features clickhouse)"
+            ));
+        }
+        if exporter_names.contains(&"clickhouse") {
+            return Err(anyhow::anyhow!("Duplicate exporter name: clickhouse"));
+        }
+        if !url_regex.is_match(&clickhouse.url) {
+            return Err(anyhow::anyhow!(
+                "Invalid ClickHouse URL format: {}",
+                clickhouse.url
+            ));
+        }
+        if !matches!(clickhouse.schema_version, 1 | 2) {
+            return Err(anyhow::anyhow!(
+                "Unsupported ClickHouse schema_version {}; use 1 or 2",
+                clickhouse.schema_version
+            ));
+        }
+        if clickhouse.batch_size == 0 {
+            return Err(anyhow::anyhow!(
+                "ClickHouse batch_size must be greater than 0"
+            ));
+        }
+        let identifier = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+        if !identifier.is_match(&clickhouse.database) {
+            return Err(anyhow::anyhow!(
+                "Invalid ClickHouse database name: {}",
+                clickhouse.database
+            ));
+        }
+    }
+
+    if let Some(ref parquet) = config.parquet {
+        if cfg!(not(feature = "parquet")) {
+            return Err(anyhow::anyhow!(
+                "[parquet] needs a build with the parquet feature (cargo build --features parquet)"
+            ));
+        }
+        if exporter_names.contains(&"parquet") {
+            return Err(anyhow::anyhow!("Duplicate exporter name: parquet"));
+        }
+        if parquet.batch_size == 0 || parquet.flush_interval_secs == 0 {
+            return Err(anyhow::anyhow!(
+                "Parquet batch_size and flush_interval_secs must be greater than 0"
+            ));
+        }
+        if let Some(ref s3) = parquet.s3 {
+            if s3.bucket.is_empty() || s3.region.is_empty() {
+                return Err(anyhow::anyhow!("parquet.s3 needs a bucket and a region"));
+            }
+            if let Some(ref endpoint) = s3.endpoint {
+                if !url_regex.is_match(endpoint) {
+                    return Err(anyhow::anyhow!(
+                        "Invalid parquet.s3 endpoint URL format: {}",
+                        endpoint
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses durations such as "500ms", "90s", "30m", "24h" or "7d"; bare numbers are seconds.
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("Invalid duration: {}", value))?;
+    let multiplier = match unit.trim() {
+        "ms" => return Ok(Duration::from_millis(number)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        other => {
+            return Err(anyhow::anyhow!(
+                "Unknown duration unit {:?} in {}",
+                other,
+                value
+            ))
+        }
+    };
+    let seconds = number
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow::anyhow!("Duration {} is too long", value))?;
+    Ok(Duration::from_secs(seconds))
+}
+
+/// `parse_duration` for a span taken from or added to dates, which chrono holds in
+/// fewer bits than `std::time::Duration`.
+pub fn parse_time_span(value: &str) -> Result<chrono::Duration> {
+    chrono::Duration::from_std(parse_duration(value)?)
+        .map_err(|_| anyhow::anyhow!("Duration {} is too long", value))
+}
+
+pub const DEFAULT_OTLP_PATH: &str = "/v1/logs";
+
+pub fn validate_otlp_path(path: &str) -> Result<()> {
+    if !path.starts_with('/') || path.contains(|c: char| c.is_whitespace() || c == '?' || c == '#')
+    {
+        return Err(anyhow::anyhow!(
+            "Invalid OTLP path {:?}: must start with / and contain no whitespace, query or fragment",
+            path
+        ));
+    }
+    Ok(())
+}
+
+/// The `scheme://host[:port]` part of a URL.
+pub fn endpoint_origin(url: &str) -> &str {
+    let host_start = url.find("://").map_or(0, |i| i + 3);
+    match url[host_start..].find('/') {
+        Some(i) => &url[..host_start + i],
+        None => url,
+    }
+}
+
+/// Completes an endpoint given as `host:port` or without a path: `http://` is assumed
+/// when there is no scheme and `path`, if any, is appended when the URL has no path of
+/// its own.
+pub fn resolve_endpoint(endpoint: &str, path: Option<&str>) -> String {
+    let endpoint = endpoint.trim();
+    let url = if endpoint.contains("://") {
+        endpoint.to_string()
+    } else {
+        format!("http://{}", endpoint)
+    };
+    match path {
+        Some(path) if endpoint_origin(&url).len() >= url.trim_end_matches('/').len() => {
+            format!("{}{}", url.trim_end_matches('/'), path)
+        }
+        _ => url,
+    }
+}
+
+/// Fills in the variables of the endpoints and completes them.
+pub fn resolve_endpoints(config: &mut Config) -> Result<()> {
+    let otlp_path = config
+        .otlp_path
+        .clone()
+        .unwrap_or_else(|| DEFAULT_OTLP_PATH.to_string());
+    let path = (config.endpoint_path == EndpointPath::Auto).then_some(otlp_path.as_str());
+    let vars = &config.endpoint_vars;
+    let expand = |endpoint: &str| {
+        template::expand(endpoint, |name| template::variable(name, vars))
+            .context("Invalid endpoint")
+    };
+    config.endpoint = resolve_endpoint(&expand(&config.endpoint)?, path);
+    for exporter in &mut config.exporters {
+        exporter.endpoint = resolve_endpoint(&expand(&exporter.endpoint)?, path);
+    }
+    if let Some(ref endpoint) = config.metrics_endpoint {
+        config.metrics_endpoint = Some(expand(endpoint)?);
+    }
+    Ok(())
+}
+
+/// Longest a shutdown waits for queued records and partial batches to be delivered,
+/// unless `shutdown_timeout_secs` says otherwise.
+pub const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_are_parsed_and_overflow_is_an_error() {
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("7d").unwrap(), Duration::from_secs(7 * 86400));
+        let error = parse_duration("999999999999999999d").unwrap_err();
+        assert!(error.to_string().contains("too long"));
+        assert!(parse_duration("5w").is_err());
+
+        assert_eq!(parse_time_span("7d").unwrap(), chrono::Duration::days(7));
+        let error = parse_time_span("99999999999999d").unwrap_err();
+        assert!(error.to_string().contains("too long"));
+        let config: Config = toml::from_str(
+            "endpoint = \"http://localhost:4318\"\nlog_files = []\nmax_record_age = \"99999999999999d\"\n",
+        )
+        .unwrap();
+        let error = validate_config(&config).unwrap_err();
+        assert!(format!("{:#}", error).contains("Invalid max_record_age"));
+    }
+
+    #[test]
+    fn endpoints_without_a_path_get_otlp_path_unless_used_as_is() {
+        let resolved = |extra: &str| {
+            let mut config: Config = toml::from_str(&format!(
+                "endpoint = \"collector:4318\"\nlog_files = []\n{}\n\n[[exporters]]\nname = \"backup\"\nendpoint = \"https://backup.example.com/ingest\"\n",
+                extra
+            ))
+            .unwrap();
+            resolve_endpoints(&mut config).unwrap();
+            (config.endpoint, config.exporters[0].endpoint.clone())
+        };
+        assert_eq!(
+            resolved(""),
+            (
+                "http://collector:4318/v1/logs".to_string(),
+                "https://backup.example.com/ingest".to_string()
+            )
+        );
+        assert_eq!(
+            resolved("otlp_path = \"/otlp/v1/logs\"").0,
+            "http://collector:4318/otlp/v1/logs"
+        );
+        assert_eq!(
+            resolved("endpoint_path = \"as_is\"").0,
+            "http://collector:4318"
+        );
+        assert_eq!(
+            resolve_endpoint("https://logs.example.com/", None),
+            "https://logs.example.com/"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::io::IsTerminal;
+
+use crate::input::key_matches;
+use crate::rules::{self, RegexLimits};
+use crate::{severity_from_name, Severity};
+
+/// `[console]`: which of the lines read the agent prints to its standard output, as it
+/// ships all of them either way, so it can stand in for `tail -f` across its files.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ConsoleConfig {
+    /// Print the lines at all.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Lines below this severity, e.g. "warn", are not printed.
+    pub min_severity: Option<String>,
+    /// Input names or patterns printed; every file-backed input when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<String>,
+    /// Only the lines matching this are printed.
+    pub pattern: Option<String>,
+    #[serde(default)]
+    pub color: Color,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl Default for ConsoleConfig {
+    fn default() -> ConsoleConfig {
+        ConsoleConfig {
+            enabled: default_enabled(),
+            min_severity: None,
+            inputs: Vec::new(),
+            pattern: None,
+            color: Color::Auto,
+        }
+    }
+}
+
+/// Whether lines are colored by their severity.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Color {
+    /// When the standard output is a terminal.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ConsoleConfig {
+    pub fn is_default(&self) -> bool {
+        *self == ConsoleConfig::default()
+    }
+
+    pub fn validate(&self, limits: &RegexLimits) -> anyhow::Result<Option<Regex>> {
+        if let Some(ref severity) = self.min_severity {
+            if severity_from_name(severity).is_none() {
+                return Err(anyhow::anyhow!(
+                    "console.min_severity {:?} is not a severity",
+                    severity
+                ));
+            }
+        }
+        self.pattern
+            .as_deref()
+            .map(|p| rules::compile("console pattern", p, limits))
+            .transpose()
+    }
+}
+
+/// Prints the lines of one input that pass the `[console]` filters.
+pub struct Console {
+    /// The file the lines are read from, printed before each.
+    path: String,
+    min_severity: u8,
+    pattern: Option<Regex>,
+    color: bool,
+}
+
+impl Console {
+    /// The console of `input`, reading `path`, if any of its lines are printed.
+    pub fn new(
+        input: &str,
+        path: &str,
+        config: &ConsoleConfig,
+        limits: &RegexLimits,
+    ) -> Option<Console> {
+        let listed = config.inputs.is_empty()
+            || config
+                .inputs
+                .iter()
+                .any(|k| key_matches(k, input) || key_matches(k, path));
+        if !config.enabled || !listed {
+            return None;
+        }
+        Some(Console {
+            path: path.to_string(),
+            min_severity: config
+                .min_severity
+                .as_deref()
+                .and_then(severity_from_name)
+                .map_or(0, |severity| severity.number),
+            pattern: config.validate(limits).ok()?,
+            color: match config.color {
+                Color::Auto => std::io::stdout().is_terminal(),
+                Color::Always => true,
+                Color::Never => false,
+            },
+        })
+    }
+
+    /// The line as printed, `None` if it is filtered out.
+    fn format(&self, line: &str, severity: Severity) -> Option<String> {
+        if severity.number < self.min_severity
+            || self.pattern.as_ref().is_some_and(|p| !p.is_match(line))
+        {
+            return None;
+        }
+        let code = match severity.text {
+            "FATAL" | "ERROR" => "31",
+            "WARN" => "33",
+            "DEBUG" | "TRACE" => "2",
+            _ => return Some(format!("[{}] {}", self.path, line)),
+        };
+        Some(match self.color {
+            true => format!("\x1b[{}m[{}] {}\x1b[0m", code, self.path, line),
+            false => format!("[{}] {}", self.path, line),
+        })
+    }
+
+    pub fn print(&self, line: &str, severity: Severity) {
+        if let Some(printed) = self.format(line, severity) {
+            println!("{}", printed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_printed_by_severity_input_and_pattern() {
+        let config: ConsoleConfig = toml::from_str(
+            "min_severity = \"warn\"\ninputs = [\"/var/log/app/*.log\"]\npattern = \"db\"\ncolor = \"always\"",
+        )
+        .unwrap();
+        let limits = RegexLimits::default();
+        assert!(Console::new("/var/log/syslog", "/var/log/syslog", &config, &limits).is_none());
+        let console = Console::new(
+            "/var/log/app/api.log",
+            "/var/log/app/api.log",
+            &config,
+            &limits,
+        )
+        .unwrap();
+
+        assert_eq!(
+            console.format("db timeout", Severity::ERROR).as_deref(),
+            Some("\x1b[31m[/var/log/app/api.log] db timeout\x1b[0m")
+        );
+        assert_eq!(console.format("db connected", Severity::INFO), None);
+        assert_eq!(console.format("cache miss", Severity::WARN), None);
+
+        let plain = Console::new("a.log", "a.log", &ConsoleConfig::default(), &limits).unwrap();
+        assert_eq!(
+            plain.format("started", Severity::INFO).as_deref(),
+            Some("[a.log] started")
+        );
+        let off: ConsoleConfig = toml::from_str("enabled = false").unwrap();
+        assert!(Console::new("a.log", "a.log", &off, &limits).is_none());
+        assert!(toml::from_str::<ConsoleConfig>("min_severity = \"loud\"")
+            .unwrap()
+            .validate(&limits)
+            .is_err());
+    }
+}
@@ -0,0 +1,204 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+use crate::processor::{self, Processor};
+use crate::{Body, Record};
+
+const ESC: char = '\x1b';
+const BEL: char = '\x07';
+
+/// What is done with what a terminal would interpret rather than print.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Removed.
+    #[default]
+    Strip,
+    /// Shipped as read.
+    Keep,
+    /// Written in caret notation, as `cat -v` does: ESC as `^[`, BEL as `^G`, DEL as `^?`.
+    Caret,
+}
+
+/// `[control_characters]`: ANSI escape sequences, such as the colors of logs captured
+/// from a terminal, and the other C0 control characters and DEL in record bodies and
+/// attribute values. Tabs and the newlines of multiline records are kept.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ControlCharsConfig {
+    /// Escape sequences: colors, cursor moves, window titles.
+    #[serde(default)]
+    pub ansi: Mode,
+    /// The control characters outside escape sequences.
+    #[serde(default)]
+    pub control: Mode,
+}
+
+/// Normalizes the records before the other processors look at them.
+pub struct ControlChars {
+    config: ControlCharsConfig,
+    /// Mark the records it changed, with `mark_mutations`.
+    mark: bool,
+}
+
+impl ControlChars {
+    pub fn new(config: &ControlCharsConfig) -> ControlChars {
+        ControlChars {
+            config: config.clone(),
+            mark: false,
+        }
+    }
+
+    pub fn marking(mut self, mark: bool) -> ControlChars {
+        self.mark = mark;
+        self
+    }
+
+    fn normalize<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if !text.chars().any(is_control) {
+            return Cow::Borrowed(text);
+        }
+        let mut normalized = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            let (len, mode) = match escape_len(rest) {
+                Some(len) => (len, self.config.ansi),
+                None => (c.len_utf8(), self.config.control),
+            };
+            let (part, after) = rest.split_at(len);
+            match mode {
+                Mode::Strip if part.chars().any(is_control) => {}
+                Mode::Strip | Mode::Keep => normalized.push_str(part),
+                Mode::Caret => part.chars().for_each(|c| push_caret(&mut normalized, c)),
+            }
+            rest = after;
+        }
+        Cow::Owned(normalized)
+    }
+}
+
+impl Processor for ControlChars {
+    fn process(&mut self, mut record: Record) -> Option<Record> {
+        let mut changed = false;
+        if let Body::Text(ref mut text) = record.body {
+            if let Cow::Owned(normalized) = self.normalize(text) {
+                changed |= normalized != *text;
+                *text = normalized;
+            }
+        }
+        for (_, value) in record.attributes.iter_mut() {
+            if let Cow::Owned(normalized) = self.normalize(value) {
+                changed |= normalized != *value;
+                *value = normalized;
+            }
+        }
+        if changed && self.mark {
+            processor::mark_mutation(&mut record, "control_characters");
+        }
+        Some(record)
+    }
+}
+
+fn is_control(c: char) -> bool {
+    (c.is_ascii_control() && c != '\t' && c != '\n') || c == '\x7f'
+}
+
+fn push_caret(out: &mut String, c: char) {
+    if is_control(c) {
+        out.push('^');
+        out.push((c as u8 ^ 0x40) as char);
+    } else {
+        out.push(c);
+    }
+}
+
+/// The length of the escape sequence `text` starts with, following ECMA-48: CSI
+/// (`ESC [`, as in colors), the strings up to their terminator (`ESC ]` titles and the
+/// like) and the two-character ones. A sequence cut off by the end of the text runs to
+/// it.
+fn escape_len(text: &str) -> Option<usize> {
+    let mut chars = text.strip_prefix(ESC)?.char_indices();
+    let end = |i: usize| i + ESC.len_utf8() + 1;
+    match chars.next()? {
+        (_, '[') => Some(
+            chars
+                .find(|&(_, c)| !('\x20'..='\x3f').contains(&c))
+                .filter(|&(_, c)| ('\x40'..='\x7e').contains(&c))
+                .map_or(text.len(), |(i, _)| end(i)),
+        ),
+        (_, ']' | 'P' | 'X' | '^' | '_') => {
+            let mut previous = None;
+            Some(
+                chars
+                    .find(|&(_, c)| {
+                        let found = c == BEL || (c == '\\' && previous == Some(ESC));
+                        previous = Some(c);
+                        found
+                    })
+                    .map_or(text.len(), |(i, _)| end(i)),
+            )
+        }
+        (_, '\x20'..='\x2f') => Some(
+            chars
+                .find(|&(_, c)| !('\x20'..='\x2f').contains(&c))
+                .filter(|&(_, c)| ('\x30'..='\x7e').contains(&c))
+                .map_or(text.len(), |(i, _)| end(i)),
+        ),
+        (i, '\x30'..='\x7e') => Some(end(i)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Attributes;
+
+    #[test]
+    fn escapes_and_control_characters_are_normalized() {
+        let processor = |config: &str| {
+            let config: ControlCharsConfig = toml::from_str(config).unwrap();
+            ControlChars::new(&config).marking(true)
+        };
+        let colored = "\x1b[1;31mERROR\x1b[0m\tdisk\x07 full\x1b]0;title\x07\nnext\x7f\x1b";
+
+        let strip = processor("");
+        assert_eq!(strip.normalize(colored), "ERROR\tdisk full\nnext");
+        assert!(matches!(strip.normalize("plain\tline"), Cow::Borrowed(_)));
+        assert_eq!(strip.normalize("a\x1b(Bb\x1b[3"), "ab");
+
+        let caret = processor("ansi = \"caret\"\ncontrol = \"caret\"");
+        assert_eq!(
+            caret.normalize(colored),
+            "^[[1;31mERROR^[[0m\tdisk^G full^[]0;title^G\nnext^?^["
+        );
+
+        let colors = processor("ansi = \"keep\"");
+        assert_eq!(
+            colors.normalize(colored),
+            "\x1b[1;31mERROR\x1b[0m\tdisk full\x1b]0;title\x07\nnext"
+        );
+
+        let mut strip = strip;
+        let record = strip
+            .process(Record {
+                attributes: Attributes::from(vec![(
+                    "user".to_string(),
+                    "\x1b[1malice".to_string(),
+                )]),
+                ..Record::new("\x1b[32mok\x1b[0m")
+            })
+            .unwrap();
+        assert_eq!(record.body.text(), "ok");
+        assert_eq!(
+            record.attributes[0],
+            ("user".to_string(), "alice".to_string())
+        );
+        assert_eq!(
+            record.attributes[1],
+            (
+                processor::MUTATIONS_ATTRIBUTE.to_string(),
+                "control_characters".to_string()
+            )
+        );
+    }
+}
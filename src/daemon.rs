@@ -0,0 +1,97 @@
+use anyhow::Result;
+use std::fs;
+#[cfg(unix)]
+use {anyhow::Context, std::fs::OpenOptions, std::io, std::os::unix::io::AsRawFd, std::path::Path};
+
+use crate::filelock::FileLock;
+
+/// Default `pid_file` and `agent_log_file`, in the state directory.
+pub const PID_FILE: &str = "agent.pid";
+pub const LOG_FILE: &str = "agent.log";
+
+/// Detaches from the controlling terminal using the classic double-fork/setsid sequence.
+///
+/// Must be called before any threads are spawned. The working directory is left unchanged
+/// so relative paths in config.toml keep resolving the same way they do in the foreground.
+/// Returns the lock on the pid file, which tells other agents this one is running for as
+/// long as it is kept.
+#[cfg(unix)]
+pub fn daemonize(pid_file: &str, log_file: &str) -> Result<FileLock> {
+    check_not_running(pid_file)?;
+
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)
+        .with_context(|| format!("Failed to open agent log file {}", log_file))?;
+    let dev_null = OpenOptions::new().read(true).open("/dev/null")?;
+
+    // SAFETY: no other threads exist yet, so forking cannot leave locks held in the child.
+    unsafe {
+        fork_and_exit_parent()?;
+        if libc::setsid() < 0 {
+            return Err(io::Error::last_os_error()).context("setsid failed");
+        }
+        fork_and_exit_parent()?;
+        libc::umask(0o027);
+
+        redirect(dev_null.as_raw_fd(), libc::STDIN_FILENO)?;
+        redirect(log.as_raw_fd(), libc::STDOUT_FILENO)?;
+        redirect(log.as_raw_fd(), libc::STDERR_FILENO)?;
+    }
+
+    let mut lock = FileLock::try_lock(Path::new(pid_file))
+        .with_context(|| format!("Failed to lock pid file {}", pid_file))?
+        .ok_or_else(|| anyhow::anyhow!("Agent already running (pid file {})", pid_file))?;
+    lock.write_pid()
+        .with_context(|| format!("Failed to write pid file {}", pid_file))?;
+    Ok(lock)
+}
+
+/// Windows has no fork: the agent runs in the background as a service instead.
+#[cfg(windows)]
+pub fn daemonize(_pid_file: &str, _log_file: &str) -> Result<FileLock> {
+    Err(anyhow::anyhow!(
+        "--daemon is not available on Windows; run install-service to register the agent as a service"
+    ))
+}
+
+pub fn remove_pid_file(pid_file: &str) {
+    fs::remove_file(pid_file).ok();
+}
+
+#[cfg(unix)]
+fn check_not_running(pid_file: &str) -> Result<()> {
+    if !Path::new(pid_file).exists() {
+        return Ok(());
+    }
+    // Locked by a running agent, where a pid alone could be one reused since.
+    if FileLock::try_lock(Path::new(pid_file))?.is_none() {
+        let pid = FileLock::holder(Path::new(pid_file));
+        return Err(anyhow::anyhow!(
+            "Agent already running with pid {} (pid file {})",
+            pid.map_or("unknown".to_string(), |pid| pid.to_string()),
+            pid_file
+        ));
+    }
+    println!("Removing stale pid file {}", pid_file);
+    fs::remove_file(pid_file)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+unsafe fn fork_and_exit_parent() -> Result<()> {
+    match libc::fork() {
+        -1 => Err(io::Error::last_os_error()).context("fork failed"),
+        0 => Ok(()),
+        _ => libc::_exit(0),
+    }
+}
+
+#[cfg(unix)]
+unsafe fn redirect(from: libc::c_int, to: libc::c_int) -> Result<()> {
+    if libc::dup2(from, to) < 0 {
+        return Err(io::Error::last_os_error()).context("dup2 failed");
+    }
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use std::sync::Arc;
+
+use crate::Record;
+
+/// What became of a record once the agent is done with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// Accepted by the exporters `delivery_policy` asks for.
+    Delivered,
+    /// Dropped before export, for the reason counted in the `dropped` stats (e.g.
+    /// "sampled", "throttled", "too_old", "processor", "unrouted").
+    Dropped(&'static str),
+    /// Sent, but not accepted by enough exporters: these did not take it, after their
+    /// retries. Counted as an `undelivered` drop.
+    Undelivered(Vec<String>),
+}
+
+/// Told the outcome of every record, once per record.
+///
+/// Listeners are called from the dispatcher and exporter threads, so they should return
+/// quickly; one that needs to do more hands the outcome to a thread of its own.
+pub trait Listener: Send + Sync {
+    fn outcome(&self, record: &Record, outcome: &Outcome);
+}
+
+impl<F> Listener for F
+where
+    F: Fn(&Record, &Outcome) + Send + Sync,
+{
+    fn outcome(&self, record: &Record, outcome: &Outcome) {
+        self(record, outcome)
+    }
+}
+
+/// Registered listeners, called in registration order.
+#[derive(Default, Clone)]
+pub struct Listeners {
+    listeners: Vec<Arc<dyn Listener>>,
+}
+
+impl Listeners {
+    pub fn push(&mut self, listener: Arc<dyn Listener>) {
+        self.listeners.push(listener);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+
+    pub fn notify(&self, record: &Record, outcome: &Outcome) {
+        for listener in &self.listeners {
+            listener.outcome(record, outcome);
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::input::{FileInput, Input, InputContext, StopFlag, DEFAULT_RESCAN_SECS};
+use crate::stats::Stage;
+use crate::{enqueue_batch, Attributes, Body, Record};
+
+/// How often a discovery loop waiting for its next listing checks whether it was stopped.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The running containers of a Docker host, found through its API socket, whose
+/// `json-file` logs are tailed like log files. Each container is its own input, named
+/// `docker:<container name>`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DockerInput {
+    #[serde(default = "default_socket")]
+    pub socket: String,
+    /// Only containers with one of these labels, as `key` or `key=value`; all when empty.
+    #[serde(default)]
+    pub include_labels: Vec<String>,
+    /// Containers with one of these labels are left out, even when included.
+    #[serde(default)]
+    pub exclude_labels: Vec<String>,
+    #[serde(skip)]
+    pub stop: StopFlag,
+}
+
+#[cfg(unix)]
+fn default_socket() -> String {
+    "/var/run/docker.sock".to_string()
+}
+
+#[cfg(windows)]
+fn default_socket() -> String {
+    r"\\.\pipe\docker_engine".to_string()
+}
+
+/// A running container as listed by the API.
+struct Container {
+    id: String,
+    name: String,
+    image: String,
+    log_path: String,
+}
+
+/// Whether `labels` has one of `filters`, each `key` or `key=value`.
+fn has_label(labels: &BTreeMap<String, String>, filters: &[String]) -> bool {
+    filters.iter().any(|filter| match filter.split_once('=') {
+        Some((key, value)) => labels.get(key).is_some_and(|v| v == value),
+        None => labels.contains_key(filter),
+    })
+}
+
+#[cfg(unix)]
+fn connect(socket: &str) -> std::io::Result<UnixStream> {
+    let stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    Ok(stream)
+}
+
+/// Docker's named pipe on Windows opens as a file.
+#[cfg(windows)]
+fn connect(socket: &str) -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(socket)
+}
+
+/// `GET path` on the Docker API at `socket`, its JSON body on a 2xx answer.
+fn get(socket: &str, path: &str) -> anyhow::Result<Value> {
+    let mut stream =
+        connect(socket).map_err(|e| anyhow::anyhow!("Failed to connect to {}: {}", socket, e))?;
+    // HTTP/1.0, so that the daemon closes the connection after a body that is not chunked.
+    write!(stream, "GET {} HTTP/1.0\r\nHost: docker\r\n\r\n", path)?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| anyhow::anyhow!("Malformed answer from {} to {}", socket, path))?;
+    let status = head.lines().next().unwrap_or_default();
+    if !status
+        .split_whitespace()
+        .nth(1)
+        .is_some_and(|code| code.starts_with('2'))
+    {
+        return Err(anyhow::anyhow!(
+            "{} answered {} to {}",
+            socket,
+            status,
+            path
+        ));
+    }
+    Ok(serde_json::from_str(body)?)
+}
+
+impl DockerInput {
+    /// The running containers passing the label filters.
+    fn containers(&self) -> anyhow::Result<Vec<Container>> {
+        let listed = get(&self.socket, "/containers/json")?;
+        let mut containers = Vec::new();
+        for listed in listed.as_array().into_iter().flatten() {
+            let labels: BTreeMap<String, String> = listed
+                .get("Labels")
+                .cloned()
+                .and_then(|l| serde_json::from_value(l).ok())
+                .unwrap_or_default();
+            if (!self.include_labels.is_empty() && !has_label(&labels, &self.include_labels))
+                || has_label(&labels, &self.exclude_labels)
+            {
+                continue;
+            }
+            let Some(id) = listed.get("Id").and_then(Value::as_str) else {
+                continue;
+            };
+            let inspected = get(&self.socket, &format!("/containers/{}/json", id))?;
+            let text = |value: &Value, pointer: &str| {
+                value
+                    .pointer(pointer)
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            containers.push(Container {
+                id: id.to_string(),
+                name: text(&inspected, "/Name")
+                    .trim_start_matches('/')
+                    .to_string(),
+                image: text(listed, "/Image"),
+                log_path: text(&inspected, "/LogPath"),
+            });
+        }
+        Ok(containers)
+    }
+}
+
+/// One line of a `json-file` log: `{"log":"...\n","stream":"stdout","time":"..."}`.
+#[derive(Deserialize)]
+pub struct JsonLine {
+    pub log: String,
+    pub stream: String,
+    pub time: Option<String>,
+}
+
+/// The handler turning `container`'s log lines into records.
+fn handler(
+    container: &Container,
+    ctx: &InputContext,
+    stats: Arc<crate::stats::InputStats>,
+) -> impl FnMut(Vec<Vec<u8>>, Option<crate::tailer::Origins>) + Send + 'static {
+    let tx = ctx.queue.sender(&stats.name);
+    let state = ctx.state.clone();
+    let sequence_attribute = ctx.config.sequence_attribute.clone();
+    let otlp_path: Option<Arc<str>> =
+        crate::input::option_for(&ctx.config.otlp_paths, &stats.name).map(|p| p.as_str().into());
+    let resource: Arc<[(String, String)]> = Arc::from(vec![
+        ("container.name".to_string(), container.name.clone()),
+        ("container.id".to_string(), container.id.clone()),
+        ("container.image.name".to_string(), container.image.clone()),
+    ]);
+    move |lines, _| {
+        let started = Instant::now();
+        let observed = Utc::now();
+        let mut entries = Vec::with_capacity(lines.len());
+        for raw in lines {
+            stats.record_line();
+            let Ok(line) = serde_json::from_slice::<JsonLine>(&raw) else {
+                state.record_drop("docker_unparsable");
+                continue;
+            };
+            let mut attributes = Attributes::from(vec![("log.iostream".to_string(), line.stream)]);
+            if let Some(ref key) = sequence_attribute {
+                attributes.push(key.clone(), stats.next_sequence().to_string());
+            }
+            entries.push(Record {
+                body: Body::Text(line.log.trim_end().to_string()),
+                attributes,
+                default_severity: None,
+                severity: None,
+                service_name: None,
+                timestamp: line
+                    .time
+                    .and_then(|t| DateTime::parse_from_rfc3339(&t).ok())
+                    .map(|t| t.with_timezone(&Utc)),
+                observed,
+                otlp_path: otlp_path.clone(),
+                trace: None,
+                resource: Some(resource.clone()),
+                checkpoint: None,
+            });
+        }
+        state.record_stage(Stage::Parse, entries.len() as u64, started.elapsed());
+        enqueue_batch(&tx, &state, entries);
+    }
+}
+
+impl Input for DockerInput {
+    fn describe(&self) -> String {
+        format!("docker:{}", self.socket)
+    }
+
+    /// Lists the containers every `glob_rescan_secs`. A container found after the first
+    /// listing is read from the start of its log; one that is gone is read on for the
+    /// deleted file grace period, and its position forgotten once its log is removed.
+    fn start(&mut self, ctx: &InputContext) -> anyhow::Result<()> {
+        let rescan = Duration::from_secs(
+            ctx.config
+                .glob_rescan_secs
+                .unwrap_or(DEFAULT_RESCAN_SECS)
+                .max(1),
+        );
+        let grace = Duration::from_secs(
+            ctx.config
+                .deleted_file_grace_secs
+                .unwrap_or(crate::tombstone::DEFAULT_GRACE_SECS),
+        );
+        let (input, ctx) = (self.clone(), ctx.clone());
+        println!("Discovering containers through {}", input.socket);
+        thread::spawn(move || {
+            let mut tailers: BTreeMap<String, (FileInput, Option<Instant>)> = BTreeMap::new();
+            let mut unsupported = BTreeSet::new();
+            let mut first_scan = true;
+            while !input.stop.is_stopped() {
+                match input.containers() {
+                    Ok(containers) => {
+                        let mut running = BTreeSet::new();
+                        for container in containers {
+                            running.insert(container.id.clone());
+                            if let Some((_, missing_since)) = tailers.get_mut(&container.id) {
+                                *missing_since = None;
+                                continue;
+                            }
+                            if container.log_path.is_empty() {
+                                if unsupported.insert(container.id.clone()) {
+                                    ctx.state.record_error(format!(
+                                        "Container {} has no json-file log to read",
+                                        container.name
+                                    ));
+                                }
+                                continue;
+                            }
+                            if !first_scan {
+                                println!("Following container {}", container.name);
+                            }
+                            let tailer = FileInput {
+                                path: container.log_path.clone(),
+                                mmap: false,
+                                rewrite_policy: None,
+                                stop: StopFlag::default(),
+                                from_start: !first_scan,
+                            };
+                            let stats = ctx
+                                .state
+                                .register_input(&format!("docker:{}", container.name));
+                            tailer.tail(
+                                &ctx.config,
+                                ctx.positions.clone(),
+                                ctx.state.clone(),
+                                stats.clone(),
+                                None,
+                                handler(&container, &ctx, stats),
+                            );
+                            tailers.insert(container.id, (tailer, None));
+                        }
+                        tailers.retain(|id, (tailer, missing_since)| {
+                            if running.contains(id)
+                                || missing_since.get_or_insert_with(Instant::now).elapsed() < grace
+                            {
+                                return true;
+                            }
+                            tailer.stop.stop();
+                            if !Path::new(&tailer.path).exists() {
+                                ctx.positions.forget(&tailer.path);
+                            }
+                            false
+                        });
+                        unsupported.retain(|id| running.contains(id));
+                        first_scan = false;
+                    }
+                    Err(e) => ctx
+                        .state
+                        .record_error(format!("Docker container discovery: {e:#}")),
+                }
+                let listed = Instant::now();
+                while listed.elapsed() < rescan && !input.stop.is_stopped() {
+                    thread::sleep(STOP_CHECK_INTERVAL);
+                }
+            }
+            for (tailer, _) in tailers.values() {
+                tailer.stop.stop();
+            }
+        });
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.stop.stop();
+    }
+}
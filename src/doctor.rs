@@ -0,0 +1,291 @@
+use std::ffi::CString;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use crate::failure::Failure;
+use crate::input::{expand, file_paths};
+use crate::Config;
+
+const AUDIT_LOG: &str = "/var/log/audit/audit.log";
+/// Only the tail of the audit log is scanned for denials.
+const AUDIT_TAIL_BYTES: u64 = 4 * 1024 * 1024;
+const APPARMOR_PROFILE_DIR: &str = "/etc/apparmor.d";
+
+/// One preflight problem on an input path, with what to do about it when known.
+pub struct Finding {
+    pub path: String,
+    pub problem: String,
+    pub suggestion: Option<String>,
+}
+
+pub struct Account {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    /// Every group of the account, its primary group included.
+    pub groups: Vec<u32>,
+}
+
+pub fn lookup_account(name: &str) -> Option<Account> {
+    let c_name = CString::new(name).ok()?;
+    // SAFETY: getpwnam returns a pointer into static storage or null; the fields are
+    // copied out before any other passwd call.
+    let (uid, gid) = unsafe {
+        let pw = libc::getpwnam(c_name.as_ptr());
+        if pw.is_null() {
+            return None;
+        }
+        ((*pw).pw_uid, (*pw).pw_gid)
+    };
+
+    let mut count: libc::c_int = 64;
+    let mut groups = vec![0 as libc::gid_t; count as usize];
+    // SAFETY: `groups` holds `count` entries; on -1 `count` is set to the required size.
+    unsafe {
+        if libc::getgrouplist(c_name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) == -1 {
+            groups = vec![0; count as usize];
+            libc::getgrouplist(c_name.as_ptr(), gid, groups.as_mut_ptr(), &mut count);
+        }
+    }
+    groups.truncate(count.max(0) as usize);
+    Some(Account {
+        name: name.to_string(),
+        uid,
+        gid,
+        groups,
+    })
+}
+
+/// Whether `account` has `bits` (an rwx triple, e.g. 4 for read) on `path` by its mode
+/// bits. ACLs are not consulted.
+fn mode_allows(account: &Account, path: &Path, bits: u32) -> bool {
+    let Ok(meta) = fs::metadata(path) else {
+        return false;
+    };
+    if account.uid == 0 {
+        return true;
+    }
+    let mode = meta.mode();
+    if meta.uid() == account.uid {
+        mode & (bits << 6) != 0
+    } else if account.groups.contains(&meta.gid()) {
+        mode & (bits << 3) != 0
+    } else {
+        mode & bits != 0
+    }
+}
+
+/// The first path component `account` cannot traverse or, for `path` itself, read.
+fn blocked_component(account: &Account, path: &Path) -> Option<String> {
+    for dir in path.ancestors().skip(1) {
+        if dir.as_os_str().is_empty() {
+            continue;
+        }
+        if !mode_allows(account, dir, 1) {
+            return Some(dir.display().to_string());
+        }
+    }
+    (!mode_allows(account, path, 4)).then(|| path.display().to_string())
+}
+
+/// `Some(true)` when SELinux is enforcing, `Some(false)` when permissive, `None` when
+/// it is not enabled.
+fn selinux_enforcing() -> Option<bool> {
+    fs::read_to_string("/sys/fs/selinux/enforce")
+        .ok()
+        .map(|s| s.trim() == "1")
+}
+
+fn apparmor_enabled() -> bool {
+    fs::read_to_string("/sys/module/apparmor/parameters/enabled")
+        .map(|s| s.trim().starts_with('Y'))
+        .unwrap_or(false)
+}
+
+fn selinux_label(path: &str) -> Option<String> {
+    let c_path = CString::new(path).ok()?;
+    let name = CString::new("security.selinux").ok()?;
+    let mut buf = vec![0u8; 256];
+    // SAFETY: `buf` is valid for `buf.len()` bytes.
+    let len = unsafe {
+        libc::getxattr(
+            c_path.as_ptr(),
+            name.as_ptr(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+        )
+    };
+    if len <= 0 {
+        return None;
+    }
+    buf.truncate(len as usize);
+    Some(
+        String::from_utf8_lossy(&buf)
+            .trim_end_matches('\0')
+            .to_string(),
+    )
+}
+
+/// AVC denial lines in the tail of the audit log that mention the agent or the file.
+fn audit_denials(path: &str) -> Vec<String> {
+    let Ok(mut file) = fs::File::open(AUDIT_LOG) else {
+        return Vec::new();
+    };
+    let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+    file.seek(SeekFrom::Start(len.saturating_sub(AUDIT_TAIL_BYTES)))
+        .ok();
+    let mut content = String::new();
+    if file.read_to_string(&mut content).is_err() {
+        return Vec::new();
+    }
+    let file_name = Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    content
+        .lines()
+        .filter(|l| l.contains("avc:") && l.contains("denied"))
+        .filter(|l| l.contains("rust-signoz") && l.contains(&format!("name=\"{}\"", file_name)))
+        .map(str::to_string)
+        .collect()
+}
+
+/// AppArmor profiles under /etc/apparmor.d that mention the agent.
+fn apparmor_profiles() -> Vec<String> {
+    let Ok(entries) = fs::read_dir(APPARMOR_PROFILE_DIR) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|e| e.path().is_file())
+        .filter(|e| {
+            fs::read_to_string(e.path())
+                .map(|c| c.contains("rust-signoz-agent"))
+                .unwrap_or(false)
+        })
+        .map(|e| e.path().display().to_string())
+        .collect()
+}
+
+/// Checks that `user` (the current user when `None`) can read every configured input,
+/// and points out SELinux/AppArmor settings that would still deny the agent.
+pub fn check(config: &Config, user: Option<&str>) -> Vec<Finding> {
+    let current = whoami::fallible::username().ok();
+    let user = user.map(str::to_string).or(current.clone());
+    let account = user.as_deref().and_then(lookup_account);
+    let selinux = selinux_enforcing();
+    let mut findings = Vec::new();
+
+    if let (Some(name), None) = (user.as_deref(), account.as_ref()) {
+        findings.push(Finding {
+            path: "-".to_string(),
+            problem: format!("user {} does not exist", name),
+            suggestion: Some("create the user or set service_user".to_string()),
+        });
+    }
+
+    for path in file_paths(config).iter().flat_map(|p| expand(p)) {
+        if !Path::new(&path).exists() {
+            findings.push(Finding {
+                path: path.clone(),
+                problem: "does not exist".to_string(),
+                suggestion: None,
+            });
+            continue;
+        }
+
+        if let Some(ref account) = account {
+            if let Some(blocked) = blocked_component(account, Path::new(&path)) {
+                let what = if blocked == path { "read" } else { "traverse" };
+                findings.push(Finding {
+                    path: path.clone(),
+                    problem: format!("user {} cannot {} {}", account.name, what, blocked),
+                    suggestion: Some(format!(
+                        "grant access, e.g. `setfacl -m u:{}:{} {}`",
+                        account.name,
+                        if what == "read" { "r" } else { "x" },
+                        blocked
+                    )),
+                });
+            }
+        }
+
+        if selinux == Some(true) {
+            let denials = audit_denials(&path);
+            if !denials.is_empty() {
+                let label = selinux_label(&path).unwrap_or_else(|| "unknown".to_string());
+                findings.push(Finding {
+                    path: path.clone(),
+                    problem: format!(
+                        "{} SELinux denial(s) in {} (file context {})",
+                        denials.len(),
+                        AUDIT_LOG,
+                        label
+                    ),
+                    suggestion: Some(format!(
+                        "label it readable for services, e.g. `semanage fcontext -a -t var_log_t '{}' && restorecon -v '{}'`",
+                        path, path
+                    )),
+                });
+            }
+        }
+    }
+    findings
+}
+
+/// The `doctor` command: prints the environment and every finding, failing with
+/// `Failure::PermissionDenied` when any input would not be readable.
+pub fn run(config: &Config) -> anyhow::Result<()> {
+    let user = config.service_user.as_deref();
+    println!(
+        "Checking inputs for user {}",
+        user.map(str::to_string)
+            .or_else(|| whoami::fallible::username().ok())
+            .unwrap_or_else(|| "(unknown)".to_string())
+    );
+    match selinux_enforcing() {
+        Some(true) => println!("SELinux: enforcing"),
+        Some(false) => println!("SELinux: permissive (denials are logged, not enforced)"),
+        None => println!("SELinux: not enabled"),
+    }
+    if apparmor_enabled() {
+        let profiles = apparmor_profiles();
+        if profiles.is_empty() {
+            println!("AppArmor: enabled, no profile confines rust-signoz-agent");
+        } else {
+            println!(
+                "AppArmor: enabled, agent profile(s): {}",
+                profiles.join(", ")
+            );
+            println!("  Make sure the profile allows reading every input:");
+            for path in file_paths(config) {
+                println!("    {} r,", path);
+            }
+        }
+    } else {
+        println!("AppArmor: not enabled");
+    }
+
+    let findings = check(config, user);
+    if findings.is_empty() {
+        let files = file_paths(config).iter().flat_map(|p| expand(p)).count();
+        println!("All {} inputs are readable", files);
+        return Ok(());
+    }
+    report(&findings);
+    Err(
+        anyhow::anyhow!("{} preflight problem(s) found", findings.len())
+            .context(Failure::PermissionDenied),
+    )
+}
+
+pub fn report(findings: &[Finding]) {
+    for finding in findings {
+        println!("  {}: {}", finding.path, finding.problem);
+        if let Some(ref suggestion) = finding.suggestion {
+            println!("    -> {}", suggestion);
+        }
+    }
+}
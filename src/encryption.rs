@@ -0,0 +1,158 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::process::{Command, Stdio};
+
+/// What encrypted files start with, which no JSON text does.
+pub const MAGIC: &[u8] = b"SNZE\x01";
+
+/// `[disk_buffer.encryption]`: the requests written to the disk buffer are encrypted with
+/// AES-256-GCM, under a 32-byte key given base64-encoded by exactly one of the sources.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EncryptionConfig {
+    /// Environment variable holding the key.
+    pub key_env: Option<String>,
+    /// File holding the key, readable by the agent alone.
+    pub key_file: Option<String>,
+    /// Program and arguments printing the key to stdout, such as a KMS client decrypting
+    /// a data key; run each time an exporter opens its buffer.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key_command: Vec<String>,
+}
+
+impl EncryptionConfig {
+    pub fn validate(&self) -> Result<()> {
+        let sources = [
+            self.key_env.is_some(),
+            self.key_file.is_some(),
+            !self.key_command.is_empty(),
+        ];
+        if sources.iter().filter(|set| **set).count() != 1 {
+            return Err(anyhow::anyhow!(
+                "disk_buffer.encryption takes exactly one of key_env, key_file and key_command"
+            ));
+        }
+        Ok(())
+    }
+
+    /// The cipher of the key, fetched from its source.
+    pub fn cipher(&self) -> Result<Cipher> {
+        let encoded = if let Some(ref var) = self.key_env {
+            std::env::var(var).with_context(|| format!("Key variable {} is not set", var))?
+        } else if let Some(ref path) = self.key_file {
+            fs::read_to_string(path).with_context(|| format!("Failed to read key {}", path))?
+        } else {
+            let (program, args) = self
+                .key_command
+                .split_first()
+                .ok_or_else(|| anyhow::anyhow!("No key source"))?;
+            let output = Command::new(program)
+                .args(args)
+                .stdin(Stdio::null())
+                .stderr(Stdio::inherit())
+                .output()
+                .with_context(|| format!("Failed to run key command {}", program))?;
+            if !output.status.success() {
+                return Err(anyhow::anyhow!(
+                    "Key command {} failed: {}",
+                    program,
+                    output.status
+                ));
+            }
+            String::from_utf8(output.stdout).context("The key command printed no text")?
+        };
+        let key = base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .context("The key is not base64")?;
+        Cipher::new(&key)
+    }
+}
+
+/// Seals data as `MAGIC`, a random nonce, then the ciphertext with its tag.
+pub struct Cipher {
+    key: LessSafeKey,
+    random: SystemRandom,
+}
+
+impl Cipher {
+    pub fn new(key: &[u8]) -> Result<Cipher> {
+        let key = UnboundKey::new(&AES_256_GCM, key)
+            .map_err(|_| anyhow::anyhow!("The key is {} bytes rather than 32", key.len()))?;
+        Ok(Cipher {
+            key: LessSafeKey::new(key),
+            random: SystemRandom::new(),
+        })
+    }
+
+    pub fn seal(&self, plain: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        self.random
+            .fill(&mut nonce)
+            .map_err(|_| anyhow::anyhow!("No randomness for a nonce"))?;
+        let mut sealed = plain.to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut sealed,
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt"))?;
+        Ok([MAGIC, &nonce, &sealed].concat())
+    }
+
+    /// The data `seal` was given, unless `sealed` was changed or sealed under another key.
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        let rest = sealed
+            .strip_prefix(MAGIC)
+            .filter(|rest| rest.len() >= NONCE_LEN)
+            .ok_or_else(|| anyhow::anyhow!("Not encrypted"))?;
+        let (nonce, data) = rest.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).unwrap();
+        let mut data = data.to_vec();
+        let plain = self
+            .key
+            .open_in_place(nonce, Aad::empty(), &mut data)
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt: another key, or a damaged file"))?;
+        Ok(plain.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_is_sealed_under_the_key_of_the_source() {
+        let key = [7u8; 32];
+        let encoded = base64::engine::general_purpose::STANDARD.encode(key);
+        let config = EncryptionConfig {
+            key_env: None,
+            key_file: None,
+            key_command: vec!["echo".to_string(), encoded],
+        };
+        config.validate().unwrap();
+        let cipher = config.cipher().unwrap();
+
+        let plain = br#"{"endpoint":"http://collector:4318/v1/logs","payload":{}}"#;
+        let sealed = cipher.seal(plain).unwrap();
+        assert!(sealed.starts_with(MAGIC));
+        assert!(!sealed.windows(8).any(|w| w == b"endpoint"));
+        assert_ne!(cipher.seal(plain).unwrap(), sealed);
+        assert_eq!(cipher.open(&sealed).unwrap(), plain);
+
+        let mut damaged = sealed.clone();
+        *damaged.last_mut().unwrap() ^= 1;
+        assert!(cipher.open(&damaged).is_err());
+        assert!(Cipher::new(&[8u8; 32]).unwrap().open(&sealed).is_err());
+        assert!(Cipher::new(&[7u8; 16]).is_err());
+
+        let both = EncryptionConfig {
+            key_env: Some("BUFFER_KEY".to_string()),
+            ..config
+        };
+        assert!(both.validate().is_err());
+    }
+}
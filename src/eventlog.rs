@@ -0,0 +1,578 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::input::{Input, InputContext, StopFlag};
+use crate::stats::{AgentState, Stage};
+use crate::{enqueue, severity_from_name, statedir, Attributes, Body, Record, Severity};
+
+/// How often each channel is asked for new events.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Most events `wevtutil` returns for one query; a full batch is followed by the next one
+/// right away.
+const BATCH: usize = 500;
+/// Wait before a channel is queried again after `wevtutil` failed.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Event levels, from most to least severe, by the names Event Viewer shows.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Critical = 1,
+    Error = 2,
+    Warning = 3,
+    Information = 4,
+    Verbose = 5,
+}
+
+/// Severity of an event's `Level`; 0, "log always", counts as information.
+fn severity(level: u8) -> Option<Severity> {
+    severity_from_name(match level {
+        1 => "CRITICAL",
+        2 => "ERROR",
+        3 => "WARNING",
+        0 | 4 => "INFO",
+        _ => "DEBUG",
+    })
+}
+
+/// The events of Windows Event Log channels, read through `wevtutil` from the record ids
+/// saved in the state directory, or from the newest event on first start.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EventLogInput {
+    /// Tells several event log inputs apart in `top` and in their bookmark files.
+    #[serde(default = "default_name")]
+    pub name: String,
+    /// Channels to read, as `wevtutil el` lists them.
+    #[serde(default = "default_channels")]
+    pub channels: Vec<String>,
+    /// Only events of these providers; all when empty.
+    #[serde(default)]
+    pub providers: Vec<String>,
+    /// Only events of this level or a more severe one.
+    pub level: Option<Level>,
+    #[serde(skip)]
+    pub stop: StopFlag,
+    #[serde(skip)]
+    reader: Reader,
+}
+
+fn default_name() -> String {
+    "eventlog".to_string()
+}
+
+fn default_channels() -> Vec<String> {
+    vec!["Application".to_string(), "System".to_string()]
+}
+
+/// What the input shares with its reader thread: the id of the last event read from each
+/// channel, and the file they are saved to once the input started.
+#[derive(Clone, Default)]
+struct Reader {
+    bookmarks: Arc<Mutex<BTreeMap<String, u64>>>,
+    bookmark_file: Option<String>,
+}
+
+impl std::fmt::Debug for Reader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Reader")
+    }
+}
+
+// Left out when a reload compares the input's settings.
+impl PartialEq for Reader {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl EventLogInput {
+    /// The XPath query for the events of a channel after record `after`.
+    fn query(&self, after: u64) -> String {
+        let mut conditions = vec![format!("EventRecordID>{}", after)];
+        if !self.providers.is_empty() {
+            let providers: Vec<String> = self
+                .providers
+                .iter()
+                .map(|p| format!("@Name='{}'", p.replace('\'', "&apos;")))
+                .collect();
+            conditions.push(format!("Provider[{}]", providers.join(" or ")));
+        }
+        if let Some(level) = self.level.filter(|&l| l != Level::Verbose) {
+            let mut levels: Vec<String> =
+                (1..=level as u8).map(|l| format!("Level={}", l)).collect();
+            if level == Level::Information {
+                levels.push("Level=0".to_string());
+            }
+            conditions.push(format!("({})", levels.join(" or ")));
+        }
+        format!("*[System[{}]]", conditions.join(" and "))
+    }
+
+    fn save_bookmarks(&self) -> anyhow::Result<()> {
+        let Some(ref path) = self.reader.bookmark_file else {
+            return Ok(());
+        };
+        let bookmarks = serde_json::to_string(&*self.reader.bookmarks.lock().unwrap())?;
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, bookmarks)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Runs `wevtutil qe` on `channel` and returns what it printed.
+fn wevtutil(channel: &str, args: &[&str]) -> anyhow::Result<String> {
+    let output = Command::new("wevtutil")
+        .arg("qe")
+        .arg(channel)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "wevtutil qe {} exited with {}: {}",
+            channel,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The `<Event>` elements of `wevtutil` output.
+fn events(xml: &str) -> impl Iterator<Item = &str> {
+    xml.split_inclusive("</Event>")
+        .filter_map(|part| Some(&part[start_of(part, "Event")?..]))
+        .filter(|event| event.ends_with("</Event>"))
+}
+
+/// Where the first `<tag>` element in `xml` starts.
+fn start_of(xml: &str, tag: &str) -> Option<usize> {
+    let mut from = 0;
+    loop {
+        let start = from + xml[from..].find(&format!("<{}", tag))?;
+        if xml[start + tag.len() + 1..].starts_with(['>', ' ', '/']) {
+            return Some(start);
+        }
+        from = start + 1;
+    }
+}
+
+/// The text of the first `<tag>` element in `xml`.
+fn element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let rest = &xml[start_of(xml, tag)? + tag.len() + 1..];
+    let open_end = rest.find('>')?;
+    if rest[..open_end].ends_with('/') {
+        return Some("");
+    }
+    let body = &rest[open_end + 1..];
+    Some(&body[..body.find(&format!("</{}>", tag))?])
+}
+
+/// The value of attribute `name` on the first `<tag>` element in `xml`.
+fn attribute<'a>(xml: &'a str, tag: &str, name: &str) -> Option<&'a str> {
+    let open = &xml[start_of(xml, tag)?..];
+    let open = &open[..open.find('>')?];
+    let value = &open[open.find(&format!(" {}=", name))? + name.len() + 2..];
+    let quote = value.chars().next()?;
+    let value = &value[1..];
+    Some(&value[..value.find(quote)?])
+}
+
+/// `text` with XML character references and the predefined entities resolved.
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let Some(end) = rest.find(';') else { break };
+        let decoded = match &rest[1..end] {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            entity => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The record for one `<Event>` of `wevtutil qe /f:RenderedXml`, with the event's record id.
+fn entry(xml: &str) -> Option<(u64, Record)> {
+    let system = element(xml, "System")?;
+    let record_id: u64 = element(system, "EventRecordID")?.trim().parse().ok()?;
+    let field = |tag: &str| element(system, tag).map(unescape).filter(|v| !v.is_empty());
+    let provider = attribute(system, "Provider", "Name").map(unescape);
+    let event_id = field("EventID");
+
+    let mut attributes = Attributes::new();
+    let mut push = |key: &str, value: Option<String>| {
+        if let Some(value) = value {
+            attributes.push(format!("windows.eventlog.{}", key), value);
+        }
+    };
+    push("channel", field("Channel"));
+    push("provider", provider.clone());
+    push("event_id", event_id.clone());
+    push("record_id", Some(record_id.to_string()));
+    push(
+        "user",
+        attribute(system, "Security", "UserID").map(unescape),
+    );
+    if let Some(pid) = attribute(system, "Execution", "ProcessID") {
+        attributes.push("process.pid", pid.to_string());
+    }
+    let mut data = Vec::new();
+    if let Some(event_data) = element(xml, "EventData") {
+        let mut rest = event_data;
+        while let Some(start) = start_of(rest, "Data") {
+            rest = &rest[start..];
+            let value = unescape(element(rest, "Data").unwrap_or_default());
+            let name = attribute(rest, "Data", "Name").map_or(data.len().to_string(), unescape);
+            rest = &rest[1..];
+            attributes.push(format!("windows.eventlog.data.{}", name), value.clone());
+            data.push(value);
+        }
+    }
+
+    let message = element(xml, "RenderingInfo")
+        .and_then(|info| element(info, "Message"))
+        .map(|m| unescape(m).trim().to_string())
+        .filter(|m| !m.is_empty());
+    let line = message.unwrap_or_else(|| match data.is_empty() {
+        false => data.join("; "),
+        true => format!(
+            "Event {} from {}",
+            event_id.as_deref().unwrap_or("?"),
+            provider.as_deref().unwrap_or("?")
+        ),
+    });
+    Some((
+        record_id,
+        Record {
+            body: Body::Text(line),
+            attributes,
+            default_severity: None,
+            severity: field("Level")
+                .and_then(|l| l.parse().ok())
+                .and_then(severity),
+            service_name: None,
+            timestamp: attribute(system, "TimeCreated", "SystemTime")
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .map(|t| t.with_timezone(&Utc)),
+            observed: Utc::now(),
+            otlp_path: None,
+            trace: None,
+            resource: field("Computer").map(|host| vec![("host.name".to_string(), host)].into()),
+            checkpoint: None,
+        },
+    ))
+}
+
+/// `backfill-evtx`: hands each event of an exported `.evtx` file to `each`, parsed by the
+/// `evtx` crate on any platform. Returns how many events the file held.
+#[cfg(feature = "evtx")]
+pub fn read_evtx(
+    path: &str,
+    state: &AgentState,
+    mut each: impl FnMut(Record),
+) -> anyhow::Result<u64> {
+    let mut parser = evtx::EvtxParser::from_path(path)
+        .map_err(|e| anyhow::anyhow!("{} is not an event log file: {}", path, e))?;
+    let mut read = 0;
+    for event in parser.records() {
+        read += 1;
+        // Events of a damaged file can fail to parse while the rest still do.
+        let Some((_, mut record)) = event.ok().and_then(|event| entry(&event.data)) else {
+            state.record_drop("eventlog_unparsable");
+            continue;
+        };
+        record.attributes.insert(0, "log.file", path.to_string());
+        each(record);
+    }
+    Ok(read)
+}
+
+/// `backfill-evtx`: hands each event of an exported `.evtx` file to `each`, read through
+/// `wevtutil` in batches by record id. Returns how many events the file held.
+#[cfg(not(feature = "evtx"))]
+pub fn read_evtx(
+    path: &str,
+    state: &AgentState,
+    mut each: impl FnMut(Record),
+) -> anyhow::Result<u64> {
+    let (mut after, mut read) = (0, 0);
+    loop {
+        let xml = wevtutil(
+            path,
+            &[
+                "/lf:true",
+                &format!("/q:*[System[EventRecordID>{}]]", after),
+                "/f:RenderedXml",
+                &format!("/c:{}", BATCH),
+            ],
+        )
+        .map_err(|e| match e.downcast_ref::<std::io::Error>() {
+            Some(io) if io.kind() == std::io::ErrorKind::NotFound => {
+                anyhow::anyhow!(
+                    "backfill-evtx reads .evtx files through wevtutil, which comes with Windows; \
+                     a build with the evtx feature reads them anywhere"
+                )
+            }
+            _ => e,
+        })?;
+        let (from, mut batch) = (after, 0);
+        for event in events(&xml) {
+            batch += 1;
+            let Some((record_id, mut record)) = entry(event) else {
+                state.record_drop("eventlog_unparsable");
+                continue;
+            };
+            after = after.max(record_id);
+            record.attributes.insert(0, "log.file", path.to_string());
+            each(record);
+        }
+        read += batch;
+        // Past the last event, or stuck on events without a record id.
+        if batch < BATCH || after == from {
+            return Ok(read as u64);
+        }
+    }
+}
+
+impl Input for EventLogInput {
+    fn describe(&self) -> String {
+        format!("eventlog:{}", self.name)
+    }
+
+    fn start(&mut self, ctx: &InputContext) -> anyhow::Result<()> {
+        let stats = ctx.register(self);
+        let tx = ctx.queue.sender(&stats.name);
+        let state = ctx.state.clone();
+        let path = statedir::file_in(
+            &ctx.config.state_directory(),
+            &format!("eventlog-{}.json", self.name),
+        );
+        let otlp_path: Option<Arc<str>> = ctx
+            .config
+            .otlp_paths
+            .get(&stats.name)
+            .map(|p| p.as_str().into());
+        if let Some(saved) = fs::read_to_string(&path)
+            .ok()
+            .and_then(|saved| serde_json::from_str(&saved).ok())
+        {
+            *self.reader.bookmarks.lock().unwrap() = saved;
+        }
+        self.reader.bookmark_file = Some(path);
+        let input = self.clone();
+
+        println!(
+            "Reading the event log channels {:?} as input {}",
+            self.channels, stats.name
+        );
+        thread::spawn(move || {
+            let mut retry_at: BTreeMap<String, Instant> = BTreeMap::new();
+            while !input.stop.is_stopped() {
+                let mut full = false;
+                for channel in &input.channels {
+                    if retry_at.get(channel).is_some_and(|at| Instant::now() < *at) {
+                        continue;
+                    }
+                    let after = input.reader.bookmarks.lock().unwrap().get(channel).copied();
+                    let result = match after {
+                        Some(after) => wevtutil(
+                            channel,
+                            &[
+                                &format!("/q:{}", input.query(after)),
+                                "/f:RenderedXml",
+                                &format!("/c:{}", BATCH),
+                            ],
+                        ),
+                        // On first start, only the id of the newest event is taken.
+                        None => wevtutil(channel, &["/f:xml", "/c:1", "/rd:true"]),
+                    };
+                    let xml = match result {
+                        Ok(xml) => xml,
+                        Err(e) => {
+                            state.record_error(format!("{:#}", e));
+                            retry_at.insert(channel.clone(), Instant::now() + RETRY_DELAY);
+                            continue;
+                        }
+                    };
+                    let started = Instant::now();
+                    let mut last = after.unwrap_or(0);
+                    let mut read = 0;
+                    for event in events(&xml) {
+                        read += 1;
+                        let Some((record_id, mut record)) = entry(event) else {
+                            state.record_drop("eventlog_unparsable");
+                            continue;
+                        };
+                        last = last.max(record_id);
+                        if after.is_none() {
+                            continue;
+                        }
+                        stats.record_line();
+                        record.otlp_path = otlp_path.clone();
+                        state.record_stage(Stage::Parse, 1, started.elapsed());
+                        enqueue(&tx, &state, record);
+                    }
+                    full |= read >= BATCH;
+                    if read > 0 || after.is_none() {
+                        input
+                            .reader
+                            .bookmarks
+                            .lock()
+                            .unwrap()
+                            .insert(channel.clone(), last);
+                        if let Err(e) = input.save_bookmarks() {
+                            state
+                                .record_error(format!("Failed to save event log bookmarks: {e:#}"));
+                        }
+                    }
+                }
+                if !full {
+                    thread::sleep(POLL_INTERVAL);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.stop.stop();
+    }
+
+    fn checkpoint(&mut self) -> anyhow::Result<()> {
+        self.save_bookmarks()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rendered_events_become_records() {
+        let xml = r#"<Event xmlns='http://schemas.microsoft.com/win/2004/08/events/event'><System><Provider Name='Service Control Manager' Guid='{555908d1-a6d7-4695-8e1e-26931d2012f4}' EventSourceName='Service Control Manager'/><EventID Qualifiers='16384'>7036</EventID><Version>0</Version><Level>4</Level><Task>0</Task><TimeCreated SystemTime='2024-03-05T10:11:12.1234567Z'/><EventRecordID>48213</EventRecordID><Execution ProcessID='712' ThreadID='5840'/><Channel>System</Channel><Computer>web-01.corp.example</Computer><Security/></System><EventData><Data Name='param1'>Windows Update</Data><Data Name='param2'>running</Data><Data Name='empty'/></EventData><RenderingInfo Culture='en-US'><Message>The Windows Update service entered the &quot;running&quot; state.</Message><Level>Information</Level></RenderingInfo></Event>"#;
+        let (record_id, record) = entry(xml).unwrap();
+        assert_eq!(record_id, 48213);
+        assert_eq!(
+            record.body.text(),
+            r#"The Windows Update service entered the "running" state."#
+        );
+        assert_eq!(record.severity, Some(Severity::INFO));
+        assert_eq!(
+            record.timestamp.unwrap().to_rfc3339(),
+            "2024-03-05T10:11:12.123456700+00:00"
+        );
+        let attribute = |key: &str| {
+            record
+                .attributes
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        };
+        assert_eq!(attribute("windows.eventlog.channel"), Some("System"));
+        assert_eq!(
+            attribute("windows.eventlog.provider"),
+            Some("Service Control Manager")
+        );
+        assert_eq!(attribute("windows.eventlog.event_id"), Some("7036"));
+        assert_eq!(attribute("windows.eventlog.data.param2"), Some("running"));
+        assert_eq!(attribute("windows.eventlog.data.empty"), Some(""));
+        assert_eq!(attribute("windows.eventlog.user"), None);
+        assert_eq!(attribute("process.pid"), Some("712"));
+        assert_eq!(
+            record.resource.as_deref(),
+            Some(&[("host.name".to_string(), "web-01.corp.example".to_string())][..])
+        );
+
+        let input = EventLogInput {
+            name: default_name(),
+            channels: default_channels(),
+            providers: vec!["Service Control Manager".to_string(), "disk".to_string()],
+            level: Some(Level::Warning),
+            stop: StopFlag::default(),
+            reader: Reader::default(),
+        };
+        assert_eq!(
+            input.query(48213),
+            "*[System[EventRecordID>48213 and Provider[@Name='Service Control Manager' or @Name='disk'] and (Level=1 or Level=2 or Level=3)]]"
+        );
+    }
+
+    #[cfg(feature = "evtx")]
+    #[test]
+    fn exported_files_are_read_without_wevtutil() {
+        // Two System channel events sharing one template, as Windows writes them.
+        let path = "testdata/system.evtx";
+        let state = AgentState::default();
+        let mut records = Vec::new();
+        assert_eq!(read_evtx(path, &state, |r| records.push(r)).unwrap(), 2);
+
+        let attributes = |record: &Record| {
+            record
+                .attributes
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(records[0].body.text(), "Windows Update; running");
+        assert_eq!(records[0].severity, Some(Severity::INFO));
+        assert_eq!(
+            records[0].timestamp.unwrap().to_rfc3339(),
+            "2024-03-05T10:11:12.123456+00:00"
+        );
+        assert_eq!(
+            attributes(&records[0]),
+            [
+                "log.file=testdata/system.evtx",
+                "windows.eventlog.channel=System",
+                "windows.eventlog.provider=Service Control Manager",
+                "windows.eventlog.event_id=7036",
+                "windows.eventlog.record_id=1",
+                "process.pid=712",
+                "windows.eventlog.data.param1=Windows Update",
+                "windows.eventlog.data.param2=running",
+            ]
+        );
+        assert_eq!(
+            records[0].resource.as_deref(),
+            Some(&[("host.name".to_string(), "web-01.corp.example".to_string())][..])
+        );
+        assert_eq!(
+            records[1].body.text(),
+            "\\Device\\Harddisk0\\DR0; bad block"
+        );
+        assert_eq!(records[1].severity, Some(Severity::ERROR));
+        assert!(attributes(&records[1]).contains(&"windows.eventlog.record_id=2".to_string()));
+
+        assert!(read_evtx("Cargo.toml", &state, |_| {}).is_err());
+    }
+}
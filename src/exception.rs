@@ -0,0 +1,208 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::processor::Processor;
+use crate::Record;
+
+/// The attributes of the OTel exception semantic conventions.
+pub const TYPE: &str = "exception.type";
+pub const MESSAGE: &str = "exception.message";
+pub const STACKTRACE: &str = "exception.stacktrace";
+
+/// A dotted class name, the last part capitalized, and what follows its colon.
+const JAVA_HEADER: &str = r"(?:^|\s)((?:[a-zA-Z_$][\w$]*\.)+[A-Z][\w$]*)(?::\s*(.*))?$";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    Python,
+    /// Java and the other JVM languages.
+    Java,
+    Go,
+}
+
+/// `[exceptions]`: records holding a stack trace (a multiline record, see `multiline`) get
+/// the `exception.type`, `exception.message` and `exception.stacktrace` attributes and
+/// `event.name = "exception"`, the shape SigNoz lists as an exception.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExceptionsConfig {
+    /// Whose stack traces are recognized; all of them by default.
+    #[serde(default = "default_languages")]
+    pub languages: Vec<Language>,
+}
+
+fn default_languages() -> Vec<Language> {
+    vec![Language::Python, Language::Java, Language::Go]
+}
+
+/// An exception recognized in a record.
+#[derive(Debug, PartialEq)]
+struct Found {
+    kind: String,
+    message: Option<String>,
+    stacktrace: String,
+}
+
+pub struct Exceptions {
+    languages: Vec<Language>,
+    java_header: Regex,
+}
+
+impl Exceptions {
+    pub fn new(config: &ExceptionsConfig) -> Exceptions {
+        Exceptions {
+            languages: config.languages.clone(),
+            java_header: crate::rules::builtin(JAVA_HEADER).unwrap(),
+        }
+    }
+
+    fn recognize(&self, text: &str) -> Option<Found> {
+        self.languages.iter().find_map(|language| match language {
+            Language::Python => python(text),
+            Language::Java => self.java(text),
+            Language::Go => go(text),
+        })
+    }
+
+    /// The line before the first `at ...` frame names the exception, possibly after the
+    /// logger's own prefix; the `Caused by:` sections stay in the stack trace.
+    fn java(&self, text: &str) -> Option<Found> {
+        let lines: Vec<&str> = text.lines().collect();
+        let first_frame = lines.iter().position(|line| {
+            line.starts_with(char::is_whitespace) && line.trim_start().starts_with("at ")
+        })?;
+        let header = lines[..first_frame].last()?;
+        let captures = self.java_header.captures(header)?;
+        let kind = captures.get(1)?;
+        let header_start = text.find(header)? + kind.start();
+        Some(Found {
+            kind: kind.as_str().to_string(),
+            message: captures.get(2).map(|m| m.as_str().trim().to_string()),
+            stacktrace: text[header_start..].to_string(),
+        })
+    }
+}
+
+/// `Traceback (most recent call last):`, the frames indented under it, then
+/// `module.Type: message` on the first line that is not.
+fn python(text: &str) -> Option<Found> {
+    let start = text.find("Traceback (most recent call last):")?;
+    let trace = &text[start..];
+    let last = trace
+        .lines()
+        .skip(1)
+        .find(|line| !line.is_empty() && !line.starts_with(char::is_whitespace))?;
+    let (kind, message) = match last.split_once(':') {
+        Some((kind, message)) => (kind, Some(message.trim().to_string())),
+        None => (last, None),
+    };
+    Some(Found {
+        kind: kind.trim().to_string(),
+        message: message.filter(|m| !m.is_empty()),
+        stacktrace: trace.trim_end().to_string(),
+    })
+}
+
+/// `panic: message`, then the stacks of the goroutines, `goroutine 1 [running]:` first.
+fn go(text: &str) -> Option<Found> {
+    let start = text.find("panic: ")?;
+    let trace = &text[start..];
+    if !trace.lines().any(|line| line.starts_with("goroutine ")) {
+        return None;
+    }
+    let message = trace.lines().next()?["panic: ".len()..]
+        .trim_end_matches(" [recovered]")
+        .to_string();
+    Some(Found {
+        kind: "panic".to_string(),
+        message: Some(message),
+        stacktrace: trace.trim_end().to_string(),
+    })
+}
+
+impl Processor for Exceptions {
+    fn process(&mut self, mut record: Record) -> Option<Record> {
+        let has = |record: &Record, key: &str| record.attributes.iter().any(|(k, _)| k == key);
+        // Set by the input, such as a JSON record carrying them, or recognized here.
+        if !has(&record, TYPE) && !has(&record, STACKTRACE) {
+            let Some(found) = self.recognize(&record.body.text()) else {
+                return Some(record);
+            };
+            record.attributes.push(TYPE.to_string(), found.kind);
+            if let Some(message) = found.message {
+                record.attributes.push(MESSAGE.to_string(), message);
+            }
+            record
+                .attributes
+                .push(STACKTRACE.to_string(), found.stacktrace);
+        }
+        if !has(&record, "event.name") {
+            record.attributes.push("event.name", "exception");
+        }
+        Some(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stack_traces_become_exception_attributes() {
+        let config: ExceptionsConfig = toml::from_str("").unwrap();
+        let mut exceptions = Exceptions::new(&config);
+        let mut attributes_of = |line: &str, attributes: Vec<(&str, &str)>| {
+            let record = Record {
+                attributes: attributes
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                ..Record::new(line)
+            };
+            exceptions.process(record).unwrap().attributes
+        };
+        let get = |attributes: &[(String, String)], key: &str| {
+            attributes
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.clone())
+        };
+
+        let python = "ERROR request failed\nTraceback (most recent call last):\n  File \"app.py\", line 3, in <module>\n    int(\"x\")\nValueError: invalid literal for int() with base 10: 'x'";
+        let attributes = attributes_of(python, vec![]);
+        assert_eq!(get(&attributes, TYPE).unwrap(), "ValueError");
+        assert_eq!(
+            get(&attributes, MESSAGE).unwrap(),
+            "invalid literal for int() with base 10: 'x'"
+        );
+        assert!(get(&attributes, STACKTRACE)
+            .unwrap()
+            .starts_with("Traceback (most recent call last):\n  File"));
+        assert_eq!(get(&attributes, "event.name").unwrap(), "exception");
+
+        let java = "2024-03-05 10:00:00 ERROR [main] c.a.Orders - Failed java.lang.IllegalStateException: no stock\n\tat com.acme.Orders.place(Orders.java:42)\nCaused by: java.io.IOException: closed\n\tat com.acme.Db.read(Db.java:7)";
+        let attributes = attributes_of(java, vec![]);
+        assert_eq!(
+            get(&attributes, TYPE).unwrap(),
+            "java.lang.IllegalStateException"
+        );
+        assert_eq!(get(&attributes, MESSAGE).unwrap(), "no stock");
+        assert!(get(&attributes, STACKTRACE)
+            .unwrap()
+            .starts_with("java.lang.IllegalStateException: no stock\n\tat"));
+
+        let go = "panic: runtime error: index out of range [5] with length 3\n\ngoroutine 1 [running]:\nmain.main()\n\t/app/main.go:8 +0x1d";
+        let attributes = attributes_of(go, vec![]);
+        assert_eq!(get(&attributes, TYPE).unwrap(), "panic");
+        assert_eq!(
+            get(&attributes, MESSAGE).unwrap(),
+            "runtime error: index out of range [5] with length 3"
+        );
+
+        // Fields the input already set are kept, and only the event name is added.
+        let attributes = attributes_of("payment failed", vec![(TYPE, "CardDeclined")]);
+        assert_eq!(get(&attributes, TYPE).unwrap(), "CardDeclined");
+        assert_eq!(get(&attributes, "event.name").unwrap(), "exception");
+        assert!(attributes_of("GET /health 200", vec![]).is_empty());
+    }
+}
@@ -0,0 +1,2254 @@
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::adaptive::AdaptiveRate;
+use crate::clock::Clock;
+use crate::delivery::{Listeners, Outcome};
+use crate::faults::{Fault, FaultInjection};
+use crate::queue::Lanes;
+use crate::spool::{Request, Retention, Spool};
+use crate::stats::{AgentState, Stage};
+use crate::{
+    build_otlp_payload, endpoint_origin, merge_otlp_payloads, severity_of, tag_batch, Config,
+    OtlpLogRecord, Record, Severity,
+};
+
+pub const MAX_RETRIES: usize = 3;
+/// Default `batch_size`.
+pub const DEFAULT_BATCH_SIZE: usize = 512;
+/// Default `flush_interval_ms`.
+pub const DEFAULT_FLUSH_INTERVAL_MS: u64 = 1000;
+/// How often a batch waiting to fill up checks for a flush request.
+const FLUSH_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+/// How long the disk buffer waits after a failed send before it tries again.
+const DRAIN_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Credentials sent with every request to an exporter's endpoint.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Auth {
+    /// HTTP Basic auth, e.g. for a collector behind nginx; the password is read from the
+    /// environment variable `password_env` so it stays out of the config file.
+    Basic {
+        username: String,
+        password_env: String,
+    },
+    /// A bearer token, e.g. for a collector behind an authenticating proxy; read from the
+    /// environment variable `token_env`.
+    Bearer { token_env: String },
+}
+
+impl Auth {
+    /// The `Authorization` header value, failing when the password variable is unset.
+    pub fn header_value(&self) -> anyhow::Result<reqwest::header::HeaderValue> {
+        match self {
+            Auth::Basic {
+                username,
+                password_env,
+            } => {
+                let password = std::env::var(password_env).map_err(|_| {
+                    anyhow::anyhow!(
+                        "Basic auth for {} needs the password in environment variable {}",
+                        username,
+                        password_env
+                    )
+                })?;
+                let credentials = base64::engine::general_purpose::STANDARD
+                    .encode(format!("{}:{}", username, password));
+                let mut value: reqwest::header::HeaderValue =
+                    format!("Basic {}", credentials).parse()?;
+                value.set_sensitive(true);
+                Ok(value)
+            }
+            Auth::Bearer { token_env } => {
+                let token = std::env::var(token_env).map_err(|_| {
+                    anyhow::anyhow!(
+                        "Bearer auth needs the token in environment variable {}",
+                        token_env
+                    )
+                })?;
+                let mut value: reqwest::header::HeaderValue =
+                    format!("Bearer {}", token.trim()).parse()?;
+                value.set_sensitive(true);
+                Ok(value)
+            }
+        }
+    }
+}
+
+/// The value of a configured header: written out, or read from an environment variable
+/// (`{ env = "NAME" }`) so secrets stay out of the config file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum HeaderSource {
+    Value(String),
+    Env { env: String },
+}
+
+impl HeaderSource {
+    fn header_value(&self, name: &str) -> anyhow::Result<reqwest::header::HeaderValue> {
+        match self {
+            HeaderSource::Value(value) => value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid value for header {}", name)),
+            HeaderSource::Env { env } => {
+                let value = std::env::var(env).map_err(|_| {
+                    anyhow::anyhow!(
+                        "Header {} needs its value in environment variable {}",
+                        name,
+                        env
+                    )
+                })?;
+                let mut value: reqwest::header::HeaderValue = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid value for header {} in {}", name, env))?;
+                value.set_sensitive(true);
+                Ok(value)
+            }
+        }
+    }
+}
+
+/// Header carrying a SigNoz Cloud ingestion key.
+pub const INGESTION_KEY_HEADER: &str = "signoz-ingestion-key";
+
+/// What the requests to one destination carry besides their payload.
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    pub auth: Option<Auth>,
+    pub ingestion_key: Option<HeaderSource>,
+    pub headers: BTreeMap<String, HeaderSource>,
+}
+
+impl Credentials {
+    /// The headers for every request, failing when a variable they are read from is unset.
+    pub fn header_map(&self) -> anyhow::Result<reqwest::header::HeaderMap> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        for (name, source) in &self.headers {
+            let key = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| anyhow::anyhow!("Invalid header name {:?}", name))?;
+            headers.insert(key, source.header_value(name)?);
+        }
+        if let Some(ref key) = self.ingestion_key {
+            headers.insert(
+                INGESTION_KEY_HEADER,
+                key.header_value(INGESTION_KEY_HEADER)?,
+            );
+        }
+        if let Some(ref auth) = self.auth {
+            headers.insert(reqwest::header::AUTHORIZATION, auth.header_value()?);
+        }
+        Ok(headers)
+    }
+}
+
+/// User-Agent of the agent's requests unless `user_agent` is set: name, version, OS and
+/// architecture.
+pub fn default_user_agent() -> String {
+    format!(
+        "rust-signoz-agent/{} ({}; {})",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )
+}
+
+/// An HTTP client for the agent's outgoing requests, sending the configured User-Agent
+/// and the headers of `credentials` on every request, with the `[tls]` settings.
+pub fn http_client(
+    config: &Config,
+    credentials: &Credentials,
+    timeout: Duration,
+) -> anyhow::Result<reqwest::blocking::Client> {
+    let headers = credentials.header_map()?;
+    let user_agent = config.user_agent.clone().unwrap_or_else(default_user_agent);
+    let mut builder = reqwest::blocking::Client::builder()
+        .timeout(timeout)
+        .user_agent(user_agent)
+        .default_headers(headers);
+    if let Some(ref tls) = config.tls {
+        builder = tls.apply(builder)?;
+    }
+    Ok(builder.build()?)
+}
+
+/// Request body compression. `Auto` sends a gzip-compressed probe at startup and keeps
+/// gzip only if the collector accepts it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    #[default]
+    Auto,
+    Gzip,
+    None,
+}
+
+/// Body encoding of OTLP/HTTP requests; gRPC always sends protobuf. `Protobuf` needs a
+/// build with the `protobuf` feature.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    #[default]
+    Json,
+    Protobuf,
+}
+
+/// OTLP transport to an endpoint. When unset, endpoints on port 4317, the OTLP/gRPC port,
+/// use gRPC and all others HTTP.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    /// OTLP/HTTP, with JSON bodies unless `encoding` says otherwise.
+    #[default]
+    Http,
+    /// OTLP/gRPC; needs a build with the `grpc` feature.
+    Grpc,
+}
+
+impl Protocol {
+    pub fn for_endpoint(protocol: Option<Protocol>, endpoint: &str) -> Protocol {
+        protocol.unwrap_or_else(|| {
+            if endpoint_origin(endpoint).ends_with(":4317") {
+                Protocol::Grpc
+            } else {
+                Protocol::Http
+            }
+        })
+    }
+}
+
+/// An additional destination that receives every record alongside `endpoint`, or those
+/// of its `environments`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExporterConfig {
+    pub name: String,
+    pub endpoint: String,
+    pub auth: Option<Auth>,
+    pub ingestion_key: Option<HeaderSource>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, HeaderSource>,
+    pub protocol: Option<Protocol>,
+    /// Environments whose records this exporter takes; every record when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub environments: Vec<String>,
+}
+
+impl ExporterConfig {
+    pub fn credentials(&self) -> Credentials {
+        Credentials {
+            auth: self.auth.clone(),
+            ingestion_key: self.ingestion_key.clone(),
+            headers: self.headers.clone(),
+        }
+    }
+}
+
+/// Writes records straight into SigNoz's ClickHouse logs tables over the ClickHouse HTTP
+/// interface, bypassing the collector. Needs a build with the `clickhouse` feature.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClickhouseConfig {
+    /// HTTP interface of the ClickHouse server, e.g. "http://clickhouse:8123".
+    pub url: String,
+    #[serde(default = "default_clickhouse_database")]
+    pub database: String,
+    /// Table layout to write: 1 for `distributed_logs`, 2 for `distributed_logs_v2` and
+    /// its resource table.
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    /// Most records per INSERT.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// Longest a record waits for its batch to fill up.
+    #[serde(default = "default_flush_interval_ms")]
+    pub flush_interval_ms: u64,
+}
+
+fn default_clickhouse_database() -> String {
+    "signoz_logs".to_string()
+}
+
+fn default_schema_version() -> u32 {
+    2
+}
+
+fn default_batch_size() -> usize {
+    10_000
+}
+
+fn default_flush_interval_ms() -> u64 {
+    1000
+}
+
+/// Writes records as Parquet files, partitioned by the hour of their time, to a
+/// directory and optionally on to S3, for analytics pipelines next to SigNoz. Needs a
+/// build with the `parquet` feature.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ParquetConfig {
+    /// Where files are written, and kept until uploaded with `s3`; `<state_dir>/parquet`
+    /// by default.
+    pub dir: Option<String>,
+    pub s3: Option<S3Config>,
+    /// Most records per file.
+    #[serde(default = "default_parquet_batch_size")]
+    pub batch_size: usize,
+    /// Longest a record waits for its file.
+    #[serde(default = "default_parquet_flush_interval_secs")]
+    pub flush_interval_secs: u64,
+}
+
+/// The bucket Parquet files are uploaded to, with the credentials in
+/// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    /// Prepended to the keys, e.g. "logs/".
+    #[serde(default)]
+    pub prefix: String,
+    /// URL of an S3-compatible store, such as MinIO, addressed by path; AWS's endpoint of
+    /// `region` by default.
+    pub endpoint: Option<String>,
+}
+
+fn default_parquet_batch_size() -> usize {
+    100_000
+}
+
+fn default_parquet_flush_interval_secs() -> u64 {
+    60
+}
+
+/// What counts as a delivered record when several exporters are configured.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryPolicy {
+    /// Every exporter must accept the record.
+    #[default]
+    All,
+    /// One accepting exporter is enough.
+    Any,
+}
+
+/// Tracks one record across the exporter group and judges it once every exporter has
+/// reported; records failing the policy are counted as `undelivered` drops. The
+/// outcome goes to `listeners`.
+pub struct Receipt {
+    remaining: AtomicUsize,
+    succeeded: AtomicUsize,
+    exporters: usize,
+    policy: DeliveryPolicy,
+    state: Arc<AgentState>,
+    record: Arc<Record>,
+    listeners: Listeners,
+    /// Exporters that did not take the record, kept only for listeners.
+    failed: Mutex<Vec<String>>,
+}
+
+impl Receipt {
+    pub fn new(
+        exporters: usize,
+        policy: DeliveryPolicy,
+        state: Arc<AgentState>,
+        record: Arc<Record>,
+        listeners: Listeners,
+    ) -> Receipt {
+        Receipt {
+            remaining: AtomicUsize::new(exporters),
+            succeeded: AtomicUsize::new(0),
+            exporters,
+            policy,
+            state,
+            record,
+            listeners,
+            failed: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn report(&self, exporter: &str, ok: bool) {
+        if ok {
+            self.succeeded.fetch_add(1, Ordering::SeqCst);
+        } else if !self.listeners.is_empty() {
+            self.failed.lock().unwrap().push(exporter.to_string());
+        }
+        if self.remaining.fetch_sub(1, Ordering::SeqCst) != 1 {
+            return;
+        }
+        let succeeded = self.succeeded.load(Ordering::SeqCst);
+        let delivered = match self.policy {
+            DeliveryPolicy::All => succeeded == self.exporters,
+            DeliveryPolicy::Any => succeeded > 0,
+        };
+        if !delivered {
+            self.state.record_drop("undelivered");
+        }
+        if !self.listeners.is_empty() {
+            let outcome = if delivered {
+                Outcome::Delivered
+            } else {
+                Outcome::Undelivered(std::mem::take(&mut *self.failed.lock().unwrap()))
+            };
+            self.listeners.notify(&self.record, &outcome);
+        }
+    }
+}
+
+/// Delivery counters for one exporter, readable from the admin socket.
+#[derive(Default)]
+pub struct ExporterStats {
+    pub name: String,
+    pub endpoint: String,
+    pub protocol: Protocol,
+    pub http_encoding: Encoding,
+    pub pending: AtomicUsize,
+    pub sent: AtomicU64,
+    pub failed: AtomicU64,
+    pub consecutive_failures: AtomicU64,
+    /// Attempts made after a request's first one failed.
+    pub retries: AtomicU64,
+    /// Whether requests are currently gzip-compressed; cleared if the collector rejects it.
+    pub gzip: AtomicBool,
+    /// Expiry of the endpoint's TLS certificate as unix seconds; 0 until checked.
+    pub cert_expires_unix: AtomicI64,
+    /// Requests rejected with 401/403, i.e. refused credentials.
+    pub auth_failures: AtomicU64,
+    /// Requests rejected with 404/405: the endpoint's path takes no OTLP logs.
+    pub not_found: AtomicU64,
+    /// Set while the last request was rejected with 404/405, for the health endpoint;
+    /// cleared by the next one delivered.
+    pub no_logs_endpoint: AtomicBool,
+    /// Records waiting in the disk buffer.
+    pub buffered: AtomicUsize,
+    /// Records dropped for room in the exporter's queue.
+    pub queue_full: AtomicU64,
+    /// What the collector said it runs at the startup handshake, if it said.
+    pub server_version: Mutex<Option<String>>,
+    /// Set when a reload replaces the exporter: it delivers what it holds, paused or not,
+    /// and exits.
+    pub closing: AtomicBool,
+}
+
+impl ExporterStats {
+    pub fn encoding(&self) -> &'static str {
+        match (
+            self.protocol,
+            self.http_encoding,
+            self.gzip.load(Ordering::SeqCst),
+        ) {
+            (Protocol::Http, Encoding::Json, true) => "json+gzip",
+            (Protocol::Http, Encoding::Json, false) => "json",
+            (Protocol::Http, Encoding::Protobuf, true) => "protobuf+gzip",
+            (Protocol::Http, Encoding::Protobuf, false) => "protobuf",
+            (Protocol::Grpc, _, true) => "grpc+gzip",
+            (Protocol::Grpc, _, false) => "grpc",
+        }
+    }
+}
+
+/// How an exporter's requests reach its endpoint.
+enum Transport {
+    /// With the gzip level of its requests.
+    Http(reqwest::blocking::Client, Encoding, u32),
+    #[cfg(feature = "grpc")]
+    Grpc(Box<crate::grpc::Client>),
+}
+
+/// Why an endpoint did not take a request; each carries the status, e.g. "HTTP 503".
+enum Rejection {
+    /// The endpoint does not accept compressed requests.
+    Gzip(String),
+    /// Credentials missing or refused.
+    Auth(String),
+    /// Nothing takes OTLP logs at the endpoint's path (HTTP 404/405).
+    NotFound(String),
+    /// Throttled (HTTP 429, gRPC `RESOURCE_EXHAUSTED`).
+    Throttled(String),
+    Status(String),
+    /// The request did not get an answer.
+    Unreachable(String),
+}
+
+impl Transport {
+    fn new(
+        protocol: Protocol,
+        endpoint: &str,
+        config: &Config,
+        credentials: &Credentials,
+        timeout: Duration,
+    ) -> anyhow::Result<Transport> {
+        match protocol {
+            Protocol::Http => Ok(Transport::Http(
+                http_client(config, credentials, timeout)?,
+                config.encoding,
+                config
+                    .compression_level
+                    .unwrap_or(crate::compress::DEFAULT_LEVEL),
+            )),
+            #[cfg(feature = "grpc")]
+            Protocol::Grpc => Ok(Transport::Grpc(Box::new(crate::grpc::Client::new(
+                endpoint,
+                config,
+                credentials,
+                timeout,
+            )?))),
+            #[cfg(not(feature = "grpc"))]
+            Protocol::Grpc => Err(anyhow::anyhow!(
+                "{} needs a build with the grpc feature",
+                endpoint
+            )),
+        }
+    }
+
+    /// Sends one OTLP request. `endpoint` is the URL for HTTP; a gRPC client is bound to
+    /// its endpoint already. Over HTTP, `body` is `payload` encoded ahead of time.
+    fn send(
+        &self,
+        endpoint: &str,
+        payload: &impl Serialize,
+        body: Option<&mut Body>,
+        gzip: bool,
+    ) -> Result<(), Rejection> {
+        match self {
+            Transport::Http(client, encoding, level) => {
+                let posted = match body {
+                    Some(body) => body.post(client, endpoint, gzip, *level),
+                    None => Body::new(payload, *encoding, None)
+                        .and_then(|mut body| body.post(client, endpoint, gzip, *level)),
+                };
+                match posted {
+                    Ok(r) if r.status().is_success() => Ok(()),
+                    Ok(r)
+                        if gzip
+                            && (r.status() == reqwest::StatusCode::UNSUPPORTED_MEDIA_TYPE
+                                || r.status() == reqwest::StatusCode::BAD_REQUEST) =>
+                    {
+                        Err(Rejection::Gzip(format!("HTTP {}", r.status())))
+                    }
+                    Ok(r)
+                        if r.status() == reqwest::StatusCode::UNAUTHORIZED
+                            || r.status() == reqwest::StatusCode::FORBIDDEN =>
+                    {
+                        Err(Rejection::Auth(format!("HTTP {}", r.status())))
+                    }
+                    Ok(r)
+                        if r.status() == reqwest::StatusCode::NOT_FOUND
+                            || r.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED =>
+                    {
+                        Err(Rejection::NotFound(format!("HTTP {}", r.status())))
+                    }
+                    Ok(r) if r.status() == reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                        Err(Rejection::Throttled(format!("HTTP {}", r.status())))
+                    }
+                    Ok(r) => Err(Rejection::Status(format!("HTTP {}", r.status()))),
+                    Err(e) => Err(Rejection::Unreachable(e)),
+                }
+            }
+            #[cfg(feature = "grpc")]
+            Transport::Grpc(client) => client.export(payload, gzip).map_err(|status| {
+                let text = format!("gRPC status {:?}: {}", status.code(), status.message());
+                match status.code() {
+                    tonic::Code::Unimplemented if gzip => Rejection::Gzip(text),
+                    tonic::Code::Unimplemented => Rejection::NotFound(text),
+                    tonic::Code::Unauthenticated | tonic::Code::PermissionDenied => {
+                        Rejection::Auth(text)
+                    }
+                    tonic::Code::Unavailable | tonic::Code::DeadlineExceeded => {
+                        Rejection::Unreachable(text)
+                    }
+                    tonic::Code::ResourceExhausted => Rejection::Throttled(text),
+                    _ => Rejection::Status(text),
+                }
+            }),
+        }
+    }
+
+    /// Sends one OTLP request as `send` does, after `[fault_injection]` had its way with
+    /// it: delayed, failed, throttled, or with `mock`, accepted without being sent.
+    fn send_or_inject(
+        &self,
+        faults: Option<&FaultInjection>,
+        endpoint: &str,
+        payload: &impl Serialize,
+        body: Option<&mut Body>,
+        gzip: bool,
+    ) -> Result<(), Rejection> {
+        let Some(faults) = faults else {
+            return self.send(endpoint, payload, body, gzip);
+        };
+        match faults.inject() {
+            Fault::Failure => Err(Rejection::Unreachable(
+                "injected failure (fault_injection)".to_string(),
+            )),
+            Fault::Throttle => Err(Rejection::Throttled(
+                "HTTP 429 Too Many Requests (fault_injection)".to_string(),
+            )),
+            Fault::None if faults.mock => Ok(()),
+            Fault::None => self.send(endpoint, payload, body, gzip),
+        }
+    }
+}
+
+/// A destination of its own that code embedding the agent registers with
+/// `Agent::exporter`, sent every record alongside the configured exporters. Records are
+/// batched for it as for those (`batch_size`, `flush_interval_ms`), and a batch `export`
+/// fails is tried again up to `MAX_RETRIES` times before it is discarded.
+pub trait Exporter: Send {
+    /// Names the exporter in the agent log and status; unique among the exporters.
+    fn name(&self) -> &str;
+    fn export(&mut self, records: &[&Record]) -> Result<(), String>;
+}
+
+/// An embedding exporter, shared by the handles a reload replaces.
+pub type Custom = Arc<Mutex<Box<dyn Exporter>>>;
+
+/// A destination with its own queue and delivery thread, so a slow or unreachable
+/// endpoint only backs up its own records, until its queue is full.
+pub struct ExporterHandle {
+    pub name: String,
+    pub stats: Arc<ExporterStats>,
+    queue: Arc<Lanes<Submitted>>,
+    worker: thread::JoinHandle<()>,
+}
+
+/// A record queued for an exporter, with the receipt it reports its outcome on.
+pub type Submitted = (Arc<Record>, Arc<Receipt>);
+
+/// The queue of an exporter, holding up to `queue_capacity` records as the input queue
+/// does, shared out among the inputs by their `queue_weights`. Full, it drops records
+/// rather than wait, as `queue_overflow` says but for "block".
+fn lanes(config: &Config) -> Arc<Lanes<Submitted>> {
+    Arc::new(Lanes::new(
+        config.queue_weights.clone(),
+        config
+            .queue_capacity
+            .unwrap_or(crate::queue::DEFAULT_CAPACITY),
+        config.queue_overflow,
+    ))
+}
+
+impl ExporterHandle {
+    pub fn spawn(
+        name: &str,
+        endpoint: &str,
+        protocol: Protocol,
+        credentials: Credentials,
+        config: Arc<Config>,
+        state: Arc<AgentState>,
+        clock: Arc<dyn Clock>,
+    ) -> ExporterHandle {
+        let queue = lanes(&config);
+        let rx = queue.clone();
+        let stats = Arc::new(ExporterStats {
+            name: name.to_string(),
+            endpoint: endpoint.to_string(),
+            protocol,
+            http_encoding: config.encoding,
+            ..Default::default()
+        });
+        let worker_stats = stats.clone();
+        let endpoint = endpoint.to_string();
+        let worker_name = name.to_string();
+        if let Some(ref faults) = config.fault_injection {
+            eprintln!(
+                "Warning: exporter {}: fault_injection is on, {}",
+                name,
+                if faults.mock {
+                    "no request reaches the endpoint"
+                } else {
+                    "some requests fail on purpose"
+                }
+            );
+        }
+
+        let worker = thread::spawn(move || {
+            // The credentials and the protocol were checked when the config was validated.
+            let transport = match Transport::new(
+                protocol,
+                &endpoint,
+                &config,
+                &credentials,
+                Duration::from_secs(10),
+            ) {
+                Ok(transport) => transport,
+                Err(e) => {
+                    state.record_error(format!("Exporter {}: {:#}", worker_name, e));
+                    while let Ok((_, receipt)) = rx.recv() {
+                        worker_stats.failed.fetch_add(1, Ordering::SeqCst);
+                        worker_stats.pending.fetch_sub(1, Ordering::SeqCst);
+                        receipt.report(&worker_name, false);
+                    }
+                    return;
+                }
+            };
+
+            let gzip = match config.compression {
+                Compression::None => false,
+                Compression::Gzip => true,
+                Compression::Auto => accepts_gzip(&transport, &endpoint),
+            };
+            worker_stats.gzip.store(gzip, Ordering::SeqCst);
+            println!(
+                "Exporter {} sends {} to {}",
+                worker_name,
+                worker_stats.encoding(),
+                endpoint
+            );
+
+            let mut spool = config.disk_buffer_dir().and_then(|dir| {
+                let dir = Path::new(&dir).join(file_name_of(&worker_name));
+                let max_bytes = config.disk_buffer.as_ref().map_or(0, |b| b.max_bytes);
+                // The retention was checked when the config was validated.
+                let retention = config
+                    .disk_buffer
+                    .as_ref()
+                    .and_then(|b| Retention::new(&b.retention).ok())
+                    .unwrap_or_default();
+                let encryption = config
+                    .disk_buffer
+                    .as_ref()
+                    .and_then(|b| b.encryption.as_ref());
+                let opened = Spool::open(&dir, max_bytes, retention).and_then(|spool| {
+                    // Not buffering at all rather than in the clear without the key.
+                    match encryption {
+                        Some(encryption) => Ok(spool.encrypted(Arc::new(encryption.cipher()?))),
+                        None => Ok(spool),
+                    }
+                });
+                match opened {
+                    Ok(spool) => {
+                        if !spool.is_empty() {
+                            println!(
+                                "Exporter {} has {} buffered logs to send from {}",
+                                worker_name,
+                                spool.records(),
+                                dir.display()
+                            );
+                        }
+                        worker_stats
+                            .buffered
+                            .store(spool.records(), Ordering::SeqCst);
+                        Some(spool)
+                    }
+                    Err(e) => {
+                        state.record_error(format!(
+                            "Exporter {}: failed to open disk buffer {}, not buffering: {:#}",
+                            worker_name,
+                            dir.display(),
+                            e
+                        ));
+                        None
+                    }
+                }
+            });
+            let mut next_drain = Instant::now();
+            let sender = Arc::new(BatchSender {
+                name: worker_name.clone(),
+                endpoint: endpoint.clone(),
+                protocol,
+                transport,
+                config: config.clone(),
+                stats: worker_stats.clone(),
+                state: state.clone(),
+                clock: clock.clone(),
+                delivered: AtomicU64::new(0),
+                adaptive: config.adaptive_rate.as_ref().map(|adaptive| {
+                    Arc::new(AdaptiveRate::new(
+                        adaptive,
+                        &worker_name,
+                        &config.state_directory(),
+                    ))
+                }),
+                // gRPC requests are compressed by the client as they are sent.
+                pool: config
+                    .compression_workers
+                    .filter(|_| gzip && protocol == Protocol::Http)
+                    .map(|workers| {
+                        crate::compress::Pool::new(
+                            &worker_name,
+                            workers,
+                            config
+                                .compression_level
+                                .unwrap_or(crate::compress::DEFAULT_LEVEL),
+                        )
+                    }),
+            });
+            // Failed requests are appended to the disk buffer and sent from it in order,
+            // so an exporter with one sends a request at a time.
+            let in_flight_limit = match spool {
+                Some(_) => 1,
+                None => config.max_in_flight_requests.unwrap_or(1).max(1),
+            };
+            // Batches are handed to the requests in flight as they become free, which
+            // holds the collection of the next one back while all of them are busy. With
+            // a compression pool a single request is sent from its own thread too, so the
+            // next batch is compressed meanwhile.
+            let threaded = in_flight_limit > 1 || (spool.is_none() && sender.pool.is_some());
+            let (requests, in_flight) = if threaded {
+                let (tx, rx) = mpsc::sync_channel::<PreparedBatch>(0);
+                let rx = Arc::new(Mutex::new(rx));
+                let in_flight: Vec<_> = (0..in_flight_limit)
+                    .map(|_| {
+                        let (sender, rx) = (sender.clone(), rx.clone());
+                        thread::spawn(move || loop {
+                            let Ok(batch) = rx.lock().unwrap().recv() else {
+                                break;
+                            };
+                            sender.send(batch, None);
+                        })
+                    })
+                    .collect();
+                (Some(tx), in_flight)
+            } else {
+                (None, Vec::new())
+            };
+
+            let batch_size = config.batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1);
+            let flush_interval = Duration::from_millis(
+                config
+                    .flush_interval_ms
+                    .unwrap_or(DEFAULT_FLUSH_INTERVAL_MS)
+                    .max(1),
+            );
+            loop {
+                let paused = state.exports_paused.load(Ordering::SeqCst)
+                    && !worker_stats.closing.load(Ordering::SeqCst);
+                if paused && spool.is_none() {
+                    // Records wait in the queue.
+                    thread::sleep(FLUSH_CHECK_INTERVAL);
+                    continue;
+                }
+                if let Some(ref mut spool) = spool {
+                    if let Err(e) = spool.expire(clock.now(), &state) {
+                        state.record_error(format!(
+                            "Exporter {}: failed to drop expired disk buffer records: {:#}",
+                            worker_name, e
+                        ));
+                    }
+                    worker_stats
+                        .buffered
+                        .store(spool.records(), Ordering::SeqCst);
+                    // A flush does not wait for the retry interval.
+                    let flushing = state.flushing.load(Ordering::SeqCst);
+                    let due =
+                        !paused && !spool.is_empty() && (flushing || Instant::now() >= next_drain);
+                    if due
+                        && !drain(
+                            spool,
+                            &sender.transport,
+                            sender.config.fault_injection.as_ref(),
+                            sender.adaptive.as_deref(),
+                            &worker_name,
+                            &worker_stats,
+                            &state,
+                        )
+                    {
+                        next_drain = Instant::now() + DRAIN_RETRY_INTERVAL;
+                    }
+                }
+                // While requests wait on disk, the queue is only waited on until they are
+                // due again, until exporting resumes or until a flush is asked for.
+                let first = if paused || spool.as_ref().is_some_and(|s| !s.is_empty()) {
+                    let wait = if paused {
+                        FLUSH_CHECK_INTERVAL
+                    } else {
+                        next_drain
+                            .saturating_duration_since(Instant::now())
+                            .min(FLUSH_CHECK_INTERVAL)
+                    };
+                    match rx.recv_timeout(wait) {
+                        Ok(first) => first,
+                        Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                        Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                    }
+                } else {
+                    match rx.recv() {
+                        Ok(first) => first,
+                        Err(_) => break,
+                    }
+                };
+                let batch = collect_batch(
+                    first,
+                    &rx,
+                    batch_size,
+                    flush_interval,
+                    &state,
+                    clock.as_ref(),
+                );
+                match requests {
+                    Some(ref requests) => {
+                        requests.send(sender.prepare(batch)).ok();
+                    }
+                    None => {
+                        if sender.send(sender.prepare(batch), spool.as_mut()) {
+                            next_drain = Instant::now() + DRAIN_RETRY_INTERVAL;
+                        }
+                    }
+                }
+            }
+            drop(requests);
+            for request in in_flight {
+                request.join().ok();
+            }
+        });
+
+        ExporterHandle {
+            name: name.to_string(),
+            stats,
+            queue,
+            worker,
+        }
+    }
+
+    /// The ClickHouse exporter, named "clickhouse", which inserts records in batches.
+    #[cfg(feature = "clickhouse")]
+    pub fn spawn_clickhouse(
+        clickhouse: ClickhouseConfig,
+        config: Arc<Config>,
+        state: Arc<AgentState>,
+        clock: Arc<dyn Clock>,
+    ) -> ExporterHandle {
+        let queue = lanes(&config);
+        let rx = queue.clone();
+        let stats = Arc::new(ExporterStats {
+            name: "clickhouse".to_string(),
+            endpoint: clickhouse.url.clone(),
+            ..Default::default()
+        });
+        let worker_stats = stats.clone();
+        let worker = thread::spawn(move || {
+            crate::clickhouse::run(clickhouse, rx, config, state, worker_stats, clock)
+        });
+        ExporterHandle {
+            name: "clickhouse".to_string(),
+            stats,
+            queue,
+            worker,
+        }
+    }
+
+    /// The Parquet exporter, named "parquet", which writes a file per batch.
+    #[cfg(feature = "parquet")]
+    pub fn spawn_parquet(
+        parquet: ParquetConfig,
+        config: Arc<Config>,
+        state: Arc<AgentState>,
+        clock: Arc<dyn Clock>,
+    ) -> ExporterHandle {
+        let queue = lanes(&config);
+        let rx = queue.clone();
+        let stats = Arc::new(ExporterStats {
+            name: crate::parquet::NAME.to_string(),
+            endpoint: crate::parquet::destination(&parquet, &config),
+            ..Default::default()
+        });
+        let worker_stats = stats.clone();
+        let worker = thread::spawn(move || {
+            crate::parquet::run(parquet, rx, config, state, worker_stats, clock)
+        });
+        ExporterHandle {
+            name: crate::parquet::NAME.to_string(),
+            stats,
+            queue,
+            worker,
+        }
+    }
+
+    /// An exporter registered by embedding code.
+    pub fn spawn_custom(
+        exporter: Custom,
+        config: Arc<Config>,
+        state: Arc<AgentState>,
+        clock: Arc<dyn Clock>,
+    ) -> ExporterHandle {
+        let name = exporter.lock().unwrap().name().to_string();
+        let queue = lanes(&config);
+        let rx = queue.clone();
+        let stats = Arc::new(ExporterStats {
+            name: name.clone(),
+            ..Default::default()
+        });
+        let worker_stats = stats.clone();
+        let worker = thread::spawn(move || {
+            let batch_size = config.batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1);
+            let flush_interval = Duration::from_millis(
+                config
+                    .flush_interval_ms
+                    .unwrap_or(DEFAULT_FLUSH_INTERVAL_MS)
+                    .max(1),
+            );
+            run_worker(
+                rx,
+                exporter,
+                (batch_size, flush_interval),
+                &state,
+                &worker_stats,
+                clock.as_ref(),
+            )
+        });
+        ExporterHandle {
+            name,
+            stats,
+            queue,
+            worker,
+        }
+    }
+
+    /// Queues a record for the exporter. A full queue drops one for room instead of
+    /// waiting, as that would hold the dispatcher, and so every other exporter, back
+    /// behind this one; the record dropped is reported as not delivered by it.
+    pub fn submit(&self, input: &Arc<str>, entry: Arc<Record>, receipt: Arc<Receipt>) {
+        self.stats.pending.fetch_add(1, Ordering::SeqCst);
+        match self.queue.push(input, (entry, receipt)) {
+            Ok(None) => {}
+            Ok(Some((_, receipt))) => {
+                self.stats.pending.fetch_sub(1, Ordering::SeqCst);
+                if self.stats.queue_full.fetch_add(1, Ordering::SeqCst) == 0 {
+                    eprintln!(
+                        "Exporter {} has a full queue; dropping records for it until it catches up",
+                        self.name
+                    );
+                }
+                receipt.report(&self.name, false);
+            }
+            Err((_, receipt)) => {
+                self.stats.pending.fetch_sub(1, Ordering::SeqCst);
+                receipt.report(&self.name, false);
+                eprintln!("Exporter {} is not accepting records", self.name);
+            }
+        }
+    }
+
+    /// Stops taking records and waits until the ones already submitted are delivered,
+    /// buffered or given up on.
+    pub fn close(self) {
+        self.stats.closing.store(true, Ordering::SeqCst);
+        self.queue.close();
+        if self.worker.join().is_err() {
+            eprintln!("Exporter {} stopped with a panic", self.name);
+        }
+    }
+}
+
+/// Sends an empty OTLP request to check that the endpoint is reachable and accepts logs.
+pub fn probe(config: &Config) -> anyhow::Result<()> {
+    let endpoint = &config.endpoint;
+    let transport = Transport::new(
+        Protocol::for_endpoint(config.protocol, endpoint),
+        endpoint,
+        config,
+        &config.credentials(),
+        Duration::from_secs(5),
+    )?;
+    match transport.send(
+        endpoint,
+        &serde_json::json!({ "resourceLogs": [] }),
+        None,
+        false,
+    ) {
+        Ok(()) => Ok(()),
+        Err(Rejection::Unreachable(e)) => {
+            Err(anyhow::anyhow!("Failed to reach {}: {}", endpoint, e))
+        }
+        Err(Rejection::NotFound(status)) => Err(anyhow::anyhow!(
+            "{} rejected the probe with {}; {}",
+            endpoint,
+            status,
+            logs_path_hint(endpoint)
+        )),
+        Err(
+            Rejection::Gzip(status)
+            | Rejection::Auth(status)
+            | Rejection::Throttled(status)
+            | Rejection::Status(status),
+        ) => Err(anyhow::anyhow!(
+            "{} rejected the probe with {}",
+            endpoint,
+            status
+        )),
+    }
+}
+
+/// `rust-signoz-agent test-connection`: sends one test record to each exporter and
+/// reports what its endpoint answered. Fails unless every endpoint accepted it.
+pub fn test_connection(config: &Config) -> anyhow::Result<()> {
+    let entry = Record::new("rust-signoz-agent test-connection");
+    let payload = build_otlp_payload(&entry, severity_of(&entry, config), config);
+    let mut endpoints = vec![(
+        "signoz",
+        config.endpoint.as_str(),
+        config.protocol,
+        config.credentials(),
+    )];
+    for extra in &config.exporters {
+        endpoints.push((
+            extra.name.as_str(),
+            extra.endpoint.as_str(),
+            extra.protocol,
+            extra.credentials(),
+        ));
+    }
+    let mut failed = 0;
+    for (name, endpoint, protocol, credentials) in endpoints {
+        let answer = Transport::new(
+            Protocol::for_endpoint(protocol, endpoint),
+            endpoint,
+            config,
+            &credentials,
+            Duration::from_secs(5),
+        )
+        .map_err(|e| format!("{:#}", e))
+        .and_then(|transport| {
+            transport
+                .send(endpoint, &payload, None, false)
+                .map_err(|rejection| match rejection {
+                    Rejection::Unreachable(e) => format!("unreachable: {}", e),
+                    Rejection::NotFound(status) => {
+                        format!("rejected with {}; {}", status, logs_path_hint(endpoint))
+                    }
+                    Rejection::Gzip(status)
+                    | Rejection::Auth(status)
+                    | Rejection::Throttled(status)
+                    | Rejection::Status(status) => {
+                        format!("rejected with {}", status)
+                    }
+                })
+        });
+        match answer {
+            Ok(()) => println!("{} ({}): accepted the test record", name, endpoint),
+            Err(e) => {
+                failed += 1;
+                println!("{} ({}): {}", name, endpoint, e);
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(
+            anyhow::anyhow!("{} endpoints did not accept the test record", failed)
+                .context(crate::failure::Failure::EndpointUnreachable),
+        );
+    }
+    Ok(())
+}
+
+/// What to try for an endpoint answered with 404/405: the logs path next to the path of
+/// another signal, or `/v1/logs` under the configured path.
+pub fn logs_path_hint(endpoint: &str) -> String {
+    let origin = endpoint_origin(endpoint);
+    let path = endpoint[origin.len()..]
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('/');
+    let prefix = ["/v1/logs", "/v1/traces", "/v1/metrics"]
+        .iter()
+        .find_map(|signal| path.strip_suffix(signal))
+        .unwrap_or(path);
+    if path.ends_with("/v1/logs") {
+        return format!(
+            "check that the collector at {} has an OTLP/HTTP receiver with a logs pipeline",
+            origin
+        );
+    }
+    format!(
+        "OTLP/HTTP collectors take logs on /v1/logs; try {}{}/v1/logs",
+        origin, prefix
+    )
+}
+
+/// Whether the collector accepts a gzip-compressed request.
+fn accepts_gzip(transport: &Transport, endpoint: &str) -> bool {
+    transport
+        .send(
+            endpoint,
+            &serde_json::json!({ "resourceLogs": [] }),
+            None,
+            true,
+        )
+        .is_ok()
+}
+
+/// An OTLP/HTTP request body, encoded once for all the attempts to send it.
+struct Body {
+    content_type: &'static str,
+    plain: Vec<u8>,
+    /// The body being compressed on the exporter's pool.
+    compressing: Option<crate::compress::Compressing>,
+    gzipped: Option<Vec<u8>>,
+}
+
+impl Body {
+    /// Encodes `payload`, and with a `pool` starts compressing it there.
+    fn new<T: Serialize>(
+        payload: &T,
+        encoding: Encoding,
+        pool: Option<&crate::compress::Pool>,
+    ) -> Result<Body, String> {
+        let (plain, content_type) = match encoding {
+            Encoding::Json => (
+                serde_json::to_vec(payload)
+                    .map_err(|e| format!("Failed to encode payload: {e}"))?,
+                "application/json",
+            ),
+            #[cfg(feature = "protobuf")]
+            Encoding::Protobuf => (crate::protobuf::encode(payload)?, "application/x-protobuf"),
+            // The config was refused when it was validated.
+            #[cfg(not(feature = "protobuf"))]
+            Encoding::Protobuf => {
+                return Err("protobuf needs a build with the protobuf feature".into())
+            }
+        };
+        Ok(Body {
+            content_type,
+            compressing: pool.map(|pool| pool.start(plain.clone())),
+            plain,
+            gzipped: None,
+        })
+    }
+
+    /// The gzipped body, compressed at `level` here unless the pool already did.
+    fn gzipped(&mut self, level: u32) -> Result<Vec<u8>, String> {
+        if self.gzipped.is_none() {
+            self.gzipped = Some(match self.compressing.take() {
+                Some(compressing) => compressing.wait()?,
+                None => crate::compress::gzip(&self.plain, level)
+                    .map_err(|e| format!("Failed to compress payload: {e}"))?,
+            });
+        }
+        Ok(self.gzipped.clone().unwrap_or_default())
+    }
+
+    fn post(
+        &mut self,
+        client: &reqwest::blocking::Client,
+        endpoint: &str,
+        gzip: bool,
+        level: u32,
+    ) -> Result<reqwest::blocking::Response, String> {
+        let request = client
+            .post(endpoint)
+            .header(reqwest::header::CONTENT_TYPE, self.content_type);
+        let request = if gzip {
+            request
+                .header(reqwest::header::CONTENT_ENCODING, "gzip")
+                .body(self.gzipped(level)?)
+        } else {
+            request.body(self.plain.clone())
+        };
+        request.send().map_err(|e| e.to_string())
+    }
+}
+
+/// What sending a batch takes, shared by the requests an exporter has in flight.
+struct BatchSender {
+    name: String,
+    endpoint: String,
+    protocol: Protocol,
+    transport: Transport,
+    config: Arc<Config>,
+    stats: Arc<ExporterStats>,
+    state: Arc<AgentState>,
+    clock: Arc<dyn Clock>,
+    /// Compresses request bodies with `compression_workers` set.
+    pool: Option<crate::compress::Pool>,
+    /// Paces requests with `[adaptive_rate]` set.
+    adaptive: Option<Arc<AdaptiveRate>>,
+    /// Records delivered, of which every `success_log_every`th is printed.
+    delivered: AtomicU64,
+}
+
+/// Records with their own `otlp_path` go to another URL, in their own request. gRPC has
+/// a single service path, so it is ignored there.
+fn path_of(entry: &Record, protocol: Protocol) -> Option<&str> {
+    entry
+        .otlp_path
+        .as_deref()
+        .filter(|_| protocol == Protocol::Http)
+}
+
+/// A batch with its requests built, one per OTLP path.
+struct PreparedBatch {
+    records: Vec<Submitted>,
+    requests: Vec<Prepared>,
+}
+
+/// One OTLP request of a batch, built before it is sent.
+struct Prepared {
+    path: Option<String>,
+    endpoint: String,
+    entries: Vec<Arc<Record>>,
+    severities: Vec<Severity>,
+    payload: OtlpLogRecord,
+    /// " in batch <id>" with a `batch_id_attribute`.
+    batch: String,
+    body: Option<Body>,
+}
+
+impl BatchSender {
+    /// Builds the requests of `batch`. Over HTTP their bodies are encoded, and with a
+    /// pool compressed on it while the batch before is still being sent.
+    fn prepare(&self, batch: Vec<Submitted>) -> PreparedBatch {
+        let mut by_path: Vec<(Option<String>, Vec<Arc<Record>>)> = Vec::new();
+        for (entry, _) in &batch {
+            let path = path_of(entry, self.protocol);
+            match by_path.iter_mut().find(|(p, _)| p.as_deref() == path) {
+                Some((_, entries)) => entries.push(entry.clone()),
+                None => by_path.push((path.map(str::to_string), vec![entry.clone()])),
+            }
+        }
+        let pool = self
+            .pool
+            .as_ref()
+            .filter(|_| self.stats.gzip.load(Ordering::SeqCst));
+        let requests = by_path
+            .into_iter()
+            .map(|(path, entries)| {
+                let endpoint = match path {
+                    Some(ref path) => format!("{}{}", endpoint_origin(&self.endpoint), path),
+                    None => self.endpoint.clone(),
+                };
+                let mut severities = Vec::with_capacity(entries.len());
+                let mut payloads = Vec::with_capacity(entries.len());
+                for entry in &entries {
+                    let severity = severity_of(entry, &self.config);
+                    severities.push(severity);
+                    payloads.push(build_otlp_payload(entry, severity, &self.config));
+                }
+                let mut payload = merge_otlp_payloads(payloads);
+                let batch = match self.config.batch_id_attribute {
+                    Some(ref key) => {
+                        let id = uuid::Uuid::new_v4().to_string();
+                        tag_batch(&mut payload, key, &id);
+                        format!(" in batch {}", id)
+                    }
+                    None => String::new(),
+                };
+                // A body that fails to encode fails again, and is reported, when sent.
+                let body = match self.transport {
+                    Transport::Http(_, encoding, _) => Body::new(&payload, encoding, pool).ok(),
+                    #[cfg(feature = "grpc")]
+                    Transport::Grpc(_) => None,
+                };
+                Prepared {
+                    path,
+                    endpoint,
+                    entries,
+                    severities,
+                    payload,
+                    batch,
+                    body,
+                }
+            })
+            .collect();
+        PreparedBatch {
+            records: batch,
+            requests,
+        }
+    }
+
+    /// Sends the requests of `batch` and reports them to the receipts of its records.
+    /// True if a request was put in the disk buffer.
+    fn send(&self, batch: PreparedBatch, mut spool: Option<&mut Spool>) -> bool {
+        let PreparedBatch {
+            records: batch,
+            requests,
+        } = batch;
+        let mut delivered: Vec<(Option<String>, bool)> = Vec::new();
+        let (mut failing, mut buffered) = (false, false);
+        for mut request in requests {
+            let auth_failures = self.stats.auth_failures.load(Ordering::SeqCst);
+            let not_found = self.stats.not_found.load(Ordering::SeqCst);
+            if let Some(ref adaptive) = self.adaptive {
+                thread::sleep(adaptive.reserve(request.entries.len(), Instant::now()));
+            }
+            let started = Instant::now();
+            let result = send_to_signoz(self, &mut request, spool.as_deref_mut());
+            let (endpoint, entries) = (&request.endpoint, &request.entries);
+            self.state
+                .record_stage(Stage::Export, entries.len() as u64, started.elapsed());
+            if auth_failures == 0 && self.stats.auth_failures.load(Ordering::SeqCst) > 0 {
+                self.state.record_error(format!(
+                    "Warning: {} rejected the credentials of exporter {}; check its ingestion key",
+                    endpoint, self.name
+                ));
+            }
+            if not_found == 0 && self.stats.not_found.load(Ordering::SeqCst) > 0 {
+                self.state.record_error(format!(
+                    "Warning: {} has nothing taking logs for exporter {}; {}",
+                    endpoint,
+                    self.name,
+                    logs_path_hint(endpoint)
+                ));
+            }
+            delivered.push((request.path.clone(), result.is_ok()));
+            match result {
+                Ok(Sent::Delivered) => {
+                    self.stats
+                        .sent
+                        .fetch_add(entries.len() as u64, Ordering::SeqCst);
+                }
+                Ok(Sent::Queued) => {}
+                Ok(Sent::Buffered) => {
+                    failing = true;
+                    buffered = true;
+                }
+                Err(e) => {
+                    failing = true;
+                    self.stats
+                        .failed
+                        .fetch_add(entries.len() as u64, Ordering::SeqCst);
+                    self.state
+                        .record_error(format!("Exporter {}: {}", self.name, e));
+                }
+            }
+        }
+        if failing {
+            self.stats
+                .consecutive_failures
+                .fetch_add(1, Ordering::SeqCst);
+        } else {
+            self.stats.consecutive_failures.store(0, Ordering::SeqCst);
+        }
+        for (entry, receipt) in &batch {
+            let path = path_of(entry, self.protocol);
+            receipt.report(
+                &self.name,
+                delivered.iter().any(|(p, ok)| p.as_deref() == path && *ok),
+            );
+        }
+        self.stats.pending.fetch_sub(batch.len(), Ordering::SeqCst);
+        buffered
+    }
+}
+
+/// The records queued for an exporter, up to `batch_size` of them: `first` and whatever
+/// arrives within `flush_interval` of it, as `clock` tells. A flush or shutdown stops the
+/// wait early.
+fn collect_batch(
+    first: Submitted,
+    rx: &Lanes<Submitted>,
+    batch_size: usize,
+    flush_interval: Duration,
+    state: &AgentState,
+    clock: &dyn Clock,
+) -> Vec<Submitted> {
+    let deadline = clock.now() + chrono::Duration::from_std(flush_interval).unwrap_or_default();
+    let mut batch = vec![first];
+    while batch.len() < batch_size {
+        let left = (deadline - clock.now()).to_std().unwrap_or_default();
+        let wait = if state.flushing.load(Ordering::SeqCst) {
+            Duration::ZERO
+        } else {
+            left.min(FLUSH_CHECK_INTERVAL)
+        };
+        match rx.recv_timeout(wait) {
+            Ok(item) => batch.push(item),
+            Err(mpsc::RecvTimeoutError::Timeout) if !wait.is_zero() && clock.now() < deadline => {}
+            Err(_) => break,
+        }
+    }
+    batch
+}
+
+/// Where the exporters without a disk buffer or requests in flight of their own write
+/// their batches: ClickHouse, Parquet and those of embedding code.
+pub trait BatchSink {
+    /// Writes `records` at once, in one attempt; the error says what failed.
+    fn write(&mut self, records: &[&Record]) -> Result<(), String>;
+    /// Called after each batch, written or given up on.
+    fn written(&mut self) {}
+}
+
+impl BatchSink for Custom {
+    fn write(&mut self, records: &[&Record]) -> Result<(), String> {
+        self.lock()
+            .unwrap()
+            .export(records)
+            .map_err(|e| format!("Failed to export {} logs: {}", records.len(), e))
+    }
+}
+
+/// Worker loop of an exporter writing to `sink`: takes batches from `rx` as
+/// `collect_batch` does and writes each with `send_with_retry`, discarding one every
+/// attempt failed for. Not buffered on disk, so a paused batch waits here.
+pub fn run_worker(
+    rx: Arc<Lanes<Submitted>>,
+    mut sink: impl BatchSink,
+    (batch_size, flush_interval): (usize, Duration),
+    state: &AgentState,
+    stats: &ExporterStats,
+    clock: &dyn Clock,
+) {
+    while let Ok(first) = rx.recv() {
+        let batch = collect_batch(first, &rx, batch_size, flush_interval, state, clock);
+        while state.exports_paused.load(Ordering::SeqCst) && !stats.closing.load(Ordering::SeqCst) {
+            thread::sleep(FLUSH_CHECK_INTERVAL);
+        }
+        let records: Vec<&Record> = batch.iter().map(|(entry, _)| &**entry).collect();
+        let started = Instant::now();
+        let result = send_with_retry(clock, |attempt| {
+            if attempt > 1 {
+                stats.retries.fetch_add(1, Ordering::SeqCst);
+            }
+            sink.write(&records)
+                .map_err(|e| format!("{} (attempt {}/{})", e, attempt, MAX_RETRIES))
+        });
+        state.record_stage(Stage::Export, batch.len() as u64, started.elapsed());
+
+        let count = batch.len();
+        for (_, receipt) in &batch {
+            receipt.report(&stats.name, result.is_ok());
+        }
+        match result {
+            Ok(()) => {
+                stats.sent.fetch_add(count as u64, Ordering::SeqCst);
+                stats.consecutive_failures.store(0, Ordering::SeqCst);
+            }
+            Err(e) => {
+                stats.failed.fetch_add(count as u64, Ordering::SeqCst);
+                stats.consecutive_failures.fetch_add(1, Ordering::SeqCst);
+                state.record_error(format!("Exporter {}: {}, discarding", stats.name, e));
+            }
+        }
+        stats.pending.fetch_sub(count, Ordering::SeqCst);
+        sink.written();
+    }
+}
+
+/// What became of a request `send_to_signoz` took.
+enum Sent {
+    Delivered,
+    /// Written to the disk buffer behind the requests already waiting there, or while
+    /// exporting is paused.
+    Queued,
+    /// Written to the disk buffer after every attempt failed.
+    Buffered,
+}
+
+/// Sends `request` in one attempt or more. With a disk buffer, a request that cannot be
+/// delivered is kept in it instead of discarded.
+fn send_to_signoz(
+    sender: &BatchSender,
+    request: &mut Prepared,
+    mut spool: Option<&mut Spool>,
+) -> Result<Sent, String> {
+    let (transport, config, stats, state) = (
+        &sender.transport,
+        &*sender.config,
+        &*sender.stats,
+        &*sender.state,
+    );
+    let (clock, adaptive) = (sender.clock.as_ref(), sender.adaptive.as_deref());
+    let Prepared {
+        endpoint,
+        entries,
+        severities,
+        payload,
+        batch,
+        body,
+        ..
+    } = request;
+    let (endpoint, entries, payload) = (endpoint.as_str(), &*entries, &*payload);
+    if config.self_check {
+        crate::selfcheck::report(payload, state);
+    }
+    if state.debug_logging.load(Ordering::SeqCst) {
+        println!(
+            "Request to {}{}: {}",
+            endpoint,
+            batch,
+            serde_json::to_string(payload).unwrap_or_default()
+        );
+    }
+    let buffer = |spool: &mut Spool, sent: Sent| {
+        let request = Request {
+            endpoint: endpoint.to_string(),
+            payload,
+        };
+        let result = spool.push(&request, entries.len(), state);
+        stats.buffered.store(spool.records(), Ordering::SeqCst);
+        result
+            .map(|()| sent)
+            .map_err(|e| format!("Failed to buffer {} logs on disk: {:#}", entries.len(), e))
+    };
+    // Later records wait behind buffered ones, so they arrive in order.
+    let paused = state.exports_paused.load(Ordering::SeqCst);
+    if let Some(spool) = spool.as_deref_mut().filter(|s| paused || !s.is_empty()) {
+        return buffer(spool, Sent::Queued);
+    }
+
+    let result = retry(clock, |attempt| {
+        if attempt > 1 {
+            stats.retries.fetch_add(1, Ordering::SeqCst);
+        }
+        let gzip = stats.gzip.load(Ordering::SeqCst);
+        let faults = config.fault_injection.as_ref();
+        match transport.send_or_inject(faults, endpoint, payload, body.as_mut(), gzip) {
+            Ok(()) => {
+                if let Some(adaptive) = adaptive {
+                    adaptive.delivered(entries.len(), Instant::now());
+                }
+                stats.no_logs_endpoint.store(false, Ordering::SeqCst);
+                Ok(())
+            }
+            Err(Rejection::Gzip(status)) => {
+                stats.gzip.store(false, Ordering::SeqCst);
+                Err(Failed::Retry(format!(
+                    "SigNoz rejected gzip with {}, falling back to uncompressed (attempt {}/{})",
+                    status, attempt, MAX_RETRIES
+                )))
+            }
+            Err(Rejection::Auth(status)) => {
+                stats.auth_failures.fetch_add(1, Ordering::SeqCst);
+                Err(Failed::Retry(format!(
+                    "SigNoz rejected the credentials: {} (attempt {}/{})",
+                    status, attempt, MAX_RETRIES
+                )))
+            }
+            // The same path gets the same answer on the next attempt.
+            Err(Rejection::NotFound(status)) => {
+                stats.not_found.fetch_add(1, Ordering::SeqCst);
+                stats.no_logs_endpoint.store(true, Ordering::SeqCst);
+                Err(Failed::Permanent(format!(
+                    "SigNoz has no logs endpoint at {}: {}; {}",
+                    endpoint,
+                    status,
+                    logs_path_hint(endpoint)
+                )))
+            }
+            Err(Rejection::Throttled(status)) => {
+                if let Some(adaptive) = adaptive {
+                    adaptive.throttled(Instant::now());
+                }
+                Err(Failed::Retry(format!(
+                    "SigNoz is throttling: {} (attempt {}/{})",
+                    status, attempt, MAX_RETRIES
+                )))
+            }
+            Err(Rejection::Status(status)) => Err(Failed::Retry(format!(
+                "Failed to send {} logs to SigNoz: {} (attempt {}/{})",
+                entries.len(),
+                status,
+                attempt,
+                MAX_RETRIES
+            ))),
+            Err(Rejection::Unreachable(e)) => Err(Failed::Retry(format!(
+                "Error sending {} logs to SigNoz: {} (attempt {}/{})",
+                entries.len(),
+                e,
+                attempt,
+                MAX_RETRIES
+            ))),
+        }
+    });
+    let attempts = match result {
+        Err(Failed::Permanent(_)) => "1 attempt".to_string(),
+        _ => format!("{} attempts", MAX_RETRIES),
+    };
+
+    match result {
+        Ok(()) => {
+            let every = config.success_log_every.unwrap_or(1);
+            let first = sender
+                .delivered
+                .fetch_add(entries.len() as u64, Ordering::Relaxed);
+            for (i, (entry, severity)) in entries.iter().zip(severities.iter()).enumerate() {
+                if every == 0 || !(first + i as u64).is_multiple_of(every) {
+                    continue;
+                }
+                let shown = if every > 1 {
+                    format!(", 1 of every {} shown", every)
+                } else {
+                    String::new()
+                };
+                println!(
+                    "Successfully sent to SigNoz: [{}] ({}/{}{})",
+                    entry.body.text(),
+                    severity.text,
+                    severity.number,
+                    shown
+                );
+            }
+            if !batch.is_empty() {
+                println!("Sent {} logs to {}{}", entries.len(), endpoint, batch);
+            }
+            Ok(Sent::Delivered)
+        }
+        Err(_) => match spool {
+            Some(spool) => {
+                eprintln!(
+                    "Failed to send {} logs{} after {}, buffering them on disk",
+                    entries.len(),
+                    batch,
+                    attempts
+                );
+                buffer(spool, Sent::Buffered)
+            }
+            None => Err(format!(
+                "Failed to send {} logs{} after {}, discarding them, starting with: {}",
+                entries.len(),
+                batch,
+                attempts,
+                entries[0].body.text()
+            )),
+        },
+    }
+}
+
+/// Sends the disk buffer's requests, oldest first, one attempt each. False once one
+/// fails, leaving it and the rest for later.
+fn drain(
+    spool: &mut Spool,
+    transport: &Transport,
+    faults: Option<&FaultInjection>,
+    adaptive: Option<&AdaptiveRate>,
+    name: &str,
+    stats: &ExporterStats,
+    state: &AgentState,
+) -> bool {
+    while let Some(front) = spool.front() {
+        let result = match front {
+            Ok((request, records)) => {
+                if let Some(adaptive) = adaptive {
+                    thread::sleep(adaptive.reserve(records, Instant::now()));
+                }
+                match transport.send_or_inject(
+                    faults,
+                    &request.endpoint,
+                    &request.payload,
+                    None,
+                    stats.gzip.load(Ordering::SeqCst),
+                ) {
+                    Ok(()) => {
+                        if let Some(adaptive) = adaptive {
+                            adaptive.delivered(records, Instant::now());
+                        }
+                        println!("Sent {} buffered logs to {}", records, request.endpoint);
+                        stats.no_logs_endpoint.store(false, Ordering::SeqCst);
+                        stats.sent.fetch_add(records as u64, Ordering::SeqCst);
+                        spool.pop()
+                    }
+                    Err(rejection) => {
+                        let status = match rejection {
+                            Rejection::Gzip(status) => {
+                                stats.gzip.store(false, Ordering::SeqCst);
+                                status
+                            }
+                            Rejection::Auth(status) => {
+                                stats.auth_failures.fetch_add(1, Ordering::SeqCst);
+                                status
+                            }
+                            Rejection::NotFound(status) => {
+                                stats.not_found.fetch_add(1, Ordering::SeqCst);
+                                stats.no_logs_endpoint.store(true, Ordering::SeqCst);
+                                status
+                            }
+                            Rejection::Throttled(status) => {
+                                if let Some(adaptive) = adaptive {
+                                    adaptive.throttled(Instant::now());
+                                }
+                                status
+                            }
+                            Rejection::Status(status) | Rejection::Unreachable(status) => status,
+                        };
+                        eprintln!(
+                            "Exporter {}: {} buffered logs still not delivered: {}",
+                            name,
+                            spool.records(),
+                            status
+                        );
+                        return false;
+                    }
+                }
+            }
+            Err(e) => {
+                state.record_error(format!(
+                    "Exporter {}: dropping unreadable disk buffer request: {:#}",
+                    name, e
+                ));
+                spool.pop()
+            }
+        };
+        stats.buffered.store(spool.records(), Ordering::SeqCst);
+        if let Err(e) = result {
+            state.record_error(format!(
+                "Exporter {}: failed to remove a sent disk buffer request: {:#}",
+                name, e
+            ));
+            return false;
+        }
+    }
+    true
+}
+
+/// `name` with every character but letters, digits, `-` and `_` replaced, for use as a
+/// file name.
+fn file_name_of(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Why an attempt of `retry` failed.
+pub enum Failed {
+    /// Another attempt may succeed: the collector was down, busy or throttling.
+    Retry(String),
+    /// Another attempt would get the same answer, such as a 404 for the path.
+    Permanent(String),
+}
+
+/// Runs `attempt` up to `MAX_RETRIES` times with exponential backoff (500ms, 1s, ...),
+/// returning the last error if every attempt failed.
+pub fn send_with_retry<F>(clock: &dyn Clock, mut attempt: F) -> Result<(), String>
+where
+    F: FnMut(usize) -> Result<(), String>,
+{
+    retry(clock, |n| attempt(n).map_err(Failed::Retry)).map_err(|e| match e {
+        Failed::Retry(e) | Failed::Permanent(e) => e,
+    })
+}
+
+/// `send_with_retry` for attempts that can tell a failure is permanent: the first
+/// `Failed::Permanent` is returned without another attempt.
+pub fn retry<F>(clock: &dyn Clock, mut attempt: F) -> Result<(), Failed>
+where
+    F: FnMut(usize) -> Result<(), Failed>,
+{
+    let mut last_error = Failed::Retry(String::new());
+    for n in 1..=MAX_RETRIES {
+        match attempt(n) {
+            Ok(()) => return Ok(()),
+            Err(Failed::Permanent(e)) => {
+                eprintln!("{}", e);
+                return Err(Failed::Permanent(e));
+            }
+            Err(Failed::Retry(e)) => {
+                eprintln!("{}", e);
+                last_error = Failed::Retry(e);
+            }
+        }
+
+        if n < MAX_RETRIES {
+            let backoff = Duration::from_millis(500 * 2u64.pow((n - 1) as u32));
+            clock.sleep(backoff);
+        }
+    }
+    Err(last_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SimulatedClock;
+    use chrono::Utc;
+
+    #[test]
+    fn retry_backs_off_exponentially_until_success() {
+        let start = Utc::now();
+        let clock = SimulatedClock::new(start);
+        let mut calls = 0;
+
+        let result = send_with_retry(&clock, |_| {
+            calls += 1;
+            if calls < 3 {
+                Err("unavailable".to_string())
+            } else {
+                Ok(())
+            }
+        });
+
+        assert!(result.is_ok());
+        assert_eq!(calls, 3);
+        assert_eq!((clock.now() - start).num_milliseconds(), 1500);
+    }
+
+    #[test]
+    fn receipt_tells_listeners_which_exporters_failed() {
+        let outcomes = Arc::new(Mutex::new(Vec::new()));
+        let mut listeners = Listeners::default();
+        let seen = outcomes.clone();
+        listeners.push(Arc::new(move |_: &Record, outcome: &Outcome| {
+            seen.lock().unwrap().push(outcome.clone())
+        }));
+        let record = Arc::new(Record::new("a"));
+        let state = Arc::new(AgentState::default());
+        for policy in [DeliveryPolicy::Any, DeliveryPolicy::All] {
+            let receipt = Receipt::new(2, policy, state.clone(), record.clone(), listeners.clone());
+            receipt.report("signoz", true);
+            assert!(outcomes.lock().unwrap().is_empty());
+            receipt.report("cloud", false);
+            let outcome = outcomes.lock().unwrap().pop().unwrap();
+            match policy {
+                DeliveryPolicy::Any => assert_eq!(outcome, Outcome::Delivered),
+                DeliveryPolicy::All => {
+                    assert_eq!(outcome, Outcome::Undelivered(vec!["cloud".to_string()]))
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn retry_gives_up_after_max_attempts() {
+        let clock = SimulatedClock::new(Utc::now());
+        let mut calls = 0;
+
+        let result = send_with_retry(&clock, |n| {
+            calls += 1;
+            Err(format!("attempt {}", n))
+        });
+
+        assert_eq!(result, Err(format!("attempt {}", MAX_RETRIES)));
+        assert_eq!(calls, MAX_RETRIES);
+    }
+
+    #[test]
+    fn not_found_hint_points_at_the_logs_path() {
+        assert!(
+            logs_path_hint("http://otel:4318/v1/traces").ends_with("try http://otel:4318/v1/logs")
+        );
+        assert!(logs_path_hint("https://gw.example.com/otlp/")
+            .ends_with("try https://gw.example.com/otlp/v1/logs"));
+        assert!(logs_path_hint("http://otel:4318/v1/logs").starts_with("check that"));
+    }
+
+    #[test]
+    fn an_endpoint_answering_not_found_is_not_retried() {
+        use std::io::{BufRead, BufReader, Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/v1/logs", listener.local_addr().unwrap());
+        let posts = Arc::new(AtomicU64::new(0));
+        {
+            let posts = posts.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let mut reader = BufReader::new(stream);
+                    let (mut line, mut length) = (String::new(), 0);
+                    reader.read_line(&mut line).unwrap();
+                    if line.starts_with("POST") {
+                        posts.fetch_add(1, Ordering::SeqCst);
+                    }
+                    while reader.read_line(&mut line).unwrap() > 0 && !line.ends_with("\r\n\r\n") {
+                        let header = line.lines().last().unwrap_or_default().to_lowercase();
+                        if let Some(value) = header.strip_prefix("content-length:") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                    reader.read_exact(&mut vec![0; length]).unwrap();
+                    let response =
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+                    reader.get_mut().write_all(response.as_bytes()).unwrap();
+                }
+            });
+        }
+        let config: Config = format!(
+            "endpoint = \"{}\"\nbatch_size = 1\ncompression = \"none\"",
+            endpoint
+        )
+        .parse()
+        .unwrap();
+        let config = Arc::new(config);
+        let state = Arc::new(AgentState::default());
+        let exporter = ExporterHandle::spawn(
+            "signoz",
+            &endpoint,
+            Protocol::Http,
+            config.credentials(),
+            config.clone(),
+            state.clone(),
+            Arc::new(SimulatedClock::new(Utc::now())),
+        );
+        state.exporters.lock().unwrap().push(exporter.stats.clone());
+        let record = Arc::new(Record::new("lost"));
+        let receipt = Receipt::new(
+            1,
+            DeliveryPolicy::Any,
+            state.clone(),
+            record.clone(),
+            Listeners::default(),
+        );
+        exporter.submit(&Arc::from("app"), record, Arc::new(receipt));
+        while exporter.stats.failed.load(Ordering::SeqCst) == 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(posts.load(Ordering::SeqCst), 1);
+        assert_eq!(exporter.stats.retries.load(Ordering::SeqCst), 0);
+        assert!(exporter.stats.no_logs_endpoint.load(Ordering::SeqCst));
+        assert_eq!(
+            crate::http_status::unhealthy(&state),
+            Some("an exporter's endpoint has no logs path")
+        );
+
+        let mut calls = 0;
+        let result = retry(&SimulatedClock::new(Utc::now()), |_| {
+            calls += 1;
+            Err(Failed::Permanent("gone".to_string()))
+        });
+        assert!(matches!(result, Err(Failed::Permanent(_))));
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn custom_exporters_are_sent_batches_and_retried() {
+        struct Recording(Arc<Mutex<Vec<Vec<String>>>>, usize);
+        impl Exporter for Recording {
+            fn name(&self) -> &str {
+                "recording"
+            }
+            fn export(&mut self, records: &[&Record]) -> Result<(), String> {
+                self.1 += 1;
+                if self.1 == 1 {
+                    return Err("not connected yet".to_string());
+                }
+                let lines = records.iter().map(|r| r.body.text().into_owned()).collect();
+                self.0.lock().unwrap().push(lines);
+                Ok(())
+            }
+        }
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let config: Config = "endpoint = \"http://localhost:4318\"\nbatch_size = 2"
+            .parse()
+            .unwrap();
+        let state = Arc::new(AgentState::default());
+        let exporter = ExporterHandle::spawn_custom(
+            Arc::new(Mutex::new(Box::new(Recording(batches.clone(), 0)))),
+            Arc::new(config),
+            state.clone(),
+            Arc::new(SimulatedClock::new(Utc::now())),
+        );
+        for line in ["a", "b", "c"] {
+            let record = Arc::new(Record::new(line));
+            let receipt = Receipt::new(
+                1,
+                DeliveryPolicy::Any,
+                state.clone(),
+                record.clone(),
+                Listeners::default(),
+            );
+            exporter.submit(&Arc::from("app"), record, Arc::new(receipt));
+        }
+        let stats = exporter.stats.clone();
+        exporter.close();
+        assert_eq!(*batches.lock().unwrap(), vec![vec!["a", "b"], vec!["c"]]);
+        assert_eq!(
+            (
+                stats.sent.load(Ordering::SeqCst),
+                stats.retries.load(Ordering::SeqCst),
+                stats.pending.load(Ordering::SeqCst)
+            ),
+            (3, 1, 0)
+        );
+    }
+
+    #[test]
+    fn a_partial_batch_is_sent_at_its_deadline() {
+        let clock = Arc::new(SimulatedClock::new(Utc::now()));
+        let state = Arc::new(AgentState::default());
+        let rx = Lanes::new(BTreeMap::new(), 10, Default::default());
+        let item = |line: &str| {
+            let record = Arc::new(Record::new(line));
+            let receipt = Receipt::new(
+                1,
+                DeliveryPolicy::Any,
+                state.clone(),
+                record.clone(),
+                Listeners::default(),
+            );
+            (record, Arc::new(receipt))
+        };
+        rx.push(&Arc::from("app"), item("b")).ok();
+        let first = item("a");
+        let collecting = {
+            let (clock, state) = (clock.clone(), state.clone());
+            thread::spawn(move || {
+                collect_batch(first, &rx, 10, Duration::from_secs(3600), &state, &*clock)
+                    .iter()
+                    .map(|(record, _)| record.body.text().into_owned())
+                    .collect::<Vec<_>>()
+            })
+        };
+        // An hour of flush interval, of which no real time passes.
+        thread::sleep(FLUSH_CHECK_INTERVAL * 3);
+        assert!(!collecting.is_finished());
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(collecting.join().unwrap(), ["a", "b"]);
+    }
+
+    #[test]
+    fn a_stalled_exporter_drops_for_room_while_the_others_keep_receiving() {
+        struct Stalled(Arc<Mutex<()>>);
+        impl Exporter for Stalled {
+            fn name(&self) -> &str {
+                "stalled"
+            }
+            fn export(&mut self, _: &[&Record]) -> Result<(), String> {
+                drop(self.0.lock().unwrap());
+                Ok(())
+            }
+        }
+        struct Counting(Arc<AtomicUsize>);
+        impl Exporter for Counting {
+            fn name(&self) -> &str {
+                "counting"
+            }
+            fn export(&mut self, records: &[&Record]) -> Result<(), String> {
+                self.0.fetch_add(records.len(), Ordering::SeqCst);
+                Ok(())
+            }
+        }
+        let config: Arc<Config> = Arc::new(
+            "endpoint = \"http://localhost:4318\"\nbatch_size = 1\nqueue_capacity = 2"
+                .parse()
+                .unwrap(),
+        );
+        let state = Arc::new(AgentState::default());
+        let clock = Arc::new(SimulatedClock::new(Utc::now()));
+        let stall = Arc::new(Mutex::new(()));
+        let stalled = stall.lock().unwrap();
+        let received = Arc::new(AtomicUsize::new(0));
+        let exporters = Arc::new([
+            ExporterHandle::spawn_custom(
+                Arc::new(Mutex::new(Box::new(Stalled(stall.clone())))),
+                config.clone(),
+                state.clone(),
+                clock.clone(),
+            ),
+            ExporterHandle::spawn_custom(
+                Arc::new(Mutex::new(Box::new(Counting(received.clone())))),
+                config.clone(),
+                state.clone(),
+                clock.clone(),
+            ),
+        ]);
+        let queue = crate::queue::FairQueue::new(
+            Default::default(),
+            2,
+            crate::queue::Overflow::Block,
+            state.clone(),
+            Listeners::default(),
+        );
+        // The dispatcher, as `run` has it.
+        {
+            let (queue, exporters, state) = (queue.clone(), exporters.clone(), state.clone());
+            thread::spawn(move || loop {
+                let (input, batch) = queue.recv();
+                for record in batch {
+                    let record = Arc::new(record);
+                    let receipt = Arc::new(Receipt::new(
+                        exporters.len(),
+                        DeliveryPolicy::Any,
+                        state.clone(),
+                        record.clone(),
+                        Listeners::default(),
+                    ));
+                    for exporter in exporters.iter() {
+                        exporter.submit(&input, record.clone(), receipt.clone());
+                    }
+                    state.queued.fetch_sub(1, Ordering::SeqCst);
+                }
+            })
+        };
+        let tx = queue.sender("app");
+        for i in 0..10 {
+            state.queued.fetch_add(1, Ordering::SeqCst);
+            tx.send(vec![Record::new(i.to_string())]);
+            thread::sleep(Duration::from_millis(20));
+        }
+        while received.load(Ordering::SeqCst) < 10 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        // One record is being exported by the stalled exporter and two wait in its queue;
+        // the others were dropped for it alone, and delivered by the other exporter.
+        let stalled_stats = &exporters[0].stats;
+        assert_eq!(stalled_stats.queue_full.load(Ordering::SeqCst), 7);
+        assert_eq!(stalled_stats.pending.load(Ordering::SeqCst), 3);
+        assert!(state.snapshot().dropped.is_empty());
+
+        drop(stalled);
+        while stalled_stats.pending.load(Ordering::SeqCst) > 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(stalled_stats.sent.load(Ordering::SeqCst), 3);
+        assert_eq!(exporters[1].stats.sent.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn batches_take_the_records_of_each_input_in_turn() {
+        struct Recording(Arc<Mutex<Vec<Vec<String>>>>, Arc<Mutex<()>>);
+        impl Exporter for Recording {
+            fn name(&self) -> &str {
+                "recording"
+            }
+            fn export(&mut self, records: &[&Record]) -> Result<(), String> {
+                drop(self.1.lock().unwrap());
+                let lines = records.iter().map(|r| r.body.text().into_owned()).collect();
+                self.0.lock().unwrap().push(lines);
+                Ok(())
+            }
+        }
+        let config: Config = "endpoint = \"http://localhost:4318\"\nbatch_size = 4\nflush_interval_ms = 1\n[queue_weights]\nweb = 2"
+            .parse()
+            .unwrap();
+        let state = Arc::new(AgentState::default());
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let stall = Arc::new(Mutex::new(()));
+        let stalled = stall.lock().unwrap();
+        let exporter = ExporterHandle::spawn_custom(
+            Arc::new(Mutex::new(Box::new(Recording(
+                batches.clone(),
+                stall.clone(),
+            )))),
+            Arc::new(config),
+            state.clone(),
+            Arc::new(crate::clock::SystemClock),
+        );
+        let submit = |input: &str, line: &str| {
+            let record = Arc::new(Record::new(line));
+            let receipt = Receipt::new(
+                1,
+                DeliveryPolicy::Any,
+                state.clone(),
+                record.clone(),
+                Listeners::default(),
+            );
+            exporter.submit(&Arc::from(input), record, Arc::new(receipt));
+        };
+        // While the first batch is being exported, a noisy input queues its backlog ahead
+        // of two quiet ones.
+        submit("app", "app 0");
+        thread::sleep(Duration::from_millis(100));
+        for i in 1..=6 {
+            submit("app", &format!("app {}", i));
+        }
+        for input in ["web", "db"] {
+            for i in 0..2 {
+                submit(input, &format!("{} {}", input, i));
+            }
+        }
+        drop(stalled);
+        exporter.close();
+        assert_eq!(
+            *batches.lock().unwrap(),
+            vec![
+                vec!["app 0"],
+                vec!["app 1", "web 0", "web 1", "db 0"],
+                vec!["app 2", "db 1", "app 3", "app 4"],
+                vec!["app 5", "app 6"],
+            ]
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use std::fmt;
+
+/// Failure categories that map to distinct process exit codes, so supervisors can tell a
+/// broken config from a transient outage. Codes follow sysexits.h where one fits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// config.toml is missing required values, malformed, or fails validation.
+    ConfigInvalid,
+    /// `--fail-fast` was given and the endpoint did not accept a probe at startup.
+    EndpointUnreachable,
+    /// A configured input exists but the agent is not allowed to read it.
+    PermissionDenied,
+    /// `flush` timed out with records still queued.
+    FlushIncomplete,
+    /// `ship --wait` or `--oneshot` ended with records the exporters did not accept.
+    ShipIncomplete,
+    /// Another running agent holds the lock on the state directory or the disk buffer.
+    StateLocked,
+    /// The state directory holds checkpoints that cannot be read back.
+    StateCorrupt,
+}
+
+impl Failure {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Failure::ConfigInvalid => 78,
+            Failure::EndpointUnreachable => 69,
+            Failure::PermissionDenied => 77,
+            Failure::FlushIncomplete | Failure::ShipIncomplete => 75,
+            Failure::StateLocked => 73,
+            Failure::StateCorrupt => 65,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            Failure::ConfigInvalid => "Invalid configuration",
+            Failure::EndpointUnreachable => "Endpoint unreachable at startup",
+            Failure::PermissionDenied => "Permission denied on input",
+            Failure::FlushIncomplete => "Flush incomplete",
+            Failure::ShipIncomplete => "Ship incomplete",
+            Failure::StateLocked => "State directory or disk buffer locked",
+            Failure::StateCorrupt => "State directory corrupt",
+        };
+        f.write_str(message)
+    }
+}
+
+/// Tags `err` with `failure` unless it already carries a more specific category.
+pub fn classify(err: anyhow::Error, failure: Failure) -> anyhow::Error {
+    if failure_of(&err).is_some() {
+        err
+    } else {
+        err.context(failure)
+    }
+}
+
+pub fn failure_of(err: &anyhow::Error) -> Option<Failure> {
+    err.downcast_ref::<Failure>().copied()
+}
+
+/// Exit code for an error returned from `main`; uncategorized errors exit with 1.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    failure_of(err).map_or(1, Failure::exit_code)
+}
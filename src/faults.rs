@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::thread;
+use std::time::Duration;
+
+/// `[fault_injection]`: the exporters' requests are delayed, failed or throttled at
+/// random, as a SigNoz outage would, to rehearse how `[disk_buffer]`, `queue_capacity` and
+/// `queue_overflow` hold up before a real one. Off unless the section is present.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FaultInjection {
+    /// Share of requests, in percent, held before they are sent, for up to `delay_ms`.
+    #[serde(default)]
+    pub delay_percent: f64,
+    #[serde(default = "default_delay_ms")]
+    pub delay_ms: u64,
+    /// Share of requests, in percent, that fail as if the endpoint could not be reached.
+    #[serde(default)]
+    pub failure_percent: f64,
+    /// Share of requests, in percent, answered with HTTP 429.
+    #[serde(default)]
+    pub throttle_percent: f64,
+    /// Whether requests that get through are accepted in the agent, never reaching the
+    /// endpoints, or sent on to them.
+    #[serde(default = "default_mock")]
+    pub mock: bool,
+}
+
+fn default_delay_ms() -> u64 {
+    5000
+}
+
+fn default_mock() -> bool {
+    true
+}
+
+/// What becomes of a request, once any delay is over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Sent, or accepted by the mock.
+    None,
+    Failure,
+    Throttle,
+}
+
+impl FaultInjection {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (key, percent) in [
+            ("delay_percent", self.delay_percent),
+            ("failure_percent", self.failure_percent),
+            ("throttle_percent", self.throttle_percent),
+        ] {
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(anyhow::anyhow!(
+                    "fault_injection {} must be between 0 and 100, got {}",
+                    key,
+                    percent
+                ));
+            }
+        }
+        if self.failure_percent + self.throttle_percent > 100.0 {
+            return Err(anyhow::anyhow!(
+                "fault_injection failure_percent and throttle_percent add up to more than 100"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Delays a request or not, and picks its fault, with `roll` drawing numbers in
+    /// [0, 100).
+    pub fn pick(&self, mut roll: impl FnMut() -> f64) -> (Duration, Fault) {
+        let delay = if roll() < self.delay_percent {
+            Duration::from_millis((roll() / 100.0 * self.delay_ms as f64) as u64)
+        } else {
+            Duration::ZERO
+        };
+        let outcome = roll();
+        let fault = if outcome < self.failure_percent {
+            Fault::Failure
+        } else if outcome < self.failure_percent + self.throttle_percent {
+            Fault::Throttle
+        } else {
+            Fault::None
+        };
+        (delay, fault)
+    }
+
+    /// Waits out the request's delay, if it gets one, and returns its fault.
+    pub fn inject(&self) -> Fault {
+        let (delay, fault) = self.pick(roll);
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+        fault
+    }
+}
+
+/// A number in [0, 100), from the random keys std seeds its hash maps with.
+fn roll() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64 * 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_delayed_failed_or_throttled_by_share() {
+        let faults: FaultInjection =
+            toml::from_str("delay_percent = 50\nfailure_percent = 10\nthrottle_percent = 20")
+                .unwrap();
+        faults.validate().unwrap();
+        assert!(faults.mock);
+
+        let rolls = |rolls: &[f64]| {
+            let mut rolls = rolls.iter().copied();
+            faults.pick(move || rolls.next().unwrap())
+        };
+        assert_eq!(
+            rolls(&[10.0, 50.0, 5.0]),
+            (Duration::from_millis(2500), Fault::Failure)
+        );
+        assert_eq!(rolls(&[60.0, 25.0]), (Duration::ZERO, Fault::Throttle));
+        assert_eq!(rolls(&[99.0, 30.0]), (Duration::ZERO, Fault::None));
+        assert!((0..1000).map(|_| roll()).all(|r| (0.0..100.0).contains(&r)));
+
+        let broken: FaultInjection =
+            toml::from_str("failure_percent = 60\nthrottle_percent = 60").unwrap();
+        assert!(broken.validate().is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use anyhow::Result;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+
+/// A field of a JSON record named by its path, as in Vector's remap language:
+/// `meta.ts`, `items[0].id`, or `."k8s.pod".name` for keys with a dot in them. A
+/// leading `.` is optional.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldPath {
+    text: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+impl FieldPath {
+    pub fn parse(path: &str) -> Result<FieldPath> {
+        let invalid = |why: &str| anyhow::anyhow!("Field path {:?} {}", path, why);
+        let text = path.strip_prefix('.').unwrap_or(path);
+        let mut segments = Vec::new();
+        let mut rest = text;
+        while !rest.is_empty() {
+            if let Some(index) = rest.strip_prefix('[') {
+                let end = index
+                    .find(']')
+                    .ok_or_else(|| invalid("has a [ without a ]"))?;
+                let n = index[..end]
+                    .parse()
+                    .map_err(|_| invalid("has an index that is not a number"))?;
+                segments.push(Segment::Index(n));
+                rest = &index[end + 1..];
+            } else if let Some(quoted) = rest.strip_prefix('"') {
+                let end = quoted
+                    .find('"')
+                    .ok_or_else(|| invalid("has an unclosed quote"))?;
+                segments.push(Segment::Key(quoted[..end].to_string()));
+                rest = &quoted[end + 1..];
+            } else {
+                let end = rest.find(['.', '[']).unwrap_or(rest.len());
+                if end == 0 {
+                    return Err(invalid("has an empty key"));
+                }
+                segments.push(Segment::Key(rest[..end].to_string()));
+                rest = &rest[end..];
+            }
+            rest = match rest.strip_prefix('.') {
+                Some("") => return Err(invalid("ends with a .")),
+                Some(next) => next,
+                None if rest.is_empty() || rest.starts_with('[') => rest,
+                None => return Err(invalid("needs a . between its keys")),
+            };
+        }
+        if segments.is_empty() {
+            return Err(invalid("is empty"));
+        }
+        Ok(FieldPath {
+            text: text.to_string(),
+            segments,
+        })
+    }
+
+    /// Whether the path goes below the top level of the record.
+    pub fn is_nested(&self) -> bool {
+        self.segments.len() > 1
+    }
+
+    /// The field in the fields of a JSON object.
+    pub fn get<'a>(&self, fields: &'a Map<String, Value>) -> Option<&'a Value> {
+        let Some((Segment::Key(first), rest)) = self.segments.split_first() else {
+            return None;
+        };
+        walk(fields.get(first)?, rest)
+    }
+
+    /// Takes the field out of `fields`, when it is a key of an object; an array element
+    /// is left in its array, and its value cloned.
+    pub fn remove(&self, fields: &mut Map<String, Value>) -> Option<Value> {
+        let (last, parents) = self.segments.split_last()?;
+        let mut current = fields;
+        for (i, segment) in parents.iter().enumerate() {
+            // An index only follows the key of its array, which ends the loop.
+            let Segment::Key(key) = segment else {
+                return None;
+            };
+            match current.get_mut(key)? {
+                Value::Object(object) => current = object,
+                array @ Value::Array(_) => return walk(array, &self.segments[i + 1..]).cloned(),
+                _ => return None,
+            }
+        }
+        match last {
+            Segment::Key(key) => current.remove(key),
+            Segment::Index(_) => None,
+        }
+    }
+
+    /// The field's value among the attributes of a record, in which the JSON parser
+    /// flattened nested objects into dotted keys and left arrays as JSON text.
+    pub fn lookup<'a>(&self, attributes: &'a [(String, String)]) -> Option<Cow<'a, str>> {
+        let find = |key: &str| {
+            attributes
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        };
+        if let Some(value) = find(&self.text) {
+            return Some(Cow::Borrowed(value));
+        }
+        let keys = self
+            .segments
+            .iter()
+            .take_while(|s| matches!(s, Segment::Key(_)))
+            .count();
+        (1..=keys).rev().find_map(|split| {
+            let flattened: Vec<&str> = self.segments[..split].iter().filter_map(key_of).collect();
+            let value = find(&flattened.join("."))?;
+            if split == self.segments.len() {
+                return Some(Cow::Borrowed(value));
+            }
+            let nested: Value = serde_json::from_str(value).ok()?;
+            text(walk(&nested, &self.segments[split..])?).map(Cow::Owned)
+        })
+    }
+}
+
+impl std::fmt::Display for FieldPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+fn key_of(segment: &Segment) -> Option<&str> {
+    match segment {
+        Segment::Key(key) => Some(key),
+        Segment::Index(_) => None,
+    }
+}
+
+fn walk<'a>(value: &'a Value, segments: &[Segment]) -> Option<&'a Value> {
+    segments
+        .iter()
+        .try_fold(value, |value, segment| match (segment, value) {
+            (Segment::Key(key), Value::Object(object)) => object.get(key),
+            (Segment::Index(i), Value::Array(array)) => array.get(*i),
+            _ => None,
+        })
+}
+
+/// A value as an attribute holds it: strings as they are, others as JSON text.
+pub fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_reach_into_nested_objects_and_arrays() {
+        let path = |p: &str| FieldPath::parse(p).unwrap();
+        let Value::Object(mut fields) = serde_json::from_str(
+            r#"{"meta": {"ts": "2024-03-05T10:00:00Z"}, "items": [{"id": 7}], "k8s.pod": {"name": "api"}}"#,
+        )
+        .unwrap() else {
+            unreachable!()
+        };
+        assert_eq!(
+            path(".meta.ts").get(&fields),
+            Some(&Value::from("2024-03-05T10:00:00Z"))
+        );
+        assert_eq!(path("items[0].id").get(&fields), Some(&Value::from(7)));
+        assert_eq!(path("items[1].id").get(&fields), None);
+        assert_eq!(
+            path(r#""k8s.pod".name"#).get(&fields),
+            Some(&Value::from("api"))
+        );
+        for invalid in ["", "a..b", "a.", "items[x]", "items[0", "\"unclosed"] {
+            assert!(FieldPath::parse(invalid).is_err(), "{:?}", invalid);
+        }
+
+        // As the JSON parser leaves them: objects flattened, arrays as JSON text.
+        let attributes = vec![
+            ("meta.ts".to_string(), "2024-03-05T10:00:00Z".to_string()),
+            (
+                "items".to_string(),
+                r#"[{"id":7,"tags":["a"]}]"#.to_string(),
+            ),
+        ];
+        assert_eq!(
+            path("meta.ts").lookup(&attributes).as_deref(),
+            Some("2024-03-05T10:00:00Z")
+        );
+        assert_eq!(
+            path("items[0].id").lookup(&attributes).as_deref(),
+            Some("7")
+        );
+        assert_eq!(
+            path("items[0].tags").lookup(&attributes).as_deref(),
+            Some("[\"a\"]")
+        );
+        assert_eq!(path("items[0].name").lookup(&attributes), None);
+
+        assert_eq!(
+            path("meta.ts").remove(&mut fields),
+            Some(Value::from("2024-03-05T10:00:00Z"))
+        );
+        assert_eq!(fields["meta"], serde_json::json!({}));
+        assert_eq!(
+            path("items[0].id").remove(&mut fields),
+            Some(Value::from(7))
+        );
+        assert_eq!(fields["items"], serde_json::json!([{"id": 7}]));
+    }
+}
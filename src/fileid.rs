@@ -0,0 +1,41 @@
+use std::fs;
+
+/// What tells files apart across renames, on every platform: the device and inode
+/// numbers on Unix. Stable Rust has no file index on Windows, so there the creation time
+/// stands in for the inode, and a file counts as deleted only once its path is gone.
+pub trait FileId {
+    fn dev(&self) -> u64;
+    fn ino(&self) -> u64;
+    /// The number of names of the file; 0 once the last was removed.
+    fn nlink(&self) -> u64;
+}
+
+#[cfg(unix)]
+impl FileId for fs::Metadata {
+    fn dev(&self) -> u64 {
+        std::os::unix::fs::MetadataExt::dev(self)
+    }
+
+    fn ino(&self) -> u64 {
+        std::os::unix::fs::MetadataExt::ino(self)
+    }
+
+    fn nlink(&self) -> u64 {
+        std::os::unix::fs::MetadataExt::nlink(self)
+    }
+}
+
+#[cfg(windows)]
+impl FileId for fs::Metadata {
+    fn dev(&self) -> u64 {
+        0
+    }
+
+    fn ino(&self) -> u64 {
+        std::os::windows::fs::MetadataExt::creation_time(self)
+    }
+
+    fn nlink(&self) -> u64 {
+        1
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::failure::Failure;
+
+/// An exclusive advisory lock on a file: `flock` on Unix, `LockFileEx` on Windows. The
+/// system releases it when the process exits, however it exits, so a crash leaves the file
+/// behind but never the lock.
+///
+/// The holder writes its pid into the file, for the error other processes show, and
+/// empties it when it lets go; a pid found in a file that is not locked is that of a
+/// process that did not shut down cleanly.
+pub struct FileLock {
+    file: File,
+    /// The pid found in the file when it was locked.
+    stale: Option<u32>,
+}
+
+impl FileLock {
+    /// Locks `path`, creating it if needed; `None` while another process holds it.
+    pub fn try_lock(path: &Path) -> io::Result<Option<FileLock>> {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if !try_lock_exclusive(&file)? {
+            return Ok(None);
+        }
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).ok();
+        Ok(Some(FileLock {
+            file,
+            stale: contents.trim().parse().ok(),
+        }))
+    }
+
+    /// The pid the holder of the lock on `path` wrote into it.
+    pub fn holder(path: &Path) -> Option<u32> {
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    }
+
+    /// The pid of the process that held the lock before and exited without letting go.
+    pub fn stale(&self) -> Option<u32> {
+        self.stale
+    }
+
+    /// Writes the current pid into the file; called again after daemonizing.
+    pub fn write_pid(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        writeln!(self.file, "{}", std::process::id())
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // The lock itself goes with the file.
+        self.file.set_len(0).ok();
+    }
+}
+
+/// Takes the lock on `name` in `dir`, creating `dir`, for as long as the process runs.
+/// `what` names the directory in messages and `setting` the setting that gives another
+/// agent its own. Fails when another agent holds the lock, unless `force` is set, in
+/// which case a warning is printed and `None` returned.
+pub fn hold(
+    dir: &str,
+    name: &str,
+    what: &str,
+    setting: &str,
+    force: bool,
+) -> Result<Option<FileLock>> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {} {}", what, dir))?;
+    let path = Path::new(dir).join(name);
+    let lock =
+        FileLock::try_lock(&path).with_context(|| format!("Failed to lock {}", path.display()))?;
+    let Some(mut lock) = lock else {
+        let holder = match FileLock::holder(&path) {
+            Some(pid) => format!("another agent (pid {})", pid),
+            None => "another agent".to_string(),
+        };
+        if force {
+            eprintln!(
+                "Warning: {} {} is in use by {}; continuing because of --force, which can corrupt it",
+                what, dir, holder
+            );
+            return Ok(None);
+        }
+        return Err(anyhow::anyhow!(
+            "{} {} is in use by {}; stop it, give this agent its own {}, or pass --force",
+            what,
+            dir,
+            holder,
+            setting
+        )
+        .context(Failure::StateLocked));
+    };
+    if let Some(pid) = lock.stale() {
+        println!(
+            "{} {} was last held by pid {}, which did not shut down cleanly; taking it over",
+            what, dir, pid
+        );
+    }
+    lock.write_pid()
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(Some(lock))
+}
+
+#[cfg(unix)]
+fn try_lock_exclusive(file: &File) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: flock on a descriptor owned by `file`.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Ok(false),
+        _ => Err(error),
+    }
+}
+
+#[cfg(windows)]
+fn try_lock_exclusive(file: &File) -> io::Result<bool> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Foundation::{ERROR_LOCK_VIOLATION, HANDLE};
+    use windows_sys::Win32::Storage::FileSystem::{
+        LockFileEx, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY,
+    };
+    use windows_sys::Win32::System::IO::OVERLAPPED;
+
+    // SAFETY: LockFileEx on a handle owned by `file`, with a zeroed OVERLAPPED. The byte
+    // locked lies far past the end of the file, so that others can still read the pid.
+    let locked = unsafe {
+        let mut overlapped: OVERLAPPED = std::mem::zeroed();
+        overlapped.Anonymous.Anonymous.OffsetHigh = u32::MAX;
+        LockFileEx(
+            file.as_raw_handle() as HANDLE,
+            LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY,
+            0,
+            1,
+            0,
+            &mut overlapped,
+        )
+    };
+    if locked != 0 {
+        return Ok(true);
+    }
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(code) if code == ERROR_LOCK_VIOLATION as i32 => Ok(false),
+        _ => Err(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn lock_is_exclusive_and_a_crashed_holder_is_noticed() {
+        let dir = TempDir::new("filelock");
+        let dir_name = dir.to_str().unwrap();
+        let path = dir.join("agent.lock");
+
+        let lock = |force| {
+            hold(
+                dir_name,
+                "agent.lock",
+                "State directory",
+                "state_dir",
+                force,
+            )
+        };
+
+        let held = lock(false).unwrap().unwrap();
+        assert_eq!(held.stale(), None);
+        assert_eq!(FileLock::holder(&path), Some(std::process::id()));
+        assert!(FileLock::try_lock(&path).unwrap().is_none());
+        let error = lock(false).err().unwrap();
+        assert!(format!("{:#}", error).contains("give this agent its own state_dir"));
+        assert!(lock(true).unwrap().is_none());
+
+        // Let go cleanly, the file is emptied; a pid left in it is a crash.
+        drop(held);
+        assert_eq!(FileLock::holder(&path), None);
+        let lock = FileLock::try_lock(&path).unwrap().unwrap();
+        assert_eq!(lock.stale(), None);
+        drop(lock);
+        fs::write(&path, "4242\n").unwrap();
+        assert_eq!(
+            FileLock::try_lock(&path).unwrap().unwrap().stale(),
+            Some(4242)
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use aho_corasick::AhoCorasick;
+use regex_syntax::hir::{literal::Extractor, HirKind};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::rules::{RegexLimits, Rule};
+use crate::stats::AgentState;
+
+/// Patterns needing more literals than this to be told apart run on every line.
+const MAX_LITERALS: usize = 64;
+
+/// Which records of an input are shipped, decided before they are queued.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FilterConfig {
+    /// Only records matching one of these are shipped; every record when empty.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Records matching one of these are dropped, even when included.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// What the literal prefilter knows about a pattern.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Prefilter {
+    /// The pattern is a plain string: it matches where the prefilter found it.
+    Exact,
+    /// A match starts with one of the pattern's literals; without one the regex cannot
+    /// match and is not run.
+    Required,
+    /// The regex runs on every line.
+    None,
+}
+
+struct FilterRule {
+    rule: Rule,
+    prefilter: Prefilter,
+}
+
+impl FilterRule {
+    fn is_match(&self, line: &str, found: bool) -> bool {
+        match self.prefilter {
+            Prefilter::Exact => {
+                self.rule.prefiltered(found);
+                found
+            }
+            Prefilter::Required if !found => {
+                self.rule.prefiltered(false);
+                false
+            }
+            _ => self.rule.is_match(line),
+        }
+    }
+}
+
+/// The include and exclude patterns, with one Aho-Corasick automaton over their literals
+/// so that a line is only matched against the regexes that can match it.
+pub struct LineFilter {
+    include: Vec<FilterRule>,
+    exclude: Vec<FilterRule>,
+    literals: Option<AhoCorasick>,
+    /// For each literal of `literals`, its rule: an index into `include`, then `exclude`.
+    owners: Vec<usize>,
+    state: Option<Arc<AgentState>>,
+}
+
+/// The literals every match of `pattern` starts with, and whether they are the whole
+/// pattern; nothing when they cannot be told.
+fn literals(pattern: &str) -> Option<(Vec<Vec<u8>>, Prefilter)> {
+    let hir = regex_syntax::Parser::new().parse(pattern).ok()?;
+    if let HirKind::Literal(literal) = hir.kind() {
+        return Some((vec![literal.0.to_vec()], Prefilter::Exact));
+    }
+    let literals = Extractor::new().extract(&hir).literals()?.to_vec();
+    if literals.is_empty()
+        || literals.len() > MAX_LITERALS
+        || literals.iter().any(|l| l.as_bytes().is_empty())
+    {
+        return None;
+    }
+    Some((
+        literals.iter().map(|l| l.as_bytes().to_vec()).collect(),
+        Prefilter::Required,
+    ))
+}
+
+impl LineFilter {
+    pub fn new(
+        name: &str,
+        config: &FilterConfig,
+        limits: &RegexLimits,
+    ) -> anyhow::Result<LineFilter> {
+        let mut patterns = Vec::new();
+        let mut owners = Vec::new();
+        let mut compile = |kind: &str, list: &[String], first: usize| {
+            list.iter()
+                .enumerate()
+                .map(|(i, pattern)| {
+                    let rule_name = format!("filters {} {}[{}]", name, kind, i);
+                    let rule = Rule::new(&rule_name, pattern, limits)?;
+                    let prefilter = match literals(pattern) {
+                        Some((literals, prefilter)) => {
+                            owners.extend(literals.iter().map(|_| first + i));
+                            patterns.extend(literals);
+                            prefilter
+                        }
+                        None => Prefilter::None,
+                    };
+                    Ok(FilterRule { rule, prefilter })
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        };
+        let include = compile("include", &config.include, 0)?;
+        let exclude = compile("exclude", &config.exclude, include.len())?;
+        let literals = match patterns.is_empty() {
+            true => None,
+            false => Some(AhoCorasick::new(&patterns)?),
+        };
+        Ok(LineFilter {
+            include,
+            exclude,
+            literals,
+            owners,
+            state: None,
+        })
+    }
+
+    /// Makes the counters of the patterns visible in `state`, until the filter is dropped.
+    pub fn register(&mut self, state: &Arc<AgentState>) {
+        for filter_rule in self.include.iter_mut().chain(&mut self.exclude) {
+            filter_rule.rule.register(state);
+        }
+        self.state = Some(state.clone());
+    }
+
+    pub fn keep(&self, line: &str) -> bool {
+        let mut found = vec![false; self.include.len() + self.exclude.len()];
+        if let Some(ref literals) = self.literals {
+            for m in literals.find_overlapping_iter(line) {
+                found[self.owners[m.pattern().as_usize()]] = true;
+            }
+        }
+        let (included, excluded) = found.split_at(self.include.len());
+        (self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .zip(included)
+                .any(|(rule, &found)| rule.is_match(line, found)))
+            && !self
+                .exclude
+                .iter()
+                .zip(excluded)
+                .any(|(rule, &found)| rule.is_match(line, found))
+    }
+}
+
+impl Drop for LineFilter {
+    fn drop(&mut self) {
+        if let Some(ref state) = self.state {
+            for filter_rule in self.include.iter().chain(&self.exclude) {
+                filter_rule.rule.unregister(state);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclude_wins_over_include() {
+        let filter = LineFilter::new(
+            "/var/log/nginx/access.log",
+            &FilterConfig {
+                include: vec!["GET ".to_string()],
+                exclude: vec!["/healthz".to_string()],
+            },
+            &RegexLimits::default(),
+        )
+        .unwrap();
+        assert!(filter.keep("GET /api/orders 200"));
+        assert!(!filter.keep("GET /healthz 200"));
+        assert!(!filter.keep("POST /api/orders 201"));
+    }
+
+    #[test]
+    fn literals_decide_lines_without_running_regexes() {
+        let mut filter = LineFilter::new(
+            "/var/log/app.log",
+            &FilterConfig {
+                include: vec![r"status=5\d\d".to_string(), r"(?i)timeout".to_string()],
+                exclude: vec!["/healthz".to_string()],
+            },
+            &RegexLimits::default(),
+        )
+        .unwrap();
+        let state = Arc::new(AgentState::default());
+        filter.register(&state);
+        assert_eq!(filter.include[0].prefilter, Prefilter::Required);
+        assert_eq!(filter.exclude[0].prefilter, Prefilter::Exact);
+        assert!(filter.keep("GET /api status=503"));
+        assert!(!filter.keep("GET /api status=200"));
+        assert!(filter.keep("upstream TIMEOUT"));
+        assert!(!filter.keep("GET /healthz status=500"));
+
+        let stats: Vec<(u64, u64, u64)> = state
+            .snapshot()
+            .rules
+            .iter()
+            .map(|r| (r.matches, r.hits, r.prefiltered))
+            .collect();
+        // The status regex only ran on the lines holding "status=5", and the exclude
+        // pattern, a plain string, on none.
+        assert_eq!(stats, vec![(2, 2, 2), (2, 1, 0), (0, 1, 3)]);
+
+        drop(filter);
+        assert!(state.rules.lock().unwrap().is_empty());
+    }
+}
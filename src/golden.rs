@@ -0,0 +1,209 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::trace_context::TraceContext;
+use crate::{build_otlp_payload, merge_otlp_payloads, severity_of, Body, Config, Record, Severity};
+
+/// Where the golden files are kept, from the root of the repository.
+pub const DIR: &str = "testdata/golden";
+
+/// The config the corpus is encoded with: every setting that shapes a record is set, so
+/// a change to any of them shows.
+const CONFIG: &str = r#"
+endpoint = "http://localhost:4318/v1/logs"
+log_files = []
+service_name = "checkout"
+host_name = "golden-host"
+max_attribute_value_length = 16
+max_attributes_per_record = 4
+mark_mutations = true
+
+[attribute_hints]
+resource = ["env"]
+trace_id = "trace_id"
+span_id = "span_id"
+component = "app"
+"#;
+
+fn at(time: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(time)
+        .unwrap()
+        .with_timezone(&Utc)
+}
+
+fn record(line: &str, attributes: &[(&str, &str)]) -> Record {
+    Record {
+        body: Body::Text(line.to_string()),
+        attributes: attributes
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        default_severity: None,
+        severity: None,
+        service_name: None,
+        timestamp: None,
+        observed: at("2024-03-05T10:00:00.123456789Z"),
+        otlp_path: None,
+        trace: None,
+        resource: None,
+        checkpoint: None,
+    }
+}
+
+/// The synthetic records, by the name of their golden files; each case is one request.
+fn corpus() -> Vec<(&'static str, Vec<Record>)> {
+    vec![
+        ("plain", vec![record("GET /health 200", &[])]),
+        (
+            "severity_and_time",
+            vec![
+                Record {
+                    timestamp: Some(at("2024-03-05T09:59:58.5Z")),
+                    ..record(
+                        "ERROR payment declined",
+                        &[("log.file", "/var/log/app.log")],
+                    )
+                },
+                Record {
+                    default_severity: Some(Severity::WARN),
+                    ..record("retrying", &[])
+                },
+                Record {
+                    severity: Some(Severity::FATAL),
+                    ..record("INFO overridden by a processor", &[])
+                },
+            ],
+        ),
+        (
+            "hints",
+            vec![record(
+                "charged",
+                &[
+                    ("env", "prod"),
+                    ("app", "billing"),
+                    ("trace_id", "4bf92f3577b34da6a3ce929d0e0e4736"),
+                    ("span_id", "00f067aa0ba902b7"),
+                ],
+            )],
+        ),
+        (
+            "trace_context",
+            vec![Record {
+                trace: Some(Arc::new(TraceContext {
+                    trace_id: "0af7651916cd43dd8448eb211c80319c".to_string(),
+                    span_id: "b7ad6b7169203331".to_string(),
+                })),
+                ..record("traced", &[])
+            }],
+        ),
+        (
+            "bytes_body",
+            vec![Record {
+                body: Body::Bytes(b"\xff\xfeUTF-16 or not\x00".to_vec()),
+                ..record("\u{fffd}\u{fffd}UTF-16 or not", &[])
+            }],
+        ),
+        (
+            "limits",
+            vec![record(
+                "many attributes",
+                &[
+                    ("user.agent", "Mozilla/5.0 (X11; Linux x86_64)"),
+                    ("a", "1"),
+                    ("b", "2"),
+                    ("c", "3"),
+                    ("d", "4"),
+                    ("log.mutations", "redaction"),
+                ],
+            )],
+        ),
+        (
+            "resources",
+            vec![
+                Record {
+                    service_name: Some("api".into()),
+                    ..record("a", &[])
+                },
+                Record {
+                    resource: Some(Arc::from(vec![
+                        ("host.name".to_string(), "db1".to_string()),
+                        ("container.name".to_string(), "postgres".to_string()),
+                    ])),
+                    ..record("b", &[])
+                },
+                Record {
+                    service_name: Some("api".into()),
+                    ..record("c", &[])
+                },
+            ],
+        ),
+    ]
+}
+
+/// Each golden file by name, with what the corpus encodes to: the OTLP/JSON request,
+/// pretty-printed so that a change reads as a diff, and with the protobuf feature the
+/// OTLP protobuf request.
+pub fn render() -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    let mut config: Config = toml::from_str(CONFIG)?;
+    config.instance_id = Some("3f2c1e9a-0000-4000-8000-000000000000".to_string());
+    let mut files = Vec::new();
+    for (name, entries) in corpus() {
+        let payload = merge_otlp_payloads(
+            entries
+                .iter()
+                .map(|entry| build_otlp_payload(entry, severity_of(entry, &config), &config))
+                .collect(),
+        );
+        let mut json = serde_json::to_vec_pretty(&payload)?;
+        json.push(b'\n');
+        files.push((format!("{}.json", name), json));
+        #[cfg(feature = "protobuf")]
+        files.push((
+            format!("{}.pb", name),
+            crate::protobuf::encode(&payload).map_err(anyhow::Error::msg)?,
+        ));
+    }
+    Ok(files)
+}
+
+/// `--emit-golden`: writes the golden files to `dir`, after a wire format change that
+/// was meant.
+pub fn emit(dir: &str) -> anyhow::Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir))?;
+    let files = render()?;
+    for (name, contents) in &files {
+        let path = Path::new(dir).join(name);
+        fs::write(&path, contents).with_context(|| format!("Cannot write {}", path.display()))?;
+    }
+    println!("Wrote {} golden files to {}", files.len(), dir);
+    if !cfg!(feature = "protobuf") {
+        println!("Built without the protobuf feature: the .pb files were left as they are");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wire_format_matches_the_golden_files() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(DIR);
+        let changed: Vec<String> = render()
+            .unwrap()
+            .into_iter()
+            .filter(|(name, contents)| fs::read(dir.join(name)).ok().as_ref() != Some(contents))
+            .map(|(name, _)| name)
+            .collect();
+        assert!(
+            changed.is_empty(),
+            "The OTLP requests differ from {:?} in {}; if that is intended, run \
+             `cargo run --features protobuf -- --emit-golden` and review the diff",
+            changed,
+            DIR
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::{Context, Result};
+use opentelemetry_proto::tonic::collector::logs::v1::logs_service_client::LogsServiceClient;
+use serde::Serialize;
+use std::time::Duration;
+use tonic::codec::CompressionEncoding;
+use tonic::metadata::{Ascii, MetadataKey, MetadataValue};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+
+use crate::exporter::{default_user_agent, Credentials};
+use crate::{endpoint_origin, Config};
+
+/// An OTLP/gRPC connection to a collector's logs service. Requests are made from the
+/// exporter's thread, on a runtime of its own.
+pub struct Client {
+    runtime: tokio::runtime::Runtime,
+    logs: LogsServiceClient<Channel>,
+    /// The headers of the credentials, as request metadata.
+    metadata: Vec<(MetadataKey<Ascii>, MetadataValue<Ascii>)>,
+}
+
+impl Client {
+    /// Connects lazily to the origin of `endpoint`; a path in it is ignored, the service
+    /// path being fixed by OTLP/gRPC.
+    pub fn new(
+        endpoint: &str,
+        config: &Config,
+        credentials: &Credentials,
+        timeout: Duration,
+    ) -> Result<Client> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("Failed to start the gRPC runtime")?;
+        let origin = endpoint_origin(endpoint);
+        let mut channel = Endpoint::from_shared(origin.to_string())
+            .with_context(|| format!("Invalid gRPC endpoint {}", endpoint))?
+            .timeout(timeout)
+            .connect_timeout(timeout)
+            .user_agent(config.user_agent.clone().unwrap_or_else(default_user_agent))?;
+        if origin.starts_with("https://") {
+            let mut tls = ClientTlsConfig::new().with_native_roots();
+            if let Some(ref settings) = config.tls {
+                if settings.insecure_skip_verify {
+                    return Err(anyhow::anyhow!(
+                        "insecure_skip_verify is not supported over gRPC ({})",
+                        endpoint
+                    ));
+                }
+                if let Some(ref ca) = settings.ca_file {
+                    tls = tls.ca_certificate(Certificate::from_pem(crate::tls::read(ca)?));
+                }
+                if let (Some(ref cert), Some(ref key)) = (&settings.cert_file, &settings.key_file) {
+                    tls = tls.identity(Identity::from_pem(
+                        crate::tls::read(cert)?,
+                        crate::tls::read(key)?,
+                    ));
+                }
+            }
+            channel = channel.tls_config(tls)?;
+        }
+        let mut metadata = Vec::new();
+        for (name, value) in &credentials.header_map()? {
+            let mut value: MetadataValue<Ascii> = value.to_str()?.parse()?;
+            value.set_sensitive(true);
+            metadata.push((name.as_str().parse()?, value));
+        }
+        let logs = {
+            let _runtime = runtime.enter();
+            LogsServiceClient::new(channel.connect_lazy())
+        };
+        Ok(Client {
+            runtime,
+            logs,
+            metadata,
+        })
+    }
+
+    /// Sends `payload`, an OTLP/JSON logs request as built for the HTTP transport.
+    pub fn export(&self, payload: &impl Serialize, gzip: bool) -> Result<(), tonic::Status> {
+        let message = crate::protobuf::request(payload).map_err(tonic::Status::internal)?;
+        let mut request = tonic::Request::new(message);
+        for (name, value) in &self.metadata {
+            request.metadata_mut().insert(name.clone(), value.clone());
+        }
+        let mut logs = self.logs.clone();
+        if gzip {
+            logs = logs.send_compressed(CompressionEncoding::Gzip);
+        }
+        self.runtime.block_on(logs.export(request)).map(|_| ())
+    }
+}
@@ -0,0 +1,219 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use crate::Config;
+
+/// `max_open_files`: how many tailed files are held open at once. Past it, the files
+/// written to least recently are closed once they are read to their end, and opened
+/// again at their saved position when they grow, so that tens of thousands of mostly
+/// idle files do not run the agent out of file descriptors.
+pub struct OpenFiles {
+    max: usize,
+    /// Bumped on every read, so the file read last has the highest number.
+    clock: AtomicU64,
+    open: Mutex<Vec<Arc<Slot>>>,
+    reported: AtomicBool,
+}
+
+struct Slot {
+    last_read: AtomicU64,
+    evicted: AtomicBool,
+}
+
+/// A tailed file's place among the open files, given up when it is dropped.
+pub struct Handle {
+    files: Arc<OpenFiles>,
+    slot: Arc<Slot>,
+}
+
+impl std::fmt::Debug for OpenFiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OpenFiles").field("max", &self.max).finish()
+    }
+}
+
+impl OpenFiles {
+    pub fn new(max: usize) -> Arc<OpenFiles> {
+        Arc::new(OpenFiles {
+            max: max.max(1),
+            clock: AtomicU64::new(0),
+            open: Mutex::new(Vec::new()),
+            reported: AtomicBool::new(false),
+        })
+    }
+
+    /// The open files of this process, with `max_open_files` set; the limit of the
+    /// config the first file was opened with holds until a restart.
+    pub fn shared(config: &Config) -> Option<Arc<OpenFiles>> {
+        static SHARED: OnceLock<Option<Arc<OpenFiles>>> = OnceLock::new();
+        SHARED
+            .get_or_init(|| config.max_open_files.map(OpenFiles::new))
+            .clone()
+    }
+
+    /// Makes room for a file to be opened: when the limit is reached, the file read
+    /// least recently is asked to close.
+    pub fn acquire(self: &Arc<Self>) -> Handle {
+        let slot = Arc::new(Slot {
+            last_read: AtomicU64::new(self.clock.fetch_add(1, Ordering::Relaxed)),
+            evicted: AtomicBool::new(false),
+        });
+        let mut open = self.open.lock().unwrap();
+        let staying = open
+            .iter()
+            .filter(|s| !s.evicted.load(Ordering::Relaxed))
+            .count();
+        if staying >= self.max {
+            let idlest = open
+                .iter()
+                .filter(|s| !s.evicted.load(Ordering::Relaxed))
+                .min_by_key(|s| s.last_read.load(Ordering::Relaxed));
+            if let Some(idlest) = idlest {
+                idlest.evicted.store(true, Ordering::Relaxed);
+            }
+            if !self.reported.swap(true, Ordering::Relaxed) {
+                println!(
+                    "{} files are open, as many as max_open_files allows; closing the files written to least recently until they grow",
+                    staying
+                );
+            }
+        }
+        open.push(slot.clone());
+        Handle {
+            files: self.clone(),
+            slot,
+        }
+    }
+}
+
+impl Handle {
+    /// Notes that the file was just read.
+    pub fn touch(&self) {
+        let now = self.files.clock.fetch_add(1, Ordering::Relaxed);
+        self.slot.last_read.store(now, Ordering::Relaxed);
+    }
+
+    /// Whether the file is to be closed at its end, to make room for another.
+    pub fn is_evicted(&self) -> bool {
+        self.slot.evicted.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.files
+            .open
+            .lock()
+            .unwrap()
+            .retain(|s| !Arc::ptr_eq(s, &self.slot));
+    }
+}
+
+/// What a file closed while idle was found to be at a check.
+pub enum Idle {
+    Unchanged,
+    Grown,
+    /// Its input was stopped: it is forgotten.
+    Stopped,
+}
+
+struct Waiting {
+    check: Box<dyn Fn() -> Idle + Send>,
+    reopen: Box<dyn FnOnce() + Send>,
+}
+
+/// `idle_close_secs`: the tailers of files that went that long without a line end, and
+/// the files wait here, closed, for `reopen` to start them again once `check` finds the
+/// file grown. One thread checks every waiting file each `interval`; the interval of the
+/// first file closed holds until a restart.
+pub fn close_idle(
+    interval: Duration,
+    check: impl Fn() -> Idle + Send + 'static,
+    reopen: impl FnOnce() + Send + 'static,
+) {
+    static WAITING: OnceLock<Mutex<Vec<Waiting>>> = OnceLock::new();
+    let mut started = false;
+    let waiting = WAITING.get_or_init(|| {
+        started = true;
+        Mutex::new(Vec::new())
+    });
+    waiting.lock().unwrap().push(Waiting {
+        check: Box::new(check),
+        reopen: Box::new(reopen),
+    });
+    if !started {
+        return;
+    }
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        // Checked without the lock, which files closing meanwhile wait on.
+        let files = std::mem::take(&mut *waiting.lock().unwrap());
+        let mut unchanged = Vec::new();
+        for file in files {
+            match (file.check)() {
+                Idle::Unchanged => unchanged.push(file),
+                Idle::Grown => (file.reopen)(),
+                Idle::Stopped => {}
+            }
+        }
+        waiting.lock().unwrap().extend(unchanged);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_read_least_recently_are_closed_first() {
+        let files = OpenFiles::new(2);
+        let a = files.acquire();
+        let b = files.acquire();
+        a.touch();
+        let c = files.acquire();
+        assert!(b.is_evicted() && !a.is_evicted() && !c.is_evicted());
+
+        // Closed, b makes room; grown again, it takes the place of the idlest.
+        drop(b);
+        c.touch();
+        let b = files.acquire();
+        assert!(a.is_evicted() && !b.is_evicted() && !c.is_evicted());
+        drop(a);
+        let d = files.acquire();
+        assert!(c.is_evicted() && !b.is_evicted() && !d.is_evicted());
+    }
+
+    #[test]
+    fn idle_files_are_reopened_once_grown() {
+        let state = Arc::new(Mutex::new((Idle::Unchanged, 0)));
+        let (check, reopen) = (state.clone(), state.clone());
+        close_idle(
+            Duration::from_millis(20),
+            move || std::mem::replace(&mut check.lock().unwrap().0, Idle::Unchanged),
+            move || reopen.lock().unwrap().1 += 1,
+        );
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stopped_check = stopped.clone();
+        close_idle(
+            Duration::from_millis(20),
+            move || {
+                stopped_check.store(true, Ordering::SeqCst);
+                Idle::Stopped
+            },
+            || panic!("stopped inputs are not reopened"),
+        );
+
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(state.lock().unwrap().1, 0);
+        assert!(stopped.load(Ordering::SeqCst));
+        state.lock().unwrap().0 = Idle::Grown;
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(state.lock().unwrap().1, 1);
+        // Reopened, it no longer waits.
+        state.lock().unwrap().0 = Idle::Grown;
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(state.lock().unwrap().1, 1);
+    }
+}
@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::exporter::{http_client, logs_path_hint, Credentials, Protocol};
+use crate::stats::AgentState;
+use crate::Config;
+
+/// Checks at startup that every HTTP exporter points at a collector taking OTLP logs.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct HandshakeConfig {
+    /// Collector info or health URL, e.g. that of the Collector's `health_check`
+    /// extension, read for the server version.
+    #[serde(default)]
+    pub info_url: Option<String>,
+    /// Refuse to start when a check fails, instead of recording a warning.
+    #[serde(default)]
+    pub fail_fast: bool,
+}
+
+/// Paths of the other OTLP signals, a common copy-paste mistake for the logs endpoint.
+const OTHER_SIGNALS: &[(&str, &str)] = &[("/v1/traces", "traces"), ("/v1/metrics", "metrics")];
+
+/// Fields of an info response that may carry the server version.
+const VERSION_FIELDS: &[&str] = &["version", "collector_version", "build_version"];
+
+/// What the collector behind one endpoint answered.
+struct Answer {
+    version: Option<String>,
+    problem: Option<String>,
+}
+
+/// Runs the checks for the default exporter and every extra one, storing the server
+/// version in their stats. Fails with all problems found when `fail_fast` is set.
+pub fn run(config: &Config, handshake: &HandshakeConfig, state: &AgentState) -> anyhow::Result<()> {
+    let mut exporters = vec![(
+        "signoz",
+        &config.endpoint,
+        Protocol::for_endpoint(config.protocol, &config.endpoint),
+        config.credentials(),
+    )];
+    for extra in &config.exporters {
+        exporters.push((
+            extra.name.as_str(),
+            &extra.endpoint,
+            Protocol::for_endpoint(extra.protocol, &extra.endpoint),
+            extra.credentials(),
+        ));
+    }
+
+    let mut problems = Vec::new();
+    for (name, endpoint, protocol, credentials) in exporters {
+        if protocol != Protocol::Http {
+            continue;
+        }
+        let answer = probe(config, handshake, endpoint, &credentials);
+        if let Some(ref version) = answer.version {
+            println!("Exporter {} talks to {}", name, version);
+            if let Some(stats) = state
+                .exporters
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|e| e.name == name)
+            {
+                *stats.server_version.lock().unwrap() = Some(version.clone());
+            }
+        }
+        if let Some(problem) = answer.problem {
+            let message = format!(
+                "Handshake with exporter {} ({}): {}",
+                name, endpoint, problem
+            );
+            state.record_error(message.clone());
+            problems.push(message);
+        }
+    }
+    if handshake.fail_fast && !problems.is_empty() {
+        return Err(anyhow::anyhow!("{}", problems.join("; ")));
+    }
+    Ok(())
+}
+
+fn probe(
+    config: &Config,
+    handshake: &HandshakeConfig,
+    endpoint: &str,
+    credentials: &Credentials,
+) -> Answer {
+    let path = endpoint
+        .split('?')
+        .next()
+        .unwrap_or(endpoint)
+        .trim_end_matches('/');
+    if let Some((_, signal)) = OTHER_SIGNALS
+        .iter()
+        .find(|(suffix, _)| path.ends_with(suffix))
+    {
+        return Answer {
+            version: None,
+            problem: Some(format!(
+                "the endpoint is that of the {} signal; logs go to /v1/logs",
+                signal
+            )),
+        };
+    }
+    let client = match http_client(config, credentials, Duration::from_secs(10)) {
+        Ok(client) => client,
+        Err(e) => {
+            return Answer {
+                version: None,
+                problem: Some(format!("{e:#}")),
+            }
+        }
+    };
+
+    // An empty export request is valid OTLP and ships nothing; a collector without a
+    // logs receiver on this path answers 404.
+    let (mut version, problem) = match client
+        .post(endpoint)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(r#"{"resourceLogs":[]}"#)
+        .send()
+    {
+        Ok(response) => {
+            let status = response.status();
+            let server = response
+                .headers()
+                .get(reqwest::header::SERVER)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string);
+            let problem = match status.as_u16() {
+                200..=299 => None,
+                404 | 405 => Some(format!(
+                    "HTTP {}: the collector does not take OTLP logs on this path; {}",
+                    status,
+                    logs_path_hint(endpoint)
+                )),
+                401 | 403 => Some(format!("HTTP {}: the credentials were refused", status)),
+                _ => Some(format!("HTTP {} to an empty logs request", status)),
+            };
+            (server, problem)
+        }
+        Err(e) => (None, Some(format!("unreachable: {}", e))),
+    };
+
+    if let Some(ref url) = handshake.info_url {
+        match client.get(url).send().and_then(|r| r.error_for_status()) {
+            Ok(response) => {
+                let body: serde_json::Value = response.json().unwrap_or_default();
+                if let Some(found) = VERSION_FIELDS
+                    .iter()
+                    .find_map(|field| body.get(field).and_then(|v| v.as_str()))
+                {
+                    version = Some(found.to_string());
+                }
+            }
+            Err(e) => {
+                // Only the version is missing; the logs endpoint was checked above.
+                eprintln!("Could not read collector info from {}: {}", url, e);
+            }
+        }
+    }
+    Answer { version, problem }
+}
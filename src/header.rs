@@ -0,0 +1,159 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::rules::{self, RegexLimits};
+use crate::tailer::Origins;
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// What precedes the records of every file an input reads from its start, as written by
+/// tools that put a header or byte order mark at the top of each rotated file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HeaderConfig {
+    /// Lines skipped at the start of each file, blank lines not counted.
+    #[serde(default)]
+    pub skip_lines: usize,
+    /// After those, the lines matching this are skipped too, up to the first that does not.
+    pub skip_pattern: Option<String>,
+    /// Drop a UTF-8 byte order mark at the start of the file.
+    #[serde(default = "default_strip_bom")]
+    pub strip_bom: bool,
+}
+
+fn default_strip_bom() -> bool {
+    true
+}
+
+/// Skips the header of a file as its lines come in, from where a line starts at offset 0.
+pub struct HeaderSkipper {
+    path: String,
+    skip_lines: usize,
+    skip_pattern: Option<Regex>,
+    strip_bom: bool,
+    /// Lines skipped since the start of the file, while still in its header.
+    in_header: Option<usize>,
+}
+
+impl HeaderSkipper {
+    pub fn new(
+        path: &str,
+        config: &HeaderConfig,
+        limits: &RegexLimits,
+    ) -> anyhow::Result<HeaderSkipper> {
+        Ok(HeaderSkipper {
+            path: path.to_string(),
+            skip_lines: config.skip_lines,
+            skip_pattern: config
+                .skip_pattern
+                .as_deref()
+                .map(|p| rules::compile(&format!("file_headers {} skip_pattern", path), p, limits))
+                .transpose()?,
+            strip_bom: config.strip_bom,
+            in_header: None,
+        })
+    }
+
+    /// Whether `line` is past the header, with the byte order mark of a line that
+    /// `starts_file` removed.
+    pub fn admit(&mut self, line: &mut Vec<u8>, starts_file: bool) -> bool {
+        if starts_file {
+            if self.strip_bom && line.starts_with(UTF8_BOM) {
+                line.drain(..UTF8_BOM.len());
+            }
+            self.in_header = Some(0);
+            if line.is_empty() {
+                return false;
+            }
+        }
+        let Some(skipped) = self.in_header else {
+            return true;
+        };
+        let matches = || {
+            self.skip_pattern
+                .as_ref()
+                .is_some_and(|re| re.is_match(&String::from_utf8_lossy(line)))
+        };
+        if skipped < self.skip_lines || matches() {
+            self.in_header = Some(skipped + 1);
+            return false;
+        }
+        if skipped > 0 {
+            println!("Skipped {} header lines of {}", skipped, self.path);
+        }
+        self.in_header = None;
+        true
+    }
+
+    /// Removes the header lines from a batch read at `origins`. A line at offset 0 starts
+    /// a file, a new one or one rewritten from its start; a file read on from a saved
+    /// position is past its header already.
+    pub fn skip(&mut self, lines: &mut Vec<Vec<u8>>, origins: &mut Origins) {
+        let mut kept = Vec::with_capacity(lines.len());
+        let mut offsets = Vec::with_capacity(lines.len());
+        for (mut line, offset) in lines.drain(..).zip(origins.offsets.drain(..)) {
+            if self.admit(&mut line, offset == 0) {
+                kept.push(line);
+                offsets.push(offset);
+            }
+        }
+        *lines = kept;
+        origins.offsets = offsets;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_lines_are_skipped_at_the_start_of_each_file() {
+        let config: HeaderConfig = toml::from_str("skip_lines = 1\nskip_pattern = '^#'").unwrap();
+        let mut header =
+            HeaderSkipper::new("/var/log/export.csv", &config, &RegexLimits::default()).unwrap();
+        let mut batch = |lines: &[&str], offsets: &[u64]| {
+            let mut lines = lines.iter().map(|l| l.as_bytes().to_vec()).collect();
+            let mut origins = Origins {
+                inode: 1,
+                offsets: offsets.to_vec(),
+            };
+            header.skip(&mut lines, &mut origins);
+            (
+                lines
+                    .into_iter()
+                    .map(|l| String::from_utf8(l).unwrap())
+                    .collect::<Vec<_>>(),
+                origins.offsets,
+            )
+        };
+
+        assert_eq!(
+            batch(&["\u{feff}Export v2", "# columns: a,b"], &[0, 13]),
+            (vec![], vec![])
+        );
+        // The header goes on into the next batch, up to its first record.
+        assert_eq!(
+            batch(&["# generated", "1,2", "# not a header"], &[28, 40, 44]),
+            (
+                vec!["1,2".to_string(), "# not a header".to_string()],
+                vec![40, 44]
+            )
+        );
+        // A rotated file starts over.
+        assert_eq!(
+            batch(&["\u{feff}Export v2", "3,4"], &[0, 13]),
+            (vec!["3,4".to_string()], vec![13])
+        );
+
+        let bom_only: HeaderConfig = toml::from_str("").unwrap();
+        let mut header = HeaderSkipper::new("a.log", &bom_only, &RegexLimits::default()).unwrap();
+        let mut lines = vec!["\u{feff}first".as_bytes().to_vec()];
+        header.skip(
+            &mut lines,
+            &mut Origins {
+                inode: 1,
+                offsets: vec![0],
+            },
+        );
+        assert_eq!(lines, vec![b"first".to_vec()]);
+    }
+}
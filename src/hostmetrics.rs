@@ -0,0 +1,207 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use sysinfo::{Disks, Networks, System};
+
+use crate::clock::Clock;
+use crate::metrics::{self, Gauge, Metric, MetricData, MetricsExporter, NumberDataPoint, Sum};
+use crate::stats::AgentState;
+use crate::{string_kv, Config, KeyValue};
+
+/// CPU, memory, filesystem and network metrics of the host, named like those of the
+/// OpenTelemetry Collector's `hostmetrics` receiver.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HostMetricsConfig {
+    #[serde(default = "default_interval")]
+    pub interval_secs: u64,
+}
+
+fn default_interval() -> u64 {
+    60
+}
+
+/// Collects one set of points per metric; counters read since boot start at boot time.
+struct Collector {
+    system: System,
+    disks: Disks,
+    networks: Networks,
+    boot: String,
+}
+
+impl Collector {
+    fn new() -> Collector {
+        let mut system = System::new();
+        // CPU usage is measured between two refreshes, so the first one happens here.
+        system.refresh_cpu();
+        let boot = DateTime::from_timestamp(System::boot_time() as i64, 0).unwrap_or_default();
+        Collector {
+            system,
+            disks: Disks::new_with_refreshed_list(),
+            networks: Networks::new_with_refreshed_list(),
+            boot: metrics::unix_nanos(boot),
+        }
+    }
+
+    fn collect(&mut self, now: DateTime<Utc>) -> Vec<Metric> {
+        self.system.refresh_cpu();
+        self.system.refresh_memory();
+        self.disks.refresh_list();
+        self.networks.refresh();
+
+        let now = metrics::unix_nanos(now);
+        let point = |attributes: Vec<KeyValue>, value: f64| NumberDataPoint {
+            attributes,
+            start_time_unix_nano: self.boot.clone(),
+            time_unix_nano: now.clone(),
+            as_double: value,
+        };
+        let gauge = |name: &str, unit: &str, data_points| Metric {
+            name: name.to_string(),
+            unit: unit.to_string(),
+            data: MetricData::Gauge(Gauge { data_points }),
+        };
+        let sum = |name: &str, unit: &str, is_monotonic, data_points| Metric {
+            name: name.to_string(),
+            unit: unit.to_string(),
+            data: MetricData::Sum(Sum {
+                aggregation_temporality: metrics::CUMULATIVE,
+                is_monotonic,
+                data_points,
+            }),
+        };
+        let mut out = Vec::new();
+
+        let cpus = self.system.cpus();
+        out.push(gauge(
+            "system.cpu.utilization",
+            "1",
+            cpus.iter()
+                .map(|cpu| {
+                    point(
+                        vec![string_kv("cpu", cpu.name())],
+                        cpu.cpu_usage() as f64 / 100.0,
+                    )
+                })
+                .collect(),
+        ));
+        let load = System::load_average();
+        for (name, value) in [("1m", load.one), ("5m", load.five), ("15m", load.fifteen)] {
+            out.push(gauge(
+                &format!("system.cpu.load_average.{}", name),
+                "{thread}",
+                vec![point(Vec::new(), value)],
+            ));
+        }
+
+        let total = self.system.total_memory() as f64;
+        let available = self.system.available_memory() as f64;
+        let states = [("used", total - available), ("free", available)];
+        out.push(sum(
+            "system.memory.usage",
+            "By",
+            false,
+            states
+                .iter()
+                .map(|&(state, bytes)| point(vec![string_kv("state", state)], bytes))
+                .collect(),
+        ));
+        if total > 0.0 {
+            out.push(gauge(
+                "system.memory.utilization",
+                "1",
+                states
+                    .iter()
+                    .map(|&(state, bytes)| point(vec![string_kv("state", state)], bytes / total))
+                    .collect(),
+            ));
+        }
+
+        let (mut usage, mut utilization) = (Vec::new(), Vec::new());
+        for disk in self.disks.list() {
+            let attributes = |state: &str| {
+                vec![
+                    string_kv("device", &disk.name().to_string_lossy()),
+                    string_kv("mountpoint", &disk.mount_point().to_string_lossy()),
+                    string_kv("type", &disk.file_system().to_string_lossy()),
+                    string_kv("state", state),
+                ]
+            };
+            let total = disk.total_space() as f64;
+            let free = disk.available_space() as f64;
+            usage.push(point(attributes("used"), total - free));
+            usage.push(point(attributes("free"), free));
+            if total > 0.0 {
+                utilization.push(point(attributes("used"), (total - free) / total));
+            }
+        }
+        out.push(sum("system.filesystem.usage", "By", false, usage));
+        out.push(gauge("system.filesystem.utilization", "1", utilization));
+
+        let (mut io, mut packets, mut errors) = (Vec::new(), Vec::new(), Vec::new());
+        for (device, data) in self.networks.iter() {
+            let attributes = |direction: &str| {
+                vec![
+                    string_kv("device", device),
+                    string_kv("direction", direction),
+                ]
+            };
+            io.push(point(attributes("receive"), data.total_received() as f64));
+            io.push(point(
+                attributes("transmit"),
+                data.total_transmitted() as f64,
+            ));
+            packets.push(point(
+                attributes("receive"),
+                data.total_packets_received() as f64,
+            ));
+            packets.push(point(
+                attributes("transmit"),
+                data.total_packets_transmitted() as f64,
+            ));
+            errors.push(point(
+                attributes("receive"),
+                data.total_errors_on_received() as f64,
+            ));
+            errors.push(point(
+                attributes("transmit"),
+                data.total_errors_on_transmitted() as f64,
+            ));
+        }
+        out.push(sum("system.network.io", "By", true, io));
+        out.push(sum("system.network.packets", "{packet}", true, packets));
+        out.push(sum("system.network.errors", "{error}", true, errors));
+
+        out.retain(|metric| match &metric.data {
+            MetricData::Gauge(g) => !g.data_points.is_empty(),
+            MetricData::Sum(s) => !s.data_points.is_empty(),
+            MetricData::Summary(s) => !s.data_points.is_empty(),
+        });
+        out
+    }
+}
+
+/// Exports the host's metrics every `interval_secs`, retrying failed requests like the
+/// log exporters do.
+pub fn run(
+    host_metrics: HostMetricsConfig,
+    config: Arc<Config>,
+    state: Arc<AgentState>,
+    clock: Arc<dyn Clock>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let exporter = MetricsExporter::new(&config);
+        let interval = Duration::from_secs(host_metrics.interval_secs.max(1));
+        let mut collector = Collector::new();
+
+        loop {
+            clock.sleep(interval);
+            let metrics = collector.collect(clock.now());
+            let payload = metrics::build_metrics_payload(&config, "hostmetrics", metrics);
+            if let Err(e) = exporter.export_with_retry(clock.as_ref(), &payload) {
+                state.record_error(format!("Host metrics: {}", e));
+            }
+        }
+    })
+}
@@ -0,0 +1,339 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::stats::{AgentState, StatusSnapshot};
+
+/// Prefix of the names on `/metrics`.
+const PREFIX: &str = "rust_signoz_agent";
+
+/// An HTTP server for health checks and Prometheus scrapes.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HttpStatusConfig {
+    #[serde(default = "default_bind")]
+    pub bind: String,
+}
+
+fn default_bind() -> String {
+    "127.0.0.1:9464".to_string()
+}
+
+/// Binds `bind` and answers `GET /healthz` and `GET /metrics`.
+pub fn serve(bind: &str, state: Arc<AgentState>) -> Result<thread::JoinHandle<()>> {
+    let listener = TcpListener::bind(bind)
+        .with_context(|| format!("Failed to bind status server {}", bind))?;
+    println!("Status server on http://{}", bind);
+
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let state = state.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = handle_connection(stream, &state) {
+                            eprintln!("Status server error: {e}");
+                        }
+                    });
+                }
+                Err(e) => eprintln!("Status server accept failed: {e}"),
+            }
+        }
+    }))
+}
+
+fn handle_connection(stream: TcpStream, state: &AgentState) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // The headers are not needed, but are read so that the client sees its request taken.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let (method, path) = (
+        parts.next(),
+        parts.next().map(|p| p.split('?').next().unwrap()),
+    );
+    let (status, content_type, body) = match (method, path) {
+        (Some("GET"), Some("/healthz")) => match unhealthy(state) {
+            None => ("200 OK", "text/plain", "ok\n".to_string()),
+            Some(reason) => (
+                "503 Service Unavailable",
+                "text/plain",
+                format!("{}\n", reason),
+            ),
+        },
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            prometheus(&state.snapshot()),
+        ),
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n".to_string(),
+        ),
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+/// Why `/healthz` fails: the agent is shutting down, an exporter's endpoint answers 404
+/// so it would never deliver anything, or the last request of every exporter failed.
+pub fn unhealthy(state: &AgentState) -> Option<&'static str> {
+    if state.stopping.load(Ordering::SeqCst) {
+        return Some("shutting down");
+    }
+    let exporters = state.exporters.lock().unwrap();
+    if exporters
+        .iter()
+        .any(|e| e.no_logs_endpoint.load(Ordering::SeqCst))
+    {
+        return Some("an exporter's endpoint has no logs path");
+    }
+    if !exporters.is_empty()
+        && exporters
+            .iter()
+            .all(|e| e.consecutive_failures.load(Ordering::SeqCst) > 0)
+    {
+        return Some("every exporter is failing");
+    }
+    None
+}
+
+/// The snapshot in the Prometheus text format.
+fn prometheus(snapshot: &StatusSnapshot) -> String {
+    let mut out = String::new();
+    let mut family = |name: &str, kind: &str, help: &str, samples: Vec<(String, f64)>| {
+        writeln!(out, "# HELP {}_{} {}", PREFIX, name, help).unwrap();
+        writeln!(out, "# TYPE {}_{} {}", PREFIX, name, kind).unwrap();
+        for (labels, value) in samples {
+            writeln!(out, "{}_{}{} {}", PREFIX, name, labels, value).unwrap();
+        }
+    };
+    let unlabeled = |value: f64| vec![(String::new(), value)];
+    let labeled = |label: &str, value: &str| format!("{{{}=\"{}\"}}", label, escape(value));
+    let per_input = |value: fn(&crate::stats::InputSnapshot) -> u64| {
+        snapshot
+            .inputs
+            .iter()
+            .map(|i| (labeled("input", &i.name), value(i) as f64))
+            .collect()
+    };
+    let per_listener = |value: fn(&crate::stats::ListenerSnapshot) -> u64| {
+        snapshot
+            .inputs
+            .iter()
+            .filter_map(|i| {
+                Some((
+                    labeled("input", &i.name),
+                    value(i.listener.as_ref()?) as f64,
+                ))
+            })
+            .collect()
+    };
+    let per_exporter = |value: fn(&crate::stats::ExporterSnapshot) -> f64| {
+        snapshot
+            .exporters
+            .iter()
+            .map(|e| (labeled("exporter", &e.name), value(e)))
+            .collect()
+    };
+
+    family(
+        "input_lines_total",
+        "counter",
+        "Lines read per input.",
+        per_input(|i| i.lines),
+    );
+    family(
+        "input_lag_bytes",
+        "gauge",
+        "Bytes written to a file but not read yet.",
+        per_input(|i| i.lag_bytes),
+    );
+    family(
+        "input_resyncs_total",
+        "counter",
+        "Lines that were not one whole record.",
+        per_input(|i| i.resyncs),
+    );
+    family(
+        "input_volume_anomalies_total",
+        "counter",
+        "Minutes whose lines jumped or dropped against the input's baseline.",
+        per_input(|i| i.volume_anomalies),
+    );
+    family(
+        "input_parse_failures_total",
+        "counter",
+        "Lines the input's log format did not parse, with parse_quarantine set.",
+        per_input(|i| i.parse_failures),
+    );
+    family(
+        "input_quarantined",
+        "gauge",
+        "1 while too many of an input's lines failed to parse.",
+        per_input(|i| i.quarantined as u64),
+    );
+    family(
+        "input_last_line_timestamp_seconds",
+        "gauge",
+        "When an input last read a line, as unix seconds; 0 before its first.",
+        per_input(|i| i.last_line_unix.max(0) as u64),
+    );
+    family(
+        "input_listener_sockets_down",
+        "gauge",
+        "Sockets of a network input waiting to be bound again.",
+        per_listener(|l| l.down),
+    );
+    family(
+        "input_listener_rebinds_total",
+        "counter",
+        "Times a network input's sockets were bound again.",
+        per_listener(|l| l.rebinds),
+    );
+    family(
+        "input_listener_connections",
+        "gauge",
+        "Open TCP connections of a network input.",
+        per_listener(|l| l.connections),
+    );
+    family(
+        "input_listener_idle",
+        "gauge",
+        "1 while a network input received nothing within its max_idle_secs.",
+        per_listener(|l| l.idle as u64),
+    );
+    family(
+        "queued_records",
+        "gauge",
+        "Records read but not delivered yet.",
+        unlabeled(snapshot.queued as f64),
+    );
+    family(
+        "exporter_pending_records",
+        "gauge",
+        "Records waiting for an exporter.",
+        per_exporter(|e| e.pending as f64),
+    );
+    family(
+        "exporter_sent_records_total",
+        "counter",
+        "Records delivered per exporter.",
+        per_exporter(|e| e.sent as f64),
+    );
+    family(
+        "exporter_failed_records_total",
+        "counter",
+        "Records an exporter gave up on.",
+        per_exporter(|e| e.failed as f64),
+    );
+    family(
+        "exporter_retries_total",
+        "counter",
+        "Attempts made after a request's first one failed.",
+        per_exporter(|e| e.retries as f64),
+    );
+    family(
+        "exporter_buffered_records",
+        "gauge",
+        "Records waiting in an exporter's disk buffer.",
+        per_exporter(|e| e.buffered as f64),
+    );
+    family(
+        "exporter_auth_failures_total",
+        "counter",
+        "Requests rejected with 401/403.",
+        per_exporter(|e| e.auth_failures as f64),
+    );
+    family(
+        "dropped_records_total",
+        "counter",
+        "Records dropped before export, by reason.",
+        snapshot
+            .dropped
+            .iter()
+            .map(|(reason, count)| (labeled("reason", reason), *count as f64))
+            .collect(),
+    );
+    family(
+        "stage_records_total",
+        "counter",
+        "Records through each pipeline stage.",
+        snapshot
+            .stages
+            .iter()
+            .map(|s| (labeled("stage", &s.stage), s.records as f64))
+            .collect(),
+    );
+    family(
+        "stage_busy_seconds_total",
+        "counter",
+        "Time spent in each pipeline stage.",
+        snapshot
+            .stages
+            .iter()
+            .map(|s| (labeled("stage", &s.stage), s.busy_nanos as f64 / 1e9))
+            .collect(),
+    );
+    family(
+        "config_reload_failures_total",
+        "counter",
+        "Edited configs rejected on SIGHUP.",
+        unlabeled(snapshot.config_reload_failures as f64),
+    );
+    family(
+        "suspect_timestamps_total",
+        "counter",
+        "Records whose event time was too far from the time read.",
+        unlabeled(snapshot.suspect_timestamps as f64),
+    );
+    family(
+        "regex_cache_patterns",
+        "gauge",
+        "Distinct regex patterns compiled.",
+        unlabeled(snapshot.regex_cache.patterns as f64),
+    );
+    family(
+        "regex_cache_hits_total",
+        "counter",
+        "Regex compilations saved by reusing a pattern compiled before.",
+        unlabeled(snapshot.regex_cache.hits as f64),
+    );
+    family(
+        "exports_paused",
+        "gauge",
+        "1 while exporting is paused.",
+        unlabeled(if snapshot.exports_paused { 1.0 } else { 0.0 }),
+    );
+    out
+}
+
+/// A label value with `\`, `"` and newlines escaped.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,324 @@
+use anyhow::Context;
+use chrono::Utc;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::input::key_matches;
+use crate::rules::{self, RegexLimits};
+use crate::stats::AgentState;
+use crate::Record;
+use crate::{queue, Attributes, Body, Severity};
+
+/// Subdirectory of the state directory the bundles are written to.
+pub const DIR: &str = "incidents";
+
+/// A pattern that, once a file-backed input reads a line matching it, has the lines
+/// around it kept in a bundle in the state directory for whoever responds.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IncidentConfig {
+    /// Names the bundles, and the queue partition `incident:<name>` of exported ones.
+    pub name: String,
+    pub pattern: String,
+    /// Input names or patterns watched; every file-backed input when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<String>,
+    /// Lines kept from before the matching line.
+    #[serde(default = "default_context_lines")]
+    pub before: usize,
+    /// Lines added after it, as they are read.
+    #[serde(default = "default_context_lines")]
+    pub after: usize,
+    /// Matches within this many seconds of a bundle of the input are part of it.
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: u64,
+    /// Bundles kept of this incident; the oldest is removed beyond that.
+    #[serde(default = "default_max_bundles")]
+    pub max_bundles: usize,
+    /// Also ship the matching line and the lines before it as one record, right away.
+    #[serde(default)]
+    pub export: bool,
+}
+
+fn default_context_lines() -> usize {
+    50
+}
+
+fn default_cooldown_secs() -> u64 {
+    300
+}
+
+fn default_max_bundles() -> usize {
+    20
+}
+
+impl IncidentConfig {
+    pub fn validate(&self, limits: &RegexLimits) -> anyhow::Result<Regex> {
+        if self.name.is_empty()
+            || !self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(anyhow::anyhow!(
+                "Incident name {:?} must be letters, digits, - and _",
+                self.name
+            ));
+        }
+        if self.max_bundles == 0 {
+            return Err(anyhow::anyhow!(
+                "Incident {} max_bundles must be greater than 0",
+                self.name
+            ));
+        }
+        rules::compile(
+            &format!("incidents {} pattern", self.name),
+            &self.pattern,
+            limits,
+        )
+    }
+}
+
+/// A bundle still taking lines after its match.
+struct Open {
+    file: fs::File,
+    remaining: usize,
+}
+
+struct Trigger {
+    config: IncidentConfig,
+    pattern: Regex,
+    tx: Option<queue::Sender>,
+    open: Option<Open>,
+    last: Option<Instant>,
+}
+
+/// The incidents one input watches for, with the lines it read last.
+pub struct Capture {
+    input: String,
+    dir: String,
+    triggers: Vec<Trigger>,
+    recent: VecDeque<String>,
+    keep: usize,
+    state: Arc<AgentState>,
+}
+
+impl Capture {
+    /// The incidents `input` is watched for, if any; exported bundles go to `queue`.
+    pub fn new(
+        input: &str,
+        incidents: &[IncidentConfig],
+        limits: &RegexLimits,
+        state_dir: &str,
+        queue: &Arc<queue::FairQueue>,
+        state: Arc<AgentState>,
+    ) -> Option<Capture> {
+        let triggers: Vec<Trigger> = incidents
+            .iter()
+            .filter(|i| i.inputs.is_empty() || i.inputs.iter().any(|k| key_matches(k, input)))
+            .filter_map(|i| {
+                Some(Trigger {
+                    pattern: i.validate(limits).ok()?,
+                    tx: i
+                        .export
+                        .then(|| queue.sender(&format!("incident:{}", i.name))),
+                    config: i.clone(),
+                    open: None,
+                    last: None,
+                })
+            })
+            .collect();
+        if triggers.is_empty() {
+            return None;
+        }
+        Some(Capture {
+            input: input.to_string(),
+            dir: crate::statedir::file_in(state_dir, DIR),
+            keep: triggers.iter().map(|t| t.config.before).max().unwrap_or(0),
+            triggers,
+            recent: VecDeque::new(),
+            state,
+        })
+    }
+
+    /// Looks at the next line read: adds it to the open bundles and opens one when it
+    /// matches an incident.
+    pub fn observe(&mut self, line: &str) {
+        for trigger in &mut self.triggers {
+            if let Some(ref mut open) = trigger.open {
+                writeln!(open.file, "{}", line).ok();
+                open.remaining -= 1;
+                if open.remaining == 0 {
+                    trigger.open = None;
+                }
+            }
+            let cooling = trigger.last.is_some_and(|last| {
+                last.elapsed() < Duration::from_secs(trigger.config.cooldown_secs)
+            });
+            if cooling || !trigger.pattern.is_match(line) {
+                continue;
+            }
+            trigger.last = Some(Instant::now());
+            let before: Vec<&str> = self
+                .recent
+                .iter()
+                .skip(self.recent.len().saturating_sub(trigger.config.before))
+                .map(String::as_str)
+                .collect();
+            match open_bundle(&self.dir, &trigger.config, &self.input, &before, line) {
+                Ok((path, file)) => {
+                    println!(
+                        "Incident {} on {}: context saved to {}",
+                        trigger.config.name, self.input, path
+                    );
+                    trigger.open = (trigger.config.after > 0).then_some(Open {
+                        file,
+                        remaining: trigger.config.after,
+                    });
+                }
+                Err(e) => self.state.record_error(format!(
+                    "Failed to save incident {} on {}: {:#}",
+                    trigger.config.name, self.input, e
+                )),
+            }
+            if let Some(ref tx) = trigger.tx {
+                let mut body = before.join("\n");
+                if !body.is_empty() {
+                    body.push('\n');
+                }
+                body.push_str(line);
+                crate::enqueue(
+                    tx,
+                    &self.state,
+                    Record {
+                        body: Body::Text(body),
+                        attributes: Attributes::from(vec![
+                            ("incident.name".to_string(), trigger.config.name.clone()),
+                            ("log.file".to_string(), self.input.clone()),
+                        ]),
+                        default_severity: Some(Severity::ERROR),
+                        severity: None,
+                        service_name: None,
+                        timestamp: None,
+                        observed: Utc::now(),
+                        otlp_path: None,
+                        trace: None,
+                        resource: None,
+                        checkpoint: None,
+                    },
+                );
+            }
+        }
+        if self.keep > 0 {
+            if self.recent.len() == self.keep {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(line.to_string());
+        }
+    }
+}
+
+/// Writes the start of a bundle, the lines `before` and the matching `line`, to a new file
+/// in `dir`, removing the oldest bundles of the incident beyond `max_bundles`.
+fn open_bundle(
+    dir: &str,
+    incident: &IncidentConfig,
+    input: &str,
+    before: &[&str],
+    line: &str,
+) -> anyhow::Result<(String, fs::File)> {
+    fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir))?;
+    let now = Utc::now();
+    let suffix = format!("-{}.log", incident.name);
+    let path = crate::statedir::file_in(
+        dir,
+        &format!("{}{}", now.format("%Y%m%dT%H%M%S%.3fZ"), suffix),
+    );
+    let mut file = fs::File::create(&path).with_context(|| format!("Cannot create {}", path))?;
+    writeln!(
+        file,
+        "# Incident {} on {} at {}\n# Matched: {}",
+        incident.name,
+        input,
+        now.to_rfc3339(),
+        line
+    )?;
+    for context in before {
+        writeln!(file, "{}", context)?;
+    }
+    writeln!(file, "{}", line)?;
+
+    // The names start with their time, so they sort oldest first.
+    let mut bundles: Vec<String> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok()?.file_name().into_string().ok())
+        .filter(|name| name.ends_with(&suffix))
+        .collect();
+    bundles.sort();
+    let excess = bundles.len().saturating_sub(incident.max_bundles);
+    for name in &bundles[..excess] {
+        fs::remove_file(crate::statedir::file_in(dir, name)).ok();
+    }
+    Ok((path, file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn matching_line_is_saved_with_its_context() {
+        let temp = TempDir::new("incident-test");
+        let dir = temp.display().to_string();
+        let state = Arc::new(AgentState::default());
+        let queue = Arc::new(queue::FairQueue::new(
+            Default::default(),
+            queue::DEFAULT_CAPACITY,
+            queue::Overflow::Block,
+            state.clone(),
+            Default::default(),
+        ));
+        let incident: IncidentConfig = toml::from_str(
+            "name = \"oom\"\npattern = \"Out of memory\"\nbefore = 2\nafter = 1\nexport = true",
+        )
+        .unwrap();
+        let mut capture = Capture::new(
+            "/var/log/kern.log",
+            &[incident],
+            &RegexLimits::default(),
+            &dir,
+            &queue,
+            state.clone(),
+        )
+        .unwrap();
+        for line in [
+            "one",
+            "two",
+            "three",
+            "Out of memory: Killed process 42",
+            "four",
+            "five",
+            "Out of memory: Killed process 43",
+        ] {
+            capture.observe(line);
+        }
+
+        let bundles: Vec<_> = fs::read_dir(crate::statedir::file_in(&dir, DIR))
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        // The second match came within the cooldown of the first.
+        assert_eq!(bundles.len(), 1);
+        let bundle = fs::read_to_string(&bundles[0]).unwrap();
+        let lines: Vec<&str> = bundle.lines().skip(2).collect();
+        assert_eq!(
+            lines,
+            vec!["two", "three", "Out of memory: Killed process 42", "four"]
+        );
+        assert_eq!(state.queued.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+}
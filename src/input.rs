@@ -0,0 +1,689 @@
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::checkpoint::Positions;
+use crate::header::HeaderSkipper;
+#[cfg(unix)]
+use crate::journald;
+use crate::queue::FairQueue;
+use crate::rewrite::RewritePolicy;
+use crate::stats::{AgentState, InputStats};
+use crate::tailer::Origins;
+use crate::trace_context::TraceContext;
+use crate::{
+    backfill, catchup, command, docker, eventlog, file_line_handler, listener, queue, replay,
+    snapshot, statsd, stdin, syslog, Config,
+};
+
+/// A source of records. Inputs are built from config, started once and stopped on
+/// shutdown; `checkpoint` is where inputs with a read position persist it.
+/// It compares equal to an input configured alike: a reload restarts an input that is not.
+pub trait Input: Send + std::fmt::Debug + SameConfig {
+    /// Name under which the input's counters are reported, e.g. "snapshot:/proc/meminfo".
+    fn describe(&self) -> String;
+    fn start(&mut self, ctx: &InputContext) -> anyhow::Result<()>;
+    /// Asks the input's threads to wind down; it does not wait for them.
+    fn stop(&mut self);
+    fn checkpoint(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Compares inputs of any type, for every input that is `PartialEq`.
+pub trait SameConfig {
+    fn as_any(&self) -> &dyn Any;
+    /// Whether `other` is an input of the same type, configured alike.
+    fn same_config(&self, other: &dyn Any) -> bool;
+}
+
+impl<T: PartialEq + 'static> SameConfig for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn same_config(&self, other: &dyn Any) -> bool {
+        other.downcast_ref::<T>() == Some(self)
+    }
+}
+
+/// Default `glob_rescan_secs`.
+pub const DEFAULT_RESCAN_SECS: u64 = 5;
+/// How often a watcher waiting for its next rescan checks whether it was stopped.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// What a starting input needs from the agent.
+#[derive(Clone)]
+pub struct InputContext {
+    pub config: Arc<Config>,
+    pub path_rules: Vec<(Regex, Option<String>)>,
+    pub queue: Arc<FairQueue>,
+    pub state: Arc<AgentState>,
+    /// Saved read positions of tailed files.
+    pub positions: Arc<Positions>,
+    /// Turns of the files read from their start on a first run, with `[catch_up]`.
+    pub catch_up: Option<Arc<catchup::CatchUp>>,
+}
+
+impl InputContext {
+    pub fn register(&self, input: &dyn Input) -> Arc<InputStats> {
+        self.state.register_input(&input.describe())
+    }
+
+    /// A watch over a socket of the network input `stats` counts for, named `what`.
+    pub fn listener_watch(&self, what: String, stats: &Arc<InputStats>) -> listener::Watch {
+        let health = option_for(&self.config.listener_health, &stats.name)
+            .cloned()
+            .unwrap_or_default();
+        listener::Watch::new(what, &health, stats.clone(), self.state.clone())
+    }
+
+    /// The shared handler for inputs reading from a file at `path`; it takes a batch of
+    /// lines, enqueued with a single send.
+    pub fn file_line_handler(
+        &self,
+        path: &str,
+        stats: Arc<InputStats>,
+    ) -> impl Fn(Vec<Vec<u8>>, Option<Origins>) + Send + Sync + 'static {
+        let tx = self.queue.sender(&stats.name);
+        self.line_handler(path, stats, tx)
+    }
+
+    /// `file_line_handler` for the inputs replaying a capture, stdin, backfills and
+    /// `ship`, whose records `replay_speed` paces.
+    pub fn replay_line_handler(
+        &self,
+        path: &str,
+        stats: Arc<InputStats>,
+    ) -> impl Fn(Vec<Vec<u8>>, Option<Origins>) + Send + Sync + 'static {
+        self.traced_line_handler(path, stats, None)
+    }
+
+    /// `replay_line_handler` for stdin, whose records belong to `trace`: that of the job
+    /// the agent was started in, named by its `TRACEPARENT`.
+    pub fn traced_line_handler(
+        &self,
+        path: &str,
+        stats: Arc<InputStats>,
+        trace: Option<Arc<TraceContext>>,
+    ) -> impl Fn(Vec<Vec<u8>>, Option<Origins>) + Send + Sync + 'static {
+        let tx = self.replay_sender(&stats.name).traced(trace);
+        self.line_handler(path, stats, tx)
+    }
+
+    /// The queue of the input `name` replaying a capture, paced per `replay_speed`.
+    pub fn replay_sender(&self, name: &str) -> queue::Sender {
+        let tx = self.queue.sender(name);
+        match self.config.replay_speed.as_deref().map(replay::speed) {
+            Some(Ok(speed)) => tx.paced(speed),
+            _ => tx,
+        }
+    }
+
+    fn line_handler(
+        &self,
+        path: &str,
+        stats: Arc<InputStats>,
+        tx: queue::Sender,
+    ) -> impl Fn(Vec<Vec<u8>>, Option<Origins>) + Send + Sync + 'static {
+        file_line_handler(
+            path,
+            &self.config,
+            &self.path_rules,
+            tx,
+            crate::incident::Capture::new(
+                &stats.name,
+                &self.config.incidents,
+                &self.config.regex_limits,
+                &self.config.state_directory(),
+                &self.queue,
+                self.state.clone(),
+            ),
+            self.state.clone(),
+            stats,
+        )
+    }
+}
+
+/// The `file_headers` entry for the file at `path`, falling back to the "*" entry.
+fn header_skipper(config: &Config, path: &str) -> Option<HeaderSkipper> {
+    let header = option_for(&config.file_headers, path).or_else(|| config.file_headers.get("*"))?;
+    HeaderSkipper::new(path, header, &config.regex_limits).ok()
+}
+
+/// Shared flag telling an input's threads to stop. Lives in the input's config struct,
+/// skipped by serde.
+#[derive(Debug, Clone, Default)]
+pub struct StopFlag(Arc<AtomicBool>);
+
+impl StopFlag {
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+// Not part of the settings the input struct is compared by.
+impl PartialEq for StopFlag {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+/// A tailed log file, followed from its saved position, or its end on first sight. A
+/// `path` with glob characters (`*`, `?`, `[`) follows every file matching it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileInput {
+    pub path: String,
+    /// Read through the mmap reader instead of buffered reads.
+    #[serde(default)]
+    pub mmap: bool,
+    /// Overrides the global `rewrite_policy` for this file.
+    pub rewrite_policy: Option<RewritePolicy>,
+    #[serde(skip)]
+    pub stop: StopFlag,
+    /// Read the file from its start on first sight, for files that appeared after startup.
+    #[serde(skip)]
+    pub from_start: bool,
+}
+
+/// Where a tailed file never read before starts.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StartPosition {
+    /// Only lines written after the agent saw the file.
+    #[default]
+    End,
+    /// Everything the file holds, after its rotated copies next to it, oldest first.
+    Beginning,
+}
+
+/// Whether `path` matches an `other_shipper_files` entry, which the agent leaves alone.
+pub fn is_other_shippers(other_shipper_files: &[String], path: &str) -> bool {
+    other_shipper_files.iter().any(|key| key_matches(key, path))
+}
+
+/// Whether `path` is a glob pattern rather than a file.
+pub fn is_pattern(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// The files `path` stands for now: itself, or the current matches of a pattern.
+#[cfg(unix)]
+pub fn expand(path: &str) -> Vec<String> {
+    if !is_pattern(path) {
+        return vec![path.to_string()];
+    }
+    glob::glob_with(path, crate::paths::match_options())
+        .map(|paths| paths.flatten().map(|p| p.display().to_string()).collect())
+        .unwrap_or_default()
+}
+
+/// The directory a pattern's matches are all in: the part before its first component
+/// with glob characters.
+pub fn pattern_root(path: &str) -> String {
+    let root: std::path::PathBuf = std::path::Path::new(path)
+        .components()
+        .take_while(|c| !is_pattern(&c.as_os_str().to_string_lossy()))
+        .collect();
+    root.display().to_string()
+}
+
+/// Whether an option keyed by input name under `key` applies to the input `name`: the
+/// key is its name, or a glob pattern matching it, compared as `paths::key` spells them.
+pub fn key_matches(key: &str, name: &str) -> bool {
+    let (key, name) = (crate::paths::key(key), crate::paths::key(name));
+    key == name || (is_pattern(&key) && glob::Pattern::new(&key).is_ok_and(|p| p.matches(&name)))
+}
+
+/// The entry of `options` for the input `name`: by its own name, else by the longest
+/// pattern matching it.
+pub fn option_for<'a, T>(options: &'a BTreeMap<String, T>, name: &str) -> Option<&'a T> {
+    options.get(name).or_else(|| {
+        options
+            .iter()
+            .filter(|(key, _)| key_matches(key, name))
+            .max_by_key(|(key, _)| key.len())
+            .map(|(_, value)| value)
+    })
+}
+
+impl FileInput {
+    /// Starts the thread tailing the file, which hands batches of lines to `handler`.
+    pub fn tail<F>(
+        &self,
+        config: &Config,
+        positions: Arc<Positions>,
+        state: Arc<AgentState>,
+        stats: Arc<InputStats>,
+        catch_up: Option<catchup::Turn>,
+        mut handler: F,
+    ) where
+        F: FnMut(Vec<Vec<u8>>, Option<Origins>) + Send + 'static,
+    {
+        let (path, stop) = (self.path.clone(), self.stop.clone());
+        let mut header = header_skipper(config, &path);
+        let options = crate::TailOptions {
+            buffer_bytes: config
+                .read_buffer_bytes
+                .unwrap_or(crate::DEFAULT_READ_BUFFER_BYTES),
+            rewrite: self.rewrite_policy.unwrap_or(config.rewrite_policy),
+            deleted_grace: Duration::from_secs(
+                config
+                    .deleted_file_grace_secs
+                    .unwrap_or(crate::tombstone::DEFAULT_GRACE_SECS),
+            ),
+            finish_rotated: config.finish_rotated_files.unwrap_or(true),
+            from_start: self.from_start || config.oneshot,
+            replay_rate: crate::replay::rate(config),
+            // Where a line starts tells whether it is in a file's header.
+            origins: config.origin_id_attribute.is_some() || header.is_some(),
+            catch_up,
+            until_end: config.oneshot.then(|| state.unread()),
+            open_files: crate::handles::OpenFiles::shared(config),
+            idle_close: option_for(&config.idle_close_secs, &path)
+                .or_else(|| config.idle_close_secs.get("*"))
+                .map(|&secs| Duration::from_secs(secs)),
+            rescan: Duration::from_secs(
+                config
+                    .glob_rescan_secs
+                    .unwrap_or(DEFAULT_RESCAN_SECS)
+                    .max(1),
+            ),
+        };
+        let handler = move |mut lines: Vec<Vec<u8>>, mut origins: Option<Origins>| {
+            if let (Some(ref mut header), Some(ref mut origins)) = (&mut header, &mut origins) {
+                header.skip(&mut lines, origins);
+                if lines.is_empty() {
+                    return;
+                }
+            }
+            handler(lines, origins)
+        };
+        if self.mmap {
+            crate::mmap_reader::tail_file_mmap(
+                path, options, positions, state, stats, stop, handler,
+            );
+        } else {
+            crate::tail_file(path, options, positions, state, stats, stop, handler);
+        }
+    }
+
+    /// Follows the files matching the pattern in `path` from a thread rescanning it every
+    /// `glob_rescan_secs`: `start` is called with a tailer for each file found. Files
+    /// found after the first scan are read from their start. Once a file no longer
+    /// matches (it was deleted or renamed) for the deleted file grace period, its tailer
+    /// is stopped and its position forgotten.
+    pub fn watch<F>(
+        &self,
+        config: &Config,
+        positions: Arc<Positions>,
+        state: Arc<AgentState>,
+        mut start: F,
+    ) where
+        F: FnMut(&FileInput) + Send + 'static,
+    {
+        let rescan = Duration::from_secs(
+            config
+                .glob_rescan_secs
+                .unwrap_or(DEFAULT_RESCAN_SECS)
+                .max(1),
+        );
+        let grace = Duration::from_secs(
+            config
+                .deleted_file_grace_secs
+                .unwrap_or(crate::tombstone::DEFAULT_GRACE_SECS),
+        );
+        let pattern = self.clone();
+        let other_shipper_files = config.other_shipper_files.clone();
+        let mut unread = config.oneshot.then(|| state.unread());
+        thread::spawn(move || {
+            let mut tailers: BTreeMap<String, (FileInput, Option<Instant>)> = BTreeMap::new();
+            let mut first_scan = true;
+            while !pattern.stop.is_stopped() {
+                let matches: BTreeSet<String> =
+                    match glob::glob_with(&pattern.path, crate::paths::match_options()) {
+                        Ok(paths) => paths
+                            .flatten()
+                            .filter(|p| p.is_file())
+                            .map(|p| p.display().to_string())
+                            .filter(|p| !is_other_shippers(&other_shipper_files, p))
+                            .collect(),
+                        Err(e) => {
+                            state.record_error(format!("Invalid pattern {}: {}", pattern.path, e));
+                            return;
+                        }
+                    };
+                for path in &matches {
+                    if let Some((_, missing_since)) = tailers.get_mut(path) {
+                        *missing_since = None;
+                        continue;
+                    }
+                    if !first_scan {
+                        println!("Following {}, which matches {}", path, pattern.path);
+                    }
+                    let tailer = FileInput {
+                        path: path.clone(),
+                        mmap: pattern.mmap,
+                        rewrite_policy: pattern.rewrite_policy,
+                        stop: StopFlag::default(),
+                        from_start: !first_scan,
+                    };
+                    start(&tailer);
+                    tailers.insert(path.clone(), (tailer, None));
+                }
+                tailers.retain(|path, (tailer, missing_since)| {
+                    if matches.contains(path)
+                        || missing_since.get_or_insert_with(Instant::now).elapsed() < grace
+                    {
+                        return true;
+                    }
+                    println!(
+                        "Stopped following {}, which no longer matches {}",
+                        path, pattern.path
+                    );
+                    tailer.stop.stop();
+                    positions.forget(path);
+                    false
+                });
+                first_scan = false;
+                // The files found are waited for themselves from now on.
+                drop(unread.take());
+                let scanned = Instant::now();
+                while scanned.elapsed() < rescan && !pattern.stop.is_stopped() {
+                    thread::sleep(STOP_CHECK_INTERVAL);
+                }
+            }
+            for (tailer, _) in tailers.values() {
+                tailer.stop.stop();
+            }
+        });
+    }
+}
+
+impl Input for FileInput {
+    fn describe(&self) -> String {
+        self.path.clone()
+    }
+
+    fn start(&mut self, ctx: &InputContext) -> anyhow::Result<()> {
+        if is_pattern(&self.path) {
+            let tailers = ctx.clone();
+            self.watch(
+                &ctx.config,
+                ctx.positions.clone(),
+                ctx.state.clone(),
+                move |tailer| {
+                    // Starting a file input always succeeds; failures are reported by its thread.
+                    tailer.clone().start(&tailers).ok();
+                },
+            );
+            return Ok(());
+        }
+        if is_other_shippers(&ctx.config.other_shipper_files, &self.path) {
+            println!("Leaving {} to another shipper", self.path);
+            return Ok(());
+        }
+        let stats = ctx.register(self);
+        let handler = ctx.file_line_handler(&self.path, stats.clone());
+        let mut tailer = self.clone();
+        let beginning =
+            option_for(&ctx.config.start_positions, &self.path) == Some(&StartPosition::Beginning);
+        tailer.from_start |= beginning;
+        let first_read = beginning && !ctx.positions.contains(&self.path);
+        let archives = if first_read {
+            crate::archive::rotated(&self.path)
+        } else {
+            Vec::new()
+        };
+        let catch_up = ctx.catch_up.clone().filter(|_| first_read);
+        if archives.is_empty() && catch_up.is_none() {
+            tailer.tail(
+                &ctx.config,
+                ctx.positions.clone(),
+                ctx.state.clone(),
+                stats,
+                None,
+                handler,
+            );
+            return Ok(());
+        }
+
+        // The rotated copies are read before the file itself, which is tailed once they
+        // are done; they are not checkpointed, so a stop before then reads them again.
+        let unread = ctx.config.oneshot.then(|| ctx.state.unread());
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let turn = match catch_up {
+                Some(catch_up) => match catch_up.wait(&tailer.path, &tailer.stop) {
+                    Some(turn) => Some(turn),
+                    None => return,
+                },
+                None => None,
+            };
+            let buffer_bytes = ctx
+                .config
+                .read_buffer_bytes
+                .unwrap_or(crate::DEFAULT_READ_BUFFER_BYTES);
+            let replay_rate = crate::replay::rate(&ctx.config);
+            let mut header = header_skipper(&ctx.config, &tailer.path);
+            for archive in &archives {
+                println!("Reading {} before {}", archive.display(), tailer.path);
+                let mut starts_file = true;
+                let read = crate::archive::read(
+                    archive,
+                    buffer_bytes,
+                    &ctx.state,
+                    &tailer.stop,
+                    |mut lines| {
+                        if let Some(ref mut header) = header {
+                            lines.retain_mut(|line| {
+                                header.admit(line, std::mem::take(&mut starts_file))
+                            });
+                            if lines.is_empty() {
+                                return;
+                            }
+                        }
+                        if let Some(rate) = replay_rate {
+                            if !crate::replay::wait(&ctx.state, rate, lines.len(), &tailer.stop) {
+                                return;
+                            }
+                        }
+                        handler(lines, None)
+                    },
+                );
+                if !read {
+                    return;
+                }
+            }
+            tailer.tail(
+                &ctx.config,
+                ctx.positions.clone(),
+                ctx.state.clone(),
+                stats,
+                turn,
+                handler,
+            );
+            drop(unread);
+        });
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.stop.stop();
+    }
+}
+
+/// An `[[inputs]]` entry: the registry entry named by `type` builds the input from the
+/// remaining keys.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InputSpec {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(flatten)]
+    pub options: toml::Table,
+}
+
+/// A `[[sources]]` entry: a tailed file, as in `log_files`, with settings of its own.
+/// The parsing options stand for entries for its path in the per-input sections.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Source {
+    /// File or glob pattern, as in `log_files`.
+    pub path: String,
+    /// `service.name` of its records, over `service_name` and `path_attributes`.
+    pub service_name: Option<String>,
+    /// Added to the resource of its records.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub resource_attributes: BTreeMap<String, String>,
+    pub log_format: Option<crate::log_format::LogFormat>,
+    pub multiline: Option<crate::multiline::MultilineConfig>,
+    pub timestamp: Option<crate::timestamp::TimestampConfig>,
+    pub start_position: Option<StartPosition>,
+    pub severity_rules: Option<Vec<crate::severity::SeverityRule>>,
+    #[serde(default)]
+    pub mmap: bool,
+}
+
+type InputFactory = fn(toml::Table) -> anyhow::Result<Box<dyn Input>>;
+
+fn build<T: Input + DeserializeOwned + 'static>(
+    options: toml::Table,
+) -> anyhow::Result<Box<dyn Input>> {
+    Ok(Box::new(toml::Value::Table(options).try_into::<T>()?))
+}
+
+/// Input types available to `[[inputs]]`, keyed by their `type`.
+pub fn registry() -> BTreeMap<&'static str, InputFactory> {
+    #[cfg_attr(windows, allow(unused_mut))]
+    let mut registry = BTreeMap::from([
+        ("file", build::<FileInput> as InputFactory),
+        ("snapshot", build::<snapshot::SnapshotInput>),
+        ("backfill", build::<backfill::BackfillInput>),
+        ("command", build::<command::CommandInput>),
+        ("statsd", build::<statsd::StatsdConfig>),
+        ("docker", build::<docker::DockerInput>),
+        ("syslog", build::<syslog::SyslogInput>),
+        ("eventlog", build::<eventlog::EventLogInput>),
+        ("stdin", build::<stdin::StdinInput>),
+    ]);
+    #[cfg(unix)]
+    registry.insert("journald", build::<journald::JournaldInput>);
+    registry
+}
+
+/// Paths of every file-backed input, including `[[inputs]]` entries.
+pub fn file_paths(config: &Config) -> Vec<String> {
+    let mut paths: Vec<String> = config.log_files.clone();
+    paths.extend(config.snapshots.iter().map(|s| s.path.clone()));
+    paths.extend(config.backfills.iter().map(|b| b.path.clone()));
+    for spec in &config.inputs {
+        if let Some(path) = spec.options.get("path").and_then(|p| p.as_str()) {
+            paths.push(path.to_string());
+        }
+    }
+    paths.sort();
+    paths.dedup_by(|a, b| crate::paths::key(a) == crate::paths::key(b));
+    paths
+}
+
+/// The tailed files: `log_files` and the `[[inputs]]` entries of type "file".
+#[cfg(unix)]
+pub fn file_inputs(config: &Config) -> anyhow::Result<Vec<FileInput>> {
+    let mut inputs: Vec<FileInput> = config
+        .log_files
+        .iter()
+        .map(|path| FileInput {
+            path: path.clone(),
+            mmap: config.mmap_files.contains(path),
+            rewrite_policy: None,
+            stop: StopFlag::default(),
+            from_start: false,
+        })
+        .collect();
+    for spec in config.inputs.iter().filter(|spec| spec.kind == "file") {
+        inputs.push(toml::Value::Table(spec.options.clone()).try_into()?);
+    }
+    Ok(inputs)
+}
+
+/// Every input in the config: the dedicated sections (`log_files`, `[[snapshots]]`, ...)
+/// followed by the `[[inputs]]` entries.
+pub fn configured_inputs(config: &Config) -> anyhow::Result<Vec<Box<dyn Input>>> {
+    let mut inputs: Vec<Box<dyn Input>> = Vec::new();
+    for path in &config.log_files {
+        inputs.push(Box::new(FileInput {
+            path: path.clone(),
+            mmap: config.mmap_files.contains(path),
+            rewrite_policy: None,
+            stop: StopFlag::default(),
+            from_start: false,
+        }));
+    }
+    for input in &config.snapshots {
+        inputs.push(Box::new(input.clone()));
+    }
+    for input in &config.backfills {
+        inputs.push(Box::new(input.clone()));
+    }
+    for input in &config.commands {
+        inputs.push(Box::new(input.clone()));
+    }
+    if let Some(ref statsd) = config.statsd {
+        inputs.push(Box::new(statsd.clone()));
+    }
+    if let Some(ref lifecycle) = config.lifecycle_events {
+        inputs.push(Box::new(lifecycle.clone()));
+    }
+
+    let registry = registry();
+    for spec in &config.inputs {
+        let factory = registry.get(spec.kind.as_str()).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Unknown input type {:?}; known types: {}",
+                spec.kind,
+                registry.keys().copied().collect::<Vec<_>>().join(", ")
+            )
+        })?;
+        let input = factory(spec.options.clone())
+            .map_err(|e| anyhow::anyhow!("Invalid {} input: {}", spec.kind, e))?;
+        inputs.push(input);
+    }
+    Ok(inputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inputs_compare_by_their_settings() {
+        let inputs = |interval: u64| {
+            let config: Config = toml::from_str(&format!(
+                r#"
+                endpoint = "http://localhost:4318"
+                log_files = ["/var/log/app.log"]
+                [[snapshots]]
+                path = "/proc/meminfo"
+                interval_secs = {interval}
+                "#
+            ))
+            .unwrap();
+            configured_inputs(&config).unwrap()
+        };
+        let (running, reloaded) = (inputs(10), inputs(30));
+        let same = |a: usize, b: usize| reloaded[a].same_config(running[b].as_any());
+        assert!(same(0, 0));
+        assert!(!same(1, 1));
+        assert!(!same(0, 1));
+        assert!(inputs(30)[1].same_config(reloaded[1].as_any()));
+    }
+}
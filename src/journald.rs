@@ -0,0 +1,511 @@
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::input::{Input, InputContext, StopFlag};
+use crate::stats::{AgentState, InputStats, Stage};
+use crate::{enqueue, queue, statedir, Attributes, Body, Record, Severity};
+
+/// How often the reader checks whether it was stopped and saves its cursor.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+const CURSOR_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+/// Wait before `journalctl` is started again after it exited on its own.
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// Journal fields shipped as attributes, and the attribute each becomes.
+const FIELD_ATTRIBUTES: &[(&str, &str)] = &[
+    ("_SYSTEMD_UNIT", "systemd.unit"),
+    ("_PID", "process.pid"),
+    ("SYSLOG_IDENTIFIER", "syslog.identifier"),
+];
+
+/// Syslog priorities, the journal's `PRIORITY` field, by their `journalctl -p` names.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Emerg,
+    Alert,
+    Crit,
+    Err,
+    Warning,
+    Notice,
+    Info,
+    Debug,
+}
+
+/// Severity of a `PRIORITY` value.
+fn severity(priority: &str) -> Option<Severity> {
+    crate::syslog::severity(priority.parse().ok()?)
+}
+
+/// The journal entries of systemd units, read through `journalctl` from the cursor saved
+/// in the state directory, or from the end of the journal on first start.
+///
+/// The boot the cursor's entry was logged in is saved with it. When the journal has moved
+/// on to another boot since, what the previous boot logged after the cursor is read
+/// first, up to `previous_boot_lines` of its last entries, then the new boot from its
+/// start.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JournaldInput {
+    /// Tells several journald inputs apart in `top` and in their cursor files.
+    #[serde(default = "default_name")]
+    pub name: String,
+    /// Only entries of these units (`journalctl -u`, globs allowed); all when empty.
+    #[serde(default)]
+    pub units: Vec<String>,
+    /// Only entries of this priority or a more urgent one.
+    pub priority: Option<Priority>,
+    /// Journal directory to read instead of the system journal, e.g. the host's journal
+    /// mounted into a container.
+    pub directory: Option<String>,
+    /// After a reboot, at most this many of the entries the previous boot logged after
+    /// the saved cursor are read, its last ones; all by default, 0 for none.
+    pub previous_boot_lines: Option<usize>,
+    #[serde(skip)]
+    pub stop: StopFlag,
+    #[serde(skip)]
+    reader: Reader,
+}
+
+fn default_name() -> String {
+    "journal".to_string()
+}
+
+/// What is saved in the cursor file: where the last entry read is, and the boot it was
+/// logged in. Files of earlier versions hold the cursor alone.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Position {
+    cursor: String,
+    boot_id: Option<String>,
+}
+
+impl Position {
+    fn parse(saved: &str) -> Option<Position> {
+        let saved = saved.trim();
+        if saved.is_empty() {
+            return None;
+        }
+        serde_json::from_str(saved).ok().or_else(|| {
+            Some(Position {
+                cursor: saved.to_string(),
+                boot_id: None,
+            })
+        })
+    }
+}
+
+/// Where a run of `journalctl` starts reading.
+#[derive(Debug, Clone, PartialEq)]
+enum Seek {
+    /// New entries only.
+    End,
+    AfterCursor(String),
+    /// The current boot from its first entry.
+    BootStart,
+}
+
+/// What the input shares with its reader thread: the position of the last entry read,
+/// the file it is saved to once the input started, and the pid of `journalctl`.
+#[derive(Clone, Default)]
+struct Reader {
+    position: Arc<Mutex<Option<Position>>>,
+    cursor_file: Option<String>,
+    pid: Arc<Mutex<Option<u32>>>,
+}
+
+impl std::fmt::Debug for Reader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Reader")
+    }
+}
+
+// Left out when a reload compares the input's settings.
+impl PartialEq for Reader {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl JournaldInput {
+    /// `journalctl` reading the input's entries as JSON.
+    fn journalctl(&self) -> Command {
+        let mut command = Command::new("journalctl");
+        command.args(["--output=json", "--no-pager", "--quiet"]);
+        for unit in &self.units {
+            command.arg(format!("--unit={}", unit));
+        }
+        if let Some(priority) = self.priority {
+            command.arg(format!("--priority={}", priority as u8));
+        }
+        if let Some(ref directory) = self.directory {
+            command.arg(format!("--directory={}", directory));
+        }
+        command
+    }
+
+    fn command(&self, seek: &Seek) -> Command {
+        let mut command = self.journalctl();
+        command.arg("--follow");
+        match seek {
+            Seek::End => command.arg("--lines=0"),
+            Seek::AfterCursor(cursor) => command.arg(format!("--after-cursor={}", cursor)),
+            Seek::BootStart => command.args(["--boot", "--no-tail"]),
+        };
+        // Out of the agent's process group, so a Ctrl-C reaches the agent alone, which
+        // then stops the input.
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .process_group(0);
+        command
+    }
+
+    /// The boot the journal is at: of its last entry, which need not be one of the
+    /// input's units.
+    fn current_boot(&self) -> Option<String> {
+        let mut command = Command::new("journalctl");
+        command.args(["--output=json", "--no-pager", "--quiet", "--lines=1"]);
+        if let Some(ref directory) = self.directory {
+            command.arg(format!("--directory={}", directory));
+        }
+        let output = command.stderr(Stdio::null()).output().ok()?;
+        let line = String::from_utf8_lossy(&output.stdout);
+        let fields: Map<String, Value> = serde_json::from_str(line.lines().last()?).ok()?;
+        fields.get("_BOOT_ID").and_then(text)
+    }
+
+    /// Where reading resumes from `saved`, once the journal is at boot `current`; reads
+    /// what is to be shipped of the previous boot first when it changed.
+    fn resume(&self, saved: Option<Position>, current: Option<String>, shipper: &Shipper) -> Seek {
+        let Some(saved) = saved else {
+            return Seek::End;
+        };
+        let rebooted =
+            matches!((&saved.boot_id, &current), (Some(saved), Some(current)) if saved != current);
+        if !rebooted {
+            return Seek::AfterCursor(saved.cursor);
+        }
+        let boot_id = saved.boot_id.as_deref().unwrap_or_default();
+        match self.previous_boot_lines {
+            None => {
+                println!(
+                    "Journal input {} was last read in boot {}, reading on from there",
+                    self.name, boot_id
+                );
+                return Seek::AfterCursor(saved.cursor);
+            }
+            Some(0) => {}
+            Some(limit) => {
+                println!(
+                    "Journal input {} was last read in boot {}, shipping up to {} of its last entries",
+                    self.name, boot_id, limit
+                );
+                let mut tail = self.journalctl();
+                tail.arg(format!("--boot={}", boot_id))
+                    .arg(format!("--after-cursor={}", saved.cursor))
+                    .stdin(Stdio::null())
+                    .stderr(Stdio::inherit());
+                match tail.output() {
+                    Ok(output) => {
+                        let text = String::from_utf8_lossy(&output.stdout);
+                        let lines: Vec<&str> = text.lines().collect();
+                        for line in &lines[lines.len().saturating_sub(limit)..] {
+                            shipper.ship(line);
+                        }
+                    }
+                    Err(e) => shipper.state.record_error(format!(
+                        "Failed to read the previous boot of journal input {}: {}",
+                        self.name, e
+                    )),
+                }
+            }
+        }
+        Seek::BootStart
+    }
+
+    fn save_cursor(&self) -> anyhow::Result<()> {
+        let (Some(ref path), Some(ref position)) = (
+            &self.reader.cursor_file,
+            &*self.reader.position.lock().unwrap(),
+        ) else {
+            return Ok(());
+        };
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, serde_json::to_string(position)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Ships the entries `journalctl` prints, moving the input's position past each.
+#[derive(Clone)]
+struct Shipper {
+    position: Arc<Mutex<Option<Position>>>,
+    stats: Arc<InputStats>,
+    tx: queue::Sender,
+    state: Arc<AgentState>,
+    otlp_path: Option<Arc<str>>,
+}
+
+impl Shipper {
+    fn ship(&self, line: &str) {
+        let started = Instant::now();
+        let Ok(Value::Object(fields)) = serde_json::from_str(line) else {
+            self.state.record_drop("journald_unparsable");
+            return;
+        };
+        self.stats.record_line();
+        let mut record = entry(&fields);
+        record.otlp_path = self.otlp_path.clone();
+        self.state.record_stage(Stage::Parse, 1, started.elapsed());
+        enqueue(&self.tx, &self.state, record);
+        if let Some(cursor) = fields.get("__CURSOR").and_then(text) {
+            *self.position.lock().unwrap() = Some(Position {
+                cursor,
+                boot_id: fields.get("_BOOT_ID").and_then(text),
+            });
+        }
+    }
+}
+
+/// A field's value: a string, or an array of bytes for values that are not UTF-8.
+fn text(field: &Value) -> Option<String> {
+    match field {
+        Value::String(s) => Some(s.clone()),
+        Value::Array(bytes) => {
+            let bytes: Vec<u8> = bytes
+                .iter()
+                .filter_map(|b| b.as_u64().map(|b| b as u8))
+                .collect();
+            Some(String::from_utf8_lossy(&bytes).into_owned())
+        }
+        _ => None,
+    }
+}
+
+/// The record for one `journalctl --output=json` entry.
+fn entry(fields: &Map<String, Value>) -> Record {
+    let field = |name: &str| fields.get(name).and_then(text);
+    let mut attributes = Attributes::new();
+    for (name, attribute) in FIELD_ATTRIBUTES {
+        if let Some(value) = field(name) {
+            attributes.push(attribute.to_string(), value);
+        }
+    }
+    Record {
+        body: Body::Text(field("MESSAGE").unwrap_or_default()),
+        attributes,
+        default_severity: None,
+        severity: field("PRIORITY").as_deref().and_then(severity),
+        service_name: None,
+        timestamp: field("__REALTIME_TIMESTAMP")
+            .and_then(|us| us.parse::<i64>().ok())
+            .and_then(|us| Utc.timestamp_micros(us).single()),
+        observed: Utc::now(),
+        otlp_path: None,
+        trace: None,
+        resource: None,
+        checkpoint: None,
+    }
+}
+
+impl Input for JournaldInput {
+    fn describe(&self) -> String {
+        format!("journald:{}", self.name)
+    }
+
+    fn start(&mut self, ctx: &InputContext) -> anyhow::Result<()> {
+        let stats = ctx.register(self);
+        let path = statedir::file_in(
+            &ctx.config.state_directory(),
+            &format!("journald-{}.cursor", self.name),
+        );
+        let shipper = Shipper {
+            position: self.reader.position.clone(),
+            tx: ctx.queue.sender(&stats.name),
+            state: ctx.state.clone(),
+            otlp_path: ctx
+                .config
+                .otlp_paths
+                .get(&stats.name)
+                .map(|p| p.as_str().into()),
+            stats,
+        };
+        let saved = fs::read_to_string(&path)
+            .ok()
+            .and_then(|saved| Position::parse(&saved));
+        *self.reader.position.lock().unwrap() = saved.clone();
+        self.reader.cursor_file = Some(path);
+        let input = self.clone();
+
+        println!("Reading the journal as input {}", shipper.stats.name);
+        thread::spawn(move || {
+            let state = shipper.state.clone();
+            let mut seek = input.resume(saved, input.current_boot(), &shipper);
+            while !input.stop.is_stopped() {
+                let mut child = match input.command(&seek).spawn() {
+                    Ok(child) => child,
+                    Err(e) => {
+                        state.record_error(format!("Failed to run journalctl: {}", e));
+                        thread::sleep(RESTART_DELAY);
+                        continue;
+                    }
+                };
+                *input.reader.pid.lock().unwrap() = Some(child.id());
+                let stdout = child.stdout.take().unwrap();
+                let before = input.reader.position.lock().unwrap().clone();
+                let reader = {
+                    let shipper = shipper.clone();
+                    thread::spawn(move || {
+                        for line in BufReader::new(stdout).lines() {
+                            let Ok(line) = line else { break };
+                            shipper.ship(&line);
+                        }
+                    })
+                };
+
+                let status = watch(&input, &mut child, &state);
+                reader.join().ok();
+                *input.reader.pid.lock().unwrap() = None;
+                input.save_cursor().ok();
+                let position = input.reader.position.lock().unwrap().clone();
+                // A cursor journalctl cannot seek to, as its entry was vacuumed since.
+                let vacuumed = status.is_some_and(|s| !s.success())
+                    && position == before
+                    && matches!(seek, Seek::AfterCursor(_));
+                if vacuumed {
+                    state.record_error(format!(
+                        "journalctl failed at the saved cursor of journal input {}, reading the current boot from its start",
+                        input.name
+                    ));
+                    *input.reader.position.lock().unwrap() = None;
+                    seek = Seek::BootStart;
+                } else if let Some(position) = position {
+                    seek = Seek::AfterCursor(position.cursor);
+                }
+                if let Some(status) = status {
+                    state.record_error(format!(
+                        "journalctl exited with {}, restarting in {}s",
+                        status,
+                        RESTART_DELAY.as_secs()
+                    ));
+                    thread::sleep(RESTART_DELAY);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Also ends the running `journalctl`, which the agent may not outlive long enough
+    /// for its reader thread to notice.
+    fn stop(&mut self) {
+        self.stop.stop();
+        if let Some(pid) = *self.reader.pid.lock().unwrap() {
+            // SAFETY: kill only sends a signal; the pid is cleared right after the child is
+            // reaped.
+            unsafe {
+                libc::kill(pid as libc::pid_t, libc::SIGTERM);
+            }
+        }
+    }
+
+    fn checkpoint(&mut self) -> anyhow::Result<()> {
+        self.save_cursor()
+    }
+}
+
+/// Waits for `child` to exit, saving the cursor on the way; kills it once the input is
+/// stopped. Returns the exit status when `journalctl` exited on its own.
+fn watch(
+    input: &JournaldInput,
+    child: &mut Child,
+    state: &crate::stats::AgentState,
+) -> Option<std::process::ExitStatus> {
+    let mut saved = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Some(status),
+            Ok(None) => {}
+            Err(e) => {
+                state.record_error(format!("Failed to wait for journalctl: {}", e));
+                return None;
+            }
+        }
+        if input.stop.is_stopped() {
+            child.kill().ok();
+            child.wait().ok();
+            return None;
+        }
+        if saved.elapsed() >= CURSOR_SAVE_INTERVAL {
+            if let Err(e) = input.save_cursor() {
+                state.record_error(format!("Failed to save the journal cursor: {e:#}"));
+            }
+            saved = Instant::now();
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reading_resumes_by_the_boot_of_the_saved_cursor() {
+        let state = Arc::new(AgentState::default());
+        let queue = queue::FairQueue::new(
+            Default::default(),
+            queue::DEFAULT_CAPACITY,
+            queue::Overflow::Block,
+            state.clone(),
+            Default::default(),
+        );
+        let shipper = Shipper {
+            position: Default::default(),
+            stats: state.register_input("journald:journal"),
+            tx: queue.sender("journald:journal"),
+            state,
+            otlp_path: None,
+        };
+        shipper.ship(r#"{"MESSAGE":"Started nginx","__CURSOR":"s=1;i=2a","_BOOT_ID":"b1"}"#);
+        let position = shipper.position.lock().unwrap().clone().unwrap();
+        assert_eq!(
+            position,
+            Position {
+                cursor: "s=1;i=2a".into(),
+                boot_id: Some("b1".into())
+            }
+        );
+        let saved = serde_json::to_string(&position).unwrap();
+        assert_eq!(Position::parse(&saved), Some(position.clone()));
+        // As saved before boots were.
+        assert_eq!(Position::parse("s=1;i=2a\n").map(|p| p.boot_id), Some(None));
+
+        let mut input: JournaldInput = toml::from_str("name = \"journal\"").unwrap();
+        let resume = |input: &JournaldInput, current: &str| {
+            input.resume(Some(position.clone()), Some(current.into()), &shipper)
+        };
+        let after = Seek::AfterCursor("s=1;i=2a".into());
+        assert_eq!(input.resume(None, Some("b1".into()), &shipper), Seek::End);
+        assert_eq!(resume(&input, "b1"), after);
+        // After a reboot, the rest of the previous boot, or nothing of it.
+        assert_eq!(resume(&input, "b2"), after);
+        input.previous_boot_lines = Some(0);
+        assert_eq!(resume(&input, "b1"), after);
+        assert_eq!(resume(&input, "b2"), Seek::BootStart);
+        let args: Vec<String> = input
+            .command(&Seek::BootStart)
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        assert!(args.ends_with(&["--follow".into(), "--boot".into(), "--no-tail".into()]));
+    }
+}
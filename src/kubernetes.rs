@@ -0,0 +1,277 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::time::Duration;
+
+/// Where a pod's service account credentials are mounted.
+const SERVICE_ACCOUNT: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+/// How long the API server gets to answer for a pod's labels.
+const API_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Records of files under `/var/log/containers` or `/var/log/pods` enriched with the pod,
+/// namespace and container the path names, and their lines decoded from the container
+/// runtime's format.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct KubernetesConfig {
+    /// Also ask the API server for the pod's labels, with the agent's service account.
+    #[serde(default)]
+    pub labels: bool,
+    /// The API server, by default the one `KUBERNETES_SERVICE_HOST` and
+    /// `KUBERNETES_SERVICE_PORT` name inside a cluster.
+    pub api_server: Option<String>,
+}
+
+/// What the path of a container's log file tells about it.
+#[derive(Debug, Default, PartialEq)]
+pub struct PodFile {
+    pub namespace: String,
+    pub pod: String,
+    pub container: String,
+    pub container_id: Option<String>,
+    pub pod_uid: Option<String>,
+    pub restart_count: Option<String>,
+}
+
+impl PodFile {
+    /// Reads `/var/log/containers/<pod>_<namespace>_<container>-<container id>.log` and
+    /// `/var/log/pods/<namespace>_<pod>_<pod uid>/<container>/<restart count>.log`.
+    pub fn parse(path: &str) -> Option<PodFile> {
+        let parts: Vec<&str> = path.trim_end_matches(".log").rsplit('/').collect();
+        match parts.as_slice() {
+            [name, "containers", ..] => {
+                let (pod, rest) = name.split_once('_')?;
+                let (namespace, rest) = rest.split_once('_')?;
+                let (container, id) = rest.rsplit_once('-')?;
+                Some(PodFile {
+                    namespace: namespace.to_string(),
+                    pod: pod.to_string(),
+                    container: container.to_string(),
+                    container_id: Some(id.to_string()),
+                    ..PodFile::default()
+                })
+            }
+            [restarts, container, pod_dir, "pods", ..] => {
+                let (namespace, rest) = pod_dir.split_once('_')?;
+                let (pod, uid) = rest.rsplit_once('_')?;
+                Some(PodFile {
+                    namespace: namespace.to_string(),
+                    pod: pod.to_string(),
+                    container: container.to_string(),
+                    pod_uid: Some(uid.to_string()),
+                    restart_count: Some(restarts.to_string()),
+                    ..PodFile::default()
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn resource(&self) -> Vec<(String, String)> {
+        let mut resource = vec![
+            ("k8s.namespace.name".to_string(), self.namespace.clone()),
+            ("k8s.pod.name".to_string(), self.pod.clone()),
+            ("k8s.container.name".to_string(), self.container.clone()),
+        ];
+        for (key, value) in [
+            ("container.id", &self.container_id),
+            ("k8s.pod.uid", &self.pod_uid),
+            ("k8s.container.restart_count", &self.restart_count),
+        ] {
+            if let Some(value) = value {
+                resource.push((key.to_string(), value.clone()));
+            }
+        }
+        resource
+    }
+}
+
+/// The `k8s.pod.label.<key>` attributes of the pod's labels, from the API server.
+pub fn pod_labels(
+    config: &KubernetesConfig,
+    file: &PodFile,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let server = match config.api_server {
+        Some(ref server) => server.clone(),
+        None => {
+            let host = std::env::var("KUBERNETES_SERVICE_HOST")
+                .context("KUBERNETES_SERVICE_HOST is not set; set api_server outside a cluster")?;
+            let port = std::env::var("KUBERNETES_SERVICE_PORT").unwrap_or("443".to_string());
+            format!("https://{}:{}", host, port)
+        }
+    };
+    let mut client = reqwest::blocking::Client::builder()
+        .user_agent(crate::exporter::default_user_agent())
+        .timeout(API_TIMEOUT);
+    if let Ok(ca) = fs::read(format!("{}/ca.crt", SERVICE_ACCOUNT)) {
+        client = client.add_root_certificate(reqwest::Certificate::from_pem(&ca)?);
+    }
+    let mut request = client.build()?.get(format!(
+        "{}/api/v1/namespaces/{}/pods/{}",
+        server.trim_end_matches('/'),
+        file.namespace,
+        file.pod
+    ));
+    if let Ok(token) = fs::read_to_string(format!("{}/token", SERVICE_ACCOUNT)) {
+        request = request.bearer_auth(token.trim());
+    }
+    let response = request.send()?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "the API server answered {} for pod {}/{}",
+            response.status(),
+            file.namespace,
+            file.pod
+        ));
+    }
+    let pod: Value = response.json()?;
+    Ok(pod["metadata"]["labels"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(key, value)| {
+            Some((
+                format!("k8s.pod.label.{}", key),
+                value.as_str()?.to_string(),
+            ))
+        })
+        .collect())
+}
+
+/// A line of a container log as the runtime wrote it.
+#[derive(Debug, PartialEq)]
+pub struct RuntimeLine {
+    pub timestamp: Option<DateTime<Utc>>,
+    pub stream: String,
+    /// The runtime split a longer line, whose next part follows.
+    pub partial: bool,
+    pub message: String,
+}
+
+impl RuntimeLine {
+    /// Decodes a CRI line (`<time> <stream> <P|F> <message>`) or a Docker `json-file`
+    /// line; `None` for anything else.
+    pub fn parse(line: &str) -> Option<RuntimeLine> {
+        if line.starts_with('{') {
+            let line: crate::docker::JsonLine = serde_json::from_str(line).ok()?;
+            return Some(RuntimeLine {
+                timestamp: line.time.as_deref().and_then(rfc3339),
+                stream: line.stream,
+                partial: !line.log.ends_with('\n'),
+                message: line.log.trim_end_matches('\n').to_string(),
+            });
+        }
+        let mut parts = line.splitn(4, ' ');
+        let timestamp = rfc3339(parts.next()?)?;
+        let stream = parts.next().filter(|s| matches!(*s, "stdout" | "stderr"))?;
+        let partial = match parts.next()?.split(':').next()? {
+            "P" => true,
+            "F" => false,
+            _ => return None,
+        };
+        Some(RuntimeLine {
+            timestamp: Some(timestamp),
+            stream: stream.to_string(),
+            partial,
+            message: parts.next().unwrap_or_default().to_string(),
+        })
+    }
+}
+
+fn rfc3339(text: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(text)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// Puts the parts of lines the runtime split back together.
+#[derive(Default)]
+pub struct Reassembler {
+    partial: Option<RuntimeLine>,
+}
+
+impl Reassembler {
+    /// The whole line once `line` is its last part.
+    pub fn push(&mut self, line: RuntimeLine) -> Option<RuntimeLine> {
+        let line = match self.partial.take() {
+            Some(mut head) => {
+                head.message.push_str(&line.message);
+                head.partial = line.partial;
+                head
+            }
+            None => line,
+        };
+        if line.partial {
+            self.partial = Some(line);
+            return None;
+        }
+        Some(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pod_files_and_runtime_lines_are_decoded() {
+        let file =
+            PodFile::parse("/var/log/containers/checkout-7d9f8b-x2kq4_shop_api-3f1c2e9a0b7d.log")
+                .unwrap();
+        assert_eq!(
+            file.resource(),
+            vec![
+                ("k8s.namespace.name".to_string(), "shop".to_string()),
+                (
+                    "k8s.pod.name".to_string(),
+                    "checkout-7d9f8b-x2kq4".to_string()
+                ),
+                ("k8s.container.name".to_string(), "api".to_string()),
+                ("container.id".to_string(), "3f1c2e9a0b7d".to_string()),
+            ]
+        );
+        let file = PodFile::parse(
+            "/var/log/pods/shop_checkout-7d9f8b-x2kq4_0b9c7f52-1d2e-4f3a-9b8c-7d6e5f4a3b2c/api-sidecar/2.log",
+        )
+        .unwrap();
+        assert_eq!(
+            (
+                file.pod.as_str(),
+                file.container.as_str(),
+                file.restart_count.as_deref()
+            ),
+            ("checkout-7d9f8b-x2kq4", "api-sidecar", Some("2"))
+        );
+        assert_eq!(PodFile::parse("/var/log/syslog"), None);
+
+        let mut reassembler = Reassembler::default();
+        let lines: Vec<Option<RuntimeLine>> = [
+            "2024-03-05T10:11:12.123456789Z stdout P {\"order\":",
+            "2024-03-05T10:11:12.123456790Z stdout F 42}",
+            "2024-03-05T10:11:13Z stderr F ",
+            r#"{"log":"listening on :8080\n","stream":"stdout","time":"2024-03-05T10:11:14Z"}"#,
+        ]
+        .into_iter()
+        .map(|line| reassembler.push(RuntimeLine::parse(line).unwrap()))
+        .collect();
+        let messages: Vec<Option<(&str, &str)>> = lines
+            .iter()
+            .map(|l| l.as_ref().map(|l| (l.stream.as_str(), l.message.as_str())))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                None,
+                Some(("stdout", "{\"order\":42}")),
+                Some(("stderr", "")),
+                Some(("stdout", "listening on :8080")),
+            ]
+        );
+        assert_eq!(
+            lines[1].as_ref().unwrap().timestamp.unwrap().to_rfc3339(),
+            "2024-03-05T10:11:12.123456789+00:00"
+        );
+        assert_eq!(RuntimeLine::parse("plain text"), None);
+    }
+}
@@ -0,0 +1,267 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use regex::Regex;
+
+use crate::log_format::ParsedLine;
+use crate::rules::{self, RegexLimits};
+use crate::severity_from_name;
+
+/// A Logback or Log4j pattern layout turned into a line parser: the conversion words
+/// become capture groups, the date pattern a chrono format.
+pub struct Layout {
+    pattern: Regex,
+    /// Capture group name and the attribute it is shipped as, for the fields that are
+    /// neither message, level nor date.
+    fields: Vec<(String, String)>,
+    /// chrono format of `%d`, `None` without a date or when it has no day in it.
+    time_format: Option<String>,
+}
+
+/// Named date formats of Logback and Log4j.
+const NAMED_DATES: &[(&str, &str)] = &[
+    ("ISO8601", "yyyy-MM-dd HH:mm:ss,SSS"),
+    ("ISO8601_BASIC", "yyyyMMdd'T'HHmmss,SSS"),
+    (
+        "ISO8601_OFFSET_DATE_TIME_HHMM",
+        "yyyy-MM-dd'T'HH:mm:ss,SSSZ",
+    ),
+    ("DEFAULT", "yyyy-MM-dd HH:mm:ss,SSS"),
+    ("DATE", "dd MMM yyyy HH:mm:ss,SSS"),
+    ("ABSOLUTE", "HH:mm:ss,SSS"),
+    ("COMPACT", "yyyyMMddHHmmssSSS"),
+];
+
+impl Layout {
+    pub fn new(layout: &str, limits: &RegexLimits) -> anyhow::Result<Layout> {
+        let mut regex = String::from("^");
+        let mut fields = Vec::new();
+        let mut time_format = None;
+        let mut chars = layout.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                // Padding and alignment make the exact spacing unreliable.
+                if c.is_whitespace() {
+                    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                        chars.next();
+                    }
+                    regex.push_str(r"\s+");
+                } else {
+                    regex.push_str(&regex::escape(&c.to_string()));
+                }
+                continue;
+            }
+            if chars.peek() == Some(&'%') {
+                chars.next();
+                regex.push('%');
+                continue;
+            }
+            // Format modifiers such as "-5" or ".30" only pad and truncate.
+            let mut padded = false;
+            while chars
+                .peek()
+                .is_some_and(|c| c.is_ascii_digit() || *c == '-' || *c == '.')
+            {
+                padded = true;
+                chars.next();
+            }
+            let mut word = String::new();
+            while chars.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+                word.push(chars.next().unwrap());
+            }
+            let mut option = None;
+            if chars.peek() == Some(&'{') {
+                chars.next();
+                let mut value = String::new();
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                    value.push(c);
+                }
+                option = Some(value);
+            }
+            let padding = if padded { r"\s*" } else { "" };
+            match word.as_str() {
+                "d" | "date" => {
+                    // Log4j allows a time zone after the pattern: "%d{ISO8601}{UTC}".
+                    if chars.peek() == Some(&'{') {
+                        chars.by_ref().take_while(|&c| c != '}').for_each(drop);
+                    }
+                    let date = option.as_deref().unwrap_or("ISO8601");
+                    let date = NAMED_DATES
+                        .iter()
+                        .find(|(name, _)| *name == date)
+                        .map_or(date, |(_, pattern)| *pattern);
+                    let (pattern, format) = translate_date(date)?;
+                    regex.push_str(&format!("(?P<date>{}){}", pattern, padding));
+                    time_format = format.contains("%d").then_some(format);
+                }
+                "p" | "le" | "level" => {
+                    regex.push_str(&format!(r"{}(?P<level>[A-Za-z]+){}", padding, padding))
+                }
+                "m" | "msg" | "message" => regex.push_str("(?P<msg>.*)"),
+                "n" => {}
+                "ex" | "exception" | "throwable" | "xEx" | "xException" | "xThrowable" => {}
+                "X" | "mdc" => {
+                    let key = option.ok_or_else(|| anyhow::anyhow!("%{} needs a key", word))?;
+                    let group = format!("mdc{}", fields.len());
+                    regex.push_str(&format!(r"(?P<{}>.*?){}", group, padding));
+                    fields.push((group, key));
+                }
+                other => {
+                    let (attribute, pattern) = match other {
+                        "t" | "thread" => ("thread.name", ".*?"),
+                        "c" | "lo" | "logger" => ("logger", r"\S+"),
+                        "C" | "class" => ("code.namespace", r"\S+"),
+                        "M" | "method" => ("code.function", r"\S+"),
+                        "L" | "line" => ("code.lineno", r"\d+"),
+                        "F" | "file" => ("code.filepath", r"\S+"),
+                        "r" | "relative" => ("process.uptime_ms", r"\d+"),
+                        "pid" => ("process.pid", r"\d+"),
+                        _ => {
+                            return Err(anyhow::anyhow!(
+                                "Unsupported conversion word %{} in layout {:?}",
+                                other,
+                                layout
+                            ))
+                        }
+                    };
+                    let group = format!("f{}", fields.len());
+                    regex.push_str(&format!("{}(?P<{}>{}){}", padding, group, pattern, padding));
+                    fields.push((group, attribute.to_string()));
+                }
+            }
+        }
+        regex.push('$');
+        Ok(Layout {
+            pattern: rules::compile("layout", &regex, limits)?,
+            fields,
+            time_format,
+        })
+    }
+
+    /// `line` taken apart, if it matches the layout.
+    pub fn parse(&self, line: &str) -> Option<ParsedLine> {
+        let caps = self.pattern.captures(line)?;
+        let timestamp = match (&self.time_format, caps.name("date")) {
+            (Some(format), Some(date)) => parse_date(date.as_str(), format),
+            _ => None,
+        };
+        let attributes = self
+            .fields
+            .iter()
+            .filter_map(|(group, attribute)| {
+                let value = caps.name(group)?.as_str().trim();
+                (!value.is_empty()).then(|| (attribute.clone(), value.to_string()))
+            })
+            .collect();
+        Some(ParsedLine {
+            body: caps
+                .name("msg")
+                .map_or_else(|| line.to_string(), |m| m.as_str().to_string()),
+            severity: caps
+                .name("level")
+                .and_then(|l| severity_from_name(l.as_str())),
+            timestamp,
+            attributes,
+        })
+    }
+}
+
+/// A SimpleDateFormat / DateTimeFormatter pattern as a regex and a chrono format.
+fn translate_date(date: &str) -> anyhow::Result<(String, String)> {
+    let (mut pattern, mut format) = (String::new(), String::new());
+    let chars: Vec<char> = date.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\'' {
+            let end = chars[i + 1..]
+                .iter()
+                .position(|&c| c == '\'')
+                .map_or(chars.len(), |p| i + 1 + p);
+            let literal: String = chars[i + 1..end].iter().collect();
+            pattern.push_str(&regex::escape(&literal));
+            format.push_str(&literal.replace('%', "%%"));
+            i = end + 1;
+            continue;
+        }
+        let run = chars[i..].iter().take_while(|&&x| x == c).count();
+        i += run;
+        let (p, f) = match (c, run) {
+            ('y' | 'u', 2) => (r"\d{2}", "%y"),
+            ('y' | 'u', _) => (r"\d{4}", "%Y"),
+            ('M', 1 | 2) => (r"\d{1,2}", "%m"),
+            ('M', 3) => ("[A-Za-z]{3}", "%b"),
+            ('M', _) => ("[A-Za-z]+", "%B"),
+            ('d', _) => (r"\d{1,2}", "%d"),
+            ('H', _) => (r"\d{1,2}", "%H"),
+            ('m', _) => (r"\d{1,2}", "%M"),
+            ('s', _) => (r"\d{1,2}", "%S"),
+            ('S', 3) => (r"\d{3}", "%3f"),
+            ('S', 6) => (r"\d{6}", "%6f"),
+            ('S', 9) => (r"\d{9}", "%9f"),
+            ('E', 1..=3) => ("[A-Za-z]{3}", "%a"),
+            ('E', _) => ("[A-Za-z]+", "%A"),
+            ('a', _) => ("[AaPp][Mm]", "%p"),
+            ('h', _) => (r"\d{1,2}", "%I"),
+            ('Z', _) => (r"[+-]\d{4}", "%z"),
+            ('X' | 'x', _) => (r"Z|[+-]\d{2}:?\d{2}", "%:z"),
+            (c, _) if c.is_ascii_alphabetic() => {
+                return Err(anyhow::anyhow!(
+                    "Unsupported date pattern letter {:?} in {:?}",
+                    c,
+                    date
+                ))
+            }
+            (c, n) => {
+                let literal: String = c.to_string().repeat(n);
+                pattern.push_str(&regex::escape(&literal));
+                format.push_str(&literal.replace('%', "%%"));
+                continue;
+            }
+        };
+        pattern.push_str(p);
+        format.push_str(f);
+    }
+    Ok((pattern, format))
+}
+
+/// The date as UTC when it has no offset.
+fn parse_date(value: &str, format: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_str(value, format)
+        .map(|t| t.with_timezone(&Utc))
+        .or_else(|_| NaiveDateTime::parse_from_str(value, format).map(|t| t.and_utc()))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Severity;
+
+    #[test]
+    fn log4j_layout_yields_fields_level_and_offset_time() {
+        let layout = Layout::new(
+            "%d{yyyy-MM-dd'T'HH:mm:ss.SSSZ} %-5p %c{1}:%L - %m%n",
+            &RegexLimits::default(),
+        )
+        .unwrap();
+        let parsed = layout
+            .parse("2024-03-05T10:11:12.345+0100 ERROR Orders:42 - Payment failed")
+            .unwrap();
+        assert_eq!(parsed.body, "Payment failed");
+        assert_eq!(parsed.severity, Some(Severity::ERROR));
+        assert_eq!(
+            parsed.timestamp.unwrap().to_rfc3339(),
+            "2024-03-05T09:11:12.345+00:00"
+        );
+        assert_eq!(
+            parsed.attributes,
+            vec![
+                ("logger".to_string(), "Orders".to_string()),
+                ("code.lineno".to_string(), "42".to_string()),
+            ]
+        );
+        assert!(layout.parse("free text").is_none());
+    }
+}
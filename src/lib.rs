@@ -0,0 +1,2156 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use chrono::Utc;
+use clap::Parser;
+use futures::executor::block_on;
+use governor::{Quota, RateLimiter};
+use nonzero_ext::nonzero;
+use regex::Regex;
+use serde::Serialize;
+#[cfg(unix)]
+use signal_hook::{consts::TERM_SIGNALS, iterator::Signals};
+use std::collections::BTreeMap;
+use std::fs;
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clock::{Clock, SystemClock};
+use failure::Failure;
+use stats::{AgentState, Stage};
+
+pub use config::Config;
+use config::{endpoint_origin, load_or_create_config, parse_duration, prepare_config};
+pub use exporter::Exporter;
+pub use record::{Attributes, Body, Record, Severity};
+use parser::{
+    detect_severity, file_line_handler, is_too_old, severity_from_name, severity_of,
+    timestamp_extractor,
+};
+use tailer::{scan_lines, tail_file, trim_line_end, TailOptions, DEFAULT_READ_BUFFER_BYTES};
+
+mod activation;
+mod adaptive;
+mod admin;
+mod archive;
+mod audit;
+mod backfill;
+mod catchup;
+#[cfg(feature = "clickhouse")]
+mod clickhouse;
+mod cert;
+mod checkpoint;
+mod cli;
+mod clock;
+mod cloud;
+mod command;
+mod compress;
+mod computed;
+mod config;
+mod console;
+mod control;
+mod daemon;
+mod docker;
+mod encryption;
+mod eventlog;
+mod exception;
+pub mod delivery;
+#[cfg(unix)]
+mod doctor;
+mod exporter;
+mod failure;
+mod faults;
+mod fieldpath;
+mod fileid;
+mod filelock;
+mod filter;
+mod golden;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod handles;
+mod handshake;
+mod header;
+mod incident;
+mod hostmetrics;
+mod http_status;
+mod input;
+#[cfg(unix)]
+mod journald;
+mod kubernetes;
+mod layout;
+mod lifecycle;
+mod listener;
+mod local_archive;
+mod log_format;
+mod manifest;
+mod metadata;
+mod metrics;
+mod migrate;
+mod mmap_reader;
+mod multiline;
+mod oneshot;
+mod otel_env;
+#[cfg(feature = "parquet")]
+mod parquet;
+mod package;
+mod parser;
+mod paths;
+#[cfg(unix)]
+mod privsep;
+pub mod processor;
+#[cfg(feature = "protobuf")]
+mod protobuf;
+mod quarantine;
+mod queue;
+mod record;
+mod redact;
+mod remote;
+mod reload;
+mod replay;
+mod resync;
+mod rewrite;
+mod rollup;
+mod routes;
+mod rules;
+mod s3;
+mod sampling;
+mod schedule;
+mod selfcheck;
+mod severity;
+mod ship;
+mod shutdown;
+mod statedir;
+mod snapshot;
+mod spool;
+mod statsd;
+mod stats;
+mod stdin;
+mod stitch;
+mod syslog;
+mod tailer;
+mod telemetry;
+mod template;
+#[cfg(test)]
+mod testutil;
+mod throttle;
+mod timestamp;
+mod tls;
+mod tombstone;
+mod top;
+mod trace_context;
+mod unit;
+mod update;
+mod volume;
+#[cfg(windows)]
+mod winservice;
+
+/// What `Record` was called before it got a module of its own.
+#[deprecated(note = "renamed to Record")]
+pub type LogEntry = Record;
+
+fn enqueue(tx: &queue::Sender, state: &AgentState, entry: Record) {
+    enqueue_batch(tx, state, vec![entry]);
+}
+
+/// Hands a batch of records to the dispatcher with a single channel send.
+fn enqueue_batch(tx: &queue::Sender, state: &AgentState, entries: Vec<Record>) {
+    if entries.is_empty() {
+        return;
+    }
+    state.queued.fetch_add(entries.len(), Ordering::SeqCst);
+    tx.send(entries);
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OtlpLogRecord {
+    resource_logs: Vec<ResourceLog>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ResourceLog {
+    resource: Resource,
+    scope_logs: Vec<ScopeLog>,
+}
+
+#[derive(Serialize, Debug)]
+struct Resource {
+    attributes: Vec<KeyValue>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ScopeLog {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<Scope>,
+    log_records: Vec<LogRecord>,
+}
+
+#[derive(Serialize, Debug)]
+struct Scope {
+    attributes: Vec<KeyValue>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct LogRecord {
+    time_unix_nano: String,
+    observed_time_unix_nano: String,
+    severity_text: String,
+    severity_number: u8,
+    body: LogBody,
+    attributes: Vec<KeyValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    span_id: Option<String>,
+    #[serde(skip_serializing_if = "is_zero")]
+    dropped_attributes_count: u32,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+enum LogBody {
+    String {
+        #[serde(rename = "stringValue")]
+        value: String,
+    },
+    /// Base64 of the raw bytes, as OTLP/JSON encodes `bytes` fields.
+    Bytes {
+        #[serde(rename = "bytesValue")]
+        value: String,
+    },
+}
+
+#[derive(Serialize, Debug)]
+struct KeyValue {
+    key: String,
+    value: AttributeValue,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+enum AttributeValue {
+    StringValue {
+        #[serde(rename = "stringValue")]
+        value: String,
+    },
+}
+
+const TRUNCATION_MARKER: &str = "...[truncated]";
+
+/// Cuts `value` to at most `max_len` bytes on a char boundary, marking the cut.
+fn truncate_attribute_value(value: &str, max_len: Option<usize>) -> std::borrow::Cow<'_, str> {
+    match max_len {
+        Some(max_len) if value.len() > max_len => {
+            let mut end = max_len;
+            while !value.is_char_boundary(end) {
+                end -= 1;
+            }
+            format!("{}{}", &value[..end], TRUNCATION_MARKER).into()
+        }
+        _ => value.into(),
+    }
+}
+
+fn string_kv(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key: key.into(),
+        value: AttributeValue::StringValue {
+            value: value.into(),
+        },
+    }
+}
+
+/// The value an `attribute_hints` entry with an array index in it names, such as
+/// `spans[0].trace_id`; the other entries are attribute keys, matched as they are.
+fn indexed_hint(hint: &str, attributes: &[(String, String)]) -> Option<String> {
+    if !hint.contains('[') {
+        return None;
+    }
+    let path = fieldpath::FieldPath::parse(hint).ok()?;
+    path.lookup(attributes).map(std::borrow::Cow::into_owned)
+}
+
+fn is_hex_id(value: &str, len: usize) -> bool {
+    value.len() == len && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// The configured host name, else the detected one (system host name, then `$HOSTNAME`),
+/// else `host_name_fallback`. Called once at startup; the result is stored in the config.
+fn resolve_host_name(config: &Config) -> String {
+    if let Some(ref name) = config.host_name {
+        return name.clone();
+    }
+    let fallback = config
+        .host_name_fallback
+        .clone()
+        .unwrap_or_else(|| "unknown".to_string());
+    if config.detect_host_name == Some(false) {
+        return fallback;
+    }
+
+    let detected = hostname::get()
+        .ok()
+        .map(|h| h.to_string_lossy().trim().to_string())
+        .filter(|h| !h.is_empty())
+        .or_else(|| {
+            std::env::var("HOSTNAME")
+                .ok()
+                .map(|h| h.trim().to_string())
+                .filter(|h| !h.is_empty())
+        });
+    detected.unwrap_or_else(|| {
+        eprintln!(
+            "Warning: could not detect the host name, reporting {:?}; set host_name to override",
+            fallback
+        );
+        fallback
+    })
+}
+
+/// Resource attributes shared by every signal the agent exports.
+fn base_resource_attributes(config: &Config, service_name: &str) -> Vec<KeyValue> {
+    let mut attributes = vec![
+        string_kv("service.name", service_name),
+        string_kv("host.name", config.host_name.as_deref().unwrap_or("unknown")),
+    ];
+    if let Some(ref id) = config.instance_id {
+        attributes.push(string_kv("agent.instance.id", id));
+    }
+    if config.cloud_metadata.is_some() {
+        for (key, value) in cloud::attributes().iter() {
+            attributes.push(string_kv(key, value));
+        }
+    }
+    for (key, value) in &config.resource_attributes {
+        attributes.retain(|kv| kv.key != *key);
+        attributes.push(string_kv(key, value));
+    }
+    attributes
+}
+
+fn build_otlp_payload(entry: &Record, severity: Severity, config: &Config) -> OtlpLogRecord {
+    let service_name = entry
+        .service_name
+        .as_deref()
+        .or(config.service_name.as_deref())
+        .unwrap_or("rust-signoz-agent");
+    let hints = &config.attribute_hints;
+    let mut resource_attributes = base_resource_attributes(config, service_name);
+    for (key, value) in entry.resource.iter().flat_map(|r| r.iter()) {
+        // The input's own host or service name stands for the agent's.
+        resource_attributes.retain(|kv| kv.key != *key);
+        resource_attributes.push(string_kv(key, value));
+    }
+    let mut attributes = Vec::with_capacity(entry.attributes.len());
+    // Kept out of `attributes` until they are cut, so that it is never the one dropped.
+    let mut mutations = None;
+    let mut truncated = false;
+    let mut trace_id = entry.trace.as_ref().map(|t| t.trace_id.clone());
+    let mut span_id = entry.trace.as_ref().map(|t| t.span_id.clone());
+    for (key, value) in &entry.attributes {
+        if config.mark_mutations && key == processor::MUTATIONS_ATTRIBUTE {
+            mutations = Some(value.clone());
+            continue;
+        }
+        let limited = truncate_attribute_value(value, config.max_attribute_value_length);
+        truncated |= matches!(limited, std::borrow::Cow::Owned(_));
+        if hints.trace_id.as_deref() == Some(key.as_str()) && is_hex_id(value, 32) {
+            trace_id = Some(value.to_lowercase());
+        } else if hints.span_id.as_deref() == Some(key.as_str()) && is_hex_id(value, 16) {
+            span_id = Some(value.to_lowercase());
+        } else if hints.resource.contains(key) {
+            resource_attributes.push(string_kv(key, &limited));
+        } else {
+            attributes.push(string_kv(key, &limited));
+        }
+        if hints.component.as_deref() == Some(key.as_str()) {
+            attributes.push(string_kv("component", &limited));
+        }
+    }
+    let indexed = |hint: &Option<String>| indexed_hint(hint.as_deref()?, &entry.attributes);
+    if let Some(value) = indexed(&hints.trace_id).filter(|v| is_hex_id(v, 32)) {
+        trace_id = Some(value.to_lowercase());
+    }
+    if let Some(value) = indexed(&hints.span_id).filter(|v| is_hex_id(v, 16)) {
+        span_id = Some(value.to_lowercase());
+    }
+    if let Some(value) = indexed(&hints.component) {
+        let limited = truncate_attribute_value(&value, config.max_attribute_value_length);
+        attributes.push(string_kv("component", &limited));
+    }
+    for hint in &hints.resource {
+        if let Some(value) = indexed_hint(hint, &entry.attributes) {
+            let limited = truncate_attribute_value(&value, config.max_attribute_value_length);
+            resource_attributes.push(string_kv(hint, &limited));
+        }
+    }
+    let mut dropped_attributes_count = 0;
+    if let Some(max) = config.max_attributes_per_record {
+        if attributes.len() > max {
+            dropped_attributes_count = (attributes.len() - max) as u32;
+            attributes.truncate(max);
+        }
+    }
+    if config.mark_mutations {
+        if truncated || dropped_attributes_count > 0 {
+            let mut steps = mutations.map(|m| m + ",").unwrap_or_default();
+            steps.push_str("truncation");
+            mutations = Some(steps);
+        }
+        if let Some(ref steps) = mutations {
+            attributes.push(string_kv(processor::MUTATIONS_ATTRIBUTE, steps));
+        }
+    }
+
+    OtlpLogRecord {
+        resource_logs: vec![ResourceLog {
+            resource: Resource {
+                attributes: resource_attributes,
+            },
+            scope_logs: vec![ScopeLog {
+                scope: None,
+                log_records: vec![LogRecord {
+                    time_unix_nano: format!(
+                        "{}",
+                        entry
+                            .timestamp
+                            .unwrap_or(entry.observed)
+                            .timestamp_nanos_opt()
+                            .unwrap_or(0)
+                    ),
+                    observed_time_unix_nano: format!(
+                        "{}",
+                        entry.observed.timestamp_nanos_opt().unwrap_or(0)
+                    ),
+                    severity_text: severity.text.into(),
+                    severity_number: severity.number,
+                    body: match entry.body {
+                        Body::Bytes(ref bytes) => LogBody::Bytes {
+                            value: base64::engine::general_purpose::STANDARD.encode(bytes),
+                        },
+                        Body::Text(ref text) => LogBody::String {
+                            value: text.clone(),
+                        },
+                    },
+                    attributes,
+                    trace_id,
+                    span_id,
+                    dropped_attributes_count,
+                }],
+            }],
+        }],
+    }
+}
+
+/// Combines single-record payloads into one request, keeping the records of equal
+/// resources under one `resourceLogs` entry, in their original order. Resources are equal
+/// when they have the same set of attributes, in whatever order.
+fn merge_otlp_payloads(payloads: Vec<OtlpLogRecord>) -> OtlpLogRecord {
+    let mut resource_logs: Vec<ResourceLog> = Vec::new();
+    let mut by_resource: BTreeMap<Vec<String>, usize> = BTreeMap::new();
+    for resource_log in payloads.into_iter().flat_map(|p| p.resource_logs) {
+        let mut key: Vec<String> = resource_log
+            .resource
+            .attributes
+            .iter()
+            .map(|kv| serde_json::to_string(kv).unwrap_or_default())
+            .collect();
+        key.sort();
+        match by_resource.get(&key) {
+            Some(&i) => resource_logs[i].scope_logs[0].log_records.extend(
+                resource_log
+                    .scope_logs
+                    .into_iter()
+                    .flat_map(|s| s.log_records),
+            ),
+            None => {
+                by_resource.insert(key, resource_logs.len());
+                resource_logs.push(resource_log);
+            }
+        }
+    }
+    OtlpLogRecord { resource_logs }
+}
+
+/// Names the export request `payload` is sent in by a scope attribute on its records.
+fn tag_batch(payload: &mut OtlpLogRecord, key: &str, id: &str) {
+    for scope_log in payload
+        .resource_logs
+        .iter_mut()
+        .flat_map(|r| r.scope_logs.iter_mut())
+    {
+        scope_log.scope = Some(Scope {
+            attributes: vec![string_kv(key, id)],
+        });
+    }
+}
+
+/// The unit file running `exe` in `working_dir`, ordered and restarted per `unit`;
+/// `user_line` is a `User=` line or empty and `service_extra` more `[Service]` lines.
+fn systemd_unit(
+    user_line: &str,
+    working_dir: &str,
+    exe: &str,
+    unit: &unit::ServiceUnit,
+    service_extra: &str,
+) -> String {
+    format!(
+        r#"[Unit]
+Description=Rust SigNoz Agent
+{}
+[Service]
+Type=simple
+{}WorkingDirectory={}
+ExecStart={}
+{}{}
+[Install]
+WantedBy=multi-user.target
+"#,
+        unit.unit_lines(),
+        user_line,
+        working_dir,
+        exe,
+        unit.service_lines(),
+        service_extra
+    )
+}
+
+/// Writes the unit to `output`, by default straight into /etc/systemd/system when run
+/// as root and into the state directory otherwise.
+#[cfg(unix)]
+fn create_systemd_service(config: Option<&Config>, output: Option<String>) -> Result<()> {
+    let user = config
+        .and_then(|c| c.service_user.clone())
+        .or_else(|| whoami::fallible::username().ok())
+        .filter(|u| !u.trim().is_empty());
+    let user_line = match user {
+        Some(ref user) => format!("User={}\n", user),
+        None => {
+            eprintln!(
+                "Warning: could not determine the current user; the unit has no User= line and will run as root. Set service_user in config.toml to choose one."
+            );
+            String::new()
+        }
+    };
+    let working_dir = std::env::current_dir()
+        .context("Cannot determine the working directory for WorkingDirectory=")?;
+    let exe = std::env::current_exe()
+        .context("Cannot determine the agent binary path for ExecStart=")?;
+
+    if let Some(config) = config {
+        let findings = doctor::check(config, user.as_deref());
+        if !findings.is_empty() {
+            eprintln!("Warning: the service will not be able to read some inputs:");
+            doctor::report(&findings);
+            println!("Run `rust-signoz-agent doctor` after fixing them to check again.");
+        }
+    }
+
+    let service_content = systemd_unit(
+        &user_line,
+        &working_dir.display().to_string(),
+        &exe.display().to_string(),
+        config.map_or(&unit::ServiceUnit::default(), |c| &c.service_unit),
+        "",
+    );
+
+    let installed = "/etc/systemd/system/rust-signoz-agent.service";
+    let service_path = output.unwrap_or_else(|| {
+        if statedir::is_root() {
+            installed.to_string()
+        } else {
+            let dir = config.map_or_else(statedir::default_dir, Config::state_directory);
+            statedir::file_in(&dir, "rust-signoz-agent.service")
+        }
+    });
+    if let Some(dir) = Path::new(&service_path).parent() {
+        fs::create_dir_all(dir).ok();
+    }
+    fs::write(&service_path, service_content)
+        .with_context(|| format!("Failed to write {}", service_path))?;
+    // Next to the service, under the name systemd pairs it with.
+    let socket = match config.and_then(activation::socket_unit) {
+        Some(socket_content) if config.is_some_and(|c| c.service_unit.socket_activation) => {
+            let socket_path = Path::new(&service_path)
+                .with_file_name(activation::SOCKET_UNIT)
+                .display()
+                .to_string();
+            fs::write(&socket_path, socket_content)
+                .with_context(|| format!("Failed to write {}", socket_path))?;
+            println!("Socket file created at: {}", socket_path);
+            Some(socket_path)
+        }
+        _ => None,
+    };
+
+    println!("Service file created at: {}", service_path);
+    println!("To install the service, run:");
+    if service_path != installed {
+        println!("  sudo cp {} {}", service_path, installed);
+        if let Some(ref socket_path) = socket {
+            println!("  sudo cp {} /etc/systemd/system/", socket_path);
+        }
+    }
+    println!("  sudo systemctl daemon-reload");
+    if socket.is_some() {
+        println!("  sudo systemctl enable --now {}", activation::SOCKET_UNIT);
+    }
+    println!("  sudo systemctl enable rust-signoz-agent");
+    println!("  sudo systemctl start rust-signoz-agent");
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn install_service_command(config_path: &str, output: Option<String>) -> Result<()> {
+    let config = if Path::new(config_path).exists() {
+        Some(
+            toml::from_str::<Config>(&fs::read_to_string(config_path)?)
+                .map_err(|e| failure::classify(e.into(), Failure::ConfigInvalid))?,
+        )
+    } else {
+        None
+    };
+    if let Some(ref config) = config {
+        config
+            .service_unit
+            .validate()
+            .map_err(|e| failure::classify(e, Failure::ConfigInvalid))?;
+        if config.service_unit.socket_activation && activation::socket_unit(config).is_none() {
+            return Err(failure::classify(
+                anyhow::anyhow!("service_unit.socket_activation needs a syslog or statsd input"),
+                Failure::ConfigInvalid,
+            ));
+        }
+    }
+    create_systemd_service(config.as_ref(), output).context("Failed to create systemd service")
+}
+
+/// Resolves the admin socket of the agent running with `config_path`, without prompting.
+fn admin_socket_path(config_path: &str) -> Result<String> {
+    if Path::new(config_path).exists() {
+        let config: Config = toml::from_str(&fs::read_to_string(config_path)?)?;
+        Ok(config.socket_path())
+    } else {
+        Ok(statedir::file_in(&statedir::default_dir(), admin::SOCKET_FILE))
+    }
+}
+
+fn top_command(config_path: &str, interval: u64) -> Result<()> {
+    top::run(
+        &admin_socket_path(config_path)?,
+        Duration::from_secs(interval.max(1)),
+    )
+}
+
+fn generate_manifest_command(
+    config_path: &str,
+    target: &str,
+    options: &manifest::Options,
+) -> Result<()> {
+    let config_text = fs::read_to_string(config_path)
+        .with_context(|| format!("generate-manifest needs {}", config_path))?;
+    let config: Config = toml::from_str(&config_text)
+        .map_err(|e| failure::classify(e.into(), Failure::ConfigInvalid))?;
+    print!(
+        "{}",
+        manifest::generate(target, &config, &config_text, options)?
+    );
+    Ok(())
+}
+
+fn flush_command(config_path: &str, timeout: u64) -> Result<()> {
+    let socket_path = admin_socket_path(config_path)?;
+    let remaining = admin::request_flush(&socket_path, Duration::from_secs(timeout))?;
+    if remaining > 0 {
+        return Err(anyhow::anyhow!(
+            "Flush timed out after {}s with {} records still queued",
+            timeout,
+            remaining
+        )
+        .context(Failure::FlushIncomplete));
+    }
+
+    println!("Flush complete, queue drained");
+    Ok(())
+}
+
+/// The agent with the processors registered on it, e.g.
+/// `Agent::default().processor(redact_tokens).run()`, or embedded in another program with
+/// `Agent::from_config(config)`, `start()` and `shutdown()`.
+#[derive(Default)]
+pub struct Agent {
+    config: Option<Config>,
+    processors: processor::Chain,
+    listeners: delivery::Listeners,
+    exporters: Vec<exporter::Custom>,
+    started: Option<Started>,
+}
+
+/// An agent started on threads of its own.
+struct Started {
+    /// Cleared to stop the agent, whose thread returns what `serve` did.
+    running: Arc<AtomicBool>,
+    agent: thread::JoinHandle<Result<()>>,
+    /// The config of `from_config`, with the inputs added and removed since, as the TOML
+    /// it would be read from.
+    config: toml::Table,
+    /// Where the configs with those inputs go, to be applied like a reloaded config.
+    reloads: std::sync::mpsc::Sender<Config>,
+}
+
+impl Agent {
+    /// An agent running `config` once started, instead of reading its command line and
+    /// `config.toml`.
+    pub fn from_config(config: Config) -> Agent {
+        Agent {
+            config: Some(config),
+            ..Agent::default()
+        }
+    }
+
+    /// Adds a processor applied to every record after the built-in filters, in
+    /// registration order.
+    pub fn processor(mut self, processor: impl processor::Processor + 'static) -> Agent {
+        self.processors.push(Box::new(processor));
+        self
+    }
+
+    /// Adds a listener told whether each record was delivered, dropped or undelivered.
+    pub fn on_outcome(mut self, listener: impl delivery::Listener + 'static) -> Agent {
+        self.listeners.push(Arc::new(listener));
+        self
+    }
+
+    /// Adds an exporter that every record is sent to besides the configured ones.
+    pub fn exporter(mut self, exporter: impl Exporter + 'static) -> Agent {
+        self.exporters
+            .push(Arc::new(Mutex::new(Box::new(exporter))));
+        self
+    }
+
+    /// Runs the agent as its command line says, until it is told to stop.
+    pub fn run(self) -> Result<()> {
+        run(self.processors, self.listeners, self.exporters)
+    }
+
+    /// Starts the agent of `from_config` on threads of its own and returns once its
+    /// inputs are started. It installs no signal handlers and does not reload its config.
+    pub fn start(&mut self) -> Result<()> {
+        if self.started.is_some() {
+            return Err(anyhow::anyhow!("the agent is already started"));
+        }
+        let config = self
+            .config
+            .take()
+            .context("Agent::start needs an agent made with Agent::from_config")?;
+        if config.privsep_user.is_some() || config.run_as_user.is_some() {
+            return Err(anyhow::anyhow!(
+                "privsep_user and run_as_user need the agent's own process; run the binary instead"
+            ));
+        }
+        let table = toml::Table::try_from(&config).context("Failed to copy the config")?;
+        let (reloads, reloaded) = std::sync::mpsc::channel();
+        let launch = Launch {
+            config: prepare_config(config)?,
+            config_file: None,
+            reloads: Some(reloaded),
+            remote: None,
+            args: cli::RunArgs::default(),
+            ship: None,
+            dry_run: false,
+        };
+        let running = Arc::new(AtomicBool::new(true));
+        let (processors, listeners, exporters) = (
+            std::mem::take(&mut self.processors),
+            std::mem::take(&mut self.listeners),
+            self.exporters.clone(),
+        );
+        let (ready, started) = std::sync::mpsc::channel();
+        let agent_running = running.clone();
+        let agent = thread::spawn(move || {
+            serve(
+                launch,
+                processors,
+                listeners,
+                exporters,
+                agent_running,
+                Some(ready),
+            )
+        });
+        if started.recv().is_err() {
+            // `serve` returned before the agent was running.
+            return match agent.join() {
+                Ok(result) => result,
+                Err(_) => Err(anyhow::anyhow!("the agent panicked while starting")),
+            };
+        }
+        self.started = Some(Started {
+            running,
+            agent,
+            config: table,
+            reloads,
+        });
+        Ok(())
+    }
+
+    /// Tails `path` in the started agent, as an `[[inputs]]` entry of type "file" with
+    /// `options` as its other keys (`mmap`, `rewrite_policy`), and returns the id to remove
+    /// it by: its path. The per-input sections of the config apply to it by its path or
+    /// a pattern matching it. The config with the input is validated like a reloaded one
+    /// and the agent's thread starts the input within a second, recording an error if it
+    /// fails to.
+    pub fn add_file_input(&mut self, path: &str, mut options: toml::Table) -> Result<String> {
+        options.insert("type".to_string(), "file".into());
+        options.insert("path".to_string(), path.into());
+        self.change_inputs(|config, inputs| {
+            let log_files = config.get("log_files").and_then(|f| f.as_array());
+            if log_files.is_some_and(|f| f.iter().any(|f| f.as_str() == Some(path)))
+                || inputs.iter().any(|i| is_file_input(i, path))
+            {
+                return Err(anyhow::anyhow!("{} is already an input", path));
+            }
+            inputs.push(toml::Value::Table(options));
+            Ok(())
+        })?;
+        Ok(path.to_string())
+    }
+
+    /// Stops the input `id` of `add_file_input`, or the `[[inputs]]` entry of type "file"
+    /// for that path, saving its read position.
+    pub fn remove_input(&mut self, id: &str) -> Result<()> {
+        self.change_inputs(|_, inputs| {
+            let before = inputs.len();
+            inputs.retain(|i| !is_file_input(i, id));
+            if inputs.len() == before {
+                return Err(anyhow::anyhow!("{} is not an input added at runtime", id));
+            }
+            Ok(())
+        })
+    }
+
+    /// Applies `change` to the `[[inputs]]` entries of the started agent's config and
+    /// hands the result to the agent, once it validates.
+    fn change_inputs(
+        &mut self,
+        change: impl FnOnce(&toml::Table, &mut Vec<toml::Value>) -> Result<()>,
+    ) -> Result<()> {
+        let started = self.started.as_mut().context("the agent is not started")?;
+        let mut table = started.config.clone();
+        let mut inputs = match table.remove("inputs") {
+            Some(toml::Value::Array(inputs)) => inputs,
+            _ => Vec::new(),
+        };
+        change(&table, &mut inputs)?;
+        table.insert("inputs".to_string(), toml::Value::Array(inputs));
+        let config = prepare_config(toml::Value::Table(table.clone()).try_into()?)?;
+        started
+            .reloads
+            .send(config)
+            .map_err(|_| anyhow::anyhow!("the agent has stopped"))?;
+        started.config = table;
+        Ok(())
+    }
+
+    /// Stops the started agent the way SIGTERM stops the binary: its inputs stop, what
+    /// they read is delivered within `shutdown_timeout_secs` and the read positions are
+    /// saved.
+    pub fn shutdown(&mut self) -> Result<()> {
+        let started = self.started.take().context("the agent is not started")?;
+        started.running.store(false, Ordering::SeqCst);
+        started
+            .agent
+            .join()
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("the agent panicked")))
+    }
+}
+
+fn is_file_input(input: &toml::Value, path: &str) -> bool {
+    input.get("type").and_then(|t| t.as_str()) == Some("file")
+        && input.get("path").and_then(|p| p.as_str()) == Some(path)
+}
+
+/// The stock binary: the agent run from its command line, exiting with the exit code
+/// of what stopped it.
+pub fn main() {
+    #[cfg(windows)]
+    if winservice::started_by_scm() {
+        if let Err(e) = winservice::dispatch(|| Agent::default().run()) {
+            eprintln!("Error: {:?}", e);
+            std::process::exit(failure::exit_code(&e));
+        }
+        return;
+    }
+    if let Err(e) = Agent::default().run() {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(failure::exit_code(&e));
+    }
+}
+
+type Limiter = RateLimiter<
+    governor::state::NotKeyed,
+    governor::state::InMemoryState,
+    governor::clock::DefaultClock,
+>;
+
+/// What the sender thread does with a record after it is dequeued; a reload replaces it.
+struct Pipeline {
+    exporters: Vec<exporter::ExporterHandle>,
+    delivery_policy: exporter::DeliveryPolicy,
+    /// Set when some exporters take only the records of their environments.
+    routes: Option<routes::Routes>,
+    limiter: Option<Arc<Limiter>>,
+    max_record_age: Option<chrono::Duration>,
+    sampler: Option<sampling::Sampler>,
+    severity_schedules: BTreeMap<String, schedule::Schedule>,
+}
+
+impl Pipeline {
+    fn new(
+        config: &Config,
+        exporters: Vec<exporter::ExporterHandle>,
+        state: &Arc<AgentState>,
+    ) -> Pipeline {
+        Pipeline {
+            routes: routes::Routes::new(config, &exporters),
+            exporters,
+            delivery_policy: config.delivery_policy,
+            limiter: config.rate_limit.map(|limit| {
+                let limit = NonZeroU32::new(limit).unwrap_or(nonzero!(100u32));
+                println!("Rate limiting enabled: {} logs/second", limit);
+                Arc::new(RateLimiter::direct(Quota::per_second(limit)))
+            }),
+            max_record_age: config
+                .max_record_age
+                .as_deref()
+                .and_then(|age| config::parse_time_span(age).ok()),
+            sampler: config.sampling.as_ref().map(|s| {
+                let mut sampler = sampling::Sampler::new(s, &config.regex_limits).unwrap();
+                sampler.register(state);
+                sampler
+            }),
+            severity_schedules: config
+                .severity_schedules
+                .iter()
+                .map(|(input, s)| (input.clone(), schedule::Schedule::new(s).unwrap()))
+                .collect(),
+        }
+    }
+}
+
+/// The exporters of `config`, their counters added to `state`.
+fn spawn_exporters(
+    config: &Arc<Config>,
+    custom: &[exporter::Custom],
+    state: &Arc<AgentState>,
+    clock: &Arc<dyn Clock>,
+) -> Vec<exporter::ExporterHandle> {
+    let mut exporters = vec![exporter::ExporterHandle::spawn(
+        "signoz",
+        &config.endpoint,
+        exporter::Protocol::for_endpoint(config.protocol, &config.endpoint),
+        config.credentials(),
+        config.clone(),
+        state.clone(),
+        clock.clone(),
+    )];
+    for extra in &config.exporters {
+        exporters.push(exporter::ExporterHandle::spawn(
+            &extra.name,
+            &extra.endpoint,
+            exporter::Protocol::for_endpoint(extra.protocol, &extra.endpoint),
+            extra.credentials(),
+            config.clone(),
+            state.clone(),
+            clock.clone(),
+        ));
+    }
+    for exporter in custom {
+        exporters.push(exporter::ExporterHandle::spawn_custom(
+            exporter.clone(),
+            config.clone(),
+            state.clone(),
+            clock.clone(),
+        ));
+    }
+    if let Some(ref archive) = config.local_archive {
+        match local_archive::Archive::new(archive, config.clone()) {
+            Ok(archive) => exporters.push(exporter::ExporterHandle::spawn_custom(
+                Arc::new(Mutex::new(Box::new(archive))),
+                config.clone(),
+                state.clone(),
+                clock.clone(),
+            )),
+            Err(e) => state.record_error(format!("Failed to set up local_archive: {:#}", e)),
+        }
+    }
+    #[cfg(feature = "clickhouse")]
+    if let Some(ref clickhouse) = config.clickhouse {
+        exporters.push(exporter::ExporterHandle::spawn_clickhouse(
+            clickhouse.clone(),
+            config.clone(),
+            state.clone(),
+            clock.clone(),
+        ));
+    }
+    #[cfg(feature = "parquet")]
+    if let Some(ref parquet) = config.parquet {
+        exporters.push(exporter::ExporterHandle::spawn_parquet(
+            parquet.clone(),
+            config.clone(),
+            state.clone(),
+            clock.clone(),
+        ));
+    }
+    state
+        .exporters
+        .lock()
+        .unwrap()
+        .extend(exporters.iter().map(|e| e.stats.clone()));
+    exporters
+}
+
+/// The `path_attributes` of `config`, compiled.
+fn path_rules(config: &Config) -> Vec<(Regex, Option<String>)> {
+    config
+        .path_attributes
+        .iter()
+        .enumerate()
+        .map(|(i, rule)| {
+            (
+                rules::compile(
+                    &format!("path_attributes[{}]", i),
+                    &rule.pattern,
+                    &config.regex_limits,
+                )
+                .unwrap(),
+                rule.service_name_group.clone(),
+            )
+        })
+        .collect()
+}
+
+/// Applies a config that passed validation on SIGHUP. Unchanged inputs keep running;
+/// removed and changed ones are stopped, and added and changed ones started. The sender
+/// settings are replaced, and the exporters when their settings changed; the old
+/// exporters deliver what they hold before they exit.
+fn apply_reload(
+    mut config: Config,
+    ctx: &mut input::InputContext,
+    inputs: &mut Vec<Box<dyn input::Input>>,
+    pipeline: &Mutex<Pipeline>,
+    custom: &[exporter::Custom],
+    clock: &Arc<dyn Clock>,
+    privsep: bool,
+) -> Result<()> {
+    let changes = reload::changes(&ctx.config, &config);
+    config.instance_id = ctx.config.instance_id.clone();
+    let config = Arc::new(config);
+    let state = ctx.state.clone();
+
+    if privsep {
+        println!("Inputs are not reloaded with privsep_user; restart the agent to change them");
+    } else {
+        let mut added = input::configured_inputs(&config)?;
+        let (mut kept, mut stopped) = (Vec::new(), Vec::new());
+        for mut input in inputs.drain(..) {
+            let same = added.iter().position(|new| {
+                !changes.inputs
+                    && new.describe() == input.describe()
+                    && new.same_config(input.as_any())
+            });
+            match same {
+                Some(i) => {
+                    added.remove(i);
+                    kept.push(input);
+                }
+                None => {
+                    input.stop();
+                    if let Err(e) = input.checkpoint() {
+                        eprintln!("Failed to checkpoint input {}: {e:#}", input.describe());
+                    }
+                    stopped.push(input.describe());
+                }
+            }
+        }
+        if !stopped.is_empty() {
+            // Lets the stopped inputs wind down, so a restarted one does not read alongside.
+            thread::sleep(Duration::from_millis(500));
+        }
+        for name in &stopped {
+            if !added.iter().any(|i| &i.describe() == name) {
+                println!("Stopped input {}", name);
+                state.retire_input(name);
+            }
+        }
+        ctx.config = config.clone();
+        ctx.path_rules = path_rules(&config);
+        for mut input in added {
+            match input.start(ctx) {
+                Ok(()) => {
+                    println!("Started input {}", input.describe());
+                    kept.push(input);
+                }
+                Err(e) => state.record_error(format!(
+                    "Failed to start input {}: {:#}",
+                    input.describe(),
+                    e
+                )),
+            }
+        }
+        *inputs = kept;
+    }
+
+    if changes.sender || changes.exporters {
+        let retired = {
+            let mut pipeline = pipeline.lock().unwrap();
+            if let Some(ref sampler) = pipeline.sampler {
+                sampler.unregister(&state);
+            }
+            let exporters = if changes.exporters {
+                spawn_exporters(&config, custom, &state, clock)
+            } else {
+                std::mem::take(&mut pipeline.exporters)
+            };
+            std::mem::replace(&mut *pipeline, Pipeline::new(&config, exporters, &state))
+        };
+        // Closed outside the lock: the sender thread goes on with the new exporters.
+        for exporter in retired.exporters {
+            let stats = exporter.stats.clone();
+            exporter.close();
+            state
+                .exporters
+                .lock()
+                .unwrap()
+                .retain(|s| !Arc::ptr_eq(s, &stats));
+        }
+    }
+    if !changes.ignored.is_empty() {
+        println!(
+            "Changes to {} apply once the agent restarts",
+            changes.ignored.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn run(
+    processors: processor::Chain,
+    listeners: delivery::Listeners,
+    exporters: Vec<exporter::Custom>,
+) -> Result<()> {
+    let cli = cli::Cli::parse();
+    let config_path = &cli.config.clone().unwrap_or_else(statedir::config_path);
+    let existing_config = || -> Result<Config> {
+        let mut config: Config = fs::read_to_string(config_path)
+            .with_context(|| format!("Failed to read {}", config_path))
+            .and_then(|contents| Ok(toml::from_str(&contents)?))
+            .map_err(|e| failure::classify(e, Failure::ConfigInvalid))?;
+        cli.overrides.apply(&mut config);
+        Ok(config)
+    };
+    if let Some(ref dir) = cli.emit_golden {
+        return golden::emit(dir);
+    }
+    let remote = cli
+        .config_url
+        .as_deref()
+        .map(|url| remote::Remote::new(url, cli.config_url_interval, config_path))
+        .transpose()?;
+    // The commands that load the config get the fetched one; the others find the running
+    // agent by the cached one.
+    let loads_config = !cli.install_service
+        && matches!(
+            cli.command,
+            None | Some(cli::Command::Run(_))
+                | Some(cli::Command::Tail { .. })
+                | Some(cli::Command::Ship { .. })
+                | Some(cli::Command::BackfillEvtx { .. })
+                | Some(cli::Command::Validate)
+                | Some(cli::Command::TestConnection)
+                | Some(cli::Command::Doctor)
+        );
+    if let Some(remote) = remote.as_ref().filter(|_| loads_config) {
+        remote
+            .fetch_at_startup(&cli.overrides)
+            .map_err(|e| failure::classify(e, Failure::ConfigInvalid))?;
+    }
+    let (args, ship, dry_run) = match cli.command {
+        None if cli.install_service => {
+            #[cfg(windows)]
+            return winservice::install(config_path, cli.output);
+            #[cfg(not(windows))]
+            return install_service_command(config_path, cli.output);
+        }
+        None => (cli.run, None, false),
+        Some(cli::Command::Run(args)) => (args, None, false),
+        Some(cli::Command::Tail { dry_run }) => (cli::RunArgs::default(), None, dry_run),
+        Some(cli::Command::Ship { file, wait }) => (
+            cli::RunArgs::default(),
+            Some(ship::Ship {
+                path: file,
+                wait,
+                evtx: false,
+            }),
+            false,
+        ),
+        Some(cli::Command::BackfillEvtx { file, wait }) => (
+            cli::RunArgs::default(),
+            Some(ship::Ship {
+                path: file,
+                wait,
+                evtx: true,
+            }),
+            false,
+        ),
+        Some(cli::Command::Validate) => {
+            let config = prepare_config(existing_config()?)?;
+            println!(
+                "{} is valid: {} inputs, {} exporters",
+                config_path,
+                input::configured_inputs(&config)?.len(),
+                1 + config.exporters.len()
+            );
+            return Ok(());
+        }
+        Some(cli::Command::TestConnection) => {
+            return exporter::test_connection(&prepare_config(existing_config()?)?);
+        }
+        Some(cli::Command::InstallService { output }) => {
+            #[cfg(windows)]
+            return winservice::install(config_path, output);
+            #[cfg(not(windows))]
+            return install_service_command(config_path, output);
+        }
+        Some(cli::Command::Flush { timeout }) => return flush_command(config_path, timeout),
+        Some(cli::Command::Pause) | Some(cli::Command::Resume) => {
+            let paused = matches!(cli.command, Some(cli::Command::Pause));
+            admin::request_pause(&admin_socket_path(config_path)?, paused)?;
+            println!(
+                "Exporting {}",
+                if paused {
+                    "paused; records are kept until `resume`"
+                } else {
+                    "resumed"
+                }
+            );
+            return Ok(());
+        }
+        Some(cli::Command::SelfUpdate {
+            channel,
+            restart,
+            releases_url,
+        }) => return update::run(&channel, &releases_url, restart),
+        Some(cli::Command::Top { interval }) => return top_command(config_path, interval),
+        #[cfg(unix)]
+        Some(cli::Command::Doctor) => return doctor::run(&existing_config()?),
+        #[cfg(windows)]
+        Some(cli::Command::Doctor) => {
+            return Err(anyhow::anyhow!("doctor checks Unix permissions and is not available on Windows"))
+        }
+        Some(cli::Command::GenerateManifest {
+            target,
+            image,
+            namespace,
+        }) => {
+            let options = manifest::Options { image, namespace };
+            return generate_manifest_command(config_path, &target, &options);
+        }
+        Some(cli::Command::Package {
+            format,
+            output,
+            maintainer,
+        }) => return package::run(config_path, &format, &output, &maintainer),
+        Some(cli::Command::Config(cli::ConfigCommand::Migrate { dry_run })) => {
+            return migrate::run(config_path, dry_run);
+        }
+        Some(cli::Command::BenchReader { file }) => return mmap_reader::bench(&file),
+    };
+    if (args.daemon || ship.is_some()) && !Path::new(config_path).exists() {
+        return Err(anyhow::anyhow!(
+            "{} requires an existing {}; run once in the foreground to create it",
+            match ship {
+                Some(ref ship) if ship.evtx => "backfill-evtx",
+                Some(_) => "ship",
+                None => "--daemon",
+            },
+            config_path
+        ));
+    }
+
+    // Piped into without a config.toml, the agent runs on the command line's settings
+    // rather than asking for them on the terminal.
+    let mut config = match cli.overrides.endpoint {
+        Some(ref endpoint) if cli.overrides.stdin && !Path::new(config_path).exists() => {
+            let settings = toml::Table::from_iter([("endpoint".into(), endpoint.clone().into())]);
+            toml::Value::Table(settings).try_into()?
+        }
+        _ => load_or_create_config(config_path)
+            .map_err(|e| failure::classify(e, Failure::ConfigInvalid))?,
+    };
+    cli.overrides.apply(&mut config);
+    let launch = Launch {
+        config: prepare_config(config)?,
+        config_file: Some((config_path.to_string(), cli.overrides)),
+        reloads: None,
+        remote,
+        args,
+        ship,
+        dry_run,
+    };
+    let running = Arc::new(AtomicBool::new(true));
+    serve(launch, processors, listeners, exporters, running, None)
+}
+
+/// What `serve` runs: a prepared config, and how the agent was started.
+struct Launch {
+    config: Config,
+    /// `config.toml` and the command-line overrides applied to it, for an agent run from
+    /// its command line; it reloads them on SIGHUP and stops on termination signals.
+    config_file: Option<(String, cli::Overrides)>,
+    /// For an embedded agent, the configs of `Agent::add_file_input` and
+    /// `Agent::remove_input`.
+    reloads: Option<std::sync::mpsc::Receiver<Config>>,
+    /// With `--config-url`, where the config is fetched again to be reloaded.
+    remote: Option<remote::Remote>,
+    args: cli::RunArgs,
+    ship: Option<ship::Ship>,
+    dry_run: bool,
+}
+
+/// Runs the agent of `launch` until `running` is cleared, then shuts it down. `ready`
+/// is told once the inputs are started.
+fn serve(
+    launch: Launch,
+    mut processors: processor::Chain,
+    mut listeners: delivery::Listeners,
+    exporters: Vec<exporter::Custom>,
+    running: Arc<AtomicBool>,
+    ready: Option<std::sync::mpsc::Sender<()>>,
+) -> Result<()> {
+    let Launch {
+        mut config,
+        config_file,
+        reloads,
+        remote,
+        args,
+        ship,
+        dry_run,
+    } = launch;
+    // Shipping a file or a dry run share the state directory with an agent that may be
+    // running, and leave what it saves there alone.
+    let one_off = ship.is_some() || dry_run;
+    let daemonize = args.daemon;
+    config.self_check = args.self_check;
+    config.oneshot = args.oneshot;
+    if one_off || config.oneshot {
+        // What is shipped is delivered before the agent exits, not left on disk for a
+        // later run.
+        config.disk_buffer = None;
+    }
+    if config.oneshot && config.privsep_user.is_some() {
+        return Err(anyhow::anyhow!("--oneshot cannot be used with privsep_user")
+            .context(Failure::ConfigInvalid));
+    }
+
+    if args.fail_fast {
+        exporter::probe(&config).context(Failure::EndpointUnreachable)?;
+        println!("Endpoint {} accepted the startup probe", config.endpoint);
+    }
+
+    let pid_file = config.pid_file_path();
+    let state_dir = config.state_directory();
+    let socket_path = config.socket_path();
+    if config.read_only_root {
+        let mut writes = vec![("admin_socket", socket_path.as_str())];
+        let log_file = config.agent_log_path();
+        let buffer_dir = config.disk_buffer_dir();
+        if let Some(ref dir) = buffer_dir {
+            writes.push(("disk_buffer", dir.as_str()));
+        }
+        if daemonize {
+            writes.push(("pid_file", pid_file.as_str()));
+            writes.push(("agent_log_file", log_file.as_str()));
+        }
+        statedir::check_contained(&state_dir, &writes)?;
+    }
+    let mut state_lock = if one_off {
+        None
+    } else {
+        filelock::hold(
+            &state_dir,
+            statedir::LOCK_FILE,
+            "State directory",
+            "state_dir",
+            args.force,
+        )?
+    };
+    // Taken once: exporters restarted by a reload open their buffers while the ones they
+    // replace still have them open.
+    let mut buffer_lock = match config.disk_buffer_dir() {
+        Some(dir) => filelock::hold(
+            &dir,
+            spool::LOCK_FILE,
+            "Disk buffer",
+            "disk_buffer.dir",
+            args.force,
+        )?,
+        None => None,
+    };
+    if let Some(s3) = config.state_s3.as_ref().filter(|_| config.oneshot) {
+        oneshot::fetch_state(s3, &state_dir, &config)?;
+    }
+    let positions = checkpoint::Positions::load(&state_dir)?;
+    // Sockets passed to an embedding program are its own.
+    if config_file.is_some() {
+        activation::inherit();
+    }
+
+    let _pid_lock = if daemonize {
+        let log_file = config.agent_log_path();
+        println!("Starting in background, logging to {}", log_file);
+        let pid_lock = daemon::daemonize(&pid_file, &log_file).context("Failed to daemonize")?;
+        for lock in [&mut state_lock, &mut buffer_lock].into_iter().flatten() {
+            lock.write_pid()?;
+        }
+        Some(pid_lock)
+    } else {
+        None
+    };
+
+    // What an unprivileged agent writes to, handed over to it before it switches.
+    #[cfg(unix)]
+    let buffer_dir = config.disk_buffer_dir();
+    #[cfg(unix)]
+    let own_dirs: Vec<&str> = std::iter::once(state_dir.as_str())
+        .chain(buffer_dir.as_deref())
+        .collect();
+    #[cfg_attr(windows, allow(unused_mut))]
+    let mut reader_pipe: Option<(fs::File, fs::File)> = None;
+    #[cfg(unix)]
+    if let Some(user) = config.privsep_user.clone().filter(|_| !one_off) {
+        match privsep::split(&user, &own_dirs)? {
+            privsep::Role::Reader { agent, pipe, acks } => {
+                let code = privsep::run_reader(config, positions, agent, pipe, acks)?;
+                if daemonize {
+                    daemon::remove_pid_file(&pid_file);
+                }
+                std::process::exit(code);
+            }
+            privsep::Role::Agent { pipe, acks } => reader_pipe = Some((pipe, acks)),
+        }
+    }
+
+    // Code embedding the agent stops it itself.
+    #[cfg(unix)]
+    if config_file.is_some() {
+        let r = running.clone();
+        let mut signals = Signals::new(TERM_SIGNALS)?;
+        thread::spawn(move || {
+            if signals.forever().next().is_some() {
+                println!("Received termination signal, shutting down...");
+                r.store(false, Ordering::SeqCst);
+            }
+        });
+    }
+    // Ctrl+C, and the C runtime's SIGTERM; the service manager's stop comes through
+    // `winservice`.
+    #[cfg(windows)]
+    if config_file.is_some() {
+        for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+            let r = running.clone();
+            // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+            unsafe { signal_hook::low_level::register(signal, move || r.store(false, Ordering::SeqCst)) }?;
+        }
+    }
+    #[cfg(windows)]
+    if args.windows_service {
+        winservice::stop_with(running.clone());
+    }
+
+    if ship.is_none() {
+        println!("Monitoring log files: {:?}", input::file_paths(&config));
+    }
+    println!("SigNoz endpoint: {}", config.endpoint);
+
+    let state = Arc::new(AgentState::default());
+    if !one_off {
+        #[cfg(unix)]
+        admin::serve(&socket_path, state.clone()).context("Failed to start admin socket")?;
+        #[cfg(unix)]
+        if config_file.is_some() {
+            admin::watch_signals(state.clone(), positions.clone())?;
+        }
+        if let Some(ref http_status) = config.http_status {
+            http_status::serve(&http_status.bind, state.clone())?;
+        }
+    }
+
+    let audit = match config.audit.clone().filter(|_| !one_off && !dry_run) {
+        Some(audit) => {
+            let id = audit::instance_id(&audit, &state_dir)?;
+            config.instance_id = Some(id.clone());
+            Some((audit, id, Arc::new(audit::Sent::new(Utc::now()))))
+        }
+        None => None,
+    };
+    if let Some(ref cloud) = config.cloud_metadata {
+        cloud::watch(cloud);
+    }
+
+    let config = Arc::new(config);
+    let sender_state = state.clone();
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+    let sender_clock = clock.clone();
+    let pipeline = Arc::new(Mutex::new(Pipeline::new(
+        &config,
+        if dry_run {
+            Vec::new()
+        } else {
+            spawn_exporters(&config, &exporters, &state, &clock)
+        },
+        &state,
+    )));
+    if let Some(handshake) = config.handshake.as_ref().filter(|_| !dry_run) {
+        handshake::run(&config, handshake, &state)?;
+    }
+    let sender_pipeline = pipeline.clone();
+    // First, so that the others see the records as they will be shipped.
+    if let Some(ref control) = config.control_characters {
+        processors.push(Box::new(
+            control::ControlChars::new(control).marking(config.mark_mutations),
+        ));
+    }
+    if let Some(ref check) = config.timestamp_check {
+        processors.push(Box::new(
+            timestamp::TimestampCheck::new(check, state.clone()).unwrap(),
+        ));
+    }
+    if let Some(ref exceptions) = config.exceptions {
+        processors.push(Box::new(exception::Exceptions::new(exceptions)));
+    }
+    if !config.computed_attributes.is_empty() {
+        processors.push(Box::new(
+            computed::Computed::new(&config.computed_attributes).unwrap(),
+        ));
+    }
+    for map in &config.severity_maps {
+        processors.push(Box::new(severity::SeverityMap {
+            mark: config.mark_mutations,
+            path: fieldpath::FieldPath::parse(&map.attribute).ok(),
+            ..map.clone()
+        }));
+    }
+    // Last, so that what other processors add is masked too.
+    if let Some(ref redaction) = config.redaction {
+        processors.push(Box::new(
+            redact::Redactor::new(redaction, &config.regex_limits)
+                .unwrap()
+                .marking(config.mark_mutations),
+        ));
+    }
+    let tally = Arc::new(ship::Tally::default());
+    if ship.is_some() || config.oneshot {
+        listeners.push(tally.clone());
+    }
+    if let Some((_, _, ref sent)) = audit {
+        listeners.push(sent.clone());
+    }
+    let queue = queue::FairQueue::new(
+        config.queue_weights.clone(),
+        config.queue_capacity.unwrap_or(queue::DEFAULT_CAPACITY),
+        config.queue_overflow,
+        state.clone(),
+        listeners.clone(),
+    );
+    let rx = queue.clone();
+    let sender_config = config.clone();
+    let sender_rollup = config
+        .severity_rollup
+        .as_ref()
+        .filter(|_| !one_off)
+        .map(|rollup| {
+            let counts = Arc::new(rollup::Rollup::default());
+            rollup::run(
+                rollup,
+                counts.clone(),
+                queue.sender(rollup::INPUT),
+                state.clone(),
+            );
+            counts
+        });
+    let stripper = (!config.metadata_only.is_empty()).then(metadata::Stripper::default);
+    let throttle = (!config.throttle_rules.is_empty()).then(|| {
+        let throttle = Arc::new(
+            throttle::Throttle::new(&config.throttle_rules, &config.regex_limits, clock.now())
+                .unwrap(),
+        );
+        throttle.register(&state);
+        throttle::run(
+            throttle.clone(),
+            clock.clone(),
+            queue.sender(throttle::INPUT),
+            state.clone(),
+        );
+        throttle
+    });
+    let _sender_thread = thread::spawn(move || {
+        loop {
+            let (input, batch) = rx.recv();
+            // The agent's own summary records are neither counted, throttled nor sampled.
+            let is_summary = &*input == rollup::INPUT || &*input == throttle::INPUT;
+            if let Some(rollup) = sender_rollup.as_ref().filter(|_| !is_summary) {
+                let severities = batch.iter().map(|e| severity_of(e, &sender_config));
+                rollup.count(&input, severities);
+            }
+            let metadata_only = input::option_for(&sender_config.metadata_only, &input)
+                .or_else(|| sender_config.metadata_only.get("*"))
+                .filter(|_| !is_summary);
+            for entry in batch {
+                let mut pipeline = sender_pipeline.lock().unwrap();
+                let started = Instant::now();
+                if is_too_old(
+                    sender_clock.as_ref(),
+                    entry.timestamp,
+                    pipeline.max_record_age,
+                ) {
+                    sender_state.record_drop("too_old");
+                    listeners.notify(&entry, &delivery::Outcome::Dropped("too_old"));
+                    sender_state.queued.fetch_sub(1, Ordering::SeqCst);
+                    continue;
+                }
+
+                let schedule = input::option_for(&pipeline.severity_schedules, &input)
+                    .or_else(|| pipeline.severity_schedules.get("*"))
+                    .filter(|_| !is_summary);
+                if let Some(schedule) = schedule {
+                    let severity = severity_of(&entry, &sender_config).number;
+                    if severity < schedule.min_severity(sender_clock.now()) {
+                        sender_state.record_drop("severity_schedule");
+                        let outcome = delivery::Outcome::Dropped("severity_schedule");
+                        listeners.notify(&entry, &outcome);
+                        sender_state.queued.fetch_sub(1, Ordering::SeqCst);
+                        continue;
+                    }
+                }
+
+                if let Some(throttle) = throttle.as_ref().filter(|_| !is_summary) {
+                    let severity = severity_of(&entry, &sender_config);
+                    let quote = metadata_only.is_none();
+                    if !throttle.admit(&entry.body.text(), severity, quote, sender_clock.now()) {
+                        sender_state.record_drop("throttled");
+                        listeners.notify(&entry, &delivery::Outcome::Dropped("throttled"));
+                        sender_state.queued.fetch_sub(1, Ordering::SeqCst);
+                        continue;
+                    }
+                }
+
+                if let Some(sampler) = pipeline.sampler.as_mut().filter(|_| !is_summary) {
+                    let severity = severity_of(&entry, &sender_config).number;
+                    if !sampler.keep(&entry.body.text(), severity) {
+                        sender_state.record_drop("sampled");
+                        listeners.notify(&entry, &delivery::Outcome::Dropped("sampled"));
+                        sender_state.queued.fetch_sub(1, Ordering::SeqCst);
+                        continue;
+                    }
+                }
+
+                sender_state.record_stage(Stage::Filter, 1, started.elapsed());
+
+                let entry = if processors.is_empty() {
+                    entry
+                } else {
+                    let started = Instant::now();
+                    // Processors take the record, so listeners of dropped ones get a copy.
+                    let original = (!listeners.is_empty()).then(|| entry.clone());
+                    let processed = processors.process(entry);
+                    sender_state.record_stage(Stage::Process, 1, started.elapsed());
+                    match processed {
+                        Some(entry) => entry,
+                        None => {
+                            sender_state.record_drop("processor");
+                            if let Some(ref original) = original {
+                                let outcome = delivery::Outcome::Dropped("processor");
+                                listeners.notify(original, &outcome);
+                            }
+                            sender_state.queued.fetch_sub(1, Ordering::SeqCst);
+                            continue;
+                        }
+                    }
+                };
+
+                // Waited for without the pipeline, which a reload would wait for too.
+                if let Some(limiter) = pipeline.limiter.clone() {
+                    drop(pipeline);
+                    if !sender_state.flushing.load(Ordering::SeqCst) {
+                        block_on(limiter.until_ready());
+                    }
+                    pipeline = sender_pipeline.lock().unwrap();
+                }
+
+                let entry = match (metadata_only, stripper.as_ref()) {
+                    (Some(metadata_only), Some(stripper)) => {
+                        let severity = severity_of(&entry, &sender_config);
+                        stripper.strip(entry, metadata_only, severity)
+                    }
+                    _ => entry,
+                };
+
+                if dry_run {
+                    let severity = severity_of(&entry, &sender_config);
+                    let payload = build_otlp_payload(&entry, severity, &sender_config);
+                    println!("{}", serde_json::to_string(&payload).unwrap_or_default());
+                    sender_state.queued.fetch_sub(1, Ordering::SeqCst);
+                    continue;
+                }
+
+                let targets: Vec<&exporter::ExporterHandle> = match pipeline.routes {
+                    Some(ref routes) => pipeline
+                        .exporters
+                        .iter()
+                        .zip(routes.targets(&entry))
+                        .filter_map(|(exporter, takes)| takes.then_some(exporter))
+                        .collect(),
+                    None => pipeline.exporters.iter().collect(),
+                };
+                if targets.is_empty() {
+                    sender_state.record_drop("unrouted");
+                    listeners.notify(&entry, &delivery::Outcome::Dropped("unrouted"));
+                    sender_state.queued.fetch_sub(1, Ordering::SeqCst);
+                    continue;
+                }
+                let entry = Arc::new(entry);
+                let receipt = Arc::new(exporter::Receipt::new(
+                    targets.len(),
+                    pipeline.delivery_policy,
+                    sender_state.clone(),
+                    entry.clone(),
+                    listeners.clone(),
+                ));
+                for exporter in targets {
+                    exporter.submit(&input, entry.clone(), receipt.clone());
+                }
+                sender_state.queued.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    });
+
+    let mut ctx = input::InputContext {
+        config: config.clone(),
+        path_rules: path_rules(&config),
+        queue,
+        state: state.clone(),
+        positions: positions.clone(),
+        catch_up: config.catch_up.as_ref().map(catchup::CatchUp::new),
+    };
+    if let Some(ref ship) = ship {
+        return ship::run(ship, &ctx, &tally, &running, config.shutdown_timeout());
+    }
+    // A dry run leaves out what reloading would start or replace.
+    let reloads = match config_file {
+        Some((path, overrides)) if !dry_run => {
+            reload::watch(path, overrides, remote, state.clone())?
+        }
+        _ => reloads.unwrap_or_else(|| std::sync::mpsc::channel().1),
+    };
+    let mut inputs = input::configured_inputs(&config)?;
+    let privsep = reader_pipe.is_some();
+    #[cfg(unix)]
+    if let Some((pipe, acks)) = reader_pipe {
+        let tailed: Vec<String> = input::file_inputs(&config)?
+            .into_iter()
+            .map(|i| i.path)
+            .collect();
+        inputs.retain(|i| !tailed.contains(&i.describe()));
+        inputs.push(Box::new(privsep::PipeInput::new(pipe, acks)));
+    }
+    for input in &mut inputs {
+        input
+            .start(&ctx)
+            .with_context(|| format!("Failed to start input {}", input.describe()))?;
+    }
+    activation::report_unused(&state);
+
+    let warning_days = config
+        .cert_warning_days
+        .unwrap_or(cert::DEFAULT_WARNING_DAYS);
+    if warning_days > 0 && !dry_run {
+        let exporters = state.exporters.lock().unwrap().clone();
+        if exporters.iter().any(|e| e.endpoint.starts_with("https://")) {
+            let identity = config.tls.as_ref().and_then(|tls| tls.identity().ok()?);
+            cert::run(exporters, warning_days, identity, state.clone());
+        }
+    }
+
+    if let Some((audit, id, sent)) = audit {
+        println!("Auditing deliveries as agent.instance.id {}", id);
+        audit::run(audit, id, sent, config.clone(), state.clone());
+    }
+    if !config.volume_alerts.is_empty() && !one_off {
+        volume::watch(config.volume_alerts.clone(), state.clone());
+    }
+    if let Some(telemetry) = config.self_telemetry.as_ref().filter(|_| !dry_run) {
+        telemetry::run(telemetry.clone(), config.clone(), state.clone());
+    }
+    if let Some(host_metrics) = config.metrics.as_ref().filter(|_| !dry_run) {
+        hostmetrics::run(
+            host_metrics.clone(),
+            config.clone(),
+            state.clone(),
+            clock.clone(),
+        );
+    }
+
+    // A oneshot run saves where it got to once that was delivered, and only then.
+    if !dry_run && !config.oneshot {
+        checkpoint::run(
+            positions.clone(),
+            Duration::from_secs(
+                config
+                    .checkpoint_interval_secs
+                    .unwrap_or(checkpoint::DEFAULT_INTERVAL_SECS)
+                    .max(1),
+            ),
+            state.clone(),
+        );
+    }
+
+    #[cfg(unix)]
+    if let Some(user) = config.run_as_user.as_deref().filter(|_| !one_off) {
+        privsep::drop_root(user, config.run_as_group.as_deref(), &own_dirs)?;
+    }
+
+    if let Some(ready) = ready {
+        ready.send(()).ok();
+    }
+    println!("rust-signoz-agent is running. Press Ctrl+C to exit.");
+    while running.load(Ordering::SeqCst) && !state.input_ended.load(Ordering::SeqCst) {
+        if config.oneshot && state.files_unread.load(Ordering::SeqCst) == 0 {
+            println!("Every file is read to its end");
+            break;
+        }
+        if let Ok(config) = reloads.recv_timeout(Duration::from_secs(1)) {
+            let previous = ctx.config.clone();
+            let reloaded = apply_reload(
+                config,
+                &mut ctx,
+                &mut inputs,
+                &pipeline,
+                &exporters,
+                &clock,
+                privsep,
+            );
+            if let Err(e) = reloaded {
+                state.record_error(format!("Failed to apply the reloaded config: {:#}", e));
+            }
+            if !Arc::ptr_eq(&previous, &ctx.config) {
+                println!("Monitoring log files: {:?}", input::file_paths(&ctx.config));
+            }
+        }
+    }
+
+    println!("Shutting down gracefully...");
+    state.stopping.store(true, Ordering::SeqCst);
+    let oneshot = config.oneshot.then_some(tally.as_ref());
+    shutdown(&mut inputs, &state, &positions, &config, clock.as_ref(), dry_run, oneshot);
+    if !dry_run {
+        fs::remove_file(&socket_path).ok();
+    }
+    if daemonize {
+        daemon::remove_pid_file(&pid_file);
+    }
+    if config.oneshot {
+        let (delivered, dropped, undelivered) = tally.counts();
+        let pending = state.pending() as u64;
+        println!(
+            "Oneshot run: {} records delivered, {} dropped, {} undelivered, {} still queued",
+            delivered, dropped, undelivered, pending
+        );
+        if undelivered + pending > 0 {
+            return Err(anyhow::anyhow!(
+                "{} records were not delivered; the state was left as the previous run saved it",
+                undelivered + pending
+            )
+            .context(Failure::ShipIncomplete));
+        }
+        if let Some(ref s3) = config.state_s3 {
+            oneshot::store_state(s3, &state_dir, &config)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Stops the inputs and waits until they read no more lines, hands what the pipeline holds
+/// to the exporters, waits for them to deliver it, and saves the read positions. With
+/// `oneshot`, the outcomes of a `--oneshot` run, they are only saved once every record
+/// was delivered.
+fn shutdown(
+    inputs: &mut [Box<dyn input::Input>],
+    state: &AgentState,
+    positions: &checkpoint::Positions,
+    config: &Config,
+    clock: &dyn Clock,
+    dry_run: bool,
+    oneshot: Option<&ship::Tally>,
+) {
+    let lines = || -> u64 {
+        let inputs = state.inputs.lock().unwrap();
+        inputs.iter().map(|i| i.lines.load(Ordering::Relaxed)).sum()
+    };
+    let read = std::cell::Cell::new(lines());
+    let timeout = |step| shutdown::timeout(step, &config.shutdown_step_timeouts);
+    let inputs = std::cell::RefCell::new(inputs);
+    let steps = vec![
+        shutdown::Step {
+            name: "inputs",
+            start: Box::new(|| {
+                for input in inputs.borrow_mut().iter_mut() {
+                    input.stop();
+                }
+            }),
+            // Until no line was read since the last check.
+            done: Box::new(|| read.replace(lines()) == read.get()),
+            left: Box::new(|| "inputs still reading".to_string()),
+            timeout: timeout("inputs"),
+        },
+        shutdown::Step {
+            name: "drain",
+            // Partial batches and records waiting for continuation lines are sent right away.
+            start: Box::new(|| state.flushing.store(true, Ordering::SeqCst)),
+            done: Box::new(|| {
+                state.queued.load(Ordering::SeqCst) + state.joining.load(Ordering::SeqCst) == 0
+            }),
+            left: Box::new(|| {
+                format!(
+                    "{} records not handed to the exporters",
+                    state.queued.load(Ordering::SeqCst) + state.joining.load(Ordering::SeqCst)
+                )
+            }),
+            timeout: timeout("drain"),
+        },
+        shutdown::Step {
+            name: "flush",
+            start: Box::new(|| {}),
+            done: Box::new(|| state.pending() == 0),
+            left: Box::new(|| format!("{} records not delivered", state.pending())),
+            timeout: timeout("flush"),
+        },
+        shutdown::Step {
+            name: "persist",
+            start: Box::new(|| {
+                let undelivered = oneshot.is_some_and(|tally| {
+                    let (_, _, undelivered) = tally.counts();
+                    undelivered > 0 || state.pending() > 0
+                });
+                if dry_run || undelivered {
+                    return;
+                }
+                for input in inputs.borrow_mut().iter_mut() {
+                    if let Err(e) = input.checkpoint() {
+                        eprintln!("Failed to checkpoint input {}: {e:#}", input.describe());
+                    }
+                }
+                if let Err(e) = positions.save() {
+                    eprintln!("Failed to save read positions: {e:#}");
+                }
+            }),
+            done: Box::new(|| true),
+            left: Box::new(String::new),
+            timeout: timeout("persist"),
+        },
+    ];
+    shutdown::run(steps, config.shutdown_timeout(), clock);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SimulatedClock;
+
+    #[test]
+    fn record_age_is_measured_against_the_clock() {
+        let start = Utc::now();
+        let clock = SimulatedClock::new(start);
+        let max_age = Some(chrono::Duration::hours(1));
+
+        assert!(!is_too_old(&clock, Some(start), max_age));
+        clock.advance(Duration::from_secs(3601));
+        assert!(is_too_old(&clock, Some(start), max_age));
+        assert!(!is_too_old(&clock, None, max_age));
+        assert!(!is_too_old(&clock, Some(start), None));
+    }
+
+    #[test]
+    fn batched_records_share_their_resource() {
+        let config: Config = toml::from_str(
+            r#"
+            endpoint = "http://localhost:4318"
+            [attribute_hints]
+            resource = ["k8s.pod.name"]
+            "#,
+        )
+        .unwrap();
+        let entry = |line: &str, service: &str| Record {
+            service_name: Some(service.into()),
+            ..Record::new(line)
+        };
+        // Records of a syslog input name their host, those of a file input their pod, in
+        // either order.
+        let from_input = |line: &str, resource: &[(&str, &str)], pod: Option<&str>| Record {
+            resource: Some(
+                resource
+                    .iter()
+                    .map(|&(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            ),
+            attributes: pod
+                .map(|pod| ("k8s.pod.name".to_string(), pod.to_string()))
+                .into_iter()
+                .collect(),
+            ..entry(line, "api")
+        };
+        let grouped = |entries: &[Record]| -> Vec<Vec<String>> {
+            merge_otlp_payloads(
+                entries
+                    .iter()
+                    .map(|e| build_otlp_payload(e, Severity::INFO, &config))
+                    .collect(),
+            )
+            .resource_logs
+            .iter()
+            .map(|r| {
+                r.scope_logs[0]
+                    .log_records
+                    .iter()
+                    .map(|l| serde_json::to_value(&l.body).unwrap()["stringValue"].to_string())
+                    .collect()
+            })
+            .collect()
+        };
+        assert_eq!(
+            grouped(&[
+                entry("a", "api"),
+                entry("b", "web"),
+                entry("c", "api"),
+                from_input("d", &[("host.name", "db1"), ("os.type", "linux")], None),
+                from_input("e", &[("os.type", "linux"), ("host.name", "db1")], None),
+                from_input("f", &[("host.name", "db2")], None),
+                from_input("g", &[], Some("web-7d9")),
+                from_input("h", &[], Some("web-7d9")),
+            ]),
+            vec![
+                vec!["\"a\"", "\"c\""],
+                vec!["\"b\""],
+                vec!["\"d\"", "\"e\""],
+                vec!["\"f\""],
+                vec!["\"g\"", "\"h\""],
+            ]
+        );
+
+        // The dispatcher interleaves the inputs in a batch; each record still joins the
+        // first of its resource, and a host of two services is two resources.
+        let syslog_of = |line: &str, service: &str| Record {
+            service_name: Some(service.into()),
+            ..from_input(line, &[("host.name", "db1")], None)
+        };
+        assert_eq!(
+            grouped(&[
+                from_input("i", &[], Some("web-7d9")),
+                syslog_of("j", "api"),
+                entry("k", "api"),
+                from_input("l", &[], Some("web-8f2")),
+                syslog_of("m", "web"),
+                from_input("n", &[], Some("web-7d9")),
+                entry("o", "api"),
+                syslog_of("p", "api"),
+            ]),
+            vec![
+                vec!["\"i\"", "\"n\""],
+                vec!["\"j\"", "\"p\""],
+                vec!["\"k\"", "\"o\""],
+                vec!["\"l\""],
+                vec!["\"m\""],
+            ]
+        );
+    }
+
+    #[test]
+    fn altered_records_name_their_mutations() {
+        let config: Config = toml::from_str(
+            r#"
+            endpoint = "http://localhost:4318"
+            mark_mutations = true
+            max_attribute_value_length = 16
+            [redaction]
+            detectors = ["email"]
+            "#,
+        )
+        .unwrap();
+        let mut redactor =
+            redact::Redactor::new(config.redaction.as_ref().unwrap(), &config.regex_limits)
+                .unwrap()
+                .marking(true);
+        let entry = |line: &str, value: &str| Record {
+            attributes: Attributes::from(vec![("user.agent".to_string(), value.to_string())]),
+            ..Record::new(line)
+        };
+        let mut mutations = |entry: Record| {
+            let entry = processor::Processor::process(&mut redactor, entry).unwrap();
+            let payload = build_otlp_payload(&entry, Severity::INFO, &config);
+            payload.resource_logs[0].scope_logs[0].log_records[0]
+                .attributes
+                .iter()
+                .find(|kv| kv.key == processor::MUTATIONS_ATTRIBUTE)
+                .map(|kv| serde_json::to_value(&kv.value).unwrap()["stringValue"].clone())
+        };
+
+        assert_eq!(mutations(entry("login ok", "curl/8.5")), None);
+        assert_eq!(
+            mutations(entry("login by jane@example.com", "curl/8.5")),
+            Some("redaction".into())
+        );
+        assert_eq!(
+            mutations(entry(
+                "login by jane@example.com",
+                "Mozilla/5.0 (X11; Linux x86_64)"
+            )),
+            Some("redaction,truncation".into())
+        );
+    }
+
+    #[test]
+    fn severity_keywords_extend_the_built_in_level_words() {
+        let keywords = BTreeMap::from([
+            ("Fehler".to_string(), "ERROR".to_string()),
+            ("ОШИБКА".to_string(), "ERROR".to_string()),
+        ]);
+        assert_eq!(
+            detect_severity("2024-03-05 [fehler] Verbindung", &keywords),
+            Some(Severity::ERROR)
+        );
+        assert_eq!(
+            detect_severity("ошибка: connection refused, WARN", &keywords),
+            Some(Severity::ERROR)
+        );
+        assert_eq!(
+            detect_severity("warning: FEHLERFREI", &keywords),
+            Some(Severity::WARN)
+        );
+        assert_eq!(detect_severity("no level here", &keywords), None);
+        assert_eq!(
+            detect_severity("mail to info@example.com failed with error.", &keywords),
+            Some(Severity::ERROR)
+        );
+    }
+}
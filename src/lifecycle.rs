@@ -0,0 +1,310 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::fs;
+#[cfg(unix)]
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::input::{Input, InputContext, StopFlag};
+use crate::{enqueue, Attributes, Body, Record, Severity};
+
+/// What the last run saw, kept in the state directory to tell upgrades and host changes
+/// that happened while the agent was down.
+const HOST_FILE: &str = "lifecycle.json";
+
+/// How often the reload counters and the stop flag are looked at.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Whether `agent.started` was sent, so an input restarted by a reload does not send it
+/// again.
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Records of the agent's own lifecycle and of changes to the host it runs on: start,
+/// stop, upgrade, config reloads, host name and address changes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LifecycleConfig {
+    /// How often the host name and addresses are checked for changes.
+    #[serde(default = "default_interval")]
+    pub interval_secs: u64,
+    #[serde(skip)]
+    pub stop: StopFlag,
+}
+
+fn default_interval() -> u64 {
+    60
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct HostState {
+    version: String,
+    host_name: String,
+    addresses: Vec<String>,
+}
+
+impl HostState {
+    fn current() -> HostState {
+        HostState {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            host_name: hostname::get()
+                .map(|h| h.to_string_lossy().trim().to_string())
+                .unwrap_or_default(),
+            addresses: host_addresses(),
+        }
+    }
+
+    fn load(path: &Path) -> Option<HostState> {
+        serde_json::from_slice(&fs::read(path).ok()?).ok()
+    }
+
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// An event record: its `event.name`, message, severity and attributes.
+struct Event {
+    name: &'static str,
+    message: String,
+    severity: Severity,
+    attributes: Vec<(&'static str, String)>,
+}
+
+impl Event {
+    fn info(name: &'static str, message: String) -> Event {
+        Event {
+            name,
+            message,
+            severity: Severity::INFO,
+            attributes: Vec::new(),
+        }
+    }
+
+    fn with(mut self, key: &'static str, value: impl Into<String>) -> Event {
+        self.attributes.push((key, value.into()));
+        self
+    }
+}
+
+/// Events for what changed between `previous` and `current`.
+fn changes(previous: &HostState, current: &HostState) -> Vec<Event> {
+    let mut events = Vec::new();
+    if previous.version != current.version {
+        events.push(
+            Event::info(
+                "agent.upgraded",
+                format!(
+                    "Agent version changed from {} to {}",
+                    previous.version, current.version
+                ),
+            )
+            .with("agent.previous_version", &previous.version),
+        );
+    }
+    if previous.host_name != current.host_name {
+        events.push(
+            Event::info(
+                "host.renamed",
+                format!(
+                    "Host name changed from {} to {}",
+                    previous.host_name, current.host_name
+                ),
+            )
+            .with("host.previous_name", &previous.host_name)
+            .with("host.detected_name", &current.host_name),
+        );
+    }
+    if previous.addresses != current.addresses {
+        events.push(
+            Event::info(
+                "host.addresses_changed",
+                format!(
+                    "Host addresses changed from [{}] to [{}]",
+                    previous.addresses.join(", "),
+                    current.addresses.join(", ")
+                ),
+            )
+            .with("host.previous_ip", previous.addresses.join(","))
+            .with("host.ip", current.addresses.join(",")),
+        );
+    }
+    events
+}
+
+impl Input for LifecycleConfig {
+    fn describe(&self) -> String {
+        "lifecycle".to_string()
+    }
+
+    fn start(&mut self, ctx: &InputContext) -> anyhow::Result<()> {
+        let stats = ctx.register(self);
+        let tx = ctx.queue.sender(&stats.name);
+        let state = ctx.state.clone();
+        let path = PathBuf::from(ctx.config.state_directory()).join(HOST_FILE);
+        let interval = Duration::from_secs(self.interval_secs.max(1));
+        let stop = self.stop.clone();
+        let emit = move |event: Event| {
+            stats.record_line();
+            let mut attributes = Attributes::new();
+            attributes.push("event.name", event.name);
+            attributes.push("agent.version", env!("CARGO_PKG_VERSION"));
+            attributes.extend(
+                event
+                    .attributes
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v)),
+            );
+            println!("[lifecycle] {}", event.message);
+            enqueue(
+                &tx,
+                &state,
+                Record {
+                    body: Body::Text(event.message),
+                    attributes,
+                    default_severity: Some(event.severity),
+                    severity: None,
+                    service_name: None,
+                    timestamp: None,
+                    observed: Utc::now(),
+                    otlp_path: None,
+                    trace: None,
+                    resource: None,
+                    checkpoint: None,
+                },
+            );
+        };
+
+        let mut host = HostState::current();
+        if let Some(previous) = HostState::load(&path) {
+            changes(&previous, &host).into_iter().for_each(&emit);
+        }
+        if !STARTED.swap(true, Ordering::SeqCst) {
+            emit(
+                Event::info("agent.started", format!("Agent {} started", host.version))
+                    .with("host.ip", host.addresses.join(",")),
+            );
+        }
+        let save = move |host: &HostState| {
+            if let Err(e) = host.save(&path) {
+                eprintln!("Failed to save {}: {e:#}", HOST_FILE);
+            }
+        };
+        save(&host);
+
+        let state = ctx.state.clone();
+        thread::spawn(move || {
+            let mut reloads = state.config_reloads.load(Ordering::SeqCst);
+            let mut failures = state.config_reload_failures.load(Ordering::SeqCst);
+            let mut checked = Instant::now();
+            while !stop.is_stopped() {
+                thread::sleep(POLL_INTERVAL);
+                let count = state.config_reloads.load(Ordering::SeqCst);
+                if count > reloads {
+                    reloads = count;
+                    emit(Event::info(
+                        "agent.config_reloaded",
+                        "Config reloaded".to_string(),
+                    ));
+                }
+                let count = state.config_reload_failures.load(Ordering::SeqCst);
+                if count > failures {
+                    failures = count;
+                    let error = state.last_config_error.lock().unwrap().clone();
+                    emit(Event {
+                        severity: Severity::WARN,
+                        ..Event::info(
+                            "agent.config_rejected",
+                            error.map_or("Config rejected".to_string(), |e| e.message),
+                        )
+                    });
+                }
+                if checked.elapsed() >= interval {
+                    checked = Instant::now();
+                    let current = HostState::current();
+                    let events = changes(&host, &current);
+                    if !events.is_empty() {
+                        events.into_iter().for_each(&emit);
+                        host = current;
+                        save(&host);
+                    }
+                }
+            }
+            if state.stopping.load(Ordering::SeqCst) {
+                emit(Event::info(
+                    "agent.stopped",
+                    format!("Agent {} stopping", host.version),
+                ));
+            }
+        });
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.stop.stop();
+    }
+}
+
+/// Addresses of the host's interfaces, without loopback and IPv6 link-local ones, sorted.
+#[cfg(unix)]
+fn host_addresses() -> Vec<String> {
+    let mut addresses = Vec::new();
+    let mut list: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: getifaddrs fills `list` with entries whose addresses match their family;
+    // the list is only read before freeifaddrs releases it.
+    unsafe {
+        if libc::getifaddrs(&mut list) != 0 {
+            return addresses;
+        }
+        let mut entry = list;
+        while !entry.is_null() {
+            let addr = (*entry).ifa_addr;
+            if !addr.is_null() {
+                let ip = match (*addr).sa_family as i32 {
+                    libc::AF_INET => {
+                        let addr = &*(addr as *const libc::sockaddr_in);
+                        Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                            addr.sin_addr.s_addr,
+                        ))))
+                    }
+                    libc::AF_INET6 => {
+                        let addr = &*(addr as *const libc::sockaddr_in6);
+                        Some(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)))
+                    }
+                    _ => None,
+                };
+                if let Some(ip) = ip {
+                    let link_local =
+                        matches!(ip, IpAddr::V6(v6) if v6.segments()[0] & 0xffc0 == 0xfe80);
+                    if !ip.is_loopback() && !link_local {
+                        addresses.push(ip.to_string());
+                    }
+                }
+            }
+            entry = (*entry).ifa_next;
+        }
+        libc::freeifaddrs(list);
+    }
+    addresses.sort();
+    addresses.dedup();
+    addresses
+}
+
+/// The address the host sends from by default, found by connecting a UDP socket, which
+/// sends nothing.
+#[cfg(windows)]
+fn host_addresses() -> Vec<String> {
+    let outbound = std::net::UdpSocket::bind("0.0.0.0:0")
+        .and_then(|socket| socket.connect("192.0.2.1:9").map(|()| socket))
+        .and_then(|socket| socket.local_addr());
+    outbound
+        .map(|addr| addr.ip())
+        .into_iter()
+        .filter(|ip| !ip.is_loopback() && !ip.is_unspecified())
+        .map(|ip| ip.to_string())
+        .collect()
+}
@@ -0,0 +1,269 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::stats::{AgentState, InputStats};
+
+/// How often a listener that is down checks whether it is time to bind again.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// First wait before binding again; it doubles with every failed attempt.
+const FIRST_REBIND_DELAY: Duration = Duration::from_secs(1);
+
+/// How the sockets of a network input (`syslog`, `statsd`) are watched and bound again,
+/// as the file inputs reopen their files.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ListenerHealth {
+    /// Bind again after the socket fails to receive or accept, waiting longer after
+    /// each attempt that fails.
+    #[serde(default = "default_rebind")]
+    pub rebind: bool,
+    /// Longest wait between two attempts.
+    #[serde(default = "default_rebind_max_delay_secs")]
+    pub rebind_max_delay_secs: u64,
+    /// Start even when the address cannot be bound yet, e.g. before its interface is
+    /// up, and keep trying in the background.
+    #[serde(default)]
+    pub wait_for_address: bool,
+    /// Without a message for this long the input is reported idle.
+    pub max_idle_secs: Option<u64>,
+    /// Bind an idle listener again, for senders that lose their way to a rebound address.
+    #[serde(default)]
+    pub rebind_when_idle: bool,
+}
+
+fn default_rebind() -> bool {
+    true
+}
+
+fn default_rebind_max_delay_secs() -> u64 {
+    60
+}
+
+impl Default for ListenerHealth {
+    fn default() -> ListenerHealth {
+        ListenerHealth {
+            rebind: default_rebind(),
+            rebind_max_delay_secs: default_rebind_max_delay_secs(),
+            wait_for_address: false,
+            max_idle_secs: None,
+            rebind_when_idle: false,
+        }
+    }
+}
+
+impl ListenerHealth {
+    pub fn validate(&self, input: &str) -> anyhow::Result<()> {
+        if self.rebind_max_delay_secs == 0 {
+            return Err(anyhow::anyhow!(
+                "listener_health {} rebind_max_delay_secs must be greater than 0",
+                input
+            ));
+        }
+        if self.max_idle_secs == Some(0) {
+            return Err(anyhow::anyhow!(
+                "listener_health {} max_idle_secs must be greater than 0",
+                input
+            ));
+        }
+        if self.rebind_when_idle && self.max_idle_secs.is_none() {
+            return Err(anyhow::anyhow!(
+                "listener_health {} has rebind_when_idle without max_idle_secs",
+                input
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Health of the sockets of a network input, shown in `status` and the metrics.
+#[derive(Default)]
+pub struct ListenerStats {
+    /// Sockets bound and receiving.
+    pub bound: AtomicU64,
+    /// Sockets waiting to be bound again.
+    pub down: AtomicU64,
+    pub rebinds: AtomicU64,
+    /// Open TCP connections.
+    pub connections: AtomicU64,
+    /// No message came within `max_idle_secs`.
+    pub idle: AtomicBool,
+}
+
+impl ListenerStats {
+    pub fn is_listener(&self) -> bool {
+        self.bound.load(Ordering::Relaxed) + self.down.load(Ordering::Relaxed) > 0
+    }
+}
+
+/// Watches one socket of an input: binds it, and binds it again after it failed.
+pub struct Watch {
+    /// Names the socket in errors, e.g. "Syslog listener udp://0.0.0.0:514".
+    what: String,
+    health: ListenerHealth,
+    input: Arc<InputStats>,
+    state: Arc<AgentState>,
+    delay: Duration,
+    /// When to bind again, while the socket is down.
+    retry_at: Option<Instant>,
+    bound_at: Instant,
+}
+
+impl Watch {
+    pub fn new(
+        what: String,
+        health: &ListenerHealth,
+        input: Arc<InputStats>,
+        state: Arc<AgentState>,
+    ) -> Watch {
+        Watch {
+            what,
+            health: health.clone(),
+            input,
+            state,
+            delay: FIRST_REBIND_DELAY,
+            retry_at: None,
+            bound_at: Instant::now(),
+        }
+    }
+
+    fn stats(&self) -> &ListenerStats {
+        &self.input.listener
+    }
+
+    /// Binds the socket the first time. A failure fails the input, unless
+    /// `wait_for_address` leaves it to `rebind`.
+    pub fn start<S>(
+        &mut self,
+        bind: impl FnOnce() -> std::io::Result<S>,
+    ) -> anyhow::Result<Option<S>> {
+        match bind() {
+            Ok(socket) => {
+                self.stats().bound.fetch_add(1, Ordering::Relaxed);
+                self.bound_at = Instant::now();
+                Ok(Some(socket))
+            }
+            Err(e) if self.health.wait_for_address => {
+                self.state
+                    .record_error(format!("Failed to bind {}, retrying: {}", self.what, e));
+                self.stats().down.fetch_add(1, Ordering::Relaxed);
+                self.retry_at = Some(Instant::now() + self.delay);
+                Ok(None)
+            }
+            Err(e) => Err(anyhow::anyhow!("Failed to bind {}: {}", self.what, e)),
+        }
+    }
+
+    /// For a socket that is down: binds it once its wait is over, else waits a little.
+    pub fn rebind<S>(&mut self, bind: impl FnOnce() -> std::io::Result<S>) -> Option<S> {
+        if self.retry_at.is_none_or(|at| Instant::now() < at) {
+            thread::sleep(POLL_INTERVAL);
+            return None;
+        }
+        match bind() {
+            Ok(socket) => {
+                println!("Bound {} again", self.what);
+                self.stats().down.fetch_sub(1, Ordering::Relaxed);
+                self.stats().bound.fetch_add(1, Ordering::Relaxed);
+                self.stats().rebinds.fetch_add(1, Ordering::Relaxed);
+                self.delay = FIRST_REBIND_DELAY;
+                self.retry_at = None;
+                self.bound_at = Instant::now();
+                Some(socket)
+            }
+            Err(e) => {
+                self.state
+                    .record_error(format!("Failed to bind {} again: {}", self.what, e));
+                self.delay =
+                    (self.delay * 2).min(Duration::from_secs(self.health.rebind_max_delay_secs));
+                self.retry_at = Some(Instant::now() + self.delay);
+                None
+            }
+        }
+    }
+
+    /// Reports an error of the socket; whether it is to be dropped and bound again.
+    pub fn failed(&mut self, error: impl std::fmt::Display) -> bool {
+        self.state.record_error(format!("{}: {}", self.what, error));
+        if self.health.rebind {
+            self.lost();
+        }
+        self.health.rebind
+    }
+
+    /// Whether the input went without a message for `max_idle_secs`, since the socket was
+    /// bound, and the socket is to be bound again for it.
+    pub fn idle(&mut self) -> bool {
+        let Some(max_idle) = self.health.max_idle_secs else {
+            return false;
+        };
+        let last = self.input.last_line_unix.load(Ordering::Relaxed);
+        let since_line = (Utc::now().timestamp() - last).max(0) as u64;
+        let idle = since_line >= max_idle && self.bound_at.elapsed().as_secs() >= max_idle;
+        if self.stats().idle.swap(idle, Ordering::Relaxed) != idle && idle {
+            println!("{} received nothing for {}s", self.what, max_idle);
+        }
+        if idle && self.health.rebind_when_idle {
+            self.lost();
+            // Bound again right away, and idle again only after another max_idle_secs.
+            self.retry_at = Some(Instant::now());
+            return true;
+        }
+        false
+    }
+
+    fn lost(&mut self) {
+        self.stats().bound.fetch_sub(1, Ordering::Relaxed);
+        self.stats().down.fetch_add(1, Ordering::Relaxed);
+        self.retry_at = Some(Instant::now() + self.delay);
+    }
+
+    /// The listener stopped, with its socket bound or not.
+    pub fn close(&mut self) {
+        let counter = match self.retry_at {
+            Some(_) => &self.stats().down,
+            None => &self.stats().bound,
+        };
+        counter.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Error, ErrorKind};
+
+    #[test]
+    fn failed_sockets_are_bound_again_after_a_growing_wait() {
+        let state = Arc::new(AgentState::default());
+        let input = state.register_input("syslog:edge");
+        let health = ListenerHealth {
+            rebind_max_delay_secs: 1,
+            ..ListenerHealth::default()
+        };
+        let mut watch = Watch::new("test listener".to_string(), &health, input.clone(), state);
+        watch.delay = Duration::from_millis(20);
+        let refused = || Err::<(), _>(Error::from(ErrorKind::AddrInUse));
+        assert!(watch.start(refused).is_err());
+
+        assert_eq!(watch.start(|| Ok(())).unwrap(), Some(()));
+        assert!(input.listener.is_listener());
+        assert!(watch.failed("connection reset"));
+        assert_eq!(input.listener.down.load(Ordering::Relaxed), 1);
+        // Not before the wait is over, which doubles after a failed attempt.
+        assert_eq!(watch.rebind(|| Ok(())), None);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(watch.rebind(refused), None);
+        assert_eq!(watch.delay, Duration::from_millis(40));
+        thread::sleep(Duration::from_millis(40));
+        assert_eq!(watch.rebind(|| Ok(())), Some(()));
+        assert_eq!(input.listener.bound.load(Ordering::Relaxed), 1);
+        assert_eq!(input.listener.down.load(Ordering::Relaxed), 0);
+        assert_eq!(input.listener.rebinds.load(Ordering::Relaxed), 1);
+
+        watch.close();
+        assert!(!input.listener.is_listener());
+    }
+}
@@ -0,0 +1,251 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::exporter::Exporter;
+use crate::parser::severity_of;
+use crate::{compress, parse_duration, Config, Record};
+
+/// The exporter name the archive is reported under.
+pub const NAME: &str = "local_archive";
+
+/// Default `local_archive.max_bytes`: 10 GiB.
+const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+
+/// `[local_archive]`: every record the exporters are sent is also written on the host, to
+/// a gzipped NDJSON file per hour, for reprocessing or compliance past the retention of
+/// SigNoz.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ArchiveConfig {
+    /// `<state_dir>/archive` by default.
+    pub dir: Option<String>,
+    /// How long the files of an hour are kept once it is over, e.g. "30d".
+    #[serde(default = "default_retention")]
+    pub retention: String,
+    /// Space the files may take up together; the oldest go first beyond it.
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64,
+}
+
+fn default_retention() -> String {
+    "7d".to_string()
+}
+
+fn default_max_bytes() -> u64 {
+    DEFAULT_MAX_BYTES
+}
+
+impl ArchiveConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        parse_duration(&self.retention)
+            .map_err(|e| anyhow::anyhow!("Invalid local_archive retention: {}", e))?;
+        Ok(())
+    }
+}
+
+/// Writes the records it is exported to the file of the current hour, one gzip member per
+/// batch, so that a file cut short by a crash loses at most its last batch.
+pub struct Archive {
+    dir: PathBuf,
+    retention: chrono::Duration,
+    max_bytes: u64,
+    config: Arc<Config>,
+    /// The hour files were last expired in.
+    expired: Option<String>,
+}
+
+impl Archive {
+    pub fn new(archive: &ArchiveConfig, config: Arc<Config>) -> anyhow::Result<Archive> {
+        let dir = archive.dir.as_ref().map_or_else(
+            || Path::new(&config.state_directory()).join("archive"),
+            PathBuf::from,
+        );
+        Ok(Archive {
+            dir,
+            retention: chrono::Duration::from_std(parse_duration(&archive.retention)?)?,
+            max_bytes: archive.max_bytes,
+            config,
+            expired: None,
+        })
+    }
+
+    /// `2024-03-05T14.ndjson.gz` for records written from 14:00 to 14:59 UTC.
+    fn file_name(hour: DateTime<Utc>) -> String {
+        format!("{}.ndjson.gz", hour.format("%Y-%m-%dT%H"))
+    }
+
+    fn write(&mut self, records: &[&Record], now: DateTime<Utc>) -> anyhow::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let mut lines = Vec::new();
+        for entry in records {
+            serde_json::to_writer(&mut lines, &self.record(entry))?;
+            lines.push(b'\n');
+        }
+        let name = Archive::file_name(now);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(&name))?;
+        file.write_all(&compress::gzip(&lines, compress::DEFAULT_LEVEL)?)?;
+        if self.expired.as_ref() != Some(&name) {
+            self.expire(now)?;
+            self.expired = Some(name);
+        }
+        Ok(())
+    }
+
+    /// A record as a JSON object the `"json"` log format reads back: its message,
+    /// severity and event time, with its attributes beside them.
+    fn record(&self, entry: &Record) -> Map<String, Value> {
+        let mut record = Map::new();
+        for (key, value) in &entry.attributes {
+            record.insert(key.clone(), value.clone().into());
+        }
+        let service_name = entry
+            .service_name
+            .as_deref()
+            .or(self.config.service_name.as_deref());
+        if let Some(service_name) = service_name {
+            record.insert("service.name".into(), service_name.into());
+        }
+        if let Some(ref trace) = entry.trace {
+            record.insert("trace_id".into(), trace.trace_id.clone().into());
+            record.insert("span_id".into(), trace.span_id.clone().into());
+        }
+        if let Some(ref resource) = entry.resource {
+            let resource: Map<String, Value> = resource
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone().into()))
+                .collect();
+            record.insert("resource".into(), resource.into());
+        }
+        record.insert(
+            "timestamp".into(),
+            entry
+                .timestamp
+                .unwrap_or(entry.observed)
+                .to_rfc3339()
+                .into(),
+        );
+        record.insert("observed".into(), entry.observed.to_rfc3339().into());
+        record.insert(
+            "severity".into(),
+            severity_of(entry, &self.config).text.into(),
+        );
+        record.insert("message".into(), entry.body.text().into());
+        record
+    }
+
+    /// Removes the files of hours over longer than `retention` ago, then the oldest ones
+    /// while all of them take up more than `max_bytes`. The file of `now` is kept.
+    fn expire(&self, now: DateTime<Utc>) -> anyhow::Result<()> {
+        let current = Archive::file_name(now);
+        let oldest = Archive::file_name(now - self.retention - chrono::Duration::hours(1));
+        let mut files: Vec<(String, u64)> = fs::read_dir(&self.dir)?
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let size = entry.metadata().ok()?.len();
+                name.ends_with(".ndjson.gz").then_some((name, size))
+            })
+            .collect();
+        files.sort();
+        let mut total: u64 = files.iter().map(|(_, size)| size).sum();
+        for (name, size) in files {
+            if name == current || (name > oldest && total <= self.max_bytes) {
+                continue;
+            }
+            fs::remove_file(self.dir.join(&name))?;
+            total -= size;
+        }
+        Ok(())
+    }
+}
+
+impl Exporter for Archive {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn export(&mut self, records: &[&Record]) -> Result<(), String> {
+        self.write(records, Utc::now())
+            .map_err(|e| format!("{}: {:#}", self.dir.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use crate::Attributes;
+    use flate2::read::MultiGzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn records_are_written_by_hour_and_expired() {
+        let dir = TempDir::new("local-archive");
+        let config: Config = toml::from_str(&format!(
+            "endpoint = \"http://localhost:4318\"\n[local_archive]\ndir = {:?}\nretention = \"2h\"",
+            dir.display().to_string()
+        ))
+        .unwrap();
+        let archive_config = config.local_archive.clone().unwrap();
+        let mut archive = Archive::new(&archive_config, Arc::new(config)).unwrap();
+        let entry = |line: &str| Record {
+            attributes: Attributes::from(vec![(
+                "log.file".to_string(),
+                "/var/log/app.log".to_string(),
+            )]),
+            service_name: Some("api".into()),
+            ..Record::new(line)
+        };
+        let hour = |h: u32| {
+            DateTime::parse_from_rfc3339(&format!("2024-03-05T{:02}:30:00Z", h))
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+
+        for h in [10, 11, 11, 12] {
+            let (a, b) = (entry("ERROR disk full"), entry(&format!("at {}", h)));
+            archive.write(&[&a, &b], hour(h)).unwrap();
+        }
+        let mut text = String::new();
+        MultiGzDecoder::new(fs::File::open(dir.join("2024-03-05T11.ndjson.gz")).unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        let lines: Vec<Value> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["message"], "ERROR disk full");
+        assert_eq!(lines[0]["severity"], "ERROR");
+        assert_eq!(lines[0]["service.name"], "api");
+        assert_eq!(lines[0]["log.file"], "/var/log/app.log");
+
+        // Two hours after 10:00-10:59 was over, its file goes.
+        archive.write(&[&entry("late")], hour(13)).unwrap();
+        let mut names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "2024-03-05T11.ndjson.gz",
+                "2024-03-05T12.ndjson.gz",
+                "2024-03-05T13.ndjson.gz"
+            ]
+        );
+
+        // Over max_bytes, the oldest go, but not the file being written.
+        archive.max_bytes = 1;
+        archive.write(&[&entry("later")], hour(14)).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,198 @@
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::fieldpath::{self, FieldPath};
+use crate::layout::Layout;
+use crate::rules::RegexLimits;
+use crate::{severity_from_name, Severity};
+
+/// How the lines of an input are read.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// The line is the body, its severity detected from the text.
+    #[default]
+    Plain,
+    /// Lines that are JSON objects are split into body, severity, timestamp and
+    /// attributes; other lines are read as plain.
+    Json,
+    /// JSON lines whose message, level or time are elsewhere than in the fields `json`
+    /// tries, e.g. `{ json_fields = { time = "meta.ts", level = "log[0].level" } }`.
+    JsonFields(JsonFields),
+    /// Lines written by a Logback or Log4j pattern layout, e.g.
+    /// `{ layout = "%d{ISO8601} [%thread] %-5level %logger - %msg%n" }`, are split
+    /// the same way; other lines are read as plain.
+    #[serde(alias = "logback", alias = "log4j")]
+    Layout(String),
+}
+
+/// Field paths of a JSON line, each tried before the fields `json` looks for.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct JsonFields {
+    pub message: Option<String>,
+    pub level: Option<String>,
+    pub time: Option<String>,
+}
+
+/// The field paths of `JsonFields`, parsed.
+#[derive(Default)]
+pub struct JsonPaths {
+    message: Option<FieldPath>,
+    level: Option<FieldPath>,
+    time: Option<FieldPath>,
+}
+
+/// The parser of a `LogFormat`.
+pub enum Parser {
+    Plain,
+    Json(JsonPaths),
+    Layout(Layout),
+}
+
+impl Parser {
+    pub fn new(format: &LogFormat, limits: &RegexLimits) -> anyhow::Result<Parser> {
+        let path = |path: &Option<String>| path.as_deref().map(FieldPath::parse).transpose();
+        Ok(match format {
+            LogFormat::Plain => Parser::Plain,
+            LogFormat::Json => Parser::Json(JsonPaths::default()),
+            LogFormat::JsonFields(fields) => Parser::Json(JsonPaths {
+                message: path(&fields.message)?,
+                level: path(&fields.level)?,
+                time: path(&fields.time)?,
+            }),
+            LogFormat::Layout(layout) => Parser::Layout(Layout::new(layout, limits)?),
+        })
+    }
+
+    /// `line` taken apart, `None` for lines read as plain.
+    pub fn parse(&self, line: &str) -> Option<ParsedLine> {
+        match self {
+            Parser::Plain => None,
+            Parser::Json(paths) => parse_json(line, paths),
+            Parser::Layout(layout) => layout.parse(line),
+        }
+    }
+}
+
+/// Fields holding the message, the level and the event time, tried in order.
+const MESSAGE_FIELDS: &[&str] = &["msg", "message"];
+const LEVEL_FIELDS: &[&str] = &["level", "severity", "lvl"];
+const TIME_FIELDS: &[&str] = &["timestamp", "time", "ts", "@timestamp"];
+
+/// A structured line taken apart.
+pub struct ParsedLine {
+    /// The message field, or the whole line when there is none.
+    pub body: String,
+    pub severity: Option<Severity>,
+    pub timestamp: Option<DateTime<Utc>>,
+    /// The remaining fields; for JSON, nested objects flattened into dotted keys.
+    pub attributes: Vec<(String, String)>,
+}
+
+/// `line` taken apart, if it is a JSON object.
+fn parse_json(line: &str, paths: &JsonPaths) -> Option<ParsedLine> {
+    if !line.trim_start().starts_with('{') {
+        return None;
+    }
+    let Ok(Value::Object(mut fields)) = serde_json::from_str::<Value>(line) else {
+        return None;
+    };
+    let body = take_first(
+        &mut fields,
+        paths.message.as_ref(),
+        MESSAGE_FIELDS,
+        |v| match v {
+            Value::String(s) => Some(s.clone()),
+            _ => None,
+        },
+    );
+    let severity = take_first(
+        &mut fields,
+        paths.level.as_ref(),
+        LEVEL_FIELDS,
+        |v| match v {
+            Value::String(s) => severity_from_name(s),
+            Value::Number(n) => n.as_u64().and_then(numeric_level),
+            _ => None,
+        },
+    );
+    let timestamp = take_first(&mut fields, paths.time.as_ref(), TIME_FIELDS, |v| match v {
+        Value::String(s) => DateTime::parse_from_rfc3339(s)
+            .ok()
+            .map(|t| t.with_timezone(&Utc)),
+        Value::Number(n) => epoch(n),
+        _ => None,
+    });
+    let mut attributes = Vec::with_capacity(fields.len());
+    flatten("", fields, &mut attributes);
+    Some(ParsedLine {
+        body: body.unwrap_or_else(|| line.to_string()),
+        severity,
+        timestamp,
+        attributes,
+    })
+}
+
+/// The value of `path`, else of the first of `keys`, that `read` accepts, removed from
+/// `fields`. Fields it does not accept stay, as attributes.
+fn take_first<T>(
+    fields: &mut Map<String, Value>,
+    path: Option<&FieldPath>,
+    keys: &[&str],
+    read: impl Fn(&Value) -> Option<T>,
+) -> Option<T> {
+    if let Some(value) = path.and_then(|p| p.get(fields)).and_then(&read) {
+        path?.remove(fields);
+        return Some(value);
+    }
+    let (key, value) = keys
+        .iter()
+        .find_map(|k| fields.get(*k).and_then(&read).map(|v| (*k, v)))?;
+    fields.remove(key);
+    Some(value)
+}
+
+/// Levels of pino and bunyan, which write them as numbers.
+fn numeric_level(level: u64) -> Option<Severity> {
+    match level {
+        10 => Some(Severity::TRACE),
+        20 => Some(Severity::DEBUG),
+        30 => Some(Severity::INFO),
+        40 => Some(Severity::WARN),
+        50 => Some(Severity::ERROR),
+        60 => Some(Severity::FATAL),
+        _ => None,
+    }
+}
+
+/// An epoch time in seconds, milliseconds, microseconds or nanoseconds, told apart by
+/// its magnitude. Whole numbers are scaled exactly.
+fn epoch(value: &serde_json::Number) -> Option<DateTime<Utc>> {
+    let magnitude = value.as_f64().filter(|v| *v > 0.0)?;
+    let scale: i64 = match magnitude {
+        v if v < 1e11 => 1_000_000_000,
+        v if v < 1e14 => 1_000_000,
+        v if v < 1e17 => 1_000,
+        _ => 1,
+    };
+    let nanos = match value.as_i64() {
+        Some(whole) => whole.checked_mul(scale)?,
+        None => (magnitude * scale as f64) as i64,
+    };
+    Some(Utc.timestamp_nanos(nanos))
+}
+
+fn flatten(prefix: &str, fields: Map<String, Value>, out: &mut Vec<(String, String)>) {
+    for (key, value) in fields {
+        let key = if prefix.is_empty() {
+            key
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match value {
+            Value::Object(nested) => flatten(&key, nested, out),
+            other => out.extend(fieldpath::text(&other).map(|text| (key, text))),
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use std::path::Path;
+
+use crate::input::{file_paths, is_pattern, pattern_root};
+use crate::statedir::{SYSTEM_CONFIG_DIR as CONFIG_DIR, SYSTEM_STATE_DIR as STATE_DIR};
+use crate::{activation, systemd_unit, Config};
+
+const APP: &str = "rust-signoz-agent";
+const BINARY_PATH: &str = "/usr/local/bin/rust-signoz-agent";
+pub const DEFAULT_IMAGE: &str = "rust-signoz-agent:latest";
+
+const CPU_REQUEST: &str = "100m";
+const CPU_LIMIT: &str = "500m";
+const MEMORY_REQUEST: &str = "64Mi";
+const MEMORY_LIMIT: &str = "256Mi";
+
+pub struct Options {
+    pub image: String,
+    pub namespace: String,
+}
+
+/// A deployment artifact for `target` ("docker", "daemonset" or "ansible") embedding
+/// `config_text`, the contents of config.toml.
+pub fn generate(
+    target: &str,
+    config: &Config,
+    config_text: &str,
+    options: &Options,
+) -> anyhow::Result<String> {
+    let config_text = deployed_config(config_text)?;
+    let log_dirs = log_dirs(config);
+    for host in ["localhost", "127.0.0.1", "[::1]"] {
+        if config.endpoint.contains(&format!("://{}", host)) {
+            eprintln!(
+                "Warning: endpoint {} points at the local host, which is not the collector once deployed",
+                config.endpoint
+            );
+        }
+    }
+    match target {
+        "docker" => Ok(docker(&config_text, &log_dirs, options)),
+        "daemonset" => Ok(daemonset(&config_text, &log_dirs, options)),
+        "ansible" => Ok(ansible(config, &config_text)),
+        other => Err(anyhow::anyhow!(
+            "Unknown manifest type {:?}; use docker, daemonset or ansible",
+            other
+        )),
+    }
+}
+
+/// The config as deployed: unchanged, except that the state directory and admin socket
+/// move into `STATE_DIR` when not set explicitly, on the host directory mounted there so
+/// `top` and `flush` work from the host, and that the containers' read-only root
+/// filesystem is checked for.
+pub fn deployed_config(config_text: &str) -> anyhow::Result<String> {
+    let mut table: toml::Table = toml::from_str(config_text)?;
+    if ["admin_socket", "state_dir", "read_only_root"]
+        .iter()
+        .all(|key| table.contains_key(*key))
+    {
+        return Ok(config_text.to_string());
+    }
+    table
+        .entry("read_only_root")
+        .or_insert(toml::Value::Boolean(true));
+    table
+        .entry("admin_socket")
+        .or_insert_with(|| toml::Value::String(format!("{}/agent.sock", STATE_DIR)));
+    table
+        .entry("state_dir")
+        .or_insert_with(|| toml::Value::String(STATE_DIR.to_string()));
+    Ok(toml::to_string_pretty(&table)?)
+}
+
+/// Directories holding file inputs, mounted read-only into containers.
+fn log_dirs(config: &Config) -> Vec<String> {
+    let mut dirs: Vec<String> = file_paths(config)
+        .iter()
+        .filter_map(|p| match is_pattern(p) {
+            true => Some(pattern_root(p)),
+            false => Path::new(p).parent().map(|d| d.display().to_string()),
+        })
+        .filter(|d| !d.is_empty())
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    dirs
+}
+
+fn indent(text: &str, spaces: usize) -> String {
+    let pad = " ".repeat(spaces);
+    text.lines()
+        .map(|l| {
+            if l.is_empty() {
+                String::new()
+            } else {
+                format!("{}{}", pad, l)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A YAML-safe quoted string (JSON strings are valid YAML).
+fn quote(s: &str) -> String {
+    serde_json::to_string(s).unwrap_or_default()
+}
+
+fn docker(config_text: &str, log_dirs: &[String], options: &Options) -> String {
+    let mut volumes = String::new();
+    for dir in log_dirs {
+        volumes.push_str(&format!("      - {}\n", quote(&format!("{0}:{0}:ro", dir))));
+    }
+    volumes.push_str(&format!(
+        "      - {}\n",
+        quote(&format!("{0}:{0}", STATE_DIR))
+    ));
+    format!(
+        r#"services:
+  {app}:
+    image: {image}
+    restart: unless-stopped
+    read_only: true
+    working_dir: {config_dir}
+    configs:
+      - source: {app}-config
+        target: {config_dir}/config.toml
+    volumes:
+{volumes}    deploy:
+      resources:
+        limits:
+          cpus: "0.5"
+          memory: 256M
+        reservations:
+          cpus: "0.1"
+          memory: 64M
+
+configs:
+  {app}-config:
+    content: |
+{config}
+"#,
+        app = APP,
+        image = quote(&options.image),
+        config_dir = CONFIG_DIR,
+        volumes = volumes,
+        config = indent(config_text, 6),
+    )
+}
+
+fn daemonset(config_text: &str, log_dirs: &[String], options: &Options) -> String {
+    let (mut mounts, mut volumes) = (String::new(), String::new());
+    for (i, dir) in log_dirs.iter().enumerate() {
+        mounts.push_str(&format!(
+            "            - name: logs-{i}\n              mountPath: {dir}\n              readOnly: true\n",
+            i = i,
+            dir = quote(dir)
+        ));
+        volumes.push_str(&format!(
+            "        - name: logs-{i}\n          hostPath:\n            path: {dir}\n",
+            i = i,
+            dir = quote(dir)
+        ));
+    }
+    format!(
+        r#"apiVersion: v1
+kind: ConfigMap
+metadata:
+  name: {app}
+  namespace: {namespace}
+data:
+  config.toml: |
+{config}
+---
+apiVersion: apps/v1
+kind: DaemonSet
+metadata:
+  name: {app}
+  namespace: {namespace}
+spec:
+  selector:
+    matchLabels:
+      app: {app}
+  template:
+    metadata:
+      labels:
+        app: {app}
+    spec:
+      containers:
+        - name: agent
+          image: {image}
+          workingDir: {config_dir}
+          securityContext:
+            readOnlyRootFilesystem: true
+          resources:
+            requests:
+              cpu: {cpu_request}
+              memory: {memory_request}
+            limits:
+              cpu: {cpu_limit}
+              memory: {memory_limit}
+          volumeMounts:
+            - name: config
+              mountPath: {config_dir}
+              readOnly: true
+            - name: state
+              mountPath: {state_dir}
+{mounts}      volumes:
+        - name: config
+          configMap:
+            name: {app}
+        - name: state
+          hostPath:
+            path: {state_dir}
+            type: DirectoryOrCreate
+{volumes}"#,
+        app = APP,
+        namespace = quote(&options.namespace),
+        config = indent(config_text, 4),
+        image = quote(&options.image),
+        config_dir = CONFIG_DIR,
+        state_dir = STATE_DIR,
+        cpu_request = CPU_REQUEST,
+        cpu_limit = CPU_LIMIT,
+        memory_request = MEMORY_REQUEST,
+        memory_limit = MEMORY_LIMIT,
+        mounts = mounts,
+        volumes = volumes,
+    )
+}
+
+fn ansible(config: &Config, config_text: &str) -> String {
+    let user_line = config
+        .service_user
+        .as_deref()
+        .map(|u| format!("User={}\n", u))
+        .unwrap_or_default();
+    let unit = systemd_unit(
+        &user_line,
+        CONFIG_DIR,
+        BINARY_PATH,
+        &config.service_unit,
+        &format!(
+            "MemoryMax=256M\nCPUQuota=50%\nProtectSystem=strict\nReadWritePaths={}\n",
+            STATE_DIR
+        ),
+    );
+    // The socket is enabled before the service that needs it.
+    let socket = match activation::socket_unit(config) {
+        Some(socket) if config.service_unit.socket_activation => format!(
+            r#"
+    - name: Install the socket unit
+      ansible.builtin.copy:
+        dest: /etc/systemd/system/{name}
+        mode: "0644"
+        content: |
+{socket}
+    - name: Enable and start {name}
+      ansible.builtin.systemd:
+        name: {name}
+        enabled: true
+        state: started
+        daemon_reload: true
+"#,
+            name = activation::SOCKET_UNIT,
+            socket = indent(&socket, 10),
+        ),
+        _ => String::new(),
+    };
+    format!(
+        r#"- name: Deploy {app}
+  hosts: all
+  become: true
+  tasks:
+    - name: Install the agent binary
+      ansible.builtin.copy:
+        src: files/{app}
+        dest: {binary}
+        mode: "0755"
+      notify: Restart {app}
+
+    - name: Create the config and state directories
+      ansible.builtin.file:
+        path: "{{{{ item }}}}"
+        state: directory
+        mode: "0755"
+{owner}      loop:
+        - {config_dir}
+        - {state_dir}
+
+    - name: Write config.toml
+      ansible.builtin.copy:
+        dest: {config_dir}/config.toml
+        mode: "0640"
+        content: !unsafe |
+{config}
+      notify: Restart {app}
+
+    - name: Install the systemd unit
+      ansible.builtin.copy:
+        dest: /etc/systemd/system/{app}.service
+        mode: "0644"
+        content: |
+{unit}
+      notify: Restart {app}
+{socket}
+    - name: Enable and start {app}
+      ansible.builtin.systemd:
+        name: {app}
+        enabled: true
+        state: started
+        daemon_reload: true
+
+  handlers:
+    - name: Restart {app}
+      ansible.builtin.systemd:
+        name: {app}
+        state: restarted
+        daemon_reload: true
+"#,
+        app = APP,
+        owner = config
+            .service_user
+            .as_deref()
+            .map(|u| format!("        owner: {}\n", quote(u)))
+            .unwrap_or_default(),
+        binary = BINARY_PATH,
+        config_dir = CONFIG_DIR,
+        state_dir = STATE_DIR,
+        config = indent(config_text, 10),
+        unit = indent(&unit, 10),
+        socket = socket,
+    )
+}
@@ -0,0 +1,142 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{Body, Record, Severity};
+
+/// What makes up a line's pattern rather than its own values: anything with a digit in
+/// it, hex ids, email addresses and quoted strings are masked.
+const VARIABLE: &str = r#""[^"]*"|'[^']*'|\S+@\S+|\b[0-9a-fA-F]{8,}\b|\S*\d\S*"#;
+
+/// A `[metadata_only]` entry: the records of the input are shipped without their
+/// content, for inputs whose lines must not leave the host.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct MetadataOnly {
+    /// Attributes shipped as they are; all others are left out, but `log.file`.
+    #[serde(default)]
+    pub keep_attributes: Vec<String>,
+    /// Ships the line's pattern in `log.record.template`. Words of the line are left in
+    /// it, so it is off unless those are known not to be sensitive.
+    #[serde(default)]
+    pub template: bool,
+    /// Mixed into `log.record.hash`, so that short lines cannot be found by hashing
+    /// guesses without it.
+    pub hash_key: Option<String>,
+}
+
+/// Turns records into their metadata.
+pub struct Stripper {
+    variable: Regex,
+}
+
+impl Default for Stripper {
+    fn default() -> Stripper {
+        Stripper {
+            variable: crate::rules::builtin(VARIABLE).unwrap(),
+        }
+    }
+}
+
+impl Stripper {
+    /// The line with its values masked as `<*>`, e.g. `user <*> logged in from <*>`.
+    pub fn template(&self, line: &str) -> String {
+        self.variable.replace_all(line, "<*>").into_owned()
+    }
+
+    /// `entry` without its line and attributes: its time, severity and resource stay,
+    /// and its byte length, the id of its pattern and a hash of the line are added.
+    pub fn strip(&self, mut entry: Record, config: &MetadataOnly, severity: Severity) -> Record {
+        let length = entry.body.len();
+        let template = self.template(&entry.body.text());
+        let pattern_id = hex(&Sha256::digest(template.as_bytes())[..8]);
+        let mut hash = Sha256::new();
+        if let Some(ref key) = config.hash_key {
+            hash.update(key.as_bytes());
+            hash.update([0]);
+        }
+        hash.update(entry.body.as_bytes());
+
+        entry.attributes.retain(|(key, _)| {
+            key == "log.file" || config.keep_attributes.iter().any(|k| k == key)
+        });
+        entry.attributes.extend([
+            ("log.record.length".to_string(), length.to_string()),
+            ("log.record.pattern_id".to_string(), pattern_id.clone()),
+            ("log.record.hash".to_string(), hex(&hash.finalize()[..16])),
+        ]);
+        if config.template {
+            entry.attributes.push("log.record.template", template);
+        }
+        entry.body = Body::Text(format!("<{} bytes, pattern {}>", length, pattern_id));
+        entry.severity = Some(severity);
+        entry
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Attributes;
+
+    #[test]
+    fn records_keep_only_their_metadata() {
+        let stripper = Stripper::default();
+        let entry = |line: &str| Record {
+            attributes: Attributes::from(vec![
+                ("log.file".to_string(), "/var/log/pii.log".to_string()),
+                ("user.email".to_string(), "ann@example.com".to_string()),
+                ("region".to_string(), "eu".to_string()),
+            ]),
+            ..Record::new(line)
+        };
+        let config = MetadataOnly {
+            keep_attributes: vec!["region".to_string()],
+            template: true,
+            hash_key: None,
+        };
+        let line = "ERROR user ann@example.com paid 42.50 for \"order 7\" on 4bf92f3577b34da6";
+        let stripped = stripper.strip(entry(line), &config, Severity::ERROR);
+        let attribute = |key: &str| {
+            stripped
+                .attributes
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.clone())
+        };
+        assert_eq!(
+            attribute("log.record.template").as_deref(),
+            Some("ERROR user <*> paid <*> for <*> on <*>")
+        );
+        assert_eq!(attribute("log.record.length"), Some(line.len().to_string()));
+        assert_eq!(attribute("region").as_deref(), Some("eu"));
+        assert_eq!(attribute("user.email"), None);
+        assert_eq!(stripped.severity, Some(Severity::ERROR));
+        let pattern_id = attribute("log.record.pattern_id").unwrap();
+        assert_eq!(
+            stripped.body.text(),
+            format!("<{} bytes, pattern {}>", line.len(), pattern_id)
+        );
+
+        // Lines of the same pattern share its id, but not their hash.
+        let other = stripper.strip(
+            entry("ERROR user bob@example.com paid 9 for \"order 8\" on 0af7651916cd43dd"),
+            &config,
+            Severity::ERROR,
+        );
+        assert!(other
+            .attributes
+            .contains(&("log.record.pattern_id".to_string(), pattern_id)));
+        assert_ne!(
+            attribute("log.record.hash"),
+            other
+                .attributes
+                .iter()
+                .find(|(k, _)| k == "log.record.hash")
+                .map(|(_, v)| v.clone())
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::clock::Clock;
+use crate::exporter::{http_client, send_with_retry};
+use crate::{base_resource_attributes, Config, KeyValue, Resource};
+
+/// OTLP/JSON metrics request, the `/v1/metrics` counterpart of `OtlpLogRecord`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OtlpMetrics {
+    resource_metrics: Vec<ResourceMetrics>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ResourceMetrics {
+    resource: Resource,
+    scope_metrics: Vec<ScopeMetrics>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ScopeMetrics {
+    scope: Scope,
+    metrics: Vec<Metric>,
+}
+
+#[derive(Serialize, Debug)]
+struct Scope {
+    name: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Metric {
+    pub name: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub unit: String,
+    #[serde(flatten)]
+    pub data: MetricData,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum MetricData {
+    Sum(Sum),
+    Gauge(Gauge),
+    Summary(Summary),
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Sum {
+    pub aggregation_temporality: u8,
+    pub is_monotonic: bool,
+    pub data_points: Vec<NumberDataPoint>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Gauge {
+    pub data_points: Vec<NumberDataPoint>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Summary {
+    pub data_points: Vec<SummaryDataPoint>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NumberDataPoint {
+    pub attributes: Vec<KeyValue>,
+    pub start_time_unix_nano: String,
+    pub time_unix_nano: String,
+    pub as_double: f64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SummaryDataPoint {
+    pub attributes: Vec<KeyValue>,
+    pub start_time_unix_nano: String,
+    pub time_unix_nano: String,
+    /// uint64 fields are strings in OTLP/JSON.
+    pub count: String,
+    pub sum: f64,
+    pub quantile_values: Vec<ValueAtQuantile>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ValueAtQuantile {
+    pub quantile: f64,
+    pub value: f64,
+}
+
+/// OTLP `AGGREGATION_TEMPORALITY_DELTA`.
+pub const DELTA: u8 = 1;
+/// OTLP `AGGREGATION_TEMPORALITY_CUMULATIVE`.
+pub const CUMULATIVE: u8 = 2;
+
+pub fn unix_nanos(time: DateTime<Utc>) -> String {
+    time.timestamp_nanos_opt().unwrap_or(0).to_string()
+}
+
+pub fn build_metrics_payload(config: &Config, scope: &str, metrics: Vec<Metric>) -> OtlpMetrics {
+    let service_name = config
+        .service_name
+        .as_deref()
+        .unwrap_or("rust-signoz-agent");
+    OtlpMetrics {
+        resource_metrics: vec![ResourceMetrics {
+            resource: Resource {
+                attributes: base_resource_attributes(config, service_name),
+            },
+            scope_metrics: vec![ScopeMetrics {
+                scope: Scope {
+                    name: scope.to_string(),
+                },
+                metrics,
+            }],
+        }],
+    }
+}
+
+/// `metrics_endpoint` if configured, otherwise the logs endpoint with its `/v1/logs`
+/// suffix swapped for `/v1/metrics`.
+pub fn metrics_endpoint(config: &Config) -> String {
+    if let Some(ref endpoint) = config.metrics_endpoint {
+        return endpoint.clone();
+    }
+    match config.endpoint.strip_suffix("/v1/logs") {
+        Some(base) => format!("{}/v1/metrics", base),
+        None => format!("{}/v1/metrics", config.endpoint.trim_end_matches('/')),
+    }
+}
+
+pub struct MetricsExporter {
+    client: reqwest::blocking::Client,
+    endpoint: String,
+}
+
+impl MetricsExporter {
+    pub fn new(config: &Config) -> MetricsExporter {
+        MetricsExporter {
+            client: http_client(config, &config.credentials(), Duration::from_secs(10))
+                .unwrap_or_else(|_| reqwest::blocking::Client::new()),
+            endpoint: metrics_endpoint(config),
+        }
+    }
+
+    pub fn export(&self, payload: &OtlpMetrics) -> Result<(), String> {
+        match self.client.post(&self.endpoint).json(payload).send() {
+            Ok(r) if r.status().is_success() => Ok(()),
+            Ok(r) => Err(format!(
+                "Failed to send metrics to {}: HTTP {}",
+                self.endpoint,
+                r.status()
+            )),
+            Err(e) => Err(format!(
+                "HTTP error sending metrics to {}: {}",
+                self.endpoint, e
+            )),
+        }
+    }
+
+    /// `export` with the backoff and attempt limit of the log exporters.
+    pub fn export_with_retry(
+        &self,
+        clock: &dyn Clock,
+        payload: &OtlpMetrics,
+    ) -> Result<(), String> {
+        send_with_retry(clock, |_| self.export(payload))
+    }
+}
@@ -0,0 +1,193 @@
+use anyhow::{Context, Result};
+use std::fs;
+use toml::{Table, Value};
+
+use crate::failure::{self, Failure};
+use crate::Config;
+
+/// Array sections that are `[[inputs]]` entries of one type, under their own name.
+const INPUT_SECTIONS: &[(&str, &str)] = &[
+    ("snapshots", "snapshot"),
+    ("backfills", "backfill"),
+    ("commands", "command"),
+];
+
+/// `config migrate [--dry-run]`: rewrites the config at `config_path` from the flat
+/// layout, with a section or list per input kind, to `[[inputs]]` entries. The previous
+/// file is kept next to it with a `.bak` suffix; `--dry-run` prints the result instead.
+pub fn run(config_path: &str, dry_run: bool) -> Result<()> {
+    let old: Table = toml::from_str(
+        &fs::read_to_string(config_path)
+            .with_context(|| format!("config migrate needs {}", config_path))?,
+    )
+    .map_err(|e| failure::classify(e.into(), Failure::ConfigInvalid))?;
+    let (new, changes) = migrate(old);
+    if changes.is_empty() {
+        println!("{} already uses the current layout", config_path);
+        return Ok(());
+    }
+    let rendered = render(new, &changes)?;
+    toml::from_str::<Config>(&rendered)
+        .context("The migrated config does not load; the original was left unchanged")?;
+    if dry_run {
+        print!("{}", rendered);
+        return Ok(());
+    }
+    let backup = format!("{}.bak", config_path);
+    fs::copy(config_path, &backup).with_context(|| format!("Failed to write {}", backup))?;
+    fs::write(config_path, rendered).with_context(|| format!("Failed to write {}", config_path))?;
+    for change in &changes {
+        println!("{}", change);
+    }
+    println!(
+        "Migrated {}; the previous version is {}",
+        config_path, backup
+    );
+    Ok(())
+}
+
+/// Moves every input into `inputs`, after the `[[inputs]]` entries already there.
+/// Returns the new config and a line per change made.
+fn migrate(mut config: Table) -> (Table, Vec<String>) {
+    let mut inputs = match config.remove("inputs") {
+        Some(Value::Array(inputs)) => inputs,
+        Some(other) => vec![other],
+        None => Vec::new(),
+    };
+    let mut changes = Vec::new();
+
+    if let Some(Value::Array(files)) = config.remove("log_files") {
+        let mut mmap = match config.remove("mmap_files") {
+            Some(Value::Array(mmap)) => mmap,
+            _ => Vec::new(),
+        };
+        for path in &files {
+            let mut input = Table::new();
+            input.insert("type".into(), "file".into());
+            input.insert("path".into(), path.clone());
+            if let Some(i) = mmap.iter().position(|m| m == path) {
+                mmap.remove(i);
+                input.insert("mmap".into(), true.into());
+            }
+            inputs.push(Value::Table(input));
+        }
+        if !mmap.is_empty() {
+            config.insert("mmap_files".into(), Value::Array(mmap));
+        }
+        changes.push(format!(
+            "log_files and mmap_files: {} file input(s) moved to [[inputs]]",
+            files.len()
+        ));
+    }
+
+    for &(section, kind) in INPUT_SECTIONS {
+        let Some(Value::Array(entries)) = config.remove(section) else {
+            continue;
+        };
+        changes.push(format!(
+            "[[{}]]: {} entry(ies) moved to [[inputs]] with type = \"{}\"",
+            section,
+            entries.len(),
+            kind
+        ));
+        inputs.extend(entries.into_iter().map(|entry| with_type(entry, kind)));
+    }
+
+    if let Some(statsd) = config.remove("statsd") {
+        changes.push("[statsd]: moved to [[inputs]] with type = \"statsd\"".to_string());
+        inputs.push(with_type(statsd, "statsd"));
+    }
+
+    if !inputs.is_empty() {
+        config.insert("inputs".into(), Value::Array(inputs));
+    }
+    (config, changes)
+}
+
+fn with_type(entry: Value, kind: &str) -> Value {
+    let mut input = Table::new();
+    input.insert("type".into(), kind.into());
+    if let Value::Table(options) = entry {
+        input.extend(options);
+    }
+    Value::Table(input)
+}
+
+/// The migrated config, with the inputs last and comments saying what changed.
+fn render(mut config: Table, changes: &[String]) -> Result<String> {
+    let inputs = config.remove("inputs");
+    let mut out = String::from(
+        "# Migrated by `rust-signoz-agent config migrate`. Comments of the previous version\n\
+         # (kept with a .bak suffix) are not carried over.\n#\n",
+    );
+    for change in changes {
+        out.push_str(&format!("# - {}\n", change));
+    }
+    out.push('\n');
+    out.push_str(&toml::to_string(&config)?);
+    if let Some(inputs) = inputs {
+        out.push_str(
+            "\n# Every input, the kind selected by `type`; the other keys are the ones of the\n\
+             # former dedicated section (see \"Inputs\" in the README).\n",
+        );
+        out.push_str(&toml::to_string(&Table::from_iter([(
+            "inputs".to_string(),
+            inputs,
+        )]))?);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_input_sections_to_inputs() {
+        let old: Table = toml::from_str(
+            r#"
+            log_files = ["/var/log/a.log", "/var/log/b.log"]
+            mmap_files = ["/var/log/b.log"]
+            endpoint = "http://localhost:4318/v1/logs"
+
+            [[inputs]]
+            type = "file"
+            path = "/var/log/c.log"
+
+            [[commands]]
+            name = "uptime"
+            command = "uptime"
+
+            [statsd]
+            bind = "127.0.0.1:8125"
+            "#,
+        )
+        .unwrap();
+        let (new, changes) = migrate(old);
+        assert_eq!(changes.len(), 3);
+        assert!(!new.contains_key("log_files") && !new.contains_key("mmap_files"));
+        let kinds: Vec<(&str, Option<&str>)> = new["inputs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|i| {
+                (
+                    i["type"].as_str().unwrap(),
+                    i.get("path").and_then(Value::as_str),
+                )
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("file", Some("/var/log/c.log")),
+                ("file", Some("/var/log/a.log")),
+                ("file", Some("/var/log/b.log")),
+                ("command", None),
+                ("statsd", None),
+            ]
+        );
+        assert_eq!(new["inputs"][2]["mmap"].as_bool(), Some(true));
+        assert!(migrate(new).1.is_empty());
+    }
+}
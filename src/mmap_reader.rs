@@ -0,0 +1,357 @@
+use memmap2::{Mmap, MmapOptions};
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::checkpoint::Positions;
+use crate::fileid::FileId;
+use crate::input::StopFlag;
+use crate::rewrite::{self, RewriteTracker};
+use crate::stats::{AgentState, InputStats, Stage};
+use crate::tailer::Origins;
+use crate::tombstone::{Follow, Follower};
+use crate::{scan_lines, trim_line_end, TailOptions};
+
+/// Tails `path` by mapping the newly appended region and scanning it for newlines,
+/// instead of copying it through a read buffer. Complete lines are delivered in batches
+/// of about `options.buffer_bytes`; a partial last line is picked up once its newline arrives.
+///
+/// The region is mapped right after checking the file length, but a file truncated
+/// while its mapping is scanned faults, so this is meant for append-only files with very
+/// high write rates. A file once seen to shrink or be rewritten in place, as with
+/// `copytruncate`, is read with positioned reads from then on, and handled per
+/// `options.rewrite`; a deleted or replaced one is handled as by `tail_file`.
+pub fn tail_file_mmap<F>(
+    path: String,
+    mut options: TailOptions,
+    positions: Arc<Positions>,
+    state: Arc<AgentState>,
+    stats: Arc<InputStats>,
+    stop: StopFlag,
+    mut handler: F,
+) -> thread::JoinHandle<()>
+where
+    F: FnMut(Vec<Vec<u8>>, Option<Origins>) + Send + 'static,
+{
+    thread::spawn(move || {
+        let file = match fs::File::open(&path) {
+            Ok(f) => f,
+            Err(e) => {
+                state.record_error(format!("Failed to open {}: {e}", path));
+                return;
+            }
+        };
+        let (file, mut offset) = positions.resume(
+            &path,
+            file,
+            &stats,
+            options.finish_rotated,
+            options.from_start,
+        );
+        // Until lines are delivered, a restart starts where this run did.
+        if let Ok(meta) = file.metadata() {
+            positions.record(&path, &meta, offset, &stats);
+        }
+        let mut tracker = RewriteTracker::new(&path, &file, options.rewrite);
+        let mut follower = Follower::new(&path, options.deleted_grace, options.finish_rotated);
+        // `None` once a deleted file was released and nothing replaced it yet.
+        let mut file = Some(file);
+        // Set once the file shrank: it is no longer mapped.
+        let mut shrinks = false;
+        // What is read at a time then, doubled while a line does not fit.
+        let mut chunk = options.buffer_bytes;
+
+        while !stop.is_stopped() {
+            // Quarantined with `action = "pause"`: the rest of the file waits for a restart.
+            if stats.paused.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(500));
+                continue;
+            }
+            let Some(ref open) = file else {
+                thread::sleep(Duration::from_millis(500));
+                if let Some(reopened) = follower.reopen() {
+                    tracker = RewriteTracker::new(&path, &reopened, options.rewrite);
+                    offset = 0;
+                    file = Some(reopened);
+                }
+                continue;
+            };
+            let meta = match open.metadata() {
+                Ok(meta) => meta,
+                Err(e) => {
+                    state.record_error(format!("Error reading {}: {e}", path));
+                    thread::sleep(Duration::from_secs(5));
+                    match fs::File::open(&path) {
+                        Ok(f) => {
+                            offset = f.metadata().map(|m| m.len()).unwrap_or(0);
+                            file = Some(f);
+                            println!("Successfully reopened {}", path);
+                        }
+                        Err(e) => {
+                            state.record_error(format!("Failed to reopen {}: {e}", path));
+                            thread::sleep(Duration::from_secs(30));
+                        }
+                    }
+                    continue;
+                }
+            };
+            let len = meta.len();
+            if tracker.is_rewritten(open, offset) {
+                shrinks = true;
+                offset = if tracker.start_over(&path, open) {
+                    0
+                } else {
+                    len
+                };
+            }
+            if len == offset {
+                stats.lag_bytes.store(0, Ordering::Relaxed);
+                // The backlog is read: the next file waiting gets its turn.
+                drop((options.catch_up.take(), options.until_end.take()));
+                match follower.poll(open) {
+                    Follow::Keep => {
+                        tracker.refresh_head(open);
+                        thread::sleep(Duration::from_millis(500));
+                    }
+                    Follow::Switch(reopened) => {
+                        tracker = RewriteTracker::new(&path, &reopened, options.rewrite);
+                        offset = 0;
+                        file = Some(reopened);
+                    }
+                    Follow::Release => file = None,
+                }
+                continue;
+            }
+
+            let region = if shrinks {
+                let want = (len - offset).min(chunk as u64) as usize;
+                read_region(open, offset, want).map(Region::Read)
+            } else {
+                // SAFETY: the mapped range lies within the file's current length; see the
+                // truncation caveat in the function docs.
+                unsafe {
+                    MmapOptions::new()
+                        .offset(offset)
+                        .len((len - offset) as usize)
+                        .map(open)
+                }
+                .map(Region::Mapped)
+            };
+            let region = match region {
+                Ok(region) => region,
+                Err(e) => {
+                    state.record_error(format!("Failed to read {}: {e}", path));
+                    thread::sleep(Duration::from_secs(5));
+                    continue;
+                }
+            };
+
+            // Time spent in the handler belongs to later stages, not to reading.
+            let started = Instant::now();
+            let (mut lines, mut handling) = (0, Duration::ZERO);
+            let (mut batch, mut pending_bytes) = (Vec::new(), 0);
+            let (mut offsets, mut next) = (Vec::new(), offset);
+            let origins = |offsets: &mut Vec<u64>| {
+                let offsets = std::mem::take(offsets);
+                options.origins.then(|| Origins {
+                    inode: meta.ino(),
+                    offsets,
+                })
+            };
+            // The position after the lines is taken once their records are done with.
+            let mut hand_on = |batch: Vec<Vec<u8>>, origins: Option<Origins>, read_to: u64| {
+                stats.set_checkpoint(Some(positions.checkpoint(&path, &meta, read_to, &stats)));
+                handler(batch, origins);
+                positions.sequenced(&path, &stats);
+                stats.set_checkpoint(None);
+            };
+            let consumed = scan_lines(&region, |line| {
+                lines += 1;
+                let start = next;
+                next += line.len() as u64;
+                let line = trim_line_end(line);
+                if tracker.admit(line) && !String::from_utf8_lossy(line).trim().is_empty() {
+                    batch.push(line.to_vec());
+                    offsets.push(start);
+                    pending_bytes += line.len();
+                }
+                if pending_bytes >= options.buffer_bytes {
+                    let handled = Instant::now();
+                    hand_on(std::mem::take(&mut batch), origins(&mut offsets), next);
+                    handling += handled.elapsed();
+                    pending_bytes = 0;
+                }
+            });
+            if !batch.is_empty() {
+                let handled = Instant::now();
+                hand_on(batch, origins(&mut offsets), next);
+                handling += handled.elapsed();
+            }
+            state.record_stage(
+                Stage::Read,
+                lines,
+                started.elapsed().saturating_sub(handling),
+            );
+            chunk = if consumed == 0 && region.len() == chunk {
+                chunk * 2
+            } else {
+                options.buffer_bytes
+            };
+            offset += consumed as u64;
+            positions.record(&path, &meta, offset, &stats);
+            stats.lag_bytes.store(len - offset, Ordering::Relaxed);
+        }
+    })
+}
+
+/// The part of a file scanned for lines.
+enum Region {
+    Mapped(Mmap),
+    Read(Vec<u8>),
+}
+
+impl std::ops::Deref for Region {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Region::Mapped(map) => map,
+            Region::Read(bytes) => bytes,
+        }
+    }
+}
+
+/// Up to `len` bytes of `file` from `offset`, fewer where it ends.
+fn read_region(file: &fs::File, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0; len];
+    let mut read = 0;
+    while read < len {
+        match rewrite::read_at(file, &mut bytes[read..], offset + read as u64)? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    bytes.truncate(read);
+    Ok(bytes)
+}
+
+/// Reads all of `path` once with the buffered and the mmap line readers and reports
+/// their throughput, to decide whether `mmap_files` pays off for a given file.
+pub fn bench(path: &str) -> anyhow::Result<()> {
+    let size = fs::metadata(path)?.len();
+
+    let started = Instant::now();
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let mut line = Vec::new();
+    let mut buffered_lines = 0u64;
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        buffered_lines += 1;
+        line.clear();
+    }
+    let buffered = started.elapsed();
+
+    let started = Instant::now();
+    let file = fs::File::open(path)?;
+    let mut mmap_lines = 0u64;
+    if size > 0 {
+        // SAFETY: benchmark input, assumed not to be truncated while being read.
+        let map = unsafe { MmapOptions::new().map(&file)? };
+        scan_lines(&map, |_| mmap_lines += 1);
+    }
+    let mapped = started.elapsed();
+
+    for (name, lines, elapsed) in [
+        ("buffered", buffered_lines, buffered),
+        ("mmap", mmap_lines, mapped),
+    ] {
+        let secs = elapsed.as_secs_f64().max(1e-9);
+        println!(
+            "{:<10} {:>10} lines in {:>8.3}s  {:>12.0} lines/s  {:>8.1} MiB/s",
+            name,
+            lines,
+            secs,
+            lines as f64 / secs,
+            size as f64 / secs / (1024.0 * 1024.0)
+        );
+    }
+    if let Some(peak) = peak_rss_kib() {
+        println!("peak RSS   {:>10.1} MiB", peak as f64 / 1024.0);
+    }
+    Ok(())
+}
+
+/// The most memory the process has had resident, from `VmHWM` in `/proc/self/status`.
+fn peak_rss_kib() -> Option<u64> {
+    fs::read_to_string("/proc/self/status")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::io::Write;
+    use std::sync::mpsc;
+
+    #[test]
+    fn a_file_truncated_in_place_is_read_without_mapping_it() {
+        let dir = TempDir::new("mmap-copytruncate");
+        let log = dir.join("app.log");
+        fs::write(&log, "").unwrap();
+        let path = log.to_str().unwrap().to_string();
+        let state = Arc::new(AgentState::default());
+        let stats = state.register_input(&path);
+        let positions = Positions::load(dir.to_str().unwrap()).unwrap();
+        let options = TailOptions {
+            buffer_bytes: 16,
+            ..TailOptions::test()
+        };
+        let (tx, lines) = mpsc::channel();
+        let stop = StopFlag::default();
+        tail_file_mmap(
+            path,
+            options,
+            positions,
+            state,
+            stats,
+            stop.clone(),
+            move |batch, _| {
+                for line in batch {
+                    tx.send(String::from_utf8(line).unwrap()).ok();
+                }
+            },
+        );
+        thread::sleep(Duration::from_millis(200));
+        let append = |text: &str| {
+            let mut file = fs::OpenOptions::new().append(true).open(&log).unwrap();
+            file.write_all(text.as_bytes()).unwrap();
+        };
+        let next = || lines.recv_timeout(Duration::from_secs(3)).unwrap();
+
+        append("mapped\n");
+        assert_eq!(next(), "mapped");
+        // As `copytruncate` leaves it: emptied, then written to again.
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&log)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        append("after\n");
+        assert_eq!(next(), "after");
+        let long = "a line longer than the read buffer";
+        append(&format!("{long}\n"));
+        assert_eq!(next(), long);
+        stop.stop();
+    }
+}
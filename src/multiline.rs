@@ -0,0 +1,132 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::rules::{self, RegexLimits};
+
+/// How an input's lines group into records, for entries spanning several lines such as
+/// stack traces.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MultilineConfig {
+    /// Matches the first line of a record; lines not matching it continue the record
+    /// before them.
+    pub pattern: String,
+    /// How long a record waits for its next line before it is sent as it is.
+    #[serde(default = "default_flush_timeout_ms")]
+    pub flush_timeout_ms: u64,
+    /// Lines joined into one record at most; the line after them starts a new one.
+    #[serde(default = "default_max_lines")]
+    pub max_lines: usize,
+}
+
+fn default_flush_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_max_lines() -> usize {
+    500
+}
+
+/// Joins continuation lines onto the record they belong to, joined by `\n`.
+pub struct Joiner {
+    start: Regex,
+    flush_timeout: Duration,
+    max_lines: usize,
+    pending: Option<Vec<u8>>,
+    lines: usize,
+    updated: Instant,
+}
+
+impl Joiner {
+    pub fn new(
+        name: &str,
+        config: &MultilineConfig,
+        limits: &RegexLimits,
+    ) -> anyhow::Result<Joiner> {
+        if config.max_lines == 0 {
+            return Err(anyhow::anyhow!(
+                "multiline max_lines must be greater than 0"
+            ));
+        }
+        Ok(Joiner {
+            start: rules::compile(&format!("multiline {}", name), &config.pattern, limits)?,
+            flush_timeout: Duration::from_millis(config.flush_timeout_ms),
+            max_lines: config.max_lines,
+            pending: None,
+            lines: 0,
+            updated: Instant::now(),
+        })
+    }
+
+    /// Adds read lines (without their terminators) and returns the records they complete.
+    pub fn push(&mut self, lines: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+        let mut records = Vec::new();
+        for line in lines {
+            match self.pending {
+                Some(ref mut record)
+                    if self.lines < self.max_lines
+                        && !self.start.is_match(&String::from_utf8_lossy(&line)) =>
+                {
+                    record.push(b'\n');
+                    record.extend_from_slice(&line);
+                    self.lines += 1;
+                }
+                _ => {
+                    records.extend(self.pending.replace(line));
+                    self.lines = 1;
+                }
+            }
+        }
+        self.updated = Instant::now();
+        records
+    }
+
+    /// The record being joined, once no line came for it within the flush timeout.
+    pub fn expired(&mut self) -> Option<Vec<u8>> {
+        if self.updated.elapsed() >= self.flush_timeout {
+            self.take()
+        } else {
+            None
+        }
+    }
+
+    /// The record being joined, complete or not.
+    pub fn take(&mut self) -> Option<Vec<u8>> {
+        self.lines = 0;
+        self.pending.take()
+    }
+
+    pub fn is_joining(&self) -> bool {
+        self.pending.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn continuation_lines_join_the_record_they_follow() {
+        let config: MultilineConfig =
+            toml::from_str("pattern = '^\\d'\nflush_timeout_ms = 50\nmax_lines = 3").unwrap();
+        let mut joiner = Joiner::new("app", &config, &RegexLimits::default()).unwrap();
+        let lines = |lines: &[&str]| lines.iter().map(|l| l.as_bytes().to_vec()).collect();
+
+        assert!(joiner.push(lines(&["1 panic", "  at main"])).is_empty());
+        assert!(joiner.push(lines(&["  at start"])).is_empty());
+        assert_eq!(
+            joiner.push(lines(&["2 next", "  a", "  b", "  c"])),
+            [
+                b"1 panic\n  at main\n  at start".to_vec(),
+                b"2 next\n  a\n  b".to_vec()
+            ]
+        );
+        // A record past `max_lines` goes on as a new one.
+        assert!(joiner.is_joining());
+        assert_eq!(joiner.expired(), None);
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(joiner.expired(), Some(b"  c".to_vec()));
+        assert!(!joiner.is_joining());
+        assert_eq!(joiner.take(), None);
+    }
+}
@@ -0,0 +1,131 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::exporter::S3Config;
+use crate::{admin, daemon, s3, statedir, Config};
+
+/// The object the state directory is kept in, below the `state_s3` prefix.
+const STATE_OBJECT: &str = "state.json";
+/// Files of the state directory that belong to the running agent rather than say where
+/// it got to.
+const LOCAL_FILES: &[&str] = &[
+    statedir::LOCK_FILE,
+    admin::SOCKET_FILE,
+    daemon::PID_FILE,
+    daemon::LOG_FILE,
+];
+
+/// Before a `--oneshot` run with `state_s3`: writes the files a previous run stored into
+/// `state_dir`, over what it holds. A bucket without them leaves it as it is, for a
+/// first run.
+pub fn fetch_state(s3: &S3Config, state_dir: &str, config: &Config) -> Result<()> {
+    let key = format!("{}{}", s3.prefix, STATE_OBJECT);
+    let object = s3::get_object(&client(config), s3, &key, Utc::now())
+        .map_err(|e| anyhow::anyhow!("Failed to fetch s3://{}/{}: {}", s3.bucket, key, e))?;
+    let Some(object) = object else {
+        println!("No state in s3://{}/{} yet", s3.bucket, key);
+        return Ok(());
+    };
+    let files: BTreeMap<String, String> = serde_json::from_slice(&object)
+        .with_context(|| format!("s3://{}/{} is not a stored state", s3.bucket, key))?;
+    restore(state_dir, &files)?;
+    println!(
+        "Restored {} state files from s3://{}/{}",
+        files.len(),
+        s3.bucket,
+        key
+    );
+    Ok(())
+}
+
+/// After a `--oneshot` run that delivered everything: stores the files of `state_dir`,
+/// the read positions among them, for the next run to fetch.
+pub fn store_state(s3: &S3Config, state_dir: &str, config: &Config) -> Result<()> {
+    let key = format!("{}{}", s3.prefix, STATE_OBJECT);
+    let files = snapshot(state_dir)?;
+    let body = serde_json::to_vec(&files)?;
+    s3::put_object(&client(config), s3, &key, body, Utc::now())
+        .map_err(|e| anyhow::anyhow!("Failed to store s3://{}/{}: {}", s3.bucket, key, e))?;
+    println!(
+        "Stored {} state files in s3://{}/{}",
+        files.len(),
+        s3.bucket,
+        key
+    );
+    Ok(())
+}
+
+fn client(config: &Config) -> reqwest::blocking::Client {
+    crate::exporter::http_client(config, &Default::default(), Duration::from_secs(60))
+        .unwrap_or_else(|_| reqwest::blocking::Client::new())
+}
+
+/// The files directly in `state_dir` by name, without the agent's own.
+fn snapshot(state_dir: &str) -> Result<BTreeMap<String, String>> {
+    let mut files = BTreeMap::new();
+    for entry in fs::read_dir(state_dir)
+        .with_context(|| format!("Failed to read {}", state_dir))?
+        .flatten()
+    {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if LOCAL_FILES.contains(&name.as_str()) || !entry.path().is_file() {
+            continue;
+        }
+        let contents = fs::read_to_string(entry.path())
+            .with_context(|| format!("Failed to read {}", entry.path().display()))?;
+        files.insert(name, contents);
+    }
+    Ok(files)
+}
+
+fn restore(state_dir: &str, files: &BTreeMap<String, String>) -> Result<()> {
+    fs::create_dir_all(state_dir).with_context(|| format!("Failed to create {}", state_dir))?;
+    for (name, contents) in files {
+        // Names come from the bucket; only plain file names are written.
+        if Path::new(name).file_name() != Some(name.as_ref()) {
+            return Err(anyhow::anyhow!(
+                "Stored state file {:?} is not a file name",
+                name
+            ));
+        }
+        let path = Path::new(state_dir).join(name);
+        fs::write(&path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn state_files_round_trip_without_the_agents_own() {
+        let dir = TempDir::new("oneshot");
+        fs::create_dir_all(dir.join("buffer")).unwrap();
+        fs::write(dir.join("positions.json"), "{\"/var/log/app.log\":{}}").unwrap();
+        fs::write(dir.join("journald-system.cursor"), "s=abc").unwrap();
+        fs::write(dir.join(statedir::LOCK_FILE), "123").unwrap();
+        let state_dir = dir.to_str().unwrap();
+
+        let files = snapshot(state_dir).unwrap();
+        assert_eq!(
+            files.keys().collect::<Vec<_>>(),
+            ["journald-system.cursor", "positions.json"]
+        );
+
+        let restored = dir.join("restored");
+        restore(restored.to_str().unwrap(), &files).unwrap();
+        assert_eq!(
+            fs::read_to_string(restored.join("journald-system.cursor")).unwrap(),
+            "s=abc"
+        );
+        let escaping = BTreeMap::from([("../x".to_string(), String::new())]);
+        assert!(restore(restored.to_str().unwrap(), &escaping).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use crate::config::DEFAULT_OTLP_PATH;
+use crate::exporter::HeaderSource;
+use crate::Config;
+
+/// Base URL of the collector, under which OTLP/HTTP takes logs on `/v1/logs`.
+const ENDPOINT: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+/// URL of the logs endpoint itself, taken over `ENDPOINT`.
+const LOGS_ENDPOINT: &str = "OTEL_EXPORTER_OTLP_LOGS_ENDPOINT";
+/// `key=value` pairs separated by commas, the values percent-encoded.
+const HEADERS: &str = "OTEL_EXPORTER_OTLP_HEADERS";
+const RESOURCE_ATTRIBUTES: &str = "OTEL_RESOURCE_ATTRIBUTES";
+/// Taken over a `service.name` in `RESOURCE_ATTRIBUTES`.
+const SERVICE_NAME: &str = "OTEL_SERVICE_NAME";
+
+/// Applies the standard OTel environment variables that are set to `config`, over what
+/// config.toml says: the endpoint, headers and resource attributes of the default
+/// exporter and the service name. The command line and the `RUST_SIGNOZ_AGENT_*`
+/// variables are applied after, so they win.
+pub fn apply(config: &mut Config) {
+    apply_from(config, |name| std::env::var(name).ok());
+}
+
+fn apply_from(config: &mut Config, var: impl Fn(&str) -> Option<String>) {
+    let var = |name: &str| var(name).filter(|value| !value.trim().is_empty());
+    if let Some(endpoint) = var(LOGS_ENDPOINT) {
+        config.endpoint = endpoint.trim().to_string();
+    } else if let Some(endpoint) = var(ENDPOINT) {
+        config.endpoint = format!(
+            "{}{}",
+            endpoint.trim().trim_end_matches('/'),
+            config.otlp_path.as_deref().unwrap_or(DEFAULT_OTLP_PATH)
+        );
+    }
+    for (name, value) in var(HEADERS).iter().flat_map(|h| pairs(h)) {
+        config.headers.insert(name, HeaderSource::Value(value));
+    }
+    let mut service_name = None;
+    for (key, value) in var(RESOURCE_ATTRIBUTES).iter().flat_map(|a| pairs(a)) {
+        match key.as_str() {
+            "service.name" => service_name = Some(value),
+            _ => {
+                config.resource_attributes.insert(key, value);
+            }
+        }
+    }
+    if let Some(name) = var(SERVICE_NAME).or(service_name) {
+        config.service_name = Some(name.trim().to_string());
+    }
+}
+
+/// The `key=value` pairs of a comma-separated list, decoded; pairs without a key or a
+/// `=` are skipped, as the OTel SDKs skip them.
+fn pairs(list: &str) -> Vec<(String, String)> {
+    list.split(',')
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once('=')?;
+            let key = percent_decode(key.trim());
+            (!key.is_empty()).then(|| (key, percent_decode(value.trim())))
+        })
+        .collect()
+}
+
+/// `%XX` escapes replaced by their bytes; anything else is kept as written.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn otel_variables_override_the_config() {
+        let config = || -> Config {
+            toml::from_str(
+                "endpoint = \"http://localhost:4318/v1/logs\"\nlog_files = []\nservice_name = \"from-config\"\n\n[headers]\nx-team = \"logs\"\n",
+            )
+            .unwrap()
+        };
+        let applied = |vars: &[(&str, &str)]| {
+            let vars: BTreeMap<String, String> = vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            let mut config = config();
+            apply_from(&mut config, |name| vars.get(name).cloned());
+            config
+        };
+
+        let config = applied(&[
+            (ENDPOINT, "https://otel.example.com:4318/"),
+            (
+                HEADERS,
+                "signoz-ingestion-key=abc%3D%3D, x-env = prod,broken",
+            ),
+            (
+                RESOURCE_ATTRIBUTES,
+                "deployment.environment=prod,service.name=checkout,team=a%2Cb",
+            ),
+        ]);
+        assert_eq!(config.endpoint, "https://otel.example.com:4318/v1/logs");
+        let header = |name: &str| match config.headers.get(name) {
+            Some(HeaderSource::Value(value)) => value.clone(),
+            _ => panic!("no header {}", name),
+        };
+        assert_eq!(header("signoz-ingestion-key"), "abc==");
+        assert_eq!(header("x-env"), "prod");
+        assert_eq!(header("x-team"), "logs");
+        assert_eq!(config.headers.len(), 3);
+        assert_eq!(config.service_name.as_deref(), Some("checkout"));
+        assert_eq!(config.resource_attributes["team"], "a,b");
+        assert!(!config.resource_attributes.contains_key("service.name"));
+
+        let config = applied(&[
+            (ENDPOINT, "https://otel.example.com"),
+            (LOGS_ENDPOINT, "https://logs.example.com/ingest"),
+            (RESOURCE_ATTRIBUTES, "service.name=checkout"),
+            (SERVICE_NAME, "payments"),
+        ]);
+        assert_eq!(config.endpoint, "https://logs.example.com/ingest");
+        assert_eq!(config.service_name.as_deref(), Some("payments"));
+
+        let config = applied(&[(SERVICE_NAME, " ")]);
+        assert_eq!(config.endpoint, "http://localhost:4318/v1/logs");
+        assert_eq!(config.service_name.as_deref(), Some("from-config"));
+    }
+}
@@ -0,0 +1,415 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+use crate::failure::{self, Failure};
+use crate::manifest::deployed_config;
+use crate::statedir::SYSTEM_CONFIG_DIR as CONFIG_DIR;
+use crate::{activation, systemd_unit, Config};
+
+const APP: &str = "rust-signoz-agent";
+const BINARY_PATH: &str = "/usr/bin/rust-signoz-agent";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const SUMMARY: &str = "Ships log files to SigNoz over OTLP";
+pub const DEFAULT_MAINTAINER: &str = "rust-signoz-agent <root@localhost>";
+
+/// A file of the package skeleton, relative to the output directory.
+#[derive(Debug)]
+struct File {
+    path: String,
+    contents: String,
+    mode: u32,
+}
+
+impl File {
+    fn new(path: String, contents: String, mode: u32) -> File {
+        File {
+            path,
+            contents,
+            mode,
+        }
+    }
+}
+
+/// What the package installs besides the binary, the same for both formats.
+struct Contents {
+    user: String,
+    state_dir: String,
+    config: String,
+    service: String,
+    socket: Option<String>,
+}
+
+/// Writes the skeleton of a `format` ("deb" or "rpm") package of the running binary and
+/// config.toml into `output`, and prints the command that builds the package from it.
+pub fn run(config_path: &str, format: &str, output: &str, maintainer: &str) -> Result<()> {
+    let config_text = fs::read_to_string(config_path)
+        .with_context(|| format!("package needs {}", config_path))?;
+    let config: Config = toml::from_str(&config_text)
+        .map_err(|e| failure::classify(e.into(), Failure::ConfigInvalid))?;
+    let contents = contents(&config, &config_text)?;
+    let (files, binary, build) = match format {
+        "deb" => {
+            let root = format!("{}_{}_{}", APP, VERSION, deb_arch());
+            let files = deb(&contents, &root, maintainer);
+            let build = format!(
+                "dpkg-deb --build --root-owner-group {}",
+                Path::new(output).join(&root).display()
+            );
+            (files, format!("{}{}", root, BINARY_PATH), build)
+        }
+        "rpm" => {
+            let files = rpm(&contents, maintainer);
+            let top = fs::canonicalize(output).unwrap_or_else(|_| output.into());
+            let build = format!(
+                "rpmbuild -bb --define '_topdir {}' {}",
+                top.display(),
+                Path::new(output)
+                    .join(format!("SPECS/{}.spec", APP))
+                    .display()
+            );
+            (files, format!("SOURCES/{}", APP), build)
+        }
+        other => {
+            return Err(anyhow::anyhow!(
+                "Unknown package format {:?}; use deb or rpm",
+                other
+            ))
+        }
+    };
+
+    let exe = std::env::current_exe().context("Cannot determine the agent binary path")?;
+    for file in &files {
+        let path = Path::new(output).join(&file.path);
+        create_parent(&path)?;
+        fs::write(&path, &file.contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        set_mode(&path, file.mode)?;
+    }
+    let binary = Path::new(output).join(binary);
+    create_parent(&binary)?;
+    fs::copy(&exe, &binary).with_context(|| format!("Failed to write {}", binary.display()))?;
+    set_mode(&binary, 0o755)?;
+    println!(
+        "{} package of {} {} written to {}",
+        format, APP, VERSION, output
+    );
+    println!("To build it, run:");
+    println!("  {}", build);
+    Ok(())
+}
+
+fn create_parent(path: &Path) -> Result<()> {
+    match path.parent() {
+        Some(dir) => {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set the mode of {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+/// The config as deployed (see `manifest`), and the units running the agent as
+/// `service_user`, or a system user of its own, confined to the state directory.
+fn contents(config: &Config, config_text: &str) -> Result<Contents> {
+    let config_text = deployed_config(config_text)?;
+    let deployed: Config = toml::from_str(&config_text)?;
+    let user = config
+        .service_user
+        .clone()
+        .unwrap_or_else(|| APP.to_string());
+    let state_dir = deployed.state_directory();
+    let service = systemd_unit(
+        &format!("User={}\n", user),
+        CONFIG_DIR,
+        BINARY_PATH,
+        &config.service_unit,
+        &format!("ProtectSystem=strict\nReadWritePaths={}\n", state_dir),
+    );
+    let socket = activation::socket_unit(config).filter(|_| config.service_unit.socket_activation);
+    Ok(Contents {
+        user,
+        state_dir,
+        config: config_text,
+        service,
+        socket,
+    })
+}
+
+/// The units to enable, the socket first since the service depends on it.
+fn units(contents: &Contents) -> String {
+    match contents.socket {
+        Some(_) => format!("{} {}.service", activation::SOCKET_UNIT, APP),
+        None => format!("{}.service", APP),
+    }
+}
+
+/// The tree `dpkg-deb --build` makes a package of, under `root`.
+fn deb(contents: &Contents, root: &str, maintainer: &str) -> Vec<File> {
+    let control = format!(
+        "Package: {app}\nVersion: {version}\nArchitecture: {arch}\nMaintainer: {maintainer}\n\
+         Section: admin\nPriority: optional\nDepends: adduser\nDescription: {summary}\n",
+        app = APP,
+        version = VERSION,
+        arch = deb_arch(),
+        maintainer = maintainer,
+        summary = SUMMARY,
+    );
+    let config_path = format!("{}/config.toml", CONFIG_DIR);
+    // Most of /var/log is readable by the adm group on Debian and Ubuntu.
+    let postinst = format!(
+        r#"#!/bin/sh
+set -e
+
+if [ "$1" = configure ]; then
+    if ! getent passwd {user} >/dev/null; then
+        adduser --system --group --no-create-home --home {state_dir} {user}
+    fi
+    if getent group adm >/dev/null; then
+        usermod -a -G adm {user}
+    fi
+    group="$(id -gn {user})"
+    install -d -m 0750 -o {user} -g "$group" {state_dir}
+    chown root:"$group" {config}
+    chmod 0640 {config}
+    if [ -d /run/systemd/system ]; then
+        systemctl daemon-reload
+        systemctl enable {units}
+        systemctl restart {units}
+    fi
+fi
+"#,
+        user = contents.user,
+        state_dir = contents.state_dir,
+        config = config_path,
+        units = units(contents),
+    );
+    let prerm = format!(
+        r#"#!/bin/sh
+set -e
+
+if [ "$1" = remove ] && [ -d /run/systemd/system ]; then
+    systemctl disable --now {units} || true
+fi
+"#,
+        units = units(contents),
+    );
+    let postrm = format!(
+        r#"#!/bin/sh
+set -e
+
+if [ "$1" = purge ]; then
+    rm -rf {state_dir}
+fi
+if [ -d /run/systemd/system ]; then
+    systemctl daemon-reload || true
+fi
+"#,
+        state_dir = contents.state_dir,
+    );
+    let at = |path: &str| format!("{}/{}", root, path.trim_start_matches('/'));
+    let mut files = vec![
+        File::new(at("DEBIAN/control"), control, 0o644),
+        // Kept as edited on upgrades, and only removed on purge.
+        File::new(at("DEBIAN/conffiles"), format!("{}\n", config_path), 0o644),
+        File::new(at("DEBIAN/postinst"), postinst, 0o755),
+        File::new(at("DEBIAN/prerm"), prerm, 0o755),
+        File::new(at("DEBIAN/postrm"), postrm, 0o755),
+        File::new(at(&config_path), contents.config.clone(), 0o640),
+        File::new(
+            at(&format!("lib/systemd/system/{}.service", APP)),
+            contents.service.clone(),
+            0o644,
+        ),
+    ];
+    if let Some(ref socket) = contents.socket {
+        files.push(File::new(
+            at(&format!("lib/systemd/system/{}", activation::SOCKET_UNIT)),
+            socket.clone(),
+            0o644,
+        ));
+    }
+    files
+}
+
+/// The spec and sources `rpmbuild` makes a package of, in its `SPECS` and `SOURCES`.
+fn rpm(contents: &Contents, maintainer: &str) -> Vec<File> {
+    let (socket_source, socket_install, socket_file) = match contents.socket {
+        Some(_) => (
+            format!("Source3: {}\n", activation::SOCKET_UNIT),
+            format!(
+                "install -D -m 0644 %{{SOURCE3}} %{{buildroot}}/usr/lib/systemd/system/{}\n",
+                activation::SOCKET_UNIT
+            ),
+            format!("/usr/lib/systemd/system/{}\n", activation::SOCKET_UNIT),
+        ),
+        None => Default::default(),
+    };
+    let spec = format!(
+        r#"# The binary is built already.
+%global debug_package %{{nil}}
+
+Name: {app}
+Version: {version}
+Release: 1
+Summary: {summary}
+License: Proprietary
+Packager: {maintainer}
+BuildArch: {arch}
+Requires(pre): shadow-utils
+Source0: {app}
+Source1: {app}.service
+Source2: config.toml
+{socket_source}
+%description
+{summary}.
+
+%install
+install -D -m 0755 %{{SOURCE0}} %{{buildroot}}{binary}
+install -D -m 0644 %{{SOURCE1}} %{{buildroot}}/usr/lib/systemd/system/{app}.service
+{socket_install}install -D -m 0640 %{{SOURCE2}} %{{buildroot}}{config_dir}/config.toml
+install -d -m 0750 %{{buildroot}}{state_dir}
+
+%pre
+getent group {user} >/dev/null || groupadd -r {user}
+getent passwd {user} >/dev/null || useradd -r -g {user} -d {state_dir} -s /sbin/nologin {user}
+exit 0
+
+%post
+if [ -d /run/systemd/system ]; then
+    systemctl daemon-reload
+    systemctl enable {units}
+    systemctl restart {units}
+fi
+
+%preun
+if [ "$1" -eq 0 ] && [ -d /run/systemd/system ]; then
+    systemctl disable --now {units} || true
+fi
+
+%postun
+if [ -d /run/systemd/system ]; then
+    systemctl daemon-reload || true
+fi
+
+%files
+{binary}
+/usr/lib/systemd/system/{app}.service
+{socket_file}%dir {config_dir}
+%config(noreplace) %attr(0640, root, {user}) {config_dir}/config.toml
+%dir %attr(0750, {user}, {user}) {state_dir}
+"#,
+        app = APP,
+        version = VERSION,
+        summary = SUMMARY,
+        maintainer = maintainer,
+        arch = rpm_arch(),
+        binary = BINARY_PATH,
+        config_dir = CONFIG_DIR,
+        state_dir = contents.state_dir,
+        user = contents.user,
+        units = units(contents),
+        socket_source = socket_source,
+        socket_install = socket_install,
+        socket_file = socket_file,
+    );
+    let mut files = vec![
+        File::new(format!("SPECS/{}.spec", APP), spec, 0o644),
+        File::new(
+            format!("SOURCES/{}.service", APP),
+            contents.service.clone(),
+            0o644,
+        ),
+        File::new(
+            "SOURCES/config.toml".to_string(),
+            contents.config.clone(),
+            0o640,
+        ),
+    ];
+    if let Some(ref socket) = contents.socket {
+        files.push(File::new(
+            format!("SOURCES/{}", activation::SOCKET_UNIT),
+            socket.clone(),
+            0o644,
+        ));
+    }
+    files
+}
+
+/// Debian's name of the architecture the binary was built for.
+fn deb_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "i386",
+        "arm" => "armhf",
+        other => other,
+    }
+}
+
+/// RPM's name of the architecture the binary was built for.
+fn rpm_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86" => "i686",
+        "arm" => "armv7hl",
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packages_install_the_unit_config_and_user() {
+        let config_text = "endpoint = \"http://collector:4318/v1/logs\"\nlog_files = [\"/var/log/app.log\"]\nservice_name = \"app\"\n";
+        let config: Config = toml::from_str(config_text).unwrap();
+        let contents = contents(&config, config_text).unwrap();
+        let find = |files: &[File], path: &str| {
+            files
+                .iter()
+                .find(|f| f.path.ends_with(path))
+                .map(|f| (f.contents.clone(), f.mode))
+                .unwrap_or_else(|| panic!("no {}", path))
+        };
+
+        let deb = deb(&contents, "root", DEFAULT_MAINTAINER);
+        assert_eq!(
+            find(&deb, "DEBIAN/conffiles").0,
+            "/etc/rust-signoz-agent/config.toml\n"
+        );
+        let (postinst, mode) = find(&deb, "DEBIAN/postinst");
+        assert_eq!(mode, 0o755);
+        assert!(postinst.contains("adduser --system --group --no-create-home --home /var/lib/rust-signoz-agent rust-signoz-agent"));
+        assert!(postinst.contains("systemctl enable rust-signoz-agent.service"));
+        let (service, _) = find(&deb, "lib/systemd/system/rust-signoz-agent.service");
+        assert!(service.contains("User=rust-signoz-agent\n"));
+        assert!(service.contains("ExecStart=/usr/bin/rust-signoz-agent\n"));
+        assert!(service.contains("ReadWritePaths=/var/lib/rust-signoz-agent\n"));
+        let (deployed, _) = find(&deb, "root/etc/rust-signoz-agent/config.toml");
+        assert!(deployed.contains("state_dir = \"/var/lib/rust-signoz-agent\""));
+        assert!(find(&deb, "DEBIAN/control")
+            .0
+            .contains("Package: rust-signoz-agent\n"));
+
+        let rpm = rpm(&contents, DEFAULT_MAINTAINER);
+        let (spec, _) = find(&rpm, "SPECS/rust-signoz-agent.spec");
+        assert!(spec.contains(
+            "%config(noreplace) %attr(0640, root, rust-signoz-agent) /etc/rust-signoz-agent/config.toml\n"
+        ));
+        assert!(spec.contains("useradd -r -g rust-signoz-agent -d /var/lib/rust-signoz-agent"));
+        assert_eq!(find(&rpm, "SOURCES/rust-signoz-agent.service").0, service);
+    }
+}
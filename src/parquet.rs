@@ -0,0 +1,650 @@
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clock::Clock;
+use crate::exporter::{run_worker, BatchSink, ExporterStats, ParquetConfig, S3Config, Submitted};
+use crate::queue::Lanes;
+use crate::s3;
+use crate::stats::AgentState;
+use crate::{build_otlp_payload, compress, severity_of, AttributeValue, Config, KeyValue};
+use crate::{LogBody, Record};
+
+/// The exporter name the Parquet files are reported under.
+pub const NAME: &str = "parquet";
+
+const MAGIC: &[u8] = b"PAR1";
+
+static NEXT_FILE: AtomicU64 = AtomicU64::new(0);
+
+/// Thrift compact protocol field types.
+const I32: u8 = 5;
+const I64: u8 = 6;
+const BINARY: u8 = 8;
+const LIST: u8 = 9;
+const STRUCT: u8 = 12;
+
+/// Parquet physical types, encodings and codec.
+const TYPE_INT32: i32 = 1;
+const TYPE_INT64: i32 = 2;
+const TYPE_BYTE_ARRAY: i32 = 6;
+const PLAIN: i32 = 0;
+const RLE: i32 = 3;
+const GZIP: i32 = 2;
+const CONVERTED_UTF8: i32 = 0;
+const CONVERTED_TIMESTAMP_MICROS: i32 = 10;
+
+/// Where the files end up, for `status`.
+pub fn destination(parquet: &ParquetConfig, config: &Config) -> String {
+    match parquet.s3 {
+        Some(ref s3) => format!("s3://{}/{}", s3.bucket, s3.prefix),
+        None => dir(parquet, config).display().to_string(),
+    }
+}
+
+fn dir(parquet: &ParquetConfig, config: &Config) -> PathBuf {
+    parquet.dir.as_ref().map_or_else(
+        || Path::new(&config.state_directory()).join("parquet"),
+        PathBuf::from,
+    )
+}
+
+/// Worker loop of the Parquet exporter: collects up to `batch_size` records, or whatever
+/// arrived within `flush_interval_secs` of the first, and writes a file for each hour
+/// they fall in. A record is delivered once its file is written; with `s3` the files are
+/// uploaded after, and those an upload failed for are tried again after the next batch.
+pub fn run(
+    parquet: ParquetConfig,
+    rx: Arc<Lanes<Submitted>>,
+    config: Arc<Config>,
+    state: Arc<AgentState>,
+    stats: Arc<ExporterStats>,
+    clock: Arc<dyn Clock>,
+) {
+    let dir = dir(&parquet, &config);
+    let client =
+        crate::exporter::http_client(&config, &Default::default(), Duration::from_secs(60))
+            .unwrap_or_else(|_| reqwest::blocking::Client::new());
+    let flush_interval = Duration::from_secs(parquet.flush_interval_secs);
+    println!(
+        "Exporter parquet writes files to {}",
+        destination(&parquet, &config)
+    );
+    if let Some(ref s3) = parquet.s3 {
+        upload_pending(&client, s3, &dir, &state);
+    }
+
+    let batching = (parquet.batch_size, flush_interval);
+    let writer = Writer {
+        dir,
+        s3: parquet.s3,
+        client,
+        config,
+        state: state.clone(),
+        clock: clock.clone(),
+    };
+    run_worker(rx, writer, batching, &state, &stats, clock.as_ref());
+}
+
+struct Writer {
+    dir: PathBuf,
+    s3: Option<S3Config>,
+    client: reqwest::blocking::Client,
+    config: Arc<Config>,
+    state: Arc<AgentState>,
+    clock: Arc<dyn Clock>,
+}
+
+impl BatchSink for Writer {
+    fn write(&mut self, records: &[&Record]) -> Result<(), String> {
+        write_batch(&self.dir, &self.config, records, self.clock.now()).map_err(|e| {
+            format!(
+                "Parquet write of {} records to {} failed: {}",
+                records.len(),
+                self.dir.display(),
+                e
+            )
+        })
+    }
+
+    fn written(&mut self) {
+        if let Some(ref s3) = self.s3 {
+            upload_pending(&self.client, s3, &self.dir, &self.state);
+        }
+    }
+}
+
+/// Writes a file per hour of the records, as
+/// `dt=2024-03-05/hour=14/part-<ms>-<n>.parquet` below `dir`, for query engines to
+/// prune partitions by.
+fn write_batch(
+    dir: &Path,
+    config: &Config,
+    records: &[&Record],
+    now: DateTime<Utc>,
+) -> std::io::Result<()> {
+    let mut hours: BTreeMap<String, Vec<Row>> = BTreeMap::new();
+    for entry in records {
+        let row = Row::of(entry, config);
+        let time = DateTime::from_timestamp_micros(row.timestamp).unwrap_or(now);
+        let partition = time.format("dt=%Y-%m-%d/hour=%H").to_string();
+        hours.entry(partition).or_default().push(row);
+    }
+    for (partition, rows) in hours {
+        let dir = dir.join(partition);
+        fs::create_dir_all(&dir)?;
+        let name = format!(
+            "part-{}-{}.parquet",
+            now.timestamp_millis(),
+            NEXT_FILE.fetch_add(1, Ordering::Relaxed)
+        );
+        let tmp = dir.join(format!("{}.tmp", name));
+        fs::write(&tmp, encode(&rows)?)?;
+        fs::rename(&tmp, dir.join(name))?;
+    }
+    Ok(())
+}
+
+/// A record as it is written: its OTLP fields, with the attributes and resource
+/// attributes as `attributes.<key>` and `resource.<key>` columns.
+struct Row {
+    timestamp: i64,
+    observed: i64,
+    severity_text: String,
+    severity_number: i32,
+    body: String,
+    trace_id: Option<String>,
+    span_id: Option<String>,
+    columns: BTreeMap<String, String>,
+}
+
+impl Row {
+    fn of(entry: &Record, config: &Config) -> Row {
+        let payload = build_otlp_payload(entry, severity_of(entry, config), config);
+        let resource_log = &payload.resource_logs[0];
+        let record = &resource_log.scope_logs[0].log_records[0];
+        let micros = |nanos: &str| nanos.parse::<i64>().unwrap_or(0) / 1000;
+        let mut columns = BTreeMap::new();
+        let mut add = |prefix: &str, attributes: &[KeyValue]| {
+            for kv in attributes {
+                let AttributeValue::StringValue { ref value } = kv.value;
+                columns.insert(format!("{}.{}", prefix, kv.key), value.clone());
+            }
+        };
+        add("attributes", &record.attributes);
+        add("resource", &resource_log.resource.attributes);
+        Row {
+            timestamp: micros(&record.time_unix_nano),
+            observed: micros(&record.observed_time_unix_nano),
+            severity_text: record.severity_text.clone(),
+            severity_number: record.severity_number as i32,
+            body: match record.body {
+                LogBody::String { ref value } | LogBody::Bytes { ref value } => value.clone(),
+            },
+            trace_id: record.trace_id.clone(),
+            span_id: record.span_id.clone(),
+            columns,
+        }
+    }
+}
+
+/// The values of one column, `None` for a row without them.
+enum Values<'a> {
+    Timestamps(Vec<i64>),
+    Int32(Vec<i32>),
+    Text(Vec<&'a str>),
+    OptionalText(Vec<Option<&'a str>>),
+}
+
+impl Values<'_> {
+    fn physical_type(&self) -> i32 {
+        match self {
+            Values::Timestamps(_) => TYPE_INT64,
+            Values::Int32(_) => TYPE_INT32,
+            Values::Text(_) | Values::OptionalText(_) => TYPE_BYTE_ARRAY,
+        }
+    }
+
+    fn converted_type(&self) -> Option<i32> {
+        match self {
+            Values::Timestamps(_) => Some(CONVERTED_TIMESTAMP_MICROS),
+            Values::Int32(_) => None,
+            Values::Text(_) | Values::OptionalText(_) => Some(CONVERTED_UTF8),
+        }
+    }
+
+    fn optional(&self) -> bool {
+        matches!(self, Values::OptionalText(_))
+    }
+
+    /// A data page of the values: the definition levels of an optional column, then
+    /// the values that are set, plainly encoded.
+    fn page(&self) -> Vec<u8> {
+        let mut page = Vec::new();
+        let text = |page: &mut Vec<u8>, value: &str| {
+            page.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            page.extend_from_slice(value.as_bytes());
+        };
+        match self {
+            Values::Timestamps(values) => {
+                for value in values {
+                    page.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            Values::Int32(values) => {
+                for value in values {
+                    page.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            Values::Text(values) => {
+                for value in values {
+                    text(&mut page, value);
+                }
+            }
+            Values::OptionalText(values) => {
+                let levels = definition_levels(values.iter().map(Option::is_some));
+                page.extend_from_slice(&(levels.len() as u32).to_le_bytes());
+                page.extend_from_slice(&levels);
+                for value in values.iter().flatten() {
+                    text(&mut page, value);
+                }
+            }
+        }
+        page
+    }
+}
+
+/// Definition levels of bit width 1 in the RLE/bit-packing hybrid, as RLE runs only.
+fn definition_levels(set: impl Iterator<Item = bool>) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let mut run: Option<(bool, u64)> = None;
+    let flush = |encoded: &mut Vec<u8>, (value, length): (bool, u64)| {
+        varint(encoded, length << 1);
+        encoded.push(value as u8);
+    };
+    for value in set {
+        run = match run {
+            Some((current, length)) if current == value => Some((current, length + 1)),
+            Some(previous) => {
+                flush(&mut encoded, previous);
+                Some((value, 1))
+            }
+            None => Some((value, 1)),
+        };
+    }
+    if let Some(last) = run {
+        flush(&mut encoded, last);
+    }
+    encoded
+}
+
+/// A Parquet file of `rows`: one row group, one gzipped data page per column. The
+/// columns are those of every `Row`, then the attributes any of the rows has.
+fn encode(rows: &[Row]) -> std::io::Result<Vec<u8>> {
+    let names: Vec<&String> = {
+        let mut names: Vec<&String> = rows.iter().flat_map(|r| r.columns.keys()).collect();
+        names.sort();
+        names.dedup();
+        names
+    };
+    let mut columns: Vec<(String, Values)> = vec![
+        (
+            "timestamp".to_string(),
+            Values::Timestamps(rows.iter().map(|r| r.timestamp).collect()),
+        ),
+        (
+            "observed_timestamp".to_string(),
+            Values::Timestamps(rows.iter().map(|r| r.observed).collect()),
+        ),
+        (
+            "severity_text".to_string(),
+            Values::Text(rows.iter().map(|r| r.severity_text.as_str()).collect()),
+        ),
+        (
+            "severity_number".to_string(),
+            Values::Int32(rows.iter().map(|r| r.severity_number).collect()),
+        ),
+        (
+            "body".to_string(),
+            Values::Text(rows.iter().map(|r| r.body.as_str()).collect()),
+        ),
+        (
+            "trace_id".to_string(),
+            Values::OptionalText(rows.iter().map(|r| r.trace_id.as_deref()).collect()),
+        ),
+        (
+            "span_id".to_string(),
+            Values::OptionalText(rows.iter().map(|r| r.span_id.as_deref()).collect()),
+        ),
+    ];
+    for name in names {
+        let values = rows
+            .iter()
+            .map(|r| r.columns.get(name).map(String::as_str))
+            .collect();
+        columns.push((name.clone(), Values::OptionalText(values)));
+    }
+
+    let mut file = MAGIC.to_vec();
+    let mut chunks = Vec::with_capacity(columns.len());
+    for (_, values) in &columns {
+        let page = values.page();
+        let compressed = compress::gzip(&page, compress::DEFAULT_LEVEL)?;
+        let mut header = Thrift::default();
+        header.i32(1, 0); // DATA_PAGE
+        header.i32(2, page.len() as i32);
+        header.i32(3, compressed.len() as i32);
+        header.begin(5);
+        header.i32(1, rows.len() as i32);
+        header.i32(2, PLAIN);
+        header.i32(3, RLE);
+        header.i32(4, RLE);
+        header.end();
+        header.stop();
+        let offset = file.len() as i64;
+        file.extend_from_slice(&header.out);
+        file.extend_from_slice(&compressed);
+        chunks.push((
+            offset,
+            (header.out.len() + page.len()) as i64,
+            (header.out.len() + compressed.len()) as i64,
+        ));
+    }
+
+    let mut footer = Thrift::default();
+    footer.i32(1, 1);
+    footer.list(2, STRUCT, columns.len() + 1);
+    footer.element();
+    footer.binary(4, b"schema");
+    footer.i32(5, columns.len() as i32);
+    footer.end();
+    for (name, values) in &columns {
+        footer.element();
+        footer.i32(1, values.physical_type());
+        footer.i32(3, values.optional() as i32);
+        footer.binary(4, name.as_bytes());
+        if let Some(converted) = values.converted_type() {
+            footer.i32(6, converted);
+        }
+        footer.end();
+    }
+    footer.i64(3, rows.len() as i64);
+    footer.list(4, STRUCT, 1);
+    footer.element();
+    footer.list(1, STRUCT, columns.len());
+    for ((name, values), &(offset, uncompressed, compressed)) in columns.iter().zip(&chunks) {
+        footer.element();
+        footer.i64(2, offset);
+        footer.begin(3);
+        footer.i32(1, values.physical_type());
+        footer.list(2, I32, 2);
+        footer.element_i32(PLAIN);
+        footer.element_i32(RLE);
+        footer.list(3, BINARY, 1);
+        footer.element_binary(name.as_bytes());
+        footer.i32(4, GZIP);
+        footer.i64(5, rows.len() as i64);
+        footer.i64(6, uncompressed);
+        footer.i64(7, compressed);
+        footer.i64(9, offset);
+        footer.end();
+        footer.end();
+    }
+    let total: i64 = chunks
+        .iter()
+        .map(|&(_, uncompressed, _)| uncompressed)
+        .sum();
+    footer.i64(2, total);
+    footer.i64(3, rows.len() as i64);
+    footer.end();
+    footer.binary(6, b"rust-signoz-agent");
+    footer.stop();
+
+    file.extend_from_slice(&footer.out);
+    file.extend_from_slice(&(footer.out.len() as u32).to_le_bytes());
+    file.extend_from_slice(MAGIC);
+    Ok(file)
+}
+
+/// Writes the Thrift compact protocol, in which the page headers and the footer are.
+struct Thrift {
+    out: Vec<u8>,
+    /// The id of the last field written, per struct being written.
+    last: Vec<i16>,
+}
+
+impl Default for Thrift {
+    fn default() -> Thrift {
+        Thrift {
+            out: Vec::new(),
+            last: vec![0],
+        }
+    }
+}
+
+impl Thrift {
+    fn field(&mut self, id: i16, kind: u8) {
+        let last = self.last.last_mut().unwrap();
+        match id - *last {
+            delta @ 1..=15 => self.out.push((delta as u8) << 4 | kind),
+            _ => {
+                self.out.push(kind);
+                varint(&mut self.out, zigzag(id as i64));
+            }
+        }
+        *last = id;
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, I32);
+        varint(&mut self.out, zigzag(value as i64));
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, I64);
+        varint(&mut self.out, zigzag(value));
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, BINARY);
+        self.element_binary(value);
+    }
+
+    fn list(&mut self, id: i16, kind: u8, len: usize) {
+        self.field(id, LIST);
+        if len < 15 {
+            self.out.push((len as u8) << 4 | kind);
+        } else {
+            self.out.push(0xf0 | kind);
+            varint(&mut self.out, len as u64);
+        }
+    }
+
+    /// A struct field; its fields follow, then `end`.
+    fn begin(&mut self, id: i16) {
+        self.field(id, STRUCT);
+        self.last.push(0);
+    }
+
+    /// A struct element of a list; its fields follow, then `end`.
+    fn element(&mut self) {
+        self.last.push(0);
+    }
+
+    fn element_i32(&mut self, value: i32) {
+        varint(&mut self.out, zigzag(value as i64));
+    }
+
+    fn element_binary(&mut self, value: &[u8]) {
+        varint(&mut self.out, value.len() as u64);
+        self.out.extend_from_slice(value);
+    }
+
+    fn end(&mut self) {
+        self.out.push(0);
+        self.last.pop();
+    }
+
+    /// Ends the outermost struct.
+    fn stop(&mut self) {
+        self.out.push(0);
+    }
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Uploads the files written below `dir`, removing each once S3 accepted it.
+fn upload_pending(
+    client: &reqwest::blocking::Client,
+    s3: &S3Config,
+    dir: &Path,
+    state: &AgentState,
+) {
+    let mut files = Vec::new();
+    collect_files(dir, &mut files);
+    files.sort();
+    for path in files {
+        let key = path.strip_prefix(dir).unwrap_or(&path);
+        let key = format!("{}{}", s3.prefix, key.to_string_lossy().replace('\\', "/"));
+        let uploaded = fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|body| s3::put_object(client, s3, &key, body, Utc::now()));
+        match uploaded {
+            Ok(()) => {
+                fs::remove_file(&path).ok();
+            }
+            Err(e) => {
+                state.record_error(format!(
+                    "Exporter parquet: upload of {} failed, trying again after the next batch: {}",
+                    key, e
+                ));
+                return;
+            }
+        }
+    }
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else if path.extension().is_some_and(|e| e == "parquet") {
+            files.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use ::parquet::basic::Type as PhysicalType;
+    use ::parquet::file::reader::{FileReader, SerializedFileReader};
+    use ::parquet::record::Field;
+
+    #[test]
+    fn records_become_parquet_files_by_hour() {
+        let dir = TempDir::new("parquet");
+        let config: Config = toml::from_str("endpoint = \"http://localhost:4318\"").unwrap();
+        let at = |time: &str| {
+            DateTime::parse_from_rfc3339(time)
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        let entry = |line: &str, time: &str, attributes: &[(&str, &str)]| Record {
+            attributes: attributes
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            service_name: Some("api".into()),
+            timestamp: Some(at(time)),
+            observed: at(time),
+            ..Record::new(line)
+        };
+        let (a, b, c) = (
+            entry(
+                "ERROR disk full",
+                "2024-03-05T14:10:00Z",
+                &[("log.file", "a")],
+            ),
+            entry("INFO ok", "2024-03-05T14:20:00Z", &[("http.status", "200")]),
+            entry("INFO later", "2024-03-05T15:00:00Z", &[]),
+        );
+        write_batch(&dir, &config, &[&a, &b, &c], at("2024-03-05T15:00:05Z")).unwrap();
+
+        let mut files = Vec::new();
+        collect_files(&dir, &mut files);
+        files.sort();
+        assert_eq!(files.len(), 2);
+        assert!(files[0].starts_with(dir.join("dt=2024-03-05/hour=14")));
+        let file = fs::read(&files[0]).unwrap();
+        assert!(file.starts_with(MAGIC) && file.ends_with(MAGIC));
+        let footer_len =
+            u32::from_le_bytes(file[file.len() - 8..file.len() - 4].try_into().unwrap());
+        let footer = &file[file.len() - 8 - footer_len as usize..file.len() - 8];
+        let has = |name: &str| footer.windows(name.len()).any(|w| w == name.as_bytes());
+        assert!(has("attributes.log.file") && has("attributes.http.status"));
+        assert!(has("resource.service.name") && !has("attributes.missing"));
+
+        // Read back with the parquet crate: the rows, the schema and the values.
+        let reader = SerializedFileReader::new(fs::File::open(&files[0]).unwrap()).unwrap();
+        let metadata = reader.metadata().file_metadata();
+        assert_eq!(metadata.num_rows(), 2);
+        let schema = metadata.schema_descr();
+        let column = |name: &str| {
+            let mut columns = (0..schema.num_columns()).map(|i| schema.column(i));
+            columns.find(|c| c.name() == name).unwrap()
+        };
+        assert_eq!(column("timestamp").physical_type(), PhysicalType::INT64);
+        assert_eq!(
+            column("severity_number").physical_type(),
+            PhysicalType::INT32
+        );
+        assert_eq!(column("body").physical_type(), PhysicalType::BYTE_ARRAY);
+        assert!(!column("body").self_type().is_optional());
+        assert!(column("attributes.log.file").self_type().is_optional());
+        let rows: Vec<BTreeMap<String, Field>> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| {
+                let row = row.unwrap();
+                let columns = row.get_column_iter();
+                columns.map(|(k, v)| (k.clone(), v.clone())).collect()
+            })
+            .collect();
+        let micros = |time| Field::TimestampMicros(at(time).timestamp_micros());
+        let text = |value: &str| Field::Str(value.to_string());
+        assert_eq!(rows[0]["timestamp"], micros("2024-03-05T14:10:00Z"));
+        assert_eq!(
+            rows[0]["observed_timestamp"],
+            micros("2024-03-05T14:10:00Z")
+        );
+        assert_eq!(rows[0]["severity_text"], text("ERROR"));
+        assert_eq!(rows[0]["severity_number"], Field::Int(17));
+        assert_eq!(rows[0]["body"], text("ERROR disk full"));
+        assert_eq!(rows[0]["trace_id"], Field::Null);
+        assert_eq!(rows[0]["attributes.log.file"], text("a"));
+        assert_eq!(rows[0]["attributes.http.status"], Field::Null);
+        assert_eq!(rows[1]["timestamp"], micros("2024-03-05T14:20:00Z"));
+        assert_eq!(rows[1]["severity_number"], Field::Int(12));
+        assert_eq!(rows[1]["body"], text("INFO ok"));
+        assert_eq!(rows[1]["attributes.log.file"], Field::Null);
+        assert_eq!(rows[1]["attributes.http.status"], text("200"));
+        assert_eq!(rows[1]["resource.service.name"], text("api"));
+    }
+}
@@ -0,0 +1,433 @@
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::checkpoint::{Checkpoint, Held};
+use crate::clock::Clock;
+use crate::stats::{AgentState, InputStats, Stage};
+use crate::tailer::Origins;
+use crate::{
+    console, enqueue_batch, filter, incident, input, kubernetes, log_format, multiline, quarantine,
+    queue, resync, severity, stitch, timestamp, Attributes, Body, Config, Record, Severity,
+};
+
+pub fn derive_path_attributes(
+    rules: &[(Regex, Option<String>)],
+    path: &str,
+) -> (Attributes, Option<String>) {
+    let mut attributes = Attributes::new();
+    let mut service_name = None;
+    for (regex, service_group) in rules {
+        let Some(caps) = regex.captures(path) else {
+            continue;
+        };
+        for name in regex.capture_names().flatten() {
+            if let Some(value) = caps.name(name) {
+                attributes.push(name.to_string(), value.as_str().to_string());
+                if service_group.as_deref() == Some(name) {
+                    service_name = Some(value.as_str().to_string());
+                }
+            }
+        }
+    }
+    (attributes, service_name)
+}
+
+/// The timestamp extractor configured for `input`, falling back to the "*" entry.
+pub fn timestamp_extractor(config: &Config, input: &str) -> Option<timestamp::TimestampExtractor> {
+    let ts = input::option_for(&config.timestamps, input).or_else(|| config.timestamps.get("*"))?;
+    timestamp::TimestampExtractor::new(ts).ok()
+}
+
+/// The multiline joiner configured for `input`, falling back to the "*" entry.
+pub fn multiline_joiner(config: &Config, input: &str) -> Option<multiline::Joiner> {
+    let ml = input::option_for(&config.multiline, input).or_else(|| config.multiline.get("*"))?;
+    multiline::Joiner::new(input, ml, &config.regex_limits).ok()
+}
+
+/// The `origin_id_attribute` of the line at `offset` of the file with `inode` on `host`:
+/// the first 16 bytes of the SHA-256 of `<host>:<inode>:<offset>`, in hex, which another
+/// shipper reading the same file can derive too.
+pub fn origin_id(host: &str, inode: u64, offset: u64) -> String {
+    Sha256::digest(format!("{}:{}:{}", host, inode, offset))[..16]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// How often a multiline record waiting for more lines is checked for its flush timeout.
+pub const MULTILINE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Builds the per-line handler shared by the file-backed inputs (tailed, snapshot and
+/// backfilled files).
+pub fn file_line_handler(
+    path: &str,
+    config: &Config,
+    path_rules: &[(Regex, Option<String>)],
+    tx: queue::Sender,
+    incidents: Option<incident::Capture>,
+    state: Arc<AgentState>,
+    stats: Arc<InputStats>,
+) -> impl Fn(Vec<Vec<u8>>, Option<Origins>) + Send + Sync + 'static {
+    let incidents = incidents.map(Mutex::new);
+    let file_id = path.to_string();
+    let (mut attributes, service_name) = derive_path_attributes(path_rules, path);
+    let source = config
+        .sources
+        .iter()
+        .find(|s| input::key_matches(&s.path, path));
+    let service_name: Option<Arc<str>> = source
+        .and_then(|s| s.service_name.clone())
+        .or(service_name)
+        .map(Into::into);
+    let kubernetes =
+        input::option_for(&config.kubernetes, &stats.name).or_else(|| config.kubernetes.get("*"));
+    let mut resource = Vec::new();
+    if let (Some(kubernetes), Some(file)) = (kubernetes, kubernetes::PodFile::parse(path)) {
+        resource = file.resource();
+        if kubernetes.labels {
+            match kubernetes::pod_labels(kubernetes, &file) {
+                Ok(labels) => resource.extend(labels),
+                Err(e) => state.record_error(format!(
+                    "Failed to get the labels of pod {}/{}: {e:#}",
+                    file.namespace, file.pod
+                )),
+            }
+        }
+    }
+    // A source's own resource attributes win over the pod's.
+    for (key, value) in source.iter().flat_map(|s| &s.resource_attributes) {
+        resource.retain(|(k, _)| k != key);
+        resource.push((key.clone(), value.clone()));
+    }
+    let resource: Option<Arc<[(String, String)]>> = (!resource.is_empty()).then(|| resource.into());
+    let reassembler = kubernetes.map(|_| Mutex::new(kubernetes::Reassembler::default()));
+    attributes.insert(0, "log.file", file_id.clone());
+    let sequence_attribute = config.sequence_attribute.clone();
+    let origin_attribute = config.origin_id_attribute.clone();
+    let host = config.host_name.clone().unwrap_or_default();
+    let keep_bytes = config
+        .bytes_body_files
+        .iter()
+        .any(|f| input::key_matches(f, path));
+    let timestamps = timestamp_extractor(config, &stats.name);
+    let skew = input::option_for(&config.clock_skew, &stats.name)
+        .or_else(|| config.clock_skew.get("*"))
+        .and_then(|s| timestamp::SkewCorrection::new(s).ok());
+    let otlp_path: Option<Arc<str>> =
+        input::option_for(&config.otlp_paths, &stats.name).map(|p| p.as_str().into());
+    let parser = input::option_for(&config.log_formats, &stats.name)
+        .or_else(|| config.log_formats.get("*"))
+        .and_then(|format| log_format::Parser::new(format, &config.regex_limits).ok())
+        .unwrap_or(log_format::Parser::Plain);
+    // Plain lines cannot fail to parse.
+    let quarantine = input::option_for(&config.parse_quarantine, &stats.name)
+        .or_else(|| config.parse_quarantine.get("*"))
+        .filter(|_| !matches!(parser, log_format::Parser::Plain))
+        .map(|q| Mutex::new(quarantine::Quarantine::new(q, stats.clone(), state.clone())));
+    let filter = input::option_for(&config.filters, &stats.name)
+        .or_else(|| config.filters.get("*"))
+        .and_then(|f| filter::LineFilter::new(&stats.name, f, &config.regex_limits).ok())
+        .map(|mut f| {
+            f.register(&state);
+            f
+        });
+    let stitcher = input::option_for(&config.stitch, &stats.name)
+        .or_else(|| config.stitch.get("*"))
+        .and_then(|s| stitch::Stitcher::new(&stats.name, s, &config.regex_limits).ok())
+        .map(|s| Arc::new(Mutex::new(s)));
+    let resyncer = input::option_for(&config.resync, &stats.name)
+        .or_else(|| config.resync.get("*"))
+        .and_then(|r| resync::Resyncer::new(&stats.name, r, &config.regex_limits).ok());
+    let resync_stats = stats.clone();
+    let severity_rules = input::option_for(&config.severity_rules, &stats.name)
+        .or_else(|| config.severity_rules.get("*"))
+        .and_then(|r| severity::SeverityRules::new(&stats.name, r, &config.regex_limits).ok());
+    let severity_keywords = config.severity_keywords.clone();
+    let console =
+        console::Console::new(&stats.name, &file_id, &config.console, &config.regex_limits);
+    let joiner = multiline_joiner(config, &stats.name).map(|j| Arc::new(Mutex::new(j)));
+    let joiner_state = state.clone();
+    let (joined, stitched) = (Arc::new(Held::default()), Arc::new(Held::default()));
+    let reading = stats.clone();
+
+    let emit = Arc::new(
+        move |lines: Vec<Vec<u8>>,
+              origins: Option<&Origins>,
+              checkpoint: Option<Arc<Checkpoint>>| {
+            let started = Instant::now();
+            let observed = Utc::now();
+            let mut entries = Vec::with_capacity(lines.len());
+            for (i, raw) in lines.into_iter().enumerate() {
+                // The read buffer becomes the line, unless its bytes are shipped as well.
+                let (mut line, raw) = if keep_bytes {
+                    (String::from_utf8_lossy(&raw).into_owned(), Some(raw))
+                } else {
+                    let line = String::from_utf8(raw)
+                        .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned());
+                    (line, None)
+                };
+                stats.record_line();
+                let mut runtime_line = None;
+                if let Some(ref reassembler) = reassembler {
+                    if let Some(decoded) =
+                        kubernetes::RuntimeLine::parse(line.trim_end_matches('\r'))
+                    {
+                        let Some(whole) = reassembler.lock().unwrap().push(decoded) else {
+                            continue;
+                        };
+                        line = whole.message.clone();
+                        runtime_line = Some(whole);
+                    }
+                }
+                line.truncate(line.trim_end().len());
+                if let Some(ref incidents) = incidents {
+                    incidents.lock().unwrap().observe(&line);
+                }
+                if filter.as_ref().is_some_and(|f| !f.keep(&line)) {
+                    state.record_drop("filtered");
+                    continue;
+                }
+                let mut attributes = attributes.clone();
+                if let Some(ref runtime_line) = runtime_line {
+                    attributes.push("log.iostream", runtime_line.stream.clone());
+                }
+                if let Some(ref key) = sequence_attribute {
+                    attributes.push(key.clone(), stats.next_sequence().to_string());
+                }
+                let offset = origins.and_then(|o| Some((o.inode, *o.offsets.get(i)?)));
+                if let (Some(ref key), Some((inode, offset))) = (&origin_attribute, offset) {
+                    attributes.push(key.clone(), origin_id(&host, inode, offset));
+                }
+                let parsed = match quarantine {
+                    Some(ref quarantine) => {
+                        let mut quarantine = quarantine.lock().unwrap();
+                        match quarantine.is_quarantined() {
+                            true => None,
+                            false => {
+                                let parsed = parser.parse(&line);
+                                quarantine.observe(parsed.is_some());
+                                parsed
+                            }
+                        }
+                    }
+                    None => parser.parse(&line),
+                };
+                let (body, severity, parsed_timestamp) = match parsed {
+                    Some(parsed) => {
+                        attributes.extend(parsed.attributes);
+                        (Some(parsed.body), parsed.severity, parsed.timestamp)
+                    }
+                    None => (None, None, None),
+                };
+                let severity = severity.or_else(|| {
+                    severity_rules
+                        .as_ref()
+                        .and_then(|rules| rules.detect(&line, &severity_keywords))
+                });
+                // A `[timestamps]` entry for the input wins over the parsed time field.
+                let timestamp = timestamps
+                    .as_ref()
+                    .and_then(|t| t.extract(&line, &attributes))
+                    .or(parsed_timestamp)
+                    .or(runtime_line.and_then(|l| l.timestamp))
+                    .map(|t| skew.as_ref().map_or(t, |s| s.apply(t, observed)));
+                if let Some(ref console) = console {
+                    let shown = severity
+                        .or_else(|| detect_severity(&line, &severity_keywords))
+                        .unwrap_or(Severity::INFO);
+                    console.print(&line, shown);
+                }
+                entries.push(Record {
+                    body: match raw {
+                        Some(raw) => Body::Bytes(raw),
+                        None => Body::Text(body.unwrap_or(line)),
+                    },
+                    attributes,
+                    default_severity: None,
+                    severity,
+                    service_name: service_name.clone(),
+                    timestamp,
+                    observed,
+                    otlp_path: otlp_path.clone(),
+                    trace: None,
+                    resource: resource.clone(),
+                    checkpoint: checkpoint.clone(),
+                });
+            }
+            state.record_stage(Stage::Parse, entries.len() as u64, started.elapsed());
+            enqueue_batch(&tx, &state, entries);
+        },
+    );
+
+    if let Some(ref joiner) = joiner {
+        let (joiner, emit, state) = (joiner.clone(), emit.clone(), joiner_state.clone());
+        let joined = joined.clone();
+        thread::spawn(move || loop {
+            thread::sleep(MULTILINE_CHECK_INTERVAL);
+            // Once the handler is gone no more lines come, so the last record is complete.
+            let done = Arc::strong_count(&joiner) == 1;
+            let record = {
+                let mut joiner = joiner.lock().unwrap();
+                if done || state.flushing.load(Ordering::SeqCst) {
+                    joiner.take()
+                } else {
+                    joiner.expired()
+                }
+            };
+            if let Some(record) = record {
+                emit(vec![record], None, joined.take());
+                // Counted as joining until enqueued, so a drain does not miss it.
+                state.joining.fetch_sub(1, Ordering::SeqCst);
+            }
+            if done {
+                break;
+            }
+        });
+    }
+
+    let stitch_state = joiner_state.clone();
+    // Joined records are made of several lines, so they get no origin.
+    let join = Arc::new(
+        move |lines: Vec<Vec<u8>>,
+              origins: Option<Origins>,
+              checkpoint: Option<Arc<Checkpoint>>| match joiner {
+            Some(ref joiner) => {
+                let (records, checkpoint) = {
+                    let mut joiner = joiner.lock().unwrap();
+                    let joining = joiner.is_joining();
+                    let records = joiner.push(lines);
+                    if !joining && joiner.is_joining() {
+                        joiner_state.joining.fetch_add(1, Ordering::SeqCst);
+                    }
+                    let progress = (joining, !records.is_empty(), joiner.is_joining());
+                    (records, joined.hand_on(checkpoint, progress))
+                };
+                if !records.is_empty() {
+                    emit(records, None, checkpoint);
+                }
+            }
+            None => emit(lines, origins.as_ref(), checkpoint),
+        },
+    );
+
+    // Stitched lines go on to the joiner, so its thread outlives this one.
+    if let Some(ref stitcher) = stitcher {
+        let (stitcher, join, state) = (stitcher.clone(), join.clone(), stitch_state.clone());
+        let stitched = stitched.clone();
+        thread::spawn(move || loop {
+            thread::sleep(MULTILINE_CHECK_INTERVAL);
+            let done = Arc::strong_count(&stitcher) == 1;
+            let line = {
+                let mut stitcher = stitcher.lock().unwrap();
+                if done || state.flushing.load(Ordering::SeqCst) {
+                    stitcher.take()
+                } else {
+                    stitcher.expired()
+                }
+            };
+            if let Some(line) = line {
+                join(vec![line], None, stitched.take());
+                state.joining.fetch_sub(1, Ordering::SeqCst);
+            }
+            if done {
+                break;
+            }
+        });
+    }
+
+    move |lines, origins| {
+        let checkpoint = reading.checkpoint();
+        // Resynced and stitched lines no longer match the lines read, nor their offsets.
+        let (lines, origins) = match resyncer {
+            Some(ref resyncer) => {
+                let resynced = resyncer.push(lines);
+                resync_stats
+                    .resyncs
+                    .fetch_add(resynced.resyncs, Ordering::Relaxed);
+                for _ in 0..resynced.dropped {
+                    stitch_state.record_drop("interleaved_fragment");
+                }
+                (resynced.lines, None)
+            }
+            None => (lines, origins),
+        };
+        let Some(ref stitcher) = stitcher else {
+            return join(lines, origins, checkpoint);
+        };
+        let (lines, stitching, now_stitching) = {
+            let mut stitcher = stitcher.lock().unwrap();
+            let stitching = stitcher.is_stitching();
+            let lines = stitcher.push(lines);
+            (lines, stitching, stitcher.is_stitching())
+        };
+        let checkpoint =
+            stitched.hand_on(checkpoint, (stitching, !lines.is_empty(), now_stitching));
+        if !stitching && now_stitching {
+            stitch_state.joining.fetch_add(1, Ordering::SeqCst);
+        }
+        if !lines.is_empty() {
+            join(lines, None, checkpoint);
+        }
+        // Counted as joining until handed on, so a drain does not miss it.
+        if stitching && !now_stitching {
+            stitch_state.joining.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+pub fn is_too_old(
+    clock: &dyn Clock,
+    timestamp: Option<DateTime<Utc>>,
+    max_age: Option<chrono::Duration>,
+) -> bool {
+    match (timestamp, max_age) {
+        (Some(timestamp), Some(max_age)) => clock.now() - timestamp > max_age,
+        _ => false,
+    }
+}
+
+/// The severity a record is sent with: its own, the one its line names, or the default
+/// of its input.
+pub fn severity_of(entry: &Record, config: &Config) -> Severity {
+    entry
+        .severity
+        .or_else(|| detect_severity(&entry.body.text(), &config.severity_keywords))
+        .or(entry.default_severity)
+        .unwrap_or(Severity::INFO)
+}
+
+/// The severity of the first level word in `line`: a built-in one such as `ERROR`, or one
+/// of `keywords`. Words joined by `@` or `.`, as in `info@example.com` or `error.log`, are
+/// not level words.
+pub fn detect_severity(line: &str, keywords: &BTreeMap<String, String>) -> Option<Severity> {
+    line.split(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '@' | '.')))
+        .map(|word| word.trim_matches(['@', '.']))
+        .filter(|word| !word.is_empty())
+        .find_map(|word| {
+            let word = word.to_uppercase();
+            severity_from_name(&word).or_else(|| {
+                keywords
+                    .iter()
+                    .find(|(keyword, _)| keyword.to_uppercase() == word)
+                    .and_then(|(_, sev)| severity_from_name(sev))
+            })
+        })
+}
+
+pub fn severity_from_name(name: &str) -> Option<Severity> {
+    match name.to_uppercase().as_str() {
+        "TRACE" => Some(Severity::TRACE),
+        "DEBUG" => Some(Severity::DEBUG),
+        "INFO" | "NOTICE" => Some(Severity::INFO),
+        "WARN" | "WARNING" => Some(Severity::WARN),
+        "ERROR" => Some(Severity::ERROR),
+        "CRITICAL" | "FATAL" => Some(Severity::FATAL),
+        _ => None,
+    }
+}
@@ -0,0 +1,52 @@
+use std::borrow::Cow;
+
+/// Whether file paths are compared the way Windows does: `\` and `/` alike, drive letters
+/// and names without regard to case.
+const WINDOWS: bool = cfg!(windows);
+
+/// The form of `path` that read positions and per-input options are keyed by: the path
+/// itself, or on Windows with forward slashes, in lowercase and without the `\\?\` prefix,
+/// so that `C:\Logs\App.log` and `c:/logs/app.log` are the same file.
+pub fn key(path: &str) -> Cow<'_, str> {
+    key_for(path, WINDOWS)
+}
+
+fn key_for(path: &str, windows: bool) -> Cow<'_, str> {
+    if !windows {
+        return Cow::Borrowed(path);
+    }
+    let path = match path.strip_prefix(r"\\?\UNC\") {
+        Some(share) => format!(r"\\{}", share),
+        None => path.strip_prefix(r"\\?\").unwrap_or(path).to_string(),
+    };
+    Cow::Owned(path.replace('\\', "/").to_lowercase())
+}
+
+/// How glob patterns over file paths match: without regard to case on Windows.
+pub fn match_options() -> glob::MatchOptions {
+    glob::MatchOptions {
+        case_sensitive: !WINDOWS,
+        ..glob::MatchOptions::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_paths_are_keyed_by_one_spelling() {
+        for path in [
+            r"C:\Logs\App.log",
+            "c:/logs/app.log",
+            r"\\?\C:\Logs\APP.LOG",
+        ] {
+            assert_eq!(key_for(path, true), "c:/logs/app.log");
+        }
+        assert_eq!(
+            key_for(r"\\?\UNC\FileServer\Share\app.log", true),
+            key_for(r"\\fileserver\share\App.log", true)
+        );
+        assert_eq!(key_for(r"/var/log/App\1.log", false), r"/var/log/App\1.log");
+    }
+}
@@ -0,0 +1,617 @@
+use anyhow::{Context, Result};
+use signal_hook::{consts::TERM_SIGNALS, iterator::Signals};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::checkpoint::{self, Checkpoint, Positions};
+use crate::doctor::{lookup_account, Account};
+use crate::failure::Failure;
+use crate::input::{self, FileInput, Input, InputContext, StopFlag};
+use crate::stats::AgentState;
+use crate::{statedir, Config};
+
+/// Which side of the `privsep_user` split this process is on.
+pub enum Role {
+    /// Still root: tails the files and streams their lines to the `agent` process, which
+    /// tells on `acks` which batches it is done with.
+    Reader {
+        agent: libc::pid_t,
+        pipe: File,
+        acks: File,
+    },
+    /// Running as the unprivileged user: everything else, with the tailed files' lines
+    /// read from `pipe`.
+    Agent { pipe: File, acks: File },
+}
+
+/// Forks into a privileged reader and the agent proper, which runs as `user`. The state
+/// directory and the disk buffer's, `dirs`, are handed to `user` so the agent can keep its
+/// admin socket and buffer there.
+///
+/// Like `daemon::daemonize`, this must be called before any threads are spawned.
+pub fn split(user: &str, dirs: &[&str]) -> Result<Role> {
+    if !statedir::is_root() {
+        return Err(
+            anyhow::anyhow!("privsep_user = {:?} needs the agent started as root", user)
+                .context(Failure::ConfigInvalid),
+        );
+    }
+    let account = lookup_account(user)
+        .ok_or_else(|| anyhow::anyhow!("privsep_user {:?} does not exist", user))
+        .context(Failure::ConfigInvalid)?;
+    hand_over(dirs, &account)?;
+
+    let (read, write) = pipe()?;
+    let (read_acks, write_acks) = pipe()?;
+
+    // SAFETY: no other threads exist yet, so forking cannot leave locks held in the child.
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()).context("fork failed"),
+        0 => {
+            drop((write, read_acks));
+            switch_to(user, &account)?;
+            println!(
+                "Running as {} (uid {}); files are read by pid {}",
+                user,
+                account.uid,
+                // SAFETY: getppid has no preconditions.
+                unsafe { libc::getppid() }
+            );
+            Ok(Role::Agent {
+                pipe: read,
+                acks: write_acks,
+            })
+        }
+        agent => {
+            drop((read, write_acks));
+            Ok(Role::Reader {
+                agent,
+                pipe: write,
+                acks: read_acks,
+            })
+        }
+    }
+}
+
+/// The read and the write end of a new pipe.
+fn pipe() -> Result<(File, File)> {
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has room for the two descriptors pipe2 returns, which the Files below
+    // take ownership of.
+    unsafe {
+        if libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) != 0 {
+            return Err(io::Error::last_os_error()).context("pipe2 failed");
+        }
+        Ok((File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])))
+    }
+}
+
+/// Hands each of `dirs` and everything below it to `account`: what root created there
+/// before the switch (the lock files, the admin socket, the disk buffer's directories)
+/// would otherwise be out of the user's reach. From the second start on the trees belong
+/// to the user, who could have left links in them, so they are walked through directory
+/// descriptors without following symlinks, and a file with other hard links is left as
+/// it is rather than handing over whatever else it is.
+fn hand_over(dirs: &[&str], account: &Account) -> Result<()> {
+    for dir in dirs {
+        let open = std::ffi::CString::new(*dir)
+            .map_err(io::Error::from)
+            .and_then(|path| {
+                // SAFETY: `path` is NUL-terminated and outlives the call.
+                check(unsafe {
+                    libc::open(
+                        path.as_ptr(),
+                        libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+                    )
+                })
+            });
+        match open {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            // SAFETY: the descriptor open returned is owned by the File alone.
+            opened => opened
+                .and_then(|fd| hand_over_tree(unsafe { File::from_raw_fd(fd) }, account))
+                .with_context(|| format!("Failed to hand {} to {}", dir, account.name))?,
+        }
+    }
+    Ok(())
+}
+
+fn hand_over_tree(dir: File, account: &Account) -> io::Result<()> {
+    let (uid, gid) = (account.uid, account.gid);
+    // SAFETY: `dir` is an open descriptor for the duration of the call.
+    check(unsafe { libc::fchown(dir.as_raw_fd(), uid, gid) })?;
+    for name in entries(&dir)? {
+        // SAFETY: `name` is NUL-terminated, `stat` is written by fstatat before it is read,
+        // and every descriptor openat returns is owned by a File alone.
+        unsafe {
+            let mut stat = std::mem::zeroed::<libc::stat>();
+            let at = |flags| check(libc::openat(dir.as_raw_fd(), name.as_ptr(), flags));
+            let nofollow = libc::AT_SYMLINK_NOFOLLOW;
+            check(libc::fstatat(
+                dir.as_raw_fd(),
+                name.as_ptr(),
+                &mut stat,
+                nofollow,
+            ))?;
+            match stat.st_mode & libc::S_IFMT {
+                libc::S_IFDIR => {
+                    let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW;
+                    hand_over_tree(File::from_raw_fd(at(flags | libc::O_CLOEXEC)?), account)?;
+                }
+                libc::S_IFREG => {
+                    // Opened and checked as one file, so it cannot be swapped in between.
+                    let flags = libc::O_RDONLY | libc::O_NOFOLLOW | libc::O_NONBLOCK;
+                    let file = File::from_raw_fd(at(flags | libc::O_CLOEXEC)?);
+                    if file.metadata()?.nlink() == 1 {
+                        check(libc::fchown(file.as_raw_fd(), uid, gid))?;
+                    }
+                }
+                libc::S_IFLNK => {}
+                // The admin socket, which cannot be opened.
+                _ if stat.st_nlink == 1 => {
+                    check(libc::fchownat(
+                        dir.as_raw_fd(),
+                        name.as_ptr(),
+                        uid,
+                        gid,
+                        nofollow,
+                    ))?;
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The names in `dir`, without `.` and `..`.
+fn entries(dir: &File) -> io::Result<Vec<std::ffi::CString>> {
+    // SAFETY: fdopendir takes over the duplicate descriptor, which closedir closes; each
+    // name is copied out before the next readdir.
+    unsafe {
+        let stream = libc::fdopendir(check(libc::fcntl(
+            dir.as_raw_fd(),
+            libc::F_DUPFD_CLOEXEC,
+            0,
+        ))?);
+        if stream.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let mut names = Vec::new();
+        loop {
+            let entry = libc::readdir(stream);
+            if entry.is_null() {
+                break;
+            }
+            let name = std::ffi::CStr::from_ptr((*entry).d_name.as_ptr());
+            if name.to_bytes() != b"." && name.to_bytes() != b".." {
+                names.push(name.to_owned());
+            }
+        }
+        libc::closedir(stream);
+        Ok(names)
+    }
+}
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        result => Ok(result),
+    }
+}
+
+/// Sets the groups, group and user of the process to those of `account`, and checks that
+/// root cannot be taken back.
+fn switch_to(user: &str, account: &Account) -> Result<()> {
+    // SAFETY: plain credential calls; the group list outlives setgroups. The libc
+    // wrappers apply them to every thread of the process.
+    unsafe {
+        if libc::setgroups(account.groups.len(), account.groups.as_ptr()) != 0
+            || libc::setgid(account.gid) != 0
+            || libc::setuid(account.uid) != 0
+        {
+            return Err(io::Error::last_os_error())
+                .with_context(|| format!("Failed to switch to user {}", user));
+        }
+        if libc::setuid(0) == 0 {
+            return Err(anyhow::anyhow!(
+                "Root privileges could be regained after switching to {}",
+                user
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// `run_as_user`: switches the whole process to `user`, and `group` instead of its primary
+/// group if given, keeping the files and sockets opened so far. The state directory is
+/// handed over first so the positions can still be saved.
+pub fn drop_root(user: &str, group: Option<&str>, dirs: &[&str]) -> Result<()> {
+    if !statedir::is_root() {
+        return Err(
+            anyhow::anyhow!("run_as_user = {:?} needs the agent started as root", user)
+                .context(Failure::ConfigInvalid),
+        );
+    }
+    let mut account = lookup_account(user)
+        .ok_or_else(|| anyhow::anyhow!("run_as_user {:?} does not exist", user))
+        .context(Failure::ConfigInvalid)?;
+    if let Some(group) = group {
+        account.gid = lookup_group(group)
+            .ok_or_else(|| anyhow::anyhow!("run_as_group {:?} does not exist", group))
+            .context(Failure::ConfigInvalid)?;
+        if !account.groups.contains(&account.gid) {
+            account.groups.push(account.gid);
+        }
+    }
+    hand_over(dirs, &account)?;
+    switch_to(user, &account)?;
+    println!(
+        "Running as {} (uid {}, gid {}) now that the inputs are open",
+        user, account.uid, account.gid
+    );
+    Ok(())
+}
+
+fn lookup_group(name: &str) -> Option<libc::gid_t> {
+    let c_name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: getgrnam returns a pointer into static storage or null; the gid is copied
+    // out before any other group call.
+    unsafe {
+        let gr = libc::getgrnam(c_name.as_ptr());
+        (!gr.is_null()).then(|| (*gr).gr_gid)
+    }
+}
+
+/// The reader process: tails the configured files into `pipe` until the agent exits,
+/// forwarding termination signals and SIGHUP to it, and saves the read positions of the
+/// batches the agent acknowledged on `acks`. Returns the exit code to leave with, the
+/// agent's own.
+pub fn run_reader(
+    config: Config,
+    positions: Arc<Positions>,
+    agent: libc::pid_t,
+    pipe: File,
+    acks: File,
+) -> Result<i32> {
+    let config = Arc::new(config);
+    let state = Arc::new(AgentState::default());
+    let inputs = input::file_inputs(&config)?;
+    // The checkpoints of the batches sent, until the agent is done with their records.
+    let unacked = Arc::new(Mutex::new(HashMap::<u64, Arc<Checkpoint>>::new()));
+    {
+        let unacked = unacked.clone();
+        thread::spawn(move || {
+            let mut acks = BufReader::new(acks);
+            let mut id = [0; 8];
+            // Ends with the agent; what it did not acknowledge is read again next time.
+            while acks.read_exact(&mut id).is_ok() {
+                unacked.lock().unwrap().remove(&u64::from_ne_bytes(id));
+            }
+        });
+    }
+    let start_tailer = {
+        let (config, positions, state) = (config.clone(), positions.clone(), state.clone());
+        let pipe = Arc::new(Mutex::new(BufWriter::new(pipe)));
+        let sequenced = config.sequence_attribute.is_some();
+        let next_id = Arc::new(AtomicU64::new(0));
+        move |input: &FileInput| {
+            let stats = state.register_input(&input.path);
+            let (path, pipe, counter) = (input.path.clone(), pipe.clone(), stats.clone());
+            let (unacked, next_id) = (unacked.clone(), next_id.clone());
+            input.tail(
+                &config,
+                positions.clone(),
+                state.clone(),
+                stats,
+                None,
+                move |lines, _| {
+                    // The sequence is kept here so it is saved with the position.
+                    let sequence = counter.sequence();
+                    if sequenced {
+                        counter.resume_sequence(sequence + lines.len() as u64);
+                    }
+                    let id = next_id.fetch_add(1, Ordering::SeqCst);
+                    if let Some(checkpoint) = counter.checkpoint() {
+                        unacked.lock().unwrap().insert(id, checkpoint);
+                    }
+                    let mut pipe = pipe.lock().unwrap();
+                    // A failed write means the agent is gone, which waitpid below notices.
+                    write_batch(&mut *pipe, &path, (sequence, id), &lines)
+                        .and_then(|_| pipe.flush())
+                        .ok();
+                },
+            );
+        }
+    };
+    for input in &inputs {
+        if input::is_pattern(&input.path) {
+            input.watch(
+                &config,
+                positions.clone(),
+                state.clone(),
+                start_tailer.clone(),
+            );
+        } else if input::is_other_shippers(&config.other_shipper_files, &input.path) {
+            println!("Leaving {} to another shipper", input.path);
+        } else {
+            start_tailer(input);
+        }
+    }
+    // The tailers and watchers hold the only other references to the pipe; the agent
+    // sees its end once they all stopped.
+    drop(start_tailer);
+    checkpoint::run(
+        positions.clone(),
+        Duration::from_secs(
+            config
+                .checkpoint_interval_secs
+                .unwrap_or(checkpoint::DEFAULT_INTERVAL_SECS)
+                .max(1),
+        ),
+        state.clone(),
+    );
+
+    let stops: Vec<StopFlag> = inputs.iter().map(|i| i.stop.clone()).collect();
+    let stop_all = move || stops.iter().for_each(StopFlag::stop);
+    let mut signals = Signals::new(TERM_SIGNALS.iter().chain(&[libc::SIGHUP]))?;
+    let stop_on_signal = stop_all.clone();
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            // Reading stops first so no line is read after the agent stopped taking them.
+            if signal != libc::SIGHUP {
+                stop_on_signal();
+            }
+            // SAFETY: kill has no memory-safety preconditions.
+            unsafe { libc::kill(agent, signal) };
+        }
+    });
+
+    let mut status = 0;
+    // SAFETY: `status` is a valid out-pointer for waitpid.
+    while unsafe { libc::waitpid(agent, &mut status, 0) } != agent {
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err).context("waitpid failed");
+        }
+    }
+    stop_all();
+    if let Err(e) = positions.save() {
+        eprintln!("Failed to save read positions: {e:#}");
+    }
+    Ok(if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else {
+        println!(
+            "The agent process was killed by signal {}",
+            libc::WTERMSIG(status)
+        );
+        128 + libc::WTERMSIG(status)
+    })
+}
+
+/// The agent side of the pipe: hands each batch from the reader to the line handler of
+/// its file, as if the file was tailed in this process, and acknowledges it on `acks`
+/// once its records are done with.
+#[derive(Debug)]
+pub struct PipeInput {
+    pipe: Option<File>,
+    acks: Arc<Mutex<File>>,
+    stop: StopFlag,
+}
+
+impl PipeInput {
+    pub fn new(pipe: File, acks: File) -> PipeInput {
+        PipeInput {
+            pipe: Some(pipe),
+            acks: Arc::new(Mutex::new(acks)),
+            stop: StopFlag::default(),
+        }
+    }
+}
+
+// Each pipe is an input of its own.
+impl PartialEq for PipeInput {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.acks, &other.acks)
+    }
+}
+
+impl Input for PipeInput {
+    fn describe(&self) -> String {
+        "privsep".to_string()
+    }
+
+    fn start(&mut self, ctx: &InputContext) -> Result<()> {
+        let pipe = self
+            .pipe
+            .take()
+            .context("The reader pipe was already taken")?;
+        let (ctx, stop, acks) = (ctx.clone(), self.stop.clone(), self.acks.clone());
+        thread::spawn(move || {
+            let mut pipe = BufReader::new(pipe);
+            // Files matching a pattern are found by the reader, so handlers are made as
+            // their lines come in.
+            let mut handlers = HashMap::new();
+            // Batches are read up to the end of the pipe, also after `stop`: the reader
+            // closes it once its tailers stopped.
+            loop {
+                match read_batch(&mut pipe) {
+                    Ok(Some(batch)) => {
+                        let (stats, handler) =
+                            handlers.entry(batch.path.clone()).or_insert_with(|| {
+                                let stats = ctx.state.register_input(&batch.path);
+                                (stats.clone(), ctx.file_line_handler(&batch.path, stats))
+                            });
+                        stats.resume_sequence(batch.sequence);
+                        let (acks, id) = (acks.clone(), batch.id);
+                        stats.set_checkpoint(Some(Checkpoint::on_done(move || {
+                            // Not heard by a reader that exited.
+                            acks.lock().unwrap().write_all(&id.to_ne_bytes()).ok();
+                        })));
+                        handler(batch.lines, None);
+                        stats.set_checkpoint(None);
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        ctx.state
+                            .record_error(format!("Failed to read from the reader: {e}"));
+                        break;
+                    }
+                }
+            }
+            // The reader also closes the pipe when it got a termination signal, which this
+            // process gets too; a shutdown in progress stops the input within a second.
+            thread::sleep(Duration::from_secs(2));
+            if !stop.is_stopped() {
+                eprintln!("The privileged reader exited, shutting down");
+                // SAFETY: raising a signal handled by the shutdown thread.
+                unsafe { libc::raise(libc::SIGTERM) };
+            }
+        });
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.stop.stop();
+    }
+}
+
+/// A batch on the pipe: the path, the sequence of its first line, the id it is
+/// acknowledged with, the number of lines and each line, lengths and counts as
+/// native-endian u32.
+fn write_batch(
+    out: &mut impl Write,
+    path: &str,
+    (sequence, id): (u64, u64),
+    lines: &[Vec<u8>],
+) -> io::Result<()> {
+    out.write_all(&(path.len() as u32).to_ne_bytes())?;
+    out.write_all(path.as_bytes())?;
+    out.write_all(&sequence.to_ne_bytes())?;
+    out.write_all(&id.to_ne_bytes())?;
+    out.write_all(&(lines.len() as u32).to_ne_bytes())?;
+    for line in lines {
+        out.write_all(&(line.len() as u32).to_ne_bytes())?;
+        out.write_all(line)?;
+    }
+    Ok(())
+}
+
+/// A batch of lines as read from the pipe.
+struct Batch {
+    path: String,
+    sequence: u64,
+    id: u64,
+    lines: Vec<Vec<u8>>,
+}
+
+/// The next batch, or `None` at the end of the pipe.
+fn read_batch(input: &mut impl Read) -> io::Result<Option<Batch>> {
+    let mut len = [0; 4];
+    match input.read_exact(&mut len) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        other => other?,
+    }
+    let path = String::from_utf8(read_bytes(input, u32::from_ne_bytes(len))?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let (mut sequence, mut id) = ([0; 8], [0; 8]);
+    input.read_exact(&mut sequence)?;
+    input.read_exact(&mut id)?;
+    input.read_exact(&mut len)?;
+    let lines = (0..u32::from_ne_bytes(len))
+        .map(|_| {
+            let mut len = [0; 4];
+            input.read_exact(&mut len)?;
+            read_bytes(input, u32::from_ne_bytes(len))
+        })
+        .collect::<io::Result<_>>()?;
+    Ok(Some(Batch {
+        path,
+        sequence: u64::from_ne_bytes(sequence),
+        id: u64::from_ne_bytes(id),
+        lines,
+    }))
+}
+
+fn read_bytes(input: &mut impl Read, len: u32) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0; len as usize];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn handing_over_follows_no_links_out_of_the_tree() {
+        let Some(nobody) = lookup_account("nobody").filter(|_| statedir::is_root()) else {
+            return;
+        };
+        let dir = crate::testutil::TempDir::new("hand-over");
+        let (tree, outside) = (dir.join("state"), dir.join("shadow"));
+        fs::create_dir_all(tree.join("buffer")).unwrap();
+        fs::write(tree.join("buffer").join("1.json"), "[]").unwrap();
+        fs::write(&outside, "root:x:0:0\n").unwrap();
+        std::os::unix::fs::symlink(&outside, tree.join("linked")).unwrap();
+        std::os::unix::fs::symlink(dir.as_ref(), tree.join("escape")).unwrap();
+        fs::hard_link(&outside, tree.join("buffer").join("hard")).unwrap();
+
+        hand_over(&[tree.to_str().unwrap()], &nobody).unwrap();
+        let owner = |path: &std::path::Path| fs::symlink_metadata(path).unwrap().uid();
+        assert_eq!(owner(&tree), nobody.uid);
+        assert_eq!(owner(&tree.join("buffer").join("1.json")), nobody.uid);
+        assert_eq!(owner(&outside), 0);
+        assert_eq!(owner(&tree.join("linked")), 0);
+        assert_eq!(owner(dir.as_ref()), 0);
+    }
+
+    #[test]
+    fn batches_cross_the_pipe_and_are_acknowledged_back() {
+        let (read, write) = pipe().unwrap();
+        let (read_acks, write_acks) = pipe().unwrap();
+        let mut out = BufWriter::new(write);
+        let lines = vec![
+            b"one".to_vec(),
+            Vec::new(),
+            "z\u{fc}rich".as_bytes().to_vec(),
+        ];
+        write_batch(&mut out, "/var/log/app.log", (41, 7), &lines).unwrap();
+        write_batch(&mut out, "/var/log/db.log", (0, 8), &[]).unwrap();
+        drop(out);
+
+        let mut pipe = BufReader::new(read);
+        let batch = read_batch(&mut pipe).unwrap().unwrap();
+        assert_eq!(
+            (batch.path.as_str(), batch.sequence, batch.id),
+            ("/var/log/app.log", 41, 7)
+        );
+        assert_eq!(batch.lines, lines);
+        let batch = read_batch(&mut pipe).unwrap().unwrap();
+        assert_eq!((batch.path.as_str(), batch.id), ("/var/log/db.log", 8));
+        assert!(batch.lines.is_empty());
+        // The reader closed its end: the agent's input ends.
+        assert!(read_batch(&mut pipe).unwrap().is_none());
+
+        // The agent acknowledges a batch once the last of its records is dropped.
+        let acks = Arc::new(Mutex::new(write_acks));
+        let checkpoint = Checkpoint::on_done(move || {
+            acks.lock().unwrap().write_all(&7u64.to_ne_bytes()).unwrap();
+        });
+        let (record, mut sent) = (checkpoint.clone(), Vec::new());
+        drop(checkpoint);
+        drop(record);
+        BufReader::new(read_acks).read_to_end(&mut sent).unwrap();
+        let id: [u8; 8] = sent.try_into().unwrap();
+        assert_eq!(u64::from_ne_bytes(id), 7);
+    }
+}
@@ -0,0 +1,64 @@
+use crate::Record;
+
+/// Attribute naming the steps that altered a record, as in "redaction,truncation".
+pub const MUTATIONS_ATTRIBUTE: &str = "log.mutations";
+
+/// Adds `step` to the `log.mutations` attribute of `record`, telling whoever reads it that
+/// the record is not the line as written. The built-in steps do this with `mark_mutations`;
+/// a processor that alters records can call it too.
+pub fn mark_mutation(record: &mut Record, step: &str) {
+    match record
+        .attributes
+        .iter_mut()
+        .find(|(key, _)| key == MUTATIONS_ATTRIBUTE)
+    {
+        Some((_, steps)) => {
+            if !steps.split(',').any(|s| s == step) {
+                steps.push(',');
+                steps.push_str(step);
+            }
+        }
+        None => record
+            .attributes
+            .push(MUTATIONS_ATTRIBUTE.to_string(), step.to_string()),
+    }
+}
+
+/// A custom step applied to every record after the built-in filters and before export.
+///
+/// Returning `None` drops the record (counted as a `processor` drop); returning a record,
+/// possibly modified, passes it on to the next processor.
+pub trait Processor: Send {
+    fn process(&mut self, record: Record) -> Option<Record>;
+}
+
+impl<F> Processor for F
+where
+    F: FnMut(Record) -> Option<Record> + Send,
+{
+    fn process(&mut self, record: Record) -> Option<Record> {
+        self(record)
+    }
+}
+
+/// Registered processors, applied in registration order.
+#[derive(Default)]
+pub struct Chain {
+    processors: Vec<Box<dyn Processor>>,
+}
+
+impl Chain {
+    pub fn push(&mut self, processor: Box<dyn Processor>) {
+        self.processors.push(processor);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processors.is_empty()
+    }
+
+    pub fn process(&mut self, record: Record) -> Option<Record> {
+        self.processors
+            .iter_mut()
+            .try_fold(record, |record, processor| processor.process(record))
+    }
+}
@@ -0,0 +1,15 @@
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use prost::Message;
+use serde::Serialize;
+
+/// The protobuf message of `payload`, an OTLP/JSON logs request as the agent builds it.
+pub fn request(payload: &impl Serialize) -> Result<ExportLogsServiceRequest, String> {
+    serde_json::to_value(payload)
+        .and_then(serde_json::from_value)
+        .map_err(|e| format!("Failed to encode payload: {e}"))
+}
+
+/// `payload` in the protobuf wire format, for OTLP/HTTP bodies.
+pub fn encode(payload: &impl Serialize) -> Result<Vec<u8>, String> {
+    Ok(request(payload)?.encode_to_vec())
+}
@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::stats::{AgentState, InputStats};
+
+/// When an input's `log_formats` entry stopped matching what its source writes, e.g.
+/// after an upgrade changed the format upstream, and what the input does then.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QuarantineConfig {
+    /// Share of the lines of a window, in percent, that may fail to parse.
+    #[serde(default = "default_max_failure_percent")]
+    pub max_failure_percent: f64,
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+    /// Windows with fewer lines are not judged.
+    #[serde(default = "default_min_lines")]
+    pub min_lines: u64,
+    #[serde(default)]
+    pub action: QuarantineAction,
+}
+
+fn default_max_failure_percent() -> f64 {
+    50.0
+}
+
+fn default_window_secs() -> u64 {
+    60
+}
+
+fn default_min_lines() -> u64 {
+    100
+}
+
+/// What a quarantined input does until it is started again.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum QuarantineAction {
+    /// Ship every line as it is, without trying to parse it.
+    #[default]
+    ShipRaw,
+    /// Stop reading the file, which keeps its lines for when the format is fixed.
+    Pause,
+}
+
+impl QuarantineConfig {
+    pub fn validate(&self, input: &str) -> anyhow::Result<()> {
+        if !(self.max_failure_percent > 0.0 && self.max_failure_percent <= 100.0) {
+            return Err(anyhow::anyhow!(
+                "parse_quarantine {} max_failure_percent must be above 0 and at most 100",
+                input
+            ));
+        }
+        if self.window_secs == 0 || self.min_lines == 0 {
+            return Err(anyhow::anyhow!(
+                "parse_quarantine {} window_secs and min_lines must be greater than 0",
+                input
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Counts the parse failures of one input over its windows.
+pub struct Quarantine {
+    config: QuarantineConfig,
+    stats: Arc<InputStats>,
+    state: Arc<AgentState>,
+    window_start: Instant,
+    lines: u64,
+    failures: u64,
+}
+
+impl Quarantine {
+    pub fn new(
+        config: &QuarantineConfig,
+        stats: Arc<InputStats>,
+        state: Arc<AgentState>,
+    ) -> Quarantine {
+        // Started again, the input gets another chance.
+        stats.quarantined.store(false, Ordering::Relaxed);
+        stats.paused.store(false, Ordering::Relaxed);
+        Quarantine {
+            config: config.clone(),
+            stats,
+            state,
+            window_start: Instant::now(),
+            lines: 0,
+            failures: 0,
+        }
+    }
+
+    /// Whether lines are shipped without being parsed.
+    pub fn is_quarantined(&self) -> bool {
+        self.stats.quarantined.load(Ordering::Relaxed)
+    }
+
+    /// Counts a line parsed or not, and quarantines the input once too many were not.
+    pub fn observe(&mut self, parsed: bool) {
+        if self.is_quarantined() {
+            return;
+        }
+        if self.window_start.elapsed() >= Duration::from_secs(self.config.window_secs) {
+            self.window_start = Instant::now();
+            self.lines = 0;
+            self.failures = 0;
+        }
+        self.lines += 1;
+        if !parsed {
+            self.failures += 1;
+            self.stats.parse_failures.fetch_add(1, Ordering::Relaxed);
+        }
+        let percent = self.failures as f64 * 100.0 / self.lines as f64;
+        if self.lines < self.config.min_lines || percent < self.config.max_failure_percent {
+            return;
+        }
+        self.stats.quarantined.store(true, Ordering::Relaxed);
+        let action = match self.config.action {
+            QuarantineAction::ShipRaw => "shipping its lines unparsed",
+            QuarantineAction::Pause => {
+                self.stats.paused.store(true, Ordering::Relaxed);
+                "pausing it"
+            }
+        };
+        self.state.record_error(format!(
+            "Input {} failed to parse {:.0}% of its last {} lines; {} until it is restarted",
+            self.stats.name, percent, self.lines, action
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_is_quarantined_once_most_lines_fail_to_parse() {
+        let state = Arc::new(AgentState::default());
+        let stats = state.register_input("/var/log/app.json");
+        let config: QuarantineConfig =
+            toml::from_str("min_lines = 10\naction = \"pause\"").unwrap();
+        config.validate("*").unwrap();
+        let mut quarantine = Quarantine::new(&config, stats.clone(), state.clone());
+
+        // Every other line fails, but the window needs 10 lines to be judged.
+        for i in 0..9 {
+            quarantine.observe(i % 2 == 1);
+        }
+        assert!(!quarantine.is_quarantined());
+        quarantine.observe(true);
+        assert!(quarantine.is_quarantined());
+        assert!(stats.paused.load(Ordering::Relaxed));
+        assert_eq!(stats.parse_failures.load(Ordering::Relaxed), 5);
+
+        // Lines are no longer counted, until the input starts again.
+        quarantine.observe(false);
+        assert_eq!(stats.parse_failures.load(Ordering::Relaxed), 5);
+        let restarted = Quarantine::new(&config, stats.clone(), state);
+        assert!(!restarted.is_quarantined() && !stats.paused.load(Ordering::Relaxed));
+
+        let broken: QuarantineConfig = toml::from_str("max_failure_percent = 0").unwrap();
+        assert!(broken.validate("*").is_err());
+    }
+}
@@ -0,0 +1,527 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::Ordering;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::delivery::{Listeners, Outcome};
+use crate::replay::Pacer;
+use crate::stats::AgentState;
+use crate::trace_context::TraceContext;
+use crate::Record;
+
+/// Records a partition may hand on per turn for each unit of its weight.
+const QUANTUM: usize = 64;
+
+/// How often a paced sender checks for shutdown while it waits.
+const PACE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Default `queue_capacity`.
+pub const DEFAULT_CAPACITY: usize = 100_000;
+
+/// What happens to records queued while the queue holds `queue_capacity` of them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Overflow {
+    /// The input waits for room, reading no further meanwhile. An exporter's queue
+    /// never waits, and drops the new records.
+    #[default]
+    Block,
+    /// The oldest records of the input with the longest backlog make room.
+    DropOldest,
+    /// The records that do not fit are dropped.
+    DropNewest,
+}
+
+/// The queue between the inputs and the dispatcher, with one partition per input. The
+/// partitions holding records are drained in turn (deficit round-robin), each turn
+/// handing on `weight * QUANTUM` records, so a noisy input cannot hold the quiet
+/// ones back behind its backlog. It holds at most `capacity` records, beyond which
+/// `overflow` applies.
+pub struct FairQueue {
+    /// Per input name, its share of the dispatcher relative to the default weight of 1.
+    weights: BTreeMap<String, u32>,
+    capacity: usize,
+    overflow: Overflow,
+    state: Arc<AgentState>,
+    /// Told about the records dropped for room.
+    listeners: Listeners,
+    partitions: Mutex<Partitions>,
+    ready: Condvar,
+    room: Condvar,
+}
+
+#[derive(Default)]
+struct Partitions {
+    by_name: BTreeMap<Arc<str>, Partition>,
+    /// Partitions with queued batches, in turn order; the front one is being drained.
+    active: VecDeque<Arc<str>>,
+    /// Records in all partitions.
+    len: usize,
+    /// The queue filled up and has not drained to half its capacity since.
+    full: bool,
+}
+
+struct Partition {
+    batches: VecDeque<Vec<Record>>,
+    weight: usize,
+    /// Records the partition may still hand on before its turn ends.
+    deficit: usize,
+}
+
+impl Partitions {
+    /// Takes up to `count` records off the front of the partitions with the longest
+    /// backlog.
+    fn drop_oldest(&mut self, mut count: usize) -> Vec<Record> {
+        let mut dropped = Vec::new();
+        while count > 0 {
+            let Some((name, partition)) = self
+                .by_name
+                .iter_mut()
+                .filter(|(_, p)| !p.batches.is_empty())
+                .max_by_key(|(_, p)| p.batches.iter().map(Vec::len).sum::<usize>())
+            else {
+                break;
+            };
+            let front = partition.batches.front_mut().expect("non-empty partition");
+            let taken = count.min(front.len());
+            dropped.extend(front.drain(..taken));
+            if front.is_empty() {
+                partition.batches.pop_front();
+            }
+            if partition.batches.is_empty() {
+                partition.deficit = 0;
+                let name = name.clone();
+                self.active.retain(|n| *n != name);
+            }
+            count -= taken;
+            self.len -= taken;
+        }
+        dropped
+    }
+}
+
+impl FairQueue {
+    pub fn new(
+        weights: BTreeMap<String, u32>,
+        capacity: usize,
+        overflow: Overflow,
+        state: Arc<AgentState>,
+        listeners: Listeners,
+    ) -> Arc<FairQueue> {
+        Arc::new(FairQueue {
+            weights,
+            capacity: capacity.max(1),
+            overflow,
+            state,
+            listeners,
+            partitions: Mutex::new(Partitions::default()),
+            ready: Condvar::new(),
+            room: Condvar::new(),
+        })
+    }
+
+    /// A sender for the partition of input `name`.
+    pub fn sender(self: &Arc<Self>, name: &str) -> Sender {
+        Sender {
+            queue: self.clone(),
+            partition: name.into(),
+            pacer: None,
+            trace: None,
+        }
+    }
+
+    fn push(&self, partition: &Arc<str>, mut batch: Vec<Record>) {
+        let mut partitions = self.partitions.lock().unwrap();
+        let mut dropped = Vec::new();
+        if partitions.len + batch.len() > self.capacity {
+            if !partitions.full {
+                partitions.full = true;
+                self.state.record_error(format!(
+                    "Queue is full at {} records; {} until the exporters catch up",
+                    self.capacity,
+                    match self.overflow {
+                        Overflow::Block => "inputs wait",
+                        Overflow::DropOldest => "dropping the oldest records",
+                        Overflow::DropNewest => "dropping new records",
+                    }
+                ));
+            }
+            match self.overflow {
+                // A batch larger than the whole queue waits for it to empty.
+                Overflow::Block => {
+                    while partitions.len > 0 && partitions.len + batch.len() > self.capacity {
+                        partitions = self.room.wait(partitions).unwrap();
+                    }
+                }
+                Overflow::DropOldest => {
+                    if batch.len() > self.capacity {
+                        dropped.extend(batch.drain(..batch.len() - self.capacity));
+                    }
+                    let excess = (partitions.len + batch.len()).saturating_sub(self.capacity);
+                    dropped.extend(partitions.drop_oldest(excess));
+                }
+                Overflow::DropNewest => {
+                    let room = self.capacity.saturating_sub(partitions.len);
+                    dropped = batch.split_off(room.min(batch.len()));
+                }
+            }
+        }
+        if !batch.is_empty() {
+            partitions.len += batch.len();
+            self.enqueue(&mut partitions, partition, batch);
+        }
+        drop(partitions);
+        for record in dropped {
+            self.state.queued.fetch_sub(1, Ordering::SeqCst);
+            self.state.record_drop("queue_full");
+            self.listeners
+                .notify(&record, &Outcome::Dropped("queue_full"));
+        }
+    }
+
+    fn enqueue(&self, partitions: &mut Partitions, partition: &Arc<str>, batch: Vec<Record>) {
+        let entry = partitions
+            .by_name
+            .entry(partition.clone())
+            .or_insert_with(|| Partition {
+                batches: VecDeque::new(),
+                weight: crate::input::option_for(&self.weights, partition)
+                    .copied()
+                    .unwrap_or(1)
+                    .max(1) as usize,
+                deficit: 0,
+            });
+        let was_idle = entry.batches.is_empty();
+        entry.batches.push_back(batch);
+        if was_idle {
+            partitions.active.push_back(partition.clone());
+            self.ready.notify_one();
+        }
+    }
+
+    /// The next batch in weighted turn order and the input it is from, waiting for one to
+    /// be queued.
+    pub fn recv(&self) -> (Arc<str>, Vec<Record>) {
+        let mut partitions = self.partitions.lock().unwrap();
+        loop {
+            let Some(name) = partitions.active.front().cloned() else {
+                partitions = self.ready.wait(partitions).unwrap();
+                continue;
+            };
+            let partition = partitions.by_name.get_mut(&name).expect("active partition");
+            if partition.deficit == 0 {
+                // The turn is over: credit the next one and move on.
+                partition.deficit = partition.weight * QUANTUM;
+                partitions.active.rotate_left(1);
+                continue;
+            }
+            let front = partition.batches.front_mut().expect("active partition");
+            // Large batches are handed on across turns rather than all at once.
+            let batch = if front.len() > partition.deficit {
+                let rest = front.split_off(partition.deficit);
+                std::mem::replace(front, rest)
+            } else {
+                partition.batches.pop_front().unwrap_or_default()
+            };
+            partition.deficit -= batch.len();
+            if partition.batches.is_empty() {
+                // An idle partition does not save up turns.
+                partition.deficit = 0;
+                partitions.active.pop_front();
+            }
+            partitions.len -= batch.len();
+            if partitions.full && partitions.len <= self.capacity / 2 {
+                partitions.full = false;
+                println!("Queue drained to {} records", partitions.len);
+            }
+            self.room.notify_all();
+            return (name, batch);
+        }
+    }
+}
+
+/// Queues the batches of one input into its partition.
+#[derive(Clone)]
+pub struct Sender {
+    queue: Arc<FairQueue>,
+    partition: Arc<str>,
+    pacer: Option<Arc<Mutex<Pacer>>>,
+    /// Set on the records sent that belong to no trace of their own.
+    trace: Option<Arc<TraceContext>>,
+}
+
+impl Sender {
+    /// Holds the records sent back to the pace of their event times, `speed` times as
+    /// fast, until the agent shuts down.
+    pub fn paced(mut self, speed: f64) -> Sender {
+        self.pacer = Some(Arc::new(Mutex::new(Pacer::new(speed))));
+        self
+    }
+
+    /// Puts the records sent without a trace into `trace`.
+    pub fn traced(mut self, trace: Option<Arc<TraceContext>>) -> Sender {
+        self.trace = trace;
+        self
+    }
+
+    pub fn send(&self, mut batch: Vec<Record>) {
+        if let Some(ref trace) = self.trace {
+            for record in batch.iter_mut().filter(|r| r.trace.is_none()) {
+                record.trace = Some(trace.clone());
+            }
+        }
+        let Some(ref pacer) = self.pacer else {
+            self.queue.push(&self.partition, batch);
+            return;
+        };
+        // What is due goes out before each wait.
+        let mut due = Vec::with_capacity(batch.len());
+        for record in batch {
+            let delay = pacer
+                .lock()
+                .unwrap()
+                .delay(record.timestamp, Instant::now());
+            if !delay.is_zero() {
+                if !due.is_empty() {
+                    self.queue.push(&self.partition, std::mem::take(&mut due));
+                }
+                self.wait(delay);
+            }
+            due.push(record);
+        }
+        if !due.is_empty() {
+            self.queue.push(&self.partition, due);
+        }
+    }
+
+    /// Sleeps for `delay`, or until the agent shuts down, after which nothing is paced.
+    fn wait(&self, delay: Duration) {
+        let until = Instant::now() + delay;
+        while !self.queue.state.stopping.load(Ordering::SeqCst) {
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return;
+            }
+            thread::sleep(left.min(PACE_CHECK_INTERVAL));
+        }
+    }
+}
+
+/// The queue of an exporter: a lane per input, taken from in turn, `weight` records at
+/// a time as `queue_weights` has it, so a batch holds the records of every input with
+/// some waiting instead of the backlog of a noisy one ahead of the others. Holds at most
+/// `capacity` items, beyond which `push` drops one per `overflow` instead of waiting.
+pub struct Lanes<T> {
+    weights: BTreeMap<String, u32>,
+    capacity: usize,
+    overflow: Overflow,
+    queued: Mutex<Queued<T>>,
+    ready: Condvar,
+}
+
+struct Queued<T> {
+    lanes: BTreeMap<Arc<str>, VecDeque<T>>,
+    /// Lanes with items, in turn order; the front one is being taken from.
+    active: VecDeque<Arc<str>>,
+    /// Items the front lane may still hand on before its turn ends.
+    credit: usize,
+    len: usize,
+    closed: bool,
+}
+
+impl<T> Queued<T> {
+    /// Takes the oldest item of the lane with the most.
+    fn drop_oldest(&mut self) -> Option<T> {
+        let (lane, items) = self.lanes.iter_mut().max_by_key(|(_, items)| items.len())?;
+        let item = items.pop_front();
+        if items.is_empty() {
+            let lane = lane.clone();
+            if self.active.front() == Some(&lane) {
+                self.credit = 0;
+            }
+            self.lanes.remove(&lane);
+            self.active.retain(|l| *l != lane);
+        }
+        self.len -= 1;
+        item
+    }
+}
+
+impl<T> Lanes<T> {
+    pub fn new(weights: BTreeMap<String, u32>, capacity: usize, overflow: Overflow) -> Lanes<T> {
+        Lanes {
+            weights,
+            capacity: capacity.max(1),
+            overflow,
+            queued: Mutex::new(Queued {
+                lanes: BTreeMap::new(),
+                active: VecDeque::new(),
+                credit: 0,
+                len: 0,
+                closed: false,
+            }),
+            ready: Condvar::new(),
+        }
+    }
+
+    /// Queues `item` in the lane of input `lane`, never waiting: a full queue drops the
+    /// oldest item of its longest lane with `DropOldest`, and `item` itself otherwise, and
+    /// hands back the one dropped. `item` is handed back as an error once the queue is
+    /// closed.
+    pub fn push(&self, lane: &Arc<str>, item: T) -> Result<Option<T>, T> {
+        let mut queued = self.queued.lock().unwrap();
+        if queued.closed {
+            return Err(item);
+        }
+        let mut dropped = None;
+        if queued.len >= self.capacity {
+            match self.overflow {
+                Overflow::DropOldest => dropped = queued.drop_oldest(),
+                Overflow::Block | Overflow::DropNewest => return Ok(Some(item)),
+            }
+        }
+        let items = queued.lanes.entry(lane.clone()).or_default();
+        items.push_back(item);
+        if items.len() == 1 {
+            queued.active.push_back(lane.clone());
+        }
+        queued.len += 1;
+        self.ready.notify_one();
+        Ok(dropped)
+    }
+
+    /// Takes no more items; those queued are still handed on.
+    pub fn close(&self) {
+        self.queued.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+
+    /// The next item in turn, waiting for one; an error once the queue is closed and
+    /// empty.
+    pub fn recv(&self) -> Result<T, mpsc::RecvError> {
+        self.take(None).map_err(|_| mpsc::RecvError)
+    }
+
+    /// `recv`, waiting no longer than `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, mpsc::RecvTimeoutError> {
+        self.take(Some(Instant::now() + timeout))
+    }
+
+    fn take(&self, deadline: Option<Instant>) -> Result<T, mpsc::RecvTimeoutError> {
+        let mut queued = self.queued.lock().unwrap();
+        loop {
+            if let Some(lane) = queued.active.front().cloned() {
+                if queued.credit == 0 {
+                    queued.credit = crate::input::option_for(&self.weights, &lane)
+                        .copied()
+                        .unwrap_or(1)
+                        .max(1) as usize;
+                }
+                let items = queued.lanes.get_mut(&lane).expect("active lane");
+                let item = items.pop_front().expect("active lane");
+                queued.credit -= 1;
+                if queued.lanes[&lane].is_empty() {
+                    // An emptied lane does not save up the rest of its turn.
+                    queued.lanes.remove(&lane);
+                    queued.active.pop_front();
+                    queued.credit = 0;
+                } else if queued.credit == 0 {
+                    queued.active.rotate_left(1);
+                }
+                queued.len -= 1;
+                return Ok(item);
+            }
+            if queued.closed {
+                return Err(mpsc::RecvTimeoutError::Disconnected);
+            }
+            let Some(deadline) = deadline else {
+                queued = self.ready.wait(queued).unwrap();
+                continue;
+            };
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(mpsc::RecvTimeoutError::Timeout);
+            }
+            queued = self.ready.wait_timeout(queued, left).unwrap().0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Body;
+
+    fn batch(input: &str, count: usize) -> Vec<Record> {
+        (0..count)
+            .map(|i| Record {
+                body: Body::Text(format!("{} {}", input, i)),
+                ..Record::new("")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_full_queue_drops_per_its_overflow_policy() {
+        let lines = |(_, batch): (Arc<str>, Vec<Record>)| -> Vec<String> {
+            batch
+                .into_iter()
+                .map(|r| r.body.text().into_owned())
+                .collect()
+        };
+        for (overflow, kept) in [
+            (
+                Overflow::DropNewest,
+                vec!["app 0", "app 1", "app 2", "web 0"],
+            ),
+            (
+                Overflow::DropOldest,
+                vec!["app 2", "web 0", "web 1", "web 2"],
+            ),
+        ] {
+            let state = Arc::new(AgentState::default());
+            let dropped = Arc::new(Mutex::new(Vec::new()));
+            let mut listeners = Listeners::default();
+            let seen = dropped.clone();
+            listeners.push(Arc::new(move |record: &Record, outcome: &Outcome| {
+                assert!(matches!(outcome, Outcome::Dropped("queue_full")));
+                seen.lock().unwrap().push(record.body.text().into_owned());
+            }));
+            let queue = FairQueue::new(BTreeMap::new(), 4, overflow, state.clone(), listeners);
+            state.queued.store(6, Ordering::SeqCst);
+            queue.sender("app").send(batch("app", 3));
+            queue.sender("web").send(batch("web", 3));
+
+            let mut queued = lines(queue.recv());
+            queued.extend(lines(queue.recv()));
+            queued.sort();
+            assert_eq!(queued, kept);
+            assert_eq!(dropped.lock().unwrap().len(), 2);
+            assert_eq!(state.queued.load(Ordering::SeqCst), 4);
+            assert_eq!(state.snapshot().dropped["queue_full"], 2);
+        }
+    }
+
+    #[test]
+    fn a_full_exporter_queue_drops_instead_of_waiting() {
+        let (app, web): (Arc<str>, Arc<str>) = ("app".into(), "web".into());
+        for (overflow, dropped, kept) in [
+            (Overflow::Block, [3, 4], [1, 10, 2]),
+            (Overflow::DropNewest, [3, 4], [1, 10, 2]),
+            (Overflow::DropOldest, [1, 2], [3, 10, 4]),
+        ] {
+            let lanes = Lanes::new(BTreeMap::new(), 3, overflow);
+            assert_eq!(lanes.push(&app, 1), Ok(None));
+            assert_eq!(lanes.push(&app, 2), Ok(None));
+            assert_eq!(lanes.push(&web, 10), Ok(None));
+            assert_eq!(lanes.push(&app, 3), Ok(Some(dropped[0])));
+            assert_eq!(lanes.push(&app, 4), Ok(Some(dropped[1])));
+            let taken: Vec<i32> = (0..3).map(|_| lanes.recv().unwrap()).collect();
+            assert_eq!(taken, kept, "{:?}", overflow);
+            lanes.close();
+            assert_eq!(lanes.push(&app, 5), Err(5));
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use chrono::{DateTime, Utc};
+use std::borrow::Cow;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use crate::{checkpoint, trace_context};
+
+/// A record as it goes through the pipeline, from an input to the exporters. It knows
+/// nothing of the wire formats: OTLP (`build_otlp_payload`), ClickHouse rows and Parquet
+/// columns are made from it only when a batch is sent.
+#[derive(Clone)]
+pub struct Record {
+    pub body: Body,
+    pub attributes: Attributes,
+    /// Severity used when the body itself carries no recognizable level.
+    pub default_severity: Option<Severity>,
+    /// Severity set by a processor (such as a `[[severity_maps]]` entry), taking
+    /// precedence over the level detected in the body.
+    pub severity: Option<Severity>,
+    /// Overrides the configured `service_name` for this record; shared by the records of
+    /// an input.
+    pub service_name: Option<Arc<str>>,
+    /// Event time taken from the record itself; `observed` is used when absent.
+    pub timestamp: Option<DateTime<Utc>>,
+    /// When the agent read the record, shipped as `observedTimeUnixNano`.
+    pub observed: DateTime<Utc>,
+    /// Replaces the path of the exporter endpoints for this record.
+    pub otlp_path: Option<Arc<str>>,
+    /// Trace the record belongs to; an attribute named by `attribute_hints` overrides it.
+    pub trace: Option<Arc<trace_context::TraceContext>>,
+    /// Resource attributes of the record's source, such as its container; shared by the
+    /// records of an input.
+    pub resource: Option<Arc<[(String, String)]>>,
+    /// Position of the file the record was read from, taken once it and the records
+    /// read before it are done with.
+    pub checkpoint: Option<Arc<checkpoint::Checkpoint>>,
+}
+
+impl Record {
+    /// A record of `body` read now, with nothing else set.
+    pub fn new(body: impl Into<Body>) -> Record {
+        Record {
+            body: body.into(),
+            attributes: Attributes::new(),
+            default_severity: None,
+            severity: None,
+            service_name: None,
+            timestamp: None,
+            observed: Utc::now(),
+            otlp_path: None,
+            trace: None,
+            resource: None,
+            checkpoint: None,
+        }
+    }
+}
+
+/// What a record says: text, as read from nearly every source, or bytes kept as they
+/// were.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Body {
+    Text(String),
+    /// Shipped as a `bytesValue` body.
+    Bytes(Vec<u8>),
+}
+
+impl Body {
+    /// The body as text, what processors match against; bytes that are not UTF-8 are
+    /// replaced.
+    pub fn text(&self) -> Cow<'_, str> {
+        match self {
+            Body::Text(text) => Cow::Borrowed(text),
+            Body::Bytes(bytes) => String::from_utf8_lossy(bytes),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Body::Text(text) => text.as_bytes(),
+            Body::Bytes(bytes) => bytes,
+        }
+    }
+
+    /// The length of the body in bytes.
+    pub fn len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for Body {
+    fn default() -> Body {
+        Body::Text(String::new())
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Body {
+        Body::Text(text)
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Body {
+        Body::Text(text.to_string())
+    }
+}
+
+/// A level on the OTLP scale: the `severityText` a record is shipped with and its
+/// `severityNumber`, say `WARN` and 13.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Severity {
+    pub text: &'static str,
+    pub number: u8,
+}
+
+impl Severity {
+    pub const TRACE: Severity = Severity::new("TRACE", 4);
+    pub const DEBUG: Severity = Severity::new("DEBUG", 8);
+    pub const INFO: Severity = Severity::new("INFO", 12);
+    pub const WARN: Severity = Severity::new("WARN", 13);
+    pub const ERROR: Severity = Severity::new("ERROR", 17);
+    pub const FATAL: Severity = Severity::new("FATAL", 21);
+
+    pub const fn new(text: &'static str, number: u8) -> Severity {
+        Severity { text, number }
+    }
+}
+
+/// The attributes of a record, in the order they were added. Several can have the same
+/// key, as after a parser and a processor each set it; the first one is the one looked
+/// up.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Attributes(Vec<(String, String)>);
+
+impl Attributes {
+    pub fn new() -> Attributes {
+        Attributes(Vec::new())
+    }
+
+    /// The value of the first attribute called `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn push(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.0.push((key.into(), value.into()));
+    }
+
+    pub fn insert(&mut self, index: usize, key: impl Into<String>, value: impl Into<String>) {
+        self.0.insert(index, (key.into(), value.into()));
+    }
+
+    /// Replaces the value of the first attribute called `key`, or adds one.
+    pub fn set(&mut self, key: &str, value: impl Into<String>) {
+        match self.0.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.into(),
+            None => self.push(key, value),
+        }
+    }
+
+    /// Removes every attribute called `key`, returning the value of the first.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let index = self.0.iter().position(|(k, _)| k == key)?;
+        let (_, value) = self.0.remove(index);
+        self.0.retain(|(k, _)| k != key);
+        Some(value)
+    }
+
+    pub fn retain(&mut self, keep: impl FnMut(&(String, String)) -> bool) {
+        self.0.retain(keep);
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.0.truncate(len);
+    }
+}
+
+impl Deref for Attributes {
+    type Target = [(String, String)];
+
+    fn deref(&self) -> &[(String, String)] {
+        &self.0
+    }
+}
+
+impl DerefMut for Attributes {
+    fn deref_mut(&mut self) -> &mut [(String, String)] {
+        &mut self.0
+    }
+}
+
+impl From<Vec<(String, String)>> for Attributes {
+    fn from(attributes: Vec<(String, String)>) -> Attributes {
+        Attributes(attributes)
+    }
+}
+
+impl FromIterator<(String, String)> for Attributes {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Attributes {
+        Attributes(iter.into_iter().collect())
+    }
+}
+
+impl Extend<(String, String)> for Attributes {
+    fn extend<I: IntoIterator<Item = (String, String)>>(&mut self, iter: I) {
+        self.0.extend(iter);
+    }
+}
+
+impl IntoIterator for Attributes {
+    type Item = (String, String);
+    type IntoIter = std::vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Attributes {
+    type Item = &'a (String, String);
+    type IntoIter = std::slice::Iter<'a, (String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut Attributes {
+    type Item = &'a mut (String, String);
+    type IntoIter = std::slice::IterMut<'a, (String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attributes_are_looked_up_set_and_removed_by_key() {
+        let mut attributes = Attributes::new();
+        attributes.push("user", "alice");
+        attributes.push("user", "bob");
+        attributes.insert(0, "log.file", "/var/log/app.log");
+        assert_eq!(attributes.get("user"), Some("alice"));
+        assert_eq!(attributes.get("missing"), None);
+
+        attributes.set("user", "carol");
+        attributes.set("level", "info");
+        assert_eq!(
+            attributes
+                .iter()
+                .map(|(k, _)| k.as_str())
+                .collect::<Vec<_>>(),
+            ["log.file", "user", "user", "level"]
+        );
+        assert_eq!(attributes.remove("user").as_deref(), Some("carol"));
+        assert_eq!(attributes.get("user"), None);
+        assert_eq!(attributes.len(), 2);
+
+        let bytes = Body::Bytes(b"caf\xc3\xa9 \xff".to_vec());
+        assert_eq!(bytes.text(), "café \u{fffd}");
+        assert_eq!(bytes.len(), 7);
+        assert!(matches!(Body::from("ok").text(), Cow::Borrowed("ok")));
+    }
+}
@@ -0,0 +1,194 @@
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+use crate::processor::{self, Processor};
+use crate::rules::{self, RegexLimits};
+use crate::{Body, Record};
+
+/// Sensitive data masked in record bodies and attribute values before they are shipped.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RedactionConfig {
+    /// Built-in detectors to run; all of them by default.
+    #[serde(default = "default_detectors")]
+    pub detectors: Vec<Detector>,
+    /// Own patterns, applied after the detectors in order.
+    #[serde(default)]
+    pub rules: Vec<RedactionRule>,
+}
+
+fn default_detectors() -> Vec<Detector> {
+    vec![Detector::Email, Detector::CreditCard, Detector::BearerToken]
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Detector {
+    /// `user@example.com`.
+    Email,
+    /// 13 to 19 digits, optionally grouped by spaces or dashes, passing the Luhn check.
+    CreditCard,
+    /// The token of `Bearer <token>`, as in `Authorization` headers.
+    BearerToken,
+}
+
+impl Detector {
+    /// The pattern and what a match is replaced with.
+    fn rule(self) -> (&'static str, &'static str) {
+        match self {
+            Detector::Email => (
+                r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}",
+                "[REDACTED:email]",
+            ),
+            Detector::CreditCard => (r"\b\d(?:[ -]?\d){12,18}\b", "[REDACTED:credit_card]"),
+            Detector::BearerToken => (
+                r"(?i)(\bbearer\s+)[A-Za-z0-9\-._~+/]+=*",
+                "${1}[REDACTED:bearer_token]",
+            ),
+        }
+    }
+}
+
+/// A `pattern` whose matches become `replacement`, which can refer to the pattern's
+/// groups as `$1` or `${name}`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RedactionRule {
+    pub pattern: String,
+    #[serde(default = "default_replacement")]
+    pub replacement: String,
+}
+
+fn default_replacement() -> String {
+    "[REDACTED]".to_string()
+}
+
+struct Step {
+    regex: Regex,
+    replacement: String,
+    /// Only matches passing the Luhn check are replaced, so that other long numbers,
+    /// such as timestamps, are left alone.
+    luhn: bool,
+}
+
+/// Runs the configured redactions on every record, after the other processors.
+pub struct Redactor {
+    steps: Vec<Step>,
+    /// Mark the records it masked something in, with `mark_mutations`.
+    mark: bool,
+}
+
+impl Redactor {
+    pub fn new(config: &RedactionConfig, limits: &RegexLimits) -> anyhow::Result<Redactor> {
+        let mut steps = Vec::new();
+        for detector in &config.detectors {
+            let (pattern, replacement) = detector.rule();
+            steps.push(Step {
+                regex: rules::compile(&format!("redaction {:?}", detector), pattern, limits)?,
+                replacement: replacement.to_string(),
+                luhn: *detector == Detector::CreditCard,
+            });
+        }
+        for (i, rule) in config.rules.iter().enumerate() {
+            steps.push(Step {
+                regex: rules::compile(&format!("redaction.rules[{}]", i), &rule.pattern, limits)?,
+                replacement: rule.replacement.clone(),
+                luhn: false,
+            });
+        }
+        Ok(Redactor { steps, mark: false })
+    }
+
+    pub fn marking(mut self, mark: bool) -> Redactor {
+        self.mark = mark;
+        self
+    }
+
+    fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for step in &self.steps {
+            let replaced = step.regex.replace_all(&text, |caps: &Captures| {
+                if step.luhn && !passes_luhn(&caps[0]) {
+                    return caps[0].to_string();
+                }
+                let mut replacement = String::new();
+                caps.expand(&step.replacement, &mut replacement);
+                replacement
+            });
+            if let Cow::Owned(replaced) = replaced {
+                text = Cow::Owned(replaced);
+            }
+        }
+        text
+    }
+}
+
+impl Processor for Redactor {
+    fn process(&mut self, mut record: Record) -> Option<Record> {
+        let mut masked = false;
+        let redacted = match self.redact(&record.body.text()) {
+            Cow::Owned(text) => Some(text),
+            Cow::Borrowed(_) => None,
+        };
+        if let Some(text) = redacted {
+            record.body = match record.body {
+                Body::Text(_) => Body::Text(text),
+                Body::Bytes(_) => Body::Bytes(text.into_bytes()),
+            };
+            masked = true;
+        }
+        for (_, value) in &mut record.attributes {
+            if let Cow::Owned(redacted) = self.redact(value) {
+                *value = redacted;
+                masked = true;
+            }
+        }
+        if masked && self.mark {
+            processor::mark_mutation(&mut record, "redaction");
+        }
+        Some(record)
+    }
+}
+
+fn passes_luhn(number: &str) -> bool {
+    let digits: Vec<u32> = number.chars().filter_map(|c| c.to_digit(10)).collect();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| match (i % 2, d * 2) {
+            (0, _) => d,
+            (_, doubled) if doubled > 9 => doubled - 9,
+            (_, doubled) => doubled,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detectors_and_rules_mask_matches() {
+        let redactor = Redactor::new(
+            &RedactionConfig {
+                detectors: default_detectors(),
+                rules: vec![RedactionRule {
+                    pattern: r"(password=)\S+".to_string(),
+                    replacement: "${1}***".to_string(),
+                }],
+            },
+            &RegexLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            redactor.redact(
+                "user jane.doe@example.com paid with 4111 1111 1111 1111 at 1709633472000123, \
+                 Authorization: Bearer eyJhbGciOi.J9.abc password=hunter2"
+            ),
+            "user [REDACTED:email] paid with [REDACTED:credit_card] at 1709633472000123, \
+             Authorization: Bearer [REDACTED:bearer_token] password=***"
+        );
+        assert!(matches!(redactor.redact("nothing here"), Cow::Borrowed(_)));
+    }
+}
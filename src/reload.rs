@@ -0,0 +1,230 @@
+#[cfg(unix)]
+use signal_hook::{consts::SIGHUP, iterator::Signals};
+use std::collections::BTreeSet;
+#[cfg(unix)]
+use std::fs;
+use std::sync::atomic::Ordering;
+use std::sync::{mpsc, Arc};
+#[cfg(unix)]
+use std::thread;
+
+use chrono::Utc;
+
+use crate::cli::Overrides;
+use crate::remote::Remote;
+use crate::stats::{AgentState, ErrorEntry};
+use crate::{prepare_config, Config};
+
+/// Reads `config_path`, applies the command line `overrides` and validates it the way
+/// startup does, compiling every regex and
+/// building every input without starting anything. A config that fails is never partly
+/// used: the error is recorded in `state` and `None` returned, leaving the running
+/// config in effect.
+#[cfg(unix)]
+pub fn load_candidate(
+    config_path: &str,
+    overrides: &Overrides,
+    state: &AgentState,
+) -> Option<Config> {
+    let contents = fs::read_to_string(config_path).map_err(anyhow::Error::from);
+    check_candidate(config_path, contents, overrides, state)
+}
+
+/// Checks the `contents` of a config read from `source`, like `load_candidate`.
+pub fn check_candidate(
+    source: &str,
+    contents: anyhow::Result<String>,
+    overrides: &Overrides,
+    state: &AgentState,
+) -> Option<Config> {
+    let result = contents
+        .and_then(|contents| Ok(toml::from_str::<Config>(&contents)?))
+        .and_then(|mut config| {
+            overrides.apply(&mut config);
+            prepare_config(config)
+        });
+    match result {
+        Ok(config) => {
+            state.config_reloads.fetch_add(1, Ordering::SeqCst);
+            Some(config)
+        }
+        Err(e) => {
+            state.config_reload_failures.fetch_add(1, Ordering::SeqCst);
+            let message = format!("Rejected {}, keeping the running config: {:#}", source, e);
+            *state.last_config_error.lock().unwrap() = Some(ErrorEntry {
+                time_unix: Utc::now().timestamp(),
+                message: message.clone(),
+            });
+            state.record_error(message);
+            None
+        }
+    }
+}
+
+/// Validates the config file again on every SIGHUP, and with `remote` each config fetched
+/// that changed; configs that pass are handed to the returned receiver, to be applied by
+/// the main thread. Windows has no SIGHUP, so there only `remote` reloads.
+#[cfg_attr(windows, allow(unused_variables))]
+pub fn watch(
+    config_path: String,
+    overrides: Overrides,
+    remote: Option<Remote>,
+    state: Arc<AgentState>,
+) -> anyhow::Result<mpsc::Receiver<Config>> {
+    let (tx, rx) = mpsc::channel();
+    if let Some(remote) = remote {
+        remote.poll(overrides.clone(), state.clone(), tx.clone());
+    }
+    #[cfg(unix)]
+    {
+        let mut signals = Signals::new([SIGHUP])?;
+        thread::spawn(move || {
+            for _ in signals.forever() {
+                if let Some(config) = load_candidate(&config_path, &overrides, &state) {
+                    println!("Reloading {}", config_path);
+                    if tx.send(config).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+    }
+    Ok(rx)
+}
+
+/// Keys whose entries are inputs, compared input by input.
+const INPUT_KEYS: &[&str] = &[
+    "log_files",
+    "mmap_files",
+    "snapshots",
+    "backfills",
+    "commands",
+    "inputs",
+    "statsd",
+    "lifecycle_events",
+    "idle_close_secs",
+    "replay_speed",
+];
+/// Keys only the sender thread reads.
+const SENDER_KEYS: &[&str] = &[
+    "rate_limit",
+    "max_record_age",
+    "sampling",
+    "severity_schedules",
+    "delivery_policy",
+    "environments",
+    "environment_attribute",
+];
+/// Keys only the exporters read.
+const EXPORTER_KEYS: &[&str] = &[
+    "endpoint",
+    "endpoint_vars",
+    "protocol",
+    "auth",
+    "ingestion_key",
+    "headers",
+    "resource_attributes",
+    "exporters",
+    "compression",
+    "encoding",
+    "batch_size",
+    "flush_interval_ms",
+    "max_in_flight_requests",
+    "user_agent",
+    "success_log_every",
+    "disk_buffer",
+    "fault_injection",
+    "adaptive_rate",
+    "local_archive",
+    "clickhouse",
+    "parquet",
+    "otlp_path",
+    "endpoint_path",
+    "batch_id_attribute",
+    "tls",
+];
+/// Keys read once at startup, which a reload cannot change.
+const RESTART_KEYS: &[&str] = &[
+    "admin_socket",
+    "state_dir",
+    "pid_file",
+    "agent_log_file",
+    "read_only_root",
+    "privsep_user",
+    "run_as_user",
+    "run_as_group",
+    "service_user",
+    "service_unit",
+    "self_telemetry",
+    "http_status",
+    "metrics",
+    "handshake",
+    "audit",
+    "cloud_metadata",
+    "state_s3",
+    "control_characters",
+    "timestamp_check",
+    "computed_attributes",
+    "exceptions",
+    "severity_maps",
+    "mark_mutations",
+    "redaction",
+    "queue_weights",
+    "queue_capacity",
+    "queue_overflow",
+    "cert_warning_days",
+    "checkpoint_interval_secs",
+    "shutdown_timeout_secs",
+    "shutdown_step_timeouts",
+    "volume_alerts",
+    "severity_rollup",
+    "throttle_rules",
+    "metadata_only",
+    "catch_up",
+    "max_open_files",
+    "replay_rate_fraction",
+];
+
+/// What differs between the running config and a reloaded one. Keys not listed above,
+/// such as `timestamps` or `attribute_hints`, shape both the records inputs make and the
+/// payloads exporters build from them.
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// Every input is started again, not only the added and changed ones.
+    pub inputs: bool,
+    pub sender: bool,
+    pub exporters: bool,
+    /// Changed keys that only apply after a restart.
+    pub ignored: Vec<String>,
+}
+
+pub fn changes(old: &Config, new: &Config) -> Changes {
+    let (Ok(serde_json::Value::Object(old)), Ok(serde_json::Value::Object(new))) =
+        (serde_json::to_value(old), serde_json::to_value(new))
+    else {
+        return Changes {
+            inputs: true,
+            sender: true,
+            exporters: true,
+            ignored: Vec::new(),
+        };
+    };
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    let mut changes = Changes::default();
+    for key in keys.into_iter().filter(|k| old.get(*k) != new.get(*k)) {
+        let key = key.as_str();
+        if INPUT_KEYS.contains(&key) {
+            continue;
+        } else if SENDER_KEYS.contains(&key) {
+            changes.sender = true;
+        } else if EXPORTER_KEYS.contains(&key) {
+            changes.exporters = true;
+        } else if RESTART_KEYS.contains(&key) {
+            changes.ignored.push(key.to_string());
+        } else {
+            changes.inputs = true;
+            changes.exporters = true;
+        }
+    }
+    changes
+}
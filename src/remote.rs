@@ -0,0 +1,204 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use crate::cli::Overrides;
+use crate::stats::AgentState;
+use crate::{prepare_config, reload, template, Config};
+
+/// How long a fetch of the config may take.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// Larger answers are refused, as no config is that long.
+const MAX_CONFIG_BYTES: u64 = 4 << 20;
+
+/// `--config-url`: the config is fetched from a URL, e.g. a service handing each agent of
+/// a fleet its own by `{hostname}`, and kept in the config file, which the agent starts
+/// from when the URL cannot be reached.
+pub struct Remote {
+    url: String,
+    interval: Duration,
+    cache: String,
+}
+
+impl Remote {
+    /// The config at `url`, with `{hostname}` and other variables filled in, fetched
+    /// every `interval_secs` and cached in `cache`.
+    pub fn new(url: &str, interval_secs: u64, cache: &str) -> Result<Remote> {
+        if interval_secs == 0 {
+            return Err(anyhow::anyhow!(
+                "--config-url-interval must be greater than 0"
+            ));
+        }
+        let url = template::expand(url, |name| match name {
+            "hostname" => hostname::get()
+                .ok()
+                .map(|h| h.to_string_lossy().trim().to_string())
+                .filter(|h| !h.is_empty()),
+            _ => template::variable(name, &BTreeMap::new()),
+        })?;
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(anyhow::anyhow!(
+                "--config-url {} is not an http(s) URL",
+                url
+            ));
+        }
+        Ok(Remote {
+            url,
+            interval: Duration::from_secs(interval_secs),
+            cache: cache.to_string(),
+        })
+    }
+
+    fn fetch(&self) -> Result<String> {
+        // Built for each fetch: its runtime thread would not survive the fork of --daemon.
+        let client = reqwest::blocking::Client::builder()
+            .user_agent(crate::exporter::default_user_agent())
+            .timeout(FETCH_TIMEOUT)
+            .build()?;
+        let response = client
+            .get(&self.url)
+            .send()
+            .with_context(|| format!("Failed to fetch {}", self.url))?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "{} answered {}",
+                self.url,
+                response.status()
+            ));
+        }
+        let mut text = String::new();
+        response
+            .take(MAX_CONFIG_BYTES + 1)
+            .read_to_string(&mut text)
+            .with_context(|| format!("Failed to read the config from {}", self.url))?;
+        if text.len() as u64 > MAX_CONFIG_BYTES {
+            return Err(anyhow::anyhow!(
+                "{} answered more than {} bytes",
+                self.url,
+                MAX_CONFIG_BYTES
+            ));
+        }
+        Ok(text)
+    }
+
+    fn cached(&self) -> Option<String> {
+        fs::read_to_string(&self.cache).ok()
+    }
+
+    /// Replaces the cached config through a temporary file, so a crash mid-write leaves
+    /// the previous one in place.
+    fn store(&self, text: &str) -> Result<()> {
+        if self.cached().as_deref() == Some(text) {
+            return Ok(());
+        }
+        if let Some(dir) = Path::new(&self.cache).parent() {
+            fs::create_dir_all(dir).ok();
+        }
+        let tmp = format!("{}.tmp", self.cache);
+        fs::write(&tmp, text).with_context(|| format!("Failed to write {}", tmp))?;
+        fs::rename(&tmp, &self.cache).with_context(|| format!("Failed to replace {}", self.cache))
+    }
+
+    /// Before the config is loaded: caches the fetched config if it passes the checks of
+    /// startup, and otherwise leaves the cached one to start from, if there is one.
+    pub fn fetch_at_startup(&self, overrides: &Overrides) -> Result<()> {
+        let fetched = self.fetch().and_then(|text| {
+            let mut config: Config = toml::from_str(&text)
+                .with_context(|| format!("The config from {} is invalid", self.url))?;
+            overrides.apply(&mut config);
+            prepare_config(config)
+                .with_context(|| format!("The config from {} is invalid", self.url))?;
+            Ok(text)
+        });
+        match fetched {
+            Ok(text) => {
+                self.store(&text)?;
+                println!("Fetched the config from {}", self.url);
+                Ok(())
+            }
+            Err(e) if Path::new(&self.cache).exists() => {
+                eprintln!("Warning: {:#}; starting from the cached {}", e, self.cache);
+                Ok(())
+            }
+            Err(e) => Err(e.context(format!("No config is cached at {} yet", self.cache))),
+        }
+    }
+
+    /// Fetches the config every `interval`; when it changed and passes the checks of a
+    /// reload, it is cached and handed to `tx` to be applied.
+    pub fn poll(self, overrides: Overrides, state: Arc<AgentState>, tx: mpsc::Sender<Config>) {
+        thread::spawn(move || {
+            // Reported once each, not on every fetch until they are fixed.
+            let mut unreachable = false;
+            let mut rejected: Option<String> = None;
+            loop {
+                thread::sleep(self.interval);
+                let text = match self.fetch() {
+                    Ok(text) => text,
+                    Err(e) => {
+                        if !unreachable {
+                            state.record_error(format!("{:#}; keeping the running config", e));
+                        }
+                        unreachable = true;
+                        continue;
+                    }
+                };
+                unreachable = false;
+                if self.cached().as_deref() == Some(text.as_str())
+                    || rejected.as_deref() == Some(text.as_str())
+                {
+                    continue;
+                }
+                let Some(config) =
+                    reload::check_candidate(&self.url, Ok(text.clone()), &overrides, &state)
+                else {
+                    rejected = Some(text);
+                    continue;
+                };
+                rejected = None;
+                if let Err(e) = self.store(&text) {
+                    state.record_error(format!("{:#}", e));
+                }
+                println!("Reloading the config from {}", self.url);
+                if tx.send(config).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn url_names_the_host_and_config_is_cached() {
+        let host = hostname::get()
+            .unwrap()
+            .to_string_lossy()
+            .trim()
+            .to_string();
+        let dir = TempDir::new("remote-test");
+        let cache = dir.join("config.toml").display().to_string();
+        let remote =
+            Remote::new("https://config.internal/agents/{hostname}.toml", 60, &cache).unwrap();
+        assert_eq!(
+            remote.url,
+            format!("https://config.internal/agents/{}.toml", host)
+        );
+
+        remote.store("endpoint = \"a\"\n").unwrap();
+        remote.store("endpoint = \"b\"\n").unwrap();
+        assert_eq!(remote.cached().as_deref(), Some("endpoint = \"b\"\n"));
+
+        assert!(Remote::new("https://config.internal/a.toml", 0, &cache).is_err());
+        assert!(Remote::new("file:///etc/config.toml", 60, &cache).is_err());
+    }
+}
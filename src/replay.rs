@@ -0,0 +1,130 @@
+use chrono::{DateTime, Utc};
+use governor::clock::{Clock, DefaultClock};
+use governor::state::{InMemoryState, NotKeyed};
+use governor::{NegativeMultiDecision, Quota, RateLimiter};
+use std::num::NonZeroU32;
+use std::time::{Duration, Instant};
+
+use crate::input::StopFlag;
+use crate::stats::AgentState;
+use crate::Config;
+
+pub type Limiter = RateLimiter<NotKeyed, InMemoryState, DefaultClock>;
+
+/// Lines per second the backlog of all inputs is read at together: `rate_limit` times
+/// `replay_rate_fraction`, at least 1. `None` reads backlog as fast as live lines.
+pub fn rate(config: &Config) -> Option<NonZeroU32> {
+    let (limit, fraction) = (config.rate_limit?, config.replay_rate_fraction?);
+    NonZeroU32::new(((limit as f64 * fraction) as u32).max(1))
+}
+
+/// Waits until `lines` more lines of backlog may be handed on, sharing `rate` with every
+/// other input of the process that is replaying backlog. False if `stop` came first, in
+/// which case none of them should be.
+pub fn wait(state: &AgentState, rate: NonZeroU32, lines: usize, stop: &StopFlag) -> bool {
+    let limiter = limiter(state, rate);
+    let mut remaining = lines as u32;
+    while remaining > 0 {
+        if stop.is_stopped() {
+            return false;
+        }
+        // At most a second's worth per wait, the limiter's burst, so `stop` is seen.
+        let chunk = remaining.min(rate.get());
+        futures::executor::block_on(limiter.until_n_ready(NonZeroU32::new(chunk).unwrap())).ok();
+        remaining -= chunk;
+    }
+    !stop.is_stopped()
+}
+
+/// `wait` without blocking, for a reader sharing its thread: takes what `rate` allows of
+/// the `remaining` lines, and returns how long to wait before trying again for the rest,
+/// or `None` once they were all taken.
+pub fn take(state: &AgentState, rate: NonZeroU32, remaining: &mut u32) -> Option<Duration> {
+    let limiter = limiter(state, rate);
+    while *remaining > 0 {
+        // At most the limiter's burst at a time, which it can always let through.
+        let chunk = (*remaining).min(rate.get());
+        match limiter.check_n(NonZeroU32::new(chunk).unwrap()) {
+            Ok(()) => *remaining -= chunk,
+            Err(NegativeMultiDecision::BatchNonConforming(_, until)) => {
+                return Some(until.wait_time_from(DefaultClock::default().now()))
+            }
+            Err(NegativeMultiDecision::InsufficientCapacity(_)) => *remaining = 0,
+        }
+    }
+    None
+}
+
+fn limiter(state: &AgentState, rate: NonZeroU32) -> &Limiter {
+    state
+        .replay_limiter
+        .get_or_init(|| RateLimiter::direct(Quota::per_second(rate)))
+}
+
+/// `replay_speed`, `--replay-speed`: "realtime", or how many times faster than its event
+/// times a capture is replayed, as in "10" or "10x".
+pub fn speed(value: &str) -> anyhow::Result<f64> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("realtime") {
+        return Ok(1.0);
+    }
+    match value.strip_suffix('x').unwrap_or(value).parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err(anyhow::anyhow!(
+            "replay_speed {:?} is neither \"realtime\" nor a multiplier such as \"10x\"",
+            value
+        )),
+    }
+}
+
+/// Holds the records of an input back to the pace of their event times: a record is
+/// handed on once the time between the first record's event time and its own, divided by
+/// the speed, has passed since the first was. Records without an event time, or with one
+/// before the first, are not held back.
+pub struct Pacer {
+    speed: f64,
+    first: Option<(DateTime<Utc>, Instant)>,
+}
+
+impl Pacer {
+    pub fn new(speed: f64) -> Pacer {
+        Pacer { speed, first: None }
+    }
+
+    /// How long to wait, from `now`, before handing on a record of `timestamp`.
+    pub fn delay(&mut self, timestamp: Option<DateTime<Utc>>, now: Instant) -> Duration {
+        let Some(timestamp) = timestamp else {
+            return Duration::ZERO;
+        };
+        let &mut (first, started) = self.first.get_or_insert((timestamp, now));
+        let Ok(since) = (timestamp - first).to_std() else {
+            return Duration::ZERO;
+        };
+        (started + since.div_f64(self.speed)).saturating_duration_since(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn records_are_held_back_to_their_event_times() {
+        assert_eq!(speed("realtime").unwrap(), 1.0);
+        assert_eq!(speed("10x").unwrap(), 10.0);
+        assert_eq!(speed("0.5").unwrap(), 0.5);
+        assert!(speed("0").is_err() && speed("fast").is_err());
+
+        let mut pacer = Pacer::new(10.0);
+        let at = |secs| Some(Utc.with_ymd_and_hms(2024, 3, 5, 10, 0, secs).unwrap());
+        let started = Instant::now();
+        assert_eq!(pacer.delay(at(0), started), Duration::ZERO);
+        assert_eq!(pacer.delay(None, started), Duration::ZERO);
+        assert_eq!(pacer.delay(at(30), started), Duration::from_secs(3));
+        // Later on, only what is left of the wait.
+        let later = started + Duration::from_secs(2);
+        assert_eq!(pacer.delay(at(30), later), Duration::from_secs(1));
+        assert_eq!(pacer.delay(at(10), later), Duration::ZERO);
+    }
+}
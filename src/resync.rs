@@ -0,0 +1,131 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::rules::{self, RegexLimits};
+
+/// How the lines of a file that several processes append to at once are put back on
+/// record boundaries, when one of them writes into the middle of another's line.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResyncConfig {
+    /// Matches the start of every record, such as its timestamp. Unanchored: a match
+    /// inside a line is where an interleaved record starts.
+    pub line_start: String,
+    /// What happens to the text outside any record: the rest of a line another record
+    /// cut into.
+    #[serde(default)]
+    pub fragments: Fragments,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Fragments {
+    /// Counted as `interleaved_fragment` drops.
+    #[default]
+    Drop,
+    /// Sent as records of their own.
+    Keep,
+}
+
+/// The lines of a batch after resynchronizing.
+#[derive(Debug, Default, PartialEq)]
+pub struct Resynced {
+    pub lines: Vec<Vec<u8>>,
+    /// Lines that were not one whole record.
+    pub resyncs: u64,
+    /// Fragments left out, with `fragments = "drop"`.
+    pub dropped: u64,
+}
+
+/// Splits lines at every record start inside them and sets apart what precedes the
+/// first one.
+pub struct Resyncer {
+    line_start: Regex,
+    fragments: Fragments,
+}
+
+impl Resyncer {
+    pub fn new(
+        name: &str,
+        config: &ResyncConfig,
+        limits: &RegexLimits,
+    ) -> anyhow::Result<Resyncer> {
+        Ok(Resyncer {
+            line_start: rules::compile(
+                &format!("resync {} line_start", name),
+                &config.line_start,
+                limits,
+            )?,
+            fragments: config.fragments,
+        })
+    }
+
+    /// Resynchronizes read lines (without their terminators). Lines of exactly one
+    /// record are passed on as they are.
+    pub fn push(&self, lines: Vec<Vec<u8>>) -> Resynced {
+        let mut resynced = Resynced {
+            lines: Vec::with_capacity(lines.len()),
+            ..Resynced::default()
+        };
+        for line in lines {
+            let text = String::from_utf8_lossy(&line);
+            let starts: Vec<usize> = self
+                .line_start
+                .find_iter(&text)
+                .map(|m| m.start())
+                .collect();
+            if starts == [0] {
+                resynced.lines.push(line);
+                continue;
+            }
+            resynced.resyncs += 1;
+            let fragment = &text[..starts.first().copied().unwrap_or(text.len())];
+            if !fragment.trim().is_empty() {
+                match self.fragments {
+                    Fragments::Drop => resynced.dropped += 1,
+                    Fragments::Keep => resynced.lines.push(fragment.as_bytes().to_vec()),
+                }
+            }
+            let ends = starts.iter().skip(1).copied().chain([text.len()]);
+            for (start, end) in starts.iter().copied().zip(ends) {
+                resynced.lines.push(text[start..end].as_bytes().to_vec());
+            }
+        }
+        resynced
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interleaved_records_are_split_and_their_remainder_dropped() {
+        let resyncer = Resyncer::new(
+            "/var/log/cron.log",
+            &ResyncConfig {
+                line_start: r"\[\d{4}-\d{2}-\d{2} [\d:]{8}\]".to_string(),
+                fragments: Fragments::Drop,
+            },
+            &RegexLimits::default(),
+        )
+        .unwrap();
+        let lines = |lines: &[&str]| lines.iter().map(|l| l.as_bytes().to_vec()).collect();
+        let resynced = resyncer.push(lines(&[
+            "[2024-03-05 10:11:12] backup: copying /srv[2024-03-05 10:11:12] rotate: done",
+            "/data to s3",
+            "[2024-03-05 10:11:13] backup: done",
+        ]));
+        assert_eq!(
+            resynced,
+            Resynced {
+                lines: lines(&[
+                    "[2024-03-05 10:11:12] backup: copying /srv",
+                    "[2024-03-05 10:11:12] rotate: done",
+                    "[2024-03-05 10:11:13] backup: done",
+                ]),
+                resyncs: 2,
+                dropped: 1,
+            }
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader};
+
+/// Bytes at the start of a file compared to notice an in-place rewrite that grew the file
+/// past the read position before it was polled.
+const HEAD_BYTES: usize = 256;
+/// Lines remembered per file for `diff`; later lines are always shipped after a rewrite.
+const MAX_TRACKED_LINES: usize = 1_000_000;
+
+/// What to do when a tailed file is rewritten in place (truncated, then filled again).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RewritePolicy {
+    /// Ship the new contents from the start.
+    #[default]
+    Reread,
+    /// Skip the leading lines that match what was read before the rewrite and ship the
+    /// rest.
+    Diff,
+    /// Ignore the rewritten contents and continue with lines appended after it.
+    Skip,
+}
+
+/// Follows one tailed file across rewrites: detects them and decides which lines of the
+/// rewritten file are shipped.
+pub struct RewriteTracker {
+    pub policy: RewritePolicy,
+    head: Vec<u8>,
+    /// Hashes of the lines of the current contents, in order (`diff` only).
+    lines: Vec<u64>,
+    /// Lines of the contents before the last rewrite, while still matching them.
+    previous: Vec<u64>,
+    matched: usize,
+}
+
+impl RewriteTracker {
+    /// Starts tracking `file`, just opened from `path`, whose existing lines are
+    /// remembered for `diff`.
+    pub fn new(path: &str, file: &fs::File, policy: RewritePolicy) -> RewriteTracker {
+        let mut tracker = RewriteTracker {
+            policy,
+            head: read_head(file),
+            lines: Vec::new(),
+            previous: Vec::new(),
+            matched: 0,
+        };
+        if policy == RewritePolicy::Diff {
+            if let Ok(file) = fs::File::open(path) {
+                let (mut reader, mut line) = (BufReader::new(file), Vec::new());
+                // A last line without its newline yet is read as part of the next one.
+                while matches!(reader.read_until(b'\n', &mut line), Ok(n) if n > 0)
+                    && line.ends_with(b"\n")
+                {
+                    tracker.remember(crate::trim_line_end(&line));
+                    line.clear();
+                }
+            }
+        }
+        tracker
+    }
+
+    /// Whether `file` was rewritten since it was read up to `position`: it shrank below
+    /// the position or its first bytes changed. Looks at the open file rather than its
+    /// path, so a new file created at the path is not mistaken for a rewrite.
+    pub fn is_rewritten(&self, file: &fs::File, position: u64) -> bool {
+        let Ok(meta) = file.metadata() else {
+            return false;
+        };
+        if meta.len() < position {
+            return true;
+        }
+        let head = read_head(file);
+        !self.head.is_empty() && !head.starts_with(&self.head) && !self.head.starts_with(&head)
+    }
+
+    /// Begins a new generation of the rewritten file; returns whether to read it from
+    /// the start (`false`: continue from its end).
+    pub fn start_over(&mut self, path: &str, file: &fs::File) -> bool {
+        println!(
+            "{} was rewritten in place, {}",
+            path,
+            match self.policy {
+                RewritePolicy::Reread => "reading it from the start",
+                RewritePolicy::Diff => "shipping the lines that changed",
+                RewritePolicy::Skip => "skipping its current contents",
+            }
+        );
+        self.head = read_head(file);
+        self.previous = std::mem::take(&mut self.lines);
+        self.matched = 0;
+        self.policy != RewritePolicy::Skip
+    }
+
+    /// Whether a line just read should be shipped.
+    pub fn admit(&mut self, line: &[u8]) -> bool {
+        if self.policy != RewritePolicy::Diff {
+            return true;
+        }
+        let hash = self.remember(line);
+        if self.matched < self.previous.len() && self.previous[self.matched] == hash {
+            self.matched += 1;
+            return false;
+        }
+        self.previous.clear();
+        self.matched = 0;
+        true
+    }
+
+    /// Completes the remembered first bytes of a file that was shorter than them, once the
+    /// file is known not to have been rewritten.
+    pub fn refresh_head(&mut self, file: &fs::File) {
+        if self.head.len() < HEAD_BYTES {
+            self.head = read_head(file);
+        }
+    }
+
+    fn remember(&mut self, line: &[u8]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        line.hash(&mut hasher);
+        let hash = hasher.finish();
+        if self.lines.len() < MAX_TRACKED_LINES {
+            self.lines.push(hash);
+        }
+        hash
+    }
+}
+
+/// The first bytes of `file`, read without moving its position.
+fn read_head(file: &fs::File) -> Vec<u8> {
+    let mut head = vec![0; HEAD_BYTES];
+    let mut len = 0;
+    while len < HEAD_BYTES {
+        match read_at(file, &mut head[len..], len as u64) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => len += n,
+        }
+    }
+    head.truncate(len);
+    head
+}
+
+#[cfg(unix)]
+pub(crate) fn read_at(file: &fs::File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+/// `seek_read` moves the position of the file on Windows; it is put back after.
+#[cfg(windows)]
+pub(crate) fn read_at(mut file: &fs::File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    use std::io::Seek;
+
+    let position = file.stream_position()?;
+    let read = std::os::windows::fs::FileExt::seek_read(file, buf, offset);
+    file.seek(std::io::SeekFrom::Start(position))?;
+    read
+}
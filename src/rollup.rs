@@ -0,0 +1,152 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::stats::AgentState;
+use crate::{enqueue_batch, queue, Attributes, Body, Record, Severity};
+
+/// The queue partition rollup records are sent through; they are not counted themselves.
+pub const INPUT: &str = "severity_rollup";
+
+/// `[severity_rollup]`: a record per input and interval with its records counted by
+/// severity, taken before sampling, so dashboards count what `sampling` left out too.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RollupConfig {
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_interval_secs() -> u64 {
+    60
+}
+
+impl RollupConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.interval_secs == 0 {
+            return Err(anyhow::anyhow!(
+                "severity_rollup interval_secs must be greater than 0"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Records of one input by severity number and name.
+type Counts = BTreeMap<(u8, &'static str), u64>;
+
+/// Per input name, its records of the current interval.
+#[derive(Default)]
+pub struct Rollup {
+    counts: Mutex<BTreeMap<Arc<str>, Counts>>,
+}
+
+impl Rollup {
+    pub fn count(&self, input: &Arc<str>, records: impl IntoIterator<Item = Severity>) {
+        let mut counts = self.counts.lock().unwrap();
+        let input = counts.entry(input.clone()).or_default();
+        for severity in records {
+            *input.entry((severity.number, severity.text)).or_default() += 1;
+        }
+    }
+
+    /// The rollup records of the interval that just ended, which starts the next one.
+    pub fn take(&self, interval_secs: u64) -> Vec<Record> {
+        let counts = std::mem::take(&mut *self.counts.lock().unwrap());
+        counts
+            .into_iter()
+            .map(|(input, severities)| {
+                let summary: Vec<String> = severities
+                    .iter()
+                    .map(|((_, text), count)| format!("{} {}", count, text))
+                    .collect();
+                let mut attributes = Attributes::new();
+                attributes.push("event.name", "agent.severity_rollup");
+                attributes.push("rollup.input", input.to_string());
+                attributes.push("rollup.interval_secs", interval_secs.to_string());
+                attributes.extend(severities.iter().map(|((_, text), count)| {
+                    (
+                        format!("rollup.count.{}", text.to_lowercase()),
+                        count.to_string(),
+                    )
+                }));
+                Record {
+                    body: Body::Text(format!(
+                        "{} in the last {}s from {}",
+                        summary.join(", "),
+                        interval_secs,
+                        input
+                    )),
+                    attributes,
+                    default_severity: None,
+                    // Not the severity of the first level word of the summary.
+                    severity: Some(Severity::INFO),
+                    service_name: None,
+                    timestamp: None,
+                    observed: Utc::now(),
+                    otlp_path: None,
+                    trace: None,
+                    resource: None,
+                    checkpoint: None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Sends the rollup records of every `interval_secs` through `tx`.
+pub fn run(
+    config: &RollupConfig,
+    rollup: Arc<Rollup>,
+    tx: queue::Sender,
+    state: Arc<AgentState>,
+) -> thread::JoinHandle<()> {
+    let interval_secs = config.interval_secs;
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(interval_secs));
+        enqueue_batch(&tx, &state, rollup.take(interval_secs));
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_are_counted_by_input_and_severity() {
+        let rollup = Rollup::default();
+        let (app, db): (Arc<str>, Arc<str>) = ("/var/log/app.log".into(), "postgres".into());
+        rollup.count(
+            &app,
+            [
+                Severity::new("INFO", 9),
+                Severity::ERROR,
+                Severity::new("INFO", 9),
+                Severity::WARN,
+            ],
+        );
+        rollup.count(&db, [Severity::new("INFO", 9)]);
+        rollup.count(&app, [Severity::new("INFO", 9)]);
+
+        let records = rollup.take(60);
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0].body.text(),
+            "3 INFO, 1 WARN, 1 ERROR in the last 60s from /var/log/app.log"
+        );
+        assert!(records[0]
+            .attributes
+            .contains(&("rollup.count.info".to_string(), "3".to_string())));
+        assert_eq!(records[0].severity, Some(Severity::INFO));
+        assert_eq!(
+            records[1].body.text(),
+            "1 INFO in the last 60s from postgres"
+        );
+
+        // Each interval counts from zero.
+        assert!(rollup.take(60).is_empty());
+        assert!(RollupConfig { interval_secs: 0 }.validate().is_err());
+    }
+}
@@ -0,0 +1,117 @@
+use crate::config::Config;
+use crate::exporter::ExporterHandle;
+use crate::Record;
+
+/// The attribute naming a record's environment, without `environment_attribute`.
+pub const DEFAULT_ATTRIBUTE: &str = "deployment.environment";
+
+/// Which exporters a record goes to by the environment it names, for the agents of
+/// hosts shared by several environments each shipping to its own SigNoz.
+pub struct Routes {
+    attribute: String,
+    /// The environments of each exporter, in order; empty for the ones taking every record.
+    environments: Vec<Vec<String>>,
+}
+
+impl Routes {
+    /// The routes to `exporters`, if any of them takes only some environments.
+    pub fn new(config: &Config, exporters: &[ExporterHandle]) -> Option<Routes> {
+        let environments: Vec<Vec<String>> = exporters
+            .iter()
+            .map(|exporter| match exporter.name.as_str() {
+                "signoz" => config.environments.clone(),
+                name => config
+                    .exporters
+                    .iter()
+                    .find(|e| e.name == name)
+                    .map(|e| e.environments.clone())
+                    .unwrap_or_default(),
+            })
+            .collect();
+        if environments.iter().all(Vec::is_empty) {
+            return None;
+        }
+        Some(Routes {
+            attribute: config
+                .environment_attribute
+                .clone()
+                .unwrap_or_else(|| DEFAULT_ATTRIBUTE.to_string()),
+            environments,
+        })
+    }
+
+    /// The environment of `entry`: its attribute, as parsed from the line or a path,
+    /// else the resource attribute of its source.
+    fn environment<'a>(&self, entry: &'a Record) -> Option<&'a str> {
+        entry
+            .attributes
+            .iter()
+            .chain(entry.resource.iter().flat_map(|r| r.iter()))
+            .find(|(key, _)| *key == self.attribute)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether each exporter takes `entry`. A record naming no environment goes only to
+    /// the exporters taking every record.
+    pub fn targets(&self, entry: &Record) -> Vec<bool> {
+        let environment = self.environment(entry);
+        self.environments
+            .iter()
+            .map(|taken| {
+                taken.is_empty() || environment.is_some_and(|e| taken.iter().any(|t| t == e))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn records_go_to_the_exporters_of_their_environment() {
+        let routes = Routes {
+            attribute: DEFAULT_ATTRIBUTE.to_string(),
+            environments: vec![
+                vec!["staging".to_string()],
+                vec!["prod".to_string()],
+                vec![],
+            ],
+        };
+        let record = |attributes: &[(&str, &str)], resource: &[(&str, &str)]| {
+            let pairs = |kv: &[(&str, &str)]| -> Vec<(String, String)> {
+                kv.iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect()
+            };
+            Record {
+                attributes: pairs(attributes).into(),
+                resource: (!resource.is_empty()).then(|| Arc::from(pairs(resource))),
+                ..Record::new("GET /")
+            }
+        };
+
+        assert_eq!(
+            routes.targets(&record(&[("deployment.environment", "prod")], &[])),
+            vec![false, true, true]
+        );
+        assert_eq!(
+            routes.targets(&record(&[], &[("deployment.environment", "staging")])),
+            vec![true, false, true]
+        );
+        // The record's own attribute wins over its source's.
+        assert_eq!(
+            routes.targets(&record(
+                &[("deployment.environment", "prod")],
+                &[("deployment.environment", "staging")]
+            )),
+            vec![false, true, true]
+        );
+        assert_eq!(
+            routes.targets(&record(&[("deployment.environment", "dev")], &[])),
+            vec![false, false, true]
+        );
+        assert_eq!(routes.targets(&record(&[], &[])), vec![false, false, true]);
+    }
+}
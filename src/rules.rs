@@ -0,0 +1,230 @@
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::stats::{AgentState, RegexCacheSnapshot};
+
+/// Bounds on user-supplied regexes. The regex engine matches in time linear in the input,
+/// so there is no catastrophic backtracking and a match cannot hang; a pattern can still
+/// compile into a huge automaton or be slow on long lines, which these limits catch.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RegexLimits {
+    /// Largest compiled size of a pattern, in bytes; bigger patterns are rejected at load.
+    #[serde(default = "default_size_limit")]
+    pub size_limit: usize,
+    /// Matches taking longer than this are counted as slow and reported with the rule.
+    #[serde(default = "default_slow_match_ms")]
+    pub slow_match_ms: u64,
+}
+
+impl Default for RegexLimits {
+    fn default() -> RegexLimits {
+        RegexLimits {
+            size_limit: default_size_limit(),
+            slow_match_ms: default_slow_match_ms(),
+        }
+    }
+}
+
+impl RegexLimits {
+    pub fn is_default(&self) -> bool {
+        *self == RegexLimits::default()
+    }
+}
+
+fn default_size_limit() -> usize {
+    1 << 20
+}
+
+fn default_slow_match_ms() -> u64 {
+    10
+}
+
+/// Regexes compiled so far, by pattern and size limit, so that a pattern used by the
+/// rules of many inputs, or validated again on every reload, is compiled once. A clone
+/// of a regex matches with buffers of its own, so the users of one do not contend.
+struct Cache {
+    compiled: BTreeMap<(String, Option<usize>), Regex>,
+    hits: u64,
+    misses: u64,
+}
+
+/// Past this many patterns, e.g. after many reloads that changed them, the cache starts
+/// over.
+const MAX_CACHED: usize = 4096;
+
+static CACHE: Mutex<Cache> = Mutex::new(Cache {
+    compiled: BTreeMap::new(),
+    hits: 0,
+    misses: 0,
+});
+
+/// The regex of `pattern` under `size_limit` (the engine's default when `None`), from the
+/// cache or built and cached.
+fn cached(pattern: &str, size_limit: Option<usize>) -> Result<Regex, regex::Error> {
+    let key = (pattern.to_string(), size_limit);
+    let mut cache = CACHE.lock().unwrap();
+    if let Some(regex) = cache.compiled.get(&key) {
+        let regex = regex.clone();
+        cache.hits += 1;
+        return Ok(regex);
+    }
+    let mut builder = RegexBuilder::new(pattern);
+    if let Some(limit) = size_limit {
+        builder.size_limit(limit).dfa_size_limit(limit);
+    }
+    let regex = builder.build()?;
+    if cache.compiled.len() >= MAX_CACHED {
+        cache.compiled.clear();
+    }
+    cache.compiled.insert(key, regex.clone());
+    cache.misses += 1;
+    Ok(regex)
+}
+
+/// Compiles one of the agent's own patterns, through the cache.
+pub fn builtin(pattern: &str) -> Result<Regex, regex::Error> {
+    cached(pattern, None)
+}
+
+/// How the regex cache fared, for `status` and `/metrics`.
+pub fn cache_snapshot() -> RegexCacheSnapshot {
+    let cache = CACHE.lock().unwrap();
+    RegexCacheSnapshot {
+        patterns: cache.compiled.len(),
+        hits: cache.hits,
+        misses: cache.misses,
+    }
+}
+
+/// Compiles a user pattern within `limits`, naming the rule in the error.
+pub fn compile(name: &str, pattern: &str, limits: &RegexLimits) -> anyhow::Result<Regex> {
+    cached(pattern, Some(limits.size_limit))
+        .map_err(|e| match e {
+            regex::Error::CompiledTooBig(limit) => anyhow::anyhow!(
+                "Rule {} ({}) compiles to more than {} bytes; simplify it or raise regex_limits.size_limit",
+                name,
+                pattern,
+                limit
+            ),
+            e => anyhow::anyhow!("Invalid regex in rule {}: {}", name, e),
+        })
+}
+
+/// Match counters of one rule, reported through `status` and self-telemetry.
+#[derive(Default)]
+pub struct RuleStats {
+    pub name: String,
+    /// Lines the regex ran on.
+    pub matches: AtomicU64,
+    /// Lines the rule matched.
+    pub hits: AtomicU64,
+    /// Lines a literal prefilter decided for the rule without running the regex.
+    pub prefiltered: AtomicU64,
+    pub busy_nanos: AtomicU64,
+    /// Matches that took longer than `slow_match_ms`.
+    pub slow: AtomicU64,
+}
+
+/// A user-supplied regex applied per record, timed so a slow rule can be pointed out.
+pub struct Rule {
+    regex: Regex,
+    timeout: Duration,
+    stats: Arc<RuleStats>,
+    state: Option<Arc<AgentState>>,
+}
+
+impl Rule {
+    pub fn new(name: &str, pattern: &str, limits: &RegexLimits) -> anyhow::Result<Rule> {
+        Ok(Rule {
+            regex: compile(name, pattern, limits)?,
+            timeout: Duration::from_millis(limits.slow_match_ms),
+            stats: Arc::new(RuleStats {
+                name: name.to_string(),
+                ..Default::default()
+            }),
+            state: None,
+        })
+    }
+
+    /// Makes the rule's counters visible in `state` and lets it report slow matches there.
+    pub fn register(&mut self, state: &Arc<AgentState>) {
+        state.rules.lock().unwrap().push(self.stats.clone());
+        self.state = Some(state.clone());
+    }
+
+    /// Removes the rule's counters from `state`, for a rule a reload replaced.
+    pub fn unregister(&self, state: &AgentState) {
+        state
+            .rules
+            .lock()
+            .unwrap()
+            .retain(|s| !Arc::ptr_eq(s, &self.stats));
+    }
+
+    /// Counts a line a literal prefilter decided for the rule, `matched` or not.
+    pub fn prefiltered(&self, matched: bool) {
+        self.stats.prefiltered.fetch_add(1, Ordering::Relaxed);
+        if matched {
+            self.stats.hits.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        let started = Instant::now();
+        let matched = self.regex.is_match(line);
+        let elapsed = started.elapsed();
+        self.stats.matches.fetch_add(1, Ordering::Relaxed);
+        self.stats
+            .busy_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+        if matched {
+            self.stats.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        if elapsed > self.timeout {
+            let slow = self.stats.slow.fetch_add(1, Ordering::Relaxed);
+            // Reported on the first slow match and then every 1000th, not per record.
+            if slow.is_multiple_of(1000) {
+                if let Some(ref state) = self.state {
+                    state.record_error(format!(
+                        "Rule {} took {}ms on a {}-byte line ({} slow matches so far)",
+                        self.stats.name,
+                        elapsed.as_millis(),
+                        line.len(),
+                        slow + 1
+                    ));
+                }
+            }
+        }
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_pattern_is_compiled_once_per_size_limit() {
+        let limits = RegexLimits::default();
+        let pattern = r"cache test (?P<level>\w+)";
+        let first = compile("a", pattern, &limits).unwrap();
+        let before = cache_snapshot();
+        let second = compile("b", pattern, &limits).unwrap();
+        assert!(cache_snapshot().hits > before.hits);
+        assert_eq!(first.as_str(), second.as_str());
+        assert!(second.is_match("cache test warn"));
+
+        // A smaller limit is compiled, and checked, on its own.
+        let tiny = RegexLimits {
+            size_limit: 10,
+            ..RegexLimits::default()
+        };
+        let e = compile("c", pattern, &tiny).unwrap_err();
+        assert!(e.to_string().contains("compiles to more than"), "{}", e);
+        assert!(compile("d", "(", &limits).is_err());
+    }
+}
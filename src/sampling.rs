@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::rules::{RegexLimits, Rule};
+use crate::stats::AgentState;
+use crate::Severity;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SamplingConfig {
+    /// Share of records shipped, between 0 (exclusive) and 1.
+    #[serde(default = "default_ratio")]
+    pub ratio: f64,
+    /// Share of records shipped by severity name, between 0 and 1, for that severity and
+    /// those above it up to the next one listed, e.g. `ERROR = 1.0` and `DEBUG = 0.1`.
+    /// Records below every listed severity are shipped at `ratio`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub severity_ratios: BTreeMap<String, f64>,
+    /// Lines matching any of these regexes are always shipped, whatever the ratio.
+    #[serde(default)]
+    pub keep_patterns: Vec<String>,
+}
+
+fn default_ratio() -> f64 {
+    1.0
+}
+
+/// Keeps an evenly spaced `ratio` share of records plus everything on the keep-list.
+pub struct Sampler {
+    ratio: f64,
+    keep: Vec<Rule>,
+    seen: u64,
+    /// Lowest severity number each of `severity_ratios` applies to, its ratio and the
+    /// records seen at it, highest severity first.
+    levels: Vec<(u8, f64, u64)>,
+}
+
+impl Sampler {
+    pub fn new(config: &SamplingConfig, limits: &RegexLimits) -> anyhow::Result<Sampler> {
+        if !(config.ratio > 0.0 && config.ratio <= 1.0) {
+            return Err(anyhow::anyhow!(
+                "Sampling ratio must be in (0, 1], got {}",
+                config.ratio
+            ));
+        }
+        let keep = config
+            .keep_patterns
+            .iter()
+            .enumerate()
+            .map(|(i, p)| Rule::new(&format!("sampling.keep_patterns[{}]", i), p, limits))
+            .collect::<anyhow::Result<_>>()?;
+        let mut levels = Vec::with_capacity(config.severity_ratios.len());
+        for (name, &ratio) in &config.severity_ratios {
+            let Some(Severity { number, .. }) = crate::severity_from_name(name) else {
+                return Err(anyhow::anyhow!(
+                    "Sampling severity_ratios has unknown severity: {}",
+                    name
+                ));
+            };
+            if !(0.0..=1.0).contains(&ratio) {
+                return Err(anyhow::anyhow!(
+                    "Sampling ratio of {} must be in [0, 1], got {}",
+                    name,
+                    ratio
+                ));
+            }
+            // The start of the severity's range of numbers, e.g. 17 for ERROR (17 to 20).
+            levels.push(((number - 1) / 4 * 4 + 1, ratio, 0));
+        }
+        levels.sort_by_key(|&(from, ..)| std::cmp::Reverse(from));
+        Ok(Sampler {
+            ratio: config.ratio,
+            keep,
+            seen: 0,
+            levels,
+        })
+    }
+
+    pub fn register(&mut self, state: &Arc<AgentState>) {
+        for rule in &mut self.keep {
+            rule.register(state);
+        }
+    }
+
+    pub fn unregister(&self, state: &AgentState) {
+        for rule in &self.keep {
+            rule.unregister(state);
+        }
+    }
+
+    /// Whether to ship the record of `line` with OTLP severity number `severity`.
+    pub fn keep(&mut self, line: &str, severity: u8) -> bool {
+        if self.keep.iter().any(|re| re.is_match(line)) {
+            return true;
+        }
+        let (ratio, seen) = match self.levels.iter_mut().find(|(from, ..)| severity >= *from) {
+            Some((_, ratio, seen)) => (*ratio, seen),
+            None => (self.ratio, &mut self.seen),
+        };
+        // Kept whenever the running count of kept records would fall behind the ratio.
+        let before = (*seen as f64 * ratio).floor();
+        *seen += 1;
+        (*seen as f64 * ratio).floor() > before
+    }
+}
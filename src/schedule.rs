@@ -0,0 +1,191 @@
+use chrono::{DateTime, Datelike, FixedOffset, Local, NaiveTime, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::severity_from_name;
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// A `[severity_schedules]` entry: the least severe records of the input shipped, by day
+/// of the week and time of day, e.g. DEBUG during business hours and WARN otherwise.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SeveritySchedule {
+    /// Records below this severity, e.g. "warn", are dropped outside every window;
+    /// every record is shipped then when unset.
+    pub min_severity: Option<String>,
+    /// The first window the time is in sets the severity.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub windows: Vec<Window>,
+    /// Offset from UTC the windows are in, such as "+02:00"; the host's time zone by
+    /// default.
+    pub utc_offset: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Window {
+    /// Days such as "sat" or ranges such as "mon-fri"; every day when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub days: Vec<String>,
+    /// "09:00"; the window starts on its days, and runs past midnight when `to` is
+    /// earlier.
+    pub from: String,
+    /// "18:00", the first minute no longer in the window.
+    pub to: String,
+    pub min_severity: String,
+}
+
+/// A schedule read, for looking up the severity at a time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    default: u8,
+    /// The days as bits from Monday, the minutes of the day the window starts and ends
+    /// at, and its severity number.
+    windows: Vec<(u8, u32, u32, u8)>,
+    offset: Option<FixedOffset>,
+}
+
+impl Schedule {
+    pub fn new(config: &SeveritySchedule) -> anyhow::Result<Schedule> {
+        let windows = config
+            .windows
+            .iter()
+            .enumerate()
+            .map(|(i, window)| {
+                let days = window.days.iter().try_fold(0, |days, range| {
+                    parse_days(range).map(|bits| days | bits).ok_or_else(|| {
+                        anyhow::anyhow!("windows[{}] has unknown days {:?}", i, range)
+                    })
+                })?;
+                let minutes = |time: &str| {
+                    NaiveTime::parse_from_str(time, "%H:%M")
+                        .map(|t| t.hour() * 60 + t.minute())
+                        .map_err(|_| anyhow::anyhow!("windows[{}] time {:?} is not HH:MM", i, time))
+                };
+                let (from, to) = (minutes(&window.from)?, minutes(&window.to)?);
+                if from == to {
+                    return Err(anyhow::anyhow!("windows[{}] starts where it ends", i));
+                }
+                let days = if days == 0 { 0x7f } else { days };
+                Ok((days, from, to, severity_number(&window.min_severity)?))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let offset =
+            match config.utc_offset {
+                Some(ref offset) => Some(offset.parse::<FixedOffset>().map_err(|_| {
+                    anyhow::anyhow!("utc_offset {:?} is not like \"+02:00\"", offset)
+                })?),
+                None => None,
+            };
+        Ok(Schedule {
+            default: match config.min_severity {
+                Some(ref name) => severity_number(name)?,
+                None => 0,
+            },
+            windows,
+            offset,
+        })
+    }
+
+    /// The least severity number shipped at `now`.
+    pub fn min_severity(&self, now: DateTime<Utc>) -> u8 {
+        let local = match self.offset {
+            Some(offset) => now.with_timezone(&offset).naive_local(),
+            None => now.with_timezone(&Local).naive_local(),
+        };
+        let day = local.weekday().num_days_from_monday();
+        let minute = local.hour() * 60 + local.minute();
+        let started_on = |days: u8, day: u32| days & (1 << day) != 0;
+        self.windows
+            .iter()
+            .find(|&&(days, from, to, _)| {
+                if from < to {
+                    started_on(days, day) && (from..to).contains(&minute)
+                } else {
+                    // Past midnight, the window started the day before.
+                    (started_on(days, day) && minute >= from)
+                        || (started_on(days, (day + 6) % 7) && minute < to)
+                }
+            })
+            .map_or(self.default, |&(.., severity)| severity)
+    }
+}
+
+/// The start of the severity's range of numbers, e.g. 9 for INFO (9 to 12).
+fn severity_number(name: &str) -> anyhow::Result<u8> {
+    severity_from_name(name)
+        .map(|severity| (severity.number - 1) / 4 * 4 + 1)
+        .ok_or_else(|| anyhow::anyhow!("min_severity {:?} is not a severity", name))
+}
+
+/// The bits of "wed" or of "mon-fri"; a range may wrap around, as "fri-mon".
+fn parse_days(range: &str) -> Option<u8> {
+    let day = |name: &str| DAYS.iter().position(|d| name.eq_ignore_ascii_case(d));
+    let (first, last) = match range.split_once('-') {
+        Some((first, last)) => (day(first.trim())?, day(last.trim())?),
+        None => (day(range.trim())?, day(range.trim())?),
+    };
+    let mut bits = 0;
+    let mut day = first;
+    loop {
+        bits |= 1 << day;
+        if day == last {
+            return Some(bits);
+        }
+        day = (day + 1) % 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn severity_follows_the_window_of_the_time() {
+        let config: SeveritySchedule = toml::from_str(
+            r#"
+            min_severity = "warn"
+            utc_offset = "+02:00"
+
+            [[windows]]
+            days = ["mon-fri"]
+            from = "09:00"
+            to = "18:00"
+            min_severity = "debug"
+
+            [[windows]]
+            days = ["fri"]
+            from = "22:00"
+            to = "02:00"
+            min_severity = "info"
+            "#,
+        )
+        .unwrap();
+        let schedule = Schedule::new(&config).unwrap();
+        let at = |time: &str| {
+            DateTime::parse_from_rfc3339(time)
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+
+        // Wednesday 2024-03-06.
+        assert_eq!(schedule.min_severity(at("2024-03-06T09:30:00+02:00")), 5);
+        assert_eq!(schedule.min_severity(at("2024-03-06T07:30:00Z")), 5);
+        assert_eq!(schedule.min_severity(at("2024-03-06T18:00:00+02:00")), 13);
+        assert_eq!(schedule.min_severity(at("2024-03-06T08:59:00+02:00")), 13);
+        // Saturday, and the Friday night window running into it.
+        assert_eq!(schedule.min_severity(at("2024-03-09T10:00:00+02:00")), 13);
+        assert_eq!(schedule.min_severity(at("2024-03-09T01:30:00+02:00")), 9);
+        assert_eq!(schedule.min_severity(at("2024-03-08T23:00:00+02:00")), 9);
+        assert_eq!(schedule.min_severity(at("2024-03-10T01:30:00+02:00")), 13);
+
+        assert_eq!(parse_days("sat-mon"), Some(0b110_0001));
+        for broken in [
+            "[[windows]]\nfrom = \"9am\"\nto = \"18:00\"\nmin_severity = \"debug\"",
+            "[[windows]]\ndays = [\"weekdays\"]\nfrom = \"09:00\"\nto = \"18:00\"\nmin_severity = \"debug\"",
+            "min_severity = \"loud\"",
+            "utc_offset = \"CET\"",
+        ] {
+            let config: SeveritySchedule = toml::from_str(broken).unwrap();
+            assert!(Schedule::new(&config).is_err(), "{}", broken);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+use crate::stats::AgentState;
+
+/// Violations already reported, by path with indices removed, so a systematic encoder
+/// bug is reported once instead of per record.
+static REPORTED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+const RESOURCE_LOGS_FIELDS: &[&str] = &["resource", "scopeLogs", "schemaUrl"];
+const SCOPE_LOGS_FIELDS: &[&str] = &["scope", "logRecords", "schemaUrl"];
+const LOG_RECORD_FIELDS: &[&str] = &[
+    "timeUnixNano",
+    "observedTimeUnixNano",
+    "severityNumber",
+    "severityText",
+    "body",
+    "attributes",
+    "droppedAttributesCount",
+    "flags",
+    "traceId",
+    "spanId",
+    "eventName",
+];
+
+/// Checks an encoded OTLP/JSON logs request and records each kind of violation found in
+/// `state` the first time it is seen. Returns the number of violations.
+pub fn report(payload: &impl serde::Serialize, state: &AgentState) -> usize {
+    let violations = match serde_json::to_value(payload) {
+        Ok(value) => check(&value),
+        Err(e) => vec![format!("payload does not encode: {}", e)],
+    };
+    let mut reported = REPORTED.lock().unwrap();
+    for violation in &violations {
+        let kind: String = violation.chars().filter(|c| !c.is_ascii_digit()).collect();
+        if reported.insert(kind) {
+            state.record_error(format!("OTLP self-check: {}", violation));
+        }
+    }
+    violations.len()
+}
+
+/// Violations of the OTLP/JSON encoding of a logs request, each prefixed with the JSON
+/// path where it occurs.
+pub fn check(payload: &Value) -> Vec<String> {
+    let mut out = Vec::new();
+    let Some(root) = object(payload, "$", &mut out) else {
+        return out;
+    };
+    for (i, resource_logs) in array(root, "resourceLogs", "$", &mut out)
+        .iter()
+        .enumerate()
+    {
+        let path = format!("resourceLogs[{}]", i);
+        let Some(resource_logs) = object(resource_logs, &path, &mut out) else {
+            continue;
+        };
+        unknown_fields(resource_logs, RESOURCE_LOGS_FIELDS, &path, &mut out);
+        if let Some(resource) = resource_logs.get("resource") {
+            let path = format!("{}.resource", path);
+            if let Some(resource) = object(resource, &path, &mut out) {
+                attributes(resource, &path, &mut out);
+            }
+        }
+        for (j, scope_logs) in array(resource_logs, "scopeLogs", &path, &mut out)
+            .iter()
+            .enumerate()
+        {
+            let path = format!("{}.scopeLogs[{}]", path, j);
+            let Some(scope_logs) = object(scope_logs, &path, &mut out) else {
+                continue;
+            };
+            unknown_fields(scope_logs, SCOPE_LOGS_FIELDS, &path, &mut out);
+            for (k, record) in array(scope_logs, "logRecords", &path, &mut out)
+                .iter()
+                .enumerate()
+            {
+                let path = format!("{}.logRecords[{}]", path, k);
+                if let Some(record) = object(record, &path, &mut out) {
+                    log_record(record, &path, &mut out);
+                }
+            }
+        }
+    }
+    out
+}
+
+fn log_record(record: &Map<String, Value>, path: &str, out: &mut Vec<String>) {
+    unknown_fields(record, LOG_RECORD_FIELDS, path, out);
+    for field in ["timeUnixNano", "observedTimeUnixNano"] {
+        match record.get(field) {
+            None => {}
+            Some(Value::String(s)) if s.parse::<u64>().is_ok() => {}
+            Some(other) => out.push(format!(
+                "{}.{}: must be a string of decimal nanoseconds, got {}",
+                path, field, other
+            )),
+        }
+    }
+    if let Some(severity) = record.get("severityNumber") {
+        if !matches!(severity.as_u64(), Some(0..=24)) {
+            out.push(format!(
+                "{}.severityNumber: must be an integer from 0 to 24, got {}",
+                path, severity
+            ));
+        }
+    }
+    if let Some(text) = record.get("severityText") {
+        if !text.is_string() {
+            out.push(format!("{}.severityText: must be a string", path));
+        }
+    }
+    for (field, len) in [("traceId", 32), ("spanId", 16)] {
+        if let Some(id) = record.get(field) {
+            let valid = id.as_str().is_some_and(|s| {
+                s.len() == len
+                    && s.bytes().all(|b| b.is_ascii_hexdigit())
+                    && s.bytes().any(|b| b != b'0')
+            });
+            if !valid {
+                out.push(format!(
+                    "{}.{}: must be {} hex characters and not all zero, got {}",
+                    path, field, len, id
+                ));
+            }
+        }
+    }
+    if let Some(count) = record.get("droppedAttributesCount") {
+        if count.as_u64().is_none_or(|n| n > u32::MAX as u64) {
+            out.push(format!(
+                "{}.droppedAttributesCount: must be a 32-bit unsigned integer",
+                path
+            ));
+        }
+    }
+    if let Some(body) = record.get("body") {
+        any_value(body, &format!("{}.body", path), out);
+    }
+    attributes(record, path, out);
+}
+
+fn attributes(parent: &Map<String, Value>, path: &str, out: &mut Vec<String>) {
+    if let Some(list) = parent.get("attributes") {
+        key_values(list, &format!("{}.attributes", path), out);
+    }
+}
+
+/// A list of `KeyValue`s: keys must be non-empty and unique.
+fn key_values(list: &Value, path: &str, out: &mut Vec<String>) {
+    let Some(list) = list.as_array() else {
+        out.push(format!("{}: must be an array", path));
+        return;
+    };
+    let mut keys = BTreeSet::new();
+    for (i, kv) in list.iter().enumerate() {
+        let path = format!("{}[{}]", path, i);
+        let Some(kv) = object(kv, &path, out) else {
+            continue;
+        };
+        match kv.get("key").and_then(Value::as_str) {
+            Some("") => out.push(format!("{}.key: must not be empty", path)),
+            Some(key) => {
+                if !keys.insert(key) {
+                    out.push(format!("{}.key: duplicate key {:?}", path, key));
+                }
+            }
+            None => out.push(format!("{}.key: must be a string", path)),
+        }
+        match kv.get("value") {
+            Some(value) => any_value(value, &format!("{}.value", path), out),
+            None => out.push(format!("{}.value: missing", path)),
+        }
+    }
+}
+
+/// An `AnyValue`: an object with exactly one of the value fields, of the right type.
+fn any_value(value: &Value, path: &str, out: &mut Vec<String>) {
+    let Some(value) = object(value, path, out) else {
+        return;
+    };
+    if value.len() != 1 {
+        out.push(format!(
+            "{}: must have exactly one value field, has {}",
+            path,
+            value.len()
+        ));
+        return;
+    }
+    let (kind, inner) = value.iter().next().expect("one field");
+    let valid = match kind.as_str() {
+        "stringValue" => inner.is_string(),
+        "boolValue" => inner.is_boolean(),
+        // 64-bit integers are strings in OTLP/JSON; plain numbers are accepted too.
+        "intValue" => inner.as_str().is_some_and(|s| s.parse::<i64>().is_ok()) || inner.is_i64(),
+        "doubleValue" => inner.is_number(),
+        "bytesValue" => inner.as_str().is_some_and(|s| {
+            use base64::Engine;
+            base64::engine::general_purpose::STANDARD.decode(s).is_ok()
+        }),
+        "arrayValue" => {
+            for (i, item) in inner
+                .get("values")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .enumerate()
+            {
+                any_value(item, &format!("{}.arrayValue.values[{}]", path, i), out);
+            }
+            inner.is_object()
+        }
+        "kvlistValue" => {
+            if let Some(list) = inner.get("values") {
+                key_values(list, &format!("{}.kvlistValue.values", path), out);
+            }
+            inner.is_object()
+        }
+        other => {
+            out.push(format!("{}: unknown value field {:?}", path, other));
+            return;
+        }
+    };
+    if !valid {
+        out.push(format!("{}.{}: invalid value {}", path, kind, inner));
+    }
+}
+
+fn object<'a>(
+    value: &'a Value,
+    path: &str,
+    out: &mut Vec<String>,
+) -> Option<&'a Map<String, Value>> {
+    let object = value.as_object();
+    if object.is_none() {
+        out.push(format!("{}: must be an object", path));
+    }
+    object
+}
+
+fn array<'a>(
+    parent: &'a Map<String, Value>,
+    field: &str,
+    path: &str,
+    out: &mut Vec<String>,
+) -> &'a [Value] {
+    match parent.get(field) {
+        Some(Value::Array(items)) => items,
+        Some(_) => {
+            out.push(format!("{}.{}: must be an array", path, field));
+            &[]
+        }
+        None => &[],
+    }
+}
+
+fn unknown_fields(object: &Map<String, Value>, known: &[&str], path: &str, out: &mut Vec<String>) {
+    for field in object.keys() {
+        if !known.contains(&field.as_str()) {
+            out.push(format!("{}: unknown field {:?}", path, field));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reports_spec_violations_by_path() {
+        let mut payload = json!({ "resourceLogs": [{
+            "resource": { "attributes": [{ "key": "service.name", "value": { "stringValue": "api" } }] },
+            "scopeLogs": [{ "logRecords": [{
+                "timeUnixNano": "1700000000000000000",
+                "severityNumber": 9,
+                "severityText": "INFO",
+                "body": { "stringValue": "hello" },
+                "attributes": [{ "key": "log.file", "value": { "stringValue": "/var/log/app.log" } }],
+                "traceId": "4bf92f3577b34da6a3ce929d0e0e4736"
+            }] }]
+        }] });
+        assert!(check(&payload).is_empty());
+
+        let record = &mut payload["resourceLogs"][0]["scopeLogs"][0]["logRecords"][0];
+        record["timeUnixNano"] = json!(1700000000000000000u64);
+        record["severityNumber"] = json!(25);
+        record["attributes"][0]["key"] = json!("");
+        record["span_id"] = json!("00f067aa0ba902b7");
+        assert_eq!(
+            check(&payload),
+            vec![
+                "resourceLogs[0].scopeLogs[0].logRecords[0]: unknown field \"span_id\"",
+                "resourceLogs[0].scopeLogs[0].logRecords[0].timeUnixNano: must be a string of decimal nanoseconds, got 1700000000000000000",
+                "resourceLogs[0].scopeLogs[0].logRecords[0].severityNumber: must be an integer from 0 to 24, got 25",
+                "resourceLogs[0].scopeLogs[0].logRecords[0].attributes[0].key: must not be empty",
+            ]
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::fieldpath::FieldPath;
+use crate::processor::{self, Processor};
+use crate::rules::{self, RegexLimits};
+use crate::{severity_from_name, Record, Severity};
+
+/// A `[[severity_maps]]` entry: the severity of records whose `attribute`, which may be a
+/// path into nested JSON such as `http.responses[0].status`, holds an integer (an HTTP
+/// status, an exit code, an errno) in one of `ranges`. The first
+/// matching range wins, and the first map that sets a severity wins over later ones.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SeverityMap {
+    pub attribute: String,
+    pub ranges: Vec<SeverityRange>,
+    /// Mark the records it set the severity of, with `mark_mutations`.
+    #[serde(skip)]
+    pub mark: bool,
+    /// `attribute`, parsed.
+    #[serde(skip)]
+    pub path: Option<FieldPath>,
+}
+
+/// Values `from` to `to`, both included; `to` defaults to `from`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SeverityRange {
+    pub from: i64,
+    pub to: Option<i64>,
+    pub severity: String,
+}
+
+impl SeverityMap {
+    pub fn validate(&self) -> Result<()> {
+        if self.attribute.is_empty() {
+            return Err(anyhow::anyhow!(
+                "severity_maps entry has an empty attribute"
+            ));
+        }
+        FieldPath::parse(&self.attribute)?;
+        for range in &self.ranges {
+            if severity_from_name(&range.severity).is_none() {
+                return Err(anyhow::anyhow!(
+                    "severity_maps entry for {} has unknown severity: {}",
+                    self.attribute,
+                    range.severity
+                ));
+            }
+            if range.to.is_some_and(|to| to < range.from) {
+                return Err(anyhow::anyhow!(
+                    "severity_maps entry for {} has a range ending before it starts ({} to {})",
+                    self.attribute,
+                    range.from,
+                    range.to.unwrap_or_default()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn severity_of(&self, value: i64) -> Option<Severity> {
+        self.ranges
+            .iter()
+            .find(|r| r.from <= value && value <= r.to.unwrap_or(r.from))
+            .and_then(|r| severity_from_name(&r.severity))
+    }
+}
+
+impl Processor for SeverityMap {
+    fn process(&mut self, mut record: Record) -> Option<Record> {
+        if record.severity.is_none() {
+            record.severity = self
+                .path
+                .as_ref()
+                .and_then(|path| path.lookup(&record.attributes))
+                .and_then(|value| value.trim().parse::<i64>().ok())
+                .and_then(|value| self.severity_of(value));
+            if self.mark && record.severity.is_some() {
+                processor::mark_mutation(&mut record, "severity_map");
+            }
+        }
+        Some(record)
+    }
+}
+
+/// A `[[severity_rules]]` entry of an input: where in a line its level is, and what the
+/// level found there means.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SeverityRule {
+    /// Finds the level: its `level` group, else its first group, else the whole match.
+    pub pattern: String,
+    /// Severity of each level found, by the level or by a glob pattern over it such as
+    /// `"5??"`, compared case-insensitively. Without one, the level is read as a level
+    /// word.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub map: BTreeMap<String, Level>,
+}
+
+/// A severity name such as `"ERROR"`, or an OTLP severity number from 1 to 24.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Level {
+    Number(u8),
+    Name(String),
+}
+
+impl Level {
+    fn severity(&self) -> Option<Severity> {
+        match *self {
+            Level::Number(number) => {
+                let text = match number {
+                    1..=4 => "TRACE",
+                    5..=8 => "DEBUG",
+                    9..=12 => "INFO",
+                    13..=16 => "WARN",
+                    17..=20 => "ERROR",
+                    21..=24 => "FATAL",
+                    _ => return None,
+                };
+                Some(Severity::new(text, number))
+            }
+            Level::Name(ref name) => severity_from_name(name),
+        }
+    }
+}
+
+/// Severity of the levels matching each pattern.
+type LevelMap = Vec<(glob::Pattern, Severity)>;
+
+/// The compiled severity rules of an input, tried in order.
+pub struct SeverityRules {
+    rules: Vec<(Regex, LevelMap)>,
+}
+
+impl SeverityRules {
+    pub fn new(name: &str, rules: &[SeverityRule], limits: &RegexLimits) -> Result<SeverityRules> {
+        let mut compiled = Vec::with_capacity(rules.len());
+        for (i, rule) in rules.iter().enumerate() {
+            let pattern = rules::compile(
+                &format!("severity_rules {} [{}] pattern", name, i),
+                &rule.pattern,
+                limits,
+            )?;
+            let mut map = Vec::with_capacity(rule.map.len());
+            for (level, severity) in &rule.map {
+                let Some(severity) = severity.severity() else {
+                    return Err(anyhow::anyhow!(
+                        "severity_rules {} [{}] maps {} to unknown severity {:?}",
+                        name,
+                        i,
+                        level,
+                        severity
+                    ));
+                };
+                let level = glob::Pattern::new(&level.to_uppercase()).map_err(|e| {
+                    anyhow::anyhow!(
+                        "severity_rules {} [{}] has invalid level {}: {}",
+                        name,
+                        i,
+                        level,
+                        e
+                    )
+                })?;
+                map.push((level, severity));
+            }
+            // Levels by themselves before patterns, and longer patterns first.
+            map.sort_by_key(|(level, _)| {
+                (
+                    crate::input::is_pattern(level.as_str()),
+                    std::cmp::Reverse(level.as_str().len()),
+                )
+            });
+            compiled.push((pattern, map));
+        }
+        Ok(SeverityRules { rules: compiled })
+    }
+
+    /// The severity the first rule that matches `line` and knows its level gives it.
+    pub fn detect(&self, line: &str, keywords: &BTreeMap<String, String>) -> Option<Severity> {
+        self.rules.iter().find_map(|(pattern, map)| {
+            let captures = pattern.captures(line)?;
+            let level = captures
+                .name("level")
+                .or_else(|| captures.get(1))
+                .or_else(|| captures.get(0))?
+                .as_str()
+                .trim();
+            if map.is_empty() {
+                return crate::detect_severity(level, keywords);
+            }
+            let level = level.to_uppercase();
+            map.iter()
+                .find(|(pattern, _)| pattern.as_str() == level || pattern.matches(&level))
+                .map(|(_, severity)| *severity)
+        })
+    }
+}
@@ -0,0 +1,149 @@
+use std::fs;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::backfill::{self, BackfillInput, Delivery};
+use crate::delivery::{Listener, Outcome};
+use crate::eventlog;
+use crate::failure::Failure;
+use crate::input::{InputContext, StopFlag};
+use crate::stats::InputStats;
+use crate::Record;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// `rust-signoz-agent ship <file> [--wait]`: the file is read once, from the start,
+/// through the pipeline the config sets up, and the agent exits once it is sent.
+pub struct Ship {
+    pub path: String,
+    /// Wait for every record to be delivered or given up on, however long that takes,
+    /// and fail unless all of them were delivered.
+    pub wait: bool,
+    /// `rust-signoz-agent backfill-evtx <file>`: the file is an exported Windows event
+    /// log, whose events are shipped as an `[[eventlogs]]` input would ship them.
+    pub evtx: bool,
+}
+
+/// Outcomes of the shipped records.
+#[derive(Default)]
+pub struct Tally {
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+    undelivered: AtomicU64,
+}
+
+impl Tally {
+    /// The records delivered, dropped and given up on so far.
+    pub fn counts(&self) -> (u64, u64, u64) {
+        (
+            self.delivered.load(Ordering::SeqCst),
+            self.dropped.load(Ordering::SeqCst),
+            self.undelivered.load(Ordering::SeqCst),
+        )
+    }
+}
+
+impl Listener for Tally {
+    fn outcome(&self, _: &Record, outcome: &Outcome) {
+        let counter = match outcome {
+            Outcome::Delivered => &self.delivered,
+            Outcome::Dropped(_) => &self.dropped,
+            Outcome::Undelivered(_) => &self.undelivered,
+        };
+        counter.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Reads the file and waits for its records to leave: up to `drain_timeout` once it was
+/// read, or until all of them have an outcome with `--wait`. Stops early once `running`
+/// is cleared.
+pub fn run(
+    ship: &Ship,
+    ctx: &InputContext,
+    tally: &Tally,
+    running: &AtomicBool,
+    drain_timeout: Duration,
+) -> anyhow::Result<()> {
+    if let Err(e) = fs::File::open(&ship.path) {
+        let err = anyhow::anyhow!("Cannot read {}: {}", ship.path, e);
+        if e.kind() == std::io::ErrorKind::PermissionDenied {
+            return Err(err.context(Failure::PermissionDenied));
+        }
+        return Err(err);
+    }
+    let state = &ctx.state;
+    let stats = state.register_input(&ship.path);
+    if ship.evtx {
+        let tx = ctx.replay_sender(&stats.name);
+        eventlog::read_evtx(&ship.path, state, |record| {
+            stats.record_line();
+            crate::enqueue(&tx, state, record);
+        })?;
+    } else {
+        read_lines(ship, ctx, &stats, running);
+    }
+
+    // Partial batches and records waiting for continuation lines are sent right away.
+    state.flushing.store(true, Ordering::SeqCst);
+    let drained = Instant::now();
+    while state.pending() > 0
+        && running.load(Ordering::SeqCst)
+        && (ship.wait || drained.elapsed() < drain_timeout)
+    {
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    let (delivered, dropped, undelivered) = tally.counts();
+    let pending = state.pending();
+    println!(
+        "Shipped {}: {} lines read, {} records delivered, {} dropped, {} undelivered, {} still queued",
+        ship.path,
+        stats.lines.load(Ordering::SeqCst),
+        delivered,
+        dropped,
+        undelivered,
+        pending
+    );
+    if ship.wait && (undelivered > 0 || pending > 0) {
+        return Err(anyhow::anyhow!(
+            "{} records of {} were not delivered",
+            undelivered + pending as u64,
+            ship.path
+        )
+        .context(Failure::ShipIncomplete));
+    }
+    Ok(())
+}
+
+/// Reads the lines of the file, as a `backfill` input does, until it ends or `running`
+/// is cleared.
+fn read_lines(ship: &Ship, ctx: &InputContext, stats: &Arc<InputStats>, running: &AtomicBool) {
+    let state = &ctx.state;
+    let input = BackfillInput {
+        path: ship.path.clone(),
+        workers: None,
+        delivery: Delivery::Ordered,
+        stop: StopFlag::default(),
+    };
+    let reader = backfill::run_backfill_input(
+        input.clone(),
+        ctx.config
+            .read_buffer_bytes
+            .unwrap_or(crate::DEFAULT_READ_BUFFER_BYTES),
+        state.clone(),
+        stats.clone(),
+        {
+            let handler = ctx.replay_line_handler(&ship.path, stats.clone());
+            move |lines| handler(lines, None)
+        },
+    );
+    while !reader.is_finished() {
+        if !running.load(Ordering::SeqCst) {
+            input.stop.stop();
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    reader.join().ok();
+}
@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::clock::Clock;
+
+/// How often a step checks whether it is done.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Steps of the shutdown that wait, in the order they run; `persist` comes last.
+pub const STEPS: &[&str] = &["inputs", "drain", "flush"];
+
+/// Default timeout of the `inputs` step.
+const DEFAULT_INPUTS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// One step of the shutdown: an action, then a wait until `done` holds.
+pub struct Step<'a> {
+    pub name: &'static str,
+    pub start: Box<dyn FnOnce() + 'a>,
+    pub done: Box<dyn FnMut() -> bool + 'a>,
+    /// What is left undone, for the log line of a step that timed out.
+    pub left: Box<dyn Fn() -> String + 'a>,
+    pub timeout: Timeout,
+}
+
+/// How long a step may wait.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timeout {
+    /// Its own time, outside the shared budget.
+    Own(Duration),
+    /// What is left of the shared budget, and at most this much.
+    Shared(Option<Duration>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Done,
+    TimedOut,
+}
+
+/// The timeout of `step` given `timeouts` in seconds by step name: `inputs` waits for a
+/// second by default, `drain` and `flush` share the budget of the shutdown.
+pub fn timeout(step: &str, timeouts: &BTreeMap<String, u64>) -> Timeout {
+    let secs = timeouts.get(step).copied().map(Duration::from_secs);
+    match step {
+        "inputs" => Timeout::Own(secs.unwrap_or(DEFAULT_INPUTS_TIMEOUT)),
+        "drain" | "flush" => Timeout::Shared(secs),
+        _ => Timeout::Own(Duration::ZERO),
+    }
+}
+
+/// Runs `steps` in order, each until it is done or out of time, and logs the ones that
+/// timed out. Steps with a shared timeout together wait at most `budget`.
+pub fn run(steps: Vec<Step>, budget: Duration, clock: &dyn Clock) -> Vec<(&'static str, Outcome)> {
+    let mut left_of_budget = budget;
+    let mut outcomes = Vec::with_capacity(steps.len());
+    for mut step in steps {
+        let timeout = match step.timeout {
+            Timeout::Own(timeout) => timeout,
+            Timeout::Shared(cap) => cap.map_or(left_of_budget, |cap| cap.min(left_of_budget)),
+        };
+        (step.start)();
+        let started = clock.now();
+        let elapsed = || (clock.now() - started).to_std().unwrap_or_default();
+        let outcome = loop {
+            if (step.done)() {
+                break Outcome::Done;
+            }
+            if elapsed() >= timeout {
+                eprintln!(
+                    "Shutdown step {} timed out after {:.1}s: {}",
+                    step.name,
+                    timeout.as_secs_f64(),
+                    (step.left)()
+                );
+                break Outcome::TimedOut;
+            }
+            clock.sleep(POLL_INTERVAL.min(timeout - elapsed()));
+        };
+        if let Timeout::Shared(_) = step.timeout {
+            left_of_budget = left_of_budget.saturating_sub(elapsed());
+        }
+        outcomes.push((step.name, outcome));
+    }
+    outcomes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SimulatedClock;
+    use chrono::Utc;
+    use std::cell::{Cell, RefCell};
+
+    fn step<'a>(
+        order: &'a RefCell<Vec<&'static str>>,
+        name: &'static str,
+        timeout: Timeout,
+        done: impl FnMut() -> bool + 'a,
+    ) -> Step<'a> {
+        Step {
+            name,
+            start: Box::new(move || order.borrow_mut().push(name)),
+            done: Box::new(done),
+            left: Box::new(|| "records queued".to_string()),
+            timeout,
+        }
+    }
+
+    #[test]
+    fn steps_run_in_order_and_share_the_budget() {
+        let clock = SimulatedClock::new(Utc::now());
+        let order = RefCell::new(Vec::new());
+        let queued = Cell::new(3);
+        let started = clock.now();
+        let outcomes = run(
+            vec![
+                step(
+                    &order,
+                    "inputs",
+                    Timeout::Own(Duration::from_secs(1)),
+                    || true,
+                ),
+                // Done after three polls, 300ms of the budget.
+                step(&order, "drain", Timeout::Shared(None), || {
+                    queued.set(queued.get() - 1);
+                    queued.get() < 0
+                }),
+                // Never done: waits what is left of the budget.
+                step(&order, "flush", Timeout::Shared(None), || false),
+            ],
+            Duration::from_secs(2),
+            &clock,
+        );
+        assert_eq!(*order.borrow(), vec!["inputs", "drain", "flush"]);
+        assert_eq!(
+            outcomes,
+            vec![
+                ("inputs", Outcome::Done),
+                ("drain", Outcome::Done),
+                ("flush", Outcome::TimedOut),
+            ]
+        );
+        assert_eq!((clock.now() - started).num_milliseconds(), 2000);
+    }
+}
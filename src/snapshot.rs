@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Read;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::input::{Input, InputContext, StopFlag};
+use crate::stats::AgentState;
+
+const DEFAULT_MAX_BYTES: u64 = 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotMode {
+    /// One record per line that differs from the previous read at the same position.
+    #[default]
+    ChangedLines,
+    /// The whole content as a single record whenever anything changed.
+    Full,
+}
+
+/// A file that is re-read as a whole on an interval rather than tailed, which suits
+/// /proc entries and status files that are rewritten in place.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SnapshotInput {
+    pub path: String,
+    pub interval_secs: u64,
+    #[serde(default)]
+    pub mode: SnapshotMode,
+    /// Upper bound on how much of the file is read per snapshot.
+    pub max_bytes: Option<u64>,
+    #[serde(skip)]
+    pub stop: StopFlag,
+}
+
+impl Input for SnapshotInput {
+    fn describe(&self) -> String {
+        format!("snapshot:{}", self.path)
+    }
+
+    fn start(&mut self, ctx: &InputContext) -> anyhow::Result<()> {
+        let handler = ctx.file_line_handler(&self.path, ctx.register(self));
+        println!(
+            "Snapshotting {} every {}s",
+            self.path,
+            self.interval_secs.max(1)
+        );
+        run_snapshot_input(self.clone(), ctx.state.clone(), move |line| {
+            handler(vec![line.into_bytes()], None)
+        });
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.stop.stop();
+    }
+}
+
+pub fn run_snapshot_input<F>(
+    input: SnapshotInput,
+    state: Arc<AgentState>,
+    mut handler: F,
+) -> thread::JoinHandle<()>
+where
+    F: FnMut(String) + Send + 'static,
+{
+    thread::spawn(move || {
+        let interval = Duration::from_secs(input.interval_secs.max(1));
+        let max_bytes = input.max_bytes.unwrap_or(DEFAULT_MAX_BYTES);
+        let mut previous: Vec<String> = Vec::new();
+
+        while !input.stop.is_stopped() {
+            match read_snapshot(&input.path, max_bytes) {
+                Ok(content) => {
+                    let lines: Vec<String> = content.lines().map(str::to_string).collect();
+                    match input.mode {
+                        SnapshotMode::ChangedLines => {
+                            for (i, line) in lines.iter().enumerate() {
+                                if !line.trim().is_empty() && previous.get(i) != Some(line) {
+                                    handler(line.clone());
+                                }
+                            }
+                        }
+                        SnapshotMode::Full => {
+                            if lines != previous && !content.trim().is_empty() {
+                                handler(content.trim_end().to_string());
+                            }
+                        }
+                    }
+                    previous = lines;
+                }
+                Err(e) => {
+                    state.record_error(format!("Failed to read snapshot {}: {e}", input.path))
+                }
+            }
+            thread::sleep(interval);
+        }
+    })
+}
+
+/// Reads up to `max_bytes`; virtual files report a zero size, so the length from
+/// metadata is never trusted.
+fn read_snapshot(path: &str, max_bytes: u64) -> std::io::Result<String> {
+    let mut buf = Vec::new();
+    fs::File::open(path)?
+        .take(max_bytes)
+        .read_to_end(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
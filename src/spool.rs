@@ -0,0 +1,462 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::parse_time_span;
+use crate::encryption::{self, Cipher, EncryptionConfig};
+use crate::stats::AgentState;
+use crate::{severity_from_name, Severity};
+
+/// Locked in the buffer directory by the agent using it.
+pub const LOCK_FILE: &str = "buffer.lock";
+
+/// Default `disk_buffer.max_bytes`: 256 MiB per exporter.
+pub const DEFAULT_MAX_BYTES: u64 = 256 * 1024 * 1024;
+
+/// The longest `disk_buffer.retention`, in days: ten years.
+const MAX_RETENTION_DAYS: i64 = 3650;
+
+/// Requests an exporter could not deliver are kept on disk and sent once the endpoint
+/// takes them again, surviving both outages and restarts. Records still queued in memory
+/// are not on disk yet: after a crash the tailed files read them again from their saved
+/// positions (see `checkpoint::Positions`), while the other inputs lose them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DiskBufferConfig {
+    /// Directory holding a subdirectory per exporter; `<state_dir>/buffer` by default.
+    pub dir: Option<String>,
+    /// Space each exporter's buffer may take up; the oldest requests go first beyond it.
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64,
+    /// How long records are kept by severity, e.g. `{ DEBUG = "1h", ERROR = "72h" }`. An
+    /// entry applies to its severity and the ones above, up to the next entry; records
+    /// below the lowest entry are kept as long as it says. Expired records are dropped
+    /// as `disk_buffer_expired`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub retention: BTreeMap<String, String>,
+    pub encryption: Option<EncryptionConfig>,
+}
+
+fn default_max_bytes() -> u64 {
+    DEFAULT_MAX_BYTES
+}
+
+/// `retention` parsed: severity numbers in ascending order, with how long their records
+/// are kept.
+#[derive(Default)]
+pub struct Retention(Vec<(u8, chrono::Duration)>);
+
+impl Retention {
+    pub fn new(config: &BTreeMap<String, String>) -> anyhow::Result<Retention> {
+        let mut levels = config
+            .iter()
+            .map(|(severity, age)| {
+                let Severity { number, .. } = severity_from_name(severity).ok_or_else(|| {
+                    anyhow::anyhow!("Unknown severity {:?} in disk_buffer.retention", severity)
+                })?;
+                let age = parse_time_span(age)
+                    .ok()
+                    .filter(|age| *age <= chrono::Duration::days(MAX_RETENTION_DAYS))
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "disk_buffer.retention {} = {:?} is longer than {} days",
+                            severity,
+                            age,
+                            MAX_RETENTION_DAYS
+                        )
+                    })?;
+                Ok((number, age))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        levels.sort_by_key(|(number, _)| *number);
+        Ok(Retention(levels))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn ttl(&self, severity: u8) -> Option<chrono::Duration> {
+        self.0
+            .iter()
+            .rev()
+            .find(|(number, _)| *number <= severity)
+            .or(self.0.first())
+            .map(|(_, ttl)| *ttl)
+    }
+
+    /// When a log record of an OTLP payload expires, from its severity and read time;
+    /// `None` for one that never does, also when that would be past the dates chrono has.
+    fn expiry(&self, record: &Value) -> Option<DateTime<Utc>> {
+        let severity = record["severityNumber"].as_u64().unwrap_or(0).min(24) as u8;
+        let read = record["observedTimeUnixNano"]
+            .as_str()
+            .or(record["timeUnixNano"].as_str())?
+            .parse::<i64>()
+            .ok()?;
+        DateTime::from_timestamp_nanos(read).checked_add_signed(self.ttl(severity)?)
+    }
+
+    /// Removes the records of `payload` that expired by `now`; returns how many were
+    /// removed and when the next of the others expires.
+    fn expire(&self, payload: &mut Value, now: DateTime<Utc>) -> (usize, Option<DateTime<Utc>>) {
+        let (mut removed, mut next) = (0, None::<DateTime<Utc>>);
+        let resources = payload["resourceLogs"].as_array_mut();
+        for resource in resources.into_iter().flatten() {
+            for scope in resource["scopeLogs"].as_array_mut().into_iter().flatten() {
+                if let Some(records) = scope["logRecords"].as_array_mut() {
+                    records.retain(|record| match self.expiry(record) {
+                        Some(expiry) if expiry <= now => {
+                            removed += 1;
+                            false
+                        }
+                        expiry => {
+                            if let Some(expiry) = expiry {
+                                next = Some(next.map_or(expiry, |n: DateTime<Utc>| n.min(expiry)));
+                            }
+                            true
+                        }
+                    });
+                }
+            }
+            if let Some(scopes) = resource["scopeLogs"].as_array_mut() {
+                scopes.retain(|s| s["logRecords"].as_array().is_some_and(|r| !r.is_empty()));
+            }
+        }
+        if let Some(resources) = payload["resourceLogs"].as_array_mut() {
+            resources.retain(|r| r["scopeLogs"].as_array().is_some_and(|s| !s.is_empty()));
+        }
+        (removed, next)
+    }
+}
+
+/// One buffered request: what it is sent to and its OTLP payload.
+#[derive(Serialize, Deserialize)]
+pub struct Request<P> {
+    pub endpoint: String,
+    pub payload: P,
+}
+
+/// A segment file, named `<sequence>-<records>.json`.
+struct Segment {
+    sequence: u64,
+    records: usize,
+    bytes: u64,
+    /// When its first record expires under `retention`: `None` until it was looked at,
+    /// `Some(None)` when none of its records expire.
+    expires: Option<Option<DateTime<Utc>>>,
+}
+
+impl Segment {
+    fn file_name(&self) -> String {
+        format!("{:020}-{}.json", self.sequence, self.records)
+    }
+
+    fn parse(name: &str, bytes: u64) -> Option<Segment> {
+        let (sequence, records) = name.strip_suffix(".json")?.split_once('-')?;
+        Some(Segment {
+            sequence: sequence.parse().ok()?,
+            records: records.parse().ok()?,
+            bytes,
+            expires: None,
+        })
+    }
+}
+
+/// A segment's contents as written to disk.
+fn seal(cipher: &Option<Arc<Cipher>>, body: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    match cipher {
+        Some(cipher) => cipher.seal(&body),
+        None => Ok(body),
+    }
+}
+
+/// An exporter's buffered requests, one segment file each, sent oldest first.
+pub struct Spool {
+    dir: PathBuf,
+    max_bytes: u64,
+    retention: Retention,
+    segments: VecDeque<Segment>,
+    bytes: u64,
+    cipher: Option<Arc<Cipher>>,
+}
+
+impl Spool {
+    /// The buffer in `dir`, with the requests a previous run left in it.
+    pub fn open(dir: &Path, max_bytes: u64, retention: Retention) -> anyhow::Result<Spool> {
+        fs::create_dir_all(dir)?;
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".tmp") {
+                // Cut short by a crash before it was complete.
+                fs::remove_file(entry.path()).ok();
+            } else if let Some(segment) = Segment::parse(&name, entry.metadata()?.len()) {
+                segments.push(segment);
+            }
+        }
+        segments.sort_by_key(|s| s.sequence);
+        Ok(Spool {
+            dir: dir.to_path_buf(),
+            max_bytes,
+            retention,
+            bytes: segments.iter().map(|s| s.bytes).sum(),
+            segments: segments.into(),
+            cipher: None,
+        })
+    }
+
+    /// Encrypts the requests written from now on. Those written before encryption was
+    /// turned on are still read and sent.
+    pub fn encrypted(mut self, cipher: Arc<Cipher>) -> Spool {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    fn read(&self, path: &Path) -> anyhow::Result<Vec<u8>> {
+        let body = fs::read(path)?;
+        if !body.starts_with(encryption::MAGIC) {
+            return Ok(body);
+        }
+        match self.cipher {
+            Some(ref cipher) => cipher.open(&body),
+            None => Err(anyhow::anyhow!(
+                "Encrypted, but disk_buffer.encryption is not set"
+            )),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Records in the buffer.
+    pub fn records(&self) -> usize {
+        self.segments.iter().map(|s| s.records).sum()
+    }
+
+    /// Writes a request of `records` records to the end of the buffer, then drops the
+    /// oldest requests while the buffer is over its size, counting their records as
+    /// `disk_buffer_full` drops.
+    pub fn push<P: Serialize>(
+        &mut self,
+        request: &Request<P>,
+        records: usize,
+        state: &AgentState,
+    ) -> anyhow::Result<()> {
+        let body = seal(&self.cipher, serde_json::to_vec(request)?)?;
+        let expires = if self.retention.is_empty() {
+            Some(None)
+        } else {
+            // Nothing expires at the epoch, so this only finds the first expiry.
+            let mut payload = serde_json::to_value(&request.payload)?;
+            Some(self.retention.expire(&mut payload, DateTime::UNIX_EPOCH).1)
+        };
+        let segment = Segment {
+            sequence: self.segments.back().map_or(1, |s| s.sequence + 1),
+            records,
+            bytes: body.len() as u64,
+            expires,
+        };
+        if segment.bytes > self.max_bytes {
+            return Err(anyhow::anyhow!(
+                "A request of {} bytes does not fit a disk buffer of {} bytes",
+                segment.bytes,
+                self.max_bytes
+            ));
+        }
+        let path = self.dir.join(segment.file_name());
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, &body)?;
+        fs::rename(&tmp, &path)?;
+        self.bytes += segment.bytes;
+        self.segments.push_back(segment);
+        while self.bytes > self.max_bytes {
+            let records = self.segments.front().map_or(0, |s| s.records);
+            self.pop()?;
+            for _ in 0..records {
+                state.record_drop("disk_buffer_full");
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the records that are past their `retention` at `now`, rewriting the segments
+    /// they were in, so that during a long outage the space goes to the records kept
+    /// longest.
+    pub fn expire(&mut self, now: DateTime<Utc>, state: &AgentState) -> anyhow::Result<()> {
+        if self.retention.is_empty() {
+            return Ok(());
+        }
+        for i in 0..self.segments.len() {
+            if self.segments[i]
+                .expires
+                .is_some_and(|e| e.is_none_or(|e| e > now))
+            {
+                continue;
+            }
+            let path = self.dir.join(self.segments[i].file_name());
+            let read = self.read(&path).ok();
+            let Some(mut request) =
+                read.and_then(|body| serde_json::from_slice::<Request<Value>>(&body).ok())
+            else {
+                // Dropped and reported once it is the next to send.
+                self.segments[i].expires = Some(None);
+                continue;
+            };
+            let (removed, next) = self.retention.expire(&mut request.payload, now);
+            let segment = &mut self.segments[i];
+            segment.expires = Some(next);
+            if removed == 0 {
+                continue;
+            }
+            for _ in 0..removed.min(segment.records) {
+                state.record_drop("disk_buffer_expired");
+            }
+            segment.records = segment.records.saturating_sub(removed);
+            let body = seal(&self.cipher, serde_json::to_vec(&request)?)?;
+            self.bytes -= segment.bytes;
+            segment.bytes = if segment.records == 0 {
+                0
+            } else {
+                let rewritten = self.dir.join(segment.file_name());
+                let tmp = rewritten.with_extension("tmp");
+                fs::write(&tmp, &body)?;
+                fs::rename(&tmp, &rewritten)?;
+                body.len() as u64
+            };
+            self.bytes += segment.bytes;
+            if path != self.dir.join(segment.file_name()) {
+                fs::remove_file(&path)?;
+            }
+        }
+        self.segments.retain(|s| s.records > 0);
+        Ok(())
+    }
+
+    /// The oldest request and its record count.
+    pub fn front(&self) -> Option<anyhow::Result<(Request<serde_json::Value>, usize)>> {
+        let segment = self.segments.front()?;
+        Some(
+            self.read(&self.dir.join(segment.file_name()))
+                .and_then(|body| Ok(serde_json::from_slice(&body)?))
+                .map(|request| (request, segment.records)),
+        )
+    }
+
+    /// Removes the oldest request, once sent or found unreadable.
+    pub fn pop(&mut self) -> anyhow::Result<()> {
+        if let Some(segment) = self.segments.pop_front() {
+            self.bytes -= segment.bytes;
+            fs::remove_file(self.dir.join(segment.file_name()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use serde_json::json;
+
+    #[test]
+    fn oldest_requests_are_dropped_beyond_the_cap_and_the_rest_survive_reopening() {
+        let dir = TempDir::new("spool-test");
+        let state = AgentState::default();
+        let request = |n: u32| Request {
+            endpoint: "http://collector:4318/v1/logs".to_string(),
+            payload: json!({ "n": n }),
+        };
+        let size = serde_json::to_vec(&request(1)).unwrap().len() as u64;
+
+        let mut spool = Spool::open(&dir, size * 2, Retention::default()).unwrap();
+        for n in 1..=3 {
+            spool.push(&request(n), 5, &state).unwrap();
+        }
+        assert_eq!(spool.records(), 10);
+        assert_eq!(state.snapshot().dropped.get("disk_buffer_full"), Some(&5));
+
+        let mut spool = Spool::open(&dir, size * 2, Retention::default()).unwrap();
+        let (front, records) = spool.front().unwrap().unwrap();
+        assert_eq!((front.payload, records), (json!({ "n": 2 }), 5));
+        spool.pop().unwrap();
+        spool.pop().unwrap();
+        assert!(spool.is_empty() && spool.front().is_none());
+    }
+
+    #[test]
+    fn records_expire_by_severity() {
+        let dir = TempDir::new("spool-retention");
+        let state = AgentState::default();
+        let retention = [("DEBUG", "1h"), ("ERROR", "72h")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let read = DateTime::parse_from_rfc3339("2024-03-05T10:00:00Z").unwrap();
+        let record = |severity: u8| {
+            json!({
+                "severityNumber": severity,
+                "observedTimeUnixNano": read.timestamp_nanos_opt().unwrap().to_string(),
+            })
+        };
+        let request = Request {
+            endpoint: "http://collector:4318/v1/logs".to_string(),
+            payload: json!({ "resourceLogs": [{ "scopeLogs": [{
+                "logRecords": [record(5), record(9), record(17), record(21)]
+            }] }] }),
+        };
+        let mut spool =
+            Spool::open(&dir, DEFAULT_MAX_BYTES, Retention::new(&retention).unwrap()).unwrap();
+        spool.push(&request, 4, &state).unwrap();
+
+        spool
+            .expire(read.to_utc() + chrono::Duration::hours(2), &state)
+            .unwrap();
+        let (front, records) = spool.front().unwrap().unwrap();
+        let severities: Vec<_> = front.payload["resourceLogs"][0]["scopeLogs"][0]["logRecords"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["severityNumber"].as_u64().unwrap())
+            .collect();
+        assert_eq!((severities, records), (vec![17, 21], 2));
+
+        spool
+            .expire(read.to_utc() + chrono::Duration::hours(73), &state)
+            .unwrap();
+        assert!(spool.is_empty());
+        assert_eq!(
+            state.snapshot().dropped.get("disk_buffer_expired"),
+            Some(&4)
+        );
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn retentions_past_the_last_date_never_expire() {
+        let long = |age: &str| Retention::new(&BTreeMap::from([("INFO".into(), age.into())]));
+        assert!(long("3650d").is_ok());
+        assert!(matches!(long("3651d"), Err(e) if e.to_string().contains("longer than")));
+        assert!(long("99999999999999d").is_err());
+
+        let dir = TempDir::new("spool-long-retention");
+        let state = AgentState::default();
+        let request = Request {
+            endpoint: "http://collector:4318/v1/logs".to_string(),
+            payload: json!({ "resourceLogs": [{ "scopeLogs": [{ "logRecords": [{
+                "severityNumber": 9,
+                "observedTimeUnixNano": i64::MAX.to_string(),
+            }] }] }] }),
+        };
+        let retention = Retention(vec![(0, chrono::Duration::MAX)]);
+        let mut spool = Spool::open(&dir, DEFAULT_MAX_BYTES, retention).unwrap();
+        spool.push(&request, 1, &state).unwrap();
+        spool.expire(DateTime::<Utc>::MAX_UTC, &state).unwrap();
+        assert_eq!(spool.records(), 1);
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+use crate::failure::Failure;
+
+const APP: &str = "rust-signoz-agent";
+/// State directory of an agent running as root.
+pub const SYSTEM_STATE_DIR: &str = "/var/lib/rust-signoz-agent";
+/// Config directory of an agent running as root.
+pub const SYSTEM_CONFIG_DIR: &str = "/etc/rust-signoz-agent";
+/// Locked by the agent using the state directory.
+pub const LOCK_FILE: &str = "agent.lock";
+
+#[cfg(unix)]
+pub fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions.
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Windows has no root; an elevated agent keeps its state where any other would.
+#[cfg(windows)]
+pub fn is_root() -> bool {
+    false
+}
+
+/// `$<var>` if set and absolute, else `$HOME/<fallback>`.
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(fallback)))
+}
+
+/// Default `state_dir`: `SYSTEM_STATE_DIR` for root, `$XDG_STATE_HOME/rust-signoz-agent`
+/// (`~/.local/state/rust-signoz-agent`) for other users, and the working directory when
+/// there is no home directory either.
+pub fn default_dir() -> String {
+    if is_root() {
+        return SYSTEM_STATE_DIR.to_string();
+    }
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+        .map(|dir| dir.join(APP).display().to_string())
+        .unwrap_or_else(|| ".".to_string())
+}
+
+/// Where config.toml is read, and created on first run: `$RUST_SIGNOZ_AGENT_CONFIG` if
+/// set, ./config.toml if it exists, else `SYSTEM_CONFIG_DIR` for root and
+/// `$XDG_CONFIG_HOME/rust-signoz-agent` (`~/.config/rust-signoz-agent`) for other users.
+pub fn config_path() -> String {
+    if let Some(path) = std::env::var_os("RUST_SIGNOZ_AGENT_CONFIG") {
+        return path.to_string_lossy().into_owned();
+    }
+    let local = "./config.toml";
+    if Path::new(local).exists() {
+        return local.to_string();
+    }
+    let dir = if is_root() {
+        Some(PathBuf::from(SYSTEM_CONFIG_DIR))
+    } else {
+        xdg_dir("XDG_CONFIG_HOME", ".config").map(|dir| dir.join(APP))
+    };
+    dir.map(|dir| dir.join("config.toml").display().to_string())
+        .unwrap_or_else(|| local.to_string())
+}
+
+/// `name` inside `dir`.
+pub fn file_in(dir: &str, name: &str) -> String {
+    Path::new(dir).join(name).display().to_string()
+}
+
+/// For `read_only_root`: fails unless every path in `writes` (what is written, where)
+/// lies inside `dir`, the only writable location.
+pub fn check_contained(dir: &str, writes: &[(&str, &str)]) -> Result<()> {
+    let absolute = |path: &str| {
+        let path = Path::new(path);
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            std::env::current_dir().unwrap_or_default().join(path)
+        };
+        // Lexically, as the files need not exist yet.
+        path.components().fold(PathBuf::new(), |mut out, c| {
+            match c {
+                std::path::Component::CurDir => {}
+                std::path::Component::ParentDir => {
+                    out.pop();
+                }
+                c => out.push(c),
+            }
+            out
+        })
+    };
+    let dir = absolute(dir);
+    let outside: Vec<String> = writes
+        .iter()
+        .filter(|(_, path)| !absolute(path).starts_with(&dir))
+        .map(|(what, path)| format!("{} {}", what, path))
+        .collect();
+    if outside.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow::anyhow!(
+        "read_only_root is set, but these are written outside state_dir {}: {}",
+        dir.display(),
+        outside.join(", ")
+    )
+    .context(Failure::ConfigInvalid))
+}
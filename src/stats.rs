@@ -0,0 +1,456 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::checkpoint::Checkpoint;
+use crate::exporter::ExporterStats;
+use crate::listener::ListenerStats;
+use crate::rules::RuleStats;
+
+const MAX_RECENT_ERRORS: usize = 20;
+
+/// Counters for one input, updated by its reader thread.
+pub struct InputStats {
+    pub name: String,
+    pub lines: AtomicU64,
+    /// Bytes written to the source but not read yet (files only).
+    pub lag_bytes: AtomicU64,
+    /// Lines that were not one whole record, with `resync` set (files only).
+    pub resyncs: AtomicU64,
+    /// Minutes whose volume jumped or dropped, with `volume_alerts` set.
+    pub volume_anomalies: AtomicU64,
+    /// Lines its `log_formats` entry did not parse, with `parse_quarantine` set.
+    pub parse_failures: AtomicU64,
+    /// Too many lines failed to parse: they are shipped unparsed.
+    pub quarantined: AtomicBool,
+    /// Quarantined with `action = "pause"`: the file is not read on.
+    pub paused: AtomicBool,
+    pub last_line_unix: AtomicI64,
+    /// Health of the sockets of network inputs.
+    pub listener: ListenerStats,
+    sequence: AtomicU64,
+    /// Of the batch being handed to the input's handler, set by its reader around it.
+    checkpoint: Mutex<Option<Arc<Checkpoint>>>,
+}
+
+impl InputStats {
+    /// Next value of the input's monotonic record sequence.
+    pub fn next_sequence(&self) -> u64 {
+        self.sequence.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// The sequence value the next record will get.
+    pub fn sequence(&self) -> u64 {
+        self.sequence.load(Ordering::SeqCst)
+    }
+
+    /// Continues the sequence from where a previous run left it.
+    pub fn resume_sequence(&self, next: u64) {
+        self.sequence.fetch_max(next, Ordering::SeqCst);
+    }
+
+    /// Hands `checkpoint` on with the records made of the lines handed over until it is
+    /// cleared.
+    pub fn set_checkpoint(&self, checkpoint: Option<Arc<Checkpoint>>) {
+        *self.checkpoint.lock().unwrap() = checkpoint;
+    }
+
+    /// What the records made now go with, to take their lines' position once they are
+    /// done with.
+    pub fn checkpoint(&self) -> Option<Arc<Checkpoint>> {
+        self.checkpoint.lock().unwrap().clone()
+    }
+
+    pub fn record_line(&self) {
+        self.lines.fetch_add(1, Ordering::Relaxed);
+        self.last_line_unix
+            .store(Utc::now().timestamp(), Ordering::Relaxed);
+    }
+}
+
+/// A step every record goes through, timed to locate pipeline bottlenecks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Reading a line from its source.
+    Read,
+    /// Turning a line into a record: attributes, sequence and timestamp extraction.
+    Parse,
+    /// Age and sampling checks in the dispatcher.
+    Filter,
+    /// Custom processors registered on the `Agent`.
+    Process,
+    /// Delivery to an exporter, including retries.
+    Export,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [
+        Stage::Read,
+        Stage::Parse,
+        Stage::Filter,
+        Stage::Process,
+        Stage::Export,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stage::Read => "read",
+            Stage::Parse => "parse",
+            Stage::Filter => "filter",
+            Stage::Process => "process",
+            Stage::Export => "export",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct StageStats {
+    pub records: AtomicU64,
+    pub busy_nanos: AtomicU64,
+}
+
+/// Shared state of a running agent: queue bookkeeping, per-component counters and the
+/// most recent errors, as reported through the admin socket.
+#[derive(Default)]
+pub struct AgentState {
+    pub queued: AtomicUsize,
+    /// Records held back by multiline inputs, waiting for their continuation lines.
+    pub joining: AtomicUsize,
+    pub flushing: AtomicBool,
+    /// Set once the agent shuts down, as opposed to inputs being stopped by a reload.
+    pub stopping: AtomicBool,
+    /// Set by an input whose end is the agent's, stdin once it was closed.
+    pub input_ended: AtomicBool,
+    /// With `--oneshot`, the files not yet read to their end; see `unread`.
+    pub files_unread: AtomicUsize,
+    /// Set by `rust-signoz-agent pause`: exporters hold on to their records, in the disk
+    /// buffer where there is one, until `resume`.
+    pub exports_paused: AtomicBool,
+    /// Toggled by SIGUSR2: every request is printed to the agent log before it is sent.
+    pub debug_logging: AtomicBool,
+    /// Paces the reading of backlog across inputs, see `replay`.
+    pub replay_limiter: OnceLock<crate::replay::Limiter>,
+    pub inputs: Mutex<Vec<Arc<InputStats>>>,
+    pub exporters: Mutex<Vec<Arc<ExporterStats>>>,
+    pub rules: Mutex<Vec<Arc<RuleStats>>>,
+    /// Records dropped before export, keyed by reason.
+    dropped: Mutex<BTreeMap<&'static str, u64>>,
+    recent_errors: Mutex<VecDeque<ErrorEntry>>,
+    stages: [StageStats; Stage::ALL.len()],
+    /// Reloads of the config file that passed validation.
+    pub config_reloads: AtomicU64,
+    /// Reloads rejected by validation, which leave the running config in effect.
+    pub config_reload_failures: AtomicU64,
+    /// Records whose event time `[timestamp_check]` found too far from the time read.
+    pub suspect_timestamps: AtomicU64,
+    pub last_config_error: Mutex<Option<ErrorEntry>>,
+}
+
+/// A file `--oneshot` waits for, counted in `files_unread` until it is dropped: once the
+/// file was read to its end, or its reader gave up.
+pub struct Unread(Arc<AgentState>);
+
+impl Drop for Unread {
+    fn drop(&mut self) {
+        self.0.files_unread.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl std::fmt::Debug for Unread {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Unread")
+    }
+}
+
+impl AgentState {
+    pub fn unread(self: &Arc<Self>) -> Unread {
+        self.files_unread.fetch_add(1, Ordering::SeqCst);
+        Unread(self.clone())
+    }
+
+    /// Records not yet delivered, counting the shared queue and every exporter queue.
+    pub fn pending(&self) -> usize {
+        let exporters = self.exporters.lock().unwrap();
+        self.queued.load(Ordering::SeqCst)
+            + self.joining.load(Ordering::SeqCst)
+            + exporters
+                .iter()
+                .map(|e| e.pending.load(Ordering::SeqCst))
+                .sum::<usize>()
+    }
+
+    /// Records the exporters hold in their disk buffers, not delivered either.
+    #[cfg(unix)]
+    pub fn buffered(&self) -> usize {
+        let exporters = self.exporters.lock().unwrap();
+        exporters
+            .iter()
+            .map(|e| e.buffered.load(Ordering::SeqCst))
+            .sum()
+    }
+
+    /// The counters of input `name`, kept across restarts of the input by a reload.
+    pub fn register_input(&self, name: &str) -> Arc<InputStats> {
+        let mut inputs = self.inputs.lock().unwrap();
+        if let Some(stats) = inputs.iter().find(|i| i.name == name) {
+            return stats.clone();
+        }
+        let stats = Arc::new(InputStats {
+            name: name.to_string(),
+            lines: AtomicU64::new(0),
+            lag_bytes: AtomicU64::new(0),
+            resyncs: AtomicU64::new(0),
+            volume_anomalies: AtomicU64::new(0),
+            parse_failures: AtomicU64::new(0),
+            quarantined: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            last_line_unix: AtomicI64::new(0),
+            listener: ListenerStats::default(),
+            sequence: AtomicU64::new(0),
+            checkpoint: Mutex::new(None),
+        });
+        inputs.push(stats.clone());
+        stats
+    }
+
+    /// Drops the counters of an input a reload removed.
+    pub fn retire_input(&self, name: &str) {
+        self.inputs.lock().unwrap().retain(|i| i.name != name);
+    }
+
+    /// Accounts `records` records that together spent `elapsed` in `stage`.
+    pub fn record_stage(&self, stage: Stage, records: u64, elapsed: Duration) {
+        let stats = &self.stages[stage as usize];
+        stats.records.fetch_add(records, Ordering::Relaxed);
+        stats
+            .busy_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn stage(&self, stage: Stage) -> &StageStats {
+        &self.stages[stage as usize]
+    }
+
+    pub fn record_drop(&self, reason: &'static str) {
+        *self.dropped.lock().unwrap().entry(reason).or_insert(0) += 1;
+    }
+
+    /// Prints the error and keeps it for `status`/`top`.
+    pub fn record_error(&self, message: String) {
+        eprintln!("{}", message);
+        let mut errors = self.recent_errors.lock().unwrap();
+        if errors.len() == MAX_RECENT_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back(ErrorEntry {
+            time_unix: Utc::now().timestamp(),
+            message,
+        });
+    }
+
+    pub fn snapshot(&self) -> StatusSnapshot {
+        StatusSnapshot {
+            queued: self.queued.load(Ordering::SeqCst),
+            exports_paused: self.exports_paused.load(Ordering::SeqCst),
+            dropped: self
+                .dropped
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(reason, count)| (reason.to_string(), *count))
+                .collect(),
+            inputs: self
+                .inputs
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|i| InputSnapshot {
+                    name: i.name.clone(),
+                    lines: i.lines.load(Ordering::Relaxed),
+                    lag_bytes: i.lag_bytes.load(Ordering::Relaxed),
+                    resyncs: i.resyncs.load(Ordering::Relaxed),
+                    volume_anomalies: i.volume_anomalies.load(Ordering::Relaxed),
+                    parse_failures: i.parse_failures.load(Ordering::Relaxed),
+                    quarantined: i.quarantined.load(Ordering::Relaxed),
+                    last_line_unix: i.last_line_unix.load(Ordering::Relaxed),
+                    listener: i.listener.is_listener().then(|| ListenerSnapshot {
+                        bound: i.listener.bound.load(Ordering::Relaxed),
+                        down: i.listener.down.load(Ordering::Relaxed),
+                        rebinds: i.listener.rebinds.load(Ordering::Relaxed),
+                        connections: i.listener.connections.load(Ordering::Relaxed),
+                        idle: i.listener.idle.load(Ordering::Relaxed),
+                    }),
+                })
+                .collect(),
+            exporters: self
+                .exporters
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|e| ExporterSnapshot {
+                    name: e.name.clone(),
+                    pending: e.pending.load(Ordering::SeqCst),
+                    sent: e.sent.load(Ordering::SeqCst),
+                    failed: e.failed.load(Ordering::SeqCst),
+                    consecutive_failures: e.consecutive_failures.load(Ordering::SeqCst),
+                    retries: e.retries.load(Ordering::SeqCst),
+                    encoding: e.encoding().to_string(),
+                    cert_expires_unix: e.cert_expires_unix.load(Ordering::SeqCst),
+                    auth_failures: e.auth_failures.load(Ordering::SeqCst),
+                    not_found: e.not_found.load(Ordering::SeqCst),
+                    buffered: e.buffered.load(Ordering::SeqCst),
+                    queue_full: e.queue_full.load(Ordering::SeqCst),
+                    server_version: e.server_version.lock().unwrap().clone(),
+                })
+                .collect(),
+            rules: self
+                .rules
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|r| RuleSnapshot {
+                    name: r.name.clone(),
+                    matches: r.matches.load(Ordering::Relaxed),
+                    hits: r.hits.load(Ordering::Relaxed),
+                    prefiltered: r.prefiltered.load(Ordering::Relaxed),
+                    busy_nanos: r.busy_nanos.load(Ordering::Relaxed),
+                    slow: r.slow.load(Ordering::Relaxed),
+                })
+                .collect(),
+            recent_errors: self.recent_errors.lock().unwrap().iter().cloned().collect(),
+            config_reloads: self.config_reloads.load(Ordering::SeqCst),
+            config_reload_failures: self.config_reload_failures.load(Ordering::SeqCst),
+            suspect_timestamps: self.suspect_timestamps.load(Ordering::Relaxed),
+            last_config_error: self.last_config_error.lock().unwrap().clone(),
+            stages: Stage::ALL
+                .iter()
+                .map(|&stage| StageSnapshot {
+                    stage: stage.as_str().to_string(),
+                    records: self.stage(stage).records.load(Ordering::Relaxed),
+                    busy_nanos: self.stage(stage).busy_nanos.load(Ordering::Relaxed),
+                })
+                .collect(),
+            regex_cache: crate::rules::cache_snapshot(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErrorEntry {
+    pub time_unix: i64,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct InputSnapshot {
+    pub name: String,
+    pub lines: u64,
+    pub lag_bytes: u64,
+    #[serde(default)]
+    pub resyncs: u64,
+    #[serde(default)]
+    pub volume_anomalies: u64,
+    #[serde(default)]
+    pub parse_failures: u64,
+    #[serde(default)]
+    pub quarantined: bool,
+    pub last_line_unix: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listener: Option<ListenerSnapshot>,
+}
+
+/// `ListenerStats` of a network input.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListenerSnapshot {
+    pub bound: u64,
+    pub down: u64,
+    pub rebinds: u64,
+    pub connections: u64,
+    pub idle: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExporterSnapshot {
+    pub name: String,
+    pub pending: usize,
+    pub sent: u64,
+    pub failed: u64,
+    pub consecutive_failures: u64,
+    #[serde(default)]
+    pub retries: u64,
+    #[serde(default)]
+    pub encoding: String,
+    #[serde(default)]
+    pub cert_expires_unix: i64,
+    #[serde(default)]
+    pub auth_failures: u64,
+    /// Requests answered 404/405, from an endpoint path taking no logs.
+    #[serde(default)]
+    pub not_found: u64,
+    /// Records waiting in the exporter's disk buffer.
+    #[serde(default)]
+    pub buffered: usize,
+    /// Records dropped because the exporter's queue was full.
+    #[serde(default)]
+    pub queue_full: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_version: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StatusSnapshot {
+    pub queued: usize,
+    #[serde(default)]
+    pub exports_paused: bool,
+    #[serde(default)]
+    pub dropped: BTreeMap<String, u64>,
+    pub inputs: Vec<InputSnapshot>,
+    pub exporters: Vec<ExporterSnapshot>,
+    #[serde(default)]
+    pub rules: Vec<RuleSnapshot>,
+    pub recent_errors: Vec<ErrorEntry>,
+    #[serde(default)]
+    pub config_reloads: u64,
+    #[serde(default)]
+    pub config_reload_failures: u64,
+    #[serde(default)]
+    pub suspect_timestamps: u64,
+    /// Why the last rejected reload failed.
+    #[serde(default)]
+    pub last_config_error: Option<ErrorEntry>,
+    #[serde(default)]
+    pub stages: Vec<StageSnapshot>,
+    #[serde(default)]
+    pub regex_cache: RegexCacheSnapshot,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct RegexCacheSnapshot {
+    /// Distinct patterns compiled.
+    pub patterns: usize,
+    /// Compilations the cache saved.
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StageSnapshot {
+    pub stage: String,
+    pub records: u64,
+    pub busy_nanos: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RuleSnapshot {
+    pub name: String,
+    pub matches: u64,
+    #[serde(default)]
+    pub hits: u64,
+    #[serde(default)]
+    pub prefiltered: u64,
+    pub busy_nanos: u64,
+    pub slow: u64,
+}
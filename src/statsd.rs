@@ -0,0 +1,311 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::input::{Input, InputContext, StopFlag};
+use crate::listener::Watch;
+use crate::metrics::{
+    self, Gauge, Metric, MetricData, MetricsExporter, NumberDataPoint, Sum, Summary,
+    SummaryDataPoint, ValueAtQuantile,
+};
+use crate::stats::{AgentState, InputStats};
+use crate::{activation, string_kv, Config};
+
+/// StatsD (and DogStatsD-tagged) UDP listener whose aggregates are exported as OTLP metrics.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StatsdConfig {
+    #[serde(default = "default_bind")]
+    pub bind: String,
+    #[serde(default = "default_flush_interval")]
+    pub flush_interval_secs: u64,
+    #[serde(skip)]
+    pub stop: StopFlag,
+}
+
+impl Input for StatsdConfig {
+    fn describe(&self) -> String {
+        format!("statsd:{}", self.bind)
+    }
+
+    fn start(&mut self, ctx: &InputContext) -> anyhow::Result<()> {
+        let stats = ctx.register(self);
+        let watch = ctx.listener_watch(format!("StatsD listener {}", self.bind), &stats);
+        run_statsd_input(
+            self.clone(),
+            ctx.config.clone(),
+            ctx.state.clone(),
+            stats,
+            watch,
+        )?;
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.stop.stop();
+    }
+}
+
+fn default_bind() -> String {
+    "127.0.0.1:8125".to_string()
+}
+
+fn default_flush_interval() -> u64 {
+    10
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Timer,
+    Set,
+}
+
+#[derive(Debug)]
+struct Sample {
+    name: String,
+    value: String,
+    kind: Kind,
+    rate: f64,
+    tags: BTreeMap<String, String>,
+}
+
+/// Parses `name:value|type[|@rate][|#tag:value,...]`.
+fn parse_line(line: &str) -> Option<Sample> {
+    let (name, rest) = line.split_once(':')?;
+    let mut fields = rest.split('|');
+    let value = fields.next()?.trim().to_string();
+    let kind = match fields.next()?.trim() {
+        "c" => Kind::Counter,
+        "g" => Kind::Gauge,
+        "ms" | "h" | "d" => Kind::Timer,
+        "s" => Kind::Set,
+        _ => return None,
+    };
+
+    let mut rate = 1.0;
+    let mut tags = BTreeMap::new();
+    for field in fields {
+        if let Some(r) = field.strip_prefix('@') {
+            rate = r.parse().ok().filter(|r: &f64| *r > 0.0)?;
+        } else if let Some(t) = field.strip_prefix('#') {
+            for tag in t.split(',').filter(|t| !t.is_empty()) {
+                let (k, v) = tag.split_once(':').unwrap_or((tag, ""));
+                tags.insert(k.to_string(), v.to_string());
+            }
+        }
+    }
+
+    if name.is_empty() || (kind != Kind::Set && value.parse::<f64>().is_err()) {
+        return None;
+    }
+    Some(Sample {
+        name: name.to_string(),
+        value,
+        kind,
+        rate,
+        tags,
+    })
+}
+
+type SeriesKey = (String, BTreeMap<String, String>);
+
+#[derive(Default)]
+struct Aggregator {
+    counters: HashMap<SeriesKey, f64>,
+    gauges: HashMap<SeriesKey, f64>,
+    timers: HashMap<SeriesKey, Vec<f64>>,
+    sets: HashMap<SeriesKey, HashSet<String>>,
+}
+
+impl Aggregator {
+    fn add(&mut self, sample: Sample) {
+        let key = (sample.name, sample.tags);
+        match sample.kind {
+            Kind::Counter => {
+                let value: f64 = sample.value.parse().unwrap_or(0.0);
+                *self.counters.entry(key).or_insert(0.0) += value / sample.rate;
+            }
+            Kind::Gauge => {
+                let value: f64 = sample.value.parse().unwrap_or(0.0);
+                let relative = sample.value.starts_with('+') || sample.value.starts_with('-');
+                let gauge = self.gauges.entry(key).or_insert(0.0);
+                if relative {
+                    *gauge += value;
+                } else {
+                    *gauge = value;
+                }
+            }
+            Kind::Timer => {
+                let value: f64 = sample.value.parse().unwrap_or(0.0);
+                self.timers.entry(key).or_default().push(value);
+            }
+            Kind::Set => {
+                self.sets.entry(key).or_default().insert(sample.value);
+            }
+        }
+    }
+
+    /// Drains the interval's counters, timers and sets; gauges keep their last value and
+    /// are reported again on every flush, as in the reference StatsD server.
+    fn flush(&mut self, start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<Metric> {
+        let start = metrics::unix_nanos(start);
+        let end = metrics::unix_nanos(end);
+        let point = |tags: &BTreeMap<String, String>, value: f64| NumberDataPoint {
+            attributes: tags.iter().map(|(k, v)| string_kv(k, v)).collect(),
+            start_time_unix_nano: start.clone(),
+            time_unix_nano: end.clone(),
+            as_double: value,
+        };
+
+        let mut out = Vec::new();
+        for ((name, tags), value) in self.counters.drain() {
+            out.push(Metric {
+                name,
+                unit: String::new(),
+                data: MetricData::Sum(Sum {
+                    aggregation_temporality: metrics::DELTA,
+                    is_monotonic: true,
+                    data_points: vec![point(&tags, value)],
+                }),
+            });
+        }
+        for ((name, tags), value) in &self.gauges {
+            out.push(Metric {
+                name: name.clone(),
+                unit: String::new(),
+                data: MetricData::Gauge(Gauge {
+                    data_points: vec![point(tags, *value)],
+                }),
+            });
+        }
+        for ((name, tags), members) in self.sets.drain() {
+            out.push(Metric {
+                name,
+                unit: String::new(),
+                data: MetricData::Gauge(Gauge {
+                    data_points: vec![point(&tags, members.len() as f64)],
+                }),
+            });
+        }
+        for ((name, tags), mut values) in self.timers.drain() {
+            values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            let quantile = |q: f64| {
+                let index = ((values.len() - 1) as f64 * q).round() as usize;
+                ValueAtQuantile {
+                    quantile: q,
+                    value: values[index],
+                }
+            };
+            out.push(Metric {
+                name,
+                unit: "ms".to_string(),
+                data: MetricData::Summary(Summary {
+                    data_points: vec![SummaryDataPoint {
+                        attributes: tags.iter().map(|(k, v)| string_kv(k, v)).collect(),
+                        start_time_unix_nano: start.clone(),
+                        time_unix_nano: end.clone(),
+                        count: values.len().to_string(),
+                        sum: values.iter().sum(),
+                        quantile_values: [0.0, 0.5, 0.9, 0.99, 1.0]
+                            .into_iter()
+                            .map(quantile)
+                            .collect(),
+                    }],
+                }),
+            });
+        }
+        out
+    }
+}
+
+pub fn run_statsd_input(
+    statsd: StatsdConfig,
+    config: Arc<Config>,
+    state: Arc<AgentState>,
+    stats: Arc<InputStats>,
+    mut watch: Watch,
+) -> anyhow::Result<thread::JoinHandle<()>> {
+    let address = statsd.bind.clone();
+    let open = move || {
+        let socket = activation::udp(&address).unwrap_or_else(|| UdpSocket::bind(&address))?;
+        socket.set_read_timeout(Some(Duration::from_millis(200)))?;
+        Ok(socket)
+    };
+    let mut socket = watch.start(&open)?;
+    if socket.is_some() {
+        println!("StatsD listener on {}", statsd.bind);
+    }
+
+    Ok(thread::spawn(move || {
+        let exporter = MetricsExporter::new(&config);
+        let interval = Duration::from_secs(statsd.flush_interval_secs.max(1));
+        let mut aggregator = Aggregator::default();
+        let mut buf = [0u8; 65535];
+        let mut last_flush = Instant::now();
+        let mut interval_start = Utc::now();
+
+        loop {
+            let stopping = statsd.stop.is_stopped();
+            let received = match socket {
+                None => {
+                    socket = watch.rebind(&open);
+                    None
+                }
+                Some(_) if watch.idle() => {
+                    socket = None;
+                    None
+                }
+                Some(ref bound) => Some(bound.recv_from(&mut buf)),
+            };
+            match received {
+                None => {}
+                Some(Ok((len, _))) => {
+                    for line in String::from_utf8_lossy(&buf[..len]).lines() {
+                        match parse_line(line.trim()) {
+                            Some(sample) => {
+                                stats.record_line();
+                                aggregator.add(sample);
+                            }
+                            None if !line.trim().is_empty() => {
+                                state.record_drop("statsd_unparsable");
+                            }
+                            None => {}
+                        }
+                    }
+                }
+                Some(Err(e))
+                    if e.kind() == std::io::ErrorKind::WouldBlock
+                        || e.kind() == std::io::ErrorKind::TimedOut => {}
+                Some(Err(e)) => {
+                    let rebind = watch.failed(format!("receive error: {e}"));
+                    if rebind {
+                        socket = None;
+                    }
+                }
+            }
+
+            // A stopping listener flushes what it aggregated so far before exiting.
+            if stopping || last_flush.elapsed() >= interval {
+                let now = Utc::now();
+                let metrics = aggregator.flush(interval_start, now);
+                if !metrics.is_empty() {
+                    let payload = metrics::build_metrics_payload(&config, "statsd", metrics);
+                    if let Err(e) = exporter.export(&payload) {
+                        state.record_error(e);
+                    }
+                }
+                last_flush = Instant::now();
+                interval_start = now;
+            }
+            if stopping {
+                break;
+            }
+        }
+        watch.close();
+    }))
+}
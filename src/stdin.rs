@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+
+use crate::input::{Input, InputContext, StopFlag};
+use crate::tailer::{trim_line_end, DEFAULT_READ_BUFFER_BYTES};
+use crate::trace_context::TraceContext;
+
+/// Lines piped to the agent, as in `myapp 2>&1 | rust-signoz-agent --stdin`, parsed like
+/// those of a tailed file named "stdin". The agent shuts down once the pipe is closed.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct StdinInput {
+    #[serde(skip)]
+    pub stop: StopFlag,
+}
+
+impl Input for StdinInput {
+    fn describe(&self) -> String {
+        "stdin".to_string()
+    }
+
+    fn start(&mut self, ctx: &InputContext) -> anyhow::Result<()> {
+        // The job the agent runs in, whose trace what it pipes in is part of.
+        let trace = TraceContext::from_env().map(Arc::new);
+        let handler = ctx.traced_line_handler("stdin", ctx.register(self), trace);
+        let capacity = ctx
+            .config
+            .read_buffer_bytes
+            .unwrap_or(DEFAULT_READ_BUFFER_BYTES);
+        let state = ctx.state.clone();
+        let stop = self.stop.clone();
+        println!("Reading records from stdin");
+        thread::spawn(move || {
+            let mut reader = BufReader::with_capacity(capacity, std::io::stdin());
+            let mut lines = Vec::new();
+            let mut line = Vec::new();
+            while !stop.is_stopped() {
+                line.clear();
+                match reader.read_until(b'\n', &mut line) {
+                    Ok(0) => break,
+                    Ok(_) => lines.push(trim_line_end(&line).to_vec()),
+                    Err(e) => {
+                        state.record_error(format!("Failed to read stdin: {}", e));
+                        break;
+                    }
+                }
+                // What is already buffered goes out with this batch.
+                if reader.buffer().is_empty() {
+                    handler(std::mem::take(&mut lines), None);
+                }
+            }
+            if !lines.is_empty() {
+                handler(lines, None);
+            }
+            if !stop.is_stopped() {
+                println!("stdin was closed, shutting down");
+                state.input_ended.store(true, Ordering::SeqCst);
+            }
+        });
+        Ok(())
+    }
+
+    /// Stops at the next line; a read waiting for one is left to finish.
+    fn stop(&mut self) {
+        self.stop.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::Positions;
+    use crate::queue::{self, FairQueue};
+    use crate::stats::AgentState;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn piped_records_belong_to_the_jobs_trace() {
+        let dir = TempDir::new("stdin-trace");
+        let state = Arc::new(AgentState::default());
+        let ctx = InputContext {
+            config: Arc::new(toml::from_str("endpoint = \"http://localhost:4318\"").unwrap()),
+            path_rules: Vec::new(),
+            queue: FairQueue::new(
+                Default::default(),
+                queue::DEFAULT_CAPACITY,
+                queue::Overflow::Block,
+                state.clone(),
+                Default::default(),
+            ),
+            state: state.clone(),
+            positions: Positions::load(dir.to_str().unwrap()).unwrap(),
+            catch_up: None,
+        };
+        let trace = TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+        let handler = ctx.traced_line_handler(
+            "stdin",
+            ctx.register(&StdinInput::default()),
+            trace.clone().map(Arc::new),
+        );
+        handler(vec![b"started".to_vec(), b"done".to_vec()], None);
+        let (_, records) = ctx.queue.recv();
+        assert_eq!(records.len(), 2);
+        for record in records {
+            assert_eq!(record.trace.as_deref(), trace.as_ref());
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::rules::{self, RegexLimits};
+
+/// How the parts of a message that a syslog daemon split across lines are put back
+/// together, before multiline joining and parsing see them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StitchConfig {
+    /// Matches the marker of a part the next line continues, such as `\\$` or
+    /// `\.\.\.$`; it is removed.
+    pub marker: String,
+    /// Matches what the next parts repeat of the first, such as the syslog header; it
+    /// is removed before they are appended.
+    pub continuation_prefix: Option<String>,
+    /// Parts stitched into one record at most; the line after them starts a new one.
+    #[serde(default = "default_max_parts")]
+    pub max_parts: usize,
+    /// How long a marked part waits for its continuation before it is sent as it is.
+    #[serde(default = "default_flush_timeout_ms")]
+    pub flush_timeout_ms: u64,
+}
+
+fn default_max_parts() -> usize {
+    16
+}
+
+fn default_flush_timeout_ms() -> u64 {
+    1000
+}
+
+/// Appends continuation lines to the part before them, without a separator, since they
+/// are pieces of one line.
+pub struct Stitcher {
+    marker: Regex,
+    prefix: Option<Regex>,
+    max_parts: usize,
+    flush_timeout: Duration,
+    pending: Option<String>,
+    parts: usize,
+    updated: Instant,
+}
+
+impl Stitcher {
+    pub fn new(
+        name: &str,
+        config: &StitchConfig,
+        limits: &RegexLimits,
+    ) -> anyhow::Result<Stitcher> {
+        if config.max_parts == 0 {
+            return Err(anyhow::anyhow!("stitch max_parts must be greater than 0"));
+        }
+        let prefix = match config.continuation_prefix {
+            Some(ref prefix) => Some(rules::compile(
+                &format!("stitch {} continuation_prefix", name),
+                prefix,
+                limits,
+            )?),
+            None => None,
+        };
+        Ok(Stitcher {
+            marker: rules::compile(&format!("stitch {} marker", name), &config.marker, limits)?,
+            prefix,
+            max_parts: config.max_parts,
+            flush_timeout: Duration::from_millis(config.flush_timeout_ms),
+            pending: None,
+            parts: 0,
+            updated: Instant::now(),
+        })
+    }
+
+    /// Adds read lines (without their terminators) and returns the lines they complete.
+    pub fn push(&mut self, lines: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+        let mut complete = Vec::new();
+        for line in lines {
+            let mut part = String::from_utf8_lossy(&line).into_owned();
+            let marked = self.marker.find(&part).map(|m| m.range());
+            if let Some(ref range) = marked {
+                part.replace_range(range.clone(), "");
+            }
+            let record = match self.pending.take() {
+                Some(mut record) => {
+                    let start = self
+                        .prefix
+                        .as_ref()
+                        .and_then(|p| p.find(&part))
+                        .filter(|m| m.start() == 0)
+                        .map_or(0, |m| m.end());
+                    record.push_str(&part[start..]);
+                    self.parts += 1;
+                    record
+                }
+                None => {
+                    self.parts = 1;
+                    part
+                }
+            };
+            if marked.is_some() && self.parts < self.max_parts {
+                self.pending = Some(record);
+            } else {
+                complete.push(record.into_bytes());
+            }
+        }
+        self.updated = Instant::now();
+        complete
+    }
+
+    /// The line being stitched, once its continuation did not come within the flush
+    /// timeout.
+    pub fn expired(&mut self) -> Option<Vec<u8>> {
+        if self.updated.elapsed() >= self.flush_timeout {
+            self.take()
+        } else {
+            None
+        }
+    }
+
+    /// The line being stitched, complete or not.
+    pub fn take(&mut self) -> Option<Vec<u8>> {
+        self.parts = 0;
+        self.pending.take().map(String::into_bytes)
+    }
+
+    pub fn is_stitching(&self) -> bool {
+        self.pending.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marked_parts_are_stitched_without_their_repeated_header() {
+        let mut stitcher = Stitcher::new(
+            "/var/log/syslog",
+            &StitchConfig {
+                marker: r"\\$".to_string(),
+                continuation_prefix: Some(r"^\w{3} [ \d]\d [\d:]{8} \S+ \S+: ".to_string()),
+                max_parts: 16,
+                flush_timeout_ms: 1000,
+            },
+            &RegexLimits::default(),
+        )
+        .unwrap();
+        let lines = |lines: &[&str]| lines.iter().map(|l| l.as_bytes().to_vec()).collect();
+        let stitched = stitcher.push(lines(&[
+            r#"Mar  5 10:11:12 web1 app[42]: request payload={"id": \"#,
+            r#"Mar  5 10:11:12 web1 app[42]: 7, "ok": true}"#,
+            "Mar  5 10:11:13 web1 app[42]: next",
+        ]));
+        assert_eq!(
+            stitched,
+            vec![
+                br#"Mar  5 10:11:12 web1 app[42]: request payload={"id": 7, "ok": true}"#.to_vec(),
+                b"Mar  5 10:11:13 web1 app[42]: next".to_vec(),
+            ]
+        );
+        assert!(!stitcher.is_stitching());
+    }
+}
@@ -0,0 +1,588 @@
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeZone, Utc};
+use native_tls::{Identity, TlsAcceptor};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{ErrorKind, Read};
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::input::{Input, InputContext, StopFlag};
+use crate::listener::Watch;
+use crate::stats::{AgentState, InputStats, Stage};
+use crate::{activation, enqueue, queue, severity_from_name, Attributes, Body, Record, Severity};
+
+/// How often the listeners check whether they were stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// Longest message taken from a TCP stream; a longer one is cut and the rest read as the
+/// next message.
+const MAX_MESSAGE_BYTES: usize = 64 * 1024;
+
+/// Facility names by code, as syslog.conf spells them.
+const FACILITIES: [&str; 24] = [
+    "kern",
+    "user",
+    "mail",
+    "daemon",
+    "auth",
+    "syslog",
+    "lpr",
+    "news",
+    "uucp",
+    "cron",
+    "authpriv",
+    "ftp",
+    "ntp",
+    "security",
+    "console",
+    "solaris-cron",
+    "local0",
+    "local1",
+    "local2",
+    "local3",
+    "local4",
+    "local5",
+    "local6",
+    "local7",
+];
+
+/// A syslog server receiving RFC 3164 and RFC 5424 messages from network devices and
+/// appliances, over UDP, TCP or both.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SyslogInput {
+    /// Tells several syslog inputs apart in `top`.
+    #[serde(default = "default_name")]
+    pub name: String,
+    /// Address to receive datagrams on, such as `0.0.0.0:514`.
+    pub udp: Option<String>,
+    /// Address to accept connections on, with messages framed by newlines or by octet
+    /// counting (RFC 6587).
+    pub tcp: Option<String>,
+    /// Makes the TCP listener accept TLS connections only (RFC 5425).
+    pub tls: Option<TlsConfig>,
+    #[serde(skip)]
+    pub stop: StopFlag,
+}
+
+fn default_name() -> String {
+    "syslog".to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    /// PEM certificate chain of the listener.
+    pub cert: String,
+    /// PEM PKCS#8 private key of the certificate.
+    pub key: String,
+}
+
+/// Severity of a syslog priority's severity part (0 to 7).
+pub fn severity(priority: u8) -> Option<Severity> {
+    let name = match priority {
+        0..=2 => "FATAL",
+        3 => "ERROR",
+        4 => "WARN",
+        5 => "NOTICE",
+        6 => "INFO",
+        _ => "DEBUG",
+    };
+    severity_from_name(name)
+}
+
+/// The parts of a message the record is made of.
+#[derive(Debug, Default, PartialEq)]
+struct Message {
+    priority: u8,
+    timestamp: Option<DateTime<Utc>>,
+    hostname: Option<String>,
+    appname: Option<String>,
+    procid: Option<String>,
+    msgid: Option<String>,
+    /// `(SD-ID, param, value)` of the RFC 5424 structured data.
+    structured_data: Vec<(String, String, String)>,
+    body: String,
+}
+
+/// Parses an RFC 5424 message, or an RFC 3164 one. What does not follow either is the
+/// body, with the `user.notice` priority of a message without one.
+fn parse(message: &str) -> Message {
+    let message = message.trim_end_matches(['\r', '\n', '\0']);
+    let Some((priority, rest)) = message
+        .strip_prefix('<')
+        .and_then(|m| m.split_once('>'))
+        .and_then(|(pri, rest)| Some((pri.parse::<u8>().ok().filter(|p| *p < 192)?, rest)))
+    else {
+        return Message {
+            priority: 13,
+            body: message.to_string(),
+            ..Message::default()
+        };
+    };
+    match rest.strip_prefix("1 ") {
+        Some(rest) => parse_5424(priority, rest),
+        None => parse_3164(priority, rest),
+    }
+}
+
+/// `TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA [MSG]`, `-` for each left out.
+fn parse_5424(priority: u8, rest: &str) -> Message {
+    let mut fields = rest.splitn(6, ' ');
+    let mut field = || fields.next().filter(|f| *f != "-").map(str::to_string);
+    let timestamp = field().and_then(|t| DateTime::parse_from_rfc3339(&t).ok());
+    let (hostname, appname, procid, msgid) = (field(), field(), field(), field());
+    let rest = fields.next().unwrap_or_default();
+    let (structured_data, body) = match rest.strip_prefix('-') {
+        Some(body) => (Vec::new(), body),
+        None => structured_data(rest),
+    };
+    Message {
+        priority,
+        timestamp: timestamp.map(|t| t.with_timezone(&Utc)),
+        hostname,
+        appname,
+        procid,
+        msgid,
+        structured_data,
+        body: body
+            .strip_prefix(' ')
+            .unwrap_or(body)
+            .trim_start_matches('\u{feff}')
+            .to_string(),
+    }
+}
+
+/// The `[id param="value" ...]` elements at the start of `rest`, and what follows them.
+fn structured_data(mut rest: &str) -> (Vec<(String, String, String)>, &str) {
+    let mut params = Vec::new();
+    while let Some(element) = rest.strip_prefix('[') {
+        let Some(id_end) = element.find([' ', ']']) else {
+            break;
+        };
+        let id = &element[..id_end];
+        let mut chars = element[id_end..].char_indices().peekable();
+        let mut end = None;
+        let (mut name, mut value, mut in_value, mut escaped) =
+            (String::new(), String::new(), false, false);
+        while let Some((i, c)) = chars.next() {
+            if in_value {
+                match c {
+                    _ if escaped => {
+                        value.push(c);
+                        escaped = false;
+                    }
+                    '\\' => escaped = true,
+                    '"' => {
+                        params.push((
+                            id.to_string(),
+                            std::mem::take(&mut name),
+                            std::mem::take(&mut value),
+                        ));
+                        in_value = false;
+                    }
+                    _ => value.push(c),
+                }
+            } else {
+                match c {
+                    ']' => {
+                        end = Some(id_end + i + 1);
+                        break;
+                    }
+                    ' ' => {}
+                    '=' if chars.peek().is_some_and(|(_, c)| *c == '"') => {
+                        chars.next();
+                        in_value = true;
+                    }
+                    _ => name.push(c),
+                }
+            }
+        }
+        let Some(end) = end else {
+            break;
+        };
+        rest = &element[end..];
+    }
+    (params, rest)
+}
+
+/// `Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG`, in local time and with the year guessed; the
+/// hostname is often left out.
+fn parse_3164(priority: u8, rest: &str) -> Message {
+    let mut message = Message {
+        priority,
+        ..Message::default()
+    };
+    let Some(timestamp) = rest.get(..15).and_then(local_time) else {
+        message.body = rest.to_string();
+        return message;
+    };
+    message.timestamp = Some(timestamp);
+    let rest = rest[15..].trim_start();
+    let rest = match rest.split_once(' ') {
+        Some((hostname, rest)) if !is_tag(hostname) => {
+            message.hostname = Some(hostname.to_string());
+            rest
+        }
+        _ => rest,
+    };
+    match rest.split_once(' ').filter(|(tag, _)| is_tag(tag)) {
+        Some((tag, body)) => {
+            let tag = tag.trim_end_matches(':');
+            match tag.strip_suffix(']').and_then(|t| t.split_once('[')) {
+                Some((appname, pid)) => {
+                    message.appname = Some(appname.to_string());
+                    message.procid = Some(pid.to_string());
+                }
+                None => message.appname = Some(tag.to_string()),
+            }
+            message.body = body.to_string();
+        }
+        None => message.body = rest.to_string(),
+    }
+    message
+}
+
+/// `sshd[42]:` or `kernel:`.
+fn is_tag(word: &str) -> bool {
+    word.ends_with(':') || word.ends_with(']')
+}
+
+/// An RFC 3164 timestamp, in the past year.
+fn local_time(timestamp: &str) -> Option<DateTime<Utc>> {
+    let now = Local::now();
+    [now.year(), now.year() - 1].into_iter().find_map(|year| {
+        let naive =
+            NaiveDateTime::parse_from_str(&format!("{} {}", year, timestamp), "%Y %b %e %H:%M:%S")
+                .ok()?;
+        let time = Local.from_local_datetime(&naive).earliest()?;
+        // A clock a little ahead of the agent's is not a year old.
+        (time <= now + chrono::Duration::days(1)).then(|| time.with_timezone(&Utc))
+    })
+}
+
+/// The record of a message received from `peer`.
+fn entry(message: &str, peer: SocketAddr) -> Record {
+    let message = parse(message);
+    let mut attributes = Attributes::from(vec![
+        (
+            "syslog.facility".to_string(),
+            FACILITIES[(message.priority / 8) as usize].to_string(),
+        ),
+        ("network.peer.address".to_string(), peer.ip().to_string()),
+    ]);
+    for (key, value) in [
+        ("syslog.identifier", message.appname),
+        ("process.pid", message.procid),
+        ("syslog.msgid", message.msgid),
+    ] {
+        if let Some(value) = value {
+            attributes.push(key.to_string(), value);
+        }
+    }
+    for (id, param, value) in message.structured_data {
+        attributes.push(format!("syslog.sd.{}.{}", id, param), value);
+    }
+    Record {
+        body: Body::Text(message.body),
+        attributes,
+        default_severity: None,
+        severity: severity(message.priority % 8),
+        service_name: None,
+        timestamp: message.timestamp,
+        observed: Utc::now(),
+        otlp_path: None,
+        trace: None,
+        resource: message
+            .hostname
+            .map(|hostname| Arc::from(vec![("host.name".to_string(), hostname)])),
+        checkpoint: None,
+    }
+}
+
+/// Turns the messages of the listeners into records.
+#[derive(Clone)]
+struct Receiver {
+    tx: queue::Sender,
+    state: Arc<AgentState>,
+    stats: Arc<InputStats>,
+    otlp_path: Option<Arc<str>>,
+}
+
+impl Receiver {
+    fn receive(&self, message: &[u8], peer: SocketAddr) {
+        let message = String::from_utf8_lossy(message);
+        if message.trim().is_empty() {
+            return;
+        }
+        let started = Instant::now();
+        self.stats.record_line();
+        let mut record = entry(&message, peer);
+        record.otlp_path = self.otlp_path.clone();
+        self.state.record_stage(Stage::Parse, 1, started.elapsed());
+        enqueue(&self.tx, &self.state, record);
+    }
+}
+
+impl SyslogInput {
+    fn acceptor(&self) -> anyhow::Result<Option<Arc<TlsAcceptor>>> {
+        let Some(ref tls) = self.tls else {
+            return Ok(None);
+        };
+        let read =
+            |path: &str| fs::read(path).map_err(|e| anyhow::anyhow!("Cannot read {}: {}", path, e));
+        let identity = Identity::from_pkcs8(&read(&tls.cert)?, &read(&tls.key)?)
+            .map_err(|e| anyhow::anyhow!("Invalid syslog TLS certificate or key: {}", e))?;
+        Ok(Some(Arc::new(TlsAcceptor::new(identity)?)))
+    }
+
+    fn run_udp(&self, bind: &str, mut watch: Watch, receiver: Receiver) -> anyhow::Result<()> {
+        let address = bind.to_string();
+        let open = move || {
+            let socket = activation::udp(&address).unwrap_or_else(|| UdpSocket::bind(&address))?;
+            socket.set_read_timeout(Some(POLL_INTERVAL))?;
+            Ok(socket)
+        };
+        let mut socket = watch.start(&open)?;
+        if socket.is_some() {
+            println!("Syslog listener on udp://{}", bind);
+        }
+        let stop = self.stop.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 65535];
+            while !stop.is_stopped() {
+                let Some(ref bound) = socket else {
+                    socket = watch.rebind(&open);
+                    continue;
+                };
+                if watch.idle() {
+                    socket = None;
+                    continue;
+                }
+                match bound.recv_from(&mut buf) {
+                    Ok((len, peer)) => receiver.receive(&buf[..len], peer),
+                    Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                    Err(e) => {
+                        if watch.failed(e) {
+                            socket = None;
+                        }
+                    }
+                }
+            }
+            watch.close();
+        });
+        Ok(())
+    }
+
+    fn run_tcp(
+        &self,
+        bind: &str,
+        acceptor: Option<Arc<TlsAcceptor>>,
+        mut watch: Watch,
+        receiver: Receiver,
+    ) -> anyhow::Result<()> {
+        let address = bind.to_string();
+        let open = move || {
+            let listener =
+                activation::tcp(&address).unwrap_or_else(|| TcpListener::bind(&address))?;
+            listener.set_nonblocking(true)?;
+            Ok(listener)
+        };
+        let mut listener = watch.start(&open)?;
+        let scheme = if acceptor.is_some() { "tls" } else { "tcp" };
+        if listener.is_some() {
+            println!("Syslog listener on {}://{}", scheme, bind);
+        }
+        let stop = self.stop.clone();
+        thread::spawn(move || {
+            while !stop.is_stopped() {
+                let Some(ref bound) = listener else {
+                    listener = watch.rebind(&open);
+                    continue;
+                };
+                if watch.idle() {
+                    listener = None;
+                    continue;
+                }
+                let (stream, peer) = match bound.accept() {
+                    Ok(accepted) => accepted,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        thread::sleep(POLL_INTERVAL);
+                        continue;
+                    }
+                    Err(e) => {
+                        if watch.failed(format!("accept: {}", e)) {
+                            listener = None;
+                        } else {
+                            thread::sleep(POLL_INTERVAL);
+                        }
+                        continue;
+                    }
+                };
+                let (acceptor, receiver, stop) = (acceptor.clone(), receiver.clone(), stop.clone());
+                thread::spawn(move || {
+                    stream.set_nonblocking(false).ok();
+                    stream.set_read_timeout(Some(POLL_INTERVAL)).ok();
+                    let stream: Box<dyn Read> = match acceptor {
+                        Some(acceptor) => match acceptor.accept(stream) {
+                            Ok(stream) => Box::new(stream),
+                            Err(e) => {
+                                receiver.state.record_error(format!(
+                                    "Syslog TLS handshake with {}: {}",
+                                    peer, e
+                                ));
+                                return;
+                            }
+                        },
+                        None => Box::new(stream),
+                    };
+                    let connections = &receiver.stats.listener.connections;
+                    connections.fetch_add(1, Ordering::Relaxed);
+                    read_stream(stream, peer, &receiver, &stop);
+                    connections.fetch_sub(1, Ordering::Relaxed);
+                });
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Reads the messages of a connection until the peer closes it or the input is stopped.
+fn read_stream(mut stream: Box<dyn Read>, peer: SocketAddr, receiver: &Receiver, stop: &StopFlag) {
+    let (mut pending, mut chunk) = (Vec::new(), [0u8; 8192]);
+    while !stop.is_stopped() {
+        match stream.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => {
+                pending.extend_from_slice(&chunk[..n]);
+                while let Some(message) = next_frame(&mut pending) {
+                    receiver.receive(&message, peer);
+                }
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => break,
+        }
+    }
+    if !pending.is_empty() {
+        receiver.receive(&pending, peer);
+    }
+}
+
+/// The first complete message of `pending`, taken out of it: `<length> <message>` with
+/// octet counting, or up to a newline otherwise.
+fn next_frame(pending: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let digits = pending.iter().take_while(|b| b.is_ascii_digit()).count();
+    if digits > 0 && pending.get(digits) == Some(&b' ') {
+        let length = std::str::from_utf8(&pending[..digits])
+            .ok()
+            .and_then(|l| l.parse::<usize>().ok())
+            .filter(|l| *l <= MAX_MESSAGE_BYTES);
+        if let Some(length) = length {
+            if pending.len() < digits + 1 + length {
+                return None;
+            }
+            let message = pending[digits + 1..digits + 1 + length].to_vec();
+            pending.drain(..digits + 1 + length);
+            return Some(message);
+        }
+    }
+    let end = match memchr::memchr(b'\n', pending) {
+        Some(newline) => newline + 1,
+        None if pending.len() >= MAX_MESSAGE_BYTES => MAX_MESSAGE_BYTES,
+        None => return None,
+    };
+    Some(pending.drain(..end).collect())
+}
+
+impl Input for SyslogInput {
+    fn describe(&self) -> String {
+        format!("syslog:{}", self.name)
+    }
+
+    fn start(&mut self, ctx: &InputContext) -> anyhow::Result<()> {
+        if self.udp.is_none() && self.tcp.is_none() {
+            return Err(anyhow::anyhow!(
+                "Syslog input {} has neither a udp nor a tcp address",
+                self.name
+            ));
+        }
+        if self.tls.is_some() && self.tcp.is_none() {
+            return Err(anyhow::anyhow!(
+                "Syslog input {} has tls without a tcp address",
+                self.name
+            ));
+        }
+        let acceptor = self.acceptor()?;
+        let stats = ctx.register(self);
+        let receiver = Receiver {
+            tx: ctx.queue.sender(&stats.name),
+            state: ctx.state.clone(),
+            otlp_path: ctx
+                .config
+                .otlp_paths
+                .get(&stats.name)
+                .map(|p| p.as_str().into()),
+            stats,
+        };
+        if let Some(ref bind) = self.udp {
+            let watch =
+                ctx.listener_watch(format!("Syslog listener udp://{}", bind), &receiver.stats);
+            self.run_udp(bind, watch, receiver.clone())?;
+        }
+        if let Some(ref bind) = self.tcp {
+            let watch =
+                ctx.listener_watch(format!("Syslog listener tcp://{}", bind), &receiver.stats);
+            self.run_tcp(bind, acceptor, watch, receiver)?;
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.stop.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc_5424_and_rfc_3164_messages_are_taken_apart() {
+        let message = parse(
+            r#"<165>1 2024-03-05T10:11:12.003Z fw1 dhcpd 42 LEASE [lease@32473 ip="10.0.0.7" mac="aa:bb"] lease granted"#,
+        );
+        assert_eq!(
+            message,
+            Message {
+                priority: 165,
+                timestamp: Some(
+                    Utc.with_ymd_and_hms(2024, 3, 5, 10, 11, 12).unwrap()
+                        + chrono::Duration::milliseconds(3)
+                ),
+                hostname: Some("fw1".to_string()),
+                appname: Some("dhcpd".to_string()),
+                procid: Some("42".to_string()),
+                msgid: Some("LEASE".to_string()),
+                structured_data: vec![
+                    ("lease@32473".into(), "ip".into(), "10.0.0.7".into()),
+                    ("lease@32473".into(), "mac".into(), "aa:bb".into()),
+                ],
+                body: "lease granted".to_string(),
+            }
+        );
+
+        let message =
+            parse("<34>Oct 11 22:14:15 mymachine su[230]: 'su root' failed on /dev/pts/8");
+        assert_eq!(
+            (
+                message.priority,
+                message.hostname.as_deref(),
+                message.appname.as_deref()
+            ),
+            (34, Some("mymachine"), Some("su"))
+        );
+        assert_eq!(message.procid.as_deref(), Some("230"));
+        assert_eq!(message.body, "'su root' failed on /dev/pts/8");
+        assert!(message.timestamp.is_some());
+    }
+}
@@ -0,0 +1,710 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::num::NonZeroU32;
+use std::sync::atomic::Ordering;
+use std::sync::{mpsc, Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::fileid::FileId;
+use crate::handles::{self, Handle, Idle, OpenFiles};
+use crate::stats::{AgentState, InputStats, Stage, Unread};
+use crate::{catchup, checkpoint, input, replay, rewrite, tombstone};
+
+/// Default `read_buffer_bytes`.
+pub const DEFAULT_READ_BUFFER_BYTES: usize = 64 * 1024;
+
+/// Strips the line terminator (`\n` or `\r\n`) and nothing else.
+pub fn trim_line_end(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// Calls `deliver` for every newline-terminated line in `data` (newline included) and
+/// returns the number of bytes consumed.
+pub fn scan_lines<F: FnMut(&[u8])>(data: &[u8], mut deliver: F) -> usize {
+    let mut start = 0;
+    for end in memchr::memchr_iter(b'\n', data) {
+        deliver(&data[start..=end]);
+        start = end + 1;
+    }
+    start
+}
+
+/// How a tailed file is read: the global settings, possibly overridden per input.
+#[derive(Debug)]
+pub struct TailOptions {
+    /// Read-ahead buffer, and about the most a batch of lines adds up to.
+    pub buffer_bytes: usize,
+    pub rewrite: rewrite::RewritePolicy,
+    /// How long a deleted or replaced file is still read through its open handle.
+    pub deleted_grace: Duration,
+    /// Finish reading a file rotated away (renamed and replaced) before switching to
+    /// the new one, also when the rotation happened while the agent was down.
+    pub finish_rotated: bool,
+    /// Read a file never read before from its start rather than its end.
+    pub from_start: bool,
+    /// Lines per second for what the file already held when tailing started.
+    pub replay_rate: Option<NonZeroU32>,
+    /// Hand on where every line starts, for `origin_id_attribute`.
+    pub origins: bool,
+    /// The file's `[catch_up]` turn, given back once it is read up to its end.
+    pub catch_up: Option<catchup::Turn>,
+    /// With `--oneshot`, dropped as well once the file is read up to its end.
+    pub until_end: Option<Unread>,
+    /// With `max_open_files`, the files held open, of which this one may be closed while
+    /// it does not grow.
+    pub open_files: Option<Arc<OpenFiles>>,
+    /// With `idle_close_secs`, how long the file may go without growing before it is
+    /// closed, until a check every `rescan` finds it grown.
+    pub idle_close: Option<Duration>,
+    pub rescan: Duration,
+}
+
+#[cfg(test)]
+impl TailOptions {
+    /// The options of a file tailed with the defaults of config.toml.
+    pub fn test() -> TailOptions {
+        TailOptions {
+            buffer_bytes: DEFAULT_READ_BUFFER_BYTES,
+            rewrite: rewrite::RewritePolicy::default(),
+            deleted_grace: Duration::from_secs(tombstone::DEFAULT_GRACE_SECS),
+            finish_rotated: true,
+            from_start: false,
+            replay_rate: None,
+            origins: false,
+            catch_up: None,
+            until_end: None,
+            open_files: None,
+            idle_close: None,
+            rescan: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A file closed for `max_open_files` or `idle_close_secs` at its end, as it was then.
+struct Closed {
+    dev: u64,
+    ino: u64,
+    len: u64,
+    modified: Option<SystemTime>,
+    /// Where its next line starts.
+    offset: u64,
+}
+
+impl Closed {
+    /// Whether the file at `path` changed since, or another one took its place.
+    fn changed(&self, path: &str) -> bool {
+        fs::metadata(path).is_ok_and(|m| {
+            (m.dev(), m.ino(), m.len()) != (self.dev, self.ino, self.len)
+                || m.modified().ok() != self.modified
+        })
+    }
+}
+
+/// Where the lines of a batch were read: the file's inode and the offset each line
+/// starts at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Origins {
+    pub inode: u64,
+    pub offsets: Vec<u64>,
+}
+
+/// How long a file read to its end, paused or waiting for its path to come back waits
+/// for its next turn.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+type Handler = Box<dyn FnMut(Vec<Vec<u8>>, Option<Origins>) + Send>;
+
+/// What a tailed file did with its turn on the reader thread.
+enum Step {
+    /// Read from the file: its next turn comes once every other file due had one.
+    Read,
+    /// Nothing to read for now: its next turn comes this long from now.
+    Wait(Duration),
+    /// Read to its end and idle for `idle_close`, as it was then.
+    Idle(Closed),
+    /// Its input was stopped, or it could not be read at all.
+    Done,
+}
+
+/// Lines of backlog read before `replay_rate` allowed handing them on.
+struct Held {
+    lines: Vec<Vec<u8>>,
+    origins: Option<Origins>,
+    read_to: Option<(fs::Metadata, u64)>,
+    /// How many of them the rate has yet to allow.
+    remaining: u32,
+}
+
+/// A tailed file between its turns on the reader thread: what it is read through and
+/// where that got to.
+struct Tailer {
+    path: String,
+    options: TailOptions,
+    positions: Arc<checkpoint::Positions>,
+    state: Arc<AgentState>,
+    stats: Arc<InputStats>,
+    stop: input::StopFlag,
+    handler: Handler,
+    handle: Option<Handle>,
+    tracker: rewrite::RewriteTracker,
+    follower: tombstone::Follower,
+    /// `None` once a deleted file was released and nothing replaced it yet, or while it
+    /// is closed.
+    reader: Option<BufReader<fs::File>>,
+    partial: Vec<u8>,
+    closed: Option<Closed>,
+    last_read: Instant,
+    /// What the file held when tailing started is backlog, read at `replay_rate`.
+    backlog_end: u64,
+    held: Option<Held>,
+    /// Reading failed: the file is opened again on the next turn.
+    reopen: bool,
+}
+
+/// Tails `path` from its end. Whatever is available is read in one go, up to
+/// `buffer_bytes`, and its complete lines are handed to `handler` as one batch; a partial
+/// last line is held back until its newline arrives, and with `origins` their offsets go
+/// along. Once the file is deleted or
+/// replaced, the new file at the path is read from its start. A file read by an earlier
+/// run resumes at its saved position instead of its end.
+///
+/// Every tailed file is read on one thread, started with the first: each file due gets a
+/// turn in order, reading at most one batch, and a file read to its end is due again
+/// every half second. The file is given up once `stop` is set.
+pub fn tail_file<F>(
+    path: String,
+    options: TailOptions,
+    positions: Arc<checkpoint::Positions>,
+    state: Arc<AgentState>,
+    stats: Arc<InputStats>,
+    stop: input::StopFlag,
+    handler: F,
+) where
+    F: FnMut(Vec<Vec<u8>>, Option<Origins>) + Send + 'static,
+{
+    tail(
+        path,
+        options,
+        positions,
+        state,
+        stats,
+        stop,
+        Box::new(handler),
+    );
+}
+
+fn tail(
+    path: String,
+    options: TailOptions,
+    positions: Arc<checkpoint::Positions>,
+    state: Arc<AgentState>,
+    stats: Arc<InputStats>,
+    stop: input::StopFlag,
+    handler: Handler,
+) {
+    let handle = acquire(&options.open_files);
+    let file = match fs::File::open(&path) {
+        Ok(f) => f,
+        Err(e) => {
+            state.record_error(format!("Failed to open {}: {e}", path));
+            return;
+        }
+    };
+
+    let (file, start) = positions.resume(
+        &path,
+        file,
+        &stats,
+        options.finish_rotated,
+        options.from_start,
+    );
+    // Until lines are delivered, a restart starts where this run did.
+    if let Ok(meta) = file.metadata() {
+        positions.record(&path, &meta, start, &stats);
+    }
+    let backlog_end = match options.replay_rate {
+        Some(_) => file.metadata().map(|m| m.len()).unwrap_or(0),
+        None => 0,
+    };
+    let tracker = rewrite::RewriteTracker::new(&path, &file, options.rewrite);
+    let follower = tombstone::Follower::new(&path, options.deleted_grace, options.finish_rotated);
+    let mut reader = BufReader::with_capacity(options.buffer_bytes, file);
+    reader.seek(SeekFrom::Start(start)).ok();
+    read_on(Tailer {
+        path,
+        options,
+        positions,
+        state,
+        stats,
+        stop,
+        handler,
+        handle,
+        tracker,
+        follower,
+        reader: Some(reader),
+        partial: Vec::new(),
+        closed: None,
+        last_read: Instant::now(),
+        backlog_end,
+        held: None,
+        reopen: false,
+    });
+}
+
+fn acquire(open_files: &Option<Arc<OpenFiles>>) -> Option<Handle> {
+    Some(open_files.as_ref()?.acquire())
+}
+
+/// Hands `tailer` to the reader thread.
+fn read_on(tailer: Tailer) {
+    static ADDED: OnceLock<mpsc::Sender<Tailer>> = OnceLock::new();
+    let added = ADDED.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        thread::Builder::new()
+            .name("tailer".into())
+            .spawn(move || read_files(rx))
+            .expect("failed to start the tailer thread");
+        tx
+    });
+    added.send(tailer).ok();
+}
+
+/// The reader thread: gives every file due a turn, then sleeps until the next one is
+/// due or another file is added.
+fn read_files(added: mpsc::Receiver<Tailer>) {
+    let mut tailers: Vec<(Tailer, Instant)> = Vec::new();
+    loop {
+        let now = Instant::now();
+        let mut next = now + POLL_INTERVAL;
+        for (mut tailer, due) in std::mem::take(&mut tailers) {
+            if due > now {
+                next = next.min(due);
+                tailers.push((tailer, due));
+                continue;
+            }
+            match tailer.step() {
+                Step::Read => {
+                    next = now;
+                    tailers.push((tailer, now));
+                }
+                Step::Wait(wait) => {
+                    let due = Instant::now() + wait;
+                    next = next.min(due);
+                    tailers.push((tailer, due));
+                }
+                Step::Idle(was) => tailer.close_idle(was),
+                Step::Done => {}
+            }
+        }
+        match added.recv_timeout(next.saturating_duration_since(Instant::now())) {
+            Ok(tailer) => tailers.push((tailer, Instant::now())),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+        tailers.extend(added.try_iter().map(|tailer| (tailer, Instant::now())));
+    }
+}
+
+impl Tailer {
+    /// One turn: reads a batch of lines from the file, or finds out why there is none.
+    fn step(&mut self) -> Step {
+        if self.stop.is_stopped() {
+            return Step::Done;
+        }
+        // Quarantined with `action = "pause"`: the rest of the file waits for a restart.
+        if self.stats.paused.load(Ordering::Relaxed) {
+            return Step::Wait(POLL_INTERVAL);
+        }
+        if let Some(held) = self.held.take() {
+            return self.hand_on(held);
+        }
+        if let Some(ref was) = self.closed {
+            if !was.changed(&self.path) {
+                return Step::Wait(POLL_INTERVAL);
+            }
+            let Ok(file) = fs::File::open(&self.path) else {
+                return Step::Wait(POLL_INTERVAL);
+            };
+            self.handle = acquire(&self.options.open_files);
+            let unchanged = file
+                .metadata()
+                .is_ok_and(|m| (m.dev(), m.ino()) == (was.dev, was.ino) && m.len() >= was.offset);
+            let (file, start) = match unchanged {
+                true => (file, was.offset),
+                // Replaced, rotated or truncated while closed, as if the agent was down.
+                false => {
+                    let resumed = self.positions.resume(
+                        &self.path,
+                        file,
+                        &self.stats,
+                        self.options.finish_rotated,
+                        false,
+                    );
+                    self.tracker =
+                        rewrite::RewriteTracker::new(&self.path, &resumed.0, self.options.rewrite);
+                    resumed
+                }
+            };
+            let mut reopened = BufReader::with_capacity(self.options.buffer_bytes, file);
+            reopened.seek(SeekFrom::Start(start)).ok();
+            self.reader = Some(reopened);
+            self.closed = None;
+            return Step::Read;
+        }
+        if std::mem::take(&mut self.reopen) {
+            match fs::File::open(&self.path) {
+                Ok(f) => {
+                    let mut reopened = BufReader::with_capacity(self.options.buffer_bytes, f);
+                    reopened.seek(SeekFrom::End(0)).ok();
+                    self.reader = Some(reopened);
+                    self.backlog_end = 0;
+                    self.partial.clear();
+                    println!("Successfully reopened {}", self.path);
+                }
+                Err(e) => {
+                    self.state
+                        .record_error(format!("Failed to reopen {}: {e}", self.path));
+                    return Step::Wait(Duration::from_secs(30));
+                }
+            }
+        }
+        let Some(current) = self.reader.as_mut() else {
+            let Some(file) = self.follower.reopen() else {
+                return Step::Wait(POLL_INTERVAL);
+            };
+            self.handle = acquire(&self.options.open_files);
+            self.tracker = rewrite::RewriteTracker::new(&self.path, &file, self.options.rewrite);
+            self.reader = Some(BufReader::with_capacity(self.options.buffer_bytes, file));
+            return Step::Read;
+        };
+        // Checked before each read from the file, so a rewrite is not read as appended.
+        if current.buffer().is_empty() {
+            let position = current.stream_position().unwrap_or(0);
+            if self.tracker.is_rewritten(current.get_ref(), position) {
+                self.partial.clear();
+                self.backlog_end = 0;
+                if self.tracker.start_over(&self.path, current.get_ref()) {
+                    current.seek(SeekFrom::Start(0)).ok();
+                } else {
+                    current.seek(SeekFrom::End(0)).ok();
+                }
+            }
+        }
+        let backlog = self.backlog_end > 0
+            && current
+                .stream_position()
+                .is_ok_and(|pos| pos < self.backlog_end);
+        let started = Instant::now();
+        let read_from = current.stream_position().unwrap_or(0);
+        match current.fill_buf() {
+            Ok([]) => {
+                self.stats.lag_bytes.store(0, Ordering::Relaxed);
+                // The backlog is read: the next file waiting gets its turn.
+                drop((self.options.catch_up.take(), self.options.until_end.take()));
+                match self.follower.poll(current.get_ref()) {
+                    tombstone::Follow::Keep => {
+                        self.tracker.refresh_head(current.get_ref());
+                        let (Ok(meta), Ok(pos)) =
+                            (current.get_ref().metadata(), current.stream_position())
+                        else {
+                            return Step::Wait(POLL_INTERVAL);
+                        };
+                        // Where its complete lines end, from where a closed file is read on.
+                        let offset = pos.saturating_sub(self.partial.len() as u64);
+                        let was = Closed {
+                            dev: meta.dev(),
+                            ino: meta.ino(),
+                            len: meta.len(),
+                            modified: meta.modified().ok(),
+                            offset,
+                        };
+                        if self.handle.as_ref().is_some_and(Handle::is_evicted) {
+                            // Closed to make room for another file.
+                            self.positions
+                                .record(&self.path, &meta, offset, &self.stats);
+                            self.closed = Some(was);
+                            self.partial.clear();
+                            self.reader = None;
+                            self.handle = None;
+                        } else if self
+                            .options
+                            .idle_close
+                            .is_some_and(|idle| self.last_read.elapsed() >= idle)
+                        {
+                            self.positions
+                                .record(&self.path, &meta, offset, &self.stats);
+                            return Step::Idle(was);
+                        }
+                        Step::Wait(POLL_INTERVAL)
+                    }
+                    tombstone::Follow::Switch(file) => {
+                        self.partial.clear();
+                        self.backlog_end = 0;
+                        self.tracker =
+                            rewrite::RewriteTracker::new(&self.path, &file, self.options.rewrite);
+                        self.reader =
+                            Some(BufReader::with_capacity(self.options.buffer_bytes, file));
+                        Step::Read
+                    }
+                    tombstone::Follow::Release => {
+                        self.partial.clear();
+                        self.backlog_end = 0;
+                        self.reader = None;
+                        self.handle = None;
+                        Step::Wait(POLL_INTERVAL)
+                    }
+                }
+            }
+            Ok(available) => {
+                let (tracker, partial) = (&mut self.tracker, &mut self.partial);
+                let mut lines = Vec::new();
+                let mut offsets = Vec::new();
+                // A held back partial line starts before what was just read.
+                let mut next = read_from.saturating_sub(partial.len() as u64);
+                let consumed = scan_lines(available, |line| {
+                    let start = next;
+                    next += (partial.len() + line.len()) as u64;
+                    let line = if partial.is_empty() {
+                        trim_line_end(line).to_vec()
+                    } else {
+                        partial.extend_from_slice(trim_line_end(line));
+                        std::mem::take(partial)
+                    };
+                    if tracker.admit(&line) && !String::from_utf8_lossy(&line).trim().is_empty() {
+                        lines.push(line);
+                        offsets.push(start);
+                    }
+                });
+                partial.extend_from_slice(&available[consumed..]);
+                let read = available.len();
+                current.consume(read);
+                self.last_read = Instant::now();
+                if let Some(ref handle) = self.handle {
+                    handle.touch();
+                }
+                self.state
+                    .record_stage(Stage::Read, lines.len() as u64, started.elapsed());
+                let read_to = match (current.get_ref().metadata(), current.stream_position()) {
+                    (Ok(meta), Ok(pos)) => {
+                        self.stats
+                            .lag_bytes
+                            .store(meta.len().saturating_sub(pos), Ordering::Relaxed);
+                        // A held back partial line is read again after a restart.
+                        Some((meta, pos.saturating_sub(self.partial.len() as u64)))
+                    }
+                    _ => None,
+                };
+                if lines.is_empty() {
+                    if let Some((meta, complete)) = read_to {
+                        self.positions
+                            .record(&self.path, &meta, complete, &self.stats);
+                    }
+                    return Step::Read;
+                }
+                let origins = self
+                    .options
+                    .origins
+                    .then(|| read_to.as_ref().map(|(meta, _)| meta.ino()))
+                    .flatten()
+                    .map(|inode| Origins { inode, offsets });
+                let remaining = match self.options.replay_rate.filter(|_| backlog) {
+                    Some(_) => lines.len() as u32,
+                    None => 0,
+                };
+                self.hand_on(Held {
+                    lines,
+                    origins,
+                    read_to,
+                    remaining,
+                })
+            }
+            Err(e) => {
+                self.state
+                    .record_error(format!("Error reading {}: {e}", self.path));
+                self.reopen = true;
+                Step::Wait(Duration::from_secs(5))
+            }
+        }
+    }
+
+    /// Hands the lines read on, once `replay_rate` allows it for backlog. Stopped before
+    /// then, they are read again next time.
+    fn hand_on(&mut self, mut held: Held) -> Step {
+        if let Some(rate) = self.options.replay_rate.filter(|_| held.remaining > 0) {
+            if let Some(wait) = replay::take(&self.state, rate, &mut held.remaining) {
+                self.held = Some(held);
+                return Step::Wait(wait);
+            }
+        }
+        // The position is taken once the records of the lines are done with.
+        self.stats
+            .set_checkpoint(held.read_to.as_ref().map(|(meta, complete)| {
+                self.positions
+                    .checkpoint(&self.path, meta, *complete, &self.stats)
+            }));
+        (self.handler)(held.lines, held.origins);
+        self.positions.sequenced(&self.path, &self.stats);
+        self.stats.set_checkpoint(None);
+        Step::Read
+    }
+
+    /// Closes the file, idle at `was`, until a check every `rescan` finds it grown; it is
+    /// then read on by a tailer started again, as after a restart.
+    fn close_idle(self, was: Closed) {
+        let Tailer {
+            path,
+            options,
+            positions,
+            state,
+            stats,
+            stop,
+            handler,
+            ..
+        } = self;
+        let (checked, checked_stop) = (path.clone(), stop.clone());
+        handles::close_idle(
+            options.rescan,
+            move || match (checked_stop.is_stopped(), was.changed(&checked)) {
+                (true, _) => Idle::Stopped,
+                (false, true) => Idle::Grown,
+                (false, false) => Idle::Unchanged,
+            },
+            move || tail(path, options, positions, state, stats, stop, handler),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::io::Write;
+
+    /// Tails `path` with `options`, handing on the lines of each batch.
+    fn tail(
+        dir: &TempDir,
+        path: &std::path::Path,
+        options: TailOptions,
+    ) -> (mpsc::Receiver<Vec<String>>, input::StopFlag) {
+        let path = path.to_str().unwrap().to_string();
+        let state = Arc::new(AgentState::default());
+        let stats = state.register_input(&path);
+        let positions = checkpoint::Positions::load(dir.to_str().unwrap()).unwrap();
+        let (tx, rx) = mpsc::channel();
+        let stop = input::StopFlag::default();
+        tail_file(
+            path,
+            options,
+            positions,
+            state,
+            stats,
+            stop.clone(),
+            move |lines, _| {
+                let lines = lines.iter().map(|l| String::from_utf8_lossy(l).into());
+                tx.send(lines.collect()).ok();
+            },
+        );
+        thread::sleep(Duration::from_millis(200));
+        (rx, stop)
+    }
+
+    fn append(path: &std::path::Path, text: &str) {
+        let mut file = fs::OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn a_partial_line_waits_for_its_newline() {
+        let dir = TempDir::new("partial");
+        let log = dir.join("app.log");
+        fs::write(&log, "").unwrap();
+        let (lines, stop) = tail(&dir, &log, TailOptions::test());
+        let next = || lines.recv_timeout(Duration::from_secs(3));
+
+        append(&log, "one\r\ntw");
+        assert_eq!(next().unwrap(), ["one"]);
+        assert!(lines.recv_timeout(Duration::from_millis(700)).is_err());
+        append(&log, "o\n\nthree\n");
+        assert_eq!(next().unwrap(), ["two", "three"]);
+        stop.stop();
+    }
+
+    #[test]
+    fn rotated_and_truncated_files_are_read_on() {
+        let dir = TempDir::new("rotation");
+        let log = dir.join("app.log");
+        fs::write(&log, "").unwrap();
+        let options = TailOptions {
+            deleted_grace: Duration::ZERO,
+            ..TailOptions::test()
+        };
+        let (lines, stop) = tail(&dir, &log, options);
+        let next = || lines.recv_timeout(Duration::from_secs(3));
+
+        append(&log, "before rotation\n");
+        assert_eq!(next().unwrap(), ["before rotation"]);
+        // The rotated file is read to its end before the new one, from its start.
+        fs::rename(&log, dir.join("app.log.1")).unwrap();
+        append(&dir.join("app.log.1"), "after rotation\n");
+        fs::write(&log, "new file\n").unwrap();
+        assert_eq!(next().unwrap(), ["after rotation"]);
+        assert_eq!(next().unwrap(), ["new file"]);
+
+        fs::write(&log, "cut\n").unwrap();
+        assert_eq!(next().unwrap(), ["cut"]);
+        stop.stop();
+    }
+
+    #[test]
+    fn files_are_read_on_one_thread_without_waiting_on_each_other() {
+        let dir = TempDir::new("reader-thread");
+        let state = Arc::new(AgentState::default());
+        let positions = checkpoint::Positions::load(dir.to_str().unwrap()).unwrap();
+        let (tx, rx) = mpsc::channel();
+        let follow = |name: &'static str, contents: &str, options: TailOptions| {
+            let path = dir.join(name);
+            fs::write(&path, contents).unwrap();
+            let path = path.to_str().unwrap().to_string();
+            let (stop, tx) = (input::StopFlag::default(), tx.clone());
+            tail_file(
+                path.clone(),
+                options,
+                positions.clone(),
+                state.clone(),
+                state.register_input(&path),
+                stop.clone(),
+                move |lines, _| {
+                    tx.send((name, lines.len(), thread::current().id())).ok();
+                },
+            );
+            stop
+        };
+        // Six lines of backlog at two a second, handed on after about two seconds.
+        let replayed = TailOptions {
+            from_start: true,
+            replay_rate: NonZeroU32::new(2),
+            ..TailOptions::test()
+        };
+        let backlog = follow("backlog.log", "1\n2\n3\n4\n5\n6\n", replayed);
+        let live = follow("live.log", "", TailOptions::test());
+        thread::sleep(Duration::from_millis(200));
+        append(&dir.join("live.log"), "now\n");
+        let (name, count, reader) = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!((name, count), ("live.log", 1));
+        assert_ne!(reader, thread::current().id());
+        let next = rx.recv_timeout(Duration::from_secs(3)).unwrap();
+        assert_eq!(next, ("backlog.log", 6, reader));
+
+        live.stop();
+        append(&dir.join("live.log"), "after stop\n");
+        append(&dir.join("backlog.log"), "7\n");
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(3)).unwrap().0,
+            "backlog.log"
+        );
+        assert!(rx.recv_timeout(Duration::from_millis(700)).is_err());
+        backlog.stop();
+    }
+}
@@ -0,0 +1,286 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::metrics::{self, Gauge, Metric, MetricData, MetricsExporter, NumberDataPoint, Sum};
+use crate::stats::{AgentState, Stage};
+use crate::{string_kv, Config};
+
+/// The agent's own metrics, sent to `metrics_endpoint` alongside any other metrics.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SelfTelemetryConfig {
+    #[serde(default = "default_interval")]
+    pub interval_secs: u64,
+}
+
+fn default_interval() -> u64 {
+    60
+}
+
+/// Periodically exports per-stage record counts, cumulative busy time and the average
+/// time per record over the last interval.
+pub fn run(
+    telemetry: SelfTelemetryConfig,
+    config: Arc<Config>,
+    state: Arc<AgentState>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let exporter = MetricsExporter::new(&config);
+        let interval = Duration::from_secs(telemetry.interval_secs.max(1));
+        let started = metrics::unix_nanos(Utc::now());
+        let mut previous = [(0u64, 0u64); Stage::ALL.len()];
+
+        loop {
+            thread::sleep(interval);
+            let now = metrics::unix_nanos(Utc::now());
+            let point = |stage: Stage, value: f64| NumberDataPoint {
+                attributes: vec![string_kv("stage", stage.as_str())],
+                start_time_unix_nano: started.clone(),
+                time_unix_nano: now.clone(),
+                as_double: value,
+            };
+
+            let (mut records, mut busy, mut latency) = (Vec::new(), Vec::new(), Vec::new());
+            for stage in Stage::ALL {
+                let stats = state.stage(stage);
+                let count = stats.records.load(Ordering::Relaxed);
+                let nanos = stats.busy_nanos.load(Ordering::Relaxed);
+                let (prev_count, prev_nanos) = previous[stage as usize];
+                previous[stage as usize] = (count, nanos);
+
+                records.push(point(stage, count as f64));
+                busy.push(point(stage, nanos as f64 / 1e9));
+                if count > prev_count {
+                    let average = (nanos - prev_nanos) as f64 / (count - prev_count) as f64;
+                    latency.push(point(stage, average / 1e3));
+                }
+            }
+
+            let cumulative = |data_points| {
+                MetricData::Sum(Sum {
+                    aggregation_temporality: metrics::CUMULATIVE,
+                    is_monotonic: true,
+                    data_points,
+                })
+            };
+            let mut out = vec![
+                Metric {
+                    name: "agent.pipeline.records".to_string(),
+                    unit: String::new(),
+                    data: cumulative(records),
+                },
+                Metric {
+                    name: "agent.pipeline.busy_time".to_string(),
+                    unit: "s".to_string(),
+                    data: cumulative(busy),
+                },
+            ];
+            if !latency.is_empty() {
+                out.push(Metric {
+                    name: "agent.pipeline.record_latency".to_string(),
+                    unit: "us".to_string(),
+                    data: MetricData::Gauge(Gauge {
+                        data_points: latency,
+                    }),
+                });
+            }
+
+            let (mut cert_expiry, mut auth_failures) = (Vec::new(), Vec::new());
+            let (mut sent, mut failed, mut retries) = (Vec::new(), Vec::new(), Vec::new());
+            for exporter in state.exporters.lock().unwrap().iter() {
+                let point = |value: f64| NumberDataPoint {
+                    attributes: vec![string_kv("exporter", &exporter.name)],
+                    start_time_unix_nano: started.clone(),
+                    time_unix_nano: now.clone(),
+                    as_double: value,
+                };
+                let expires = exporter.cert_expires_unix.load(Ordering::SeqCst);
+                if expires > 0 {
+                    cert_expiry.push(point((expires - Utc::now().timestamp()) as f64 / 86400.0));
+                }
+                auth_failures.push(point(exporter.auth_failures.load(Ordering::SeqCst) as f64));
+                sent.push(point(exporter.sent.load(Ordering::SeqCst) as f64));
+                failed.push(point(exporter.failed.load(Ordering::SeqCst) as f64));
+                retries.push(point(exporter.retries.load(Ordering::SeqCst) as f64));
+            }
+            if !cert_expiry.is_empty() {
+                out.push(Metric {
+                    name: "agent.exporter.cert_expiry".to_string(),
+                    unit: "d".to_string(),
+                    data: MetricData::Gauge(Gauge {
+                        data_points: cert_expiry,
+                    }),
+                });
+            }
+            out.push(Metric {
+                name: "agent.exporter.auth_failures".to_string(),
+                unit: String::new(),
+                data: cumulative(auth_failures),
+            });
+            for (name, data_points) in [
+                ("agent.exporter.sent", sent),
+                ("agent.exporter.failed", failed),
+                ("agent.exporter.retries", retries),
+            ] {
+                out.push(Metric {
+                    name: name.to_string(),
+                    unit: String::new(),
+                    data: cumulative(data_points),
+                });
+            }
+
+            let (mut match_time, mut slow_matches, mut hits) = (Vec::new(), Vec::new(), Vec::new());
+            for rule in state.rules.lock().unwrap().iter() {
+                let point = |value: f64| NumberDataPoint {
+                    attributes: vec![string_kv("rule", &rule.name)],
+                    start_time_unix_nano: started.clone(),
+                    time_unix_nano: now.clone(),
+                    as_double: value,
+                };
+                match_time.push(point(rule.busy_nanos.load(Ordering::Relaxed) as f64 / 1e9));
+                slow_matches.push(point(rule.slow.load(Ordering::Relaxed) as f64));
+                hits.push(point(rule.hits.load(Ordering::Relaxed) as f64));
+            }
+            if !match_time.is_empty() {
+                out.push(Metric {
+                    name: "agent.rule.match_time".to_string(),
+                    unit: "s".to_string(),
+                    data: cumulative(match_time),
+                });
+                out.push(Metric {
+                    name: "agent.rule.slow_matches".to_string(),
+                    unit: String::new(),
+                    data: cumulative(slow_matches),
+                });
+                out.push(Metric {
+                    name: "agent.rule.hits".to_string(),
+                    unit: String::new(),
+                    data: cumulative(hits),
+                });
+            }
+
+            let (mut lines, mut lag, mut resyncs) = (Vec::new(), Vec::new(), Vec::new());
+            let (mut anomalies, mut down, mut rebinds) = (Vec::new(), Vec::new(), Vec::new());
+            let (mut failures, mut quarantined) = (Vec::new(), Vec::new());
+            for input in state.inputs.lock().unwrap().iter() {
+                let point = |value: u64| NumberDataPoint {
+                    attributes: vec![string_kv("input", &input.name)],
+                    start_time_unix_nano: started.clone(),
+                    time_unix_nano: now.clone(),
+                    as_double: value as f64,
+                };
+                lines.push(point(input.lines.load(Ordering::Relaxed)));
+                lag.push(point(input.lag_bytes.load(Ordering::Relaxed)));
+                let resynced = input.resyncs.load(Ordering::Relaxed);
+                if resynced > 0 {
+                    resyncs.push(point(resynced));
+                }
+                if config.volume_alerts.contains_key("*")
+                    || crate::input::option_for(&config.volume_alerts, &input.name).is_some()
+                {
+                    anomalies.push(point(input.volume_anomalies.load(Ordering::Relaxed)));
+                }
+                let failed = input.parse_failures.load(Ordering::Relaxed);
+                if failed > 0 {
+                    failures.push(point(failed));
+                    quarantined.push(point(input.quarantined.load(Ordering::Relaxed) as u64));
+                }
+                if input.listener.is_listener() {
+                    down.push(point(input.listener.down.load(Ordering::Relaxed)));
+                    rebinds.push(point(input.listener.rebinds.load(Ordering::Relaxed)));
+                }
+            }
+            out.push(Metric {
+                name: "agent.input.lines".to_string(),
+                unit: String::new(),
+                data: cumulative(lines),
+            });
+            out.push(Metric {
+                name: "agent.input.lag".to_string(),
+                unit: "By".to_string(),
+                data: MetricData::Gauge(Gauge { data_points: lag }),
+            });
+            if !resyncs.is_empty() {
+                out.push(Metric {
+                    name: "agent.input.resyncs".to_string(),
+                    unit: String::new(),
+                    data: cumulative(resyncs),
+                });
+            }
+            if !anomalies.is_empty() {
+                out.push(Metric {
+                    name: "agent.input.volume_anomalies".to_string(),
+                    unit: String::new(),
+                    data: cumulative(anomalies),
+                });
+            }
+            if !failures.is_empty() {
+                out.push(Metric {
+                    name: "agent.input.parse_failures".to_string(),
+                    unit: String::new(),
+                    data: cumulative(failures),
+                });
+                out.push(Metric {
+                    name: "agent.input.quarantined".to_string(),
+                    unit: String::new(),
+                    data: MetricData::Gauge(Gauge {
+                        data_points: quarantined,
+                    }),
+                });
+            }
+            if !down.is_empty() {
+                out.push(Metric {
+                    name: "agent.input.listener.down".to_string(),
+                    unit: String::new(),
+                    data: MetricData::Gauge(Gauge { data_points: down }),
+                });
+                out.push(Metric {
+                    name: "agent.input.listener.rebinds".to_string(),
+                    unit: String::new(),
+                    data: cumulative(rebinds),
+                });
+            }
+
+            out.push(Metric {
+                name: "agent.queue.depth".to_string(),
+                unit: String::new(),
+                data: MetricData::Gauge(Gauge {
+                    data_points: vec![NumberDataPoint {
+                        attributes: Vec::new(),
+                        start_time_unix_nano: started.clone(),
+                        time_unix_nano: now.clone(),
+                        as_double: state.pending() as f64,
+                    }],
+                }),
+            });
+            out.push(Metric {
+                name: "agent.config.reload_failures".to_string(),
+                unit: String::new(),
+                data: cumulative(vec![NumberDataPoint {
+                    attributes: Vec::new(),
+                    start_time_unix_nano: started.clone(),
+                    time_unix_nano: now.clone(),
+                    as_double: state.config_reload_failures.load(Ordering::SeqCst) as f64,
+                }]),
+            });
+            out.push(Metric {
+                name: "agent.timestamps.suspect".to_string(),
+                unit: String::new(),
+                data: cumulative(vec![NumberDataPoint {
+                    attributes: Vec::new(),
+                    start_time_unix_nano: started.clone(),
+                    time_unix_nano: now.clone(),
+                    as_double: state.suspect_timestamps.load(Ordering::Relaxed) as f64,
+                }]),
+            });
+
+            let payload = metrics::build_metrics_payload(&config, "rust-signoz-agent", out);
+            if let Err(e) = exporter.export(&payload) {
+                state.record_error(format!("Self-telemetry: {}", e));
+            }
+        }
+    })
+}
@@ -0,0 +1,103 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use crate::cloud;
+
+/// The value of `{name}` in an endpoint: the `endpoint_vars` entry, else the environment
+/// variable of the name in upper case, else for `region` the cloud metadata service's.
+pub fn variable(name: &str, vars: &BTreeMap<String, String>) -> Option<String> {
+    vars.get(name)
+        .cloned()
+        .or_else(|| {
+            std::env::var(name.to_uppercase())
+                .ok()
+                .filter(|v| !v.is_empty())
+        })
+        .or_else(|| match name {
+            "region" => cloud_region(),
+            _ => None,
+        })
+}
+
+/// `template` with every `{name}` replaced by `variable(name)`.
+pub fn expand(template: &str, variable: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow::anyhow!("{} has a {{ without a closing }}", template))?
+            + start;
+        let name = &rest[start + 1..end];
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(anyhow::anyhow!(
+                "{} uses {{{}}}, which is not a variable name",
+                template,
+                name
+            ));
+        }
+        let value = variable(name).ok_or_else(|| {
+            anyhow::anyhow!(
+                "{} uses {{{}}}, which neither endpoint_vars nor the {} environment variable sets{}",
+                template,
+                name,
+                name.to_uppercase(),
+                match name {
+                    "region" => ", and no cloud metadata service answered",
+                    _ => "",
+                }
+            )
+        })?;
+        expanded.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// The region of the AWS, GCP or Azure instance the agent runs on, asked once.
+fn cloud_region() -> Option<String> {
+    static REGION: OnceLock<Option<String>> = OnceLock::new();
+    REGION
+        .get_or_init(|| {
+            let region = cloud::region()?;
+            println!("Cloud metadata service gives region {}", region);
+            Some(region)
+        })
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoint_variables_are_substituted() {
+        let vars = BTreeMap::from([("region".to_string(), "eu".to_string())]);
+        let lookup = |name: &str| match name {
+            "tenant" => Some("acme".to_string()),
+            name => vars.get(name).cloned(),
+        };
+        assert_eq!(
+            expand(
+                "https://ingest.{region}.signoz.cloud/{tenant}/v1/logs",
+                lookup
+            )
+            .unwrap(),
+            "https://ingest.eu.signoz.cloud/acme/v1/logs"
+        );
+        assert_eq!(
+            expand("http://localhost:4318", lookup).unwrap(),
+            "http://localhost:4318"
+        );
+        for broken in [
+            "https://ingest.{zone}.signoz.cloud",
+            "https://ingest.{region.signoz.cloud",
+            "https://ingest.{}.signoz.cloud",
+        ] {
+            assert!(expand(broken, lookup).is_err(), "{}", broken);
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::clock::Clock;
+use crate::rules::{RegexLimits, Rule};
+use crate::stats::AgentState;
+use crate::{enqueue_batch, queue, Attributes, Body, Record, Severity};
+
+/// The queue partition suppression records are sent through; they are not throttled.
+pub const INPUT: &str = "throttle_rules";
+
+/// How often suppressions are looked at for their record.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A `[[throttle_rules]]` entry: lines matching `pattern` pass at most `max_lines` per
+/// `interval_secs`, and those beyond are counted into one record per interval instead.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ThrottleRule {
+    pub pattern: String,
+    pub max_lines: u32,
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_interval_secs() -> u64 {
+    60
+}
+
+/// A token bucket per rule: `max_lines` tokens, refilled over `interval_secs`.
+struct Bucket {
+    rule: Rule,
+    pattern: String,
+    capacity: f64,
+    interval: chrono::Duration,
+    tokens: f64,
+    refilled: DateTime<Utc>,
+    suppressed: Option<Suppressed>,
+}
+
+/// The lines a rule held back since its last record.
+struct Suppressed {
+    since: DateTime<Utc>,
+    lines: u64,
+    /// The most severe of them, which the record takes.
+    severity: Severity,
+    /// The first of them, unless its input is `metadata_only`.
+    first: Option<String>,
+}
+
+pub struct Throttle {
+    buckets: Mutex<Vec<Bucket>>,
+}
+
+impl Throttle {
+    pub fn new(
+        rules: &[ThrottleRule],
+        limits: &RegexLimits,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<Throttle> {
+        let buckets = rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                if rule.max_lines == 0 || rule.interval_secs == 0 {
+                    return Err(anyhow::anyhow!(
+                        "throttle_rules[{}] max_lines and interval_secs must be greater than 0",
+                        i
+                    ));
+                }
+                Ok(Bucket {
+                    rule: Rule::new(&format!("throttle_rules[{}]", i), &rule.pattern, limits)?,
+                    pattern: rule.pattern.clone(),
+                    capacity: rule.max_lines as f64,
+                    interval: chrono::Duration::seconds(rule.interval_secs as i64),
+                    tokens: rule.max_lines as f64,
+                    refilled: now,
+                    suppressed: None,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Throttle {
+            buckets: Mutex::new(buckets),
+        })
+    }
+
+    pub fn register(&self, state: &Arc<AgentState>) {
+        for bucket in self.buckets.lock().unwrap().iter_mut() {
+            bucket.rule.register(state);
+        }
+    }
+
+    /// Whether the record of `line` passes: true unless the first rule it matches is out
+    /// of lines for now. The record of the lines held back quotes the first of them if
+    /// `quote` is set.
+    pub fn admit(&self, line: &str, severity: Severity, quote: bool, now: DateTime<Utc>) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        let Some(bucket) = buckets.iter_mut().find(|b| b.rule.is_match(line)) else {
+            return true;
+        };
+        let elapsed = (now - bucket.refilled).num_milliseconds().max(0) as f64;
+        let rate = bucket.capacity / bucket.interval.num_milliseconds() as f64;
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(bucket.capacity);
+        bucket.refilled = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return true;
+        }
+        let suppressed = bucket.suppressed.get_or_insert_with(|| Suppressed {
+            since: now,
+            lines: 0,
+            severity,
+            first: quote.then(|| line.to_string()),
+        });
+        suppressed.lines += 1;
+        if severity.number > suppressed.severity.number {
+            suppressed.severity = severity;
+        }
+        false
+    }
+
+    /// A record for each rule that has held lines back for its interval.
+    pub fn summaries(&self, now: DateTime<Utc>) -> Vec<Record> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let mut records = Vec::new();
+        for bucket in buckets.iter_mut() {
+            let interval = bucket.interval;
+            let Some(suppressed) = bucket.suppressed.take_if(|s| now - s.since >= interval) else {
+                continue;
+            };
+            records.push(Record {
+                body: Body::Text(format!(
+                    "Suppressed {} similar lines matching {:?} in the last {}s{}",
+                    suppressed.lines,
+                    bucket.pattern,
+                    (now - suppressed.since).num_seconds(),
+                    suppressed
+                        .first
+                        .map_or(String::new(), |first| format!(", the first: {}", first))
+                )),
+                attributes: Attributes::from(vec![
+                    ("event.name".to_string(), "agent.throttled".to_string()),
+                    ("throttle.pattern".to_string(), bucket.pattern.clone()),
+                    (
+                        "throttle.suppressed".to_string(),
+                        suppressed.lines.to_string(),
+                    ),
+                ]),
+                default_severity: None,
+                severity: Some(suppressed.severity),
+                service_name: None,
+                timestamp: None,
+                observed: now,
+                otlp_path: None,
+                trace: None,
+                resource: None,
+                checkpoint: None,
+            });
+        }
+        records
+    }
+}
+
+/// Sends the suppression records of `throttle` through `tx` as their intervals end.
+pub fn run(
+    throttle: Arc<Throttle>,
+    clock: Arc<dyn Clock>,
+    tx: queue::Sender,
+    state: Arc<AgentState>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        thread::sleep(CHECK_INTERVAL);
+        enqueue_batch(&tx, &state, throttle.summaries(clock.now()));
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_past_the_limit_are_counted_into_one_record() {
+        let start = Utc::now();
+        let at = |secs: i64| start + chrono::Duration::seconds(secs);
+        let rules: Vec<ThrottleRule> = vec![toml::from_str(
+            "pattern = \"retrying connection\"\nmax_lines = 2\ninterval_secs = 10",
+        )
+        .unwrap()];
+        let throttle = Throttle::new(&rules, &RegexLimits::default(), start).unwrap();
+
+        let admitted: Vec<bool> = (0..5)
+            .map(|i| {
+                let severity = if i == 3 {
+                    Severity::ERROR
+                } else {
+                    Severity::WARN
+                };
+                throttle.admit(
+                    &format!("retrying connection #{}", i),
+                    severity,
+                    true,
+                    at(0),
+                )
+            })
+            .collect();
+        assert_eq!(admitted, [true, true, false, false, false]);
+        assert!(throttle.admit("GET /health", Severity::new("INFO", 9), true, at(0)));
+        assert!(throttle.summaries(at(5)).is_empty());
+
+        let records = throttle.summaries(at(10));
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].body.text(),
+            "Suppressed 3 similar lines matching \"retrying connection\" in the last 10s, the first: retrying connection #2"
+        );
+        assert_eq!(records[0].severity, Some(Severity::ERROR));
+        assert!(throttle.summaries(at(20)).is_empty());
+
+        // Half the interval later, half the lines are let through again.
+        assert!(throttle.admit("retrying connection #5", Severity::WARN, true, at(5)));
+        assert!(!throttle.admit("retrying connection #6", Severity::WARN, false, at(5)));
+
+        let zero: Vec<ThrottleRule> =
+            vec![toml::from_str("pattern = \"x\"\nmax_lines = 0").unwrap()];
+        assert!(Throttle::new(&zero, &RegexLimits::default(), start).is_err());
+    }
+}
@@ -0,0 +1,625 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, TimeZone, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+use crate::fieldpath::FieldPath;
+use crate::processor::Processor;
+use crate::stats::AgentState;
+use crate::{parse_duration, rules, Record};
+
+/// Where an input's event time lives and how it is written.
+///
+/// The field is looked up among the record's attributes first, so parsers that extract
+/// fields share this one setting, and otherwise as a `key=value` or `"key": "value"` pair
+/// in the line itself. It may be a path into nested JSON, such as `meta.ts` or
+/// `events[0].time`. Without a field, the first timestamp in the line written in one of
+/// the `auto` formats is taken.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimestampConfig {
+    pub field: Option<String>,
+    /// Tried in order: "auto", "rfc3339", "rfc2822", "unix", "unix_ms", "unix_us",
+    /// "unix_ns" or a strftime pattern (read as UTC when it carries no offset).
+    #[serde(default = "default_formats")]
+    pub formats: Vec<String>,
+    /// Language of month names in the value ("fr", "de_DE", ...), which are translated to
+    /// English before the formats' `%b`/`%B` see them.
+    pub locale: Option<String>,
+}
+
+fn default_formats() -> Vec<String> {
+    vec!["rfc3339".to_string()]
+}
+
+pub struct TimestampExtractor {
+    field: Option<FieldPath>,
+    /// Finds the field's value, or without a field a timestamp, in the line.
+    pattern: Regex,
+    formats: Vec<String>,
+    months: Option<&'static Months>,
+}
+
+/// Full month names of a language, plus abbreviations that are not a prefix of them.
+struct Months {
+    names: [&'static str; 12],
+    abbreviations: &'static [(&'static str, usize)],
+}
+
+/// Timestamps found in lines without a `field`: ISO 8601 / RFC 3339 (also with a space or
+/// a comma before the fraction), the bracketed time of the Common Log Format, and the
+/// BSD syslog time at the start of the line.
+const AUTO_PATTERN: &str = concat!(
+    r"(\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(?:[.,]\d+)?(?:Z|[+-]\d{2}:?\d{2})?)",
+    r"|\[(\d{2}/[A-Za-z]{3}/\d{4}:\d{2}:\d{2}:\d{2} [+-]\d{4})\]",
+    r"|^(?:<\d+>)?([A-Z][a-z]{2} [ \d]\d \d{2}:\d{2}:\d{2})\b",
+);
+
+/// The `auto` formats with an offset, then those read as UTC.
+const AUTO_OFFSET_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f%:z",
+    "%Y-%m-%d %H:%M:%S%.f%z",
+    "%Y-%m-%dT%H:%M:%S%.f%z",
+    "%d/%b/%Y:%H:%M:%S %z",
+];
+const AUTO_NAIVE_FORMATS: &[&str] = &["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"];
+
+const ENGLISH_MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const LOCALES: &[(&str, Months)] = &[
+    (
+        "fr",
+        Months {
+            names: [
+                "janvier",
+                "février",
+                "mars",
+                "avril",
+                "mai",
+                "juin",
+                "juillet",
+                "août",
+                "septembre",
+                "octobre",
+                "novembre",
+                "décembre",
+            ],
+            abbreviations: &[],
+        },
+    ),
+    (
+        "de",
+        Months {
+            names: [
+                "januar",
+                "februar",
+                "märz",
+                "april",
+                "mai",
+                "juni",
+                "juli",
+                "august",
+                "september",
+                "oktober",
+                "november",
+                "dezember",
+            ],
+            abbreviations: &[("mrz", 2), ("jän", 0), ("jänner", 0)],
+        },
+    ),
+    (
+        "es",
+        Months {
+            names: [
+                "enero",
+                "febrero",
+                "marzo",
+                "abril",
+                "mayo",
+                "junio",
+                "julio",
+                "agosto",
+                "septiembre",
+                "octubre",
+                "noviembre",
+                "diciembre",
+            ],
+            abbreviations: &[("set", 8), ("setiembre", 8)],
+        },
+    ),
+    (
+        "it",
+        Months {
+            names: [
+                "gennaio",
+                "febbraio",
+                "marzo",
+                "aprile",
+                "maggio",
+                "giugno",
+                "luglio",
+                "agosto",
+                "settembre",
+                "ottobre",
+                "novembre",
+                "dicembre",
+            ],
+            abbreviations: &[],
+        },
+    ),
+    (
+        "pt",
+        Months {
+            names: [
+                "janeiro",
+                "fevereiro",
+                "março",
+                "abril",
+                "maio",
+                "junho",
+                "julho",
+                "agosto",
+                "setembro",
+                "outubro",
+                "novembro",
+                "dezembro",
+            ],
+            abbreviations: &[],
+        },
+    ),
+    (
+        "nl",
+        Months {
+            names: [
+                "januari",
+                "februari",
+                "maart",
+                "april",
+                "mei",
+                "juni",
+                "juli",
+                "augustus",
+                "september",
+                "oktober",
+                "november",
+                "december",
+            ],
+            abbreviations: &[("mrt", 2)],
+        },
+    ),
+];
+
+/// Month names of `locale`, `None` for English, whose names need no translation.
+fn months_for(locale: &str) -> anyhow::Result<Option<&'static Months>> {
+    let language = locale
+        .split(['_', '-', '.'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    if language == "en" || language == "c" {
+        return Ok(None);
+    }
+    LOCALES
+        .iter()
+        .find(|(name, _)| *name == language)
+        .map(|(_, months)| Some(months))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Unsupported timestamp locale {:?}; supported: en, {}",
+                locale,
+                LOCALES
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+}
+
+/// The accented letters of month names folded to ASCII, so "fevrier" matches "février".
+fn fold(word: &str) -> String {
+    word.chars()
+        .map(|c| match c {
+            'à' | 'â' | 'ä' => 'a',
+            'ç' => 'c',
+            'é' | 'è' | 'ê' | 'ë' => 'e',
+            'î' | 'ï' => 'i',
+            'ô' | 'ö' => 'o',
+            'ù' | 'û' | 'ü' => 'u',
+            c => c,
+        })
+        .collect()
+}
+
+impl Months {
+    /// Index of the month `word` names and whether it is the full name; abbreviations are
+    /// prefixes of at least three letters naming a single month, or listed ones.
+    fn find(&self, word: &str) -> Option<(usize, bool)> {
+        let word = fold(&word.to_lowercase());
+        let names: Vec<String> = self.names.iter().map(|n| fold(n)).collect();
+        if let Some(month) = names.iter().position(|n| *n == word) {
+            return Some((month, true));
+        }
+        if let Some(&(_, month)) = self.abbreviations.iter().find(|(a, _)| fold(a) == word) {
+            return Some((month, false));
+        }
+        if word.chars().count() < 3 {
+            return None;
+        }
+        let mut matches = names
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| n.starts_with(&word));
+        match (matches.next(), matches.next()) {
+            (Some((month, _)), None) => Some((month, false)),
+            _ => None,
+        }
+    }
+
+    /// `value` with month names replaced by the English ones, full names for `%B` and
+    /// three-letter abbreviations for `%b`; the dot ending an abbreviation ("janv.") is
+    /// dropped.
+    fn translate(&self, value: &str) -> String {
+        let mut out = String::with_capacity(value.len());
+        let mut rest = value;
+        while let Some(start) = rest.find(char::is_alphabetic) {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let end = rest
+                .find(|c: char| !c.is_alphabetic())
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            rest = &rest[end..];
+            match self.find(word) {
+                Some((month, true)) => out.push_str(ENGLISH_MONTHS[month]),
+                Some((month, false)) => {
+                    out.push_str(&ENGLISH_MONTHS[month][..3]);
+                    rest = rest.strip_prefix('.').unwrap_or(rest);
+                }
+                None => out.push_str(word),
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+impl TimestampExtractor {
+    pub fn new(config: &TimestampConfig) -> anyhow::Result<TimestampExtractor> {
+        let pattern = match config.field {
+            Some(ref field) if field.trim().is_empty() => {
+                return Err(anyhow::anyhow!("Timestamp field must not be empty"))
+            }
+            Some(ref field) if config.formats.is_empty() => {
+                return Err(anyhow::anyhow!("Timestamp field {} has no formats", field))
+            }
+            Some(ref field) => rules::builtin(&format!(
+                r#"(?:^|[\s{{,])"?{}"?\s*[=:]\s*(?:"([^"]*)"|([^\s,}}"]+))"#,
+                regex::escape(field)
+            ))?,
+            None => rules::builtin(AUTO_PATTERN)?,
+        };
+        let months = match config.locale {
+            Some(ref locale) => months_for(locale)?,
+            None => None,
+        };
+        Ok(TimestampExtractor {
+            field: config.field.as_deref().map(FieldPath::parse).transpose()?,
+            pattern,
+            formats: config.formats.clone(),
+            months,
+        })
+    }
+
+    pub fn extract(&self, line: &str, attributes: &[(String, String)]) -> Option<DateTime<Utc>> {
+        let Some(ref field) = self.field else {
+            let caps = self.pattern.captures(line)?;
+            let value = caps.iter().skip(1).flatten().next()?.as_str();
+            return parse_auto(value);
+        };
+        let value = match field.lookup(attributes) {
+            Some(value) => value,
+            // A line read as plain that is JSON, with the field below its top level.
+            None if field.is_nested() && line.trim_start().starts_with('{') => {
+                let Ok(serde_json::Value::Object(fields)) = serde_json::from_str(line) else {
+                    return None;
+                };
+                Cow::Owned(crate::fieldpath::text(field.get(&fields)?)?)
+            }
+            None => {
+                let caps = self.pattern.captures(line)?;
+                Cow::Borrowed(caps.get(1).or_else(|| caps.get(2))?.as_str())
+            }
+        };
+        match self.months {
+            Some(months) => {
+                let value = months.translate(&value);
+                self.formats.iter().find_map(|f| parse_with(f, &value))
+            }
+            None => self.formats.iter().find_map(|f| parse_with(f, &value)),
+        }
+    }
+}
+
+fn parse_with(format: &str, value: &str) -> Option<DateTime<Utc>> {
+    let epoch = |scale: f64| {
+        let n: f64 = value.parse().ok()?;
+        (n > 0.0).then(|| Utc.timestamp_nanos((n * scale) as i64))
+    };
+    match format {
+        "auto" => parse_auto(value),
+        "rfc3339" => DateTime::parse_from_rfc3339(value)
+            .ok()
+            .map(|t| t.with_timezone(&Utc)),
+        "rfc2822" => DateTime::parse_from_rfc2822(value)
+            .ok()
+            .map(|t| t.with_timezone(&Utc)),
+        "unix" => epoch(1e9),
+        "unix_ms" => epoch(1e6),
+        "unix_us" => epoch(1e3),
+        "unix_ns" => epoch(1.0),
+        pattern => DateTime::parse_from_str(value, pattern)
+            .map(|t| t.with_timezone(&Utc))
+            .or_else(|_| NaiveDateTime::parse_from_str(value, pattern).map(|t| t.and_utc()))
+            .ok(),
+    }
+}
+
+/// Correction of an input's event times, for a source whose clock is off.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClockSkewConfig {
+    /// Added to every event time, with an optional sign: "-3m" for a clock that runs
+    /// 3 minutes fast.
+    pub offset: Option<String>,
+    /// Also correct for a clock found to run ahead of the time records are read.
+    #[serde(default)]
+    pub estimate: bool,
+}
+
+/// Records the estimate looks back on.
+const SKEW_WINDOW: usize = 100;
+
+pub struct SkewCorrection {
+    offset: Duration,
+    /// Receive time minus event time of the last records, in milliseconds.
+    window: Option<Mutex<VecDeque<i64>>>,
+}
+
+impl SkewCorrection {
+    pub fn new(config: &ClockSkewConfig) -> anyhow::Result<SkewCorrection> {
+        let offset = match config.offset.as_deref().map(str::trim) {
+            Some(offset) => {
+                let (negative, value) = match offset.strip_prefix('-') {
+                    Some(value) => (true, value),
+                    None => (false, offset.strip_prefix('+').unwrap_or(offset)),
+                };
+                let offset = Duration::from_std(parse_duration(value)?)?;
+                if negative {
+                    -offset
+                } else {
+                    offset
+                }
+            }
+            None => Duration::zero(),
+        };
+        Ok(SkewCorrection {
+            offset,
+            window: config
+                .estimate
+                .then(|| Mutex::new(VecDeque::with_capacity(SKEW_WINDOW))),
+        })
+    }
+
+    /// `time` with the offset added and, when estimating, moved back by how far the
+    /// recent records were ahead of `observed` at most. Records read late look like a
+    /// clock running behind, so only a clock running ahead is corrected.
+    pub fn apply(&self, time: DateTime<Utc>, observed: DateTime<Utc>) -> DateTime<Utc> {
+        let time = time + self.offset;
+        let Some(ref window) = self.window else {
+            return time;
+        };
+        let mut window = window.lock().unwrap();
+        if window.len() == SKEW_WINDOW {
+            window.pop_front();
+        }
+        window.push_back((observed - time).num_milliseconds());
+        let ahead = window.iter().copied().min().unwrap_or(0).min(0);
+        time + Duration::milliseconds(ahead)
+    }
+}
+
+/// Attribute set on records whose event time is too far from the time they were read.
+pub const SUSPECT_ATTRIBUTE: &str = "log.timestamp_suspect";
+
+/// `[timestamp_check]`: flags records whose event time is further from the time the
+/// agent read them than a source with a working clock would write, such as a host whose
+/// NTP is broken, before they land in the wrong time range.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TimestampCheckConfig {
+    /// How far ahead of the time read an event time may be.
+    #[serde(default = "default_max_ahead")]
+    pub max_ahead: String,
+    /// How far behind it may be; unset, since a file read late holds old records too.
+    pub max_behind: Option<String>,
+}
+
+fn default_max_ahead() -> String {
+    "5m".to_string()
+}
+
+pub struct TimestampCheck {
+    max_ahead: Duration,
+    max_behind: Option<Duration>,
+    state: Arc<AgentState>,
+}
+
+impl TimestampCheck {
+    pub fn new(config: &TimestampCheckConfig, state: Arc<AgentState>) -> anyhow::Result<Self> {
+        let duration = |value: &str| -> anyhow::Result<Duration> {
+            Ok(Duration::from_std(parse_duration(value)?)?)
+        };
+        Ok(TimestampCheck {
+            max_ahead: duration(&config.max_ahead)?,
+            max_behind: config.max_behind.as_deref().map(duration).transpose()?,
+            state,
+        })
+    }
+}
+
+impl Processor for TimestampCheck {
+    fn process(&mut self, mut record: Record) -> Option<Record> {
+        let Some(timestamp) = record.timestamp else {
+            return Some(record);
+        };
+        let skew = timestamp - record.observed;
+        if skew > self.max_ahead || self.max_behind.is_some_and(|behind| -skew > behind) {
+            record
+                .attributes
+                .push(SUSPECT_ATTRIBUTE.to_string(), "true".to_string());
+            self.state
+                .suspect_timestamps
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        Some(record)
+    }
+}
+
+/// `value` in any of the formats `AUTO_PATTERN` finds, or RFC 2822. A syslog time has no
+/// year; it is taken to be in the past year up to a day from now.
+fn parse_auto(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim().replacen(',', ".", 1);
+    if let Ok(t) = DateTime::parse_from_rfc3339(&value) {
+        return Some(t.with_timezone(&Utc));
+    }
+    if let Some(t) = AUTO_OFFSET_FORMATS
+        .iter()
+        .find_map(|f| DateTime::parse_from_str(&value, f).ok())
+    {
+        return Some(t.with_timezone(&Utc));
+    }
+    if let Some(t) = AUTO_NAIVE_FORMATS
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(&value, f).ok())
+    {
+        return Some(t.and_utc());
+    }
+    if let Ok(t) = DateTime::parse_from_rfc2822(&value) {
+        return Some(t.with_timezone(&Utc));
+    }
+    let now = Utc::now();
+    let in_year = |year: i32| {
+        NaiveDateTime::parse_from_str(&format!("{} {}", year, value), "%Y %b %e %H:%M:%S")
+            .ok()
+            .map(|t| t.and_utc())
+    };
+    let t = in_year(now.year())?;
+    if t > now + Duration::days(1) {
+        in_year(now.year() - 1)
+    } else {
+        Some(t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn without_a_field_iso_clf_and_syslog_times_are_found_in_the_line() {
+        let extractor = TimestampExtractor::new(&TimestampConfig {
+            field: None,
+            formats: default_formats(),
+            locale: None,
+        })
+        .unwrap();
+        let time = |line: &str| extractor.extract(line, &[]).map(|t| t.to_rfc3339());
+        assert_eq!(
+            time("2024-03-05 10:11:12,345 ERROR Payment failed"),
+            Some("2024-03-05T10:11:12.345+00:00".to_string())
+        );
+        assert_eq!(
+            time(r#"10.0.0.1 - - [05/Mar/2024:10:11:12 +0100] "GET / HTTP/1.1" 200 512"#),
+            Some("2024-03-05T09:11:12+00:00".to_string())
+        );
+        let syslog = extractor
+            .extract("<34>Mar  5 10:11:12 web1 sshd[42]: Accepted key", &[])
+            .unwrap();
+        assert_eq!(
+            syslog.format("%m-%d %H:%M:%S").to_string(),
+            "03-05 10:11:12"
+        );
+        assert!(syslog <= Utc::now() + Duration::days(1));
+        assert_eq!(time("no time here"), None);
+    }
+
+    #[test]
+    fn times_far_from_when_they_were_read_are_suspect() {
+        let state = Arc::new(AgentState::default());
+        let config: TimestampCheckConfig = toml::from_str("max_behind = \"1d\"").unwrap();
+        let mut check = TimestampCheck::new(&config, state.clone()).unwrap();
+        let observed = Utc.with_ymd_and_hms(2024, 3, 5, 10, 0, 0).unwrap();
+        let mut suspect = |timestamp: Option<DateTime<Utc>>| {
+            let record = Record {
+                timestamp,
+                observed,
+                ..Record::new("")
+            };
+            let record = check.process(record).unwrap();
+            record
+                .attributes
+                .iter()
+                .any(|(k, v)| k == SUSPECT_ATTRIBUTE && v == "true")
+        };
+
+        assert!(!suspect(None));
+        assert!(!suspect(Some(observed + Duration::minutes(4))));
+        assert!(suspect(Some(observed + Duration::minutes(6))));
+        assert!(!suspect(Some(observed - Duration::hours(23))));
+        assert!(suspect(Some(observed - Duration::hours(25))));
+        assert_eq!(state.suspect_timestamps.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn skew_offset_and_estimate_move_times_back() {
+        let observed = Utc.with_ymd_and_hms(2024, 3, 5, 10, 0, 0).unwrap();
+        let fixed = SkewCorrection::new(&ClockSkewConfig {
+            offset: Some("-3m".to_string()),
+            estimate: false,
+        })
+        .unwrap();
+        assert_eq!(
+            fixed.apply(observed + Duration::minutes(3), observed),
+            observed
+        );
+
+        let estimated = SkewCorrection::new(&ClockSkewConfig {
+            offset: None,
+            estimate: true,
+        })
+        .unwrap();
+        // A record read late says nothing about the clock.
+        let late = observed - Duration::minutes(10);
+        assert_eq!(estimated.apply(late, observed), late);
+        let ahead = observed + Duration::seconds(90);
+        assert_eq!(estimated.apply(ahead, observed), observed);
+        assert_eq!(
+            estimated.apply(late, observed),
+            late - Duration::seconds(90)
+        );
+    }
+}
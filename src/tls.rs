@@ -0,0 +1,69 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// Client TLS of the agent's HTTPS and gRPC connections, for collectors whose certificate
+/// an internal CA signed or that ask for a client certificate.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TlsConfig {
+    /// PEM bundle of CA certificates trusted besides the system's.
+    pub ca_file: Option<String>,
+    /// PEM certificate chain presented to collectors that ask for one (mTLS).
+    pub cert_file: Option<String>,
+    /// PEM PKCS#8 private key of `cert_file`.
+    pub key_file: Option<String>,
+    /// Accept any server certificate, signed by whoever for whatever name. OTLP/HTTP only.
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+}
+
+pub fn read(path: &str) -> anyhow::Result<Vec<u8>> {
+    fs::read(path).with_context(|| format!("Cannot read {}", path))
+}
+
+impl TlsConfig {
+    /// Reads and parses the configured files.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.cert_file.is_some() != self.key_file.is_some() {
+            return Err(anyhow::anyhow!("cert_file and key_file are set together"));
+        }
+        self.ca_certificates()?;
+        self.identity()?;
+        Ok(())
+    }
+
+    pub fn ca_certificates(&self) -> anyhow::Result<Vec<reqwest::Certificate>> {
+        let Some(ref path) = self.ca_file else {
+            return Ok(Vec::new());
+        };
+        let certificates = reqwest::Certificate::from_pem_bundle(&read(path)?)
+            .with_context(|| format!("Invalid CA bundle {}", path))?;
+        if certificates.is_empty() {
+            return Err(anyhow::anyhow!("No certificate in CA bundle {}", path));
+        }
+        Ok(certificates)
+    }
+
+    /// The client certificate and its key, when set.
+    pub fn identity(&self) -> anyhow::Result<Option<reqwest::Identity>> {
+        let (Some(ref cert), Some(ref key)) = (&self.cert_file, &self.key_file) else {
+            return Ok(None);
+        };
+        let identity = reqwest::Identity::from_pkcs8_pem(&read(cert)?, &read(key)?)
+            .with_context(|| format!("Invalid client certificate {} or key {}", cert, key))?;
+        Ok(Some(identity))
+    }
+
+    pub fn apply(
+        &self,
+        mut builder: reqwest::blocking::ClientBuilder,
+    ) -> anyhow::Result<reqwest::blocking::ClientBuilder> {
+        for certificate in self.ca_certificates()? {
+            builder = builder.add_root_certificate(certificate);
+        }
+        if let Some(identity) = self.identity()? {
+            builder = builder.identity(identity);
+        }
+        Ok(builder.danger_accept_invalid_certs(self.insecure_skip_verify))
+    }
+}
@@ -0,0 +1,147 @@
+use chrono::Utc;
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::fileid::FileId;
+
+/// Default `deleted_file_grace_secs`.
+pub const DEFAULT_GRACE_SECS: u64 = 5;
+/// Tombstones kept; the oldest are forgotten first.
+const MAX_TOMBSTONES: usize = 1024;
+
+/// A deleted file whose handle was released. Its inode number may be reused by any file
+/// created afterwards, which must then not be taken for the deleted one.
+#[derive(Debug, Clone)]
+pub struct Tombstone {
+    pub dev: u64,
+    pub ino: u64,
+    pub path: String,
+    pub released_unix: i64,
+}
+
+static TOMBSTONES: Mutex<Vec<Tombstone>> = Mutex::new(Vec::new());
+
+fn bury(tombstone: Tombstone) {
+    let mut tombstones = TOMBSTONES.lock().unwrap();
+    if tombstones.len() == MAX_TOMBSTONES {
+        tombstones.remove(0);
+    }
+    tombstones.push(tombstone);
+}
+
+/// The tombstone of a deleted file whose inode `meta` now belongs to, if any.
+pub fn recycled(meta: &fs::Metadata) -> Option<Tombstone> {
+    TOMBSTONES
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .find(|t| t.dev == meta.dev() && t.ino == meta.ino())
+        .cloned()
+}
+
+/// What a tailer should do with its open file after reaching its end.
+pub enum Follow {
+    /// Keep reading the open file.
+    Keep,
+    /// The open file was deleted or replaced and its grace period is over: read this
+    /// file, now at the path, from the start instead.
+    Switch(fs::File),
+    /// As `Switch`, but nothing is at the path yet; wait for a file to appear.
+    Release,
+}
+
+/// Notices when the file a tailer holds open no longer is the one at its path (deleted,
+/// or renamed away and replaced). The open handle is read for `grace` longer, for writes
+/// still arriving through it, then released so a deleted file's disk space is freed.
+/// Without `finish_rotated`, a file renamed away is let go of right away instead.
+pub struct Follower {
+    path: String,
+    grace: Duration,
+    finish_rotated: bool,
+    gone_since: Option<Instant>,
+}
+
+impl Follower {
+    pub fn new(path: &str, grace: Duration, finish_rotated: bool) -> Follower {
+        Follower {
+            path: path.to_string(),
+            grace,
+            finish_rotated,
+            gone_since: None,
+        }
+    }
+
+    /// Called when `file` has been read to its end.
+    pub fn poll(&mut self, file: &fs::File) -> Follow {
+        let Ok(open) = file.metadata() else {
+            return Follow::Keep;
+        };
+        let current = fs::metadata(&self.path)
+            .map(|m| m.dev() == open.dev() && m.ino() == open.ino())
+            .unwrap_or(false);
+        if current && open.nlink() > 0 {
+            self.gone_since = None;
+            return Follow::Keep;
+        }
+        if open.nlink() > 0 && !self.finish_rotated {
+            return self.switch();
+        }
+        let gone_since = *self.gone_since.get_or_insert_with(|| {
+            println!(
+                "{} was {}; reading the open file for {}s more",
+                self.path,
+                if open.nlink() == 0 {
+                    "deleted"
+                } else {
+                    "replaced"
+                },
+                self.grace.as_secs()
+            );
+            Instant::now()
+        });
+        if gone_since.elapsed() < self.grace {
+            return Follow::Keep;
+        }
+        self.gone_since = None;
+        if open.nlink() == 0 {
+            bury(Tombstone {
+                dev: open.dev(),
+                ino: open.ino(),
+                path: self.path.clone(),
+                released_unix: Utc::now().timestamp(),
+            });
+        }
+        self.switch()
+    }
+
+    fn switch(&self) -> Follow {
+        match self.reopen() {
+            Some(file) => Follow::Switch(file),
+            None => {
+                println!("Released {}; waiting for it to be created again", self.path);
+                Follow::Release
+            }
+        }
+    }
+
+    /// The file now at the path, opened; to be read from the start.
+    pub fn reopen(&self) -> Option<fs::File> {
+        let file = fs::File::open(&self.path).ok()?;
+        let meta = file.metadata().ok()?;
+        match recycled(&meta) {
+            Some(tombstone) => println!(
+                "{} reuses inode {} of a file deleted from {} (released {}); reading it as a new file",
+                self.path,
+                tombstone.ino,
+                tombstone.path,
+                chrono::DateTime::from_timestamp(tombstone.released_unix, 0)
+                    .map(|t| t.to_rfc3339())
+                    .unwrap_or_default()
+            ),
+            None => println!("Following the new {}", self.path),
+        }
+        Some(file)
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::Result;
+use chrono::{TimeZone, Utc};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::admin;
+use crate::stats::StatusSnapshot;
+
+/// Live terminal view of a running agent, polling its admin socket until interrupted.
+pub fn run(socket_path: &str, interval: Duration) -> Result<()> {
+    let mut previous: Option<(Instant, Counters, Counters)> = None;
+
+    loop {
+        let snapshot = admin::request_status(socket_path)?;
+        let now = Instant::now();
+        let input_lines: Counters = snapshot
+            .inputs
+            .iter()
+            .map(|i| (i.name.clone(), i.lines))
+            .collect();
+        let exporter_sent: Counters = snapshot
+            .exporters
+            .iter()
+            .map(|e| (e.name.clone(), e.sent))
+            .collect();
+
+        let rates = previous.as_ref().map(|(at, lines, sent)| {
+            let elapsed = now.duration_since(*at).as_secs_f64().max(0.001);
+            let rate = |prev: &Counters, name: &str, current: u64| {
+                current.saturating_sub(*prev.get(name).unwrap_or(&current)) as f64 / elapsed
+            };
+            (
+                snapshot
+                    .inputs
+                    .iter()
+                    .map(|i| (i.name.clone(), rate(lines, &i.name, i.lines)))
+                    .collect::<HashMap<_, _>>(),
+                snapshot
+                    .exporters
+                    .iter()
+                    .map(|e| (e.name.clone(), rate(sent, &e.name, e.sent)))
+                    .collect::<HashMap<_, _>>(),
+            )
+        });
+
+        render(&snapshot, rates.as_ref())?;
+        previous = Some((now, input_lines, exporter_sent));
+        thread::sleep(interval);
+    }
+}
+
+type Counters = HashMap<String, u64>;
+type Rates = (HashMap<String, f64>, HashMap<String, f64>);
+
+fn render(snapshot: &StatusSnapshot, rates: Option<&Rates>) -> Result<()> {
+    let mut out = io::stdout().lock();
+    // Clear the screen and move the cursor home.
+    write!(out, "\x1b[2J\x1b[H")?;
+    writeln!(
+        out,
+        "rust-signoz-agent top — {} (Ctrl+C to exit)",
+        Utc::now().format("%H:%M:%S")
+    )?;
+    writeln!(out, "Queued: {}", snapshot.queued)?;
+    if !snapshot.dropped.is_empty() {
+        let dropped: Vec<String> = snapshot
+            .dropped
+            .iter()
+            .map(|(reason, count)| format!("{}={}", reason, count))
+            .collect();
+        writeln!(out, "Dropped: {}", dropped.join(" "))?;
+    }
+    writeln!(out)?;
+
+    writeln!(
+        out,
+        "{:<40} {:>10} {:>12} {:>12} {:>10}",
+        "INPUT", "LINES/S", "TOTAL", "LAG BYTES", "LAST"
+    )?;
+    for input in &snapshot.inputs {
+        let rate = rates.and_then(|(r, _)| r.get(&input.name)).copied();
+        writeln!(
+            out,
+            "{:<40} {:>10} {:>12} {:>12} {:>10}",
+            truncate(&input.name, 40),
+            format_rate(rate),
+            input.lines,
+            input.lag_bytes,
+            format_age(input.last_line_unix)
+        )?;
+    }
+    writeln!(out)?;
+
+    writeln!(
+        out,
+        "{:<20} {:>10} {:>10} {:>12} {:>10} {:>10} {:>12}",
+        "EXPORTER", "SENT/S", "PENDING", "SENT", "FAILED", "ENCODING", "STATUS"
+    )?;
+    for exporter in &snapshot.exporters {
+        let rate = rates.and_then(|(_, r)| r.get(&exporter.name)).copied();
+        let status = if snapshot.exports_paused {
+            "paused".to_string()
+        } else if exporter.consecutive_failures > 0 {
+            format!("failing x{}", exporter.consecutive_failures)
+        } else if exporter.buffered > 0 {
+            format!("{} on disk", exporter.buffered)
+        } else {
+            "ok".to_string()
+        };
+        writeln!(
+            out,
+            "{:<20} {:>10} {:>10} {:>12} {:>10} {:>10} {:>12}",
+            truncate(&exporter.name, 20),
+            format_rate(rate),
+            exporter.pending,
+            exporter.sent,
+            exporter.failed,
+            exporter.encoding,
+            status
+        )?;
+    }
+    writeln!(out)?;
+
+    writeln!(out, "RECENT ERRORS")?;
+    for error in snapshot.recent_errors.iter().rev().take(10) {
+        let time = Utc
+            .timestamp_opt(error.time_unix, 0)
+            .single()
+            .map(|t| t.format("%H:%M:%S").to_string())
+            .unwrap_or_default();
+        writeln!(out, "{} {}", time, truncate(&error.message, 110))?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn format_rate(rate: Option<f64>) -> String {
+    rate.map_or("-".to_string(), |r| format!("{:.1}", r))
+}
+
+fn format_age(unix: i64) -> String {
+    if unix == 0 {
+        return "never".to_string();
+    }
+    let age = (Utc::now().timestamp() - unix).max(0);
+    format!("{}s ago", age)
+}
+
+fn truncate(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        s.to_string()
+    } else {
+        let kept: String = s.chars().take(max.saturating_sub(1)).collect();
+        format!("{}…", kept)
+    }
+}
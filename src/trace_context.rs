@@ -0,0 +1,72 @@
+/// The trace a record belongs to, shipped as its `traceId` and `spanId`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    /// 32 lowercase hex characters.
+    pub trace_id: String,
+    /// 16 lowercase hex characters.
+    pub span_id: String,
+}
+
+/// Environment variable holding a W3C `traceparent`, as set for traced jobs by CI
+/// systems and tools such as `otel-cli`.
+const TRACEPARENT_VAR: &str = "TRACEPARENT";
+
+impl TraceContext {
+    /// Reads a W3C `traceparent` value: `<version>-<trace-id>-<parent-id>-<flags>`.
+    /// All-zero ids are invalid, and so is version `ff`; later versions may add fields.
+    pub fn parse(traceparent: &str) -> Option<TraceContext> {
+        let mut fields = traceparent.trim().split('-');
+        let version = fields.next()?;
+        let (trace_id, span_id, flags) = (fields.next()?, fields.next()?, fields.next()?);
+        let hex = |s: &str, len: usize| {
+            s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        };
+        let valid = hex(version, 2)
+            && version != "ff"
+            && (version != "00" || fields.next().is_none())
+            && hex(trace_id, 32)
+            && hex(span_id, 16)
+            && hex(flags, 2)
+            && trace_id.bytes().any(|b| b != b'0')
+            && span_id.bytes().any(|b| b != b'0');
+        valid.then(|| TraceContext {
+            trace_id: trace_id.to_string(),
+            span_id: span_id.to_string(),
+        })
+    }
+
+    /// The trace of the job the agent runs in, if its environment names a valid one.
+    pub fn from_env() -> Option<TraceContext> {
+        let value = std::env::var(TRACEPARENT_VAR).ok()?;
+        let context = TraceContext::parse(&value);
+        if context.is_none() {
+            eprintln!("Ignoring invalid {}: {:?}", TRACEPARENT_VAR, value);
+        }
+        context
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traceparent_is_validated() {
+        let context =
+            TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        assert_eq!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.span_id, "00f067aa0ba902b7");
+        for invalid in [
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        ] {
+            assert_eq!(TraceContext::parse(invalid), None, "{}", invalid);
+        }
+        assert!(TraceContext::parse(
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra"
+        )
+        .is_some());
+    }
+}
@@ -0,0 +1,171 @@
+use serde::{Deserialize, Serialize};
+
+/// Seconds before a restart after a failure, without `restart_secs`.
+const DEFAULT_RESTART_SECS: u64 = 5;
+
+/// How many restarts it takes to get from `restart_secs` to `restart_max_delay_secs`.
+const RESTART_STEPS: u32 = 5;
+
+/// Startup ordering, delay and restart pacing of the systemd unit `install-service` writes,
+/// so that the agent starts after the applications whose logs it ships.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ServiceUnit {
+    /// Start once the network is configured (network-online.target), not just up.
+    #[serde(default)]
+    pub wait_for_network: bool,
+    /// Units the agent starts after, e.g. the services whose logs it ships.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub after: Vec<String>,
+    /// Units the agent needs: it starts after them, not at all when they fail to, and is
+    /// stopped with them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub requires: Vec<String>,
+    /// Seconds to wait before starting, e.g. for applications that create their log files
+    /// a while after they started.
+    pub start_delay_secs: Option<u64>,
+    /// Seconds before a restart after a failure.
+    pub restart_secs: Option<u64>,
+    /// Consecutive restarts wait longer and longer, up to this (systemd 254 and later).
+    pub restart_max_delay_secs: Option<u64>,
+    /// Restarts are given up once the agent was started this often within
+    /// `start_limit_interval_secs`.
+    pub start_limit_burst: Option<u32>,
+    pub start_limit_interval_secs: Option<u64>,
+    /// Also write a socket unit holding the syslog and StatsD listeners, so that they stay
+    /// open while the agent restarts or is upgraded.
+    #[serde(default)]
+    pub socket_activation: bool,
+}
+
+impl ServiceUnit {
+    pub fn is_default(&self) -> bool {
+        *self == ServiceUnit::default()
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for name in self.after.iter().chain(&self.requires) {
+            if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c.is_control()) {
+                return Err(anyhow::anyhow!(
+                    "service_unit: {:?} is not a unit name",
+                    name
+                ));
+            }
+        }
+        if self
+            .restart_max_delay_secs
+            .is_some_and(|max| max < self.restart_secs())
+        {
+            return Err(anyhow::anyhow!(
+                "service_unit.restart_max_delay_secs must be at least restart_secs"
+            ));
+        }
+        if self.start_limit_burst == Some(0) {
+            return Err(anyhow::anyhow!(
+                "service_unit.start_limit_burst must be greater than 0"
+            ));
+        }
+        Ok(())
+    }
+
+    fn restart_secs(&self) -> u64 {
+        self.restart_secs.unwrap_or(DEFAULT_RESTART_SECS)
+    }
+
+    /// The ordering and start limit lines of `[Unit]`.
+    pub fn unit_lines(&self) -> String {
+        let mut lines = String::new();
+        let mut after = vec![match self.wait_for_network {
+            true => "network-online.target",
+            false => "network.target",
+        }];
+        let mut requires: Vec<&str> = self.requires.iter().map(String::as_str).collect();
+        if self.socket_activation {
+            requires.push(crate::activation::SOCKET_UNIT);
+        }
+        after.extend(
+            self.after
+                .iter()
+                .map(String::as_str)
+                .chain(requires.clone()),
+        );
+        lines.push_str(&format!("After={}\n", after.join(" ")));
+        if self.wait_for_network {
+            lines.push_str("Wants=network-online.target\n");
+        }
+        if !requires.is_empty() {
+            lines.push_str(&format!("Requires={}\n", requires.join(" ")));
+        }
+        if let Some(interval) = self.start_limit_interval_secs {
+            lines.push_str(&format!("StartLimitIntervalSec={}\n", interval));
+        }
+        if let Some(burst) = self.start_limit_burst {
+            lines.push_str(&format!("StartLimitBurst={}\n", burst));
+        }
+        lines
+    }
+
+    /// The delay and restart lines of `[Service]`.
+    pub fn service_lines(&self) -> String {
+        let mut lines = String::from("Restart=on-failure\n");
+        lines.push_str(&format!("RestartSec={}s\n", self.restart_secs()));
+        if let Some(max) = self.restart_max_delay_secs {
+            lines.push_str(&format!(
+                "RestartSteps={}\nRestartMaxDelaySec={}s\n",
+                RESTART_STEPS, max
+            ));
+        }
+        if let Some(delay) = self.start_delay_secs.filter(|&d| d > 0) {
+            // The delay counts against the start timeout, 90s by default.
+            lines.push_str(&format!(
+                "ExecStartPre=/bin/sleep {}\nTimeoutStartSec={}s\n",
+                delay,
+                delay + 90
+            ));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_orders_the_agent_after_its_applications() {
+        let unit: ServiceUnit = toml::from_str(
+            r#"
+            wait_for_network = true
+            after = ["nginx.service"]
+            requires = ["postgresql.service"]
+            start_delay_secs = 10
+            restart_max_delay_secs = 60
+            "#,
+        )
+        .unwrap();
+        unit.validate().unwrap();
+        assert_eq!(
+            unit.unit_lines(),
+            "After=network-online.target nginx.service postgresql.service\n\
+             Wants=network-online.target\n\
+             Requires=postgresql.service\n"
+        );
+        assert_eq!(
+            unit.service_lines(),
+            "Restart=on-failure\nRestartSec=5s\nRestartSteps=5\nRestartMaxDelaySec=60s\n\
+             ExecStartPre=/bin/sleep 10\nTimeoutStartSec=100s\n"
+        );
+
+        let default = ServiceUnit::default();
+        assert_eq!(default.unit_lines(), "After=network.target\n");
+        assert_eq!(
+            default.service_lines(),
+            "Restart=on-failure\nRestartSec=5s\n"
+        );
+
+        let broken = ServiceUnit {
+            after: vec!["nginx.service\nExecStartPre=/bin/true".to_string()],
+            ..ServiceUnit::default()
+        };
+        assert!(broken.validate().is_err());
+    }
+}
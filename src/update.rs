@@ -0,0 +1,327 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::process::Command;
+use std::time::Duration;
+
+/// Releases API of the project on GitHub; `--releases-url` points elsewhere, e.g. at a
+/// mirror.
+pub const RELEASES_URL: &str = "https://api.github.com/repos/AndreiGam/rust-signoz-agent/releases";
+
+/// Release asset listing the SHA-256 of every binary, as written by `sha256sum`, after
+/// a `VERSION_LINE` naming the release.
+const CHECKSUMS_ASSET: &str = "SHA256SUMS";
+
+/// Starts the `SHA256SUMS` line holding the version released, which `sha256sum -c` skips
+/// as a comment. Signed along with the checksums, unlike the release's tag, so an older
+/// release cannot be passed off as the newest one under a later tag.
+const VERSION_LINE: &str = "# rust-signoz-agent ";
+
+/// Release asset holding the Ed25519 signature of `SHA256SUMS`, base64-encoded, as
+/// `openssl pkeyutl -sign -rawin -inkey release-key.pem -in SHA256SUMS | base64` writes it.
+const SIGNATURE_ASSET: &str = "SHA256SUMS.sig";
+
+/// Public key of the key releases are signed with. `SHA256SUMS` comes from the same
+/// place as the binaries, so whoever could replace a binary could replace its checksum
+/// too; the signature is what tells a release from a tampered one. The private key is
+/// kept offline by the maintainer who publishes releases, never in the repository or in
+/// CI, and `SHA256SUMS` is signed on that machine before it is uploaded (see the README's
+/// Self-Update section). A new key takes a release shipping its public half here, signed
+/// with the old one.
+const RELEASE_KEY: [u8; 32] = [
+    0x55, 0xe4, 0x3d, 0x7e, 0x1e, 0x30, 0x28, 0x4d, 0x1a, 0x98, 0xcb, 0x9b, 0x79, 0x7a, 0x40, 0x54,
+    0x57, 0x9b, 0x7d, 0xd2, 0x40, 0xcd, 0xc7, 0x32, 0x60, 0xbc, 0x20, 0x69, 0x72, 0x4a, 0x1e, 0x6d,
+];
+
+/// systemd unit restarted by `--restart`, as written by `--install-service`.
+const SERVICE: &str = "rust-signoz-agent";
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    #[serde(default)]
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    fn asset(&self, name: &str) -> Result<&Asset> {
+        self.assets
+            .iter()
+            .find(|a| a.name == name)
+            .ok_or_else(|| anyhow::anyhow!("Release {} has no asset {}", self.tag_name, name))
+    }
+}
+
+/// `rust-signoz-agent self-update [--channel stable|prerelease] [--restart]
+/// [--releases-url URL]`: replaces the running binary with that of the newest release
+/// of the channel, once `SHA256SUMS` is signed by the release key for the version the
+/// release is tagged with and lists its checksum.
+pub fn run(channel: &str, releases_url: &str, restart: bool) -> Result<()> {
+    let client = reqwest::blocking::Client::builder()
+        .user_agent(crate::exporter::default_user_agent())
+        .timeout(Duration::from_secs(300))
+        .build()?;
+    let get = |url: &str| -> Result<reqwest::blocking::Response> {
+        let response = client
+            .get(url)
+            .send()
+            .with_context(|| format!("Failed to fetch {}", url))?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("{} answered {}", url, response.status()));
+        }
+        Ok(response)
+    };
+
+    let release: Release = match channel {
+        "stable" => get(&format!("{}/latest", releases_url))?.json()?,
+        "prerelease" => get(&format!("{}?per_page=1", releases_url))?
+            .json::<Vec<Release>>()?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("No releases at {}", releases_url))?,
+        other => {
+            return Err(anyhow::anyhow!(
+                "Unknown channel {:?}; use \"stable\" or \"prerelease\"",
+                other
+            ))
+        }
+    };
+    let current = env!("CARGO_PKG_VERSION");
+    let latest = release.tag_name.trim_start_matches('v');
+    if !is_newer(latest, current) {
+        println!(
+            "rust-signoz-agent {} is up to date ({} channel)",
+            current, channel
+        );
+        return Ok(());
+    }
+
+    let name = format!(
+        "rust-signoz-agent-{}-{}",
+        std::env::consts::ARCH,
+        std::env::consts::OS
+    );
+    let checksums = get(&release.asset(CHECKSUMS_ASSET)?.browser_download_url)?.bytes()?;
+    let signature = get(&release.asset(SIGNATURE_ASSET)?.browser_download_url)?.text()?;
+    verify_signature(&checksums, &signature, &RELEASE_KEY)
+        .and_then(|()| verify_version(&String::from_utf8_lossy(&checksums), latest))
+        .with_context(|| format!("Not installing {}", release.tag_name))?;
+    println!("Downloading {} {}", name, release.tag_name);
+    let binary = get(&release.asset(&name)?.browser_download_url)?.bytes()?;
+    verify_checksum(&String::from_utf8_lossy(&checksums), &name, &binary)?;
+
+    let exe = std::env::current_exe()?.canonicalize()?;
+    replace(&exe, &binary).with_context(|| format!("Failed to replace {}", exe.display()))?;
+    println!(
+        "Updated {} from {} to {}",
+        exe.display(),
+        current,
+        release.tag_name
+    );
+
+    if restart {
+        let status = Command::new("systemctl")
+            .args(["restart", SERVICE])
+            .status()
+            .context("Failed to run systemctl")?;
+        if !status.success() {
+            return Err(anyhow::anyhow!(
+                "systemctl restart {} failed with {}",
+                SERVICE,
+                status
+            ));
+        }
+        println!("Restarted {}", SERVICE);
+    } else {
+        println!("Restart the agent to run the new version");
+    }
+    Ok(())
+}
+
+/// Checks that `signature`, base64, is the signature of `checksums` by `key`.
+fn verify_signature(checksums: &[u8], signature: &str, key: &[u8]) -> Result<()> {
+    let encoded: String = signature.split_whitespace().collect();
+    let signature = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| anyhow::anyhow!("{} is not base64: {}", SIGNATURE_ASSET, e))?;
+    UnparsedPublicKey::new(&ED25519, key)
+        .verify(checksums, &signature)
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "{} is not signed by the release key ({} does not match)",
+                CHECKSUMS_ASSET,
+                SIGNATURE_ASSET
+            )
+        })
+}
+
+/// Checks that `checksums` were signed for `version`, the one the release is tagged with.
+fn verify_version(checksums: &str, version: &str) -> Result<()> {
+    let signed = checksums
+        .lines()
+        .find_map(|line| line.strip_prefix(VERSION_LINE))
+        .map(str::trim)
+        .ok_or_else(|| anyhow::anyhow!("{} names no version", CHECKSUMS_ASSET))?;
+    if signed != version {
+        return Err(anyhow::anyhow!(
+            "{} is signed for version {}, not {}",
+            CHECKSUMS_ASSET,
+            signed,
+            version
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that the SHA-256 of `binary` is the one `checksums` lists for `name`.
+fn verify_checksum(checksums: &str, name: &str, binary: &[u8]) -> Result<()> {
+    let expected = checksums
+        .lines()
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .find(|(_, file)| file.trim().trim_start_matches('*') == name)
+        .map(|(sum, _)| sum.to_lowercase())
+        .ok_or_else(|| anyhow::anyhow!("{} lists no checksum for {}", CHECKSUMS_ASSET, name))?;
+    let actual: String = Sha256::digest(binary)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    if actual != expected {
+        return Err(anyhow::anyhow!(
+            "Checksum mismatch for {}: expected {}, got {}; keeping the installed binary",
+            name,
+            expected,
+            actual
+        ));
+    }
+    Ok(())
+}
+
+/// Writes `binary` next to `exe` and renames it over, so the path never holds a partial
+/// file. The running process keeps its own copy open.
+fn replace(exe: &std::path::Path, binary: &[u8]) -> Result<()> {
+    let tmp = exe.with_extension("new");
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(binary)?;
+    #[cfg(unix)]
+    file.set_permissions(fs::Permissions::from_mode(0o755))?;
+    file.sync_all()?;
+    fs::rename(&tmp, exe)?;
+    Ok(())
+}
+
+/// A dot-separated part of a pre-release; numbers sort before words, as in semver.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Identifier {
+    Number(u64),
+    Word(String),
+}
+
+/// Whether `candidate` is a later version than `current`, comparing the numbers of
+/// `major.minor.patch`; a pre-release of a version ("1.2.0-rc.1") is earlier than it,
+/// and pre-releases compare part by part ("rc.2" before "rc.10").
+fn is_newer(candidate: &str, current: &str) -> bool {
+    let parse = |version: &str| {
+        let (numbers, pre) = match version.split_once('-') {
+            Some((numbers, pre)) => (numbers, Some(pre)),
+            None => (version, None),
+        };
+        let numbers: Vec<u64> = numbers.split('.').map(|n| n.parse().unwrap_or(0)).collect();
+        let pre: Option<Vec<Identifier>> = pre.map(|pre| {
+            pre.split('.')
+                .map(|part| match part.parse() {
+                    Ok(number) => Identifier::Number(number),
+                    Err(_) => Identifier::Word(part.to_string()),
+                })
+                .collect()
+        });
+        // Releases sort after the pre-releases of the same version.
+        (numbers, pre.is_none(), pre)
+    };
+    parse(candidate) > parse(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    #[test]
+    fn versions_are_compared_with_pre_releases_before_their_release() {
+        assert!(is_newer("1.2.1", "1.2.0"));
+        assert!(is_newer("1.10.0", "1.9.3"));
+        assert!(is_newer("2.0.0", "1.99.99"));
+        assert!(!is_newer("1.2.0", "1.2.0"));
+        assert!(!is_newer("1.1.9", "1.2.0"));
+        assert!(is_newer("1.2.0", "1.2.0-rc.1"));
+        assert!(!is_newer("1.2.0-rc.1", "1.2.0"));
+        assert!(!is_newer("1.2.0-rc.1", "1.2.0-rc.1"));
+        assert!(is_newer("1.2.0-rc.2", "1.2.0-rc.1"));
+        assert!(is_newer("1.3.0-rc.1", "1.2.0"));
+        assert!(is_newer("1.2.0-rc.10", "1.2.0-rc.2"));
+        assert!(!is_newer("1.2.0-rc.2", "1.2.0-rc.10"));
+        assert!(is_newer("1.2.0-rc.1", "1.2.0-rc"));
+        assert!(is_newer("1.2.0-rc.1", "1.2.0-beta.9"));
+        assert!(is_newer("1.2.0-alpha.beta", "1.2.0-alpha.1"));
+    }
+
+    #[test]
+    fn checksums_must_be_signed_for_the_tagged_version() {
+        let checksums = "# rust-signoz-agent 1.3.0\n0123  rust-signoz-agent-x86_64-linux\n";
+        verify_version(checksums, "1.3.0").unwrap();
+        // An older release, re-tagged as a newer one.
+        let rolled_back = verify_version(checksums, "1.4.0").unwrap_err();
+        assert!(rolled_back.to_string().contains("signed for version 1.3.0"));
+        assert!(verify_version("0123  rust-signoz-agent-x86_64-linux\n", "1.3.0").is_err());
+    }
+
+    #[test]
+    fn only_signed_checksums_that_match_are_installed() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let key = pair.public_key().as_ref();
+        let binary = b"new agent";
+        let sum: String = Sha256::digest(binary)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let checksums = format!("{}  rust-signoz-agent-x86_64-linux\n", sum);
+        let signature =
+            base64::engine::general_purpose::STANDARD.encode(pair.sign(checksums.as_bytes()));
+
+        verify_signature(checksums.as_bytes(), &signature, key).unwrap();
+        let tampered = checksums.replacen("  ", " *", 1);
+        assert!(verify_signature(tampered.as_bytes(), &signature, key).is_err());
+        assert!(verify_signature(checksums.as_bytes(), &signature, &RELEASE_KEY).is_err());
+        assert!(verify_signature(checksums.as_bytes(), "not base64!", key).is_err());
+
+        verify_checksum(&checksums, "rust-signoz-agent-x86_64-linux", binary).unwrap();
+        let mismatch = verify_checksum(&checksums, "rust-signoz-agent-x86_64-linux", b"other");
+        assert!(mismatch
+            .unwrap_err()
+            .to_string()
+            .contains("Checksum mismatch"));
+        assert!(verify_checksum(&checksums, "rust-signoz-agent-aarch64-linux", binary).is_err());
+    }
+
+    #[test]
+    fn signatures_made_with_openssl_verify_against_the_release_key() {
+        // `openssl pkeyutl -sign -rawin -inkey release-key.pem -in sums | base64`, sums
+        // holding "hello\n".
+        let signature = "sSwbsyZCpNxvN8Qaj5ky1d+1Va44+r6Ati0bkdnwT0MbR8AJt9AcKibZ6Fswy3tUn1+vN74zcvZ8\niRmR/toMDw==\n";
+        verify_signature(b"hello\n", signature, &RELEASE_KEY).unwrap();
+        assert!(verify_signature(b"hello", signature, &RELEASE_KEY).is_err());
+    }
+}
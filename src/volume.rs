@@ -0,0 +1,245 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::stats::AgentState;
+
+/// How often the lines read by each input are counted.
+const MINUTE: Duration = Duration::from_secs(60);
+
+/// When the lines per minute an input reads count as a sudden change: how many minutes
+/// the baseline averages, and how many times above or below it a minute has to be.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VolumeAlertConfig {
+    #[serde(default = "default_baseline_minutes")]
+    pub baseline_minutes: usize,
+    /// A minute with this many times the baseline's lines is a jump.
+    #[serde(default = "default_multiple")]
+    pub jump: f64,
+    /// A minute with the baseline's lines divided by this many is a drop.
+    #[serde(default = "default_multiple")]
+    pub drop: f64,
+    /// Inputs with a baseline below this many lines per minute are not watched.
+    #[serde(default = "default_min_baseline")]
+    pub min_baseline: f64,
+}
+
+fn default_baseline_minutes() -> usize {
+    30
+}
+
+fn default_multiple() -> f64 {
+    5.0
+}
+
+fn default_min_baseline() -> f64 {
+    10.0
+}
+
+impl VolumeAlertConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.baseline_minutes == 0 {
+            return Err(anyhow::anyhow!("baseline_minutes must be at least 1"));
+        }
+        if !(self.jump > 1.0 && self.drop > 1.0) {
+            return Err(anyhow::anyhow!("jump and drop must be above 1"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Change {
+    Jump {
+        lines: u64,
+        baseline: f64,
+    },
+    Drop {
+        lines: u64,
+        baseline: f64,
+    },
+    /// Back within the multiples of the baseline.
+    Normal {
+        lines: u64,
+    },
+    /// As many minutes at the changed volume as the baseline holds, which becomes the
+    /// baseline.
+    Settled {
+        baseline: f64,
+    },
+}
+
+/// The lines per minute of one input over the last `baseline_minutes`.
+pub struct Baseline {
+    config: VolumeAlertConfig,
+    minutes: VecDeque<u64>,
+    /// Minutes since a jump or drop, left out of `minutes` until they settle.
+    changed: Option<(bool, VecDeque<u64>)>,
+}
+
+impl Baseline {
+    pub fn new(config: VolumeAlertConfig) -> Baseline {
+        Baseline {
+            minutes: VecDeque::with_capacity(config.baseline_minutes),
+            config,
+            changed: None,
+        }
+    }
+
+    fn average(minutes: &VecDeque<u64>) -> f64 {
+        minutes.iter().sum::<u64>() as f64 / minutes.len().max(1) as f64
+    }
+
+    /// Takes the lines read in the last minute, and returns how that changed the volume.
+    /// Only once the baseline covers `baseline_minutes` is a minute compared with it.
+    pub fn minute(&mut self, lines: u64) -> Option<Change> {
+        let full = self.minutes.len() == self.config.baseline_minutes;
+        let baseline = Self::average(&self.minutes);
+        let jumped = lines as f64 > baseline * self.config.jump;
+        let dropped = (lines as f64) < baseline / self.config.drop;
+        let watched = full && baseline >= self.config.min_baseline;
+
+        if let Some((was_jump, ref mut minutes)) = self.changed {
+            if watched && (jumped || dropped) && jumped == was_jump {
+                minutes.push_back(lines);
+                if minutes.len() < self.config.baseline_minutes {
+                    return None;
+                }
+                self.minutes = std::mem::take(minutes);
+                self.changed = None;
+                return Some(Change::Settled {
+                    baseline: Self::average(&self.minutes),
+                });
+            }
+            self.changed = None;
+            self.push(lines);
+            return Some(Change::Normal { lines });
+        }
+        if watched && (jumped || dropped) {
+            self.changed = Some((jumped, VecDeque::from([lines])));
+            return Some(match jumped {
+                true => Change::Jump { lines, baseline },
+                false => Change::Drop { lines, baseline },
+            });
+        }
+        self.push(lines);
+        None
+    }
+
+    fn push(&mut self, lines: u64) {
+        if self.minutes.len() == self.config.baseline_minutes {
+            self.minutes.pop_front();
+        }
+        self.minutes.push_back(lines);
+    }
+}
+
+/// Counts the lines of every input each minute and warns about those whose volume jumped
+/// or dropped, per the entry of `alerts` for the input (looked up like `timestamps`).
+pub fn watch(
+    alerts: BTreeMap<String, VolumeAlertConfig>,
+    state: Arc<AgentState>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut inputs: BTreeMap<String, (u64, Baseline)> = BTreeMap::new();
+        loop {
+            thread::sleep(MINUTE);
+            for input in state.inputs.lock().unwrap().iter() {
+                let Some(config) =
+                    crate::input::option_for(&alerts, &input.name).or_else(|| alerts.get("*"))
+                else {
+                    continue;
+                };
+                let total = input.lines.load(Ordering::Relaxed);
+                let (last, baseline) = inputs
+                    .entry(input.name.clone())
+                    .or_insert_with(|| (total, Baseline::new(config.clone())));
+                // A restarted input counts from zero again.
+                let lines = total.checked_sub(*last).unwrap_or(total);
+                *last = total;
+                let name = &input.name;
+                match baseline.minute(lines) {
+                    Some(Change::Jump { lines, baseline }) => {
+                        input.volume_anomalies.fetch_add(1, Ordering::Relaxed);
+                        state.record_error(format!(
+                            "Input {} read {} lines in the last minute, {:.1} times its baseline of {:.0}",
+                            name,
+                            lines,
+                            lines as f64 / baseline,
+                            baseline
+                        ));
+                    }
+                    Some(Change::Drop { lines, baseline }) => {
+                        input.volume_anomalies.fetch_add(1, Ordering::Relaxed);
+                        state.record_error(format!(
+                            "Input {} read {} lines in the last minute against a baseline of {:.0}; its source may have stopped logging",
+                            name, lines, baseline
+                        ));
+                    }
+                    Some(Change::Normal { lines }) => {
+                        println!("Input {} is back to {} lines a minute", name, lines)
+                    }
+                    Some(Change::Settled { baseline }) => println!(
+                        "Input {} kept at about {:.0} lines a minute, now its baseline",
+                        name, baseline
+                    ),
+                    None => {}
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stopped_and_flooding_inputs_are_reported_once() {
+        let mut baseline = Baseline::new(VolumeAlertConfig {
+            baseline_minutes: 3,
+            jump: 5.0,
+            drop: 5.0,
+            min_baseline: 10.0,
+        });
+        let changes: Vec<Option<Change>> = [100, 120, 80, 0, 0, 110, 600, 90]
+            .into_iter()
+            .map(|lines| baseline.minute(lines))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                None,
+                None,
+                None,
+                Some(Change::Drop {
+                    lines: 0,
+                    baseline: 100.0
+                }),
+                None,
+                Some(Change::Normal { lines: 110 }),
+                Some(Change::Jump {
+                    lines: 600,
+                    baseline: 310.0 / 3.0
+                }),
+                Some(Change::Normal { lines: 90 }),
+            ]
+        );
+
+        // Quiet inputs are left alone; a lasting change becomes the baseline.
+        let mut quiet = Baseline::new(baseline.config.clone());
+        assert!([2, 3, 1, 0, 0]
+            .into_iter()
+            .all(|l| quiet.minute(l).is_none()));
+        let changes: Vec<Option<Change>> = [900, 1000, 1100]
+            .into_iter()
+            .map(|lines| baseline.minute(lines))
+            .collect();
+        assert!(matches!(changes[0], Some(Change::Jump { .. })));
+        assert_eq!(changes[1], None);
+        assert_eq!(changes[2], Some(Change::Settled { baseline: 1000.0 }));
+    }
+}
@@ -0,0 +1,226 @@
+use anyhow::{Context, Result};
+use std::ffi::c_void;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use windows_sys::Win32::Foundation::{ERROR_CALL_NOT_IMPLEMENTED, NO_ERROR};
+use windows_sys::Win32::System::Services::{
+    RegisterServiceCtrlHandlerExW, SetServiceStatus, StartServiceCtrlDispatcherW,
+    SERVICE_ACCEPT_SHUTDOWN, SERVICE_ACCEPT_STOP, SERVICE_CONTROL_INTERROGATE,
+    SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP, SERVICE_RUNNING, SERVICE_START_PENDING,
+    SERVICE_STATUS, SERVICE_STATUS_CURRENT_STATE, SERVICE_STOPPED, SERVICE_STOP_PENDING,
+    SERVICE_TABLE_ENTRYW, SERVICE_WIN32_OWN_PROCESS,
+};
+
+/// The name the agent is registered under with the Service Control Manager.
+pub const SERVICE_NAME: &str = "rust-signoz-agent";
+
+/// The flag the Service Control Manager starts the agent with.
+pub const FLAG: &str = "--windows-service";
+
+/// How long the Service Control Manager is told a stop may take.
+const STOP_WAIT_HINT_MS: u32 = 30_000;
+
+/// The agent, run by the service main function.
+static AGENT: OnceLock<fn() -> Result<()>> = OnceLock::new();
+/// Cleared when the Service Control Manager asks the agent to stop.
+static RUNNING: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+static STATUS_HANDLE: AtomicPtr<c_void> = AtomicPtr::new(std::ptr::null_mut());
+static RESULT: Mutex<Option<Result<()>>> = Mutex::new(None);
+
+/// Whether the agent was started by the Service Control Manager.
+pub fn started_by_scm() -> bool {
+    std::env::args().any(|arg| arg == FLAG)
+}
+
+/// The `sc.exe` arguments that register the agent at `exe` with `config_path`, start it with
+/// Windows and restart it 5 seconds after it failed.
+fn sc_commands(exe: &str, config_path: &str) -> Vec<Vec<String>> {
+    let command = format!("\"{}\" --config \"{}\" {}", exe, config_path, FLAG);
+    [
+        vec![
+            "create",
+            SERVICE_NAME,
+            "binPath=",
+            &command,
+            "start=",
+            "auto",
+            "DisplayName=",
+            "Rust SigNoz Agent",
+        ],
+        vec![
+            "description",
+            SERVICE_NAME,
+            "Ships logs to SigNoz over OTLP",
+        ],
+        vec![
+            "failure",
+            SERVICE_NAME,
+            "reset=",
+            "86400",
+            "actions=",
+            "restart/5000",
+        ],
+    ]
+    .iter()
+    .map(|args| args.iter().map(|a| a.to_string()).collect())
+    .collect()
+}
+
+/// Registers the agent with the Service Control Manager, or with `output` writes the
+/// `sc.exe` commands that would to that script instead.
+pub fn install(config_path: &str, output: Option<String>) -> Result<()> {
+    let exe = std::env::current_exe().context("Cannot determine the agent binary path")?;
+    let config_path = Path::new(config_path);
+    if !config_path.exists() {
+        return Err(anyhow::anyhow!(
+            "{} does not exist; run once in the foreground to create it",
+            config_path.display()
+        ));
+    }
+    let config_path = fs::canonicalize(config_path)?;
+    let commands = sc_commands(
+        &exe.display().to_string(),
+        &config_path.display().to_string(),
+    );
+
+    if let Some(output) = output {
+        let script: String = commands
+            .iter()
+            .map(|args| {
+                let args: Vec<String> = args
+                    .iter()
+                    .map(|a| match a.contains([' ', '"']) {
+                        true => format!("\"{}\"", a.replace('"', "\\\"")),
+                        false => a.clone(),
+                    })
+                    .collect();
+                format!("sc.exe {}\r\n", args.join(" "))
+            })
+            .collect();
+        fs::write(&output, script).with_context(|| format!("Failed to write {}", output))?;
+        println!("Service commands written to: {}", output);
+        println!("Run it from an elevated prompt to register the service.");
+        return Ok(());
+    }
+
+    for args in &commands {
+        let status = Command::new("sc.exe")
+            .args(args)
+            .status()
+            .context("Failed to run sc.exe")?;
+        if !status.success() {
+            return Err(anyhow::anyhow!(
+                "sc.exe {} failed with {}; registering a service needs an elevated prompt",
+                args[0],
+                status
+            ));
+        }
+    }
+    println!("Service {} registered", SERVICE_NAME);
+    println!("To start it, run:");
+    println!("  sc.exe start {}", SERVICE_NAME);
+    Ok(())
+}
+
+/// Clears `running` when the Service Control Manager asks the agent to stop.
+pub fn stop_with(running: Arc<AtomicBool>) {
+    RUNNING.set(running).ok();
+}
+
+/// Hands the main thread to the Service Control Manager, which runs `agent` in the
+/// service main function, and returns what `agent` returned.
+pub fn dispatch(agent: fn() -> Result<()>) -> Result<()> {
+    AGENT.set(agent).ok();
+    let mut name: Vec<u16> = SERVICE_NAME.encode_utf16().chain([0]).collect();
+    let table = [
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: name.as_mut_ptr(),
+            lpServiceProc: Some(service_main),
+        },
+        SERVICE_TABLE_ENTRYW::default(),
+    ];
+    // SAFETY: the table ends with a null entry and outlives the call, which returns once
+    // the service stopped.
+    if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } == 0 {
+        return Err(std::io::Error::last_os_error())
+            .context("Failed to connect to the Service Control Manager");
+    }
+    RESULT.lock().unwrap().take().unwrap_or(Ok(()))
+}
+
+unsafe extern "system" fn service_main(_argc: u32, _argv: *mut *mut u16) {
+    let name: Vec<u16> = SERVICE_NAME.encode_utf16().chain([0]).collect();
+    // SAFETY: the name is null-terminated; the handler is a plain function.
+    let handle =
+        unsafe { RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(handler), std::ptr::null()) };
+    if handle.is_null() {
+        return;
+    }
+    STATUS_HANDLE.store(handle, Ordering::SeqCst);
+    set_status(SERVICE_START_PENDING, NO_ERROR);
+    // Relative paths in config.toml resolve from its directory, as when run from there.
+    if let Some(dir) = config_dir() {
+        std::env::set_current_dir(dir).ok();
+    }
+    set_status(SERVICE_RUNNING, NO_ERROR);
+    let result = AGENT.get().map_or(Ok(()), |agent| agent());
+    let exit_code = if result.is_ok() { NO_ERROR } else { 1 };
+    // Kept before the stop is reported, after which the dispatcher returns.
+    *RESULT.lock().unwrap() = Some(result);
+    set_status(SERVICE_STOPPED, exit_code);
+}
+
+unsafe extern "system" fn handler(
+    control: u32,
+    _event_type: u32,
+    _event_data: *mut c_void,
+    _context: *mut c_void,
+) -> u32 {
+    match control {
+        SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+            set_status(SERVICE_STOP_PENDING, NO_ERROR);
+            if let Some(running) = RUNNING.get() {
+                println!("Service stop requested, shutting down...");
+                running.store(false, Ordering::SeqCst);
+            }
+            NO_ERROR
+        }
+        SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+        _ => ERROR_CALL_NOT_IMPLEMENTED,
+    }
+}
+
+fn set_status(state: SERVICE_STATUS_CURRENT_STATE, exit_code: u32) {
+    let status = SERVICE_STATUS {
+        dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+        dwCurrentState: state,
+        dwControlsAccepted: match state {
+            SERVICE_RUNNING => SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN,
+            _ => 0,
+        },
+        dwWin32ExitCode: exit_code,
+        dwServiceSpecificExitCode: 0,
+        dwCheckPoint: 0,
+        dwWaitHint: match state {
+            SERVICE_START_PENDING | SERVICE_STOP_PENDING => STOP_WAIT_HINT_MS,
+            _ => 0,
+        },
+    };
+    // SAFETY: the handle came from RegisterServiceCtrlHandlerExW and is never closed.
+    unsafe {
+        SetServiceStatus(STATUS_HANDLE.load(Ordering::SeqCst), &status);
+    }
+}
+
+/// The directory of the `--config` the service was registered with.
+fn config_dir() -> Option<std::path::PathBuf> {
+    let args: Vec<String> = std::env::args().collect();
+    let path = args
+        .iter()
+        .position(|a| a == "--config")
+        .and_then(|i| args.get(i + 1))?;
+    Path::new(path).parent().map(Path::to_path_buf)
+}
@@ -0,0 +1,25 @@
+
+
+u
+
+service.name
+
+checkout
+
+	host.name
+golden-host
+;
+agent.instance.id&
+$3f2c1e9a-0000-4000-8000-000000000000	CչINFO*
+many attributes2.
+
+user.agent 
+Mozilla/5.0 (X11...[truncated]2
+a
+12
+b
+22
+c
+32'
+log.mutations
+redaction,truncation8YCչ
\ No newline at end of file
@@ -0,0 +1,14 @@
+
+
+u
+
+service.name
+
+checkout
+
+	host.name
+golden-host
+;
+agent.instance.id&
+$3f2c1e9a-0000-4000-8000-000000000000/-	CչINFO*
+GET /health 200YCչ
\ No newline at end of file
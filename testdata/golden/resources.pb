@@ -0,0 +1,31 @@
+
+
+p
+
+service.name
+api
+
+	host.name
+golden-host
+;
+agent.instance.id&
+$3f2c1e9a-0000-4000-8000-000000000000B	CչINFO*
+aYCչ	CչINFO*
+cYCչ
+
+
+
+service.name
+
+checkout
+;
+agent.instance.id&
+$3f2c1e9a-0000-4000-8000-000000000000
+
+	host.name
+db1
+
+container.name
+
+postgres!	CչINFO*
+bYCչ
\ No newline at end of file
@@ -0,0 +1,15 @@
+
+
+u
+
+service.name
+
+checkout
+
+	host.name
+golden-host
+;
+agent.instance.id&
+$3f2c1e9a-0000-4000-8000-000000000000B@	CչINFO*
+tracedJ
+eC݄H!1Rkqi 31YCչ
\ No newline at end of file
@@ -0,0 +1,85 @@
+//! Memory of the agent tailing one file to one endpoint: its RSS once idle, held to the
+//! 15 MiB target for small hosts, and its peak after 20k lines. Only the optimized builds
+//! say anything about it, so it is ignored by default:
+//!
+//! ```text
+//! cargo test --release --test idle_rss -- --ignored --nocapture
+//! cargo test --profile minimal --no-default-features --test idle_rss -- --ignored --nocapture
+//! ```
+
+#![cfg(target_os = "linux")]
+
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// The target for an idle agent.
+const IDLE_RSS_KIB: u64 = 15 * 1024;
+
+#[test]
+#[ignore]
+fn an_idle_agent_stays_under_15_mib() {
+    let dir = std::env::temp_dir().join(format!("rsa-idle-rss-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("app.log");
+    fs::write(&log, "").unwrap();
+    let config = dir.join("config.toml");
+    // Nothing listens on the discard port: records are retried, as on a host whose
+    // collector is down, but none is delivered.
+    fs::write(
+        &config,
+        format!(
+            "endpoint = \"http://127.0.0.1:9/v1/logs\"\nlog_files = [{:?}]\nstate_dir = {:?}\n",
+            log,
+            dir.join("state")
+        ),
+    )
+    .unwrap();
+
+    let mut agent = Command::new(env!("CARGO_BIN_EXE_rust-signoz-agent"))
+        .arg("--config")
+        .arg(&config)
+        .arg("run")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(5));
+    let idle = status_kib(agent.id(), "VmRSS:");
+
+    let mut file = fs::OpenOptions::new().append(true).open(&log).unwrap();
+    for i in 0..20_000 {
+        writeln!(
+            file,
+            "2024-03-05T10:11:12Z INFO request {} served in 12ms",
+            i
+        )
+        .unwrap();
+    }
+    thread::sleep(Duration::from_secs(5));
+    let peak = status_kib(agent.id(), "VmHWM:");
+    // SAFETY: the pid is that of the child, not reaped yet.
+    unsafe { libc::kill(agent.id() as libc::pid_t, libc::SIGTERM) };
+    agent.wait().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    println!(
+        "idle RSS {:.1} MiB, peak RSS after 20k lines {:.1} MiB",
+        idle as f64 / 1024.0,
+        peak as f64 / 1024.0
+    );
+    assert!(idle < IDLE_RSS_KIB, "idle RSS of {} KiB", idle);
+}
+
+/// A field of `/proc/<pid>/status`, in KiB.
+fn status_kib(pid: u32, field: &str) -> u64 {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).unwrap();
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(field))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+        .unwrap()
+}
@@ -0,0 +1,85 @@
+//! `run_as_user` end to end: the agent started as root, with a disk buffer, switches to
+//! `nobody` and can still buffer what it fails to send. Skipped unless run as root on a
+//! host with a `nobody` account.
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn a_disk_buffer_keeps_working_after_dropping_root() {
+    let nobody = Command::new("id").args(["-u", "nobody"]).output().ok();
+    let nobody = nobody.and_then(|id| String::from_utf8(id.stdout).ok()?.trim().parse().ok());
+    // SAFETY: geteuid has no preconditions.
+    let (Some(nobody), 0) = (nobody, unsafe { libc::geteuid() }) else {
+        eprintln!("skipped: needs root and a nobody account");
+        return;
+    };
+    let dir = std::env::temp_dir().join(format!("rsa-run-as-user-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let (state, log) = (dir.join("state"), dir.join("app.log"));
+    fs::create_dir_all(&state).unwrap();
+    fs::set_permissions(&dir, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    fs::write(&log, "").unwrap();
+    let config = dir.join("config.toml");
+    fs::write(
+        &config,
+        format!(
+            "endpoint = \"http://127.0.0.1:9/v1/logs\"\nlog_files = [{:?}]\nstate_dir = {:?}\nrun_as_user = \"nobody\"\n\n[disk_buffer]\n",
+            log, state
+        ),
+    )
+    .unwrap();
+
+    let agent = Command::new(env!("CARGO_BIN_EXE_rust-signoz-agent"))
+        .arg("--config")
+        .arg(&config)
+        .arg("run")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    writeln!(
+        fs::OpenOptions::new().append(true).open(&log).unwrap(),
+        "refused"
+    )
+    .unwrap();
+
+    let buffer = state.join("buffer").join("signoz");
+    let started = Instant::now();
+    while fs::read_dir(&buffer).map_or(true, |mut d| d.next().is_none())
+        && started.elapsed() < Duration::from_secs(20)
+    {
+        thread::sleep(Duration::from_millis(200));
+    }
+    // SAFETY: the pid is that of the child, not reaped yet.
+    unsafe { libc::kill(agent.id() as libc::pid_t, libc::SIGTERM) };
+    let output = agent.wait_with_output().unwrap();
+    let printed = String::from_utf8_lossy(&output.stdout).into_owned()
+        + &String::from_utf8_lossy(&output.stderr);
+
+    assert!(!printed.contains("not buffering"), "{}", printed);
+    assert!(printed.contains("buffering them on disk"), "{}", printed);
+    let mut owners = Vec::new();
+    walk(&state, &mut owners);
+    assert!(owners.len() >= 5, "{:?}", owners);
+    for (path, uid) in owners {
+        assert_eq!(uid, nobody, "{} is not nobody's", path.display());
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+fn walk(path: &Path, owners: &mut Vec<(PathBuf, u32)>) {
+    let meta = fs::symlink_metadata(path).unwrap();
+    owners.push((path.to_path_buf(), meta.uid()));
+    if meta.is_dir() {
+        for entry in fs::read_dir(path).unwrap() {
+            walk(&entry.unwrap().path(), owners);
+        }
+    }
+}